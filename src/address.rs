@@ -1,9 +1,15 @@
-use std::fmt;
-use std::str::FromStr;
-
-use super::header::{FromHeader, ToFoldedHeader};
+use core::error::Error;
+use core::fmt;
+use core::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use core::str::FromStr;
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use super::header::{fold_value, FoldingPolicy, FromHeader, ToFoldedHeader, ToHeader};
 use super::results::{ParsingError, ParsingResult};
-use super::rfc5322::{Rfc5322Parser, MIME_LINE_LENGTH};
+#[cfg(not(feature = "std"))]
+use super::rfc2047::encode_base64_words;
+use super::rfc5322::Rfc5322Parser;
 
 /// Represents an RFC 5322 Address
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -32,6 +38,52 @@ impl Address {
     pub fn new_group(name: String, mailboxes: Vec<Mailbox>) -> Address {
         Address::Group(name, mailboxes)
     }
+
+    /// Parse a comma separated list of addresses, such as user input or
+    /// the value of an address header.
+    ///
+    /// Display names may be quoted, with escaped characters, or RFC 2047
+    /// encoded. Groups and comments are understood, and a comment after a
+    /// bare address is taken as its display name.
+    pub fn parse_list(s: &str) -> ParsingResult<Vec<Address>> {
+        AddressParser::new(s).parse_address_list()
+    }
+
+    /// This address with any internationalized domains converted to
+    /// A-labels, see `Mailbox::to_ascii`.
+    #[cfg(feature = "std")]
+    pub fn to_ascii(&self) -> ParsingResult<Address> {
+        Ok(match *self {
+            Address::Mailbox(ref mbox) => Address::Mailbox(mbox.to_ascii()?),
+            Address::Group(ref name, ref mboxes) => Address::Group(
+                name.clone(),
+                mboxes
+                    .iter()
+                    .map(|mbox| mbox.to_ascii())
+                    .collect::<ParsingResult<_>>()?,
+            ),
+        })
+    }
+}
+
+// Format `name` as an RFC 5322 phrase: as atoms where possible, otherwise a
+// quoted string, and as RFC 2047 encoded words if it isn't plain ASCII.
+fn format_phrase(name: &str) -> String {
+    if name.contains("=?") || !name.chars().all(|c| c.is_ascii() && !c.is_ascii_control()) {
+        #[cfg(feature = "std")]
+        return encoded_words::encode(name, None, encoded_words::EncodingFlag::Shortest, None);
+        #[cfg(not(feature = "std"))]
+        return encode_base64_words(name);
+    } else if name.split(' ').all(|atom| !atom.is_empty() && atom.chars().all(is_atext)) {
+        name.to_string()
+    } else {
+        quote_string(name)
+    }
+}
+
+// Make a quoted string of `s`, escaping any quotes and backslashes.
+fn quote_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
 }
 
 impl fmt::Display for Address {
@@ -47,7 +99,7 @@ impl fmt::Display for Address {
                     }
                     mailbox_list.push_str(&mbox.to_string()[..]);
                 }
-                write!(fmt, "{}: {};", name, mailbox_list)
+                write!(fmt, "{}: {};", format_phrase(name), mailbox_list)
             }
         }
     }
@@ -78,29 +130,140 @@ impl Mailbox {
             address,
         }
     }
+
+    /// Parse a single mailbox, such as `Joe Blogs <joe@example.org>`.
+    ///
+    /// Fails if anything other than comments follows the mailbox.
+    pub fn parse(s: &str) -> ParsingResult<Mailbox> {
+        let mut parser = AddressParser::new(s);
+        let mailbox = parser.parse_mailbox()?;
+        parser.p.consume_cfws();
+        if !parser.p.eof() {
+            return Err(parser.p.unexpected("end of mailbox"));
+        }
+        Ok(mailbox)
+    }
+
+    // Split the address into its local part and domain.
+    fn split_address(&self) -> (&str, &str) {
+        match self.address.rfind('@') {
+            Some(pos) => (&self.address[..pos], &self.address[pos + 1..]),
+            None => (&self.address[..], ""),
+        }
+    }
+
+    /// The domain of this mailbox, either a name or an IP address literal.
+    ///
+    /// Literals which aren't a valid IPv4 or `IPv6:` tagged IPv6 address
+    /// are given as a name, as they appear.
+    pub fn domain(&self) -> Domain {
+        let (_, domain) = self.split_address();
+        match parse_domain_literal(domain) {
+            Some(ip) => Domain::Literal(ip),
+            None => Domain::Name(domain.to_string()),
+        }
+    }
+
+    /// The domain of this mailbox, with any internationalized labels
+    /// converted to A-labels ("xn--" punycode) as per IDNA.
+    ///
+    /// Domain literals are kept as they are. Fails if the domain isn't a
+    /// valid internationalized domain name.
+    #[cfg(feature = "std")]
+    pub fn domain_ascii(&self) -> ParsingResult<String> {
+        let (local_part, domain) = self.split_address();
+        if let literal @ Domain::Literal(_) = self.domain() {
+            return Ok(literal.to_string());
+        }
+        idna::domain_to_ascii(domain).map_err(|_| ParsingError::InvalidToken {
+            pos: local_part.len() + 1,
+            kind: "domain".to_string(),
+            token: domain.to_string(),
+        })
+    }
+
+    /// The domain of this mailbox, with any A-labels converted back to
+    /// Unicode for display.
+    ///
+    /// Labels which aren't valid punycode are left as they are.
+    #[cfg(feature = "std")]
+    pub fn domain_unicode(&self) -> String {
+        let (_, domain) = self.split_address();
+        let (unicode, result) = idna::domain_to_unicode(domain);
+        match result {
+            Ok(()) => unicode,
+            Err(_) => domain.to_string(),
+        }
+    }
+
+    /// The address of this mailbox in a form for comparing it with others,
+    /// lowercased, and with its domain converted to A-labels.
+    ///
+    /// Local parts are only compared without regard to case by convention,
+    /// but the convention is near universal. Domains which aren't valid
+    /// internationalized domain names are only lowercased.
+    #[cfg(feature = "std")]
+    pub fn normalized_address(&self) -> String {
+        let (local_part, domain) = self.split_address();
+        if !self.address.contains('@') {
+            return local_part.to_lowercase();
+        }
+        let domain = self.domain_ascii().unwrap_or_else(|_| domain.to_string());
+        format!("{}@{}", local_part.to_lowercase(), domain.to_lowercase())
+    }
+
+    /// This mailbox with its domain converted to A-labels, for sending over
+    /// transports which don't support SMTPUTF8.
+    ///
+    /// The local part is kept as it is, so must already be ASCII for the
+    /// result to be usable over such a transport.
+    #[cfg(feature = "std")]
+    pub fn to_ascii(&self) -> ParsingResult<Mailbox> {
+        if !self.address.contains('@') {
+            return Ok(self.clone());
+        }
+        let (local_part, _) = self.split_address();
+        Ok(Mailbox {
+            name: self.name.clone(),
+            address: format!("{}@{}", local_part, self.domain_ascii()?),
+        })
+    }
 }
 
 impl fmt::Display for Mailbox {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        // Quote the local part if it isn't a dot-atom
+        let (local_part, domain) = self.split_address();
+        let is_dot_atom = local_part
+            .split('.')
+            .all(|atom| !atom.is_empty() && atom.chars().all(|c| is_atext(c) || !c.is_ascii()));
+        let address = if is_dot_atom || !self.address.contains('@') {
+            self.address.clone()
+        } else {
+            format!("{}@{}", quote_string(local_part), domain)
+        };
+
         match self.name {
-            Some(ref name) => {
-                if name.chars().all(|c| c.is_ascii_alphanumeric() || c == ' ') {
-                    write!(fmt, "{} <{}>", name, self.address)
-                } else {
-                    let s = encoded_words::encode(
-                        name,
-                        None,
-                        encoded_words::EncodingFlag::Shortest,
-                        None,
-                    );
-                    write!(fmt, "{} <{}>", s, self.address)
-                }
+            Some(ref name) if !name.is_empty() => {
+                write!(fmt, "{} <{}>", format_phrase(name), address)
             }
-            None => write!(fmt, "<{}>", self.address),
+            _ => write!(fmt, "<{}>", address),
         }
     }
 }
 
+impl FromHeader for Mailbox {
+    fn from_header(value: String) -> ParsingResult<Mailbox> {
+        Mailbox::parse(&value[..])
+    }
+}
+
+impl ToHeader for Mailbox {
+    fn to_header(value: Mailbox) -> ParsingResult<String> {
+        Ok(value.to_string())
+    }
+}
+
 impl<'a> From<&'a str> for Mailbox {
     fn from(mailbox: &'a str) -> Mailbox {
         Mailbox::new(mailbox.into())
@@ -124,13 +287,13 @@ impl FromStr for Mailbox {
     type Err = ParsingError;
 
     fn from_str(s: &str) -> ParsingResult<Mailbox> {
-        AddressParser::new(s).parse_mailbox()
+        Mailbox::parse(s)
     }
 }
 
 impl FromHeader for Vec<Address> {
     fn from_header(value: String) -> ParsingResult<Vec<Address>> {
-        AddressParser::new(&value[..]).parse_address_list()
+        Address::parse_list(&value[..])
     }
 }
 
@@ -140,27 +303,12 @@ impl ToFoldedHeader for Vec<Address> {
             return Err(ParsingError::new(format!("Header value cannot be empty")));
         }
 
-        let mut header = String::new();
-
-        let mut line_len = start_pos;
-
-        for addr in value.iter() {
-            let addr_str = format!("{}, ", addr);
-
-            if line_len + addr_str.len() > MIME_LINE_LENGTH {
-                // Adding this would cause a wrap, so wrap before!
-                header.push_str("\r\n\t");
-                line_len = 0;
-            }
-            line_len += addr_str.len();
-            header.push_str(&addr_str[..]);
-        }
-
-        // Clear up the final ", "
-        let real_len = header.len() - 2;
-        header.truncate(real_len);
-
-        Ok(header)
+        let addresses: Vec<String> = value.iter().map(|addr| addr.to_string()).collect();
+        Ok(fold_value(
+            start_pos,
+            &addresses.join(", ")[..],
+            &FoldingPolicy::default(),
+        ))
     }
 }
 
@@ -177,53 +325,50 @@ impl<'s> AddressParser<'s> {
 
     pub fn parse_address_list(&mut self) -> ParsingResult<Vec<Address>> {
         let mut result = Vec::new();
-        let mut expected_separator: char;
 
-        while !self.p.eof() {
-            self.p.push_position();
+        loop {
+            self.skip_empty_members();
+            if self.p.eof() {
+                break;
+            }
+            let start = self.p.pos();
 
             match self.parse_group() {
                 Ok(x) => {
                     // Is a group
                     result.push(x);
-                    expected_separator = ';';
                 }
                 Err(e) => {
                     // If we failed to parse as group, try again as mailbox
-                    self.p.pop_position();
+                    self.p.set_pos(start);
                     result.push(Address::Mailbox(match self.parse_mailbox() {
                         Ok(x) => x,
+                        // Report whichever attempt got further
                         Err(e2) => {
-                            return Err(ParsingError::new(format!(
-                                "Failed to parse as group: {}\n\
-                                     Failed to parse as mailbox: {}",
-                                e, e2
-                            )))
+                            return Err(if e.position() > e2.position() { e } else { e2 });
                         }
                     }));
-                    expected_separator = ',';
                 }
             };
-
-            self.p.consume_linear_whitespace();
-            if !self.p.eof() && self.p.peek() == expected_separator {
-                // Clear the separator
-                self.p.consume_char();
-            }
         }
 
         Ok(result)
     }
 
+    // Skip past any separators and comments before the next list member,
+    // including the empty members allowed by the obsolete syntax.
+    fn skip_empty_members(&mut self) {
+        self.p.consume_cfws();
+        while !self.p.eof() && self.p.peek() == ',' {
+            self.p.consume_char();
+            self.p.consume_cfws();
+        }
+    }
+
     pub fn parse_group(&mut self) -> ParsingResult<Address> {
         let name = match self.p.consume_phrase(false) {
             Some(x) => x,
-            None => {
-                return Err(ParsingError::new(format!(
-                    "Couldn't find group name: {}",
-                    self.p.peek_to_end()
-                )))
-            }
+            None => return Err(self.p.unexpected("group name")),
         };
 
         self.p.assert_char(':')?;
@@ -231,26 +376,50 @@ impl<'s> AddressParser<'s> {
 
         let mut mailboxes = Vec::new();
 
-        while !self.p.eof() && self.p.peek() != ';' {
-            mailboxes.push(self.parse_mailbox()?);
-
-            if !self.p.eof() && self.p.peek() == ',' {
-                self.p.consume_char();
+        loop {
+            self.skip_empty_members();
+            if self.p.eof() || self.p.peek() == ';' {
+                break;
             }
+            mailboxes.push(self.parse_mailbox()?);
+        }
+        // Be lenient about a missing semicolon at the end of the input
+        if !self.p.eof() {
+            self.p.consume_char();
         }
 
         Ok(Address::Group(name, mailboxes))
     }
 
     pub fn parse_mailbox(&mut self) -> ParsingResult<Mailbox> {
-        // Push the current position of the parser so we can back out later
-        self.p.push_position();
+        // Note the current position of the parser so we can back out later
+        let start = self.p.pos();
         match self.parse_name_addr() {
             Ok(result) => Ok(result),
-            Err(_) => {
+            Err(e) => {
                 // Revert back to our original position to try to parse an addr-spec
-                self.p.pop_position();
-                Ok(Mailbox::new(self.parse_addr_spec()?))
+                self.p.set_pos(start);
+                match self.parse_addr_spec() {
+                    Ok(addr) => {
+                        // The obsolete style of giving the name in a comment
+                        // after the address
+                        self.p.consume_linear_whitespace();
+                        let comment = if !self.p.eof() && self.p.peek() == '(' {
+                            self.p.consume_comment()
+                        } else {
+                            None
+                        };
+                        self.p.consume_cfws();
+                        Ok(match comment {
+                            Some(ref name) if !name.trim().is_empty() => {
+                                Mailbox::new_with_name(name.trim().to_string(), addr)
+                            }
+                            _ => Mailbox::new(addr),
+                        })
+                    }
+                    // Report whichever attempt got further
+                    Err(e2) => Err(if e.position() > e2.position() { e } else { e2 }),
+                }
             }
         }
     }
@@ -258,50 +427,334 @@ impl<'s> AddressParser<'s> {
     fn parse_name_addr(&mut self) -> ParsingResult<Mailbox> {
         // Find display-name
         let display_name = self.p.consume_phrase(false);
-        self.p.consume_linear_whitespace();
+        self.p.consume_cfws();
 
         self.p.assert_char('<')?;
         self.p.consume_char();
 
         let addr = self.parse_addr_spec()?;
-        if self.p.consume_char() != Some('>') {
-            // Fail because we should have a closing RANGLE here (to match the opening one)
-            Err(ParsingError::new(
-                "Missing '>' at end while parsing address header.".to_string(),
-            ))
-        } else {
-            Ok(match display_name {
-                Some(name) => Mailbox::new_with_name(name, addr),
-                None => Mailbox::new(addr),
-            })
-        }
+        // We should have a closing RANGLE here (to match the opening one)
+        self.p.assert_char('>')?;
+        self.p.consume_char();
+        self.p.consume_cfws();
+        Ok(match display_name {
+            Some(name) => Mailbox::new_with_name(name, addr),
+            None => Mailbox::new(addr),
+        })
     }
 
     fn parse_addr_spec(&mut self) -> ParsingResult<String> {
         // local-part is a phrase, but allows dots in atoms
         let local_part = match self.p.consume_phrase(true) {
             Some(x) => x,
-            None => {
-                return Err(ParsingError::new(
-                    "Couldn't find local part while parsing address.".to_owned(),
-                ))
-            }
+            None => return Err(self.p.unexpected("local part")),
         };
 
+        self.p.consume_cfws();
         self.p.assert_char('@')?;
         self.p.consume_char();
+        self.p.consume_cfws();
 
         let domain = self.parse_domain()?;
+        self.p.consume_linear_whitespace();
         Ok(format!("{}@{}", local_part, domain))
     }
 
     fn parse_domain(&mut self) -> ParsingResult<String> {
-        // TODO: support domain-literal
+        if !self.p.eof() && self.p.peek() == '[' {
+            // domain-literal, such as [192.0.2.1]
+            let literal = self.p.consume_while(|c| c != ']' && !c.is_whitespace());
+            self.p.assert_char(']')?;
+            self.p.consume_char();
+            return Ok(format!("{}]", literal));
+        }
         match self.p.consume_atom(true) {
             Some(x) => Ok(x),
-            None => Err(ParsingError::new("Failed to parse domain.".to_string())),
+            None => Err(self.p.unexpected("domain")),
+        }
+    }
+}
+
+/// The longest address allowed in an SMTP path, see RFC 5321 Section 4.5.3.1.3
+const MAX_ADDRESS_LENGTH: usize = 254;
+/// RFC 5321 Section 4.5.3.1.1
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+/// RFC 5321 Section 4.5.3.1.2
+const MAX_DOMAIN_LENGTH: usize = 255;
+/// RFC 1035 Section 2.3.4
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// Why an address was rejected by `validate`.
+///
+/// Positions are byte offsets into the address.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum AddressError {
+    /// The address is empty.
+    Empty,
+    /// The address is longer than the 254 octets SMTP allows.
+    TooLong { length: usize },
+    /// There is no `@` following the local part.
+    MissingAt,
+    /// A character which isn't allowed where it was found.
+    InvalidChar { pos: usize, found: char },
+    /// There is nothing before the `@`.
+    EmptyLocalPart,
+    /// The unquoted local part starts or ends with a dot, or has two in a row.
+    MisplacedDot { pos: usize },
+    /// A quoted local part has no closing quote.
+    UnterminatedQuote,
+    /// The local part is longer than the 64 octets SMTP allows.
+    LocalPartTooLong { length: usize },
+    /// There is nothing after the `@`.
+    EmptyDomain,
+    /// A label of the domain is empty.
+    EmptyLabel { pos: usize },
+    /// A label of the domain starts or ends with a hyphen, or is not a
+    /// valid internationalized label.
+    InvalidLabel { pos: usize, label: String },
+    /// A label of the domain is longer than 63 octets, once converted to
+    /// an A-label if it is internationalized.
+    LabelTooLong { pos: usize, length: usize },
+    /// The domain is longer than 255 octets.
+    DomainTooLong { length: usize },
+    /// A domain literal isn't an IPv4 or `IPv6:` tagged IPv6 address.
+    InvalidDomainLiteral { literal: String },
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AddressError::Empty => write!(f, "Address is empty"),
+            AddressError::TooLong { length } => {
+                write!(f, "Address is {} octets, longer than {}", length, MAX_ADDRESS_LENGTH)
+            }
+            AddressError::MissingAt => write!(f, "Address has no @"),
+            AddressError::InvalidChar { pos, found } => {
+                write!(f, "Invalid character {:?} at {}", found, pos)
+            }
+            AddressError::EmptyLocalPart => write!(f, "Local part is empty"),
+            AddressError::MisplacedDot { pos } => write!(f, "Misplaced dot at {}", pos),
+            AddressError::UnterminatedQuote => write!(f, "Quoted local part is unterminated"),
+            AddressError::LocalPartTooLong { length } => write!(
+                f,
+                "Local part is {} octets, longer than {}",
+                length, MAX_LOCAL_PART_LENGTH
+            ),
+            AddressError::EmptyDomain => write!(f, "Domain is empty"),
+            AddressError::EmptyLabel { pos } => write!(f, "Empty domain label at {}", pos),
+            AddressError::InvalidLabel { pos, ref label } => {
+                write!(f, "Invalid domain label at {}: {}", pos, label)
+            }
+            AddressError::LabelTooLong { pos, length } => write!(
+                f,
+                "Domain label at {} is {} octets, longer than {}",
+                pos, length, MAX_LABEL_LENGTH
+            ),
+            AddressError::DomainTooLong { length } => write!(
+                f,
+                "Domain is {} octets, longer than {}",
+                length, MAX_DOMAIN_LENGTH
+            ),
+            AddressError::InvalidDomainLiteral { ref literal } => {
+                write!(f, "Invalid domain literal: {}", literal)
+            }
+        }
+    }
+}
+
+impl Error for AddressError {}
+
+/// The domain of a validated address.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Domain {
+    /// A domain name, as written
+    Name(String),
+    /// A domain literal, such as `[192.0.2.1]` or `[IPv6:2001:db8::1]`
+    Literal(IpAddr),
+}
+
+impl fmt::Display for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Domain::Name(ref name) => name.fmt(f),
+            Domain::Literal(IpAddr::V4(ref ip)) => write!(f, "[{}]", ip),
+            Domain::Literal(IpAddr::V6(ref ip)) => write!(f, "[IPv6:{}]", ip),
+        }
+    }
+}
+
+/// An addr-spec which has passed `validate`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ValidatedAddress {
+    /// The local part, as written, including the quotes if it is quoted
+    pub local_part: String,
+    /// The domain
+    pub domain: Domain,
+}
+
+impl ValidatedAddress {
+    /// Whether the local part is a quoted string.
+    pub fn is_quoted(&self) -> bool {
+        self.local_part.starts_with('"')
+    }
+}
+
+impl fmt::Display for ValidatedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}@{}", self.local_part, self.domain)
+    }
+}
+
+/// Strictly validate `address` as an addr-spec which can be used with SMTP.
+///
+/// Unlike the lenient parsing of addresses in incoming mail, this checks
+/// the syntax of RFC 5321 Section 4.1.2 along with the length limits of
+/// Section 4.5.3.1: the local part must be a dot-atom or quoted string of
+/// ASCII, and the domain a host name or an IP address literal. Domains may
+/// be internationalized, in which case their lengths are those of the
+/// A-labels they convert to.
+pub fn validate(address: &str) -> Result<ValidatedAddress, AddressError> {
+    if address.is_empty() {
+        return Err(AddressError::Empty);
+    }
+    if address.len() > MAX_ADDRESS_LENGTH {
+        return Err(AddressError::TooLong {
+            length: address.len(),
+        });
+    }
+
+    let at = if address.starts_with('"') {
+        validate_quoted_local_part(address)?
+    } else {
+        validate_dot_atom_local_part(address)?
+    };
+    if at > MAX_LOCAL_PART_LENGTH {
+        return Err(AddressError::LocalPartTooLong { length: at });
+    }
+
+    Ok(ValidatedAddress {
+        local_part: address[..at].to_string(),
+        domain: validate_domain(address, at + 1)?,
+    })
+}
+
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c)
+}
+
+// Check an unquoted local part, returning the position of the `@` after it.
+fn validate_dot_atom_local_part(address: &str) -> Result<usize, AddressError> {
+    let mut after_dot = true;
+    for (pos, c) in address.char_indices() {
+        match c {
+            '@' if pos == 0 => return Err(AddressError::EmptyLocalPart),
+            '@' if after_dot => return Err(AddressError::MisplacedDot { pos: pos - 1 }),
+            '@' => return Ok(pos),
+            '.' if after_dot => return Err(AddressError::MisplacedDot { pos }),
+            '.' => after_dot = true,
+            c if is_atext(c) => after_dot = false,
+            found => return Err(AddressError::InvalidChar { pos, found }),
+        }
+    }
+    Err(AddressError::MissingAt)
+}
+
+// Check a quoted local part, returning the position of the `@` after it.
+fn validate_quoted_local_part(address: &str) -> Result<usize, AddressError> {
+    let mut escaped = false;
+    for (pos, c) in address.char_indices().skip(1) {
+        if !(' '..='~').contains(&c) {
+            return Err(AddressError::InvalidChar { pos, found: c });
+        } else if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            let at = pos + 1;
+            return match address[at..].chars().next() {
+                Some('@') => Ok(at),
+                Some(found) => Err(AddressError::InvalidChar { pos: at, found }),
+                None => Err(AddressError::MissingAt),
+            };
         }
     }
+    Err(AddressError::UnterminatedQuote)
+}
+
+// Parse a domain literal, such as `[192.0.2.1]` or `[IPv6:2001:db8::1]`.
+fn parse_domain_literal(domain: &str) -> Option<IpAddr> {
+    let literal = domain.strip_prefix('[')?.strip_suffix(']')?;
+    match literal.get(..5) {
+        Some(tag) if tag.eq_ignore_ascii_case("IPv6:") => {
+            literal[5..].parse::<Ipv6Addr>().map(IpAddr::V6).ok()
+        }
+        _ => literal.parse::<Ipv4Addr>().map(IpAddr::V4).ok(),
+    }
+}
+
+// Check the domain of `address`, which starts at `start`.
+fn validate_domain(address: &str, start: usize) -> Result<Domain, AddressError> {
+    let domain = &address[start..];
+    if domain.is_empty() {
+        return Err(AddressError::EmptyDomain);
+    }
+
+    if domain.starts_with('[') {
+        return parse_domain_literal(domain)
+            .map(Domain::Literal)
+            .ok_or_else(|| AddressError::InvalidDomainLiteral {
+                literal: domain.to_string(),
+            });
+    }
+
+    let mut length = 0;
+    let mut pos = start;
+    for label in domain.split('.') {
+        if label.is_empty() {
+            return Err(AddressError::EmptyLabel { pos });
+        }
+        if let Some((offset, found)) = label
+            .char_indices()
+            .find(|&(_, c)| !(c.is_alphanumeric() || c == '-'))
+        {
+            return Err(AddressError::InvalidChar {
+                pos: pos + offset,
+                found,
+            });
+        }
+        let invalid_label = || AddressError::InvalidLabel {
+            pos,
+            label: label.to_string(),
+        };
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(invalid_label());
+        }
+        // Without IDNA support, only the UTF-8 length of a non-ASCII label
+        // is checked rather than that of its A-label
+        #[cfg(feature = "std")]
+        let label_length = if label.is_ascii() {
+            label.len()
+        } else {
+            idna::domain_to_ascii(label)
+                .map_err(|_| invalid_label())?
+                .len()
+        };
+        #[cfg(not(feature = "std"))]
+        let label_length = label.len();
+        if label_length > MAX_LABEL_LENGTH {
+            return Err(AddressError::LabelTooLong {
+                pos,
+                length: label_length,
+            });
+        }
+        length += label_length + 1;
+        pos += label.len() + 1;
+    }
+    // Don't count the dot added after the last label
+    if length - 1 > MAX_DOMAIN_LENGTH {
+        return Err(AddressError::DomainTooLong { length: length - 1 });
+    }
+    Ok(Domain::Name(domain.to_string()))
 }
 
 #[cfg(test)]
@@ -320,6 +773,303 @@ mod tests {
         assert_eq!(name_addr.to_string(), "Joe Blogs <foo@example.org>");
     }
 
+    #[test]
+    fn test_display_name_to_string() {
+        let tests = [
+            ("Mr. Black", "\"Mr. Black\" <a@example.org>"),
+            ("Say \"Hi\"", "\"Say \\\"Hi\\\"\" <a@example.org>"),
+            ("O'Neill", "O'Neill <a@example.org>"),
+        ];
+        for &(name, expected) in tests.iter() {
+            let mailbox = Mailbox::new_with_name(name.to_string(), "a@example.org".to_string());
+            assert_eq!(mailbox.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_display_name_round_trip() {
+        let names = [
+            "Blogs, Joe",
+            "Joe; Blogs",
+            "Joe \"JB\" Blogs",
+            "Joe (JB) Blogs",
+            "back\\slash\\",
+            "<joe@example.org>",
+            "a:b",
+            "  spaced  out ",
+            "=?utf-8?q?not_encoded?=",
+            "Zoë \"Z\", Smith",
+            "line\r\nbreak",
+            "\u{1F600}",
+        ];
+        for name in names.iter() {
+            let mailbox =
+                Mailbox::new_with_name(name.to_string(), "joe@example.org".to_string());
+            let header = Header::new_with_value("From".to_string(), mailbox.clone()).unwrap();
+            assert_eq!(header.get_value::<Mailbox>().unwrap(), mailbox, "{}", name);
+
+            let group = Address::new_group(name.to_string(), vec![mailbox.clone()]);
+            let header = Header::new_with_value("To".to_string(), vec![group.clone()]).unwrap();
+            assert_eq!(header.get_value::<Vec<Address>>().unwrap(), vec![group], "{}", name);
+        }
+
+        let mailbox = Mailbox::new_with_name("".to_string(), "joe@example.org".to_string());
+        assert_eq!(mailbox.to_string(), "<joe@example.org>");
+        let mailbox = Mailbox::new("john \"jd\" doe@example.org".to_string());
+        assert_eq!(mailbox.to_string(), "<\"john \\\"jd\\\" doe\"@example.org>");
+        assert_eq!(Mailbox::parse(&mailbox.to_string()).unwrap(), mailbox);
+    }
+
+    #[test]
+    fn test_encoded_display_name() {
+        let mailbox = "=?UTF-8?B?5pel5pys6Kqe?= <a@example.jp>"
+            .parse::<Mailbox>()
+            .unwrap();
+        assert_eq!(mailbox.name, Some("日本語".to_string()));
+        assert_eq!(mailbox.address, "a@example.jp".to_string());
+
+        // Non-ASCII and awkward names survive a round trip
+        let addresses = vec![
+            Address::new_mailbox_with_name("Jürgen Groß".to_string(), "j@example.de".to_string()),
+            Address::new_mailbox_with_name("Mr. \"B\"".to_string(), "b@example.org".to_string()),
+            Address::new_group(
+                "Équipe".to_string(),
+                vec![Mailbox::new_with_name(
+                    "日本語".to_string(),
+                    "a@example.jp".to_string(),
+                )],
+            ),
+        ];
+        let header = Header::new_with_value("To".to_string(), addresses.clone()).unwrap();
+        assert!(header.raw_value().is_ascii());
+        assert_eq!(header.get_value::<Vec<Address>>().unwrap(), addresses);
+    }
+
+    #[test]
+    fn test_domain_literal() {
+        let addresses = Address::parse_list(
+            "Postmaster <postmaster@[192.0.2.1]>, root@[IPv6:2001:db8::1], joe@example.org",
+        )
+        .unwrap();
+        let domains: Vec<Domain> = addresses
+            .iter()
+            .map(|address| match *address {
+                Address::Mailbox(ref mbox) => mbox.domain(),
+                Address::Group(..) => panic!("Unexpected group"),
+            })
+            .collect();
+        assert_eq!(
+            domains,
+            vec![
+                Domain::Literal("192.0.2.1".parse().unwrap()),
+                Domain::Literal("2001:db8::1".parse().unwrap()),
+                Domain::Name("example.org".to_string()),
+            ]
+        );
+
+        let header = Header::new_with_value("To".to_string(), addresses.clone()).unwrap();
+        assert_eq!(
+            header.raw_value(),
+            "Postmaster <postmaster@[192.0.2.1]>, <root@[IPv6:2001:db8::1]>, <joe@example.org>"
+        );
+        assert_eq!(header.get_value::<Vec<Address>>().unwrap(), addresses);
+
+        let ip = "2001:db8::2".parse().unwrap();
+        let mailbox = Mailbox::new(format!("root@{}", Domain::Literal(ip)));
+        assert_eq!(mailbox.address, "root@[IPv6:2001:db8::2]");
+        assert_eq!(mailbox.domain(), Domain::Literal(ip));
+        assert_eq!(mailbox.domain_ascii().unwrap(), "[IPv6:2001:db8::2]");
+        assert_eq!(
+            Mailbox::new("root@[300.1.1.1]".to_string()).domain(),
+            Domain::Name("[300.1.1.1]".to_string())
+        );
+    }
+
+    #[test]
+    fn test_normalized_address() {
+        let mailbox = Mailbox::new("Joe.Blogs@Bücher.Example".to_string());
+        assert_eq!(
+            mailbox.normalized_address(),
+            "joe.blogs@xn--bcher-kva.example"
+        );
+        let mailbox = Mailbox::new("joe.blogs@xn--bcher-kva.EXAMPLE".to_string());
+        assert_eq!(
+            mailbox.normalized_address(),
+            "joe.blogs@xn--bcher-kva.example"
+        );
+        assert_eq!(
+            Mailbox::new("Postmaster".to_string()).normalized_address(),
+            "postmaster"
+        );
+    }
+
+    #[test]
+    fn test_domain_idna() {
+        let mailbox = Mailbox::new("kontakt@Bücher.example".to_string());
+        assert_eq!(mailbox.domain_ascii().unwrap(), "xn--bcher-kva.example");
+        assert_eq!(mailbox.domain_unicode(), "bücher.example");
+        assert_eq!(
+            mailbox.to_ascii().unwrap().address,
+            "kontakt@xn--bcher-kva.example"
+        );
+
+        let mailbox = Mailbox::new("info@xn--mnchen-3ya.de".to_string());
+        assert_eq!(mailbox.domain_unicode(), "münchen.de");
+        assert_eq!(mailbox.domain_ascii().unwrap(), "xn--mnchen-3ya.de");
+
+        let group = Address::new_group(
+            "Friends".to_string(),
+            vec![Mailbox::new_with_name(
+                "Jürgen".to_string(),
+                "j@münchen.de".to_string(),
+            )],
+        );
+        assert_eq!(
+            group.to_ascii().unwrap(),
+            Address::new_group(
+                "Friends".to_string(),
+                vec![Mailbox::new_with_name(
+                    "Jürgen".to_string(),
+                    "j@xn--mnchen-3ya.de".to_string(),
+                )],
+            )
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = validate("john.doe+tag@example.org").unwrap();
+        assert_eq!(valid.local_part, "john.doe+tag");
+        assert_eq!(valid.domain, Domain::Name("example.org".to_string()));
+        assert!(!valid.is_quoted());
+
+        let valid = validate("\"john @doe\\\"\"@example.org").unwrap();
+        assert_eq!(valid.local_part, "\"john @doe\\\"\"");
+        assert!(valid.is_quoted());
+
+        let valid = validate("postmaster@[192.0.2.1]").unwrap();
+        assert_eq!(valid.domain, Domain::Literal("192.0.2.1".parse().unwrap()));
+        let valid = validate("postmaster@[IPv6:2001:db8::1]").unwrap();
+        assert_eq!(valid.to_string(), "postmaster@[IPv6:2001:db8::1]");
+
+        assert!(validate("kontakt@bücher.example").is_ok());
+        assert!(validate(&format!("{}@example.org", "a".repeat(64))).is_ok());
+    }
+
+    #[test]
+    fn test_validate_errors() {
+        let long_label = format!("a@{}.org", "b".repeat(64));
+        let tests = vec![
+            ("", AddressError::Empty),
+            ("joe", AddressError::MissingAt),
+            ("@example.org", AddressError::EmptyLocalPart),
+            (".joe@example.org", AddressError::MisplacedDot { pos: 0 }),
+            ("joe..doe@example.org", AddressError::MisplacedDot { pos: 4 }),
+            ("joe.@example.org", AddressError::MisplacedDot { pos: 3 }),
+            ("jo e@example.org", AddressError::InvalidChar { pos: 2, found: ' ' }),
+            ("jöe@example.org", AddressError::InvalidChar { pos: 1, found: 'ö' }),
+            ("\"joe@example.org", AddressError::UnterminatedQuote),
+            ("\"joe\"x@example.org", AddressError::InvalidChar { pos: 5, found: 'x' }),
+            ("joe@", AddressError::EmptyDomain),
+            ("joe@example..org", AddressError::EmptyLabel { pos: 12 }),
+            ("joe@example.org.", AddressError::EmptyLabel { pos: 16 }),
+            ("joe@exam_ple.org", AddressError::InvalidChar { pos: 8, found: '_' }),
+            (
+                "joe@-example.org",
+                AddressError::InvalidLabel {
+                    pos: 4,
+                    label: "-example".to_string(),
+                },
+            ),
+            (&long_label[..], AddressError::LabelTooLong { pos: 2, length: 64 }),
+            (
+                "joe@[300.0.0.1]",
+                AddressError::InvalidDomainLiteral {
+                    literal: "[300.0.0.1]".to_string(),
+                },
+            ),
+            (
+                "joe@[IPv6:1.2.3.4]",
+                AddressError::InvalidDomainLiteral {
+                    literal: "[IPv6:1.2.3.4]".to_string(),
+                },
+            ),
+        ];
+        for (address, error) in tests {
+            assert_eq!(validate(address), Err(error), "{}", address);
+        }
+
+        let local_part = format!("{}@example.org", "a".repeat(65));
+        assert_eq!(
+            validate(&local_part),
+            Err(AddressError::LocalPartTooLong { length: 65 })
+        );
+        assert_eq!(
+            validate(&format!("{}{}", "a".repeat(250), "@b.cd")),
+            Err(AddressError::TooLong { length: 255 })
+        );
+        // Within the overall limit, but not once the domain is converted
+        // to A-labels, each "ü" becoming "xn--tda"
+        let long_domain = format!("a@{}", vec!["ü"; 60].join("."));
+        assert_eq!(
+            validate(&long_domain),
+            Err(AddressError::DomainTooLong { length: 479 })
+        );
+    }
+
+    #[test]
+    fn test_mailbox_parse() {
+        let tests = vec![
+            (
+                "\"Blogs, Joe \\\"JB\\\"\" <joe@example.org>",
+                Some("Blogs, Joe \"JB\""),
+                "joe@example.org",
+            ),
+            ("joe@example.org (Joe Blogs)", Some("Joe Blogs"), "joe@example.org"),
+            ("Joe (the) Blogs <joe@example.org> (work)", Some("Joe Blogs"), "joe@example.org"),
+            ("  joe @ example.org  ", None, "joe@example.org"),
+            ("<postmaster@[192.0.2.1]>", None, "postmaster@[192.0.2.1]"),
+        ];
+        for (input, name, address) in tests {
+            let mailbox = Mailbox::parse(input).unwrap();
+            assert_eq!(mailbox.name.as_deref(), name, "{}", input);
+            assert_eq!(mailbox.address, address, "{}", input);
+        }
+
+        assert!(Mailbox::parse("joe@example.org, jane@example.org").is_err());
+        assert!(Mailbox::parse("Joe <joe@example.org").is_err());
+    }
+
+    #[test]
+    fn test_address_parse_list() {
+        let addresses =
+            Address::parse_list("Team: a@example.org, (nobody) , b@example.org;, , c@example.org,")
+                .unwrap();
+        assert_eq!(
+            addresses,
+            vec![
+                Address::new_group(
+                    "Team".to_string(),
+                    vec![
+                        Mailbox::new("a@example.org".to_string()),
+                        Mailbox::new("b@example.org".to_string()),
+                    ]
+                ),
+                Address::new_mailbox("c@example.org".to_string()),
+            ]
+        );
+
+        let addresses = Address::parse_list("Empty:;\r\n (comment) d@example.org").unwrap();
+        assert_eq!(
+            addresses,
+            vec![
+                Address::new_group("Empty".to_string(), vec![]),
+                Address::new_mailbox("d@example.org".to_string()),
+            ]
+        );
+        assert_eq!(Address::parse_list("").unwrap(), vec![]);
+    }
+
     #[test]
     fn test_address_from_string() {
         let addr = "\"Joe Blogs\" <joe@example.org>"
@@ -487,7 +1237,7 @@ mod tests {
 
         let header = Header::new_with_value("To".to_string(), addresses).unwrap();
         assert_eq!(&header.to_string()[..],
-                   "To: Joe Blogs <joe@example.org>, John Doe <john@example.org>, \r\n\tMr Black <mafia_black@example.org>");
+                   "To: Joe Blogs <joe@example.org>, John Doe <john@example.org>,\r\n Mr Black <mafia_black@example.org>");
     }
 
     #[test]
@@ -496,6 +1246,21 @@ mod tests {
         assert!(header.is_err());
     }
 
+    #[test]
+    fn test_parse_error_position() {
+        let err = AddressParser::new("Joe Blogs <joe@example.org")
+            .parse_address_list()
+            .unwrap_err();
+        assert_eq!(err.position(), Some(26));
+
+        let header = Header::new("To".to_string(), "joe@".to_string());
+        let err = header.get_value::<Vec<Address>>().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid To header: Expected domain at 4, found end of input"
+        );
+    }
+
     #[test]
     fn test_escape_email_address() {
         let display_name = "ä space";
@@ -0,0 +1,672 @@
+//! Module for the Authenticated Received Chain header set, as defined by RFC 8617.
+//!
+//! This deals with the structure of the `ARC-Authentication-Results`,
+//! `ARC-Message-Signature` and `ARC-Seal` headers and the chaining rules
+//! between their instances. Producing and checking the actual signatures is
+//! left to the caller; `seal_signing_input` gives the canonicalized data the
+//! seal's signature is computed over.
+use std::fmt;
+
+use super::canonicalize::header_relaxed;
+use super::header::{FromHeader, HeaderMap, ToHeader};
+use super::results::{ParsingError, ParsingResult};
+
+/// The highest instance number an ARC set may have (RFC 8617 Section 4.2.1)
+pub const MAX_ARC_INSTANCE: u32 = 50;
+
+pub const ARC_AUTHENTICATION_RESULTS: &str = "ARC-Authentication-Results";
+pub const ARC_MESSAGE_SIGNATURE: &str = "ARC-Message-Signature";
+pub const ARC_SEAL: &str = "ARC-Seal";
+
+/// A DKIM style `tag=value` list, as used by the ARC and DKIM signature headers.
+///
+/// Defined by RFC 6376 Section 3.2. Tags are kept in the order they were given.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct TagList {
+    tags: Vec<(String, String)>,
+}
+
+impl TagList {
+    pub fn new() -> TagList {
+        TagList { tags: Vec::new() }
+    }
+
+    /// Parse a tag-list from `s`.
+    ///
+    /// Duplicate tags are an error, as are tags without an `=`.
+    pub fn parse(s: &str) -> ParsingResult<TagList> {
+        let mut list = TagList::new();
+        for spec in s.split(';') {
+            if spec.trim().is_empty() {
+                // A trailing ';' is allowed
+                continue;
+            }
+            let mut parts = spec.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(v) => v.trim(),
+                None => {
+                    return Err(ParsingError::new(format!(
+                        "Missing '=' in tag-spec: {}",
+                        spec.trim()
+                    )))
+                }
+            };
+            if name.is_empty() {
+                return Err(ParsingError::new("Empty tag name in tag-list.".to_string()));
+            }
+            if list.get(name).is_some() {
+                return Err(ParsingError::new(format!("Duplicate tag: {}", name)));
+            }
+            list.tags.push((name.to_string(), value.to_string()));
+        }
+        Ok(list)
+    }
+
+    /// Get the value of the tag with `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| &v[..])
+    }
+
+    /// Get the value of the tag with `name`, failing if it is absent.
+    pub fn require(&self, name: &str) -> ParsingResult<&str> {
+        self.get(name)
+            .ok_or_else(|| ParsingError::new(format!("Missing required tag: {}", name)))
+    }
+
+    /// Set the value of tag `name`, keeping its position if it already exists.
+    pub fn set(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(n, _)| n == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Iterate over the `(name, value)` pairs of this list.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.tags.iter().map(|(n, v)| (&n[..], &v[..]))
+    }
+}
+
+impl fmt::Display for TagList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (name, value)) in self.tags.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}={}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Remove all whitespace from a tag value, such as a folded base64 signature.
+pub(crate) fn strip_whitespace(s: &str) -> String {
+    s.chars().filter(|c| !c.is_whitespace()).collect()
+}
+
+fn parse_instance(tags: &TagList) -> ParsingResult<u32> {
+    let value = tags.require("i")?;
+    match value.parse::<u32>() {
+        Ok(i) if (1..=MAX_ARC_INSTANCE).contains(&i) => Ok(i),
+        _ => Err(ParsingError::new(format!(
+            "Invalid ARC instance: {}",
+            value
+        ))),
+    }
+}
+
+/// The chain validation status recorded in an `ARC-Seal`'s `cv=` tag.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum ChainValidation {
+    /// No prior ARC sets existed, only valid for instance 1.
+    None,
+    /// The chain up to this point validated.
+    Pass,
+    /// The chain up to this point failed to validate.
+    Fail,
+}
+
+impl ChainValidation {
+    fn from_str(s: &str) -> ParsingResult<ChainValidation> {
+        match &s.to_ascii_lowercase()[..] {
+            "none" => Ok(ChainValidation::None),
+            "pass" => Ok(ChainValidation::Pass),
+            "fail" => Ok(ChainValidation::Fail),
+            x => Err(ParsingError::new(format!(
+                "Invalid chain validation: {}",
+                x
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ChainValidation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChainValidation::None => "none".fmt(f),
+            ChainValidation::Pass => "pass".fmt(f),
+            ChainValidation::Fail => "fail".fmt(f),
+        }
+    }
+}
+
+/// The `ARC-Authentication-Results` header, RFC 8617 Section 4.1.1
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ArcAuthenticationResults {
+    /// The instance number of the ARC set this belongs to
+    pub instance: u32,
+    /// The `Authentication-Results` payload, without the instance tag
+    pub results: String,
+}
+
+impl FromHeader for ArcAuthenticationResults {
+    fn from_header(value: String) -> ParsingResult<ArcAuthenticationResults> {
+        let mut parts = value.splitn(2, ';');
+        let instance_tag = TagList::parse(parts.next().unwrap_or(""))?;
+        let instance = parse_instance(&instance_tag)?;
+        Ok(ArcAuthenticationResults {
+            instance,
+            results: parts.next().unwrap_or("").trim().to_string(),
+        })
+    }
+}
+
+impl ToHeader for ArcAuthenticationResults {
+    fn to_header(value: ArcAuthenticationResults) -> ParsingResult<String> {
+        Ok(format!("i={}; {}", value.instance, value.results))
+    }
+}
+
+/// The `ARC-Message-Signature` header, RFC 8617 Section 4.1.2
+///
+/// This is a DKIM-Signature in all but name, so all tags are kept available
+/// through `tags`, with the commonly used ones pulled out for convenience.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ArcMessageSignature {
+    /// The instance number of the ARC set this belongs to
+    pub instance: u32,
+    /// The signing algorithm, `a=`
+    pub algorithm: String,
+    /// The signing domain, `d=`
+    pub domain: String,
+    /// The selector, `s=`
+    pub selector: String,
+    /// The names of the signed header fields, `h=`
+    pub signed_headers: Vec<String>,
+    /// The base64 body hash, `bh=`
+    pub body_hash: String,
+    /// The base64 signature, `b=`
+    pub signature: String,
+    /// All tags of the header, in order
+    pub tags: TagList,
+}
+
+impl FromHeader for ArcMessageSignature {
+    fn from_header(value: String) -> ParsingResult<ArcMessageSignature> {
+        let tags = TagList::parse(&value[..])?;
+        Ok(ArcMessageSignature {
+            instance: parse_instance(&tags)?,
+            algorithm: tags.require("a")?.to_string(),
+            domain: tags.require("d")?.to_string(),
+            selector: tags.require("s")?.to_string(),
+            signed_headers: tags
+                .require("h")?
+                .split(':')
+                .map(|h| h.trim().to_string())
+                .filter(|h| !h.is_empty())
+                .collect(),
+            body_hash: strip_whitespace(tags.require("bh")?),
+            signature: strip_whitespace(tags.require("b")?),
+            tags,
+        })
+    }
+}
+
+impl ToHeader for ArcMessageSignature {
+    fn to_header(value: ArcMessageSignature) -> ParsingResult<String> {
+        let mut tags = value.tags;
+        tags.set("i", &value.instance.to_string()[..]);
+        tags.set("a", &value.algorithm[..]);
+        tags.set("d", &value.domain[..]);
+        tags.set("s", &value.selector[..]);
+        tags.set("h", &value.signed_headers.join(":")[..]);
+        tags.set("bh", &value.body_hash[..]);
+        tags.set("b", &value.signature[..]);
+        Ok(tags.to_string())
+    }
+}
+
+/// The `ARC-Seal` header, RFC 8617 Section 4.1.3
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ArcSeal {
+    /// The instance number of the ARC set this belongs to
+    pub instance: u32,
+    /// The signing algorithm, `a=`
+    pub algorithm: String,
+    /// The chain validation status, `cv=`
+    pub chain_validation: ChainValidation,
+    /// The signing domain, `d=`
+    pub domain: String,
+    /// The selector, `s=`
+    pub selector: String,
+    /// The signature timestamp, `t=`
+    pub timestamp: Option<u64>,
+    /// The base64 signature, `b=`
+    pub signature: String,
+}
+
+impl FromHeader for ArcSeal {
+    fn from_header(value: String) -> ParsingResult<ArcSeal> {
+        let tags = TagList::parse(&value[..])?;
+        if tags.get("h").is_some() {
+            return Err(ParsingError::new(
+                "ARC-Seal must not have an h= tag.".to_string(),
+            ));
+        }
+        let timestamp = match tags.get("t") {
+            Some(t) => match t.parse() {
+                Ok(t) => Some(t),
+                Err(_) => return Err(ParsingError::new(format!("Invalid timestamp: {}", t))),
+            },
+            None => None,
+        };
+        Ok(ArcSeal {
+            instance: parse_instance(&tags)?,
+            algorithm: tags.require("a")?.to_string(),
+            chain_validation: ChainValidation::from_str(tags.require("cv")?)?,
+            domain: tags.require("d")?.to_string(),
+            selector: tags.require("s")?.to_string(),
+            timestamp,
+            signature: strip_whitespace(tags.require("b")?),
+        })
+    }
+}
+
+impl ToHeader for ArcSeal {
+    fn to_header(value: ArcSeal) -> ParsingResult<String> {
+        let mut result = format!(
+            "i={}; a={}; cv={}; d={}; s={}",
+            value.instance, value.algorithm, value.chain_validation, value.domain, value.selector
+        );
+        if let Some(t) = value.timestamp {
+            result = format!("{}; t={}", result, t);
+        }
+        Ok(format!("{}; b={}", result, value.signature))
+    }
+}
+
+/// One complete ARC set: the three headers sharing an instance number.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct ArcSet {
+    pub authentication_results: ArcAuthenticationResults,
+    pub message_signature: ArcMessageSignature,
+    pub seal: ArcSeal,
+}
+
+impl ArcSet {
+    /// The instance number of this set.
+    pub fn instance(&self) -> u32 {
+        self.seal.instance
+    }
+}
+
+/// The ordered collection of ARC sets found on a message.
+#[derive(Eq, PartialEq, Debug, Clone, Default)]
+pub struct ArcChain {
+    /// The sets of this chain, ordered by instance starting at 1
+    pub sets: Vec<ArcSet>,
+}
+
+impl ArcChain {
+    /// Collect and structurally validate the ARC sets in `headers`.
+    ///
+    /// This checks the rules of RFC 8617 Section 5.2 which do not need any
+    /// cryptography: every instance from 1 up to the highest one has exactly
+    /// one of each ARC header, the first seal has `cv=none` and every later
+    /// seal has `cv=pass`.
+    pub fn from_headers(headers: &HeaderMap) -> ParsingResult<ArcChain> {
+        let results: Vec<ArcAuthenticationResults> = collect(headers, ARC_AUTHENTICATION_RESULTS)?;
+        let signatures: Vec<ArcMessageSignature> = collect(headers, ARC_MESSAGE_SIGNATURE)?;
+        let seals: Vec<ArcSeal> = collect(headers, ARC_SEAL)?;
+
+        let count = seals.len();
+        if results.len() != count || signatures.len() != count {
+            return Err(ParsingError::new(format!(
+                "Incomplete ARC sets: {} {}, {} {}, {} {}",
+                results.len(),
+                ARC_AUTHENTICATION_RESULTS,
+                signatures.len(),
+                ARC_MESSAGE_SIGNATURE,
+                count,
+                ARC_SEAL
+            )));
+        }
+
+        let mut sets = Vec::new();
+        for instance in 1..=(count as u32) {
+            let find_err = |name: &str| {
+                ParsingError::new(format!("Missing {} for instance {}", name, instance))
+            };
+            let authentication_results = results
+                .iter()
+                .find(|h| h.instance == instance)
+                .ok_or_else(|| find_err(ARC_AUTHENTICATION_RESULTS))?;
+            let message_signature = signatures
+                .iter()
+                .find(|h| h.instance == instance)
+                .ok_or_else(|| find_err(ARC_MESSAGE_SIGNATURE))?;
+            let seal = seals
+                .iter()
+                .find(|h| h.instance == instance)
+                .ok_or_else(|| find_err(ARC_SEAL))?;
+
+            let expected_cv = if instance == 1 {
+                ChainValidation::None
+            } else {
+                ChainValidation::Pass
+            };
+            if seal.chain_validation != expected_cv {
+                return Err(ParsingError::new(format!(
+                    "ARC-Seal instance {} has cv={}, expected cv={}",
+                    instance, seal.chain_validation, expected_cv
+                )));
+            }
+
+            sets.push(ArcSet {
+                authentication_results: authentication_results.clone(),
+                message_signature: message_signature.clone(),
+                seal: seal.clone(),
+            });
+        }
+
+        Ok(ArcChain { sets })
+    }
+
+    /// The instance number the next ARC set added to this message should use.
+    pub fn next_instance(&self) -> ParsingResult<u32> {
+        let next = self.sets.len() as u32 + 1;
+        if next > MAX_ARC_INSTANCE {
+            Err(ParsingError::new(format!(
+                "ARC chain already has {} sets.",
+                MAX_ARC_INSTANCE
+            )))
+        } else {
+            Ok(next)
+        }
+    }
+
+    /// The `cv=` value a new seal added to this chain should carry, assuming
+    /// the signatures of the existing sets have been verified as valid.
+    pub fn next_chain_validation(&self) -> ChainValidation {
+        if self.sets.is_empty() {
+            ChainValidation::None
+        } else {
+            ChainValidation::Pass
+        }
+    }
+}
+
+fn collect<T: FromHeader>(headers: &HeaderMap, name: &str) -> ParsingResult<Vec<T>> {
+    match headers.find(name) {
+        Some(found) => found.iter().map(|h| h.get_value()).collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Remove the value of the `b=` tag from a signature header value,
+/// leaving the rest of it untouched.
+fn empty_signature_tag(value: &str) -> String {
+    let specs: Vec<String> = value
+        .split(';')
+        .map(|spec| {
+            let mut parts = spec.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(_)) if name.trim() == "b" => {
+                    let leading = &spec[..spec.len() - spec.trim_start().len()];
+                    format!("{}b=", leading)
+                }
+                _ => spec.to_string(),
+            }
+        })
+        .collect();
+    specs.join(";")
+}
+
+/// Build the data the signature of the `ARC-Seal` with `instance` is
+/// computed over, as defined in RFC 8617 Section 5.1.1.
+///
+/// This is every ARC header up to and including `instance`, in set order,
+/// with relaxed header canonicalization and the `b=` value of the seal
+/// being signed removed. To create a new seal, add it to `headers` with an
+/// empty `b=` first, sign the result of this, and then replace the header.
+pub fn seal_signing_input(headers: &HeaderMap, instance: u32) -> ParsingResult<String> {
+    let mut result = String::new();
+    for i in 1..=instance {
+        for name in [ARC_AUTHENTICATION_RESULTS, ARC_MESSAGE_SIGNATURE, ARC_SEAL].iter() {
+            let header = headers
+                .find(name)
+                .and_then(|found| {
+                    found.into_iter().find(|h| {
+                        let tags = h.raw_value().split(';').next().unwrap_or("");
+                        TagList::parse(tags)
+                            .ok()
+                            .and_then(|tags| parse_instance(&tags).ok())
+                            == Some(i)
+                    })
+                })
+                .ok_or_else(|| ParsingError::new(format!("Missing {} for instance {}", name, i)))?;
+
+            // What's signed is the value as it's written, with any encoded
+            // words left undecoded
+            let value = header.raw_value();
+            if *name == ARC_SEAL && i == instance {
+                let canonical = header_relaxed(name, &empty_signature_tag(value)[..]);
+                // The seal being signed has no trailing CRLF
+                result.push_str(canonical.trim_end_matches("\r\n"));
+            } else {
+                result.push_str(&header_relaxed(name, value)[..]);
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::header::Header;
+    use super::*;
+
+    fn sample_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let arc_headers = vec![
+            (ARC_SEAL, "i=2; a=rsa-sha256; t=12346; cv=pass; d=example.net; s=sel2; b=c2Vh\r\n\t bDI="),
+            (ARC_MESSAGE_SIGNATURE, "i=2; a=rsa-sha256; c=relaxed/relaxed; d=example.net; s=sel2; h=from:to:subject; bh=Ym9keQ==; b=c2ln Mg=="),
+            (ARC_AUTHENTICATION_RESULTS, "i=2; example.net; arc=pass"),
+            (ARC_SEAL, "i=1; a=rsa-sha256; t=12345; cv=none; d=example.org; s=sel1; b=c2VhbDE="),
+            (ARC_MESSAGE_SIGNATURE, "i=1; a=rsa-sha256; c=relaxed/relaxed; d=example.org; s=sel1; h=from:to; bh=Ym9keQ==; b=c2lnMQ=="),
+            (ARC_AUTHENTICATION_RESULTS, "i=1; example.org; spf=pass smtp.mailfrom=example.org"),
+        ];
+        for (name, value) in arc_headers.into_iter() {
+            headers.insert(Header::new(name.to_string(), value.to_string()));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_tag_list_parse() {
+        let tags = TagList::parse(" a = rsa-sha256;d=example.org; b=abc\r\n\tdef;").unwrap();
+        assert_eq!(tags.get("a"), Some("rsa-sha256"));
+        assert_eq!(tags.get("d"), Some("example.org"));
+        assert_eq!(tags.get("b"), Some("abc\r\n\tdef"));
+        assert_eq!(tags.get("x"), None);
+        assert_eq!(
+            tags.to_string(),
+            "a=rsa-sha256; d=example.org; b=abc\r\n\tdef"
+        );
+
+        assert!(TagList::parse("a=1; a=2").is_err());
+        assert!(TagList::parse("a=1; novalue").is_err());
+    }
+
+    #[test]
+    fn test_arc_header_parse() {
+        let seal: ArcSeal = Header::new(
+            ARC_SEAL.to_string(),
+            "i=1; a=rsa-sha256; t=12345; cv=none; d=example.org; s=sel1; b=c2Vh bDE=".to_string(),
+        )
+        .get_value()
+        .unwrap();
+        assert_eq!(seal.instance, 1);
+        assert_eq!(seal.chain_validation, ChainValidation::None);
+        assert_eq!(seal.timestamp, Some(12345));
+        assert_eq!(seal.signature, "c2VhbDE=");
+
+        let ams: ArcMessageSignature = Header::new(
+            ARC_MESSAGE_SIGNATURE.to_string(),
+            "i=3; a=rsa-sha256; d=example.org; s=sel; h=From : To; bh=YQ==; b=Yg==".to_string(),
+        )
+        .get_value()
+        .unwrap();
+        assert_eq!(ams.instance, 3);
+        assert_eq!(
+            ams.signed_headers,
+            vec!["From".to_string(), "To".to_string()]
+        );
+
+        let aar: ArcAuthenticationResults = Header::new(
+            ARC_AUTHENTICATION_RESULTS.to_string(),
+            "i=1; example.org; dkim=pass".to_string(),
+        )
+        .get_value()
+        .unwrap();
+        assert_eq!(aar.results, "example.org; dkim=pass");
+
+        let bad_instances = vec![
+            "i=0; a=x; cv=none; d=x; s=x; b=",
+            "i=51; a=x; cv=none; d=x; s=x; b=",
+        ];
+        for value in bad_instances.into_iter() {
+            let header = Header::new(ARC_SEAL.to_string(), value.to_string());
+            assert!(header.get_value::<ArcSeal>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_arc_header_construction() {
+        let seal = ArcSeal {
+            instance: 1,
+            algorithm: "rsa-sha256".to_string(),
+            chain_validation: ChainValidation::None,
+            domain: "example.org".to_string(),
+            selector: "sel".to_string(),
+            timestamp: Some(1),
+            signature: "".to_string(),
+        };
+        let header = Header::new_with_value(ARC_SEAL.to_string(), seal.clone()).unwrap();
+        assert_eq!(
+            header.to_string(),
+            "ARC-Seal: i=1; a=rsa-sha256; cv=none; d=example.org; s=sel; t=1; b="
+        );
+        assert_eq!(header.get_value::<ArcSeal>().unwrap(), seal);
+
+        let aar = ArcAuthenticationResults {
+            instance: 1,
+            results: "example.org; spf=pass".to_string(),
+        };
+        let header = Header::new_with_value(ARC_AUTHENTICATION_RESULTS.to_string(), aar).unwrap();
+        assert_eq!(
+            header.to_string(),
+            "ARC-Authentication-Results: i=1; example.org; spf=pass"
+        );
+    }
+
+    #[test]
+    fn test_chain_validation() {
+        let chain = ArcChain::from_headers(&sample_headers()).unwrap();
+        assert_eq!(chain.sets.len(), 2);
+        assert_eq!(chain.sets[0].instance(), 1);
+        assert_eq!(chain.sets[1].message_signature.domain, "example.net");
+        assert_eq!(chain.next_instance().unwrap(), 3);
+        assert_eq!(chain.next_chain_validation(), ChainValidation::Pass);
+
+        let empty = ArcChain::from_headers(&HeaderMap::new()).unwrap();
+        assert!(empty.sets.is_empty());
+        assert_eq!(empty.next_chain_validation(), ChainValidation::None);
+
+        // A gap in the instances
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new(
+            ARC_SEAL.to_string(),
+            "i=2; a=x; cv=pass; d=x; s=x; b=".to_string(),
+        ));
+        headers.insert(Header::new(
+            ARC_MESSAGE_SIGNATURE.to_string(),
+            "i=2; a=x; d=x; s=x; h=from; bh=; b=".to_string(),
+        ));
+        headers.insert(Header::new(
+            ARC_AUTHENTICATION_RESULTS.to_string(),
+            "i=2; x".to_string(),
+        ));
+        assert!(ArcChain::from_headers(&headers).is_err());
+
+        // An incomplete set
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new(
+            ARC_SEAL.to_string(),
+            "i=1; a=x; cv=none; d=x; s=x; b=".to_string(),
+        ));
+        assert!(ArcChain::from_headers(&headers).is_err());
+
+        // The first seal must have cv=none
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new(
+            ARC_SEAL.to_string(),
+            "i=1; a=x; cv=pass; d=x; s=x; b=".to_string(),
+        ));
+        headers.insert(Header::new(
+            ARC_MESSAGE_SIGNATURE.to_string(),
+            "i=1; a=x; d=x; s=x; h=from; bh=; b=".to_string(),
+        ));
+        headers.insert(Header::new(
+            ARC_AUTHENTICATION_RESULTS.to_string(),
+            "i=1; x".to_string(),
+        ));
+        assert!(ArcChain::from_headers(&headers).is_err());
+    }
+
+    #[test]
+    fn test_seal_signing_input() {
+        let input = seal_signing_input(&sample_headers(), 1).unwrap();
+        assert_eq!(
+            input,
+            "arc-authentication-results:i=1; example.org; spf=pass smtp.mailfrom=example.org\r\n\
+             arc-message-signature:i=1; a=rsa-sha256; c=relaxed/relaxed; d=example.org; s=sel1; h=from:to; bh=Ym9keQ==; b=c2lnMQ==\r\n\
+             arc-seal:i=1; a=rsa-sha256; t=12345; cv=none; d=example.org; s=sel1; b="
+        );
+
+        let input = seal_signing_input(&sample_headers(), 2).unwrap();
+        assert!(input.contains(
+            "arc-seal:i=1; a=rsa-sha256; t=12345; cv=none; d=example.org; s=sel1; b=c2VhbDE=\r\n"
+        ));
+        assert!(input
+            .ends_with("arc-seal:i=2; a=rsa-sha256; t=12346; cv=pass; d=example.net; s=sel2; b="));
+
+        assert!(seal_signing_input(&sample_headers(), 3).is_err());
+
+        let mut headers = HeaderMap::new();
+        let arc_headers = [
+            (ARC_SEAL, "i=1; cv=none; b=c2VhbDE="),
+            (ARC_MESSAGE_SIGNATURE, "i=1; b=c2lnMQ=="),
+            (ARC_AUTHENTICATION_RESULTS, "i=1; =?utf-8?q?caf=C3=A9?="),
+        ];
+        for &(name, value) in arc_headers.iter() {
+            headers.insert(Header::new(name.to_string(), value.to_string()));
+        }
+        let input = seal_signing_input(&headers, 1).unwrap();
+        assert!(input.contains("i=1; =?utf-8?q?caf=C3=A9?=\r\n"));
+    }
+}
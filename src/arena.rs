@@ -0,0 +1,297 @@
+//! Module for parsing messages in bulk, into parts whose strings are
+//! borrowed from the input or from an arena reused from one message to
+//! the next.
+//!
+//! Parsing into a `MimeMessage` allocates each header name and value, and
+//! each body, on its own, which adds up to thousands of small allocations
+//! for a large message. A `ParseArena` instead keeps the only strings
+//! which aren't in the input as they are, the values of folded headers,
+//! in one buffer, and its parts and headers in two lists. All three keep
+//! their capacity between messages, so once the arena has grown to fit the
+//! largest message, parsing allocates little more than what's needed to
+//! split multiparts.
+use std::ops::Range;
+
+use super::header::FromHeader;
+use super::message::{limit_exceeded, Limits, MimeMessage};
+use super::mimeheaders::ContentType;
+use super::results::ParsingResult;
+use super::rfc5322::{unfold, Rfc5322Parser};
+
+// Where a string parsed into the arena is, either in the input or in the
+// arena's own text
+#[derive(Debug, Clone)]
+enum Slot {
+    Input(Range<usize>),
+    Text(Range<usize>),
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: Range<usize>,
+    value: Slot,
+}
+
+#[derive(Debug, Clone)]
+struct Part {
+    // The indexes of the part's headers in the arena's fields
+    fields: Range<usize>,
+    body: Range<usize>,
+    // The index after the last of the part's descendants, which are the
+    // parts following it
+    end: usize,
+}
+
+/// Storage for parsing one message after another, as a service ingesting
+/// mail in bulk does.
+#[derive(Debug, Clone, Default)]
+pub struct ParseArena {
+    limits: Limits,
+    text: String,
+    fields: Vec<Field>,
+    parts: Vec<Part>,
+}
+
+impl ParseArena {
+    pub fn new() -> ParseArena {
+        ParseArena::default()
+    }
+
+    /// An arena which fails to parse input beyond `limits`, as
+    /// `ParseOptions::limits` does. The decoded size of bodies isn't
+    /// limited, as they aren't decoded.
+    pub fn with_limits(limits: Limits) -> ParseArena {
+        ParseArena {
+            limits,
+            ..ParseArena::default()
+        }
+    }
+
+    /// Parse the message `input`, replacing whatever was parsed into the
+    /// arena before.
+    ///
+    /// The headers are found as `MimeMessage::parse` finds them, and the
+    /// children of multipart parts split up the same way, but nothing is
+    /// decoded and no repairs are made.
+    pub fn parse<'a>(&'a mut self, input: &'a str) -> ParsingResult<ArenaPart<'a>> {
+        self.text.clear();
+        self.fields.clear();
+        self.parts.clear();
+        self.parse_part(input, 0..input.len(), 1)?;
+        Ok(ArenaPart {
+            input,
+            arena: self,
+            index: 0,
+        })
+    }
+
+    fn parse_part(&mut self, input: &str, span: Range<usize>, depth: usize) -> ParsingResult<()> {
+        if depth > self.limits.max_depth {
+            return Err(limit_exceeded("nesting depth", self.limits.max_depth));
+        } else if self.parts.len() >= self.limits.max_parts {
+            return Err(limit_exceeded("part count", self.limits.max_parts));
+        }
+        let index = self.parts.len();
+        let fields_start = self.fields.len();
+        let body_start = self.parse_fields(&input[..span.end], span.start)?;
+        self.parts.push(Part {
+            fields: fields_start..self.fields.len(),
+            body: body_start..span.end,
+            end: index + 1,
+        });
+
+        let boundary = self.part(input, index).boundary();
+        if let Some(boundary) = boundary {
+            let body = &input[body_start..span.end];
+            let (mut children, _) = MimeMessage::split_boundary(body, &boundary);
+            // What comes before the first child is the body of the part
+            if !children.is_empty() {
+                let preamble = children.remove(0);
+                self.parts[index].body = body_start..body_start + preamble.end;
+            }
+            for child in children {
+                let child = body_start + child.start..body_start + child.end;
+                self.parse_part(input, child, depth + 1)?;
+            }
+        }
+        self.parts[index].end = self.parts.len();
+        Ok(())
+    }
+
+    // Parse the headers starting at `start`, giving where the body after
+    // them starts.
+    fn parse_fields(&mut self, input: &str, start: usize) -> ParsingResult<usize> {
+        let mut parser = Rfc5322Parser::new_at(input, start);
+        let mut count = 0;
+        loop {
+            let field_start = parser.pos();
+            let (name, value) = match parser.consume_field() {
+                Some(field) => field,
+                None => break,
+            };
+            count += 1;
+            if count > self.limits.max_header_count {
+                return Err(limit_exceeded("header count", self.limits.max_header_count));
+            } else if parser.pos() - field_start > self.limits.max_header_length {
+                return Err(limit_exceeded(
+                    "header length",
+                    self.limits.max_header_length,
+                ));
+            }
+            let folded = &input[value.clone()];
+            let value = if folded.contains(&['\r', '\n'][..]) {
+                let text_start = self.text.len();
+                unfold(folded, &mut self.text);
+                Slot::Text(text_start..self.text.len())
+            } else {
+                Slot::Input(value)
+            };
+            self.fields.push(Field { name, value });
+        }
+        // The blank line ending the headers isn't part of the body
+        if !parser.eof() && parser.peek_linebreak() {
+            parser.consume_linebreak();
+        }
+        Ok(parser.pos())
+    }
+
+    fn part<'a>(&'a self, input: &'a str, index: usize) -> ArenaPart<'a> {
+        ArenaPart {
+            input,
+            arena: self,
+            index,
+        }
+    }
+}
+
+/// A part of a message parsed with `ParseArena::parse`, borrowing from
+/// the input and the arena.
+#[derive(Debug, Clone, Copy)]
+pub struct ArenaPart<'a> {
+    input: &'a str,
+    arena: &'a ParseArena,
+    index: usize,
+}
+
+impl<'a> ArenaPart<'a> {
+    /// The name and value of each header, in order, with the value
+    /// unfolded as `Header::raw_value` gives it.
+    pub fn headers(&self) -> impl Iterator<Item = (&'a str, &'a str)> + 'a {
+        let (input, arena) = (self.input, self.arena);
+        let fields = &arena.fields[arena.parts[self.index].fields.clone()];
+        fields.iter().map(move |field| {
+            let value = match field.value {
+                Slot::Input(ref range) => &input[range.clone()],
+                Slot::Text(ref range) => &arena.text[range.clone()],
+            };
+            (&input[field.name.clone()], value)
+        })
+    }
+
+    /// The value of the last header with `name`, as with `HeaderMap::get`.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers()
+            .filter(|(found, _)| found.eq_ignore_ascii_case(name))
+            .last()
+            .map(|(_, value)| value)
+    }
+
+    /// The body of the part, still transfer encoded, which for a multipart
+    /// is what comes before its first child, as with `MimeMessage::body`.
+    pub fn body(&self) -> &'a str {
+        &self.input[self.arena.parts[self.index].body.clone()]
+    }
+
+    /// The children of a multipart part, in order.
+    pub fn children(&self) -> impl Iterator<Item = ArenaPart<'a>> + 'a {
+        let (input, arena) = (self.input, self.arena);
+        let end = arena.parts[self.index].end;
+        let mut next = self.index + 1;
+        std::iter::from_fn(move || {
+            if next >= end {
+                return None;
+            }
+            let child = arena.part(input, next);
+            next = arena.parts[next].end;
+            Some(child)
+        })
+    }
+
+    /// The Content-Type of the part, if it has a valid one.
+    pub fn content_type(&self) -> Option<ContentType> {
+        ContentType::from_header(self.header("Content-Type")?.to_string()).ok()
+    }
+
+    // The boundary of a multipart part
+    fn boundary(&self) -> Option<String> {
+        // Most parts aren't multiparts, so don't parse their Content-Type
+        let value = self.header("Content-Type")?.trim_start();
+        let is_multipart = value.get(..10)?.eq_ignore_ascii_case("multipart/");
+        if !is_multipart {
+            return None;
+        }
+        let content_type = self.content_type()?;
+        content_type.boundary().map(str::to_string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_arena() {
+        let input = "Subject: Hello\r\n\tWorld \r\n\
+                     Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+                     Preamble\r\n\
+                     --b\r\n\
+                     Content-Type: text/plain\r\n\r\n\
+                     One\r\n\
+                     --b\r\n\
+                     Content-Type: multipart/alternative; boundary=c\r\n\r\n\
+                     --c\r\n\r\nTwo\r\n\
+                     --c--\r\n\
+                     --b--\r\n";
+        let mut arena = ParseArena::new();
+        let message = arena.parse(input).unwrap();
+        assert_eq!(message.header("subject"), Some("Hello World "));
+        assert_eq!(message.body(), "Preamble\r\n");
+        let children: Vec<ArenaPart> = message.children().collect();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].body(), "One\r\n");
+        assert_eq!(children[1].children().count(), 1);
+        let nested = children[1].children().next().unwrap();
+        assert_eq!(nested.headers().count(), 0);
+        assert_eq!(nested.body(), "Two\r\n");
+
+        // Headers and bodies come out as parsing into a `MimeMessage` gives
+        let parsed = MimeMessage::parse(input).unwrap();
+        let headers: Vec<(&str, &str)> = message.headers().collect();
+        let expected: Vec<(&str, &str)> = parsed
+            .headers
+            .iter()
+            .map(|header| (&header.name[..], header.raw_value()))
+            .collect();
+        assert_eq!(headers, expected);
+        assert_eq!(children[0].body(), parsed.children[0].body);
+
+        // The arena is reused for the next message
+        let message = arena.parse("Subject: Again\r\n\r\nBody").unwrap();
+        assert_eq!(message.header("Subject"), Some("Again"));
+        assert_eq!(message.children().count(), 0);
+        assert_eq!(message.body(), "Body");
+    }
+
+    #[test]
+    fn test_parse_arena_limits() {
+        let limits = Limits {
+            max_depth: 1,
+            ..Limits::default()
+        };
+        let mut arena = ParseArena::with_limits(limits);
+        let input = "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+                     --b\r\n\r\nOne\r\n--b--\r\n";
+        assert!(arena.parse(input).is_err());
+        assert!(arena.parse("Subject: Hi\r\n\r\n").is_ok());
+    }
+}
@@ -0,0 +1,513 @@
+//! Module for listing the files attached to a message.
+//!
+//! Every part of a message besides its text and HTML bodies is taken as an
+//! attachment. Outlook may instead wrap the files it sends in a single
+//! `application/ms-tnef` part, usually named `winmail.dat`, which other
+//! clients have to unwrap themselves. `MimeMessage::attachments` gives such
+//! a part as it is, so it's never lost, while
+//! `MimeMessage::attachments_with_tnef` unwraps it with a `TnefDecoder`.
+//! Files yEncoded in text parts are also given, as are files uuencoded in
+//! them when the message was parsed with `ParseOptions::uuencode`.
+//!
+//! Files from old Mac clients, in BinHex, AppleSingle or AppleDouble, are
+//! unwrapped so their data fork is given as the content, and any resource
+//! fork separately.
+use super::macfile::{decode_appledouble_header, decode_applesingle, decode_binhex};
+use super::message::MimeMessage;
+use super::mimeheaders::{guess_mime_type, ContentType};
+use super::results::ParsingResult;
+use super::uuencode::find_uuencoded;
+use super::walk::PartPath;
+use super::yenc::find_yenc;
+
+#[cfg(feature = "tnef")]
+use super::message::Quirk;
+#[cfg(feature = "tnef")]
+use super::results::ParsingError;
+
+/// Where an `Attachment` was found.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum AttachmentSource {
+    /// The attachment is a part of the message
+    Part,
+    /// The attachment was unwrapped from an `application/ms-tnef` part
+    Tnef,
+    /// The attachment was uuencoded in the text of a part
+    Uuencoded,
+    /// The attachment was yEncoded in the text of a part
+    Yenc,
+    /// The attachment was unwrapped from an `application/mac-binhex40` part
+    BinHex,
+    /// The attachment was unwrapped from an AppleSingle
+    /// `application/applefile` part
+    AppleSingle,
+    /// The attachment is the data of a `multipart/appledouble` part
+    AppleDouble,
+}
+
+/// A file attached to a message.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Attachment {
+    /// The part the attachment is, or was found in
+    pub path: PartPath,
+    pub source: AttachmentSource,
+    pub filename: Option<String>,
+    pub content_type: ContentType,
+    /// The decoded content, or the data fork of a Mac file
+    pub content: Vec<u8>,
+    /// The resource fork of a Mac file, if it has one
+    pub resource_fork: Option<Vec<u8>>,
+}
+
+/// A file unwrapped from TNEF data.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TnefFile {
+    pub filename: Option<String>,
+    pub content: Vec<u8>,
+}
+
+/// A decoder for the TNEF data of `application/ms-tnef` parts.
+pub trait TnefDecoder {
+    /// The files wrapped in `data`.
+    fn decode(&self, data: &[u8]) -> ParsingResult<Vec<TnefFile>>;
+}
+
+impl MimeMessage {
+    /// Whether this part holds TNEF data, as sent by Outlook.
+    pub fn is_tnef(&self) -> bool {
+        let content_type = self.content_type();
+        content_type.ttype == "application"
+            && (content_type.subtype == "ms-tnef" || content_type.subtype == "vnd.ms-tnef")
+    }
+
+    /// The files attached to this message, in the order they appear.
+    ///
+    /// Any TNEF part is given as it is; see `attachments_with_tnef` to
+    /// unwrap the files inside. With the `tnef` feature, the message is
+    /// unwrapped with `SimpleTnefDecoder` instead if it was parsed with
+    /// `Quirk::TnefAttachments`.
+    pub fn attachments(&self) -> Vec<Attachment> {
+        #[cfg(feature = "tnef")]
+        {
+            if self.quirks.contains(Quirk::TnefAttachments) {
+                return self.find_attachments(Some(&SimpleTnefDecoder));
+            }
+        }
+        self.find_attachments(None)
+    }
+
+    /// The files attached to this message, with any TNEF parts unwrapped
+    /// by `decoder`.
+    ///
+    /// A TNEF part which can't be decoded, or which holds no files, is
+    /// given as it is.
+    pub fn attachments_with_tnef(&self, decoder: &dyn TnefDecoder) -> Vec<Attachment> {
+        self.find_attachments(Some(decoder))
+    }
+
+    fn find_attachments(&self, tnef: Option<&dyn TnefDecoder>) -> Vec<Attachment> {
+        let text_body = self.find_body_part("plain");
+        let html_body = self.find_body_part("html");
+        let is_body = |part: &MimeMessage| {
+            [text_body, html_body]
+                .iter()
+                .any(|body| body.is_some_and(|body| std::ptr::eq(body, part)))
+        };
+
+        let mut attachments = Vec::new();
+        let mut apple_double: Option<PartPath> = None;
+        for (path, part) in self.parts_depth_first() {
+            // The parts of an AppleDouble file were given with it
+            if apple_double
+                .as_ref()
+                .is_some_and(|parent| path.indices().starts_with(parent.indices()))
+            {
+                continue;
+            }
+            if let Some(attachment) = apple_double_attachment(&path, part) {
+                attachments.push(attachment);
+                apple_double = Some(path);
+                continue;
+            }
+            if part.message_type.is_some() || !part.children.is_empty() {
+                continue;
+            }
+            let content = part
+                .decoded_body_bytes()
+                .unwrap_or_else(|| part.body.as_bytes().to_vec());
+
+            if part.is_tnef() {
+                let files = tnef.and_then(|decoder| decoder.decode(&content[..]).ok());
+                if let Some(files) = files.filter(|files| !files.is_empty()) {
+                    attachments.extend(files.into_iter().map(|file| Attachment {
+                        path: path.clone(),
+                        source: AttachmentSource::Tnef,
+                        content_type: guess_mime_type(file.filename.as_deref().unwrap_or("")),
+                        filename: file.filename,
+                        content: file.content,
+                        resource_fork: None,
+                    }));
+                    continue;
+                }
+            }
+            if let Some(attachment) = mac_attachment(&path, part, &content[..]) {
+                attachments.push(attachment);
+                continue;
+            }
+
+            // Files encoded in the text, in the order they appear
+            let content_type = part.content_type();
+            let mut embedded = Vec::new();
+            if content_type.ttype == "text" {
+                if part.uuencode {
+                    let files = find_uuencoded(&String::from_utf8_lossy(&content[..]));
+                    embedded.extend(files.into_iter().map(|file| {
+                        (file.span.start, AttachmentSource::Uuencoded, file.filename, file.content)
+                    }));
+                }
+                let files = find_yenc(&content[..]).into_iter();
+                embedded.extend(files.filter(|file| file.is_complete()).map(|file| {
+                    (file.span.start, AttachmentSource::Yenc, file.filename, file.content)
+                }));
+                embedded.sort_by_key(|&(start, ..)| start);
+            }
+            if !is_body(part) {
+                attachments.push(Attachment {
+                    path: path.clone(),
+                    source: AttachmentSource::Part,
+                    filename: part.filename(),
+                    content_type,
+                    content,
+                    resource_fork: None,
+                });
+            }
+            attachments.extend(embedded.into_iter().map(|(_, source, filename, content)| {
+                Attachment {
+                    path: path.clone(),
+                    source,
+                    content_type: guess_mime_type(&filename[..]),
+                    filename: Some(filename),
+                    content,
+                    resource_fork: None,
+                }
+            }));
+        }
+        attachments
+    }
+}
+
+// The file of a `multipart/appledouble` part, which holds an AppleDouble
+// header then the data fork.
+fn apple_double_attachment(path: &PartPath, part: &MimeMessage) -> Option<Attachment> {
+    let content_type = part.content_type();
+    if content_type.ttype != "multipart" || content_type.subtype != "appledouble" {
+        return None;
+    }
+    let (header, data) = match &part.children[..] {
+        [header, data] => (header, data),
+        _ => return None,
+    };
+    let file = decode_appledouble_header(&header.decoded_body_bytes()?[..]).ok()?;
+    Some(Attachment {
+        path: path.clone(),
+        source: AttachmentSource::AppleDouble,
+        filename: data.filename().or(file.filename),
+        content_type: data.content_type(),
+        content: data
+            .decoded_body_bytes()
+            .unwrap_or_else(|| data.body.as_bytes().to_vec()),
+        resource_fork: Some(file.resource_fork).filter(|fork| !fork.is_empty()),
+    })
+}
+
+// The file of a BinHex or AppleSingle part.
+fn mac_attachment(path: &PartPath, part: &MimeMessage, content: &[u8]) -> Option<Attachment> {
+    let content_type = part.content_type();
+    if content_type.ttype != "application" {
+        return None;
+    }
+    let (source, file) = match &content_type.subtype[..] {
+        "mac-binhex40" => (
+            AttachmentSource::BinHex,
+            decode_binhex(&String::from_utf8_lossy(content)).ok()?,
+        ),
+        "applefile" => (AttachmentSource::AppleSingle, decode_applesingle(content).ok()?),
+        _ => return None,
+    };
+    let filename = file.filename.or_else(|| part.filename());
+    Some(Attachment {
+        path: path.clone(),
+        source,
+        content_type: guess_mime_type(filename.as_deref().unwrap_or("")),
+        filename,
+        content: file.data_fork,
+        resource_fork: Some(file.resource_fork).filter(|fork| !fork.is_empty()),
+    })
+}
+
+/// A TNEF decoder for the attachments of simple TNEF streams: their titles,
+/// which are used as filenames, and data.
+///
+/// MAPI properties, such as long filenames and the message body, are
+/// skipped.
+#[cfg(feature = "tnef")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimpleTnefDecoder;
+
+#[cfg(feature = "tnef")]
+const TNEF_SIGNATURE: u32 = 0x223e_9f78;
+#[cfg(feature = "tnef")]
+const ATT_ATTACH_REND_DATA: u32 = 0x0006_9002;
+#[cfg(feature = "tnef")]
+const ATT_ATTACH_TITLE: u32 = 0x0001_8010;
+#[cfg(feature = "tnef")]
+const ATT_ATTACH_DATA: u32 = 0x0006_800f;
+
+#[cfg(feature = "tnef")]
+impl TnefDecoder for SimpleTnefDecoder {
+    fn decode(&self, data: &[u8]) -> ParsingResult<Vec<TnefFile>> {
+        let truncated = || ParsingError::new("Truncated TNEF data".to_string());
+        let u32_at = |pos: usize| -> ParsingResult<u32> {
+            let bytes = data.get(pos..pos + 4).ok_or_else(truncated)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+
+        if u32_at(0)? != TNEF_SIGNATURE {
+            return Err(ParsingError::new("Missing TNEF signature".to_string()));
+        }
+        // Skip the signature and legacy key
+        let mut pos = 6;
+        let mut files: Vec<TnefFile> = Vec::new();
+        // Each attribute is a level byte, an ID, a length, the data and a
+        // checksum
+        while pos < data.len() {
+            let id = u32_at(pos + 1)?;
+            let length = u32_at(pos + 5)? as usize;
+            let start = pos + 9;
+            let value = data
+                .get(start..start.saturating_add(length))
+                .ok_or_else(truncated)?;
+            pos = start + length + 2;
+
+            match id {
+                ATT_ATTACH_REND_DATA => files.push(TnefFile {
+                    filename: None,
+                    content: Vec::new(),
+                }),
+                ATT_ATTACH_TITLE => {
+                    if let Some(file) = files.last_mut() {
+                        let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+                        let title = String::from_utf8_lossy(&value[..end]).into_owned();
+                        file.filename = Some(title).filter(|title| !title.is_empty());
+                    }
+                }
+                ATT_ATTACH_DATA => {
+                    if let Some(file) = files.last_mut() {
+                        file.content = value.to_vec();
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::ParseOptions;
+    use crate::results::ParsingError;
+
+    const MESSAGE: &str = "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+                           --b\r\n\
+                           Content-Type: multipart/alternative; boundary=alt\r\n\r\n\
+                           --alt\r\n\
+                           Content-Type: text/plain\r\n\r\n\
+                           Text\r\n\
+                           --alt\r\n\
+                           Content-Type: text/html\r\n\r\n\
+                           <p>HTML</p>\r\n\
+                           --alt--\r\n\
+                           --b\r\n\
+                           Content-Type: text/plain; name=notes.txt\r\n\r\n\
+                           Notes\r\n\
+                           --b\r\n\
+                           Content-Type: application/ms-tnef; name=winmail.dat\r\n\
+                           Content-Transfer-Encoding: base64\r\n\r\n\
+                           eJ8+IgAAAgKQBgAAAAAAAAACEIABAAYAAABhLnBkZgDJAQIPgAYAAwAAAFBERtoAAgKQBgAAAAAA\r\n\
+                           AAACEIABAAYAAABiLnR4dADwAQIPgAYAAgAAAGhp0QA=\r\n\
+                           --b--\r\n";
+
+    struct FailingDecoder;
+
+    impl TnefDecoder for FailingDecoder {
+        fn decode(&self, _: &[u8]) -> ParsingResult<Vec<TnefFile>> {
+            Err(ParsingError::new("Not TNEF".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_attachments() {
+        let message = MimeMessage::parse(MESSAGE).unwrap();
+        let attachments = message.attachments();
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[0].path.to_string(), "2");
+        assert_eq!(attachments[0].filename, Some("notes.txt".to_string()));
+        assert_eq!(attachments[0].content, b"Notes\r\n");
+        assert_eq!(attachments[1].filename, Some("winmail.dat".to_string()));
+        assert_eq!(attachments[1].source, AttachmentSource::Part);
+        assert!(message.part(&attachments[1].path).unwrap().is_tnef());
+
+        assert_eq!(message.attachments_with_tnef(&FailingDecoder), attachments);
+    }
+
+    #[test]
+    fn test_uuencoded_attachments() {
+        let input = "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+                     --b\r\n\
+                     Content-Type: text/plain\r\n\r\n\
+                     See below.\r\n\
+                     begin 644 cat.txt\r\n\
+                     #0V%T\r\n\
+                     `\r\n\
+                     end\r\n\
+                     --b\r\n\
+                     Content-Type: text/plain; name=notes.txt\r\n\r\n\
+                     begin 600 dog.txt\r\n\
+                     #1&]G\r\n\
+                     end\r\n\
+                     --b--\r\n";
+        let message = MimeMessage::parse(input).unwrap();
+        assert_eq!(message.attachments().len(), 1);
+
+        let options = ParseOptions {
+            uuencode: true,
+            ..ParseOptions::default()
+        };
+        let message = MimeMessage::parse_with_options(input, &options).unwrap();
+        let attachments = message.attachments();
+        assert_eq!(attachments.len(), 3);
+        assert_eq!(attachments[0].source, AttachmentSource::Uuencoded);
+        assert_eq!(attachments[0].path.to_string(), "1");
+        assert_eq!(attachments[0].filename, Some("cat.txt".to_string()));
+        assert_eq!(attachments[0].content_type.to_string(), "text/plain");
+        assert_eq!(attachments[0].content, b"Cat");
+        assert_eq!(attachments[1].filename, Some("notes.txt".to_string()));
+        assert_eq!(attachments[1].source, AttachmentSource::Part);
+        assert_eq!(attachments[2].filename, Some("dog.txt".to_string()));
+        assert_eq!(attachments[2].content, b"Dog");
+        assert_eq!(attachments[2].path.to_string(), "2");
+    }
+
+    #[test]
+    fn test_yenc_attachments() {
+        let message = MimeMessage::parse(
+            "Content-Type: text/plain\r\n\r\n\
+             =ybegin line=128 size=7 name=hello.dat\r\n\
+             rovvy*=}\r\n\
+             =yend size=7 crc32=06a4a651\r\n\
+             begin 644 cat.txt\r\n\
+             #0V%T\r\n\
+             end\r\n\
+             =ybegin part=1 total=2 line=128 size=8 name=abc.txt\r\n\
+             =ypart begin=1 end=4\r\n\
+             klmn\r\n\
+             =yend size=4 part=1 pcrc32=db1720a5\r\n",
+        )
+        .unwrap();
+        let attachments = message.attachments();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].source, AttachmentSource::Yenc);
+        assert_eq!(attachments[0].filename, Some("hello.dat".to_string()));
+        assert_eq!(attachments[0].content, b"HELLO\x00\x13");
+    }
+
+    #[test]
+    fn test_mac_attachments() {
+        let message = MimeMessage::parse(
+            "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Files from my Mac\r\n\
+             --b\r\n\
+             Content-Type: multipart/appledouble; boundary=ad\r\n\r\n\
+             --ad\r\n\
+             Content-Type: application/applefile\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n\
+             AAUWBwACAAAAAAAAAAAAAAAAAAAAAAAAAAIAAAADAAAAMgAAAAcAAAACAAAAOQAAAARwaWMu\
+             Z2lmRm9yaw==\r\n\
+             --ad\r\n\
+             Content-Type: image/gif\r\n\r\n\
+             GIF89a\r\n\
+             --ad--\r\n\
+             --b\r\n\
+             Content-Type: application/mac-binhex40; name=hello.hqx\r\n\r\n\
+             (This file must be converted with BinHex 4.0)\r\n\r\n\
+             :#@KPE'a[,R4iG!\"849K8G(4iG!#3\"4F!N!-%S%G)C@aXEb`J6@&M)3eKN!U3!'UU\r\n\
+             8P053iqh:\r\n\
+             --b\r\n\
+             Content-Type: application/applefile\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n\
+             AAUWAAACAAAAAAAAAAAAAAAAAAAAAAAAAAMAAAADAAAAPgAAAAkAAAABAAAARwAAAAQAAAACAAAASwAAAA\
+             Rub3Rlcy50eHREYXRhUnNyYw==\r\n\
+             --b\r\n\
+             Content-Type: application/mac-binhex40; name=broken.hqx\r\n\r\n\
+             Not BinHex\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+        let attachments = message.attachments();
+        assert_eq!(attachments.len(), 4);
+
+        assert_eq!(attachments[0].source, AttachmentSource::AppleDouble);
+        assert_eq!(attachments[0].path.to_string(), "2");
+        assert_eq!(attachments[0].filename, Some("pic.gif".to_string()));
+        assert_eq!(attachments[0].content_type.to_string(), "image/gif");
+        assert_eq!(attachments[0].content, b"GIF89a\r\n");
+        assert_eq!(attachments[0].resource_fork, Some(b"Fork".to_vec()));
+
+        assert_eq!(attachments[1].source, AttachmentSource::BinHex);
+        assert_eq!(attachments[1].filename, Some("hello.txt".to_string()));
+        assert_eq!(attachments[1].content_type.to_string(), "text/plain");
+        assert_eq!(attachments[1].content, &b"Hello, Mac!\raaaaaaaaaa\x90"[..]);
+        assert_eq!(attachments[1].resource_fork, Some(b"RSRC".to_vec()));
+
+        assert_eq!(attachments[2].source, AttachmentSource::AppleSingle);
+        assert_eq!(attachments[2].filename, Some("notes.txt".to_string()));
+        assert_eq!(attachments[2].content, b"Data");
+
+        assert_eq!(attachments[3].source, AttachmentSource::Part);
+        assert_eq!(attachments[3].filename, Some("broken.hqx".to_string()));
+        assert_eq!(attachments[3].resource_fork, None);
+    }
+
+    #[cfg(feature = "tnef")]
+    #[test]
+    fn test_simple_tnef_decoder() {
+        use crate::message::Quirks;
+
+        let message = MimeMessage::parse(MESSAGE).unwrap();
+        let attachments = message.attachments_with_tnef(&SimpleTnefDecoder);
+        assert_eq!(attachments.len(), 3);
+        assert_eq!(attachments[1].source, AttachmentSource::Tnef);
+        assert_eq!(attachments[1].path.to_string(), "3");
+        assert_eq!(attachments[1].filename, Some("a.pdf".to_string()));
+        assert_eq!(attachments[1].content_type.to_string(), "application/pdf");
+        assert_eq!(attachments[1].content, b"PDF");
+        assert_eq!(attachments[2].filename, Some("b.txt".to_string()));
+        assert_eq!(attachments[2].content, b"hi");
+
+        let options = ParseOptions {
+            quirks: Quirks::none().with(Quirk::TnefAttachments),
+            ..ParseOptions::default()
+        };
+        let message = MimeMessage::parse_with_options(MESSAGE, &options).unwrap();
+        assert_eq!(message.attachments(), attachments);
+
+        assert!(SimpleTnefDecoder.decode(b"not tnef").is_err());
+        assert!(SimpleTnefDecoder
+            .decode(&[0x78, 0x9f, 0x3e, 0x22, 0, 0, 2])
+            .is_err());
+    }
+}
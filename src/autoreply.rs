@@ -0,0 +1,371 @@
+//! Module for the headers which mark mail as sent automatically, and for
+//! deciding whether an automatic reply, such as a vacation notice, should
+//! be sent, as described in RFC 3834.
+//!
+//! Replying automatically to mail that was itself sent automatically, or
+//! to a mailing list, risks a loop of replies between two responders, or
+//! replies to everyone on the list.
+use super::address::Address;
+use super::header::{FromHeader, ToHeader};
+use super::listheaders::ListHeaders;
+use super::message::MimeMessage;
+use super::results::{ParsingError, ParsingResult};
+
+pub const AUTO_SUBMITTED: &str = "Auto-Submitted";
+pub const PRECEDENCE: &str = "Precedence";
+pub const X_AUTO_RESPONSE_SUPPRESS: &str = "X-Auto-Response-Suppress";
+
+/// The `Auto-Submitted` header, RFC 3834 Section 5
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum AutoSubmitted {
+    /// The message was sent by a person
+    No,
+    /// The message was sent by an automatic process, other than in reply
+    /// to another message
+    AutoGenerated,
+    /// The message is an automatic reply to another message
+    AutoReplied,
+    /// The message is a Sieve notification, RFC 5436 Section 8
+    AutoNotified,
+    /// Any other value, lowercased, which is taken as automatic
+    Other(String),
+}
+
+impl AutoSubmitted {
+    /// Whether the message was sent automatically.
+    pub fn is_automatic(&self) -> bool {
+        *self != AutoSubmitted::No
+    }
+}
+
+impl FromHeader for AutoSubmitted {
+    fn from_header(value: String) -> ParsingResult<AutoSubmitted> {
+        // Any parameters are ignored
+        let keyword = value.split(';').next().unwrap_or("").trim();
+        if keyword.is_empty() {
+            return Err(ParsingError::new("Empty Auto-Submitted header".to_string()));
+        }
+        Ok(match &keyword.to_ascii_lowercase()[..] {
+            "no" => AutoSubmitted::No,
+            "auto-generated" => AutoSubmitted::AutoGenerated,
+            "auto-replied" => AutoSubmitted::AutoReplied,
+            "auto-notified" => AutoSubmitted::AutoNotified,
+            other => AutoSubmitted::Other(other.to_string()),
+        })
+    }
+}
+
+impl ToHeader for AutoSubmitted {
+    fn to_header(value: AutoSubmitted) -> ParsingResult<String> {
+        Ok(match value {
+            AutoSubmitted::No => "no".to_string(),
+            AutoSubmitted::AutoGenerated => "auto-generated".to_string(),
+            AutoSubmitted::AutoReplied => "auto-replied".to_string(),
+            AutoSubmitted::AutoNotified => "auto-notified".to_string(),
+            AutoSubmitted::Other(other) => other,
+        })
+    }
+}
+
+/// The non-standard `Precedence` header, as set by mailing lists and bulk
+/// senders.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Precedence {
+    Bulk,
+    List,
+    Junk,
+    /// Any other value, lowercased
+    Other(String),
+}
+
+impl FromHeader for Precedence {
+    fn from_header(value: String) -> ParsingResult<Precedence> {
+        let value = value.trim().to_ascii_lowercase();
+        Ok(match &value[..] {
+            "" => return Err(ParsingError::new("Empty Precedence header".to_string())),
+            "bulk" => Precedence::Bulk,
+            "list" => Precedence::List,
+            "junk" => Precedence::Junk,
+            _ => Precedence::Other(value),
+        })
+    }
+}
+
+impl ToHeader for Precedence {
+    fn to_header(value: Precedence) -> ParsingResult<String> {
+        Ok(match value {
+            Precedence::Bulk => "bulk".to_string(),
+            Precedence::List => "list".to_string(),
+            Precedence::Junk => "junk".to_string(),
+            Precedence::Other(other) => other,
+        })
+    }
+}
+
+/// A kind of automatic response which Exchange may be asked not to send.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SuppressedResponse {
+    /// Every kind of automatic response
+    All,
+    DeliveryReceipt,
+    NonDeliveryReceipt,
+    ReadNotification,
+    NonReadNotification,
+    /// Out of office notices
+    OutOfOffice,
+    /// Other automatic replies
+    AutoReply,
+    /// Any other value, as given
+    Other(String),
+}
+
+impl SuppressedResponse {
+    fn as_str(&self) -> &str {
+        match self {
+            SuppressedResponse::All => "All",
+            SuppressedResponse::DeliveryReceipt => "DR",
+            SuppressedResponse::NonDeliveryReceipt => "NDR",
+            SuppressedResponse::ReadNotification => "RN",
+            SuppressedResponse::NonReadNotification => "NRN",
+            SuppressedResponse::OutOfOffice => "OOF",
+            SuppressedResponse::AutoReply => "AutoReply",
+            SuppressedResponse::Other(other) => &other[..],
+        }
+    }
+}
+
+/// The `X-Auto-Response-Suppress` header of Microsoft Exchange, MS-OXCMAIL
+/// Section 2.1.3.2.20
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct AutoResponseSuppress {
+    /// The responses not to send, which is empty for `None`
+    pub responses: Vec<SuppressedResponse>,
+}
+
+impl AutoResponseSuppress {
+    /// Whether out of office notices and other automatic replies are to
+    /// be suppressed.
+    pub fn suppresses_auto_reply(&self) -> bool {
+        self.responses.iter().any(|response| {
+            matches!(
+                response,
+                SuppressedResponse::All
+                    | SuppressedResponse::OutOfOffice
+                    | SuppressedResponse::AutoReply
+            )
+        })
+    }
+}
+
+impl FromHeader for AutoResponseSuppress {
+    fn from_header(value: String) -> ParsingResult<AutoResponseSuppress> {
+        let mut responses = Vec::new();
+        for keyword in value.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+            let response = match &keyword.to_ascii_lowercase()[..] {
+                "none" => continue,
+                "all" => SuppressedResponse::All,
+                "dr" => SuppressedResponse::DeliveryReceipt,
+                "ndr" => SuppressedResponse::NonDeliveryReceipt,
+                "rn" => SuppressedResponse::ReadNotification,
+                "nrn" => SuppressedResponse::NonReadNotification,
+                "oof" => SuppressedResponse::OutOfOffice,
+                "autoreply" => SuppressedResponse::AutoReply,
+                _ => SuppressedResponse::Other(keyword.to_string()),
+            };
+            responses.push(response);
+        }
+        Ok(AutoResponseSuppress { responses })
+    }
+}
+
+impl ToHeader for AutoResponseSuppress {
+    fn to_header(value: AutoResponseSuppress) -> ParsingResult<String> {
+        if value.responses.is_empty() {
+            return Ok("None".to_string());
+        }
+        let keywords: Vec<&str> = value.responses.iter().map(|r| r.as_str()).collect();
+        Ok(keywords.join(", "))
+    }
+}
+
+// Whether `address` belongs to mailing list software or a mail server,
+// which RFC 3834 Section 2 says not to reply to.
+fn is_system_address(address: &str) -> bool {
+    let local = address[..address.rfind('@').unwrap_or(address.len())].to_ascii_lowercase();
+    local == "mailer-daemon"
+        || local == "postmaster"
+        || local == "listserv"
+        || local == "majordomo"
+        || local.starts_with("owner-")
+        || local.ends_with("-request")
+        || local.ends_with("-owner")
+}
+
+impl MimeMessage {
+    /// Whether an automatic reply to this message should be left unsent,
+    /// following RFC 3834 and common practice.
+    ///
+    /// That's the case if the message:
+    ///
+    /// - was sent automatically, as marked by `Auto-Submitted`, or the
+    ///   `X-Autoreply` or `X-Autorespond` headers of some responders;
+    /// - asks for no automatic replies with `X-Auto-Response-Suppress`;
+    /// - has a `Precedence` of `bulk`, `list` or `junk`;
+    /// - came from a mailing list, having a `List-Id` or another list
+    ///   header;
+    /// - has a null `Return-Path`, as bounces do;
+    /// - or came from an address such as `MAILER-DAEMON` or
+    ///   `owner-list`.
+    pub fn should_suppress_auto_reply(&self) -> bool {
+        let headers = &self.headers;
+        let auto_submitted: Option<AutoSubmitted> =
+            headers.get_value(AUTO_SUBMITTED.to_string()).ok();
+        if auto_submitted.is_some_and(|value| value.is_automatic())
+            || headers.get("X-Autoreply".to_string()).is_some()
+            || headers.get("X-Autorespond".to_string()).is_some()
+        {
+            return true;
+        }
+
+        let suppress: Option<AutoResponseSuppress> =
+            headers.get_value(X_AUTO_RESPONSE_SUPPRESS.to_string()).ok();
+        let precedence: Option<Precedence> = headers.get_value(PRECEDENCE.to_string()).ok();
+        if suppress.is_some_and(|suppress| suppress.suppresses_auto_reply())
+            || precedence.is_some_and(|precedence| {
+                matches!(
+                    precedence,
+                    Precedence::Bulk | Precedence::List | Precedence::Junk
+                )
+            })
+            || ListHeaders::from_headers(headers).is_list_message()
+        {
+            return true;
+        }
+
+        let return_path = headers.get("Return-Path".to_string());
+        if return_path.is_some_and(|header| header.raw_value().trim() == "<>") {
+            return true;
+        }
+        let from: Vec<Address> = headers.get_value("From".to_string()).unwrap_or_default();
+        let sender: Vec<Address> = headers.get_value("Sender".to_string()).unwrap_or_default();
+        from.iter()
+            .chain(sender.iter())
+            .any(|address| match address {
+                Address::Mailbox(mailbox) => is_system_address(&mailbox.address),
+                Address::Group(..) => false,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::Header;
+
+    #[test]
+    fn test_auto_submitted() {
+        let tests = [
+            ("no", AutoSubmitted::No),
+            ("Auto-Generated", AutoSubmitted::AutoGenerated),
+            (
+                " auto-replied; owner-email=\"a@example.org\"",
+                AutoSubmitted::AutoReplied,
+            ),
+            ("auto-notified", AutoSubmitted::AutoNotified),
+            (
+                "auto-custom",
+                AutoSubmitted::Other("auto-custom".to_string()),
+            ),
+        ];
+        for (value, expected) in tests.iter() {
+            let parsed = AutoSubmitted::from_header(value.to_string()).unwrap();
+            assert_eq!(&parsed, expected);
+            assert_eq!(parsed.is_automatic(), *expected != AutoSubmitted::No);
+        }
+        assert!(AutoSubmitted::from_header(" ".to_string()).is_err());
+        assert_eq!(
+            AutoSubmitted::to_header(AutoSubmitted::AutoReplied).unwrap(),
+            "auto-replied"
+        );
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(
+            Precedence::from_header(" BULK ".to_string()).unwrap(),
+            Precedence::Bulk
+        );
+        assert_eq!(
+            Precedence::from_header("first-class".to_string()).unwrap(),
+            Precedence::Other("first-class".to_string())
+        );
+        assert_eq!(Precedence::to_header(Precedence::List).unwrap(), "list");
+    }
+
+    #[test]
+    fn test_auto_response_suppress() {
+        let suppress = AutoResponseSuppress::from_header("DR, RN, NRN".to_string()).unwrap();
+        assert_eq!(suppress.responses.len(), 3);
+        assert!(!suppress.suppresses_auto_reply());
+
+        let suppress = AutoResponseSuppress::from_header("OOF,AutoReply".to_string()).unwrap();
+        assert_eq!(
+            suppress.responses,
+            vec![
+                SuppressedResponse::OutOfOffice,
+                SuppressedResponse::AutoReply
+            ]
+        );
+        assert!(suppress.suppresses_auto_reply());
+        assert_eq!(
+            AutoResponseSuppress::to_header(suppress).unwrap(),
+            "OOF, AutoReply"
+        );
+
+        let none = AutoResponseSuppress::from_header("None".to_string()).unwrap();
+        assert!(none.responses.is_empty());
+        assert_eq!(AutoResponseSuppress::to_header(none).unwrap(), "None");
+    }
+
+    #[test]
+    fn test_should_suppress_auto_reply() {
+        let message = |headers: &[(&str, &str)]| {
+            let mut message = MimeMessage::parse("From: joe@example.org\r\n\r\nHi").unwrap();
+            for &(name, value) in headers {
+                message
+                    .headers
+                    .insert(Header::new(name.to_string(), value.to_string()));
+            }
+            message
+        };
+
+        assert!(!message(&[]).should_suppress_auto_reply());
+        assert!(!message(&[("Auto-Submitted", "no")]).should_suppress_auto_reply());
+        assert!(!message(&[("Precedence", "first-class")]).should_suppress_auto_reply());
+        assert!(!message(&[("X-Auto-Response-Suppress", "DR")]).should_suppress_auto_reply());
+        assert!(!message(&[("Return-Path", "<joe@example.org>")]).should_suppress_auto_reply());
+
+        let suppressed = [
+            ("Auto-Submitted", "auto-generated"),
+            ("X-Autoreply", "yes"),
+            ("X-Auto-Response-Suppress", "All"),
+            ("Precedence", "bulk"),
+            ("List-Id", "<list.example.org>"),
+            ("List-Unsubscribe", "<mailto:leave@example.org>"),
+            ("Return-Path", "<>"),
+            ("Sender", "owner-list@example.org"),
+        ];
+        for &(name, value) in suppressed.iter() {
+            assert!(
+                message(&[(name, value)]).should_suppress_auto_reply(),
+                "{}",
+                name
+            );
+        }
+
+        let bounce = MimeMessage::parse("From: MAILER-DAEMON@example.org\r\n\r\nFailed").unwrap();
+        assert!(bounce.should_suppress_auto_reply());
+        let request = MimeMessage::parse("From: list-request@example.org\r\n\r\nHi").unwrap();
+        assert!(request.should_suppress_auto_reply());
+    }
+}
@@ -0,0 +1,230 @@
+//! Module for the headers of Brand Indicators for Message Identification,
+//! by which a mailbox can show the logo of the brand a message is from, as
+//! described by draft-brand-indicators-for-message-identification.
+//!
+//! The sender asks for a logo with `BIMI-Selector`. Once the receiving
+//! server has checked the message passes DMARC and found the logo, it
+//! adds `BIMI-Location`, giving where the logo and the evidence for it
+//! are, and `BIMI-Indicator`, holding the logo itself.
+use super::arc::{strip_whitespace, TagList};
+use super::header::{FromHeader, Header, HeaderMap, ToHeader};
+use super::results::{ParsingError, ParsingResult};
+
+pub const BIMI_SELECTOR: &str = "BIMI-Selector";
+pub const BIMI_LOCATION: &str = "BIMI-Location";
+pub const BIMI_INDICATOR: &str = "BIMI-Indicator";
+
+/// The only version of BIMI there is
+pub const BIMI_VERSION: &str = "BIMI1";
+
+fn check_version(tags: &TagList) -> ParsingResult<()> {
+    let version = tags.require("v")?;
+    if version.eq_ignore_ascii_case(BIMI_VERSION) {
+        Ok(())
+    } else {
+        Err(ParsingError::new(format!(
+            "Unknown BIMI version: {}",
+            version
+        )))
+    }
+}
+
+/// The `BIMI-Selector` header, which names the BIMI record of the author
+/// domain to look up.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BimiSelector {
+    /// The selector, `s=`, which is `default` unless the domain has
+    /// several records
+    pub selector: String,
+}
+
+impl FromHeader for BimiSelector {
+    fn from_header(value: String) -> ParsingResult<BimiSelector> {
+        let tags = TagList::parse(&value[..])?;
+        check_version(&tags)?;
+        Ok(BimiSelector {
+            selector: tags.require("s")?.to_string(),
+        })
+    }
+}
+
+impl ToHeader for BimiSelector {
+    fn to_header(value: BimiSelector) -> ParsingResult<String> {
+        Ok(format!("v={}; s={}", BIMI_VERSION, value.selector))
+    }
+}
+
+/// The `BIMI-Location` header, which gives where the logo of the author
+/// domain and the evidence for its right to use it were found.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BimiLocation {
+    /// The HTTPS URI of the logo, an SVG, `l=`
+    pub location: Option<String>,
+    /// The HTTPS URI of the mark certificate for the logo, `a=`
+    pub evidence: Option<String>,
+}
+
+impl FromHeader for BimiLocation {
+    /// Empty URIs are taken as missing.
+    fn from_header(value: String) -> ParsingResult<BimiLocation> {
+        let tags = TagList::parse(&value[..])?;
+        check_version(&tags)?;
+        let uri = |name| {
+            let uri = strip_whitespace(tags.get(name).unwrap_or(""));
+            Some(uri).filter(|uri| !uri.is_empty())
+        };
+        Ok(BimiLocation {
+            location: uri("l"),
+            evidence: uri("a"),
+        })
+    }
+}
+
+impl ToHeader for BimiLocation {
+    fn to_header(value: BimiLocation) -> ParsingResult<String> {
+        let mut result = format!("v={}", BIMI_VERSION);
+        if let Some(location) = value.location {
+            result = format!("{}; l={}", result, location);
+        }
+        if let Some(evidence) = value.evidence {
+            result = format!("{}; a={}", result, evidence);
+        }
+        Ok(result)
+    }
+}
+
+/// The `BIMI-Indicator` header, which holds the logo to show.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct BimiIndicator {
+    /// The SVG document of the logo, decoded from base64
+    pub svg: Vec<u8>,
+}
+
+impl FromHeader for BimiIndicator {
+    fn from_header(value: String) -> ParsingResult<BimiIndicator> {
+        match base64::decode(strip_whitespace(&value[..])) {
+            Ok(svg) if !svg.is_empty() => Ok(BimiIndicator { svg }),
+            _ => Err(ParsingError::new("Invalid BIMI-Indicator.".to_string())),
+        }
+    }
+}
+
+impl ToHeader for BimiIndicator {
+    fn to_header(value: BimiIndicator) -> ParsingResult<String> {
+        Ok(base64::encode(&value.svg[..]))
+    }
+}
+
+/// The BIMI headers of a message.
+///
+/// Headers which are missing or fail to parse are `None`.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct BimiHeaders {
+    pub selector: Option<BimiSelector>,
+    pub location: Option<BimiLocation>,
+    pub indicator: Option<BimiIndicator>,
+}
+
+impl BimiHeaders {
+    /// Pull the BIMI headers out of `headers`, taking the last of each.
+    ///
+    /// `BIMI-Location` and `BIMI-Indicator` can only be trusted when the
+    /// receiving server added them, having removed any the sender put in.
+    /// Where that isn't known, use `from_headers_above` instead, so that a
+    /// sender can't show a logo of their choosing.
+    pub fn from_headers(headers: &HeaderMap) -> BimiHeaders {
+        BimiHeaders {
+            selector: headers.get_value(BIMI_SELECTOR.to_string()).ok(),
+            location: headers.get_value(BIMI_LOCATION.to_string()).ok(),
+            indicator: headers.get_value(BIMI_INDICATOR.to_string()).ok(),
+        }
+    }
+
+    /// Pull the BIMI headers out of the first `boundary` headers of
+    /// `headers`, those which the caller's own servers added on top of the
+    /// message, taking the topmost of each.
+    ///
+    /// `BIMI-Selector`, which comes from the sender, is still taken from
+    /// anywhere in `headers`.
+    pub fn from_headers_above(headers: &HeaderMap, boundary: usize) -> BimiHeaders {
+        let trusted = |name: &str| {
+            let mut above = headers.iter().take(boundary);
+            above.find(|header: &&Header| header.name == name)
+        };
+        BimiHeaders {
+            selector: headers.get_value(BIMI_SELECTOR.to_string()).ok(),
+            location: trusted(BIMI_LOCATION).and_then(|h| h.get_value().ok()),
+            indicator: trusted(BIMI_INDICATOR).and_then(|h| h.get_value().ok()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MimeMessage;
+
+    #[test]
+    fn test_bimi_selector() {
+        let selector = BimiSelector::from_header("v=BIMI1; s=brand;".to_string()).unwrap();
+        assert_eq!(selector.selector, "brand");
+        assert_eq!(
+            BimiSelector::to_header(selector).unwrap(),
+            "v=BIMI1; s=brand"
+        );
+        assert!(BimiSelector::from_header("v=BIMI2; s=brand".to_string()).is_err());
+        assert!(BimiSelector::from_header("v=BIMI1".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_bimi_headers() {
+        let message = MimeMessage::parse(
+            "BIMI-Location: v=BIMI1;\r\n\
+             \tl=https://example.org/logo.svg;\r\n\
+             \ta=https://example.org/vmc.pem\r\n\
+             BIMI-Indicator: PHN2Zz48\r\n\
+             \tL3N2Zz4=\r\n\r\n",
+        )
+        .unwrap();
+        let bimi = BimiHeaders::from_headers(&message.headers);
+        assert_eq!(bimi.selector, None);
+        let location = bimi.location.unwrap();
+        assert_eq!(
+            location.location.as_deref(),
+            Some("https://example.org/logo.svg")
+        );
+        assert_eq!(
+            location.evidence.as_deref(),
+            Some("https://example.org/vmc.pem")
+        );
+        assert_eq!(bimi.indicator.unwrap().svg, b"<svg></svg>");
+
+        let location = BimiLocation::from_header("v=BIMI1; l=".to_string()).unwrap();
+        assert_eq!(location.location, None);
+        assert!(BimiIndicator::from_header("not base64!".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_bimi_headers_above() {
+        let message = MimeMessage::parse(
+            "BIMI-Location: v=BIMI1; l=https://example.org/logo.svg\r\n\
+             Received: from mx.example.com\r\n\
+             BIMI-Selector: v=BIMI1; s=brand\r\n\
+             BIMI-Location: v=BIMI1; l=https://example.net/fake.svg\r\n\
+             BIMI-Indicator: PHN2Zz48L3N2Zz4=\r\n\r\n",
+        )
+        .unwrap();
+        let bimi = BimiHeaders::from_headers(&message.headers);
+        let location = bimi.location.unwrap().location;
+        assert_eq!(location.as_deref(), Some("https://example.net/fake.svg"));
+
+        let bimi = BimiHeaders::from_headers_above(&message.headers, 1);
+        assert_eq!(bimi.selector.unwrap().selector, "brand");
+        let location = bimi.location.unwrap().location;
+        assert_eq!(location.as_deref(), Some("https://example.org/logo.svg"));
+        assert_eq!(bimi.indicator, None);
+
+        let bimi = BimiHeaders::from_headers_above(&message.headers, 0);
+        assert_eq!(bimi.location, None);
+    }
+}
@@ -0,0 +1,270 @@
+//! Module for finding the quoted replies and signature in a plain text body.
+//!
+//! Replies usually quote the message they answer, either with `>` at the
+//! start of each line or below an "Original Message" separator, and end with
+//! a signature. `split_body` divides a body into `Block`s of these, so that
+//! a client can hide the quotes, show just the new text in a preview or
+//! requote the body in a reply.
+use std::ops::Range;
+
+/// What a `Block` of a body is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockKind {
+    /// Text written by the sender
+    Text,
+    /// A line introducing a quote, as in "On Monday, Joe wrote:"
+    Attribution,
+    /// Quoted text, `level` quotes deep
+    Quote { level: usize },
+    /// The sender's signature
+    Signature,
+}
+
+/// A run of whole lines of a body which are all of one kind.
+///
+/// Any blank lines belong to the block before them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub kind: BlockKind,
+    /// The span of the body covered, including the last line break
+    pub span: Range<usize>,
+}
+
+impl Block {
+    /// The lines of `body` covered by this block.
+    pub fn text<'a>(&self, body: &'a str) -> &'a str {
+        &body[self.span.clone()]
+    }
+
+    /// The lines of `body` covered by this block, with their `>` quotes
+    /// removed if this is a `Quote`.
+    pub fn unquoted(&self, body: &str) -> String {
+        let level = match self.kind {
+            BlockKind::Quote { level } => level,
+            _ => return self.text(body).to_string(),
+        };
+        self.text(body)
+            .split_inclusive('\n')
+            .map(|line| {
+                // Keep any quotes deeper than this block's
+                let mut rest = line;
+                for _ in 0..level {
+                    match rest.strip_prefix('>') {
+                        Some(unquoted) => rest = unquoted.strip_prefix(' ').unwrap_or(unquoted),
+                        None => break,
+                    }
+                }
+                rest
+            })
+            .collect()
+    }
+}
+
+/// Split a plain text `body` into blocks of text, quotes, attributions and
+/// signature, in order.
+///
+/// A signature starts at the `-- ` line described in RFC 3676, or at a line
+/// added by a mail client such as "Sent from my iPhone", and runs until the
+/// next quote. Everything after an "Original Message" separator, as sent by
+/// Outlook, is quoted.
+pub fn split_body(body: &str) -> Vec<Block> {
+    let lines: Vec<&str> = body.split_inclusive('\n').collect();
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut in_signature = false;
+    let mut in_original = false;
+    let mut start = 0;
+
+    for (i, line) in lines.iter().enumerate() {
+        let content = line.trim_end_matches(['\r', '\n']);
+        let (depth, _) = quote_depth(content);
+        let kind = if content.trim().is_empty() {
+            None
+        } else if in_original {
+            Some(BlockKind::Quote { level: depth + 1 })
+        } else if is_original_separator(&lines[i..]) {
+            in_original = true;
+            Some(BlockKind::Quote { level: 1 })
+        } else if depth > 0 {
+            in_signature = false;
+            Some(BlockKind::Quote { level: depth })
+        } else if is_attribution(content, &lines[i + 1..]) {
+            in_signature = false;
+            Some(BlockKind::Attribution)
+        } else if in_signature || is_signature_start(content) {
+            in_signature = true;
+            Some(BlockKind::Signature)
+        } else {
+            Some(BlockKind::Text)
+        };
+
+        let end = start + line.len();
+        match (kind, blocks.last_mut()) {
+            (Some(kind), Some(last)) if last.kind == kind => last.span.end = end,
+            (None, Some(last)) => last.span.end = end,
+            (kind, _) => blocks.push(Block {
+                kind: kind.unwrap_or(BlockKind::Text),
+                span: start..end,
+            }),
+        }
+        start = end;
+    }
+
+    // Leading blank lines belong to the first block
+    if blocks.len() > 1 && body[blocks[0].span.clone()].trim().is_empty() {
+        let blank = blocks.remove(0);
+        blocks[0].span.start = blank.span.start;
+    }
+    blocks
+}
+
+/// The text written by the sender of `body`, without any quotes,
+/// attributions or signature.
+pub fn visible_text(body: &str) -> String {
+    split_body(body)
+        .iter()
+        .filter(|block| block.kind == BlockKind::Text)
+        .map(|block| block.text(body))
+        .collect()
+}
+
+// The number of `>` quoting `line`, and the rest of it.
+pub(crate) fn quote_depth(line: &str) -> (usize, &str) {
+    let mut depth = 0;
+    let mut rest = line;
+    // Both "> > " and ">> " quote twice
+    while let Some(unquoted) = rest.strip_prefix('>') {
+        depth += 1;
+        rest = unquoted.strip_prefix(' ').unwrap_or(unquoted);
+    }
+    (depth, rest)
+}
+
+// Lines added to the end of messages by mail clients
+const CLIENT_SIGNATURES: &[&str] = &[
+    "Get Outlook for ",
+    "Sent from Mail for Windows",
+    "Sent from my ",
+    "Sent from Yahoo Mail",
+];
+
+fn is_signature_start(line: &str) -> bool {
+    line.trim_end() == "--"
+        || line.len() < 60 && CLIENT_SIGNATURES.iter().any(|s| line.starts_with(s))
+}
+
+// Whether `line` ends with a colon and the next line which isn't blank is
+// quoted.
+fn is_attribution(line: &str, rest: &[&str]) -> bool {
+    line.trim_end().ends_with(':')
+        && rest
+            .iter()
+            .map(|line| line.trim())
+            .find(|line| !line.is_empty())
+            .is_some_and(|line| line.starts_with('>'))
+}
+
+// Whether `lines` start with the separator Outlook puts above the message
+// being replied to: either "-----Original Message-----" or a line of
+// underscores followed by the original's From header.
+fn is_original_separator(lines: &[&str]) -> bool {
+    let line = lines[0].trim();
+    if line.starts_with('-') {
+        let words = line.trim_matches('-').trim();
+        return words.eq_ignore_ascii_case("original message");
+    }
+    line.len() >= 20
+        && line.bytes().all(|b| b == b'_')
+        && lines
+            .get(1)
+            .is_some_and(|next| next.trim_start().starts_with("From:"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(body: &str) -> Vec<(BlockKind, &str)> {
+        split_body(body)
+            .into_iter()
+            .map(|block| (block.kind, block.text(body)))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_body() {
+        let body = "\r\n\
+                    Sounds good.\r\n\
+                    \r\n\
+                    On Monday, Joe wrote:\r\n\
+                    > Lunch?\r\n\
+                    >> Is noon ok?\r\n\
+                    > > Or one?\r\n\
+                    \r\n\
+                    Anna\r\n\
+                    -- \r\n\
+                    Anna Example\r\n\
+                    > Not a signature\r\n";
+        assert_eq!(
+            kinds(body),
+            vec![
+                (BlockKind::Text, "\r\nSounds good.\r\n\r\n"),
+                (BlockKind::Attribution, "On Monday, Joe wrote:\r\n"),
+                (BlockKind::Quote { level: 1 }, "> Lunch?\r\n"),
+                (
+                    BlockKind::Quote { level: 2 },
+                    ">> Is noon ok?\r\n> > Or one?\r\n\r\n"
+                ),
+                (BlockKind::Text, "Anna\r\n"),
+                (BlockKind::Signature, "-- \r\nAnna Example\r\n"),
+                (BlockKind::Quote { level: 1 }, "> Not a signature\r\n"),
+            ]
+        );
+        assert_eq!(visible_text(body), "\r\nSounds good.\r\n\r\nAnna\r\n");
+
+        let blocks = split_body(body);
+        assert_eq!(blocks[3].unquoted(body), "Is noon ok?\r\nOr one?\r\n\r\n");
+        assert_eq!(blocks[2].unquoted(body), "Lunch?\r\n");
+        let nested = Block {
+            kind: BlockKind::Quote { level: 1 },
+            span: blocks[2].span.start..blocks[3].span.end,
+        };
+        assert_eq!(
+            nested.unquoted(body),
+            "Lunch?\r\n> Is noon ok?\r\n> Or one?\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_split_body_client_patterns() {
+        let body = "Yes\n\nSent from my iPhone\n\n\
+                    -----Original Message-----\n\
+                    From: Joe\n\
+                    > Earlier\n";
+        assert_eq!(
+            kinds(body),
+            vec![
+                (BlockKind::Text, "Yes\n\n"),
+                (BlockKind::Signature, "Sent from my iPhone\n\n"),
+                (
+                    BlockKind::Quote { level: 1 },
+                    "-----Original Message-----\nFrom: Joe\n"
+                ),
+                (BlockKind::Quote { level: 2 }, "> Earlier\n"),
+            ]
+        );
+
+        let body = "Fine\n________________________________\nFrom: Joe\nSent: Monday";
+        assert_eq!(
+            kinds(body),
+            vec![
+                (BlockKind::Text, "Fine\n"),
+                (
+                    BlockKind::Quote { level: 1 },
+                    "________________________________\nFrom: Joe\nSent: Monday"
+                ),
+            ]
+        );
+        assert_eq!(kinds(""), vec![]);
+        assert_eq!(kinds("\n\n"), vec![(BlockKind::Text, "\n\n")]);
+    }
+}
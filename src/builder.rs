@@ -0,0 +1,1221 @@
+//! Module for composing new messages, including replies and forwards.
+use chrono::{DateTime, FixedOffset};
+
+use super::address::{Address, Mailbox};
+use super::calendar::CalendarMethod;
+use super::convert::html_to_text;
+use super::environment;
+use super::flowed::FlowedText;
+use super::header::{check_header_value, Header, HeaderMap, HeaderValueError};
+use super::listheaders::{ListPost, LIST_POST};
+use super::message::{MimeMessage, MimeMultipartType};
+use super::messageid::MessageId;
+use super::mimeheaders::{
+    guess_mime_type, ContentDisposition, ContentType, MimeContentTransferEncoding,
+};
+use super::priority::MessagePriority;
+use super::receipt::DISPOSITION_NOTIFICATION_TO;
+use super::results::{ParsingError, ParsingResult};
+use super::subject;
+
+/// Encode `value` as RFC 2047 encoded-words if it isn't plain ASCII.
+pub(crate) fn encode_header_text(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        encoded_words::encode(value, None, encoded_words::EncodingFlag::Shortest, None)
+    }
+}
+
+// Check that the display name and address of `mailbox` can't be used to
+// add other headers to the header `name`.
+fn check_mailbox(name: &str, mailbox: &Mailbox) -> Result<(), HeaderValueError> {
+    if let Some(ref display_name) = mailbox.name {
+        check_header_value(name, display_name)?;
+    }
+    check_header_value(name, &mailbox.address)
+}
+
+// As `check_mailbox`, for each of `addresses` and the names of groups.
+fn check_addresses(name: &str, addresses: &[Address]) -> Result<(), HeaderValueError> {
+    for address in addresses {
+        match *address {
+            Address::Mailbox(ref mailbox) => check_mailbox(name, mailbox)?,
+            Address::Group(ref group, ref mailboxes) => {
+                check_header_value(name, group)?;
+                for mailbox in mailboxes {
+                    check_mailbox(name, mailbox)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Make a single part text message with the given content subtype and any
+/// further `parameters`, using `encoding` or else the transfer encoding best
+/// suited to the text.
+fn text_part(
+    subtype: &str,
+    parameters: &str,
+    text: &str,
+    encoding: Option<MimeContentTransferEncoding>,
+) -> MimeMessage {
+    let mut part = MimeMessage::new_blank_message();
+    let separator = if parameters.is_empty() { "" } else { "; " };
+    part.headers.insert(Header::new(
+        "Content-Type".to_string(),
+        format!("text/{}; charset=utf-8{}{}", subtype, separator, parameters),
+    ));
+    let encoding =
+        encoding.unwrap_or_else(|| MimeContentTransferEncoding::choose(text.as_bytes()));
+    part.set_body_bytes_with_encoding(text.as_bytes(), encoding);
+    part
+}
+
+/// Builder for composing a new message.
+///
+/// Each setter consumes and returns the builder, so calls can be chained,
+/// with `build` producing the final `MimeMessage`.
+#[derive(Debug, Clone, Default)]
+pub struct MessageBuilder {
+    from: Vec<Address>,
+    sender: Option<Mailbox>,
+    reply_to: Vec<Address>,
+    to: Vec<Address>,
+    cc: Vec<Address>,
+    bcc: Vec<Address>,
+    receipt_to: Vec<Address>,
+    subject: Option<String>,
+    date: Option<DateTime<FixedOffset>>,
+    message_id: Option<MessageId>,
+    in_reply_to: Vec<MessageId>,
+    references: Vec<MessageId>,
+    priority: Option<MessagePriority>,
+    headers: Vec<Header>,
+    text: Option<String>,
+    html: Option<String>,
+    body: Option<MimeMessage>,
+    attachments: Vec<MimeMessage>,
+    boundary_prefix: String,
+    transfer_encoding: Option<MimeContentTransferEncoding>,
+    ascii_domains: bool,
+    flowed: bool,
+    calendar: Option<(CalendarMethod, String)>,
+}
+
+impl MessageBuilder {
+    pub fn new() -> MessageBuilder {
+        MessageBuilder::default()
+    }
+
+    /// Add an author of the message.
+    pub fn from<M: Into<Mailbox>>(mut self, mailbox: M) -> MessageBuilder {
+        self.from.push(Address::Mailbox(mailbox.into()));
+        self
+    }
+
+    /// Set the mailbox responsible for sending the message, if it differs from the author.
+    pub fn sender<M: Into<Mailbox>>(mut self, mailbox: M) -> MessageBuilder {
+        self.sender = Some(mailbox.into());
+        self
+    }
+
+    /// Add an address replies should be directed to.
+    pub fn reply_to<M: Into<Mailbox>>(mut self, mailbox: M) -> MessageBuilder {
+        self.reply_to.push(Address::Mailbox(mailbox.into()));
+        self
+    }
+
+    /// Add a primary recipient.
+    pub fn to<M: Into<Mailbox>>(mut self, mailbox: M) -> MessageBuilder {
+        self.to.push(Address::Mailbox(mailbox.into()));
+        self
+    }
+
+    /// Add a carbon-copy recipient.
+    pub fn cc<M: Into<Mailbox>>(mut self, mailbox: M) -> MessageBuilder {
+        self.cc.push(Address::Mailbox(mailbox.into()));
+        self
+    }
+
+    /// Add a blind carbon-copy recipient.
+    ///
+    /// These are included in `recipients` but never written to the message.
+    pub fn bcc<M: Into<Mailbox>>(mut self, mailbox: M) -> MessageBuilder {
+        self.bcc.push(Address::Mailbox(mailbox.into()));
+        self
+    }
+
+    /// Ask for a read receipt to be sent to `mailbox`, with the
+    /// `Disposition-Notification-To` header of RFC 8098.
+    pub fn request_receipt<M: Into<Mailbox>>(mut self, mailbox: M) -> MessageBuilder {
+        self.receipt_to.push(Address::Mailbox(mailbox.into()));
+        self
+    }
+
+    pub fn subject<S: Into<String>>(mut self, subject: S) -> MessageBuilder {
+        self.subject = Some(subject.into());
+        self
+    }
+
+    /// Set the origination date of the message, which defaults to now.
+    pub fn date(mut self, date: DateTime<FixedOffset>) -> MessageBuilder {
+        self.date = Some(date);
+        self
+    }
+
+    /// Set the `Message-ID`, which is otherwise generated from the author's domain.
+    pub fn message_id(mut self, id: MessageId) -> MessageBuilder {
+        self.message_id = Some(id);
+        self
+    }
+
+    pub fn in_reply_to(mut self, id: MessageId) -> MessageBuilder {
+        self.in_reply_to.push(id);
+        self
+    }
+
+    pub fn references(mut self, ids: Vec<MessageId>) -> MessageBuilder {
+        self.references = ids;
+        self
+    }
+
+    /// Set how urgent the message is, with each of the headers which mail
+    /// programs read it from, as given by `MessagePriority::headers`.
+    pub fn priority(mut self, priority: MessagePriority) -> MessageBuilder {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Add an arbitrary header to the message.
+    pub fn header<H: Into<Header>>(mut self, header: H) -> MessageBuilder {
+        self.headers.push(header.into());
+        self
+    }
+
+    /// Set the plain text body.
+    pub fn text_body<S: Into<String>>(mut self, text: S) -> MessageBuilder {
+        self.text = Some(text.into());
+        self
+    }
+
+    /// Set the HTML body.
+    ///
+    /// The HTML is sent as a multipart/alternative with the text body, or if
+    /// there's no text body, with plain text made from the HTML by
+    /// `convert::html_to_text`.
+    pub fn html_body<S: Into<String>>(mut self, html: S) -> MessageBuilder {
+        self.html = Some(html.into());
+        self
+    }
+
+    /// Set the body to a part which has already been built, in place of any
+    /// text or HTML body.
+    pub fn body(mut self, part: MimeMessage) -> MessageBuilder {
+        self.body = Some(part);
+        self
+    }
+
+    /// Attach a part, which is added after the body in a multipart/mixed.
+    pub fn attach(mut self, part: MimeMessage) -> MessageBuilder {
+        self.attachments.push(part);
+        self
+    }
+
+    /// Attach a file named `filename` with the given content, using
+    /// `content_type` or else a type guessed from the filename.
+    pub fn attach_file(
+        self,
+        filename: &str,
+        content: &[u8],
+        content_type: Option<ContentType>,
+    ) -> MessageBuilder {
+        let mut content_type = content_type.unwrap_or_else(|| guess_mime_type(filename));
+        content_type.set_parameter("name", filename);
+        let mut part = MimeMessage::new_blank_message();
+        part.headers.insert(Header::new(
+            "Content-Type".to_string(),
+            content_type.to_string(),
+        ));
+        part.headers.insert(Header::new(
+            "Content-Disposition".to_string(),
+            ContentDisposition::attachment(filename).to_string(),
+        ));
+        part.set_body_bytes(content);
+        self.attach(part)
+    }
+
+    /// Send a calendar object, such as an invitation, with the given iTIP
+    /// `method`.
+    ///
+    /// The calendar is added as a `text/calendar` alternative to the text
+    /// and HTML bodies, and attached as `invite.ics`, as Outlook and Google
+    /// Calendar expect. The alternative is left out if the body is set with
+    /// `body`.
+    pub fn calendar_invite(mut self, method: CalendarMethod, ics: &str) -> MessageBuilder {
+        self.calendar = Some((method, ics.to_string()));
+        self.attach_file(
+            "invite.ics",
+            ics.as_bytes(),
+            Some(ContentType::new("application", "ics")),
+        )
+    }
+
+    /// Attach a contact card as `filename`, such as `Joe Bloggs.vcf`.
+    pub fn attach_vcard(self, filename: &str, vcard: &str) -> MessageBuilder {
+        let mut content_type = ContentType::new("text", "vcard");
+        content_type.set_parameter("charset", "utf-8");
+        self.attach_file(filename, vcard.as_bytes(), Some(content_type))
+    }
+
+    /// Encode the text and HTML bodies with `encoding`, rather than choosing
+    /// an encoding based on their content.
+    pub fn transfer_encoding(mut self, encoding: MimeContentTransferEncoding) -> MessageBuilder {
+        self.transfer_encoding = Some(encoding);
+        self
+    }
+
+    /// Convert internationalized domains in the address headers to
+    /// A-labels, for transports which don't support SMTPUTF8.
+    pub fn ascii_domains(mut self, ascii_domains: bool) -> MessageBuilder {
+        self.ascii_domains = ascii_domains;
+        self
+    }
+
+    /// Send the plain text body as `format=flowed`, wrapping its long lines
+    /// so that readers which understand the format can join them again.
+    pub fn format_flowed(mut self, flowed: bool) -> MessageBuilder {
+        self.flowed = flowed;
+        self
+    }
+
+    /// Start the boundaries of any multiparts made by the builder with
+    /// `prefix`, which is followed by random characters.
+    pub fn boundary_prefix(mut self, prefix: &str) -> MessageBuilder {
+        self.boundary_prefix = prefix.to_string();
+        self
+    }
+
+    /// The subject set on this builder.
+    pub fn get_subject(&self) -> Option<&str> {
+        self.subject.as_ref().map(|s| &s[..])
+    }
+
+    /// The primary recipients set on this builder.
+    pub fn get_to(&self) -> &[Address] {
+        &self.to[..]
+    }
+
+    /// The carbon-copy recipients set on this builder.
+    pub fn get_cc(&self) -> &[Address] {
+        &self.cc[..]
+    }
+
+    /// The blind carbon-copy recipients set on this builder.
+    pub fn get_bcc(&self) -> &[Address] {
+        &self.bcc[..]
+    }
+
+    /// The plain text body set on this builder.
+    pub fn get_text_body(&self) -> Option<&str> {
+        self.text.as_ref().map(|s| &s[..])
+    }
+
+    /// All of the recipient mailboxes of this message, including Bcc,
+    /// as needed for the SMTP envelope.
+    pub fn recipients(&self) -> Vec<Mailbox> {
+        let mut result = Vec::new();
+        for address in self.to.iter().chain(self.cc.iter()).chain(self.bcc.iter()) {
+            match *address {
+                Address::Mailbox(ref mbox) => result.push(mbox.clone()),
+                Address::Group(_, ref mboxes) => result.extend(mboxes.iter().cloned()),
+            }
+        }
+        result
+    }
+
+    /// The domain of the first author, used for generating identifiers.
+    fn author_domain(&self) -> String {
+        self.from
+            .iter()
+            .filter_map(|address| match *address {
+                Address::Mailbox(ref mbox) => mbox.address.rsplit('@').next(),
+                Address::Group(..) => None,
+            })
+            .next()
+            .unwrap_or("localhost")
+            .to_string()
+    }
+
+    fn plain_part(&self, text: &str) -> MimeMessage {
+        if !self.flowed {
+            return text_part("plain", "", text, self.transfer_encoding);
+        }
+        let flowed = FlowedText::default();
+        let text = flowed.encode(text);
+        text_part("plain", flowed.parameters(), &text[..], self.transfer_encoding)
+    }
+
+    fn build_body(&mut self) -> MimeMessage {
+        let body = match self.body.take() {
+            Some(body) => body,
+            None => {
+                let html = self.html.take();
+                let text = match (self.text.take(), &html) {
+                    (Some(text), _) => text,
+                    (None, Some(html)) => html_to_text(&html[..]),
+                    (None, None) => String::new(),
+                };
+                let mut alternatives = vec![self.plain_part(&text[..])];
+                if let Some(html) = html {
+                    alternatives.push(text_part("html", "", &html[..], self.transfer_encoding));
+                }
+                if let Some((method, ics)) = &self.calendar {
+                    let method = format!("method={}", method);
+                    let part = text_part("calendar", &method[..], ics, self.transfer_encoding);
+                    alternatives.push(part);
+                }
+
+                if alternatives.len() == 1 {
+                    alternatives.remove(0)
+                } else {
+                    MimeMessage::new_with_boundary(
+                        "".to_string(),
+                        MimeMultipartType::Alternative,
+                        alternatives,
+                        MimeMessage::generate_boundary(&self.boundary_prefix[..]),
+                    )
+                }
+            }
+        };
+
+        if self.attachments.is_empty() {
+            body
+        } else {
+            let mut children = vec![body];
+            children.append(&mut self.attachments);
+            MimeMessage::new_with_boundary(
+                "".to_string(),
+                MimeMultipartType::Mixed,
+                children,
+                MimeMessage::generate_boundary(&self.boundary_prefix[..]),
+            )
+        }
+    }
+
+    /// Build the message.
+    ///
+    /// Fails if there is no author, or if there are several but no sender,
+    /// as RFC 5322 requires one, or if a header value can't be generated.
+    /// A subject, display name or address with a line break in it is
+    /// rejected, with a `HeaderValueError` giving the error, rather than
+    /// being allowed to add other headers.
+    pub fn build(mut self) -> ParsingResult<MimeMessage> {
+        if self.from.is_empty() {
+            return Err(ParsingError::new(
+                "A message requires a From address.".to_string(),
+            ));
+        }
+        let author_count: usize = self
+            .from
+            .iter()
+            .map(|address| match *address {
+                Address::Mailbox(_) => 1,
+                Address::Group(_, ref mailboxes) => mailboxes.len(),
+            })
+            .sum();
+        if author_count > 1 && self.sender.is_none() {
+            return Err(ParsingError::new(
+                "A message with several authors requires a Sender.".to_string(),
+            ));
+        }
+        if let Some(ref subject) = self.subject {
+            check_header_value("Subject", subject)?;
+        }
+        if let Some(ref sender) = self.sender {
+            check_mailbox("Sender", sender)?;
+        }
+        for &(name, addresses) in [
+            ("From", &self.from),
+            ("Reply-To", &self.reply_to),
+            ("To", &self.to),
+            ("Cc", &self.cc),
+            ("Bcc", &self.bcc),
+            (DISPOSITION_NOTIFICATION_TO, &self.receipt_to),
+        ]
+        .iter()
+        {
+            check_addresses(name, addresses)?;
+        }
+        if self.ascii_domains {
+            let to_ascii = |addresses: &[Address]| {
+                addresses
+                    .iter()
+                    .map(Address::to_ascii)
+                    .collect::<ParsingResult<Vec<_>>>()
+            };
+            self.from = to_ascii(&self.from)?;
+            self.sender = self.sender.as_ref().map(Mailbox::to_ascii).transpose()?;
+            self.reply_to = to_ascii(&self.reply_to)?;
+            self.to = to_ascii(&self.to)?;
+            self.cc = to_ascii(&self.cc)?;
+            self.bcc = to_ascii(&self.bcc)?;
+            self.receipt_to = to_ascii(&self.receipt_to)?;
+        }
+
+        let mut headers = HeaderMap::new();
+        let date = self.date.unwrap_or_else(environment::now);
+        headers.insert(Header::new_with_value("Date".to_string(), date)?);
+        headers.insert(Header::new_with_value(
+            "From".to_string(),
+            self.from.clone(),
+        )?);
+        if let Some(sender) = self.sender.take() {
+            headers.insert(Header::new_with_value("Sender".to_string(), sender)?);
+        }
+        for &(name, addresses) in [
+            ("Reply-To", &self.reply_to),
+            ("To", &self.to),
+            ("Cc", &self.cc),
+            (DISPOSITION_NOTIFICATION_TO, &self.receipt_to),
+        ]
+        .iter()
+        {
+            if !addresses.is_empty() {
+                headers.insert(Header::new_with_value(
+                    name.to_string(),
+                    addresses.to_vec(),
+                )?);
+            }
+        }
+        if let Some(subject) = self.subject.take() {
+            headers.insert(Header::new(
+                "Subject".to_string(),
+                encode_header_text(&subject[..]),
+            ));
+        }
+        let message_id = match self.message_id.take() {
+            Some(id) => id,
+            None => MessageId::generate(&self.author_domain()[..]),
+        };
+        headers.insert(Header::new_with_value(
+            "Message-ID".to_string(),
+            message_id,
+        )?);
+        if !self.in_reply_to.is_empty() {
+            headers.insert(Header::new_with_value(
+                "In-Reply-To".to_string(),
+                &self.in_reply_to[..],
+            )?);
+        }
+        if !self.references.is_empty() {
+            headers.insert(Header::new_with_value(
+                "References".to_string(),
+                &self.references[..],
+            )?);
+        }
+        if let Some(priority) = self.priority {
+            for header in priority.headers() {
+                headers.insert(header);
+            }
+        }
+        for header in self.headers.drain(..) {
+            headers.insert(header);
+        }
+        headers.insert(Header::new("MIME-Version".to_string(), "1.0".to_string()));
+
+        let mut message = self.build_body();
+        for header in message.headers.iter() {
+            headers.insert(header.clone());
+        }
+        message.headers = headers;
+        Ok(message)
+    }
+}
+
+/// Who a reply should be addressed to.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ReplyMode {
+    /// Reply to the author only, honouring `Reply-To`.
+    #[default]
+    Sender,
+    /// Reply to the author and all other recipients, honouring
+    /// `Mail-Followup-To` if present.
+    All,
+    /// Reply to the mailing list the message was received through.
+    List,
+}
+
+/// Options for `MimeMessage::reply_builder`.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ReplyOptions {
+    /// Who to address the reply to
+    pub mode: ReplyMode,
+    /// Whether to quote the original text body in the reply
+    pub quote: bool,
+    /// Addresses of the person replying, which are left out of the recipients
+    pub own_addresses: Vec<String>,
+}
+
+/// How a message should be forwarded.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ForwardMode {
+    /// Attach the original message as a message/rfc822 part.
+    Attached,
+    /// Include the text of the original message in the body.
+    Inline,
+}
+
+/// Strip `prefixes` from the start of `subject` as many times as they
+/// occur, as `subject::strip_prefixes` does, then add `prefix` once.
+fn prefix_subject(prefix: &str, prefixes: &[&str], subject: &str) -> String {
+    format!("{} {}", prefix, subject::strip_prefixes(subject, prefixes))
+}
+
+// The mailboxes in the address list header `name`, including those of
+// groups.
+pub(crate) fn mailboxes(message: &MimeMessage, name: &str) -> Vec<Mailbox> {
+    let addresses: Vec<Address> = message
+        .headers
+        .get_value(name.to_string())
+        .unwrap_or_default();
+    let mut result = Vec::new();
+    for address in addresses.into_iter() {
+        match address {
+            Address::Mailbox(mbox) => result.push(mbox),
+            Address::Group(_, mboxes) => result.extend(mboxes),
+        }
+    }
+    result
+}
+
+impl MimeMessage {
+    /// Start a reply to this message.
+    ///
+    /// The recipients are chosen by `options.mode`, `In-Reply-To` and
+    /// `References` are set to continue the thread, and the subject gets a
+    /// single `Re:` prefix. Fails if no recipients could be found.
+    pub fn reply_builder(&self, options: &ReplyOptions) -> ParsingResult<MessageBuilder> {
+        let is_own = |mbox: &Mailbox| {
+            options
+                .own_addresses
+                .iter()
+                .any(|own| own.eq_ignore_ascii_case(&mbox.address[..]))
+        };
+
+        let author = {
+            let reply_to = mailboxes(self, "Reply-To");
+            if reply_to.is_empty() {
+                mailboxes(self, "From")
+            } else {
+                reply_to
+            }
+        };
+
+        let (to, cc) = match options.mode {
+            ReplyMode::Sender => (author, Vec::new()),
+            ReplyMode::All => {
+                let followup = mailboxes(self, "Mail-Followup-To");
+                if followup.is_empty() {
+                    let mut others = mailboxes(self, "To");
+                    others.extend(mailboxes(self, "Cc"));
+                    (author, others)
+                } else {
+                    (followup, Vec::new())
+                }
+            }
+            ReplyMode::List => {
+                let post: Option<ListPost> = self.headers.get_value(LIST_POST.to_string()).ok();
+                let list = match post {
+                    Some(ListPost::Uris(uris)) => uris.with_scheme("mailto").map(|uri| {
+                        let address = &uri["mailto:".len()..];
+                        Mailbox::new(address.split('?').next().unwrap_or("").to_string())
+                    }),
+                    _ => None,
+                };
+                match list {
+                    Some(list) => (vec![list], Vec::new()),
+                    None => (mailboxes(self, "Mail-Followup-To"), Vec::new()),
+                }
+            }
+        };
+
+        let mut builder = MessageBuilder::new();
+        let mut seen: Vec<String> = Vec::new();
+        for (mbox, is_to) in to
+            .into_iter()
+            .map(|m| (m, true))
+            .chain(cc.into_iter().map(|m| (m, false)))
+        {
+            let key = mbox.address.to_ascii_lowercase();
+            if is_own(&mbox) || seen.contains(&key) {
+                continue;
+            }
+            seen.push(key);
+            builder = if is_to {
+                builder.to(mbox)
+            } else {
+                builder.cc(mbox)
+            };
+        }
+        if builder.to.is_empty() {
+            if builder.cc.is_empty() {
+                return Err(ParsingError::new(
+                    "Couldn't find any recipients for the reply.".to_string(),
+                ));
+            }
+            // Everyone else was ourselves, so promote the Cc list
+            builder.to = std::mem::take(&mut builder.cc);
+        }
+
+        let subject: String = self
+            .headers
+            .get_value("Subject".to_string())
+            .unwrap_or_default();
+        builder = builder.subject(prefix_subject("Re:", &["re"], &subject[..]));
+
+        let message_id: Option<MessageId> = self.headers.get_value("Message-ID".to_string()).ok();
+        let mut references: Vec<MessageId> = self
+            .headers
+            .get_value("References".to_string())
+            .unwrap_or_default();
+        if references.is_empty() {
+            let in_reply_to: Vec<MessageId> = self
+                .headers
+                .get_value("In-Reply-To".to_string())
+                .unwrap_or_default();
+            references.extend(in_reply_to.into_iter().take(1));
+        }
+        if let Some(id) = message_id {
+            references.push(id.clone());
+            builder = builder.in_reply_to(id);
+        }
+        builder = builder.references(references);
+
+        if options.quote {
+            if let Some(text) = self.text_body() {
+                let author = mailboxes(self, "From")
+                    .into_iter()
+                    .next()
+                    .map(|m| m.to_string())
+                    .unwrap_or_else(|| "someone".to_string());
+                let date: Option<String> = self.headers.get_value("Date".to_string()).ok();
+                let mut quoted = match date {
+                    Some(date) => format!("On {}, {} wrote:\r\n", date, author),
+                    None => format!("{} wrote:\r\n", author),
+                };
+                for line in text.lines() {
+                    if line.is_empty() || line.starts_with('>') {
+                        quoted.push('>');
+                    } else {
+                        quoted.push_str("> ");
+                    }
+                    quoted.push_str(line);
+                    quoted.push_str("\r\n");
+                }
+                builder = builder.text_body(quoted);
+            }
+        }
+
+        Ok(builder)
+    }
+
+    /// Start a forward of this message.
+    ///
+    /// The subject gets a single `Fwd:` prefix and `References` is set to
+    /// this message's `Message-ID`. Recipients are left to the caller.
+    pub fn forward_builder(&self, mode: ForwardMode) -> MessageBuilder {
+        let subject: String = self
+            .headers
+            .get_value("Subject".to_string())
+            .unwrap_or_default();
+        let mut builder =
+            MessageBuilder::new().subject(prefix_subject("Fwd:", &["fwd", "fw"], &subject[..]));
+
+        if let Ok(id) = self
+            .headers
+            .get_value::<MessageId>("Message-ID".to_string())
+        {
+            builder = builder.references(vec![id]);
+        }
+
+        match mode {
+            ForwardMode::Attached => {
+                let mut part = MimeMessage::new_blank_message();
+                part.headers.insert(Header::new(
+                    "Content-Type".to_string(),
+                    "message/rfc822".to_string(),
+                ));
+                part.headers.insert(Header::new(
+                    "Content-Disposition".to_string(),
+                    "inline".to_string(),
+                ));
+                part.body = self.to_bytes().into();
+                builder.attach(part)
+            }
+            ForwardMode::Inline => {
+                let mut text = "---------- Forwarded message ----------\r\n".to_string();
+                for name in ["From", "Date", "Subject", "To", "Cc"].iter() {
+                    if let Ok(value) = self.headers.get_value::<String>(name.to_string()) {
+                        text.push_str(&format!("{}: {}\r\n", name, value)[..]);
+                    }
+                }
+                text.push_str("\r\n");
+                if let Some(original) = self.text_body() {
+                    text.push_str(&original[..]);
+                }
+                builder.text_body(text)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::offset::TimeZone;
+
+    fn original() -> MimeMessage {
+        MimeMessage::parse(
+            "From: Joe Blogs <joe@example.org>\r\n\
+             To: john@example.org, me@example.net\r\n\
+             Cc: Jane <jane@example.org>, JOHN@example.org\r\n\
+             Subject: Re: RE: Lunch\r\n\
+             Date: Wed, 17 Dec 2014 09:35:07 +0100\r\n\
+             Message-ID: <3@example.org>\r\n\
+             References: <1@example.org> <2@example.org>\r\n\
+             \r\n\
+             Shall we?\r\n\
+             > Earlier\r\n",
+        )
+        .unwrap()
+    }
+
+    fn header_value(message: &MimeMessage, name: &str) -> Option<String> {
+        message.headers.get_value(name.to_string()).ok()
+    }
+
+    #[test]
+    fn test_build_simple() {
+        let message = MessageBuilder::new()
+            .from(("joe@example.org", "Joe Blogs"))
+            .to("john@example.org")
+            .bcc("secret@example.org")
+            .subject("Hello")
+            .date(FixedOffset::east(0).ymd(2020, 1, 2).and_hms(3, 4, 5))
+            .message_id(MessageId::new("id@example.org".to_string()))
+            .text_body("Hi there")
+            .build()
+            .unwrap();
+
+        let headers: Vec<String> = message.headers.iter().map(|h| h.to_string()).collect();
+        assert_eq!(
+            headers,
+            vec![
+                "Date: Thu, 2 Jan 2020 03:04:05 +0000",
+                "From: Joe Blogs <joe@example.org>",
+                "To: <john@example.org>",
+                "Subject: Hello",
+                "Message-ID: <id@example.org>",
+                "MIME-Version: 1.0",
+                "Content-Type: text/plain; charset=utf-8",
+                "Content-Transfer-Encoding: 7bit",
+            ]
+        );
+        assert_eq!(message.body, "Hi there");
+        assert!(message.children.is_empty());
+    }
+
+    #[test]
+    fn test_build_multipart() {
+        let message = MessageBuilder::new()
+            .from("joe@example.org")
+            .subject("Grüße")
+            .text_body("Text")
+            .html_body("<p>HTML</p>")
+            .attach(MimeMessage::new_blank_message())
+            .boundary_prefix("=_part_")
+            .build()
+            .unwrap();
+        assert_eq!(message.message_type, Some(MimeMultipartType::Mixed));
+        assert!(message.boundary.starts_with("=_part_"));
+        assert!(message.children[0].boundary.starts_with("=_part_"));
+        assert_eq!(message.children.len(), 2);
+        assert_eq!(
+            message.children[0].message_type,
+            Some(MimeMultipartType::Alternative)
+        );
+        assert_eq!(message.children[0].children[1].body, "<p>HTML</p>");
+        let message_id: MessageId = message.headers.get_value("Message-ID".to_string()).unwrap();
+        assert_eq!(message_id.right(), Some("example.org"));
+        assert_eq!(header_value(&message, "Subject").unwrap(), "Grüße");
+
+        let reparsed = MimeMessage::parse(&message.as_string()[..]).unwrap();
+        assert_eq!(reparsed.children.len(), 2);
+        assert_eq!(reparsed.children[0].children.len(), 2);
+    }
+
+    #[test]
+    fn test_build_html_only() {
+        let message = MessageBuilder::new()
+            .from("joe@example.org")
+            .html_body("<p>Hello <a href=\"https://example.org\">there</a></p>")
+            .build()
+            .unwrap();
+        assert_eq!(message.message_type, Some(MimeMultipartType::Alternative));
+        assert_eq!(message.children.len(), 2);
+        assert_eq!(
+            message.children[0].text_body(),
+            Some("Hello there[1]\n\n[1] https://example.org\n".to_string())
+        );
+        assert_eq!(
+            message.children[1].body,
+            "<p>Hello <a href=\"https://example.org\">there</a></p>"
+        );
+    }
+
+    #[test]
+    fn test_build_format_flowed() {
+        let text = "A paragraph long enough to need wrapping when it is sent as flowed text, \
+                    as this one is.\r\n> From a quote\r\n";
+        let message = MessageBuilder::new()
+            .from("joe@example.org")
+            .text_body(text)
+            .format_flowed(true)
+            .build()
+            .unwrap();
+        assert_eq!(
+            header_value(&message, "Content-Type").unwrap(),
+            "text/plain; charset=utf-8; format=flowed; delsp=yes"
+        );
+        assert_eq!(
+            message.body,
+            "A paragraph long enough to need wrapping when it is sent as flowed text, as  \r\n\
+             this one is.\r\n\
+             > From a quote\r\n"
+        );
+        assert_eq!(message.text_body(), Some(text.to_string()));
+    }
+
+    #[test]
+    fn test_build_transfer_encoding() {
+        let text = "Grüße aus Köln, wir sehen uns am Wochenende";
+        let builder = MessageBuilder::new().from("joe@example.org").text_body(text);
+        let message = builder.clone().build().unwrap();
+        assert_eq!(
+            header_value(&message, "Content-Transfer-Encoding").unwrap(),
+            "quoted-printable"
+        );
+        assert_eq!(
+            message.body,
+            "Gr=C3=BC=C3=9Fe aus K=C3=B6ln, wir sehen uns am Wochenende"
+        );
+        assert_eq!(message.text_body(), Some(text.to_string()));
+
+        let message = builder
+            .transfer_encoding(MimeContentTransferEncoding::Base64)
+            .build()
+            .unwrap();
+        assert_eq!(
+            header_value(&message, "Content-Transfer-Encoding").unwrap(),
+            "base64"
+        );
+        assert_eq!(message.text_body(), Some(text.to_string()));
+    }
+
+    #[test]
+    fn test_build_ascii_domains() {
+        let builder = MessageBuilder::new()
+            .from("joe@bücher.example")
+            .to(("anna@münchen.de", "Anna"));
+        let message = builder.clone().build().unwrap();
+        assert_eq!(
+            header_value(&message, "To").unwrap(),
+            "Anna <anna@münchen.de>"
+        );
+
+        let message = builder.ascii_domains(true).build().unwrap();
+        assert_eq!(
+            header_value(&message, "From").unwrap(),
+            "<joe@xn--bcher-kva.example>"
+        );
+        assert_eq!(
+            header_value(&message, "To").unwrap(),
+            "Anna <anna@xn--mnchen-3ya.de>"
+        );
+        let message_id: MessageId = message.headers.get_value("Message-ID".to_string()).unwrap();
+        assert_eq!(message_id.right(), Some("xn--bcher-kva.example"));
+    }
+
+    #[test]
+    fn test_build_attach_file() {
+        let message = MessageBuilder::new()
+            .from("joe@example.org")
+            .text_body("See attached")
+            .attach_file("Bericht März.pdf", b"%PDF-1.4\0", None)
+            .attach_file("data.bin", b"1,2", Some(ContentType::new("text", "csv")))
+            .build()
+            .unwrap();
+        assert_eq!(message.children.len(), 3);
+
+        let pdf = &message.children[1];
+        assert_eq!(
+            header_value(pdf, "Content-Type").unwrap(),
+            "application/pdf; name*=utf-8''Bericht%20M%C3%A4rz.pdf"
+        );
+        let disposition: ContentDisposition =
+            pdf.headers.get_value("Content-Disposition".to_string()).unwrap();
+        assert_eq!(disposition.filename, Some("Bericht März.pdf".to_string()));
+        assert_eq!(pdf.decoded_body_bytes(), Some(b"%PDF-1.4\0".to_vec()));
+
+        let csv = &message.children[2];
+        assert_eq!(csv.content_type().to_string(), "text/csv; name=data.bin");
+    }
+
+    #[test]
+    fn test_build_calendar_invite() {
+        let ics = "BEGIN:VCALENDAR\r\nMETHOD:REQUEST\r\nEND:VCALENDAR\r\n";
+        let message = MessageBuilder::new()
+            .from("joe@example.org")
+            .text_body("You're invited")
+            .calendar_invite(CalendarMethod::Request, ics)
+            .build()
+            .unwrap();
+        assert_eq!(message.message_type, Some(MimeMultipartType::Mixed));
+        assert_eq!(message.children.len(), 2);
+
+        let alternative = &message.children[0];
+        assert_eq!(alternative.message_type, Some(MimeMultipartType::Alternative));
+        assert_eq!(alternative.children.len(), 2);
+        assert_eq!(
+            header_value(&alternative.children[1], "Content-Type").unwrap(),
+            "text/calendar; charset=utf-8; method=REQUEST"
+        );
+        assert_eq!(
+            message.children[1].content_type().to_string(),
+            "application/ics; name=invite.ics"
+        );
+
+        let reparsed = MimeMessage::parse(&message.as_string()[..]).unwrap();
+        assert_eq!(reparsed.text_body().unwrap().trim_end(), "You're invited");
+        let calendar = reparsed.calendar().unwrap();
+        assert_eq!(calendar.method, Some(CalendarMethod::Request));
+        assert!(calendar.ics.starts_with(ics));
+    }
+
+    #[test]
+    fn test_build_attach_vcard() {
+        let vcard = "BEGIN:VCARD\r\nVERSION:4.0\r\nFN:Jörg\r\nEND:VCARD\r\n";
+        let message = MessageBuilder::new()
+            .from("joe@example.org")
+            .text_body("My card")
+            .attach_vcard("Jörg.vcf", vcard)
+            .build()
+            .unwrap();
+        let card = &message.children[1];
+        assert_eq!(
+            card.content_type().to_string(),
+            "text/vcard; charset=utf-8; name*=utf-8''J%C3%B6rg.vcf"
+        );
+        let disposition: ContentDisposition =
+            card.headers.get_value("Content-Disposition".to_string()).unwrap();
+        assert!(disposition.is_attachment());
+        assert_eq!(message.vcards(), vec![vcard.to_string()]);
+    }
+
+    #[test]
+    fn test_build_requires_from() {
+        assert!(MessageBuilder::new().to("a@example.org").build().is_err());
+    }
+
+    #[test]
+    fn test_build_priority() {
+        let message = MessageBuilder::new()
+            .from("joe@example.org")
+            .priority(MessagePriority::High)
+            .build()
+            .unwrap();
+        assert_eq!(
+            header_value(&message, "X-Priority"),
+            Some("1 (Highest)".to_string())
+        );
+        assert_eq!(
+            header_value(&message, "X-MSMail-Priority"),
+            Some("High".to_string())
+        );
+        assert_eq!(
+            header_value(&message, "Importance"),
+            Some("high".to_string())
+        );
+        assert_eq!(
+            header_value(&message, "Priority"),
+            Some("urgent".to_string())
+        );
+        assert_eq!(message.priority(), MessagePriority::High);
+    }
+
+    #[test]
+    fn test_build_rejects_line_breaks() {
+        let builder = MessageBuilder::new().from("joe@example.org");
+        let err = builder
+            .clone()
+            .subject("Hi\r\nBcc: victim@example.net")
+            .build()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Invalid Subject header: Expected text at 2, found '\\r'");
+
+        let mailbox = Mailbox::new_with_name(
+            "Anna\nBcc: victim@example.net".to_string(),
+            "anna@example.net".to_string(),
+        );
+        assert!(builder.clone().to(mailbox).build().is_err());
+
+        // Filenames are encoded rather than rejected
+        let message = builder
+            .attach_file("a\r\nBcc: victim@example.net", b"", None)
+            .build()
+            .unwrap();
+        let written = message.as_string();
+        assert!(!written.contains("\nBcc"));
+        assert!(written.contains("filename*=utf-8''a%0D%0ABcc"));
+    }
+
+    #[test]
+    fn test_reply_sender() {
+        let reply = original()
+            .reply_builder(&ReplyOptions::default())
+            .unwrap()
+            .from("me@example.net")
+            .build()
+            .unwrap();
+        assert_eq!(
+            header_value(&reply, "To").unwrap(),
+            "Joe Blogs <joe@example.org>"
+        );
+        assert_eq!(header_value(&reply, "Cc"), None);
+        assert_eq!(header_value(&reply, "Subject").unwrap(), "Re: Lunch");
+        assert_eq!(
+            header_value(&reply, "In-Reply-To").unwrap(),
+            "<3@example.org>"
+        );
+        assert_eq!(
+            header_value(&reply, "References").unwrap(),
+            "<1@example.org> <2@example.org> <3@example.org>"
+        );
+    }
+
+    #[test]
+    fn test_reply_all() {
+        let options = ReplyOptions {
+            mode: ReplyMode::All,
+            quote: true,
+            own_addresses: vec!["ME@example.net".to_string()],
+        };
+        let builder = original().reply_builder(&options).unwrap();
+        let recipients: Vec<String> = builder
+            .recipients()
+            .into_iter()
+            .map(|m| m.address)
+            .collect();
+        assert_eq!(
+            recipients,
+            vec!["joe@example.org", "john@example.org", "jane@example.org"]
+        );
+        assert_eq!(
+            builder.get_text_body().unwrap(),
+            "On Wed, 17 Dec 2014 09:35:07 +0100, Joe Blogs <joe@example.org> wrote:\r\n\
+             > Shall we?\r\n\
+             >> Earlier\r\n"
+        );
+
+        // Mail-Followup-To overrides everything else
+        let mut message = original();
+        message.headers.insert(Header::new(
+            "Mail-Followup-To".to_string(),
+            "list@example.org".to_string(),
+        ));
+        let builder = message.reply_builder(&options).unwrap();
+        let recipients: Vec<String> = builder
+            .recipients()
+            .into_iter()
+            .map(|m| m.address)
+            .collect();
+        assert_eq!(recipients, vec!["list@example.org"]);
+    }
+
+    #[test]
+    fn test_reply_to_header() {
+        let mut message = original();
+        message.headers.insert(Header::new(
+            "Reply-To".to_string(),
+            "replies@example.org".to_string(),
+        ));
+        let builder = message.reply_builder(&ReplyOptions::default()).unwrap();
+        assert_eq!(builder.recipients()[0].address, "replies@example.org");
+    }
+
+    #[test]
+    fn test_reply_list() {
+        let options = ReplyOptions {
+            mode: ReplyMode::List,
+            ..Default::default()
+        };
+        assert!(original().reply_builder(&options).is_err());
+
+        let mut message = original();
+        message.headers.insert(Header::new(
+            LIST_POST.to_string(),
+            "<mailto:list@example.org?subject=post>".to_string(),
+        ));
+        let builder = message.reply_builder(&options).unwrap();
+        assert_eq!(builder.recipients()[0].address, "list@example.org");
+    }
+
+    #[test]
+    fn test_prefix_subject() {
+        let reply = |subject| prefix_subject("Re:", &["re"], subject);
+        assert_eq!(reply("No\u{eb}l"), "Re: No\u{eb}l");
+        assert_eq!(reply("RE: No\u{eb}l"), "Re: No\u{eb}l");
+        let forward = |subject| prefix_subject("Fwd:", &["fwd", "fw"], subject);
+        assert_eq!(forward("Caf\u{e9}"), "Fwd: Caf\u{e9}");
+        assert_eq!(forward("Fw: Caf\u{e9}"), "Fwd: Caf\u{e9}");
+        assert_eq!(reply("Re[2]: RE\u{ff1a} No\u{eb}l"), "Re: No\u{eb}l");
+        assert_eq!(reply("Fwd: No\u{eb}l"), "Re: Fwd: No\u{eb}l");
+        assert_eq!(reply("Reunion"), "Re: Reunion");
+    }
+
+    #[test]
+    fn test_forward() {
+        let builder = original().forward_builder(ForwardMode::Inline);
+        assert_eq!(builder.get_subject(), Some("Fwd: Re: RE: Lunch"));
+        assert!(builder
+            .get_text_body()
+            .unwrap()
+            .ends_with("\r\n\r\nShall we?\r\n> Earlier\r\n"));
+
+        let mut message = original();
+        message.headers.replace(Header::new(
+            "Subject".to_string(),
+            "FWD: Fw: Lunch".to_string(),
+        ));
+        let forward = message
+            .forward_builder(ForwardMode::Attached)
+            .from("me@example.net")
+            .to("friend@example.org")
+            .build()
+            .unwrap();
+        assert_eq!(header_value(&forward, "Subject").unwrap(), "Fwd: Lunch");
+        assert_eq!(
+            header_value(&forward, "References").unwrap(),
+            "<3@example.org>"
+        );
+        assert_eq!(forward.children.len(), 2);
+        let attached = MimeMessage::parse(&forward.children[1].body[..]).unwrap();
+        assert_eq!(
+            header_value(&attached, "Message-ID").unwrap(),
+            "<3@example.org>"
+        );
+    }
+}
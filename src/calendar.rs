@@ -0,0 +1,169 @@
+//! Module for calendar invitations sent by mail, as described in RFC 6047.
+//!
+//! An invitation, or a reply to one, is sent as a `text/calendar` part whose
+//! `method` parameter gives the iTIP method of RFC 5546, such as `REQUEST`.
+//! Clients such as Outlook and Google Calendar also expect the same
+//! calendar attached as an `.ics` file; `MessageBuilder::calendar_invite`
+//! adds both.
+use std::fmt;
+use std::str::FromStr;
+
+use super::message::MimeMessage;
+use super::results::{ParsingError, ParsingResult};
+
+/// The iTIP method of a calendar object, RFC 5546 Section 1.4
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum CalendarMethod {
+    Publish,
+    Request,
+    Reply,
+    Add,
+    Cancel,
+    Refresh,
+    Counter,
+    DeclineCounter,
+    /// Any other method, uppercased
+    Other(String),
+}
+
+impl CalendarMethod {
+    pub fn as_str(&self) -> &str {
+        match self {
+            CalendarMethod::Publish => "PUBLISH",
+            CalendarMethod::Request => "REQUEST",
+            CalendarMethod::Reply => "REPLY",
+            CalendarMethod::Add => "ADD",
+            CalendarMethod::Cancel => "CANCEL",
+            CalendarMethod::Refresh => "REFRESH",
+            CalendarMethod::Counter => "COUNTER",
+            CalendarMethod::DeclineCounter => "DECLINECOUNTER",
+            CalendarMethod::Other(method) => &method[..],
+        }
+    }
+}
+
+impl FromStr for CalendarMethod {
+    type Err = ParsingError;
+
+    fn from_str(s: &str) -> ParsingResult<CalendarMethod> {
+        let method = s.trim().to_ascii_uppercase();
+        if method.is_empty()
+            || !method
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        {
+            return Err(ParsingError::new(format!("Invalid calendar method: {}", s)));
+        }
+        Ok(match &method[..] {
+            "PUBLISH" => CalendarMethod::Publish,
+            "REQUEST" => CalendarMethod::Request,
+            "REPLY" => CalendarMethod::Reply,
+            "ADD" => CalendarMethod::Add,
+            "CANCEL" => CalendarMethod::Cancel,
+            "REFRESH" => CalendarMethod::Refresh,
+            "COUNTER" => CalendarMethod::Counter,
+            "DECLINECOUNTER" => CalendarMethod::DeclineCounter,
+            _ => CalendarMethod::Other(method),
+        })
+    }
+}
+
+impl fmt::Display for CalendarMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A `text/calendar` part of a message.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CalendarPart {
+    /// The method from the part's `Content-Type`, or failing that from the
+    /// calendar itself
+    pub method: Option<CalendarMethod>,
+    /// The decoded iCalendar data
+    pub ics: String,
+}
+
+impl MimeMessage {
+    /// The first `text/calendar` part of this message, such as an
+    /// invitation or a reply to one.
+    pub fn calendar(&self) -> Option<CalendarPart> {
+        let (_, part) = self.parts_depth_first().find(|(_, part)| {
+            let content_type = part.content_type();
+            content_type.ttype == "text" && content_type.subtype == "calendar"
+        })?;
+        let ics = part.decoded_body_string().ok()?;
+        let method = part
+            .content_type()
+            .parameter("method")
+            .and_then(|method| method.parse().ok())
+            .or_else(|| ics_method(&ics[..]));
+        Some(CalendarPart { method, ics })
+    }
+}
+
+// The METHOD property of the calendar object in `ics`.
+fn ics_method(ics: &str) -> Option<CalendarMethod> {
+    ics.lines()
+        .take_while(|line| !line.starts_with("BEGIN:V") || line.starts_with("BEGIN:VCALENDAR"))
+        .find_map(|line| line.strip_prefix("METHOD:"))
+        .and_then(|method| method.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ICS: &str = "BEGIN:VCALENDAR\r\n\
+                       VERSION:2.0\r\n\
+                       METHOD:CANCEL\r\n\
+                       BEGIN:VEVENT\r\n\
+                       UID:1@example.org\r\n\
+                       END:VEVENT\r\n\
+                       END:VCALENDAR\r\n";
+
+    #[test]
+    fn test_calendar_method() {
+        assert_eq!(
+            "request".parse::<CalendarMethod>().unwrap(),
+            CalendarMethod::Request
+        );
+        assert_eq!(
+            "X-Custom".parse::<CalendarMethod>().unwrap(),
+            CalendarMethod::Other("X-CUSTOM".to_string())
+        );
+        assert_eq!(CalendarMethod::DeclineCounter.to_string(), "DECLINECOUNTER");
+        assert!("".parse::<CalendarMethod>().is_err());
+        assert!("RE QUEST".parse::<CalendarMethod>().is_err());
+    }
+
+    #[test]
+    fn test_calendar_part() {
+        let message = MimeMessage::parse(&format!(
+            "Content-Type: multipart/alternative; boundary=alt\r\n\r\n\
+             --alt\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Cancelled\r\n\
+             --alt\r\n\
+             Content-Type: text/calendar; charset=utf-8; method=REQUEST\r\n\r\n\
+             {}\r\n\
+             --alt--\r\n",
+            ICS
+        ))
+        .unwrap();
+        let calendar = message.calendar().unwrap();
+        assert_eq!(calendar.method, Some(CalendarMethod::Request));
+        assert!(calendar.ics.starts_with(ICS));
+
+        // Without a method parameter, the calendar's own is used
+        let message =
+            MimeMessage::parse(&format!("Content-Type: text/calendar\r\n\r\n{}", ICS)).unwrap();
+        assert_eq!(
+            message.calendar().unwrap().method,
+            Some(CalendarMethod::Cancel)
+        );
+
+        let message = MimeMessage::parse("Content-Type: text/plain\r\n\r\nHi").unwrap();
+        assert_eq!(message.calendar(), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! Module for the canonical forms of headers and bodies defined for DKIM,
+//! RFC 6376 Section 3.4.
+//!
+//! A signature is computed over one of these forms, so that changes made to
+//! a message in transit which don't alter its meaning don't break it. ARC
+//! uses the same forms, and they serve for comparing messages too.
+//!
+//! The "simple" forms change next to nothing, while the "relaxed" ones
+//! allow for changes to case and whitespace. Both take line breaks of
+//! either LF or CRLF, and give CRLF.
+
+/// Canonicalize a header using the "simple" algorithm, Section 3.4.1.
+///
+/// `value` is taken as it appears in the message, with any folding, as
+/// `Header::folded_value` gives it, and is kept as it is. The result
+/// includes the trailing CRLF.
+pub fn header_simple(name: &str, value: &str) -> String {
+    let mut canonical = format!("{}:", name);
+    let mut lines = value.split('\n').peekable();
+    while let Some(line) = lines.next() {
+        canonical.push_str(line.strip_suffix('\r').unwrap_or(line));
+        if lines.peek().is_some() {
+            canonical.push_str("\r\n");
+        }
+    }
+    canonical.push_str("\r\n");
+    canonical
+}
+
+/// Canonicalize a header using the "relaxed" algorithm, Section 3.4.2.
+///
+/// The name is lowercased, and the value unfolded, with each run of
+/// whitespace in it reduced to a single space and any at either end
+/// removed. The result includes the trailing CRLF.
+pub fn header_relaxed(name: &str, value: &str) -> String {
+    let mut canonical = String::new();
+    for word in value.split_whitespace() {
+        if !canonical.is_empty() {
+            canonical.push(' ');
+        }
+        canonical.push_str(word);
+    }
+    format!("{}:{}\r\n", name.trim().to_ascii_lowercase(), canonical)
+}
+
+/// Canonicalize a body using the "simple" algorithm, Section 3.4.3.
+///
+/// Empty lines at the end are removed, and the last line is ended by CRLF.
+/// An empty body becomes a single CRLF.
+pub fn body_simple(body: &[u8]) -> Vec<u8> {
+    let mut canonical = Vec::with_capacity(body.len() + 2);
+    for line in lines(body) {
+        canonical.extend_from_slice(line);
+        canonical.extend_from_slice(b"\r\n");
+    }
+    trim_empty_lines(&mut canonical);
+    if canonical.is_empty() {
+        canonical.extend_from_slice(b"\r\n");
+    }
+    canonical
+}
+
+/// Canonicalize a body using the "relaxed" algorithm, Section 3.4.4.
+///
+/// Whitespace at the end of each line is removed, and each other run of it
+/// reduced to a single space. Empty lines at the end are then removed, and
+/// the last line is ended by CRLF. An empty body stays empty.
+pub fn body_relaxed(body: &[u8]) -> Vec<u8> {
+    let mut canonical = Vec::with_capacity(body.len() + 2);
+    for line in lines(body) {
+        let mut space = false;
+        for &b in line {
+            if b == b' ' || b == b'\t' {
+                space = true;
+                continue;
+            }
+            if space {
+                canonical.push(b' ');
+                space = false;
+            }
+            canonical.push(b);
+        }
+        canonical.extend_from_slice(b"\r\n");
+    }
+    trim_empty_lines(&mut canonical);
+    canonical
+}
+
+// The lines of `body` without their line breaks, with a last line lacking
+// one included.
+fn lines(body: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let body = body.strip_suffix(b"\n").unwrap_or(body);
+    body.split(|&b| b == b'\n')
+        .filter(move |_| !body.is_empty())
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+}
+
+// Remove the empty lines from the end of CRLF-ended `lines`.
+fn trim_empty_lines(lines: &mut Vec<u8>) {
+    while lines.ends_with(b"\r\n\r\n") {
+        lines.truncate(lines.len() - 2);
+    }
+    if lines == b"\r\n" {
+        lines.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_simple() {
+        assert_eq!(
+            header_simple("Subject", " A  test\r\n\t value  "),
+            "Subject: A  test\r\n\t value  \r\n"
+        );
+        assert_eq!(header_simple("X-Folded", " a\n b"), "X-Folded: a\r\n b\r\n");
+    }
+
+    #[test]
+    fn test_header_relaxed() {
+        assert_eq!(
+            header_relaxed("Subject ", " A  test\r\n\t value  "),
+            "subject:A test value\r\n"
+        );
+    }
+
+    #[test]
+    fn test_body_simple() {
+        assert_eq!(
+            body_simple(b" C \r\nD \t E\r\n\r\n\r\n"),
+            b" C \r\nD \t E\r\n"
+        );
+        assert_eq!(body_simple(b"line\nno break"), b"line\r\nno break\r\n");
+        assert_eq!(body_simple(b""), b"\r\n");
+        assert_eq!(body_simple(b"\r\n\r\n"), b"\r\n");
+    }
+
+    #[test]
+    fn test_body_relaxed() {
+        // The example of RFC 6376 Section 3.4.5
+        assert_eq!(body_relaxed(b" C \r\nD \t E\r\n\r\n\r\n"), b" C\r\nD E\r\n");
+        assert_eq!(body_relaxed(b"line \nno break\t"), b"line\r\nno break\r\n");
+        assert_eq!(body_relaxed(b""), b"");
+        assert_eq!(body_relaxed(b" \r\n\r\n"), b"");
+    }
+}
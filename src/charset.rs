@@ -0,0 +1,285 @@
+//! Module for decoding text in the character set declared for it.
+//!
+//! Mail in the wild often declares a charset that is unknown, or that does
+//! not match the bytes actually sent. Rather than giving up, text that can't
+//! be decoded as declared is checked for being valid UTF-8, then decoded with
+//! a fallback charset (windows-1252 by default, which accepts any input).
+//!
+//! Charsets are looked up by the labels of the WHATWG Encoding Standard, as
+//! browsers do, so `iso-8859-1` is decoded as windows-1252, which it is
+//! almost always mislabeled as, and `gb2312` as its superset GBK. Labels
+//! which only turn up in mail, such as `cp936` or `latin-1`, are aliased to
+//! the charsets they mean, and more aliases can be registered with
+//! `register_alias`.
+use alloc::borrow::Cow;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::sync::{PoisonError, RwLock};
+
+use encoding_rs::Encoding;
+
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use super::results::{ParsingError, ParsingResult};
+
+/// What to do with bytes that are malformed in the charset finally used to
+/// decode them.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum OnError {
+    /// Replace each malformed sequence with U+FFFD
+    Replace,
+    /// Fail with a `ParsingError::InvalidEncoding`
+    Strict,
+}
+
+/// Options controlling how text is decoded from its declared charset.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct DecodeOptions {
+    /// The charset used when the declared one is unknown or doesn't fit the
+    /// text, and it isn't valid UTF-8. `None` disables the fallback.
+    pub fallback_charset: Option<String>,
+    /// How malformed input is handled
+    pub on_error: OnError,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> DecodeOptions {
+        DecodeOptions {
+            fallback_charset: Some("windows-1252".to_string()),
+            on_error: OnError::Replace,
+        }
+    }
+}
+
+// Labels used in mail which the Encoding Standard doesn't know, with the
+// label of the charset they mean
+static ALIASES: [(&str, &str); 22] = [
+    ("latin-1", "windows-1252"),
+    ("ansi", "windows-1252"),
+    ("iso646-us", "windows-1252"),
+    ("cp874", "windows-874"),
+    ("cp932", "shift_jis"),
+    ("windows-932", "shift_jis"),
+    ("ujis", "euc-jp"),
+    ("cp936", "gbk"),
+    ("ms936", "gbk"),
+    ("windows-936", "gbk"),
+    ("euc-cn", "gbk"),
+    ("cp949", "euc-kr"),
+    ("ms949", "euc-kr"),
+    ("uhc", "euc-kr"),
+    ("ks_c_5601", "euc-kr"),
+    ("cp950", "big5"),
+    ("ms950", "big5"),
+    ("windows-950", "big5"),
+    ("koi8r", "koi8-r"),
+    ("koi8u", "koi8-u"),
+    ("mac-roman", "macintosh"),
+    ("macroman", "macintosh"),
+];
+
+#[cfg(feature = "std")]
+lazy_static! {
+    static ref CUSTOM_ALIASES: RwLock<HashMap<String, &'static Encoding>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Look up a charset by its label, ignoring case and surrounding whitespace.
+///
+/// Labels the Encoding Standard doesn't know are looked up among the
+/// aliases, both registered and built in, and then with any `x-` prefix
+/// removed and underscores taken as hyphens, as in `x-iso_8859_2`.
+pub fn lookup(charset: &str) -> Option<&'static Encoding> {
+    let label = charset.trim().to_ascii_lowercase();
+    #[cfg(feature = "std")]
+    {
+        let custom = CUSTOM_ALIASES
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&label)
+            .copied();
+        if custom.is_some() {
+            return custom;
+        }
+    }
+    let alias = |label: &str| {
+        ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == label)
+            .and_then(|(_, charset)| Encoding::for_label(charset.as_bytes()))
+    };
+    Encoding::for_label(label.as_bytes())
+        .or_else(|| alias(&label))
+        .or_else(|| {
+            let label = label.strip_prefix("x-").unwrap_or(&label).replace('_', "-");
+            Encoding::for_label(label.as_bytes()).or_else(|| alias(&label))
+        })
+}
+
+/// Register `alias` as a label of `charset`, for all lookups from now on,
+/// in place of any charset it was a label of.
+///
+/// Fails if `charset` is unknown.
+#[cfg(feature = "std")]
+pub fn register_alias(alias: &str, charset: &str) -> ParsingResult<()> {
+    let encoding = lookup(charset).ok_or_else(|| ParsingError::InvalidEncoding {
+        encoding: charset.to_string(),
+    })?;
+    CUSTOM_ALIASES
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(alias.trim().to_ascii_lowercase(), encoding);
+    Ok(())
+}
+
+/// Decode `bytes` as text in `charset`, or best guess if no charset was
+/// declared.
+///
+/// Text is first decoded with the declared charset. Should it be unknown, or
+/// the bytes malformed in it, the text is taken as UTF-8 if valid, then
+/// decoded with the fallback charset of `options`.
+pub fn decode(
+    bytes: &[u8],
+    charset: Option<&str>,
+    options: &DecodeOptions,
+) -> ParsingResult<String> {
+    let declared = charset.and_then(lookup);
+    if let Some(text) = declared
+        .and_then(|encoding| encoding.decode_without_bom_handling_and_without_replacement(bytes))
+    {
+        return Ok(text.into_owned());
+    }
+    if let Ok(text) = core::str::from_utf8(bytes) {
+        return Ok(text.to_string());
+    }
+
+    let fallback = options
+        .fallback_charset
+        .as_ref()
+        .and_then(|label| lookup(label));
+    let encoding = match fallback.or(declared) {
+        Some(encoding) => encoding,
+        None => {
+            return Err(ParsingError::InvalidEncoding {
+                encoding: charset.unwrap_or("us-ascii").to_string(),
+            })
+        }
+    };
+    match options.on_error {
+        OnError::Replace => Ok(encoding.decode_without_bom_handling(bytes).0.into_owned()),
+        OnError::Strict => encoding
+            .decode_without_bom_handling_and_without_replacement(bytes)
+            .map(Cow::into_owned)
+            .ok_or_else(|| ParsingError::InvalidEncoding {
+                encoding: encoding.name().to_string(),
+            }),
+    }
+}
+
+/// Encode `text` in `charset`, replacing anything it can't represent.
+///
+/// Charsets that can only be decoded, such as UTF-16, are encoded as UTF-8.
+pub fn encode(text: &str, charset: &str) -> ParsingResult<Vec<u8>> {
+    match lookup(charset) {
+        Some(encoding) => Ok(encoding.encode(text).0.into_owned()),
+        None => Err(ParsingError::InvalidEncoding {
+            encoding: charset.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_declared() {
+        let options = DecodeOptions::default();
+        assert_eq!(
+            decode(b"caf\xc3\xa9", Some("utf-8"), &options).unwrap(),
+            "café".to_string()
+        );
+        assert_eq!(
+            decode(b"caf\xe9", Some(" ISO-8859-1 "), &options).unwrap(),
+            "café".to_string()
+        );
+    }
+
+    #[test]
+    fn test_decode_fallback() {
+        let options = DecodeOptions::default();
+        // Unknown charset, valid UTF-8
+        assert_eq!(
+            decode(b"caf\xc3\xa9", Some("x-unknown"), &options).unwrap(),
+            "café".to_string()
+        );
+        // Declared UTF-8, but actually windows-1252
+        assert_eq!(
+            decode(b"\x93caf\xe9\x94", Some("utf-8"), &options).unwrap(),
+            "\u{201c}café\u{201d}".to_string()
+        );
+        // Nothing declared
+        assert_eq!(
+            decode(b"caf\xe9", None, &options).unwrap(),
+            "café".to_string()
+        );
+    }
+
+    #[test]
+    fn test_decode_without_fallback() {
+        let replace = DecodeOptions {
+            fallback_charset: None,
+            on_error: OnError::Replace,
+        };
+        assert_eq!(
+            decode(b"caf\xe9", Some("utf-8"), &replace).unwrap(),
+            "caf\u{fffd}".to_string()
+        );
+        assert!(decode(b"caf\xe9", Some("x-unknown"), &replace).is_err());
+
+        let strict = DecodeOptions {
+            fallback_charset: None,
+            on_error: OnError::Strict,
+        };
+        match decode(b"caf\xe9", Some("utf-8"), &strict) {
+            Err(ParsingError::InvalidEncoding { encoding }) => assert_eq!(encoding, "UTF-8"),
+            other => panic!("Expected an encoding error, got {:?}", other),
+        }
+        assert_eq!(
+            decode(b"caf\xc3\xa9", Some("utf-8"), &strict).unwrap(),
+            "café".to_string()
+        );
+    }
+
+    #[test]
+    fn test_lookup_aliases() {
+        let windows_1252 = lookup("windows-1252");
+        assert!(windows_1252.is_some());
+        for label in ["ISO-8859-1", "latin-1", "Latin1", "ISO_8859_1", "x-ansi"].iter() {
+            assert_eq!(lookup(label), windows_1252, "{}", label);
+        }
+        assert_eq!(lookup("KOI8R"), lookup("koi8-r"));
+        assert_eq!(lookup("x-unknown"), None);
+        assert_eq!(
+            decode(b"caf\xe9", Some("latin-1"), &DecodeOptions::default()).unwrap(),
+            "café"
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_register_alias() {
+        assert_eq!(lookup("x-test-cyrillic"), None);
+        register_alias("X-Test-Cyrillic", "cp1251").unwrap();
+        assert_eq!(lookup("x-test-cyrillic"), lookup("windows-1251"));
+        assert!(register_alias("x-test-unknown", "x-unknown").is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode("café", "latin1").unwrap(), b"caf\xe9".to_vec());
+        assert_eq!(encode("café", "utf-8").unwrap(), b"caf\xc3\xa9".to_vec());
+        assert!(encode("café", "x-unknown").is_err());
+    }
+}
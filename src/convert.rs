@@ -0,0 +1,586 @@
+//! Module for converting message bodies between HTML and plain text.
+//!
+//! A message with an HTML body should also carry a plain text alternative
+//! for readers which can't, or won't, show HTML. `html_to_text` produces a
+//! readable one: paragraphs are separated by blank lines, list items are
+//! marked, quotes are prefixed with `>` and links become numbered footnotes.
+//! Going the other way, `text_to_html` marks up a plain text body so that it
+//! can be sent alongside as HTML.
+use super::bodyparse::quote_depth;
+use super::sanitize::{tag_end, tag_name};
+
+// Elements whose content is never shown
+const SKIPPED: &[&str] = &["head", "script", "style", "template", "title"];
+
+// Elements which start and end a paragraph
+const PARAGRAPHS: &[&str] = &[
+    "blockquote",
+    "dl",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "ol",
+    "p",
+    "pre",
+    "table",
+    "ul",
+];
+
+// Elements which start and end a line
+const LINES: &[&str] = &[
+    "address", "article", "aside", "dd", "div", "dt", "figure", "footer", "form", "header", "li",
+    "main", "nav", "section", "tr",
+];
+
+const ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("apos", '\''),
+    ("bull", '•'),
+    ("colon", ':'),
+    ("copy", '©'),
+    ("euro", '€'),
+    ("gt", '>'),
+    ("hellip", '…'),
+    ("laquo", '«'),
+    ("ldquo", '“'),
+    ("lsquo", '‘'),
+    ("lt", '<'),
+    ("mdash", '—'),
+    ("middot", '·'),
+    ("nbsp", '\u{a0}'),
+    ("ndash", '–'),
+    ("NewLine", '\n'),
+    ("quot", '"'),
+    ("raquo", '»'),
+    ("rdquo", '”'),
+    ("reg", '®'),
+    ("rsquo", '’'),
+    ("Tab", '\t'),
+    ("trade", '™'),
+];
+
+/// Convert an HTML document or fragment to plain text.
+///
+/// The result ends with a line break unless it's empty. Links whose text
+/// isn't their address are numbered, with the addresses listed at the end.
+pub fn html_to_text(html: &str) -> String {
+    let lower = html.to_ascii_lowercase();
+    let mut writer = TextWriter::default();
+    let mut pos = 0;
+
+    while let Some(offset) = html[pos..].find('<') {
+        writer.text(&decode_entities(&html[pos..pos + offset]));
+        let start = pos + offset;
+
+        if html[start..].starts_with("<!--") {
+            pos = html[start..]
+                .find("-->")
+                .map_or(html.len(), |end| start + end + 3);
+            continue;
+        }
+        let is_tag = html[start + 1..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        let end = match tag_end(html, start) {
+            Some(end) if is_tag => end,
+            _ => {
+                writer.text("<");
+                pos = start + 1;
+                continue;
+            }
+        };
+
+        let tag = &html[start..end];
+        let name = tag_name(&lower[start..end]);
+        let closing = tag.starts_with("</");
+        pos = end;
+        if !closing && SKIPPED.contains(&name) {
+            let close = format!("</{}", name);
+            pos = lower[end..]
+                .find(&close)
+                .and_then(|i| tag_end(html, end + i))
+                .unwrap_or(html.len());
+            continue;
+        }
+        writer.element(name, tag, closing);
+    }
+    writer.text(&decode_entities(&html[pos..]));
+    writer.finish()
+}
+
+/// Convert a plain text body to HTML.
+///
+/// Special characters are escaped, line breaks become `<br>`, lines quoted
+/// with `>` are put in nested `<blockquote>`s and URLs are made into links.
+pub fn text_to_html(text: &str) -> String {
+    let text = text
+        .strip_suffix("\r\n")
+        .or_else(|| text.strip_suffix('\n'))
+        .unwrap_or(text);
+    let mut html = String::with_capacity(text.len() + text.len() / 8);
+    let mut depth = 0;
+    let mut first = true;
+
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let (line_depth, content) = quote_depth(line);
+        if line_depth == depth {
+            if !first {
+                html.push_str("<br>\n");
+            }
+        } else {
+            if !first {
+                html.push('\n');
+            }
+            while depth > line_depth {
+                html.push_str("</blockquote>\n");
+                depth -= 1;
+            }
+            while depth < line_depth {
+                html.push_str("<blockquote>\n");
+                depth += 1;
+            }
+        }
+        push_linked(&mut html, content);
+        first = false;
+    }
+
+    if depth > 0 {
+        html.push('\n');
+    }
+    for _ in 0..depth {
+        html.push_str("</blockquote>\n");
+    }
+    html
+}
+
+const URL_PREFIXES: &[&str] = &["http://", "https://", "mailto:", "www."];
+
+// Push `text` to `html`, escaped, with its URLs made into links.
+fn push_linked(html: &mut String, text: &str) {
+    let lower = text.to_ascii_lowercase();
+    let mut written = 0;
+    let mut i = 0;
+    while i < text.len() {
+        let at_word_start = !text[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric());
+        let prefix = URL_PREFIXES
+            .iter()
+            .find(|prefix| lower[i..].starts_with(*prefix));
+        if let (true, Some(prefix)) = (at_word_start, prefix) {
+            let end = url_end(text, i);
+            if end > i + prefix.len() {
+                let url = &text[i..end];
+                let href = if *prefix == "www." {
+                    format!("http://{}", url)
+                } else {
+                    url.to_string()
+                };
+                push_escaped(html, &text[written..i]);
+                html.push_str("<a href=\"");
+                push_escaped(html, &href);
+                html.push_str("\">");
+                push_escaped(html, url);
+                html.push_str("</a>");
+                written = end;
+                i = end;
+                continue;
+            }
+        }
+        i += text[i..].chars().next().map_or(1, char::len_utf8);
+    }
+    push_escaped(html, &text[written..]);
+}
+
+// The end of the URL starting at `start` in `text`, leaving out any
+// punctuation which ends the sentence it's in.
+fn url_end(text: &str, start: usize) -> usize {
+    let url = &text[start..];
+    let mut url = &url[..url
+        .find(|c: char| c.is_whitespace() || c == '<' || c == '>' || c == '"')
+        .unwrap_or(url.len())];
+    loop {
+        let trimmed = url.trim_end_matches(|c| ".,;:!?'".contains(c));
+        let unbalanced =
+            trimmed.ends_with(')') && trimmed.matches(')').count() > trimmed.matches('(').count();
+        if unbalanced {
+            url = &trimmed[..trimmed.len() - 1];
+        } else {
+            url = trimmed;
+            break;
+        }
+    }
+    start + url.len()
+}
+
+fn push_escaped(html: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            _ => html.push(c),
+        }
+    }
+}
+
+/// Replace the character references in `text` with the characters.
+///
+/// Unknown named references are left as they are. As in browsers, the
+/// semicolon after a numeric reference may be left out.
+pub fn decode_entities(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        result.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        match decode_entity(&rest[1..]) {
+            Some((c, len)) => {
+                result.push(c);
+                rest = &rest[len + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// The character of the reference at the start of `text`, which follows
+// an `&`, and the length of the reference.
+fn decode_entity(text: &str) -> Option<(char, usize)> {
+    if let Some(number) = text.strip_prefix('#') {
+        let (digits, radix) = match number.strip_prefix(|c| c == 'x' || c == 'X') {
+            Some(hex) => (hex, 16),
+            None => (number, 10),
+        };
+        let len = digits
+            .find(|c: char| !c.is_digit(radix))
+            .unwrap_or(digits.len());
+        if len == 0 {
+            return None;
+        }
+        // Numbers too large for a u32 aren't characters either
+        let code = u32::from_str_radix(&digits[..len], radix).unwrap_or(u32::MAX);
+        let mut end = text.len() - digits.len() + len;
+        if text[end..].starts_with(';') {
+            end += 1;
+        }
+        return Some((core::char::from_u32(code).unwrap_or('\u{fffd}'), end));
+    }
+    let semicolon = text.find(';').filter(|&semicolon| semicolon <= 10)?;
+    ENTITIES
+        .iter()
+        .find(|&&(entity, _)| entity == &text[..semicolon])
+        .map(|&(_, c)| (c, semicolon + 1))
+}
+
+// The decoded value of the attribute `name` in `tag`, if it has one.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut search = 0;
+    while let Some(i) = lower[search..].find(name) {
+        let start = search + i;
+        search = start + name.len();
+        let before = lower[..start].chars().next_back();
+        if !before.is_some_and(char::is_whitespace) {
+            continue;
+        }
+        let after_equals = match tag[search..].trim_start().strip_prefix('=') {
+            Some(after_equals) => after_equals.trim_start(),
+            None => continue,
+        };
+        let value = match after_equals.chars().next() {
+            Some(q) if q == '"' || q == '\'' => {
+                let value = &after_equals[1..];
+                &value[..value.find(q).unwrap_or(value.len())]
+            }
+            _ => {
+                let end = after_equals
+                    .find(|c: char| c.is_whitespace() || c == '>')
+                    .unwrap_or(after_equals.len());
+                after_equals[..end].trim_end_matches('/')
+            }
+        };
+        return Some(decode_entities(value));
+    }
+    None
+}
+
+struct List {
+    ordered: bool,
+    count: usize,
+}
+
+#[derive(Default)]
+struct TextWriter {
+    out: String,
+    // Line breaks to write before the next text
+    breaks: usize,
+    // Whether to write a space before the next text on the same line
+    space: bool,
+    at_line_start: bool,
+    quote_depth: usize,
+    pre_depth: usize,
+    lists: Vec<List>,
+    // The address of the link being written, and where its text starts
+    link: Option<(String, usize)>,
+    footnotes: Vec<String>,
+}
+
+impl TextWriter {
+    fn element(&mut self, name: &str, tag: &str, closing: bool) {
+        // Lists within lists are only set apart by a line break
+        let nested_list = match name {
+            "ol" | "ul" => self.lists.len() > closing as usize,
+            _ => false,
+        };
+        if PARAGRAPHS.contains(&name) && !nested_list {
+            self.block(2);
+        } else if LINES.contains(&name) || nested_list {
+            self.block(1);
+        }
+
+        match (name, closing) {
+            ("br", _) if !self.out.is_empty() => self.breaks += 1,
+            ("hr", _) => {
+                self.block(2);
+                self.word("----");
+                self.block(2);
+            }
+            ("td", false) | ("th", false) => self.space = true,
+            ("blockquote", false) => self.quote_depth += 1,
+            ("blockquote", true) => self.quote_depth = self.quote_depth.saturating_sub(1),
+            ("pre", false) => self.pre_depth += 1,
+            ("pre", true) => self.pre_depth = self.pre_depth.saturating_sub(1),
+            ("ul", false) | ("ol", false) => self.lists.push(List {
+                ordered: name == "ol",
+                count: 0,
+            }),
+            ("ul", true) | ("ol", true) => {
+                self.lists.pop();
+            }
+            ("li", false) => {
+                let marker = match self.lists.last_mut() {
+                    Some(list) if list.ordered => {
+                        list.count += 1;
+                        format!("{}.", list.count)
+                    }
+                    _ => "*".to_string(),
+                };
+                self.word(&marker);
+                self.space = true;
+            }
+            ("img", false) => {
+                if let Some(alt) = attribute(tag, "alt") {
+                    self.text(&alt);
+                }
+            }
+            ("a", false) => {
+                self.link = attribute(tag, "href").map(|href| (href, self.out.len()));
+            }
+            ("a", true) => {
+                if let Some((href, start)) = self.link.take() {
+                    self.end_link(href, start);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn end_link(&mut self, href: String, start: usize) {
+        let href = href.trim();
+        let lower = href.to_ascii_lowercase();
+        if href.is_empty() || href.starts_with('#') || lower.starts_with("javascript:") {
+            return;
+        }
+        let text = self.out[start.min(self.out.len())..].trim();
+        if text == href || Some(text) == href.get(7..).filter(|_| lower.starts_with("mailto:")) {
+            return;
+        }
+        self.footnotes.push(href.to_string());
+        let footnote = format!("[{}]", self.footnotes.len());
+        self.space = false;
+        self.word(&footnote);
+    }
+
+    // Start a new line, with `breaks - 1` blank lines, before the next text
+    fn block(&mut self, breaks: usize) {
+        if !self.out.is_empty() {
+            self.breaks = self.breaks.max(breaks);
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        if self.pre_depth > 0 {
+            for (i, line) in text.split('\n').enumerate() {
+                if i > 0 {
+                    self.breaks += 1;
+                }
+                let line = line.trim_end_matches('\r');
+                if !line.is_empty() {
+                    self.space = false;
+                    self.word(line);
+                }
+            }
+            return;
+        }
+
+        // Only ASCII whitespace is collapsed, so no-break spaces are kept
+        let is_space = |c: char| c.is_ascii_whitespace();
+        if text.starts_with(is_space) {
+            self.space = true;
+        }
+        let words = text.split(is_space).filter(|word| !word.is_empty());
+        for (i, word) in words.enumerate() {
+            if i > 0 {
+                self.space = true;
+            }
+            self.word(word);
+        }
+        if text.ends_with(is_space) {
+            self.space = true;
+        }
+    }
+
+    fn word(&mut self, word: &str) {
+        if self.breaks > 0 {
+            if !self.out.is_empty() {
+                self.out.push('\n');
+                for _ in 1..self.breaks {
+                    self.out.push('\n');
+                }
+                self.at_line_start = true;
+            }
+            self.breaks = 0;
+        }
+        if self.out.is_empty() {
+            self.at_line_start = true;
+        }
+
+        if self.at_line_start {
+            for _ in 0..self.quote_depth {
+                self.out.push_str("> ");
+            }
+            for _ in 1..self.lists.len() {
+                self.out.push_str("  ");
+            }
+            self.at_line_start = false;
+        } else if self.space {
+            self.out.push(' ');
+        }
+        self.out.push_str(word);
+        self.space = false;
+    }
+
+    fn finish(self) -> String {
+        let mut result = self.out.trim_end().to_string();
+        if !self.footnotes.is_empty() {
+            result.push_str("\n\n");
+            for (i, href) in self.footnotes.iter().enumerate() {
+                result.push_str(&format!("[{}] {}\n", i + 1, href));
+            }
+        } else if !result.is_empty() {
+            result.push('\n');
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_html_to_text() {
+        let html = "<html><head><title>News</title><style>p { color: red }</style></head>\
+                    <body><h1>Hello&nbsp;there</h1>\n<p>Some   <b>bold</b>\n text, \
+                    a <a href=\"https://example.org/more\">link</a> and \
+                    <a href='https://example.org'>https://example.org</a>.<br>\
+                    Next line &amp; &#233;&#x20ac; &unknown;</p>\
+                    <script>alert('hi')</script><ul><li>One</li><li>Two<ol><li>A</li>\
+                    <li>B</li></ol></li></ul><!-- <p>hidden</p> -->\
+                    <blockquote><p>Quoted</p><p>Twice</p></blockquote>\
+                    <pre>  keep\n    spacing</pre><p>1 < 2 <img src=x.png alt=\"[logo]\"></p>\
+                    </body></html>";
+        assert_eq!(
+            html_to_text(html),
+            "Hello\u{a0}there\n\
+             \n\
+             Some bold text, a link[1] and https://example.org.\n\
+             Next line & é€ &unknown;\n\
+             \n\
+             * One\n\
+             * Two\n\
+             \x20 1. A\n\
+             \x20 2. B\n\
+             \n\
+             > Quoted\n\
+             \n\
+             > Twice\n\
+             \n\
+             \x20 keep\n\
+             \x20   spacing\n\
+             \n\
+             1 < 2 [logo]\n\
+             \n\
+             [1] https://example.org/more\n"
+        );
+        assert_eq!(html_to_text(""), "");
+        assert_eq!(html_to_text("<p>Line</p><p></p>"), "Line\n");
+    }
+
+    #[test]
+    fn test_text_to_html() {
+        let text = "Hi <Joe> & co,\r\n\
+                    see https://example.org/a_(b). Or www.example.org!\r\n\
+                    > You wrote:\r\n\
+                    > > Is \"mailto:joe@example.org\" right?\r\n\
+                    >> Yes\r\n\
+                    Thanks\r\n";
+        assert_eq!(
+            text_to_html(text),
+            "Hi &lt;Joe&gt; &amp; co,<br>\n\
+             see <a href=\"https://example.org/a_(b)\">https://example.org/a_(b)</a>. \
+             Or <a href=\"http://www.example.org\">www.example.org</a>!\n\
+             <blockquote>\n\
+             You wrote:\n\
+             <blockquote>\n\
+             Is &quot;<a href=\"mailto:joe@example.org\">mailto:joe@example.org</a>&quot; \
+             right?<br>\n\
+             Yes\n\
+             </blockquote>\n\
+             </blockquote>\n\
+             Thanks"
+        );
+        assert_eq!(
+            text_to_html("> Only a quote"),
+            "<blockquote>\nOnly a quote\n</blockquote>\n"
+        );
+        assert_eq!(
+            text_to_html("nothttp://x wwwx http://"),
+            "nothttp://x wwwx http://"
+        );
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(decode_entities("&lt;a&gt; &quot;b&quot;"), "<a> \"b\"");
+        assert_eq!(decode_entities("AT&T &#65;&#x42; &#xzz;"), "AT&T AB &#xzz;");
+        assert_eq!(decode_entities("&#1114112;"), "\u{fffd}");
+        assert_eq!(decode_entities("&#106avascript&colon;"), "javascript:");
+    }
+}
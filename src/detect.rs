@@ -0,0 +1,368 @@
+//! Module for inspecting the content of text parts, to find out the
+//! charset they are really in and the language they are written in.
+//!
+//! The charset a part declares is often wrong, or missing, in mail sent by
+//! older or careless software. Detection is a guess from the bytes alone:
+//! byte order marks and UTF-8 are recognized for certain, while legacy
+//! charsets are told apart by how plausible the text is once decoded with
+//! each of them. Languages are guessed from the script the text is in and,
+//! for Latin script, from its most common words.
+use super::charset;
+use super::message::MimeMessage;
+
+// Legacy charsets tried on text which isn't UTF-8, in order of preference
+// when they fit equally well
+static CANDIDATES: [&str; 10] = [
+    "windows-1252",
+    "windows-1250",
+    "windows-1251",
+    "koi8-r",
+    "windows-1253",
+    "shift_jis",
+    "euc-jp",
+    "gbk",
+    "big5",
+    "euc-kr",
+];
+
+// The most common words of languages written in Latin script
+static STOPWORDS: [(&str, &str); 8] = [
+    ("en", "the and of to is in that it you for"),
+    ("de", "der die und das ist nicht ich mit sie ein"),
+    ("fr", "le la les et est des une pas vous que"),
+    ("es", "el la los y es que de en por una"),
+    ("it", "il di che e la non per sono una gli"),
+    ("nl", "de het een en van ik niet dat is zijn"),
+    ("pt", "o a os que de não uma para com é"),
+    ("sv", "och det att i en är som jag inte på"),
+];
+
+/// What inspecting the content of a text part found, see
+/// `MimeMessage::part_info`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PartInfo {
+    declared_charset: Option<String>,
+    detected_charset: Option<&'static str>,
+    language: Option<&'static str>,
+}
+
+impl PartInfo {
+    /// The charset given by the Content-Type of the part.
+    pub fn declared_charset(&self) -> Option<&str> {
+        self.declared_charset.as_deref()
+    }
+
+    /// The charset the content appears to be in, if it could be told.
+    ///
+    /// Content which is all ASCII is detected as `US-ASCII`, which fits
+    /// any charset declared for it.
+    pub fn detected_charset(&self) -> Option<&str> {
+        self.detected_charset
+    }
+
+    /// Whether the detected charset differs from the declared one, taking
+    /// a missing charset as `us-ascii`.
+    pub fn charset_mismatch(&self) -> bool {
+        let detected = match self.detected_charset {
+            Some("US-ASCII") | None => return false,
+            Some(detected) => charset::lookup(detected),
+        };
+        match self.declared_charset.as_deref().and_then(charset::lookup) {
+            Some(declared) => Some(declared) != detected,
+            None => true,
+        }
+    }
+
+    /// The language the content appears to be written in, as a language
+    /// tag such as `en` or `ja`.
+    pub fn language(&self) -> Option<&str> {
+        self.language
+    }
+}
+
+impl MimeMessage {
+    /// Inspect the content of this part, if it's a `text/*` part whose
+    /// body is valid for its transfer encoding.
+    pub fn part_info(&self) -> Option<PartInfo> {
+        let content_type = self.content_type();
+        if content_type.ttype != "text" || !self.children.is_empty() {
+            return None;
+        }
+        let bytes = self.decoded_body()?;
+        let declared_charset = content_type.charset().map(str::to_string);
+        let detected_charset = detect_charset(&bytes);
+        let language = charset::decode(
+            &bytes,
+            detected_charset.or(declared_charset.as_deref()),
+            &Default::default(),
+        )
+        .ok()
+        .and_then(|text| detect_language(&text));
+        Some(PartInfo {
+            declared_charset,
+            detected_charset,
+            language,
+        })
+    }
+}
+
+/// The charset `bytes` appear to be in, or None if no charset fits.
+pub fn detect_charset(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\xEF\xBB\xBF") {
+        return Some("UTF-8");
+    } else if bytes.starts_with(b"\xFF\xFE") {
+        return Some("UTF-16LE");
+    } else if bytes.starts_with(b"\xFE\xFF") {
+        return Some("UTF-16BE");
+    }
+    // ISO-2022-JP is 7bit, and switches to Japanese with escape sequences
+    if [&b"\x1B$B"[..], b"\x1B$@", b"\x1B(J"]
+        .iter()
+        .any(|escape| bytes.windows(3).any(|w| w == *escape))
+    {
+        return Some("ISO-2022-JP");
+    }
+    if bytes.is_ascii() {
+        return Some("US-ASCII");
+    }
+    if core::str::from_utf8(bytes).is_ok() {
+        return Some("UTF-8");
+    }
+
+    let mut best: Option<(&'static str, i64)> = None;
+    for encoding in CANDIDATES.iter().filter_map(|label| charset::lookup(label)) {
+        let text = match encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+            Some(text) => text,
+            None => continue,
+        };
+        let score = plausibility(&text);
+        match best {
+            Some((_, best)) if best >= score => {}
+            _ => best = Some((encoding.name(), score)),
+        }
+    }
+    best.map(|(name, _)| name)
+}
+
+/// The language `text` appears to be written in, as a language tag, or
+/// None if it can't be told.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let mut counts = [0usize; 10];
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        counts[script(c) as usize] += 1;
+    }
+    // Japanese mixes kana with Han characters, which alone are Chinese
+    let kana = counts[Script::Kana as usize];
+    counts[Script::Han as usize] += kana;
+    counts[Script::Kana as usize] = 0;
+    let (dominant, &count) = counts.iter().enumerate().max_by_key(|&(_, count)| count)?;
+    if count == 0 {
+        return None;
+    }
+
+    match SCRIPTS[dominant] {
+        Script::Latin => latin_language(text),
+        Script::Cyrillic => {
+            let ukrainian = |c: char| "іїєґІЇЄҐ".contains(c);
+            Some(if text.chars().any(ukrainian) {
+                "uk"
+            } else {
+                "ru"
+            })
+        }
+        Script::Greek => Some("el"),
+        Script::Hebrew => Some("he"),
+        Script::Arabic => Some("ar"),
+        Script::Thai => Some("th"),
+        Script::Hangul => Some("ko"),
+        Script::Han if kana > 0 => Some("ja"),
+        Script::Han => Some("zh"),
+        Script::Kana | Script::Other => None,
+    }
+}
+
+// The language of `text` in Latin script, by which language's most common
+// words it has the most of.
+fn latin_language(text: &str) -> Option<&'static str> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let mut best: Option<(&'static str, usize)> = None;
+    for (language, stopwords) in STOPWORDS.iter() {
+        let count = words
+            .iter()
+            .filter(|word| stopwords.split(' ').any(|stopword| stopword == *word))
+            .count();
+        match best {
+            Some((_, best)) if best >= count => {}
+            _ if count >= 2 => best = Some((language, count)),
+            _ => {}
+        }
+    }
+    best.map(|(language, _)| language)
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Hebrew,
+    Arabic,
+    Thai,
+    Hangul,
+    Kana,
+    Han,
+    Other,
+}
+
+static SCRIPTS: [Script; 10] = [
+    Script::Latin,
+    Script::Greek,
+    Script::Cyrillic,
+    Script::Hebrew,
+    Script::Arabic,
+    Script::Thai,
+    Script::Hangul,
+    Script::Kana,
+    Script::Han,
+    Script::Other,
+];
+
+fn script(c: char) -> Script {
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{C0}'..='\u{24F}' => Script::Latin,
+        '\u{370}'..='\u{3FF}' => Script::Greek,
+        '\u{400}'..='\u{4FF}' => Script::Cyrillic,
+        '\u{590}'..='\u{5FF}' => Script::Hebrew,
+        '\u{600}'..='\u{6FF}' => Script::Arabic,
+        '\u{E00}'..='\u{E7F}' => Script::Thai,
+        '\u{1100}'..='\u{11FF}' | '\u{3130}'..='\u{318F}' | '\u{AC00}'..='\u{D7AF}' => {
+            Script::Hangul
+        }
+        '\u{3040}'..='\u{30FF}' => Script::Kana,
+        '\u{3400}'..='\u{4DBF}' | '\u{4E00}'..='\u{9FFF}' => Script::Han,
+        _ => Script::Other,
+    }
+}
+
+// How plausible `text` is as the decoding of some bytes: higher for more
+// letters, lower for controls, symbols and letters which are unlikely to
+// follow each other.
+fn plausibility(text: &str) -> i64 {
+    let mut score = 0;
+    let mut previous: Option<char> = None;
+    for c in text.chars() {
+        if !c.is_ascii() {
+            score += match script(c) {
+                // These take two bytes in their legacy charsets
+                Script::Hangul | Script::Kana | Script::Han => 2,
+                _ if c.is_alphabetic() => 1,
+                // Punctuation of Chinese and Japanese, and full-width forms
+                _ if ('\u{3000}'..='\u{303F}').contains(&c)
+                    || ('\u{FF00}'..='\u{FFEF}').contains(&c) =>
+                {
+                    0
+                }
+                _ if c.is_control() => -10,
+                _ => -1,
+            };
+        }
+        if let Some(p) = previous.filter(|p| !p.is_ascii() || !c.is_ascii()) {
+            let (ps, cs) = (script(p), script(c));
+            if p.is_alphabetic() && c.is_alphabetic() {
+                let cjk = |s| matches!(s, Script::Kana | Script::Han);
+                if ps != cs && !(cjk(ps) && cjk(cs)) {
+                    score -= 2;
+                } else if ps == Script::Latin && !p.is_ascii() && !c.is_ascii() {
+                    score -= 1;
+                }
+                if p.is_lowercase() && c.is_uppercase() {
+                    score -= 2;
+                }
+            }
+        }
+        previous = Some(c);
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_charset() {
+        let russian = b"\xcf\xf0\xe8\xe2\xe5\xf2, \xea\xe0\xea \xe4\xe5\xeb\xe0? \
+                        \xdd\xf2\xee \xf2\xe5\xf1\xf2.";
+        assert_eq!(detect_charset(russian), Some("windows-1251"));
+        let russian = b"\xf0\xd2\xc9\xd7\xc5\xd4, \xcb\xc1\xcb \xc4\xc5\xcc\xc1? \
+                        \xfc\xd4\xcf \xd4\xc5\xd3\xd4.";
+        assert_eq!(detect_charset(russian), Some("KOI8-R"));
+        assert_eq!(
+            detect_charset(b"Gr\xfc\xdfe aus M\xfcnchen"),
+            Some("windows-1252")
+        );
+        assert_eq!(detect_charset("Grüße".as_bytes()), Some("UTF-8"));
+        assert_eq!(detect_charset(b"\xFF\xFEH\x00i\x00"), Some("UTF-16LE"));
+        assert_eq!(
+            detect_charset(b"\x1b$B$3$s$K$A$O\x1b(B"),
+            Some("ISO-2022-JP")
+        );
+        assert_eq!(detect_charset(b"Hello"), Some("US-ASCII"));
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let texts = [
+            ("The cat is in the garden, and it is asleep.", Some("en")),
+            ("Die Katze ist nicht im Garten, sie schläft.", Some("de")),
+            ("Le chat est dans la maison, et il dort.", Some("fr")),
+            ("Привет, как дела?", Some("ru")),
+            ("Привіт, як справи? Є питання.", Some("uk")),
+            ("Καλημέρα", Some("el")),
+            ("こんにちは、世界", Some("ja")),
+            ("你好，世界", Some("zh")),
+            ("안녕하세요", Some("ko")),
+            ("Lunch", None),
+            ("1234", None),
+        ];
+        for &(text, language) in texts.iter() {
+            assert_eq!(detect_language(text), language, "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_part_info() {
+        let message = MimeMessage::parse(
+            "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\n\
+             Content-Type: text/plain; charset=iso-8859-1\r\n\r\n\
+             Die Straße ist nicht weit, sie ist gleich um die Ecke.\r\n\
+             --b\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\r\n\
+             The meeting is at noon, and lunch is after that.\r\n\
+             --b\r\n\
+             Content-Type: application/pdf\r\n\r\n\
+             %PDF\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+        assert_eq!(message.part_info(), None);
+
+        let info = message.children[0].part_info().unwrap();
+        assert_eq!(info.declared_charset(), Some("iso-8859-1"));
+        assert_eq!(info.detected_charset(), Some("UTF-8"));
+        assert!(info.charset_mismatch());
+        assert_eq!(info.language(), Some("de"));
+
+        let info = message.children[1].part_info().unwrap();
+        assert_eq!(info.detected_charset(), Some("US-ASCII"));
+        assert!(!info.charset_mismatch());
+        assert_eq!(info.language(), Some("en"));
+
+        assert_eq!(message.children[2].part_info(), None);
+    }
+}
@@ -0,0 +1,290 @@
+//! Module for comparing two messages, such as a message before and after
+//! it has been through some processing.
+//!
+//! `diff` walks the two messages' trees of parts side by side, and lists the
+//! headers, parts and bodies which differ between them.
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+use super::message::MimeMessage;
+use super::walk::PartPath;
+
+/// A change from one message to another, as found by `diff`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Change {
+    /// A header only in the second message
+    HeaderAdded {
+        path: PartPath,
+        name: String,
+        value: String,
+    },
+    /// A header only in the first message
+    HeaderRemoved {
+        path: PartPath,
+        name: String,
+        value: String,
+    },
+    /// A header whose value differs
+    HeaderChanged {
+        path: PartPath,
+        name: String,
+        before: String,
+        after: String,
+    },
+    /// A part only in the second message, at its path there
+    PartAdded { path: PartPath },
+    /// A part only in the first message, at its path there
+    PartRemoved { path: PartPath },
+    /// A body whose content differs.
+    ///
+    /// The ranges are of the decoded content of each, and cover everything
+    /// between the bytes the two have in common at their start and end.
+    BodyChanged {
+        path: PartPath,
+        before: Range<usize>,
+        after: Range<usize>,
+    },
+    /// A body with the same content, but transfer encoded differently
+    BodyReencoded { path: PartPath },
+}
+
+impl Change {
+    /// The path of the part the change is in.
+    pub fn path(&self) -> &PartPath {
+        match *self {
+            Change::HeaderAdded { ref path, .. }
+            | Change::HeaderRemoved { ref path, .. }
+            | Change::HeaderChanged { ref path, .. }
+            | Change::PartAdded { ref path }
+            | Change::PartRemoved { ref path }
+            | Change::BodyChanged { ref path, .. }
+            | Change::BodyReencoded { ref path } => path,
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path();
+        if path.is_root() {
+            write!(f, "message: ")?;
+        } else {
+            write!(f, "part {}: ", path)?;
+        }
+        match *self {
+            Change::HeaderAdded { ref name, .. } => write!(f, "{} header added", name),
+            Change::HeaderRemoved { ref name, .. } => write!(f, "{} header removed", name),
+            Change::HeaderChanged {
+                ref name,
+                ref before,
+                ref after,
+                ..
+            } => write!(
+                f,
+                "{} header changed from {:?} to {:?}",
+                name, before, after
+            ),
+            Change::PartAdded { .. } => write!(f, "part added"),
+            Change::PartRemoved { .. } => write!(f, "part removed"),
+            Change::BodyChanged {
+                ref before,
+                ref after,
+                ..
+            } => write!(
+                f,
+                "body changed at bytes {}..{}, now {}..{}",
+                before.start, before.end, after.start, after.end
+            ),
+            Change::BodyReencoded { .. } => write!(f, "body reencoded"),
+        }
+    }
+}
+
+/// The changes from message `a` to message `b`, part by part.
+///
+/// Parts are matched by their position, and headers by their name and
+/// position among those of the same name, so reordering headers of
+/// different names isn't a change. Bodies are compared once decoded, with
+/// a body that can't be decoded taken as it is.
+pub fn diff(a: &MimeMessage, b: &MimeMessage) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_parts(&PartPath::root(), a, b, &mut changes);
+    changes
+}
+
+fn diff_parts(path: &PartPath, a: &MimeMessage, b: &MimeMessage, changes: &mut Vec<Change>) {
+    diff_headers(path, a, b, changes);
+
+    if a.body.as_str() != b.body.as_str() {
+        let (before, after) = (content(a), content(b));
+        if before == after {
+            changes.push(Change::BodyReencoded { path: path.clone() });
+        } else {
+            let start = before
+                .iter()
+                .zip(after.iter())
+                .take_while(|(x, y)| x == y)
+                .count();
+            let end = before[start..]
+                .iter()
+                .rev()
+                .zip(after[start..].iter().rev())
+                .take_while(|(x, y)| x == y)
+                .count();
+            changes.push(Change::BodyChanged {
+                path: path.clone(),
+                before: start..before.len() - end,
+                after: start..after.len() - end,
+            });
+        }
+    }
+
+    for index in 0..a.children.len().max(b.children.len()) {
+        let child = path.child(index);
+        match (a.children.get(index), b.children.get(index)) {
+            (Some(a), Some(b)) => diff_parts(&child, a, b, changes),
+            (Some(_), None) => changes.push(Change::PartRemoved { path: child }),
+            (None, Some(_)) => changes.push(Change::PartAdded { path: child }),
+            (None, None) => {}
+        }
+    }
+}
+
+// The decoded body of `part`, or its raw body if that can't be decoded.
+fn content(part: &MimeMessage) -> Cow<'_, [u8]> {
+    part.decoded_body()
+        .unwrap_or_else(|| Cow::Borrowed(part.body.as_bytes()))
+}
+
+fn diff_headers(path: &PartPath, a: &MimeMessage, b: &MimeMessage, changes: &mut Vec<Change>) {
+    // The headers of `b` matched so far
+    let mut matched = vec![false; b.headers.len()];
+    for (index, header) in a.headers.iter().enumerate() {
+        // Which occurrence of its name this header is
+        let nth = a
+            .headers
+            .iter()
+            .take(index)
+            .filter(|h| h.name.eq_ignore_ascii_case(&header.name))
+            .count();
+        let found = b
+            .headers
+            .iter()
+            .enumerate()
+            .filter(|(_, h)| h.name.eq_ignore_ascii_case(&header.name))
+            .nth(nth);
+        match found {
+            Some((i, other)) => {
+                matched[i] = true;
+                if header.raw_value() != other.raw_value() {
+                    changes.push(Change::HeaderChanged {
+                        path: path.clone(),
+                        name: header.name.to_string(),
+                        before: header.raw_value().to_string(),
+                        after: other.raw_value().to_string(),
+                    });
+                }
+            }
+            None => changes.push(Change::HeaderRemoved {
+                path: path.clone(),
+                name: header.name.to_string(),
+                value: header.raw_value().to_string(),
+            }),
+        }
+    }
+    for (header, _) in b.headers.iter().zip(matched).filter(|(_, m)| !m) {
+        changes.push(Change::HeaderAdded {
+            path: path.clone(),
+            name: header.name.to_string(),
+            value: header.raw_value().to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::header::Header;
+
+    #[test]
+    fn test_diff() {
+        let a = MimeMessage::parse(
+            "Subject: Hi\r\n\
+             Received: from a by b; Fri, 1 Mar 2024 12:30:00 +0100\r\n\
+             X-Spam: no\r\n\
+             Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Hello there\r\n\
+             --b\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Bye\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+        assert_eq!(diff(&a, &a), vec![]);
+
+        let mut b = a.clone();
+        b.headers
+            .replace(Header::new("Subject".to_string(), "Re: Hi".to_string()));
+        b.headers.remove_all("X-Spam");
+        b.headers.insert(Header::new(
+            "Received".to_string(),
+            "from c by d; Fri, 1 Mar 2024 12:45:00 +0100".to_string(),
+        ));
+        b.children[0].body = "Hello here\r\n".to_string().into();
+        b.children.pop();
+
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                Change::HeaderChanged {
+                    path: PartPath::root(),
+                    name: "Subject".to_string(),
+                    before: "Hi".to_string(),
+                    after: "Re: Hi".to_string(),
+                },
+                Change::HeaderRemoved {
+                    path: PartPath::root(),
+                    name: "X-Spam".to_string(),
+                    value: "no".to_string(),
+                },
+                Change::HeaderAdded {
+                    path: PartPath::root(),
+                    name: "Received".to_string(),
+                    value: "from c by d; Fri, 1 Mar 2024 12:45:00 +0100".to_string(),
+                },
+                Change::BodyChanged {
+                    path: "1".parse().unwrap(),
+                    before: 6..7,
+                    after: 6..6,
+                },
+                Change::PartRemoved {
+                    path: "2".parse().unwrap(),
+                },
+            ]
+        );
+        let changes: Vec<String> = diff(&b, &a).iter().map(|c| c.to_string()).collect();
+        assert_eq!(changes[3], "part 1: body changed at bytes 6..6, now 6..7");
+        assert_eq!(changes[4], "part 2: part added");
+    }
+
+    #[test]
+    fn test_diff_reencoded() {
+        let a = MimeMessage::parse("Subject: Hi\r\n\r\nCaf\u{e9}\r\n").unwrap();
+        let mut b = a.clone();
+        b.headers.insert(Header::new(
+            "Content-Transfer-Encoding".to_string(),
+            "quoted-printable".to_string(),
+        ));
+        b.body = "Caf=C3=A9\r\n".to_string().into();
+        assert_eq!(
+            diff(&a, &b)[1],
+            Change::BodyReencoded {
+                path: PartPath::root()
+            }
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Module for digests, which send many messages as one.
+//!
+//! A digest is a `multipart/digest` part, as mailing lists send to readers
+//! who ask for one message a day. Each of its parts is a `message/rfc822`
+//! by default, RFC 2046 Section 5.1.5, so needs no header of its own.
+use std::slice;
+
+use super::message::{MimeMessage, MimeMultipartType};
+use super::walk::PartsDepthFirst;
+
+/// Builder for a `multipart/digest` part holding several messages.
+///
+/// The digest can be sent as it is, or used as the body of a message with
+/// `MessageBuilder::body`, or attached after an introduction with
+/// `MessageBuilder::attach`.
+#[derive(Debug, Clone, Default)]
+pub struct DigestBuilder {
+    messages: Vec<MimeMessage>,
+    boundary_prefix: String,
+}
+
+impl DigestBuilder {
+    pub fn new() -> DigestBuilder {
+        DigestBuilder::default()
+    }
+
+    /// Add a message, after those already added.
+    pub fn message(mut self, message: MimeMessage) -> DigestBuilder {
+        self.messages.push(message);
+        self
+    }
+
+    /// Start the boundary of the digest with `prefix`, which is followed
+    /// by random characters.
+    pub fn boundary_prefix(mut self, prefix: &str) -> DigestBuilder {
+        self.boundary_prefix = prefix.to_string();
+        self
+    }
+
+    /// Build the `multipart/digest` part.
+    pub fn build(self) -> MimeMessage {
+        let children = self
+            .messages
+            .iter()
+            .map(|message| {
+                let mut part = MimeMessage::new_blank_message();
+                part.body = message.to_bytes().into();
+                part
+            })
+            .collect();
+        MimeMessage::new_with_boundary(
+            "".to_string(),
+            MimeMultipartType::Digest,
+            children,
+            MimeMessage::generate_boundary(&self.boundary_prefix[..]),
+        )
+    }
+}
+
+/// Iterator over the messages in the digests of a message.
+///
+/// Returned by `MimeMessage::digest_messages`.
+pub struct DigestMessages<'a> {
+    parts: PartsDepthFirst<'a>,
+    digest: slice::Iter<'a, MimeMessage>,
+}
+
+impl<'a> Iterator for DigestMessages<'a> {
+    type Item = MimeMessage;
+
+    fn next(&mut self) -> Option<MimeMessage> {
+        loop {
+            if let Some(message) = self.digest.by_ref().find_map(embedded_message) {
+                return Some(message);
+            }
+            let (_, digest) = self
+                .parts
+                .find(|(_, part)| part.message_type == Some(MimeMultipartType::Digest))?;
+            self.digest = digest.children.iter();
+        }
+    }
+}
+
+impl MimeMessage {
+    /// Iterate over the messages in each `multipart/digest` part of this
+    /// message, in the order they appear.
+    ///
+    /// Parts of a digest which aren't messages, such as a table of
+    /// contents, are skipped, as are messages which can't be parsed.
+    pub fn digest_messages(&self) -> DigestMessages<'_> {
+        DigestMessages {
+            parts: self.parts_depth_first(),
+            digest: [].iter(),
+        }
+    }
+}
+
+// The message held by a part of a digest, which is `message/rfc822` unless
+// it has a `Content-Type` saying otherwise.
+fn embedded_message(part: &MimeMessage) -> Option<MimeMessage> {
+    let content_type = part.effective_content_type();
+    if content_type.ttype != "message" || content_type.subtype != "rfc822" {
+        return None;
+    }
+    let body = part.decoded_body_bytes()?;
+    MimeMessage::parse_bytes(&body[..]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::Header;
+
+    fn message(subject: &str, body: &str) -> MimeMessage {
+        let mut message = MimeMessage::new_blank_message();
+        message.body = body.into();
+        message
+            .headers
+            .insert(Header::new("Subject".to_string(), subject.to_string()));
+        message
+    }
+
+    #[test]
+    fn test_build_digest() {
+        let digest = DigestBuilder::new()
+            .message(message("First", "One"))
+            .message(message("Second", "Two"))
+            .boundary_prefix("=_digest_")
+            .build();
+        assert_eq!(digest.message_type, Some(MimeMultipartType::Digest));
+        assert!(digest.boundary.starts_with("=_digest_"));
+        assert!(digest.children[0]
+            .headers
+            .get("Content-Type".to_string())
+            .is_none());
+
+        let parsed = MimeMessage::parse(&digest.as_string()).unwrap();
+        let messages: Vec<MimeMessage> = parsed.digest_messages().collect();
+        assert_eq!(messages.len(), 2);
+        let subject: String = messages[1]
+            .headers
+            .get_value("Subject".to_string())
+            .unwrap();
+        assert_eq!(subject, "Second");
+        assert_eq!(messages[1].body.trim_end(), "Two");
+    }
+
+    #[test]
+    fn test_digest_messages() {
+        let message = MimeMessage::parse(
+            "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Today's topics\r\n\
+             --b\r\n\
+             Content-Type: multipart/digest; boundary=d\r\n\r\n\
+             --d\r\n\
+             \r\n\
+             Subject: One\r\n\r\n\
+             First\r\n\
+             --d\r\n\
+             Content-Type: message/rfc822\r\n\r\n\
+             Subject: Two\r\n\r\n\
+             Second\r\n\
+             --d\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Not a message\r\n\
+             --d--\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+        let subjects: Vec<String> = message
+            .digest_messages()
+            .map(|message| message.headers.get_value("Subject".to_string()).unwrap())
+            .collect();
+        assert_eq!(subjects, vec!["One".to_string(), "Two".to_string()]);
+
+        let plain = MimeMessage::parse("Subject: Hi\r\n\r\nHi").unwrap();
+        assert_eq!(plain.digest_messages().count(), 0);
+    }
+}
@@ -0,0 +1,266 @@
+//! Module for checking whether the domains which passed SPF and DKIM are
+//! aligned with the domain of the authors of a message, as DMARC requires,
+//! RFC 7489 Section 3.1.
+//!
+//! Relaxed alignment only needs the domains to share an organizational
+//! domain, which is found with a `PublicSuffixProvider`. The crate doesn't
+//! ship the Public Suffix List itself, so `SimplePublicSuffix` only knows
+//! the few top-level domains, such as `com`, under which names are
+//! registered directly. Where the public suffix of a domain isn't known,
+//! relaxed alignment falls back to strict, rather than taking domains such
+//! as `bank.co.uk` and `attacker.co.uk` to be aligned; filters should plug
+//! in the full list to get relaxed alignment for other domains.
+use super::message::MimeMessage;
+
+/// How closely the domains must match, from the `aspf` and `adkim` tags of
+/// a DMARC record.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum AlignmentMode {
+    /// The domains must share an organizational domain
+    #[default]
+    Relaxed,
+    /// The domains must be the same
+    Strict,
+}
+
+/// A source of public suffixes, the domains under which anyone may
+/// register a name, such as `com` or `co.uk`.
+pub trait PublicSuffixProvider {
+    /// The public suffix of `domain`, which is lowercased, or None if it
+    /// isn't known.
+    fn public_suffix<'d>(&self, domain: &'d str) -> Option<&'d str>;
+}
+
+// The top-level domains which are public suffixes with no others under
+// them in the Public Suffix List
+const SINGLE_LABEL_SUFFIXES: &[&str] = &[
+    "biz", "com", "edu", "gov", "info", "int", "mil", "net", "org",
+];
+
+/// A `PublicSuffixProvider` which only knows the public suffixes of
+/// domains under the generic top-level domains, such as `com` and `org`,
+/// which have no other public suffixes under them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimplePublicSuffix;
+
+impl PublicSuffixProvider for SimplePublicSuffix {
+    fn public_suffix<'d>(&self, domain: &'d str) -> Option<&'d str> {
+        let label = domain.rsplit('.').next()?;
+        Some(label).filter(|label| SINGLE_LABEL_SUFFIXES.contains(label))
+    }
+}
+
+/// Which of the SPF and DKIM domains given to `evaluate_alignment` are
+/// aligned with the author domain.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Alignment {
+    pub spf: bool,
+    /// The first of the DKIM domains which is aligned
+    pub dkim: Option<String>,
+}
+
+impl Alignment {
+    /// Whether either SPF or DKIM is aligned, which is all DMARC needs to
+    /// pass.
+    pub fn is_aligned(&self) -> bool {
+        self.spf || self.dkim.is_some()
+    }
+}
+
+// `domain` lowercased, without a trailing dot
+fn normalize(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// The organizational domain of `domain`: its public suffix along with the
+/// label before it, RFC 7489 Section 3.2. A domain which is itself a
+/// public suffix is its own organizational domain.
+///
+/// Returns None if `suffixes` doesn't know the public suffix of `domain`,
+/// or gives one which isn't a suffix of it.
+pub fn organizational_domain(domain: &str, suffixes: &dyn PublicSuffixProvider) -> Option<String> {
+    let domain = normalize(domain);
+    let suffix = suffixes.public_suffix(&domain)?;
+    if suffix.is_empty() || !domain.ends_with(suffix) {
+        return None;
+    } else if suffix.len() == domain.len() {
+        return Some(domain);
+    }
+    let rest = domain[..domain.len() - suffix.len()].strip_suffix('.')?;
+    let label_start = rest.rfind('.').map_or(0, |i| i + 1);
+    Some(domain[label_start..].to_string())
+}
+
+/// Check the alignment of the author domain `from_domain`, as given by
+/// `MimeMessage::author_domain`, with `spf_domain`, the domain which SPF
+/// passed for, and `dkim_domains`, the `d=` domains of the DKIM signatures
+/// which verified. Only domains which passed should be given.
+///
+/// Relaxed alignment of two domains is strict unless the organizational
+/// domains of both are known.
+pub fn evaluate_alignment(
+    from_domain: &str,
+    spf_domain: Option<&str>,
+    dkim_domains: &[&str],
+    mode: AlignmentMode,
+    suffixes: &dyn PublicSuffixProvider,
+) -> Alignment {
+    let from_domain = normalize(from_domain);
+    let from_org = match mode {
+        AlignmentMode::Strict => None,
+        AlignmentMode::Relaxed => organizational_domain(&from_domain, suffixes),
+    };
+    let is_aligned = |domain: &str| {
+        let org = from_org
+            .as_ref()
+            .and_then(|_| organizational_domain(domain, suffixes));
+        match (&from_org, org) {
+            (Some(from_org), Some(org)) => org == *from_org,
+            _ => normalize(domain) == from_domain,
+        }
+    };
+    Alignment {
+        spf: spf_domain.is_some_and(is_aligned),
+        dkim: dkim_domains
+            .iter()
+            .find(|domain| is_aligned(domain))
+            .map(|domain| normalize(domain)),
+    }
+}
+
+impl MimeMessage {
+    /// Check the alignment of the SPF and DKIM domains which passed with
+    /// the domain of this message's authors, as `evaluate_alignment` does.
+    ///
+    /// This is None if the authors don't share a single domain, which
+    /// DMARC can't be applied to.
+    pub fn dmarc_alignment(
+        &self,
+        spf_domain: Option<&str>,
+        dkim_domains: &[&str],
+        mode: AlignmentMode,
+        suffixes: &dyn PublicSuffixProvider,
+    ) -> Option<Alignment> {
+        let from_domain = self.author_domain()?;
+        Some(evaluate_alignment(
+            &from_domain,
+            spf_domain,
+            dkim_domains,
+            mode,
+            suffixes,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Knows that `co.uk` is a public suffix
+    struct UkSuffixes;
+
+    // Gives `org` as the suffix of every domain, even those it isn't a
+    // suffix of
+    struct BrokenSuffixes;
+
+    impl PublicSuffixProvider for BrokenSuffixes {
+        fn public_suffix<'d>(&self, _: &'d str) -> Option<&'d str> {
+            Some("org")
+        }
+    }
+
+    impl PublicSuffixProvider for UkSuffixes {
+        fn public_suffix<'d>(&self, domain: &'d str) -> Option<&'d str> {
+            if domain.ends_with(".co.uk") {
+                Some(&domain[domain.len() - 5..])
+            } else {
+                domain.rsplit('.').next()
+            }
+        }
+    }
+
+    #[test]
+    fn test_organizational_domain() {
+        let tests = [
+            ("mail.Example.org.", "example.org"),
+            ("example.org", "example.org"),
+            ("org", "org"),
+            ("a.b.example.co.uk", "example.co.uk"),
+        ];
+        for &(domain, expected) in tests.iter() {
+            assert_eq!(
+                organizational_domain(domain, &UkSuffixes).as_deref(),
+                Some(expected),
+                "{}",
+                domain
+            );
+        }
+        let org = organizational_domain("mail.example.org", &SimplePublicSuffix);
+        assert_eq!(org.as_deref(), Some("example.org"));
+        let org = organizational_domain("a.b.example.co.uk", &SimplePublicSuffix);
+        assert_eq!(org, None);
+        assert_eq!(organizational_domain("xorg", &BrokenSuffixes), None);
+        assert_eq!(organizational_domain("or", &BrokenSuffixes), None);
+    }
+
+    #[test]
+    fn test_evaluate_alignment() {
+        let alignment = evaluate_alignment(
+            "example.org",
+            Some("bounces.example.org"),
+            &["other.net", "Mail.Example.org"],
+            AlignmentMode::Relaxed,
+            &SimplePublicSuffix,
+        );
+        assert!(alignment.spf);
+        assert_eq!(alignment.dkim, Some("mail.example.org".to_string()));
+
+        let alignment = evaluate_alignment(
+            "example.org",
+            Some("bounces.example.org"),
+            &["mail.example.org", "example.org"],
+            AlignmentMode::Strict,
+            &SimplePublicSuffix,
+        );
+        assert!(!alignment.spf);
+        assert_eq!(alignment.dkim, Some("example.org".to_string()));
+
+        let alignment = evaluate_alignment(
+            "shop.example.co.uk",
+            Some("other.co.uk"),
+            &[],
+            AlignmentMode::Relaxed,
+            &UkSuffixes,
+        );
+        assert!(!alignment.is_aligned());
+
+        // Without knowing that `co.uk` is a public suffix, only the same
+        // domain is aligned
+        let alignment = evaluate_alignment(
+            "bank.co.uk",
+            Some("attacker.co.uk"),
+            &["mail.bank.co.uk", "Bank.co.uk"],
+            AlignmentMode::Relaxed,
+            &SimplePublicSuffix,
+        );
+        assert!(!alignment.spf);
+        assert_eq!(alignment.dkim, Some("bank.co.uk".to_string()));
+    }
+
+    #[test]
+    fn test_dmarc_alignment() {
+        let message = MimeMessage::parse("From: Joe <joe@news.example.org>\r\n\r\n").unwrap();
+        let alignment = message.dmarc_alignment(
+            Some("example.org"),
+            &[],
+            AlignmentMode::default(),
+            &SimplePublicSuffix,
+        );
+        assert!(alignment.unwrap().spf);
+
+        let message = MimeMessage::parse("From: a@example.org, b@example.net\r\n\r\n").unwrap();
+        let suffixes = SimplePublicSuffix;
+        let alignment = message.dmarc_alignment(None, &[], AlignmentMode::Strict, &suffixes);
+        assert_eq!(alignment, None);
+    }
+}
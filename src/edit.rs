@@ -0,0 +1,208 @@
+//! Module for editing a parsed message while recording the changes made.
+//!
+//! A mail filter, such as a milter, has to tell the MTA what to change in a
+//! message rather than handing back a whole new message. A `MessageEditor`
+//! applies each edit to its message and records it as a `Change`, so the
+//! changes can be sent on in the order they were made.
+use super::header::Header;
+use super::message::MimeMessage;
+
+/// A change made to a message by a `MessageEditor`.
+///
+/// Each change applies to the message as it was left by the ones before.
+/// Header values are as they'd be written in the message, so may be RFC
+/// 2047 encoded.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Change {
+    /// A header was added after all of the others
+    AddHeader { name: String, value: String },
+    /// A header was inserted at `index` among all of the headers, counting
+    /// from 0
+    InsertHeader {
+        index: usize,
+        name: String,
+        value: String,
+    },
+    /// The `index`th header with `name`, counting from 1 as milters do, was
+    /// given `value`
+    ChangeHeader {
+        name: String,
+        index: usize,
+        value: String,
+    },
+    /// The `index`th header with `name`, counting from 1, was removed
+    RemoveHeader { name: String, index: usize },
+    /// The body, including any parts, was replaced with this
+    ReplaceBody(String),
+}
+
+/// Edits a message, recording each edit as a `Change`.
+#[derive(Debug, Clone)]
+pub struct MessageEditor {
+    message: MimeMessage,
+    changes: Vec<Change>,
+}
+
+impl MessageEditor {
+    pub fn new(message: MimeMessage) -> MessageEditor {
+        MessageEditor {
+            message,
+            changes: Vec::new(),
+        }
+    }
+
+    /// The message, with the edits made so far.
+    pub fn message(&self) -> &MimeMessage {
+        &self.message
+    }
+
+    /// The changes made so far, in order.
+    pub fn changes(&self) -> &[Change] {
+        &self.changes[..]
+    }
+
+    /// The edited message and the changes made to it.
+    pub fn into_parts(self) -> (MimeMessage, Vec<Change>) {
+        (self.message, self.changes)
+    }
+
+    /// Add `header` after all of the others.
+    pub fn add_header(&mut self, header: Header) {
+        self.changes.push(Change::AddHeader {
+            name: header.name.as_str().to_string(),
+            value: header.raw_value().to_string(),
+        });
+        self.message.headers.insert(header);
+    }
+
+    /// Insert `header` at `index` among all of the headers, counting from
+    /// 0, or after all of them if there are fewer.
+    pub fn insert_header(&mut self, index: usize, header: Header) {
+        let index = index.min(self.message.headers.len());
+        self.changes.push(Change::InsertHeader {
+            index,
+            name: header.name.as_str().to_string(),
+            value: header.raw_value().to_string(),
+        });
+        self.message.headers.insert_at(index, header);
+    }
+
+    /// Give the `n`th header with `name`, counting from 0, the new `value`.
+    ///
+    /// Returns false, making no change, if there are fewer headers with the
+    /// name.
+    pub fn change_header(&mut self, name: &str, n: usize, value: String) -> bool {
+        // Keep the name as it's written in the message
+        let existing = match self.message.headers.find(name) {
+            Some(headers) if n < headers.len() => headers[n].name.as_str().to_string(),
+            _ => return false,
+        };
+        let header = Header::new(existing, value);
+        self.changes.push(Change::ChangeHeader {
+            name: name.to_string(),
+            index: n + 1,
+            value: header.raw_value().to_string(),
+        });
+        self.message.headers.replace_nth(name, n, header);
+        true
+    }
+
+    /// Remove the `n`th header with `name`, counting from 0.
+    ///
+    /// Returns false, making no change, if there are fewer headers with the
+    /// name.
+    pub fn remove_header(&mut self, name: &str, n: usize) -> bool {
+        if self.message.headers.remove_nth(name, n).is_none() {
+            return false;
+        }
+        self.changes.push(Change::RemoveHeader {
+            name: name.to_string(),
+            index: n + 1,
+        });
+        true
+    }
+
+    /// Replace the body of the message with `body`, as it's to be written in
+    /// the message, including any parts of a multipart.
+    pub fn replace_body(&mut self, body: &str) {
+        self.changes.push(Change::ReplaceBody(body.to_string()));
+        self.message.body = body.into();
+        self.message.children.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_editor() {
+        let message = MimeMessage::parse(
+            "Received: from a\r\n\
+             Received: from b\r\n\
+             Subject: Hello\r\n\
+             \r\n\
+             Body\r\n",
+        )
+        .unwrap();
+        let mut editor = MessageEditor::new(message);
+        editor.add_header(Header::new("X-Spam".to_string(), "no".to_string()));
+        editor.insert_header(0, Header::new("Received".to_string(), "from c".to_string()));
+        assert!(editor.change_header("subject", 0, "[Ext] Hello".to_string()));
+        assert!(editor.remove_header("received", 2));
+        assert!(!editor.remove_header("Received", 2));
+        assert!(!editor.change_header("To", 0, "joe@example.org".to_string()));
+        editor.replace_body("New body\r\n");
+
+        assert_eq!(
+            editor.message().as_string(),
+            "Received: from c\r\n\
+             Received: from a\r\n\
+             Subject: [Ext] Hello\r\n\
+             X-Spam: no\r\n\
+             \r\n\
+             New body\r\n\r\n"
+        );
+        let (_, changes) = editor.into_parts();
+        assert_eq!(
+            changes,
+            vec![
+                Change::AddHeader {
+                    name: "X-Spam".to_string(),
+                    value: "no".to_string(),
+                },
+                Change::InsertHeader {
+                    index: 0,
+                    name: "Received".to_string(),
+                    value: "from c".to_string(),
+                },
+                Change::ChangeHeader {
+                    name: "subject".to_string(),
+                    index: 1,
+                    value: "[Ext] Hello".to_string(),
+                },
+                Change::RemoveHeader {
+                    name: "received".to_string(),
+                    index: 3,
+                },
+                Change::ReplaceBody("New body\r\n".to_string()),
+            ]
+        );
+
+        let message = MimeMessage::parse("Subject: Hello\r\n\r\n").unwrap();
+        let mut editor = MessageEditor::new(message);
+        let value = "Hi\r\nBcc: eve@example.org".to_string();
+        assert!(editor.change_header("Subject", 0, value));
+        let (message, changes) = editor.into_parts();
+        let subject = message.headers.get("Subject".to_string()).unwrap();
+        assert!(!subject.raw_value().contains(['\r', '\n']));
+        assert_eq!(
+            changes,
+            vec![Change::ChangeHeader {
+                name: "Subject".to_string(),
+                index: 1,
+                value: subject.raw_value().to_string(),
+            }]
+        );
+    }
+}
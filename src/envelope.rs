@@ -0,0 +1,214 @@
+//! Module for the SMTP envelope of a message, RFC 5321.
+//!
+//! The envelope, made up of the sender and recipients given to `MAIL FROM`
+//! and `RCPT TO` along with where and when the message came from, isn't
+//! part of the message itself, but has to be carried along with it by an
+//! MTA. It can be set as `MimeMessage::envelope`, and recorded in the
+//! message as trace headers once the message is delivered.
+use std::net::IpAddr;
+
+use chrono::{DateTime, FixedOffset};
+
+use super::address::{Address, Mailbox};
+use super::environment;
+use super::header::Header;
+use super::message::MimeMessage;
+use super::results::ParsingResult;
+
+/// The SMTP envelope a message was received with.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Envelope {
+    /// The reverse-path given to `MAIL FROM`, which is empty for bounces
+    pub mail_from: String,
+    /// The forward-paths given to `RCPT TO`
+    pub rcpt_to: Vec<String>,
+    pub received_at: Option<DateTime<FixedOffset>>,
+    /// The address of the client which sent the message
+    pub remote_ip: Option<IpAddr>,
+}
+
+impl Envelope {
+    /// The trace headers recording this envelope for a message received by
+    /// the host `by`, in the order they go at the top of the message.
+    ///
+    /// These are `Return-Path`, a `Received` header as described in RFC 5321
+    /// Section 4.4, which is dated now if `received_at` isn't set, and an
+    /// `X-Original-To` listing the recipients.
+    pub fn trace_headers(&self, by: &str) -> ParsingResult<Vec<Header>> {
+        let mut received = String::new();
+        match self.remote_ip {
+            Some(IpAddr::V4(ip)) => received.push_str(&format!("from [{}] ", ip)),
+            Some(IpAddr::V6(ip)) => received.push_str(&format!("from [IPv6:{}] ", ip)),
+            None => {}
+        }
+        received.push_str(&format!("by {} with SMTP", by));
+        // Only a single recipient may be given
+        if let [recipient] = &self.rcpt_to[..] {
+            received.push_str(&format!(" for <{}>", recipient));
+        }
+        let date = self.received_at.unwrap_or_else(environment::now);
+        received.push_str(&format!("; {}", date.to_rfc2822()));
+
+        let mut headers = vec![
+            Header::new("Return-Path".to_string(), format!("<{}>", self.mail_from)),
+            Header::new("Received".to_string(), received),
+        ];
+        if !self.rcpt_to.is_empty() {
+            headers.push(Header::new(
+                "X-Original-To".to_string(),
+                self.rcpt_to.join(", "),
+            ));
+        }
+        Ok(headers)
+    }
+}
+
+impl MimeMessage {
+    /// The addresses of the `Delivered-To` headers added by each MTA which
+    /// delivered this message, latest first.
+    ///
+    /// Values which aren't addresses are skipped.
+    pub fn delivered_to(&self) -> Vec<Mailbox> {
+        self.trace_addresses("Delivered-To")
+    }
+
+    /// The addresses of the `X-Original-To` headers, which record the
+    /// recipients the message was sent to before any aliases were expanded.
+    pub fn original_to(&self) -> Vec<Mailbox> {
+        self.trace_addresses("X-Original-To")
+    }
+
+    /// Whether this message has already been delivered to `address`, by a
+    /// `Delivered-To` header naming it, such as when it has come back
+    /// round a forwarding loop.
+    ///
+    /// Addresses are compared as given by `Mailbox::normalized_address`.
+    pub fn has_delivered_to(&self, address: &str) -> bool {
+        let address = Mailbox::parse(address)
+            .unwrap_or_else(|_| Mailbox::new(address.trim().to_string()))
+            .normalized_address();
+        self.delivered_to()
+            .iter()
+            .any(|mailbox| mailbox.normalized_address() == address)
+    }
+
+    fn trace_addresses(&self, name: &str) -> Vec<Mailbox> {
+        let mut mailboxes = Vec::new();
+        for header in self.headers.find(name).unwrap_or_default() {
+            for address in header.get_value::<Vec<Address>>().unwrap_or_default() {
+                match address {
+                    Address::Mailbox(mailbox) => mailboxes.push(mailbox),
+                    Address::Group(_, members) => mailboxes.extend(members),
+                }
+            }
+        }
+        mailboxes
+    }
+
+    /// Record the envelope of this message, if it has one, in trace headers
+    /// at the top of its headers, as a host `by` does on delivering it.
+    ///
+    /// See `Envelope::trace_headers`.
+    pub fn add_trace_headers(&mut self, by: &str) -> ParsingResult<()> {
+        let headers = match &self.envelope {
+            Some(envelope) => envelope.trace_headers(by)?,
+            None => return Ok(()),
+        };
+        for (index, header) in headers.into_iter().enumerate() {
+            self.headers.insert_at(index, header);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::offset::TimeZone;
+
+    fn envelope() -> Envelope {
+        Envelope {
+            mail_from: "joe@example.org".to_string(),
+            rcpt_to: vec!["anna@example.net".to_string()],
+            received_at: Some(FixedOffset::east(3600).ymd(2024, 3, 1).and_hms(12, 30, 0)),
+            remote_ip: Some("192.0.2.1".parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_trace_headers() {
+        let headers = envelope().trace_headers("mx.example.net").unwrap();
+        let headers: Vec<(&str, &str)> = headers
+            .iter()
+            .map(|header| (&header.name[..], header.raw_value()))
+            .collect();
+        assert_eq!(
+            headers,
+            vec![
+                ("Return-Path", "<joe@example.org>"),
+                (
+                    "Received",
+                    "from [192.0.2.1] by mx.example.net with SMTP for <anna@example.net>; \
+                     Fri, 1 Mar 2024 12:30:00 +0100"
+                ),
+                ("X-Original-To", "anna@example.net"),
+            ]
+        );
+
+        let bounce = Envelope {
+            mail_from: String::new(),
+            rcpt_to: vec!["a@example.net".to_string(), "b@example.net".to_string()],
+            remote_ip: Some("2001:db8::1".parse().unwrap()),
+            ..envelope()
+        };
+        let headers = bounce.trace_headers("mx.example.net").unwrap();
+        assert_eq!(headers[0].raw_value(), "<>");
+        assert!(headers[1]
+            .raw_value()
+            .starts_with("from [IPv6:2001:db8::1] by mx.example.net with SMTP; "));
+        assert_eq!(headers[2].raw_value(), "a@example.net, b@example.net");
+    }
+
+    #[test]
+    fn test_delivered_to() {
+        let message = MimeMessage::parse(
+            "Delivered-To: Anna@Example.NET\r\n\
+             Delivered-To: <list@example.org>\r\n\
+             X-Original-To: team@example.net, bob@example.net\r\n\
+             Subject: Hi\r\n\r\nHi",
+        )
+        .unwrap();
+        let addresses: Vec<String> = message
+            .delivered_to()
+            .into_iter()
+            .map(|mailbox| mailbox.address)
+            .collect();
+        assert_eq!(addresses, vec!["Anna@Example.NET", "list@example.org"]);
+        assert_eq!(message.original_to().len(), 2);
+
+        assert!(message.has_delivered_to("anna@example.net"));
+        assert!(message.has_delivered_to("Anna <ANNA@example.net>"));
+        assert!(message.has_delivered_to("list@EXAMPLE.org"));
+        assert!(!message.has_delivered_to("team@example.net"));
+    }
+
+    #[test]
+    fn test_add_trace_headers() {
+        let mut message = MimeMessage::parse("Subject: Hi\r\n\r\nHi").unwrap();
+        message.add_trace_headers("mx.example.net").unwrap();
+        assert_eq!(message.headers.len(), 1);
+
+        message.envelope = Some(envelope());
+        message.add_trace_headers("mx.example.net").unwrap();
+        let names: Vec<&str> = message
+            .headers
+            .iter()
+            .map(|header| &header.name[..])
+            .collect();
+        assert_eq!(
+            names,
+            vec!["Return-Path", "Received", "X-Original-To", "Subject"]
+        );
+    }
+}
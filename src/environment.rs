@@ -0,0 +1,163 @@
+//! Module for the randomness and current time used when generating messages.
+//!
+//! Boundaries, Message-IDs and Maildir filenames need random bytes, and
+//! composed messages are dated with the current time. By default these come
+//! from the operating system: random bytes through `rand`, when that feature
+//! is enabled, and the time from the system clock. On platforms which have
+//! neither, such as `wasm32-unknown-unknown`, an `EntropySource` and a
+//! `Clock` can be supplied with `set_entropy_source` and `set_clock`.
+use std::sync::{Arc, PoisonError, RwLock};
+
+use chrono::{DateTime, FixedOffset};
+
+/// A source of random bytes.
+pub trait EntropySource: Send + Sync {
+    /// Fill `dest` with random bytes.
+    fn fill_bytes(&self, dest: &mut [u8]);
+}
+
+/// A source of the current time.
+pub trait Clock: Send + Sync {
+    /// The current time.
+    fn now(&self) -> DateTime<FixedOffset>;
+}
+
+/// The operating system's secure random source.
+#[cfg(feature = "rand")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsEntropy;
+
+#[cfg(feature = "rand")]
+impl EntropySource for OsEntropy {
+    fn fill_bytes(&self, dest: &mut [u8]) {
+        use rand::RngCore;
+        rand::rngs::OsRng.fill_bytes(dest);
+    }
+}
+
+/// The system clock, giving times in UTC.
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<FixedOffset> {
+        chrono::Utc::now().with_timezone(&FixedOffset::east(0))
+    }
+}
+
+lazy_static! {
+    static ref ENTROPY_SOURCE: RwLock<Option<Arc<dyn EntropySource>>> = {
+        #[cfg(feature = "rand")]
+        let source: Option<Arc<dyn EntropySource>> = Some(Arc::new(OsEntropy));
+        #[cfg(not(feature = "rand"))]
+        let source: Option<Arc<dyn EntropySource>> = None;
+        RwLock::new(source)
+    };
+    static ref CLOCK: RwLock<Option<Arc<dyn Clock>>> = {
+        #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+        let clock: Option<Arc<dyn Clock>> = Some(Arc::new(SystemClock));
+        #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+        let clock: Option<Arc<dyn Clock>> = None;
+        RwLock::new(clock)
+    };
+}
+
+/// Use `source` for all random bytes from now on.
+pub fn set_entropy_source<E: EntropySource + 'static>(source: E) {
+    *ENTROPY_SOURCE
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(source));
+}
+
+/// Use `clock` for the current time from now on.
+pub fn set_clock<C: Clock + 'static>(clock: C) {
+    *CLOCK.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(clock));
+}
+
+/// Fill `dest` with bytes from the current entropy source.
+///
+/// Panics if there's no entropy source, which is only the case when the
+/// `rand` feature is disabled and none has been set.
+pub fn fill_random(dest: &mut [u8]) {
+    let source = ENTROPY_SOURCE
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    source
+        .expect("no entropy source is available, one must be set with set_entropy_source")
+        .fill_bytes(dest);
+}
+
+/// The current time from the current clock.
+///
+/// Panics if there's no clock, which is only the case on
+/// `wasm32-unknown-unknown` when none has been set.
+pub fn now() -> DateTime<FixedOffset> {
+    let clock = CLOCK.read().unwrap_or_else(PoisonError::into_inner).clone();
+    clock
+        .expect("no clock is available, one must be set with set_clock")
+        .now()
+}
+
+/// A random `u64` from the current entropy source.
+pub(crate) fn random_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    fill_random(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+const ALPHANUMERIC: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+/// `length` random ASCII letters and digits from the current entropy source.
+pub(crate) fn random_alphanumeric(length: usize) -> String {
+    alphanumeric_from(fill_random, length)
+}
+
+fn alphanumeric_from<F: FnMut(&mut [u8])>(mut fill: F, length: usize) -> String {
+    let mut result = String::with_capacity(length);
+    let mut random = vec![0u8; 2 * length];
+    // Only take bytes below the largest multiple of the alphabet size, so
+    // that every character is equally likely
+    let limit = 256 - 256 % ALPHANUMERIC.len();
+    while result.len() < length {
+        fill(&mut random);
+        for &byte in random.iter().filter(|&&byte| (byte as usize) < limit) {
+            if result.len() == length {
+                break;
+            }
+            result.push(ALPHANUMERIC[byte as usize % ALPHANUMERIC.len()] as char);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alphanumeric_from() {
+        let fill = |dest: &mut [u8]| {
+            let bytes = [250, 0, 255, 61, 26, 248];
+            for (byte, &value) in dest.iter_mut().zip(bytes.iter().cycle()) {
+                *byte = value;
+            }
+        };
+        // 248 and above are skipped to keep the characters uniform
+        assert_eq!(alphanumeric_from(fill, 3), "A9a");
+
+        let random = random_alphanumeric(30);
+        assert_eq!(random.len(), 30);
+        assert!(random.chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    #[test]
+    fn test_now() {
+        let before = chrono::Utc::now();
+        let now = now();
+        assert!(now.timestamp() >= before.timestamp());
+        assert_eq!(now.offset().local_minus_utc(), 0);
+    }
+}
@@ -0,0 +1,295 @@
+//! Module for `message/external-body` parts, RFC 2046 Section 5.2.3, which
+//! refer to content kept elsewhere rather than holding it.
+//!
+//! The parameters of the Content-Type say how to get at the content, and
+//! the body holds the header the content would have had in the message,
+//! followed by a "phantom body" which is only used by some access types.
+use super::header::Header;
+use super::message::MimeMessage;
+use super::mimeheaders::ContentType;
+
+/// How the content of a `message/external-body` part is got at, as given
+/// by its `access-type` parameter.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum AccessType {
+    /// Fetched from `url`, RFC 2017
+    Url(String),
+    /// Fetched by anonymous FTP from `site`
+    AnonFtp {
+        site: String,
+        directory: Option<String>,
+        name: String,
+        /// The transfer mode, such as `image`, which defaults to `ascii`
+        mode: Option<String>,
+    },
+    /// Requested by mail from `server`, with the phantom body of the part
+    /// as the body of the request
+    MailServer {
+        server: String,
+        subject: Option<String>,
+    },
+    /// Any other access type, with all of the Content-Type parameters
+    Other {
+        access_type: String,
+        parameters: Vec<(String, String)>,
+    },
+}
+
+/// A `message/external-body` part.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ExternalBody {
+    pub access: AccessType,
+    /// When the content stops being available, as an RFC 5322 date
+    pub expiration: Option<String>,
+    /// The size of the content in bytes, before any transfer encoding
+    pub size: Option<u64>,
+    /// Whether the content can be changed, `read` or `read-write`
+    pub permission: Option<String>,
+    /// The Content-Type of the content
+    pub content_type: ContentType,
+    /// The Content-ID of the content, which should be given for caching
+    pub content_id: Option<String>,
+    /// The Content-Transfer-Encoding of the content as it's fetched
+    pub content_transfer_encoding: Option<String>,
+    /// The body after the header of the content, which is the request to
+    /// send for `AccessType::MailServer`
+    pub phantom_body: String,
+}
+
+impl ExternalBody {
+    /// An external body to fetch from `url`, with the given Content-Type.
+    pub fn url(url: &str, content_type: ContentType) -> ExternalBody {
+        ExternalBody {
+            access: AccessType::Url(url.to_string()),
+            expiration: None,
+            size: None,
+            permission: None,
+            content_type,
+            content_id: None,
+            content_transfer_encoding: None,
+            phantom_body: String::new(),
+        }
+    }
+
+    /// The part referring to the content, to take its place in a message.
+    pub fn to_part(&self) -> MimeMessage {
+        let mut content_type = ContentType::new("message", "external-body");
+        match self.access {
+            AccessType::Url(ref url) => {
+                content_type.set_parameter("access-type", "URL");
+                content_type.set_parameter("url", url);
+            }
+            AccessType::AnonFtp {
+                ref site,
+                ref directory,
+                ref name,
+                ref mode,
+            } => {
+                content_type.set_parameter("access-type", "anon-ftp");
+                content_type.set_parameter("site", site);
+                if let Some(directory) = directory {
+                    content_type.set_parameter("directory", directory);
+                }
+                content_type.set_parameter("name", name);
+                if let Some(mode) = mode {
+                    content_type.set_parameter("mode", mode);
+                }
+            }
+            AccessType::MailServer {
+                ref server,
+                ref subject,
+            } => {
+                content_type.set_parameter("access-type", "mail-server");
+                content_type.set_parameter("server", server);
+                if let Some(subject) = subject {
+                    content_type.set_parameter("subject", subject);
+                }
+            }
+            AccessType::Other {
+                ref access_type,
+                ref parameters,
+            } => {
+                content_type.set_parameter("access-type", access_type);
+                for (name, value) in parameters.iter() {
+                    content_type.set_parameter(name, value);
+                }
+            }
+        }
+        if let Some(ref expiration) = self.expiration {
+            content_type.set_parameter("expiration", expiration);
+        }
+        if let Some(size) = self.size {
+            content_type.set_parameter("size", &size.to_string());
+        }
+        if let Some(ref permission) = self.permission {
+            content_type.set_parameter("permission", permission);
+        }
+
+        let mut body = format!("Content-Type: {}\r\n", self.content_type);
+        if let Some(ref id) = self.content_id {
+            body.push_str(&format!("Content-ID: <{}>\r\n", id));
+        }
+        if let Some(ref encoding) = self.content_transfer_encoding {
+            body.push_str(&format!("Content-Transfer-Encoding: {}\r\n", encoding));
+        }
+        body.push_str("\r\n");
+        body.push_str(&self.phantom_body);
+
+        let mut part = MimeMessage::new(body);
+        part.headers.replace(Header::new(
+            "Content-Type".to_string(),
+            content_type.to_string(),
+        ));
+        part
+    }
+}
+
+impl MimeMessage {
+    /// This part as a `message/external-body`, if it is one with the
+    /// parameters its access type needs.
+    pub fn external_body(&self) -> Option<ExternalBody> {
+        let content_type = self.content_type();
+        if (&content_type.ttype[..], &content_type.subtype[..]) != ("message", "external-body") {
+            return None;
+        }
+        let parameter = |name: &str| content_type.parameter(name).map(str::to_string);
+        let access_type = content_type.parameter("access-type")?;
+        let access = match &access_type.to_ascii_lowercase()[..] {
+            // Whitespace in a URL is to be ignored, as it may have been
+            // added to fold it, RFC 2017 Section 3
+            "url" => AccessType::Url(parameter("url")?.split_whitespace().collect()),
+            "anon-ftp" => AccessType::AnonFtp {
+                site: parameter("site")?,
+                directory: parameter("directory"),
+                name: parameter("name")?,
+                mode: parameter("mode"),
+            },
+            "mail-server" => AccessType::MailServer {
+                server: parameter("server")?,
+                subject: parameter("subject"),
+            },
+            _ => AccessType::Other {
+                access_type: access_type.to_string(),
+                parameters: content_type
+                    .parameters
+                    .iter()
+                    .filter(|(name, _)| {
+                        !["access-type", "expiration", "size", "permission"].contains(&&name[..])
+                    })
+                    .cloned()
+                    .collect(),
+            },
+        };
+
+        let (headers, phantom_body) = match self.body.find("\r\n\r\n") {
+            Some(end) => (&self.body[..end + 2], &self.body[end + 4..]),
+            None => match self.body.find("\n\n") {
+                Some(end) => (&self.body[..end + 1], &self.body[end + 2..]),
+                None => (&self.body[..], ""),
+            },
+        };
+        // The line break ending the body isn't part of the phantom body
+        let phantom_body = phantom_body
+            .strip_suffix("\r\n")
+            .or_else(|| phantom_body.strip_suffix('\n'))
+            .unwrap_or(phantom_body);
+        let headers = MimeMessage::parse_headers_only(headers);
+        Some(ExternalBody {
+            access,
+            expiration: parameter("expiration"),
+            size: content_type.parameter("size").and_then(|s| s.parse().ok()),
+            permission: parameter("permission"),
+            content_type: headers
+                .get_value("Content-Type".to_string())
+                .unwrap_or_default(),
+            content_id: headers.get("Content-ID".to_string()).map(|id| {
+                id.raw_value()
+                    .trim()
+                    .trim_matches(&['<', '>'][..])
+                    .to_string()
+            }),
+            content_transfer_encoding: headers
+                .get("Content-Transfer-Encoding".to_string())
+                .map(|encoding| encoding.raw_value().trim().to_string()),
+            phantom_body: phantom_body.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_external_body() {
+        let message = MimeMessage::parse(
+            "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\n\
+             Content-Type: message/external-body; access-type=URL;\r\n \
+             URL=\"https://example.org/files/\r\n report.pdf\"; size=1048576\r\n\r\n\
+             Content-Type: application/pdf\r\n\
+             Content-ID: <report@example.org>\r\n\r\n\
+             \r\n\
+             --b\r\n\
+             Content-Type: message/external-body; access-type=mail-server;\r\n \
+             server=\"listserv@example.org\"\r\n\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             get archive.txt\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+
+        let url = message.children[0].external_body().unwrap();
+        assert_eq!(
+            url.access,
+            AccessType::Url("https://example.org/files/report.pdf".to_string())
+        );
+        assert_eq!(url.size, Some(1048576));
+        assert_eq!(url.content_type, ContentType::new("application", "pdf"));
+        assert_eq!(url.content_id, Some("report@example.org".to_string()));
+
+        let mail = message.children[1].external_body().unwrap();
+        assert_eq!(
+            mail.access,
+            AccessType::MailServer {
+                server: "listserv@example.org".to_string(),
+                subject: None,
+            }
+        );
+        assert_eq!(mail.phantom_body, "get archive.txt");
+        assert_eq!(message.external_body(), None);
+    }
+
+    #[test]
+    fn test_external_body_part() {
+        let mut external = ExternalBody::url(
+            "https://example.org/a.zip",
+            ContentType::new("application", "zip"),
+        );
+        external.content_id = Some("a@example.org".to_string());
+        external.expiration = Some("Fri, 1 Mar 2024 12:00:00 +0100".to_string());
+        let part = external.to_part();
+        assert_eq!(
+            part.headers
+                .get("Content-Type".to_string())
+                .unwrap()
+                .raw_value(),
+            "message/external-body; access-type=URL; url=\"https://example.org/a.zip\"; \
+             expiration=\"Fri, 1 Mar 2024 12:00:00 +0100\""
+        );
+        let reparsed = MimeMessage::parse(&part.as_string()).unwrap();
+        assert_eq!(reparsed.external_body(), Some(external));
+
+        let ftp = ExternalBody {
+            access: AccessType::AnonFtp {
+                site: "ftp.example.org".to_string(),
+                directory: Some("pub".to_string()),
+                name: "a.zip".to_string(),
+                mode: Some("image".to_string()),
+            },
+            ..ExternalBody::url("", ContentType::new("application", "zip"))
+        };
+        let reparsed = MimeMessage::parse(&ftp.to_part().as_string()).unwrap();
+        assert_eq!(reparsed.external_body(), Some(ftp));
+    }
+}
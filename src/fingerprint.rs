@@ -0,0 +1,206 @@
+//! Module for fingerprints of messages, for telling copies of a message
+//! apart from other messages.
+//!
+//! The same message is often stored or received more than once, with only
+//! the trace headers added on the way differing between the copies. A
+//! `Fingerprint` is a SHA-256 hash of the message with such headers left
+//! out, and the rest in their relaxed canonical forms, so that copies have
+//! the same one.
+use std::fmt;
+
+use super::canonicalize::{body_relaxed, header_relaxed};
+use super::message::MimeMessage;
+
+/// Which parts of a message a fingerprint covers.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct FingerprintPolicy {
+    /// The names of the headers to leave out
+    pub ignored_headers: Vec<String>,
+}
+
+impl Default for FingerprintPolicy {
+    /// Leave out the trace headers added as a message is delivered.
+    fn default() -> FingerprintPolicy {
+        FingerprintPolicy {
+            ignored_headers: ["Received", "Return-Path", "Delivered-To", "X-Original-To"]
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl FingerprintPolicy {
+    /// Leave out the header `name` as well, such as `Date` or `Message-ID`
+    /// when a message may have been sent again.
+    pub fn ignore(mut self, name: &str) -> FingerprintPolicy {
+        self.ignored_headers.push(name.to_string());
+        self
+    }
+
+    fn is_ignored(&self, name: &str) -> bool {
+        self.ignored_headers
+            .iter()
+            .any(|ignored| ignored.eq_ignore_ascii_case(name))
+    }
+}
+
+/// The fingerprint of a message, as given by `MimeMessage::fingerprint`.
+///
+/// It's shown as the hash in lowercase hex.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub struct Fingerprint(pub [u8; 32]);
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.0.iter() {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+impl MimeMessage {
+    /// The fingerprint of this message, covering the headers other than
+    /// those left out by `policy`, in order, and the whole body, including
+    /// any parts.
+    pub fn fingerprint(&self, policy: &FingerprintPolicy) -> Fingerprint {
+        let mut data = Vec::new();
+        for header in self.headers.iter() {
+            if !policy.is_ignored(&header.name) {
+                data.extend_from_slice(header_relaxed(&header.name, header.raw_value()).as_bytes());
+            }
+        }
+        data.extend_from_slice(b"\r\n");
+        data.extend_from_slice(&body_relaxed(self.as_string_without_headers().as_bytes())[..]);
+        Fingerprint(sha256(&data[..]))
+    }
+}
+
+// The round constants of SHA-256, FIPS 180-4 Section 4.2.2.
+#[rustfmt::skip]
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+// The initial hash value of SHA-256, Section 5.3.3.
+#[rustfmt::skip]
+const H: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+// The SHA-256 hash of `data`, FIPS 180-4 Section 6.2.
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut state = H;
+
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in padded.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h].iter()) {
+            *word = word.wrapping_add(*value);
+        }
+    }
+
+    let mut hash = [0u8; 32];
+    for (bytes, word) in hash.chunks_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint() {
+        let message = MimeMessage::parse(
+            "Received: from a by b; Fri, 1 Mar 2024 12:30:00 +0100\r\n\
+             Subject: Hi\r\n\
+             Date: Fri, 1 Mar 2024 12:00:00 +0100\r\n\r\n\
+             Hello\r\n",
+        )
+        .unwrap();
+        let copy = MimeMessage::parse(
+            "Received: from c by d; Fri, 1 Mar 2024 12:45:00 +0100\r\n\
+             Received: from a by b; Fri, 1 Mar 2024 12:30:00 +0100\r\n\
+             subject:  Hi\r\n\
+             Date: Fri, 1 Mar 2024 12:00:00 +0100\r\n\r\n\
+             Hello  \r\n\r\n",
+        )
+        .unwrap();
+        let resent = MimeMessage::parse(
+            "Subject: Hi\r\n\
+             Date: Sat, 2 Mar 2024 09:00:00 +0100\r\n\r\n\
+             Hello\r\n",
+        )
+        .unwrap();
+
+        let policy = FingerprintPolicy::default();
+        assert_eq!(message.fingerprint(&policy), copy.fingerprint(&policy));
+        assert_ne!(message.fingerprint(&policy), resent.fingerprint(&policy));
+        let policy = policy.ignore("Date");
+        assert_eq!(message.fingerprint(&policy), resent.fingerprint(&policy));
+    }
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            Fingerprint(sha256(b"")).to_string(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            Fingerprint(sha256(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            ))
+            .to_string(),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+}
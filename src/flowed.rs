@@ -0,0 +1,280 @@
+//! Module for `text/plain; format=flowed` bodies, as described in RFC 3676.
+//!
+//! Flowed text is wrapped to a short line length for readers which can't
+//! reflow it, with a space at the end of each line that continues on the
+//! next. Readers which understand the format join those lines back into
+//! paragraphs, which can then be wrapped to fit the display.
+use super::bodyparse::quote_depth;
+use super::mimeheaders::ContentType;
+
+/// How to encode and decode flowed text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowedText {
+    /// The longest line to write, including quotes, when encoding
+    pub width: usize,
+    /// Whether the space ending a flowed line is added when wrapping, and so
+    /// removed when joining, as with `delsp=yes`
+    pub delsp: bool,
+}
+
+impl Default for FlowedText {
+    /// Wrap at 78 characters, the longest line recommended by RFC 5322,
+    /// with `delsp=yes`.
+    fn default() -> FlowedText {
+        FlowedText {
+            width: 78,
+            delsp: true,
+        }
+    }
+}
+
+impl FlowedText {
+    /// The flowed text options given by `content_type`, if it's
+    /// `format=flowed`.
+    pub fn from_content_type(content_type: &ContentType) -> Option<FlowedText> {
+        let format = content_type.parameter("format")?;
+        if !format.eq_ignore_ascii_case("flowed") {
+            return None;
+        }
+        let delsp = content_type
+            .parameter("delsp")
+            .is_some_and(|delsp| delsp.eq_ignore_ascii_case("yes"));
+        Some(FlowedText {
+            delsp,
+            ..FlowedText::default()
+        })
+    }
+
+    /// The parameters to add to a text/plain content type for text encoded
+    /// with these options.
+    pub fn parameters(&self) -> &'static str {
+        if self.delsp {
+            "format=flowed; delsp=yes"
+        } else {
+            "format=flowed"
+        }
+    }
+
+    /// Join the flowed lines of `text` into paragraphs.
+    ///
+    /// Quoted paragraphs keep their quotes, followed by a space. Every line
+    /// of the result ends with CRLF.
+    pub fn decode(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        // The quote depth and text of the paragraph being joined
+        let mut paragraph: Option<(usize, String)> = None;
+
+        for line in lines(text) {
+            let depth = line.bytes().take_while(|&b| b == b'>').count();
+            let content = &line[depth..];
+            let content = content.strip_prefix(' ').unwrap_or(content);
+            let flowed = content.ends_with(' ') && content != "-- ";
+
+            // A change of quote depth ends a paragraph, even after a flowed
+            // line
+            if let Some((last_depth, last)) = paragraph.take() {
+                if last_depth == depth {
+                    paragraph = Some((last_depth, last));
+                } else {
+                    push_line(&mut result, last_depth, &last);
+                }
+            }
+            let content = match (flowed, self.delsp) {
+                (true, true) => &content[..content.len() - 1],
+                _ => content,
+            };
+            let (_, joined) = paragraph.get_or_insert_with(|| (depth, String::new()));
+            joined.push_str(content);
+
+            if !flowed {
+                let (depth, joined) = paragraph.take().unwrap();
+                push_line(&mut result, depth, &joined);
+            }
+        }
+        if let Some((depth, joined)) = paragraph {
+            push_line(&mut result, depth, &joined);
+        }
+        result
+    }
+
+    /// Wrap the lines of `text` as flowed text.
+    ///
+    /// Lines quoted with `>` are rewrapped within their quotes. Lines which
+    /// could be mistaken for quotes, or for an mbox `From ` line, are space
+    /// stuffed. Every line of the result ends with CRLF.
+    pub fn encode(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len() + text.len() / 16);
+
+        for line in lines(text) {
+            let (depth, content) = quote_depth(line);
+            // Spaces at the end of a line would make it flow into the next
+            let content = match content {
+                "-- " => content,
+                _ => content.trim_end_matches(' '),
+            };
+            // Leave room for the quotes, a stuffed space and a flowed space
+            let room = self.width.saturating_sub(depth + 2).max(1);
+
+            let mut chunks = Vec::new();
+            let mut chunk_start = 0;
+            let mut chunk_width = 0;
+            let mut position = 0;
+            for word in content.split_inclusive(' ') {
+                let width = word.chars().count();
+                if chunk_width > 0 && chunk_width + width > room {
+                    chunks.push(&content[chunk_start..position]);
+                    chunk_start = position;
+                    chunk_width = 0;
+                }
+                chunk_width += width;
+                position += word.len();
+            }
+            chunks.push(&content[chunk_start..]);
+
+            let last = chunks.len() - 1;
+            for (i, chunk) in chunks.into_iter().enumerate() {
+                for _ in 0..depth {
+                    result.push('>');
+                }
+                let stuff =
+                    chunk.starts_with(' ') || chunk.starts_with('>') || chunk.starts_with("From ");
+                if (depth > 0 && !chunk.is_empty()) || stuff {
+                    result.push(' ');
+                }
+                result.push_str(chunk);
+                if i < last && self.delsp {
+                    result.push(' ');
+                }
+                result.push_str("\r\n");
+            }
+        }
+        result
+    }
+}
+
+// The lines of `text`, without their line breaks.
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    text.split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+        // An empty text has no lines, rather than a single empty one
+        .filter(move |_| !text.is_empty())
+}
+
+fn push_line(result: &mut String, depth: usize, text: &str) {
+    for _ in 0..depth {
+        result.push('>');
+    }
+    if depth > 0 && !text.is_empty() {
+        result.push(' ');
+    }
+    result.push_str(text);
+    result.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let text = "This is a  \r\n\
+                    flowed paragraph.\r\n\
+                    \x20From the start.\r\n\
+                    > Quoted and \r\n\
+                    >> deeper \r\n\
+                    >> still\r\n\
+                    \r\n\
+                    -- \r\n\
+                    Joe \r\n";
+        let delsp = FlowedText::default();
+        assert_eq!(
+            delsp.decode(text),
+            "This is a flowed paragraph.\r\n\
+             From the start.\r\n\
+             > Quoted and\r\n\
+             >> deeperstill\r\n\
+             \r\n\
+             -- \r\n\
+             Joe\r\n"
+        );
+        let fixed = FlowedText {
+            delsp: false,
+            ..FlowedText::default()
+        };
+        assert_eq!(
+            fixed.decode(text),
+            "This is a  flowed paragraph.\r\n\
+             From the start.\r\n\
+             > Quoted and \r\n\
+             >> deeper still\r\n\
+             \r\n\
+             -- \r\n\
+             Joe \r\n"
+        );
+        assert_eq!(delsp.decode(""), "");
+    }
+
+    #[test]
+    fn test_encode() {
+        let options = FlowedText {
+            width: 20,
+            delsp: true,
+        };
+        let text = "A line which is long enough to be wrapped.   \n\
+                    From here\n\
+                    \n\
+                    > > A quoted line which is wrapped too\n\
+                    -- \n\
+                    Averyveryverylongwordwhichcannotbewrapped";
+        let encoded = options.encode(text);
+        assert_eq!(
+            encoded,
+            "A line which is  \r\n\
+             long enough to be  \r\n\
+             wrapped.\r\n\
+             \x20From here\r\n\
+             \r\n\
+             >> A quoted line  \r\n\
+             >> which is  \r\n\
+             >> wrapped too\r\n\
+             -- \r\n\
+             Averyveryverylongwordwhichcannotbewrapped\r\n"
+        );
+        assert_eq!(
+            options.decode(&encoded),
+            "A line which is long enough to be wrapped.\r\n\
+             From here\r\n\
+             \r\n\
+             >> A quoted line which is wrapped too\r\n\
+             -- \r\n\
+             Averyveryverylongwordwhichcannotbewrapped\r\n"
+        );
+
+        let fixed = FlowedText {
+            width: 20,
+            delsp: false,
+        };
+        let encoded = fixed.encode("A line which is long enough");
+        assert_eq!(encoded, "A line which is \r\nlong enough\r\n");
+        assert_eq!(fixed.decode(&encoded), "A line which is long enough\r\n");
+    }
+
+    #[test]
+    fn test_from_content_type() {
+        let mut content_type = ContentType::new("text", "plain");
+        assert_eq!(FlowedText::from_content_type(&content_type), None);
+        content_type.set_parameter("format", "Flowed");
+        assert_eq!(
+            FlowedText::from_content_type(&content_type),
+            Some(FlowedText {
+                width: 78,
+                delsp: false
+            })
+        );
+        content_type.set_parameter("delsp", "yes");
+        let options = FlowedText::from_content_type(&content_type).unwrap();
+        assert!(options.delsp);
+        assert_eq!(options.parameters(), "format=flowed; delsp=yes");
+    }
+}
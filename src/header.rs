@@ -1,14 +1,25 @@
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-use std::fmt;
-use std::ops::Deref;
-use std::slice::Iter as SliceIter;
-use std::sync::Arc;
+use alloc::borrow::Cow;
+use alloc::collections::btree_map::Entry;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::cmp::Ordering;
+use core::error::Error;
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::{Deref, Range};
+use core::slice::Iter as SliceIter;
 
+#[cfg(feature = "std")]
 use chrono::{DateTime, FixedOffset, Utc};
 
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+#[cfg(feature = "std")]
+use super::registry::{self, AnyValue};
 use super::results::{ParsingError, ParsingResult};
-use super::rfc2047::decode_rfc2047;
+use super::rfc2047::decode_text;
+use super::rfc5322::MIME_LINE_LENGTH;
+#[cfg(feature = "std")]
 use super::rfc822::Rfc822DateParser;
 
 /// Trait for converting from RFC822 Header values into
@@ -50,72 +61,11 @@ impl<T: ToHeader> ToFoldedHeader for T {
 
 impl FromHeader for String {
     fn from_header(value: String) -> ParsingResult<String> {
-        #[derive(Debug, Clone, Copy)]
-        enum ParseState {
-            Normal(usize),
-            SeenEquals(usize),
-            SeenQuestion(usize, usize),
-        }
-
-        let mut state = ParseState::Normal(0);
-        let mut decoded = String::new();
-
-        let value_slice = &value[..];
-
-        for (pos, c) in value.char_indices() {
-            state = match (state, c) {
-                (ParseState::SeenQuestion(start_pos, 4), '=') => {
-                    let next_pos = pos + c.len_utf8();
-                    // Go to decode if we've seen enough ?
-                    let part_decoded = decode_rfc2047(&value_slice[start_pos..next_pos]);
-                    let to_push = match part_decoded {
-                        Some(ref s) => &s[..],
-                        // Decoding failed, push the undecoded string in.
-                        None => &value_slice[start_pos..pos],
-                    };
-                    decoded.push_str(to_push);
-                    // Revert us to normal state, but starting at the next character.
-                    ParseState::Normal(next_pos)
-                }
-                (ParseState::SeenQuestion(start_pos, count), '?') => {
-                    ParseState::SeenQuestion(start_pos, count + 1)
-                }
-                (ParseState::SeenQuestion(start_pos, count), _) => {
-                    if count > 4 {
-                        // This isn't a RFC2047 sequence, so go back to a normal.
-                        ParseState::Normal(start_pos)
-                    } else {
-                        state
-                    }
-                }
-                (ParseState::SeenEquals(start_pos), '?') => ParseState::SeenQuestion(start_pos, 1),
-                (ParseState::SeenEquals(start_pos), _) => {
-                    // This isn't a RFC2047 sequence, so go back to a normal.
-                    ParseState::Normal(start_pos)
-                }
-                (ParseState::Normal(start_pos), '=') => {
-                    if start_pos != pos {
-                        // Push all up to the =, if there is stuff to push.
-                        decoded.push_str(&value_slice[start_pos..pos]);
-                    }
-                    ParseState::SeenEquals(pos)
-                }
-                (ParseState::Normal(_), _) => state,
-            };
-        }
-
-        // Don't forget to push on whatever we have left
-        let last_start = match state {
-            ParseState::Normal(start_pos) => start_pos,
-            ParseState::SeenEquals(start_pos) => start_pos,
-            ParseState::SeenQuestion(start_pos, _) => start_pos,
-        };
-        decoded.push_str(&value_slice[last_start..]);
-
-        Ok(decoded)
+        Ok(decode_text(&value))
     }
 }
 
+#[cfg(feature = "std")]
 impl FromHeader for DateTime<FixedOffset> {
     fn from_header(value: String) -> ParsingResult<DateTime<FixedOffset>> {
         let mut parser = Rfc822DateParser::new(&value[..]);
@@ -123,6 +73,7 @@ impl FromHeader for DateTime<FixedOffset> {
     }
 }
 
+#[cfg(feature = "std")]
 impl FromHeader for DateTime<Utc> {
     fn from_header(value: String) -> ParsingResult<DateTime<Utc>> {
         let dt: ParsingResult<DateTime<FixedOffset>> = FromHeader::from_header(value);
@@ -142,13 +93,641 @@ impl<'a> ToHeader for &'a str {
     }
 }
 
+#[cfg(feature = "std")]
+impl ToHeader for DateTime<FixedOffset> {
+    fn to_header(value: DateTime<FixedOffset>) -> ParsingResult<String> {
+        Ok(value.to_rfc2822())
+    }
+}
+
+#[cfg(feature = "std")]
+impl ToHeader for DateTime<Utc> {
+    fn to_header(value: DateTime<Utc>) -> ParsingResult<String> {
+        Ok(value.to_rfc2822())
+    }
+}
+
+/// Limits used when folding a header onto multiple lines.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct FoldingPolicy {
+    /// The length lines are kept within wherever there is somewhere to fold
+    pub line_length: usize,
+    /// The length no line may exceed
+    pub max_line_length: usize,
+}
+
+impl Default for FoldingPolicy {
+    /// The limits from RFC 5322 Section 2.1.1
+    fn default() -> FoldingPolicy {
+        FoldingPolicy {
+            line_length: MIME_LINE_LENGTH,
+            max_line_length: 998,
+        }
+    }
+}
+
+/// When the encoded words in a header, RFC 2047, are decoded.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum HeaderDecoding {
+    /// Decoded once, when the header is parsed
+    Eager,
+    /// Decoded each time `Header::decoded_value` is called
+    #[default]
+    OnDemand,
+    /// Never decoded, so that the value is only ever seen as it appeared,
+    /// as when checking a signature over it
+    Raw,
+}
+
+/// Which headers have their encoded words decoded when, as set by
+/// `ParseOptions::header_decoding`.
+///
+/// This governs `Header::decoded_value`. The typed values of `get_value`
+/// are always parsed from the raw value, as the encoded words are part of
+/// their syntax.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct HeaderDecodePolicy {
+    /// How headers without a rule of their own are decoded
+    pub default: HeaderDecoding,
+    rules: Vec<(HeaderName, HeaderDecoding)>,
+}
+
+impl HeaderDecodePolicy {
+    /// A policy decoding every header as `default`.
+    pub fn new(default: HeaderDecoding) -> HeaderDecodePolicy {
+        HeaderDecodePolicy {
+            default,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Decode headers with `name` as `decoding`, rather than as the
+    /// default.
+    pub fn set(&mut self, name: &str, decoding: HeaderDecoding) {
+        let name = HeaderName::from(name);
+        match self.rules.iter_mut().find(|(rule, _)| *rule == name) {
+            Some(rule) => rule.1 = decoding,
+            None => self.rules.push((name, decoding)),
+        }
+    }
+
+    /// How headers with `name` are decoded.
+    pub fn decoding(&self, name: &str) -> HeaderDecoding {
+        self.rules
+            .iter()
+            .find(|(rule, _)| rule == name)
+            .map_or(self.default, |&(_, decoding)| decoding)
+    }
+}
+
+// Split an unfolded header value into the pieces between which it can be
+// folded, each after the first starting with the whitespace it can be folded
+// at. Quoted strings are kept whole.
+fn fold_pieces(value: &str) -> Vec<&str> {
+    let mut starts = vec![0];
+    let mut in_quote = false;
+    let mut escaped = false;
+    let mut prev_whitespace = false;
+    for (pos, c) in value.char_indices() {
+        let whitespace = (c == ' ' || c == '\t') && !in_quote;
+        if whitespace && !prev_whitespace && pos > 0 {
+            starts.push(pos);
+        }
+        if escaped {
+            escaped = false;
+        } else if in_quote && c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            in_quote = !in_quote;
+        }
+        prev_whitespace = whitespace;
+    }
+    // Keep trailing whitespace with the piece before it, so that no line is
+    // left with only whitespace on it
+    if starts.len() > 1 && value[*starts.last().unwrap()..].trim().is_empty() {
+        starts.pop();
+    }
+    let ends = starts.iter().skip(1).cloned().chain(Some(value.len()));
+    starts
+        .iter()
+        .zip(ends)
+        .map(|(&start, end)| &value[start..end])
+        .collect()
+}
+
+// Remove any existing folding, keeping the whitespace it was made at, and
+// treat any other line break as whitespace.
+fn unfold(value: &str) -> String {
+    value
+        .replace("\r\n", "\n")
+        .replace("\n ", " ")
+        .replace("\n\t", "\t")
+        .replace('\n', " ")
+}
+
+// Fold `value`, the first line of which starts `start_pos` characters in.
+pub(crate) fn fold_value(start_pos: usize, value: &str, policy: &FoldingPolicy) -> String {
+    let unfolded = unfold(value);
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut current: Vec<&str> = Vec::new();
+    let mut current_len = start_pos;
+    for piece in fold_pieces(&unfolded[..]) {
+        if current_len + piece.len() > policy.line_length && !current.is_empty() {
+            // Prefer to fold after a comma, as long as what follows it fits
+            let after_comma = (1..current.len())
+                .rev()
+                .find(|&i| current[i - 1].ends_with(','))
+                .filter(|&i| {
+                    let rest: usize = current[i..].iter().map(|p| p.len()).sum();
+                    rest + piece.len() <= policy.line_length
+                });
+            let rest = current.split_off(after_comma.unwrap_or(current.len()));
+            lines.push(current.concat());
+            current = rest;
+            current_len = current.iter().map(|p| p.len()).sum();
+        }
+        current.push(piece);
+        current_len += piece.len();
+    }
+    lines.push(current.concat());
+
+    // A piece with nowhere to fold it may still be too long for any line,
+    // in which case the only option left is to break it up
+    let mut result = Vec::new();
+    let mut line_start = start_pos;
+    for mut line in lines.into_iter() {
+        while line_start + line.len() > policy.max_line_length {
+            let end = policy.max_line_length.saturating_sub(line_start).max(1);
+            let mut end = end.min(line.len());
+            while !line.is_char_boundary(end) {
+                end += 1;
+            }
+            // Nothing would be left to continue with, as when the name
+            // alone is too long
+            if end == line.len() {
+                break;
+            }
+            // The continuation needs whitespace to be folded at
+            let rest = format!(" {}", &line[end..]);
+            line.truncate(end);
+            result.push(line);
+            line = rest;
+            line_start = 0;
+        }
+        result.push(line);
+        line_start = 0;
+    }
+    result.join("\r\n")
+}
+
+/// Fold the header `name: value` onto lines within the limits of `policy`.
+///
+/// Any folding already in `value` is removed first. Lines are folded at
+/// whitespace, preferring to fold after a comma, and never inside a quoted
+/// string or encoded word. Only where there's no whitespace to fold at within
+/// `max_line_length` is the value broken up elsewhere.
+///
+/// The result has CRLF between its lines, but no CRLF at the end.
+pub fn fold(name: &str, value: &str, policy: &FoldingPolicy) -> String {
+    format!("{}: {}", name, fold_value(name.len() + 2, value, policy))
+}
+
+/// The name of a header.
+///
+/// Names compare and hash case-insensitively, as RFC 5322 requires, but keep
+/// the capitalization they were given so that headers are written back out
+/// as they were read.
+///
+/// The names of the most common headers are interned, so that they take no
+/// allocation and compare as integers, and are available as constants such
+/// as `HeaderName::CONTENT_TYPE`.
+#[derive(Clone)]
+pub struct HeaderName(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    // The name at this index of `STANDARD_NAMES`, written as it is there
+    Standard(u8),
+    // Any other name, along with the index of the standard name it's a
+    // different capitalization of, if it is one
+    Custom(String, Option<u8>),
+}
+
+// The names which are interned, in their usual capitalization
+static STANDARD_NAMES: [&str; 60] = [
+    "Return-Path",
+    "Received",
+    "Date",
+    "From",
+    "Sender",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Bcc",
+    "Message-ID",
+    "In-Reply-To",
+    "References",
+    "Subject",
+    "Comments",
+    "Keywords",
+    "Resent-Date",
+    "Resent-From",
+    "Resent-Sender",
+    "Resent-To",
+    "Resent-Cc",
+    "Resent-Bcc",
+    "Resent-Message-ID",
+    "MIME-Version",
+    "Content-Type",
+    "Content-Transfer-Encoding",
+    "Content-Disposition",
+    "Content-ID",
+    "Content-Description",
+    "Content-Language",
+    "Content-Location",
+    "Content-MD5",
+    "DKIM-Signature",
+    "ARC-Seal",
+    "ARC-Message-Signature",
+    "ARC-Authentication-Results",
+    "Authentication-Results",
+    "Received-SPF",
+    "Delivered-To",
+    "Disposition-Notification-To",
+    "Return-Receipt-To",
+    "List-Id",
+    "List-Unsubscribe",
+    "List-Unsubscribe-Post",
+    "List-Subscribe",
+    "List-Post",
+    "List-Help",
+    "List-Owner",
+    "List-Archive",
+    "Precedence",
+    "Auto-Submitted",
+    "Importance",
+    "Priority",
+    "X-Priority",
+    "Organization",
+    "User-Agent",
+    "X-Mailer",
+    "Thread-Index",
+    "Thread-Topic",
+    "Autocrypt",
+    "Feedback-ID",
+];
+
+// Words which are capitalized in full in the usual form of header names
+static UPPERCASE_WORDS: [&str; 6] = ["ARC", "DKIM", "ID", "MD5", "MIME", "SPF"];
+
+impl HeaderName {
+    pub const RETURN_PATH: HeaderName = HeaderName::standard("Return-Path");
+    pub const RECEIVED: HeaderName = HeaderName::standard("Received");
+    pub const DATE: HeaderName = HeaderName::standard("Date");
+    pub const FROM: HeaderName = HeaderName::standard("From");
+    pub const SENDER: HeaderName = HeaderName::standard("Sender");
+    pub const REPLY_TO: HeaderName = HeaderName::standard("Reply-To");
+    pub const TO: HeaderName = HeaderName::standard("To");
+    pub const CC: HeaderName = HeaderName::standard("Cc");
+    pub const BCC: HeaderName = HeaderName::standard("Bcc");
+    pub const MESSAGE_ID: HeaderName = HeaderName::standard("Message-ID");
+    pub const IN_REPLY_TO: HeaderName = HeaderName::standard("In-Reply-To");
+    pub const REFERENCES: HeaderName = HeaderName::standard("References");
+    pub const SUBJECT: HeaderName = HeaderName::standard("Subject");
+    pub const COMMENTS: HeaderName = HeaderName::standard("Comments");
+    pub const KEYWORDS: HeaderName = HeaderName::standard("Keywords");
+    pub const RESENT_DATE: HeaderName = HeaderName::standard("Resent-Date");
+    pub const RESENT_FROM: HeaderName = HeaderName::standard("Resent-From");
+    pub const RESENT_SENDER: HeaderName = HeaderName::standard("Resent-Sender");
+    pub const RESENT_TO: HeaderName = HeaderName::standard("Resent-To");
+    pub const RESENT_CC: HeaderName = HeaderName::standard("Resent-Cc");
+    pub const RESENT_BCC: HeaderName = HeaderName::standard("Resent-Bcc");
+    pub const RESENT_MESSAGE_ID: HeaderName = HeaderName::standard("Resent-Message-ID");
+    pub const MIME_VERSION: HeaderName = HeaderName::standard("MIME-Version");
+    pub const CONTENT_TYPE: HeaderName = HeaderName::standard("Content-Type");
+    pub const CONTENT_TRANSFER_ENCODING: HeaderName =
+        HeaderName::standard("Content-Transfer-Encoding");
+    pub const CONTENT_DISPOSITION: HeaderName = HeaderName::standard("Content-Disposition");
+    pub const CONTENT_ID: HeaderName = HeaderName::standard("Content-ID");
+    pub const CONTENT_DESCRIPTION: HeaderName = HeaderName::standard("Content-Description");
+    pub const CONTENT_LANGUAGE: HeaderName = HeaderName::standard("Content-Language");
+    pub const CONTENT_LOCATION: HeaderName = HeaderName::standard("Content-Location");
+    pub const CONTENT_MD5: HeaderName = HeaderName::standard("Content-MD5");
+    pub const DKIM_SIGNATURE: HeaderName = HeaderName::standard("DKIM-Signature");
+    pub const ARC_SEAL: HeaderName = HeaderName::standard("ARC-Seal");
+    pub const ARC_MESSAGE_SIGNATURE: HeaderName = HeaderName::standard("ARC-Message-Signature");
+    pub const ARC_AUTHENTICATION_RESULTS: HeaderName =
+        HeaderName::standard("ARC-Authentication-Results");
+    pub const AUTHENTICATION_RESULTS: HeaderName = HeaderName::standard("Authentication-Results");
+    pub const RECEIVED_SPF: HeaderName = HeaderName::standard("Received-SPF");
+    pub const DELIVERED_TO: HeaderName = HeaderName::standard("Delivered-To");
+    pub const DISPOSITION_NOTIFICATION_TO: HeaderName =
+        HeaderName::standard("Disposition-Notification-To");
+    pub const RETURN_RECEIPT_TO: HeaderName = HeaderName::standard("Return-Receipt-To");
+    pub const LIST_ID: HeaderName = HeaderName::standard("List-Id");
+    pub const LIST_UNSUBSCRIBE: HeaderName = HeaderName::standard("List-Unsubscribe");
+    pub const LIST_UNSUBSCRIBE_POST: HeaderName = HeaderName::standard("List-Unsubscribe-Post");
+    pub const LIST_SUBSCRIBE: HeaderName = HeaderName::standard("List-Subscribe");
+    pub const LIST_POST: HeaderName = HeaderName::standard("List-Post");
+    pub const LIST_HELP: HeaderName = HeaderName::standard("List-Help");
+    pub const LIST_OWNER: HeaderName = HeaderName::standard("List-Owner");
+    pub const LIST_ARCHIVE: HeaderName = HeaderName::standard("List-Archive");
+    pub const PRECEDENCE: HeaderName = HeaderName::standard("Precedence");
+    pub const AUTO_SUBMITTED: HeaderName = HeaderName::standard("Auto-Submitted");
+    pub const IMPORTANCE: HeaderName = HeaderName::standard("Importance");
+    pub const PRIORITY: HeaderName = HeaderName::standard("Priority");
+    pub const X_PRIORITY: HeaderName = HeaderName::standard("X-Priority");
+    pub const ORGANIZATION: HeaderName = HeaderName::standard("Organization");
+    pub const USER_AGENT: HeaderName = HeaderName::standard("User-Agent");
+    pub const X_MAILER: HeaderName = HeaderName::standard("X-Mailer");
+    pub const THREAD_INDEX: HeaderName = HeaderName::standard("Thread-Index");
+    pub const THREAD_TOPIC: HeaderName = HeaderName::standard("Thread-Topic");
+    pub const AUTOCRYPT: HeaderName = HeaderName::standard("Autocrypt");
+    pub const FEEDBACK_ID: HeaderName = HeaderName::standard("Feedback-ID");
+
+    /// Create a header name, keeping the capitalization of `name`.
+    pub fn new<S: Into<String>>(name: S) -> HeaderName {
+        HeaderName::from(name.into())
+    }
+
+    // The standard name `name`, which must be written exactly as it is in
+    // `STANDARD_NAMES`, so that a constant for any other fails to compile.
+    const fn standard(name: &str) -> HeaderName {
+        let mut i = 0;
+        while i < STANDARD_NAMES.len() {
+            let (a, b) = (STANDARD_NAMES[i].as_bytes(), name.as_bytes());
+            if a.len() == b.len() {
+                let mut j = 0;
+                while j < a.len() && a[j] == b[j] {
+                    j += 1;
+                }
+                if j == a.len() {
+                    return HeaderName(Repr::Standard(i as u8));
+                }
+            }
+            i += 1;
+        }
+        panic!("not a standard header name")
+    }
+
+    // The index of the standard name which `name` is, in any capitalization
+    fn standard_index(name: &str) -> Option<u8> {
+        let is_name = |standard: &&str| standard.eq_ignore_ascii_case(name);
+        STANDARD_NAMES.iter().position(is_name).map(|i| i as u8)
+    }
+
+    // The index of the standard name this is, in any capitalization
+    fn index(&self) -> Option<u8> {
+        match self.0 {
+            Repr::Standard(i) => Some(i),
+            Repr::Custom(_, i) => i,
+        }
+    }
+
+    /// Whether this is, in any capitalization, one of the names with a
+    /// constant such as `HeaderName::CONTENT_TYPE`.
+    pub fn is_standard(&self) -> bool {
+        self.index().is_some()
+    }
+
+    /// The name, as it was given.
+    pub fn as_str(&self) -> &str {
+        match self.0 {
+            Repr::Standard(i) => STANDARD_NAMES[i as usize],
+            Repr::Custom(ref name, _) => &name[..],
+        }
+    }
+
+    /// The name in its usual capitalization, such as `Content-Type` or
+    /// `Message-ID`.
+    ///
+    /// Each hyphenated word is title-cased, apart from acronyms such as
+    /// `MIME` which are capitalized in full.
+    pub fn canonical(&self) -> String {
+        let words: Vec<String> = self
+            .as_str()
+            .split('-')
+            .map(|word| {
+                if let Some(upper) = UPPERCASE_WORDS
+                    .iter()
+                    .find(|upper| upper.eq_ignore_ascii_case(word))
+                {
+                    return upper.to_string();
+                }
+                let mut chars = word.chars();
+                match chars.next() {
+                    Some(first) => first
+                        .to_uppercase()
+                        .chain(chars.flat_map(|c| c.to_lowercase()))
+                        .collect(),
+                    None => String::new(),
+                }
+            })
+            .collect();
+        words.join("-")
+    }
+}
+
+impl PartialEq for HeaderName {
+    fn eq(&self, other: &HeaderName) -> bool {
+        // Every capitalization of a standard name has its index, so only
+        // two other names need comparing as strings
+        match (self.index(), other.index()) {
+            (Some(a), Some(b)) => a == b,
+            (None, None) => self.as_str().eq_ignore_ascii_case(other.as_str()),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for HeaderName {}
+
+impl<'a> PartialEq<&'a str> for HeaderName {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_str().eq_ignore_ascii_case(other)
+    }
+}
+
+impl PartialEq<str> for HeaderName {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str().eq_ignore_ascii_case(other)
+    }
+}
+
+impl PartialEq<String> for HeaderName {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str().eq_ignore_ascii_case(other)
+    }
+}
+
+// Ordered as by their lowercased bytes, to agree with equality.
+impl Ord for HeaderName {
+    fn cmp(&self, other: &HeaderName) -> Ordering {
+        if self.index().is_some() && self.index() == other.index() {
+            return Ordering::Equal;
+        }
+        let lower = |b: &u8| b.to_ascii_lowercase();
+        let (a, b) = (self.as_str().as_bytes(), other.as_str().as_bytes());
+        a.iter().map(lower).cmp(b.iter().map(lower))
+    }
+}
+
+impl PartialOrd for HeaderName {
+    fn partial_cmp(&self, other: &HeaderName) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Hash for HeaderName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.index() {
+            Some(i) => state.write_u8(i),
+            None => {
+                for b in self.as_str().bytes() {
+                    state.write_u8(b.to_ascii_lowercase());
+                }
+                state.write_u8(0xff);
+            }
+        }
+    }
+}
+
+impl Deref for HeaderName {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl fmt::Debug for HeaderName {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_tuple("HeaderName").field(&self.as_str()).finish()
+    }
+}
+
+impl fmt::Display for HeaderName {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt(fmt)
+    }
+}
+
+impl From<String> for HeaderName {
+    fn from(name: String) -> HeaderName {
+        match HeaderName::standard_index(&name) {
+            Some(i) if STANDARD_NAMES[i as usize] == name => HeaderName(Repr::Standard(i)),
+            index => HeaderName(Repr::Custom(name, index)),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for HeaderName {
+    fn from(name: &'a str) -> HeaderName {
+        // Standard names are interned without copying them
+        match HeaderName::standard_index(name) {
+            Some(i) if STANDARD_NAMES[i as usize] == name => HeaderName(Repr::Standard(i)),
+            index => HeaderName(Repr::Custom(name.to_string(), index)),
+        }
+    }
+}
+
+/// Why a header was rejected by `Header::try_new`, as its name or value
+/// could be used to add other headers to a message.
+///
+/// Positions are byte offsets into the value.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum HeaderValueError {
+    /// The name is empty, or has a character which isn't printable ASCII,
+    /// or a colon.
+    InvalidName { name: String },
+    /// The value of the header `name` has a CR, LF or NUL.
+    InvalidChar {
+        name: String,
+        pos: usize,
+        found: char,
+    },
+}
+
+impl fmt::Display for HeaderValueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeaderValueError::InvalidName { ref name } => {
+                write!(f, "Invalid header name {:?}", name)
+            }
+            HeaderValueError::InvalidChar {
+                ref name,
+                pos,
+                found,
+            } => write!(f, "Invalid character {:?} at {} in {} header", found, pos, name),
+        }
+    }
+}
+
+impl Error for HeaderValueError {}
+
+impl From<HeaderValueError> for ParsingError {
+    fn from(error: HeaderValueError) -> ParsingError {
+        match error {
+            HeaderValueError::InvalidName { name } => ParsingError::InvalidToken {
+                pos: 0,
+                kind: "header name".to_string(),
+                token: name,
+            },
+            HeaderValueError::InvalidChar { name, pos, found } => ParsingError::UnexpectedChar {
+                pos,
+                found: Some(found),
+                expected: "text".to_string(),
+            }
+            .context(format!("Invalid {} header", name)),
+        }
+    }
+}
+
+/// Check that `value` can be given to a header `name` without adding
+/// other headers to a message, as it has no CR, LF or NUL.
+pub fn check_header_value(name: &str, value: &str) -> Result<(), HeaderValueError> {
+    match value.find(['\r', '\n', '\0']) {
+        Some(pos) => Err(HeaderValueError::InvalidChar {
+            name: name.to_string(),
+            pos,
+            found: value[pos..].chars().next().unwrap(),
+        }),
+        None => Ok(()),
+    }
+}
+
 /// Represents an RFC 822 Header
 /// [unstable]
-#[derive(PartialEq, Eq, Clone, Debug, Hash)]
+#[derive(Clone, Debug)]
 pub struct Header {
     /// The name of this header
-    pub name: String,
+    pub name: HeaderName,
     value: String,
+    folded: Option<String>,
+    span: Option<Range<usize>>,
+    // The whole field as it appeared in the input it was parsed from
+    raw: Option<String>,
+    decoding: HeaderDecoding,
+    // The decoded value, if decoded eagerly and different from the value
+    decoded: Option<String>,
+}
+
+// Where a header was parsed from doesn't affect its identity, so the span
+// is left out of comparisons.
+impl PartialEq for Header {
+    fn eq(&self, other: &Header) -> bool {
+        self.name == other.name && self.value == other.value
+    }
+}
+
+impl Eq for Header {}
+
+impl Hash for Header {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.value.hash(state);
+    }
 }
 
 impl<S: Into<String>, T: Into<String>> From<(S, T)> for Header {
@@ -160,9 +739,74 @@ impl<S: Into<String>, T: Into<String>> From<(S, T)> for Header {
 
 impl Header {
     /// Creates a new Header for the given `name` and `value`
+    ///
+    /// Line breaks can't be used to add other headers: any in the value are
+    /// replaced by spaces, undoing any folding, and any in the name are
+    /// removed. Use `try_new` to reject them instead, or `new_raw` for
+    /// trusted values to be kept as they are.
     /// [unstable]
     pub fn new(name: String, value: String) -> Header {
-        Header { name, value }
+        let name = if name.contains(['\r', '\n']) {
+            name.replace(['\r', '\n'], "")
+        } else {
+            name
+        };
+        let value = if value.contains(['\r', '\n']) {
+            unfold(&value).replace('\r', " ")
+        } else {
+            value
+        };
+        Header::new_raw(name, value)
+    }
+
+    /// Creates a new Header for the given `name` and `value`, failing if
+    /// either could be used to add other headers to a message.
+    ///
+    /// The name must be printable ASCII without a colon, and the value have
+    /// no CR, LF or NUL.
+    pub fn try_new(name: String, value: String) -> Result<Header, HeaderValueError> {
+        let valid = |b: u8| b.is_ascii_graphic() && b != b':';
+        if name.is_empty() || !name.bytes().all(valid) {
+            return Err(HeaderValueError::InvalidName { name });
+        }
+        check_header_value(&name, &value)?;
+        Ok(Header::new_raw(name, value))
+    }
+
+    /// Creates a new Header for the given `name` and `value` exactly as
+    /// they're given, without the checks of `new`.
+    ///
+    /// This is for trusted values only, such as those parsed from a message.
+    pub fn new_raw<N: Into<HeaderName>>(name: N, value: String) -> Header {
+        Header {
+            name: name.into(),
+            value,
+            folded: None,
+            span: None,
+            raw: None,
+            decoding: HeaderDecoding::default(),
+            decoded: None,
+        }
+    }
+
+    // Keep the value as it was before being unfolded, if it was folded.
+    pub(crate) fn with_folded_value(mut self, folded: &str) -> Header {
+        if folded != self.value {
+            self.folded = Some(folded.to_string());
+        }
+        self
+    }
+
+    // Record where this header was found in the input it was parsed from.
+    pub(crate) fn with_span(mut self, span: Range<usize>) -> Header {
+        self.span = Some(span);
+        self
+    }
+
+    // Keep the whole field as it was in the input it was parsed from.
+    pub(crate) fn with_raw_field(mut self, raw: &str) -> Header {
+        self.raw = Some(raw.to_string());
+        self
     }
 
     /// Creates a new Header for the given `name` and `value`,
@@ -173,7 +817,7 @@ impl Header {
     pub fn new_with_value<T: ToFoldedHeader>(name: String, value: T) -> ParsingResult<Header> {
         let header_len = name.len() + 2;
         ToFoldedHeader::to_folded_header(header_len, value)
-            .map(|val| Header::new(name.clone(), val))
+            .map(|val| Header::new(name.clone(), unfold(&val)).with_folded_value(&val))
     }
 
     /// Get the value represented by this header, as parsed
@@ -181,12 +825,93 @@ impl Header {
     /// [unstable]
     pub fn get_value<T: FromHeader>(&self) -> ParsingResult<T> {
         FromHeader::from_header(self.value.clone())
+            .map_err(|e| e.context(format!("Invalid {} header", self.name)))
+    }
+
+    /// Get the value of this header as parsed into the type registered
+    /// for it, or as a String if there is none. See the `registry` module.
+    #[cfg(feature = "std")]
+    pub fn get_any(&self) -> ParsingResult<AnyValue> {
+        registry::parse(&self.name, self.value.clone())
+            .map_err(|e| e.context(format!("Invalid {} header", self.name)))
+    }
+
+    /// Get the value of this header as it appears in the message, without
+    /// any decoding.
+    ///
+    /// Folding is undone for parsed headers, with the whitespace at each
+    /// fold becoming a single space.
+    pub fn raw_value(&self) -> &str {
+        &self.value[..]
+    }
+
+    /// Get the value of this header exactly as it appeared in the input it
+    /// was parsed from, including any folding.
+    ///
+    /// This is the same as `raw_value` for headers which weren't parsed, or
+    /// weren't folded.
+    pub fn folded_value(&self) -> &str {
+        self.folded.as_ref().unwrap_or(&self.value)
+    }
+
+    /// The byte range this header occupied in the input it was parsed from,
+    /// covering the name through to the line break ending the value.
+    ///
+    /// Returns None if the header was created rather than parsed.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+
+    /// When the encoded words in this header are decoded, which is
+    /// `HeaderDecoding::OnDemand` unless set otherwise when parsing.
+    pub fn decoding(&self) -> HeaderDecoding {
+        self.decoding
+    }
+
+    // Decode the encoded words in this header as `decoding` says.
+    #[cfg(feature = "std")]
+    pub(crate) fn set_decoding(&mut self, decoding: HeaderDecoding) {
+        self.decoding = decoding;
+        self.decoded = match decoding {
+            HeaderDecoding::Eager => Some(decode_text(&self.value)).filter(|d| *d != self.value),
+            _ => None,
+        };
+    }
+
+    /// The value of this header with its encoded words decoded, unless its
+    /// decoding is `HeaderDecoding::Raw`, in which case it's the raw value.
+    pub fn decoded_value(&self) -> Cow<'_, str> {
+        match (self.decoding, &self.decoded) {
+            (HeaderDecoding::Raw, _) | (HeaderDecoding::Eager, None) => Cow::Borrowed(&self.value),
+            (HeaderDecoding::Eager, Some(decoded)) => Cow::Borrowed(decoded),
+            (HeaderDecoding::OnDemand, _) => Cow::Owned(decode_text(&self.value)),
+        }
+    }
+
+    /// The whole field exactly as it appeared in the input it was parsed
+    /// from, from the name through to the line break ending the value,
+    /// with any folding and the whitespace around the colon.
+    ///
+    /// Returns None if the header was created rather than parsed, or has
+    /// been renamed since.
+    pub fn raw_field(&self) -> Option<&str> {
+        // `name` may also have been assigned directly
+        self.raw.as_deref().filter(|raw| {
+            let rest = raw.strip_prefix(self.name.as_str());
+            rest.is_some_and(|rest| rest.trim_start_matches([' ', '\t']).starts_with(':'))
+        })
+    }
+
+    // Give the header a new name, which it no longer appeared with.
+    fn set_name(&mut self, name: HeaderName) {
+        self.name = name;
+        self.raw = None;
     }
 }
 
 impl fmt::Display for Header {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "{}: {}", self.name, self.value)
+        write!(fmt, "{}: {}", self.name, self.folded_value())
     }
 }
 
@@ -226,7 +951,7 @@ pub struct HeaderMap {
     // and all of the headers with that name.
     //
     // This allows quick retrival of a header by name.
-    headers: HashMap<String, Vec<Arc<Header>>>,
+    headers: BTreeMap<HeaderName, Vec<Arc<Header>>>,
 }
 
 impl HeaderMap {
@@ -234,7 +959,7 @@ impl HeaderMap {
     pub fn new() -> HeaderMap {
         HeaderMap {
             ordered_headers: Vec::new(),
-            headers: HashMap::new(),
+            headers: BTreeMap::new(),
         }
     }
 
@@ -262,6 +987,10 @@ impl HeaderMap {
     }
 
     /// Replace an existing, or insert new.
+    ///
+    /// The new header takes the position of the first header with the same
+    /// name, and any others with that name are removed. If there are none,
+    /// it is added at the end.
     pub fn replace(&mut self, header: Header) {
         let header_name = header.name.clone();
         let rc = Arc::new(header);
@@ -271,16 +1000,16 @@ impl HeaderMap {
                 let i = self
                     .ordered_headers
                     .iter()
-                    .position(|v| v == header)
+                    .position(|v| Arc::ptr_eq(v, header))
                     .expect("Inconsistent headers");
 
-                std::mem::replace(&mut self.ordered_headers[i], rc.clone());
+                core::mem::replace(&mut self.ordered_headers[i], rc.clone());
             }
             for header in headers.iter().skip(1) {
                 let i = self
                     .ordered_headers
                     .iter()
-                    .position(|v| v == header)
+                    .position(|v| Arc::ptr_eq(v, header))
                     .expect("Inconsistent headers");
                 self.ordered_headers.remove(i);
             }
@@ -305,6 +1034,136 @@ impl HeaderMap {
         }
     }
 
+    /// Remove all of the headers with `name`, returning them in order.
+    pub fn remove_all(&mut self, name: &str) -> Vec<Header> {
+        let removed = match self.headers.remove(&HeaderName::from(name)) {
+            Some(removed) => removed,
+            None => return Vec::new(),
+        };
+        self.ordered_headers
+            .retain(|header| !removed.iter().any(|r| Arc::ptr_eq(r, header)));
+        removed
+            .into_iter()
+            .map(|rc| Arc::try_unwrap(rc).unwrap_or_else(|rc| (*rc).clone()))
+            .collect()
+    }
+
+    /// Insert a header at `index` among all of the headers, counting from 0,
+    /// or at the end if there are fewer headers than that.
+    pub fn insert_at(&mut self, index: usize, header: Header) {
+        let index = index.min(self.ordered_headers.len());
+        self.ordered_headers.insert(index, Arc::new(header));
+        self.reindex();
+    }
+
+    /// Replace the `n`th header with `name`, counting from 0, keeping its
+    /// position. Returns false if there are fewer headers with the name.
+    pub fn replace_nth(&mut self, name: &str, n: usize, header: Header) -> bool {
+        match self.position_of(name, n) {
+            Some(i) => {
+                self.ordered_headers[i] = Arc::new(header);
+                self.reindex();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove the `n`th header with `name`, counting from 0.
+    pub fn remove_nth(&mut self, name: &str, n: usize) -> Option<Header> {
+        let i = self.position_of(name, n)?;
+        let removed = self.ordered_headers.remove(i);
+        self.reindex();
+        Some(Arc::try_unwrap(removed).unwrap_or_else(|rc| (*rc).clone()))
+    }
+
+    // The position in the ordered list of the `n`th header with `name`.
+    fn position_of(&self, name: &str, n: usize) -> Option<usize> {
+        let header = self.headers.get(&HeaderName::from(name))?.get(n)?;
+        self.ordered_headers
+            .iter()
+            .position(|v| Arc::ptr_eq(v, header))
+    }
+
+    /// Rename all of the headers with `from` to `to`, keeping their
+    /// positions and values.
+    pub fn rename(&mut self, from: &str, to: &str) {
+        if !self.headers.contains_key(&HeaderName::from(from)) {
+            return;
+        }
+        for rc in self.ordered_headers.iter_mut() {
+            if rc.name == from {
+                Arc::make_mut(rc).set_name(HeaderName::from(to));
+            }
+        }
+        self.reindex();
+    }
+
+    /// Give every header its usual capitalization, as from
+    /// `HeaderName::canonical`, for when the names should be tidied on output.
+    pub fn canonicalize_names(&mut self) {
+        for rc in self.ordered_headers.iter_mut() {
+            let canonical = rc.name.canonical();
+            if rc.name.as_str() != canonical {
+                Arc::make_mut(rc).set_name(HeaderName::from(canonical));
+            }
+        }
+        self.reindex();
+    }
+
+    /// Keep only the headers for which `keep` returns true, in their
+    /// existing order.
+    pub fn retain<F: FnMut(&Header) -> bool>(&mut self, mut keep: F) {
+        self.ordered_headers.retain(|header| keep(header));
+        self.reindex();
+    }
+
+    // The headers in order, shared with the map, so that whether it has
+    // been changed since can be told with `is_snapshot`.
+    #[cfg(feature = "std")]
+    pub(crate) fn snapshot(&self) -> Vec<Arc<Header>> {
+        self.ordered_headers.clone()
+    }
+
+    // Whether the map holds just the headers of `snapshot`, in order, none
+    // of them having been changed.
+    #[cfg(feature = "std")]
+    pub(crate) fn is_snapshot(&self, snapshot: &[Arc<Header>]) -> bool {
+        self.ordered_headers.len() == snapshot.len()
+            && self
+                .ordered_headers
+                .iter()
+                .zip(snapshot.iter())
+                .all(|(header, original)| Arc::ptr_eq(header, original))
+    }
+
+    // Rebuild the mapping from names to headers after the ordered list of
+    // headers has been changed.
+    // Set the decoding of each header to the one `policy` gives for it.
+    #[cfg(feature = "std")]
+    pub(crate) fn apply_decode_policy(&mut self, policy: &HeaderDecodePolicy) {
+        if *policy == HeaderDecodePolicy::default() {
+            return;
+        }
+        for rc in self.ordered_headers.iter_mut() {
+            let decoding = policy.decoding(&rc.name);
+            if rc.decoding != decoding {
+                Arc::make_mut(rc).set_decoding(decoding);
+            }
+        }
+        self.reindex();
+    }
+
+    fn reindex(&mut self) {
+        self.headers.clear();
+        for rc in self.ordered_headers.iter() {
+            self.headers
+                .entry(rc.name.clone())
+                .or_default()
+                .push(rc.clone());
+        }
+    }
+
     /// Get an Iterator over the collection of headers.
     /// [unstable]
     pub fn iter(&self) -> HeaderIter {
@@ -315,7 +1174,7 @@ impl HeaderMap {
     /// [unstable]
     pub fn get(&self, name: String) -> Option<&Header> {
         self.headers
-            .get(&name)
+            .get(&HeaderName::from(name))
             .map(|headers| headers.last().unwrap())
             .map(|rc| rc.deref())
     }
@@ -323,9 +1182,22 @@ impl HeaderMap {
     /// Get the last value of the header with `name`, as a decoded type.
     /// [unstable]
     pub fn get_value<T: FromHeader>(&self, name: String) -> ParsingResult<T> {
-        match self.get(name) {
+        match self.get(name.clone()) {
             Some(ref header) => header.get_value(),
-            None => Err(ParsingError::new("Couldn't find header value.".to_string())),
+            None => Err(ParsingError::MissingHeader { name }),
+        }
+    }
+
+    /// Get the last value of the header registered for `T`, see the
+    /// `registry` module.
+    #[cfg(feature = "std")]
+    pub fn get_typed<T: FromHeader + 'static>(&self) -> ParsingResult<T> {
+        match registry::registered_name::<T>() {
+            Some(name) => self.get_value(name.as_str().to_string()),
+            None => Err(ParsingError::new(format!(
+                "No header is registered for {}",
+                std::any::type_name::<T>()
+            ))),
         }
     }
 
@@ -339,12 +1211,35 @@ impl HeaderMap {
         self.len() == 0
     }
 
+    /// The fields of every header with `name`, in order, each exactly as it
+    /// appeared in the input from its name through to its line break, as
+    /// DKIM and ARC verification need.
+    ///
+    /// Headers which weren't parsed are given folded as they would be
+    /// written, see `Header::raw_field`.
+    pub fn get_raw(&self, name: &str) -> Vec<Cow<'_, [u8]>> {
+        let headers = match self.headers.get(&HeaderName::from(name)) {
+            Some(headers) => headers,
+            None => return Vec::new(),
+        };
+        headers
+            .iter()
+            .map(|header| match header.raw_field() {
+                Some(raw) => Cow::Borrowed(raw.as_bytes()),
+                None => {
+                    let folded = fold(&header.name, &header.value, &FoldingPolicy::default());
+                    Cow::Owned(format!("{}\r\n", folded).into_bytes())
+                }
+            })
+            .collect()
+    }
+
     /// [unstable]
     /// Find a list of headers of `name`, `None` if there
     /// are no headers with that name.
     pub fn find(&self, name: &str) -> Option<Vec<&Header>> {
         self.headers
-            .get(name)
+            .get(&HeaderName::from(name))
             .map(|rcs| rcs.iter().map(|rc| rc.deref()).collect())
     }
 }
@@ -355,10 +1250,115 @@ impl Default for HeaderMap {
     }
 }
 
+/// The headers found by `scan`, with their values unfolded but otherwise
+/// raw, borrowed from the input where they weren't folded.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ScannedHeaders<'a> {
+    // Each header found, in order, with its name as it was in the input
+    found: Vec<(&'a str, Cow<'a, str>)>,
+}
+
+impl<'a> ScannedHeaders<'a> {
+    /// The value of the last header with `name`, as with `HeaderMap::get`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.found
+            .iter()
+            .rev()
+            .find(|(found, _)| found.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// The values of every header with `name`, in order.
+    pub fn get_all<'s>(&'s self, name: &'s str) -> impl Iterator<Item = &'s str> + 's {
+        self.found
+            .iter()
+            .filter(move |(found, _)| found.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// The number of headers found.
+    pub fn len(&self) -> usize {
+        self.found.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.found.is_empty()
+    }
+}
+
+/// Find the values of only the headers with `names` in the message
+/// `input`, in a single pass over its header section, without parsing the
+/// rest of the headers or the body.
+///
+/// This is a fast path for filtering many messages on a few headers. The
+/// values are unfolded as `Header::raw_value` gives them, but aren't
+/// otherwise checked, and invalid UTF-8 in them is replaced.
+pub fn scan<'a>(input: &'a [u8], names: &[&str]) -> ScannedHeaders<'a> {
+    let is_whitespace = |b: &u8| *b == b' ' || *b == b'\t';
+    let trim_end = |s: &mut Vec<u8>| {
+        while s.last().is_some_and(is_whitespace) {
+            s.pop();
+        }
+    };
+    // A line without the whitespace at its start or its line break
+    let trim = |line: &'a [u8]| {
+        let start = line.iter().take_while(|b| is_whitespace(b)).count();
+        let line = &line[start..];
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        line.strip_suffix(b"\r").unwrap_or(line)
+    };
+
+    let mut found = Vec::new();
+    let mut lines = input.split_inclusive(|&b| b == b'\n').peekable();
+    while let Some(line) = lines.next() {
+        if line == b"\n" || line == b"\r\n" {
+            break;
+        }
+        let wanted = match line.iter().position(|&b| b == b':') {
+            Some(colon) if !line.first().is_some_and(is_whitespace) => {
+                core::str::from_utf8(&line[..colon])
+                    .ok()
+                    .map(|name| name.trim_end_matches(&[' ', '\t'][..]))
+                    .filter(|name| names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+                    .map(|name| (name, &line[colon + 1..]))
+            }
+            _ => None,
+        };
+
+        // The continuation lines of a header are taken with it, whether or
+        // not it's wanted
+        let mut folded: Option<Vec<u8>> = None;
+        while let Some(next) = lines.next_if(|next| next.first().is_some_and(is_whitespace)) {
+            let (_, value) = match wanted {
+                Some(wanted) => wanted,
+                None => continue,
+            };
+            let unfolded = folded.get_or_insert_with(|| trim(value).to_vec());
+            let next = trim(next);
+            if !next.is_empty() {
+                trim_end(unfolded);
+                if !unfolded.is_empty() {
+                    unfolded.push(b' ');
+                }
+                unfolded.extend_from_slice(next);
+            }
+        }
+
+        if let Some((name, value)) = wanted {
+            let value = match folded {
+                Some(unfolded) => Cow::Owned(String::from_utf8_lossy(&unfolded).into_owned()),
+                None => String::from_utf8_lossy(trim(value)),
+            };
+            found.push((name, value));
+        }
+    }
+    ScannedHeaders { found }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashSet;
+    use std::collections::{BTreeSet, HashSet};
 
     use chrono::offset::TimeZone;
     use chrono::{DateTime, FixedOffset, Utc};
@@ -377,6 +1377,39 @@ mod tests {
             .collect()
     }
 
+    #[test]
+    fn test_header_line_breaks() {
+        let header = Header::new(
+            "X-Test\r\n".to_string(),
+            "a\r\nBcc: victim@example.net\r b".to_string(),
+        );
+        assert_eq!(header.name, "X-Test");
+        assert_eq!(header.raw_value(), "a Bcc: victim@example.net  b");
+        let header = Header::new("Subject".to_string(), "A\r\n folded value".to_string());
+        assert_eq!(header.raw_value(), "A folded value");
+
+        assert_eq!(
+            Header::try_new("Subject".to_string(), "a\nb".to_string()).unwrap_err(),
+            HeaderValueError::InvalidChar {
+                name: "Subject".to_string(),
+                pos: 1,
+                found: '\n',
+            }
+        );
+        for name in ["", "Bad Name", "Bad:Name"].iter() {
+            assert_eq!(
+                Header::try_new(name.to_string(), "value".to_string()).unwrap_err(),
+                HeaderValueError::InvalidName {
+                    name: name.to_string()
+                }
+            );
+        }
+        assert!(Header::try_new("Subject".to_string(), "Hi".to_string()).is_ok());
+
+        let header = Header::new_raw("X-Raw".to_string(), "a\r\n b".to_string());
+        assert_eq!(header.raw_value(), "a\r\n b");
+    }
+
     #[test]
     fn test_header_to_string() {
         let header = Header::new("Test".to_string(), "Value".to_string());
@@ -400,9 +1433,13 @@ mod tests {
                 result: Some("Test text"),
             },
             HeaderTest {
-                input: "=?ISO-8859-1?Q?Multiple?= =?utf-8?b?ZW5jb2Rpbmdz?=",
+                input: "=?ISO-8859-1?Q?Multiple_?= =?utf-8?b?ZW5jb2Rpbmdz?=",
                 result: Some("Multiple encodings"),
             },
+            HeaderTest {
+                input: "=?utf-8?q?Spl?=\r\n =?utf-8?q?it?= =?utf-8?q?_words?= and =?utf-8?q?text?=",
+                result: Some("Split words and text"),
+            },
             HeaderTest {
                 input: "Some things with =?utf-8?b?ZW5jb2Rpbmdz?=, other things without.",
                 result: Some("Some things with encodings, other things without."),
@@ -443,6 +1480,14 @@ mod tests {
         assert_eq!(dt_value, Utc.ymd(2014, 12, 17).and_hms(8, 35, 7));
     }
 
+    #[test]
+    fn test_datetime_to_header() {
+        let dt = FixedOffset::east(3600).ymd(2014, 12, 17).and_hms(9, 35, 7);
+        let header = Header::new_with_value("Date".to_string(), dt).unwrap();
+        assert_eq!(header.to_string(), "Date: Wed, 17 Dec 2014 09:35:07 +0100");
+        assert_eq!(header.get_value::<DateTime<FixedOffset>>().unwrap(), dt);
+    }
+
     #[test]
     fn test_to_header_string() {
         let header = Header::new_with_value("Test".to_string(), "Value".to_string()).unwrap();
@@ -502,6 +1547,255 @@ mod tests {
         assert_eq!(headers.len(), 4);
     }
 
+    #[test]
+    fn test_header_map_get_raw() {
+        let input = "Received: from a\r\n\tby b\r\n\
+                     Subject :Hello\r\n\
+                     received: from c\r\n\r\n";
+        let mut headers = crate::rfc5322::Rfc5322Parser::new(input).consume_headers();
+        headers.insert(Header::new("Received".to_string(), "from d".to_string()));
+        assert_eq!(
+            headers.get_raw("Received"),
+            vec![
+                &b"Received: from a\r\n\tby b\r\n"[..],
+                &b"received: from c\r\n"[..],
+                &b"Received: from d\r\n"[..],
+            ]
+        );
+        assert_eq!(headers.get_raw("subject"), vec![&b"Subject :Hello\r\n"[..]]);
+        assert!(headers.get_raw("To").is_empty());
+
+        headers.rename("Subject", "X-Subject");
+        let subject = headers.get("X-Subject".to_string()).unwrap();
+        assert_eq!(subject.raw_field(), None);
+        assert_eq!(
+            headers.get_raw("X-Subject"),
+            vec![&b"X-Subject: Hello\r\n"[..]]
+        );
+
+        let mut headers = crate::rfc5322::Rfc5322Parser::new(input).consume_headers();
+        headers.rename("Subject", "Sub");
+        assert_eq!(headers.get("Sub".to_string()).unwrap().raw_field(), None);
+        headers.canonicalize_names();
+        let received = headers.find("Received").unwrap();
+        assert!(received[0].raw_field().is_some());
+        assert_eq!(received[1].raw_field(), None);
+    }
+
+    #[test]
+    fn test_header_decode_policy() {
+        let mut policy = HeaderDecodePolicy::new(HeaderDecoding::Eager);
+        policy.set("subject", HeaderDecoding::Raw);
+        assert_eq!(policy.decoding("Subject"), HeaderDecoding::Raw);
+        assert_eq!(policy.decoding("From"), HeaderDecoding::Eager);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::from(("Subject", "=?utf-8?q?caf=C3=A9?=")));
+        headers.insert(Header::from(("Comments", "=?utf-8?q?th=C3=A9?=")));
+        headers.insert(Header::from(("To", "joe@example.org")));
+        let subject = headers.get("Subject".to_string()).unwrap();
+        assert_eq!(subject.decoded_value(), "café");
+        headers.apply_decode_policy(&policy);
+
+        let subject = headers.get("Subject".to_string()).unwrap();
+        assert_eq!(subject.decoding(), HeaderDecoding::Raw);
+        assert_eq!(subject.decoded_value(), "=?utf-8?q?caf=C3=A9?=");
+        let comments = headers.get("Comments".to_string()).unwrap();
+        assert_eq!(comments.decoded_value(), "thé");
+        assert_eq!(comments.raw_value(), "=?utf-8?q?th=C3=A9?=");
+        let to = headers.get("To".to_string()).unwrap();
+        assert_eq!(to.decoded_value(), "joe@example.org");
+    }
+
+    #[test]
+    fn test_scan() {
+        let input = b"Received: from a\r\n\
+                      \tby b\r\n\
+                      Subject: Lunch\r\n \r\n today \r\n\
+                      X-Spam-Score: 5.2\r\n\
+                      received: from c\r\n\
+                      To: joe@example.org\r\n\
+                      \r\n\
+                      X-Spam-Score: 0\r\n";
+        let scanned = scan(input, &["received", "Subject", "X-Spam-Score", "Cc"]);
+        assert_eq!(scanned.len(), 4);
+        assert_eq!(
+            scanned.get_all("Received").collect::<Vec<_>>(),
+            vec!["from a by b", "from c"]
+        );
+        assert_eq!(scanned.get("Received"), Some("from c"));
+        assert_eq!(scanned.get("subject"), Some("Lunch today "));
+        // Headers after the blank line are part of the body
+        assert_eq!(scanned.get("X-Spam-Score"), Some("5.2"));
+        assert_eq!(scanned.get("To"), None);
+        assert_eq!(scanned.get("Cc"), None);
+
+        let headers = crate::rfc5322::Rfc5322Parser::new(core::str::from_utf8(input).unwrap())
+            .consume_headers();
+        assert_eq!(
+            scanned.get("Subject"),
+            Some(headers.get("Subject".to_string()).unwrap().raw_value())
+        );
+        let scanned = scan(b"Subject: caf\xe9\n\n", &["Subject"]);
+        assert_eq!(scanned.get("Subject"), Some("caf\u{FFFD}"));
+        assert!(scan(b"", &["Subject"]).is_empty());
+    }
+
+    #[test]
+    fn test_header_map_edit() {
+        let mut headers = HeaderMap::new();
+        for &(name, value) in [
+            ("Received", "from a"),
+            ("X-Spam", "yes"),
+            ("Received", "from b"),
+            ("Subject", "Hi"),
+            ("X-Spam", "no"),
+        ]
+        .iter()
+        {
+            headers.insert(Header::new(name.to_string(), value.to_string()));
+        }
+        let names = |headers: &HeaderMap| -> Vec<String> {
+            headers
+                .iter()
+                .map(|h| format!("{}: {}", h.name, h.raw_value()))
+                .collect()
+        };
+
+        let removed = headers.remove_all("X-Spam");
+        assert_eq!(
+            removed,
+            vec![
+                Header::new("X-Spam".to_string(), "yes".to_string()),
+                Header::new("X-Spam".to_string(), "no".to_string()),
+            ]
+        );
+        assert!(headers.find("X-Spam").is_none());
+        assert!(headers.remove_all("X-Spam").is_empty());
+
+        headers.rename("Received", "X-Original-Received");
+        assert!(headers.find("Received").is_none());
+        assert_eq!(headers.find("X-Original-Received").unwrap().len(), 2);
+        assert_eq!(
+            names(&headers),
+            vec![
+                "X-Original-Received: from a",
+                "X-Original-Received: from b",
+                "Subject: Hi",
+            ]
+        );
+
+        headers.retain(|h| h.raw_value() != "from a");
+        assert_eq!(
+            names(&headers),
+            vec!["X-Original-Received: from b", "Subject: Hi"]
+        );
+        assert_eq!(
+            headers
+                .get_value::<String>("X-Original-Received".to_string())
+                .unwrap(),
+            "from b"
+        );
+
+        headers.replace(Header::new("Subject".to_string(), "Bye".to_string()));
+        assert_eq!(
+            names(&headers),
+            vec!["X-Original-Received: from b", "Subject: Bye"]
+        );
+
+        headers.insert_at(1, Header::new("To".to_string(), "b@x".to_string()));
+        headers.insert_at(9, Header::new("To".to_string(), "c@x".to_string()));
+        assert!(headers.replace_nth("to", 1, Header::new("To".to_string(), "d@x".to_string())));
+        assert!(!headers.replace_nth("To", 2, Header::new("To".to_string(), "e@x".to_string())));
+        assert_eq!(
+            names(&headers),
+            vec!["X-Original-Received: from b", "To: b@x", "Subject: Bye", "To: d@x"]
+        );
+        assert_eq!(
+            headers.remove_nth("To", 0),
+            Some(Header::new("To".to_string(), "b@x".to_string()))
+        );
+        assert_eq!(headers.remove_nth("To", 1), None);
+        assert_eq!(headers.get_value::<String>("To".to_string()).unwrap(), "d@x");
+        assert_eq!(headers.len(), 3);
+    }
+
+    #[test]
+    fn test_header_name() {
+        let name = HeaderName::from("content-type");
+        assert_eq!(name, HeaderName::from("Content-Type"));
+        assert_eq!(name, "CONTENT-TYPE");
+        assert_eq!(name.as_str(), "content-type");
+        assert_eq!(name.to_string(), "content-type");
+
+        let mut names = HashSet::new();
+        names.insert(name);
+        assert!(names.contains(&HeaderName::from("Content-type")));
+
+        for &(name, canonical) in [
+            ("content-type", "Content-Type"),
+            ("MESSAGE-ID", "Message-ID"),
+            ("mime-version", "MIME-Version"),
+            ("dkim-signature", "DKIM-Signature"),
+            ("x-mailer", "X-Mailer"),
+            ("Content-Id", "Content-ID"),
+            ("received-spf", "Received-SPF"),
+        ]
+        .iter()
+        {
+            assert_eq!(HeaderName::from(name).canonical(), canonical);
+        }
+    }
+
+    #[test]
+    fn test_standard_header_names() {
+        assert_eq!(HeaderName::CONTENT_TYPE, HeaderName::from("Content-Type"));
+        assert_eq!(HeaderName::CONTENT_TYPE, HeaderName::from("content-TYPE"));
+        assert_ne!(HeaderName::CONTENT_TYPE, HeaderName::CONTENT_ID);
+        assert_ne!(HeaderName::CONTENT_TYPE, HeaderName::from("Content-Types"));
+        assert_eq!(HeaderName::MESSAGE_ID.as_str(), "Message-ID");
+        // Other capitalizations are kept as they were given
+        assert_eq!(HeaderName::from("message-id").as_str(), "message-id");
+        assert_eq!(
+            HeaderName::from("X-Custom"),
+            HeaderName::new("x-custom".to_string())
+        );
+
+        let mut names = HashSet::new();
+        names.insert(HeaderName::from("SUBJECT"));
+        assert!(names.contains(&HeaderName::SUBJECT));
+        let mut names = BTreeSet::new();
+        names.insert(HeaderName::from("received"));
+        assert!(names.contains(&HeaderName::RECEIVED));
+
+        for (i, name) in STANDARD_NAMES.iter().enumerate() {
+            assert_eq!(HeaderName::standard_index(name), Some(i as u8), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_header_map_case_insensitive() {
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new("subject".to_string(), "Hi".to_string()));
+        headers.insert(Header::new("X-MAILER".to_string(), "Test".to_string()));
+
+        assert_eq!(
+            headers.get_value::<String>("Subject".to_string()).unwrap(),
+            "Hi"
+        );
+        assert_eq!(headers.find("x-mailer").unwrap().len(), 1);
+        headers.insert(Header::new("X-Mailer".to_string(), "Other".to_string()));
+        assert_eq!(headers.find("x-mailer").unwrap().len(), 2);
+
+        // The original capitalization is kept until asked otherwise
+        let lines: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+        assert_eq!(lines, vec!["subject: Hi", "X-MAILER: Test", "X-Mailer: Other"]);
+        headers.canonicalize_names();
+        let lines: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+        assert_eq!(lines, vec!["Subject: Hi", "X-Mailer: Test", "X-Mailer: Other"]);
+        assert_eq!(headers.find("X-MAILER").unwrap().len(), 2);
+    }
+
     #[test]
     fn test_header_map_iter() {
         let mut headers = HeaderMap::new();
@@ -520,4 +1814,56 @@ mod tests {
         // And that there is the right number of them
         assert_eq!(count, expected_headers.len());
     }
+
+    #[test]
+    fn test_fold() {
+        let policy = FoldingPolicy::default();
+        assert_eq!(fold("Subject", "Short", &policy), "Subject: Short");
+
+        // Folds after the comma, rather than in the display name
+        let value = "Joe Blogs <joe@example.org>, John Doe <john@example.org>, \
+                     Mr Black <mafia_black@example.org>";
+        assert_eq!(
+            fold("Cc", value, &policy),
+            "Cc: Joe Blogs <joe@example.org>, John Doe <john@example.org>,\r\n \
+             Mr Black <mafia_black@example.org>"
+        );
+
+        // Never inside a quoted string, or an encoded word
+        let value = "\"A very long quoted display name which goes past the limit\" <a@example.org>";
+        let folded = fold("From", value, &policy);
+        assert_eq!(
+            folded,
+            "From: \"A very long quoted display name which goes past the limit\"\r\n \
+             <a@example.org>"
+        );
+        let words = "=?utf-8?q?Gr=C3=BC=C3=9Fe_aus_K=C3=B6ln?= ".repeat(3);
+        let folded = fold("Subject", words.trim(), &policy);
+        assert!(folded.split("\r\n").all(|line| line.len() <= 78));
+        assert!(folded
+            .split_whitespace()
+            .skip(1)
+            .all(|word| word.starts_with("=?") && word.ends_with("?=")));
+
+        // Existing folding is undone first
+        let refolded = fold("Cc", &fold("Cc", "a, b", &policy)[4..], &policy);
+        assert_eq!(refolded, "Cc: a, b");
+
+        // Only broken up without whitespace when over the hard limit
+        let policy = FoldingPolicy {
+            line_length: 10,
+            max_line_length: 20,
+        };
+        assert_eq!(
+            fold("X", &"a".repeat(30), &policy),
+            format!("X: {}\r\n {}", "a".repeat(17), "a".repeat(13))
+        );
+        assert_eq!(fold("X", "aaaa bbbb cccc", &policy), "X: aaaa\r\n bbbb cccc");
+
+        // A name at or past the hard limit leaves nothing to break up
+        let name = "X".repeat(997);
+        let policy = FoldingPolicy::default();
+        assert_eq!(fold(&name, "", &policy), format!("{}: ", name));
+        assert_eq!(fold(&name, "ab", &policy), format!("{}: a\r\n b", name));
+    }
 }
@@ -0,0 +1,247 @@
+//! Helpers for serving messages over IMAP (RFC 3501).
+use std::fmt;
+
+use super::address::{Address, Mailbox};
+use super::builder::encode_header_text;
+use super::header::{FromHeader, Header};
+use super::message::MimeMessage;
+
+/// An address in an IMAP envelope, as the quadruple of display name,
+/// source route, mailbox name and host name.
+///
+/// Groups are represented by a start marker, with no host name and the
+/// group name as the mailbox name, followed by the members and an end
+/// marker, which has every field set to NIL.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct EnvelopeAddress {
+    pub name: Option<String>,
+    pub adl: Option<String>,
+    pub mailbox: Option<String>,
+    pub host: Option<String>,
+}
+
+impl EnvelopeAddress {
+    fn from_mailbox(mbox: &Mailbox) -> EnvelopeAddress {
+        // A missing domain is sent as an empty host name, as NIL would mark
+        // the start of a group.
+        let (mailbox, host) = match mbox.address.rfind('@') {
+            Some(i) => (&mbox.address[..i], &mbox.address[i + 1..]),
+            None => (&mbox.address[..], ""),
+        };
+        EnvelopeAddress {
+            name: mbox.name.as_ref().map(|name| encode_header_text(name)),
+            adl: None,
+            mailbox: Some(mailbox.to_string()),
+            host: Some(host.to_string()),
+        }
+    }
+
+    fn group_start(name: &str) -> EnvelopeAddress {
+        EnvelopeAddress {
+            mailbox: Some(encode_header_text(name)),
+            ..Default::default()
+        }
+    }
+
+    fn group_end() -> EnvelopeAddress {
+        EnvelopeAddress::default()
+    }
+
+    fn from_addresses(addresses: &[Address]) -> Vec<EnvelopeAddress> {
+        let mut result = Vec::new();
+        for address in addresses.iter() {
+            match *address {
+                Address::Mailbox(ref mbox) => result.push(EnvelopeAddress::from_mailbox(mbox)),
+                Address::Group(ref name, ref mboxes) => {
+                    result.push(EnvelopeAddress::group_start(name));
+                    result.extend(mboxes.iter().map(EnvelopeAddress::from_mailbox));
+                    result.push(EnvelopeAddress::group_end());
+                }
+            }
+        }
+        result
+    }
+}
+
+impl fmt::Display for EnvelopeAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "({} {} {} {})",
+            nstring(&self.name),
+            nstring(&self.adl),
+            nstring(&self.mailbox),
+            nstring(&self.host)
+        )
+    }
+}
+
+/// The envelope structure of a message, as returned by the IMAP `FETCH
+/// ENVELOPE` command.
+///
+/// The date, subject and identifiers are left undecoded, as they appear in
+/// the message. Fields which are missing or empty are NIL.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Envelope {
+    pub date: Option<String>,
+    pub subject: Option<String>,
+    pub from: Vec<EnvelopeAddress>,
+    pub sender: Vec<EnvelopeAddress>,
+    pub reply_to: Vec<EnvelopeAddress>,
+    pub to: Vec<EnvelopeAddress>,
+    pub cc: Vec<EnvelopeAddress>,
+    pub bcc: Vec<EnvelopeAddress>,
+    pub in_reply_to: Option<String>,
+    pub message_id: Option<String>,
+}
+
+impl fmt::Display for Envelope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({} {}", nstring(&self.date), nstring(&self.subject))?;
+        for addresses in [
+            &self.from,
+            &self.sender,
+            &self.reply_to,
+            &self.to,
+            &self.cc,
+            &self.bcc,
+        ]
+        .iter()
+        {
+            if addresses.is_empty() {
+                write!(f, " NIL")?;
+            } else {
+                write!(f, " (")?;
+                for address in addresses.iter() {
+                    write!(f, "{}", address)?;
+                }
+                write!(f, ")")?;
+            }
+        }
+        write!(
+            f,
+            " {} {})",
+            nstring(&self.in_reply_to),
+            nstring(&self.message_id)
+        )
+    }
+}
+
+// Format a string as an IMAP nstring: NIL, a quoted string, or a literal if
+// it can't be quoted.
+fn nstring(value: &Option<String>) -> String {
+    match *value {
+        None => "NIL".to_string(),
+        Some(ref s) => {
+            if s.bytes()
+                .all(|b| b > 0 && b < 0x80 && b != b'\r' && b != b'\n')
+            {
+                format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+            } else {
+                format!("{{{}}}\r\n{}", s.len(), s)
+            }
+        }
+    }
+}
+
+// The first header named `name`, ignoring case.
+fn first_header<'a>(message: &'a MimeMessage, name: &str) -> Option<&'a Header> {
+    message
+        .headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case(name))
+}
+
+fn raw_header(message: &MimeMessage, name: &str) -> Option<String> {
+    first_header(message, name)
+        .map(|header| header.raw_value().trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+fn address_header(message: &MimeMessage, name: &str) -> Vec<EnvelopeAddress> {
+    first_header(message, name)
+        .and_then(|header| Vec::<Address>::from_header(header.raw_value().to_string()).ok())
+        .map(|addresses| EnvelopeAddress::from_addresses(&addresses[..]))
+        .unwrap_or_default()
+}
+
+/// Build the IMAP envelope structure for `message`.
+///
+/// As recommended by RFC 3501, the sender and reply-to fields default to
+/// the from field if their headers are missing.
+pub fn envelope(message: &MimeMessage) -> Envelope {
+    let from = address_header(message, "From");
+    let mut sender = address_header(message, "Sender");
+    if sender.is_empty() {
+        sender = from.clone();
+    }
+    let mut reply_to = address_header(message, "Reply-To");
+    if reply_to.is_empty() {
+        reply_to = from.clone();
+    }
+
+    Envelope {
+        date: raw_header(message, "Date"),
+        subject: raw_header(message, "Subject"),
+        from,
+        sender,
+        reply_to,
+        to: address_header(message, "To"),
+        cc: address_header(message, "Cc"),
+        bcc: address_header(message, "Bcc"),
+        in_reply_to: raw_header(message, "In-Reply-To"),
+        message_id: raw_header(message, "Message-ID"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope() {
+        let message = MimeMessage::parse(
+            "Date: Wed, 17 Dec 2014 09:35:07 +0100\r\n\
+             From: \"Joe \\\"JB\\\" Blogs\" <joe@example.org>\r\n\
+             Subject: =?utf-8?q?Gr=C3=BC=C3=9Fe?=\r\n\
+             To: jim@localhost, Team: jane@example.org, John <john@example.org>;\r\n\
+             Message-Id: <1@example.org>\r\n\
+             \r\n\
+             Hello!",
+        )
+        .unwrap();
+
+        let env = envelope(&message);
+        assert_eq!(env.subject.as_ref().unwrap(), "=?utf-8?q?Gr=C3=BC=C3=9Fe?=");
+        assert_eq!(env.sender, env.from);
+        assert_eq!(env.reply_to, env.from);
+        assert_eq!(env.to.len(), 5);
+        assert_eq!(env.message_id.as_ref().unwrap(), "<1@example.org>");
+        assert_eq!(
+            env.to_string(),
+            "(\"Wed, 17 Dec 2014 09:35:07 +0100\" \"=?utf-8?q?Gr=C3=BC=C3=9Fe?=\" \
+             ((\"Joe \\\"JB\\\" Blogs\" NIL \"joe\" \"example.org\")) \
+             ((\"Joe \\\"JB\\\" Blogs\" NIL \"joe\" \"example.org\")) \
+             ((\"Joe \\\"JB\\\" Blogs\" NIL \"joe\" \"example.org\")) \
+             ((NIL NIL \"jim\" \"localhost\")(NIL NIL \"Team\" NIL)\
+             (NIL NIL \"jane\" \"example.org\")\
+             (\"John\" NIL \"john\" \"example.org\")(NIL NIL NIL NIL)) \
+             NIL NIL NIL \"<1@example.org>\")"
+        );
+    }
+
+    #[test]
+    fn test_envelope_empty() {
+        let message = MimeMessage::parse("X-Foo: bar\r\n\r\nHello!").unwrap();
+        assert_eq!(
+            envelope(&message).to_string(),
+            "(NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL)"
+        );
+    }
+
+    #[test]
+    fn test_nstring_literal() {
+        assert_eq!(nstring(&Some("a\r\nb".to_string())), "{4}\r\na\r\nb");
+        assert_eq!(nstring(&Some("é".to_string())), "{2}\r\né");
+    }
+}
@@ -0,0 +1,1018 @@
+//! Conversion between messages and JMAP Email objects (RFC 8621).
+//!
+//! `to_email` produces the JSON representation of a parsed message, and
+//! `from_email` builds a message from the kind of object given to
+//! `Email/set` when creating an email for submission.
+//!
+//! Part identifiers are the IMAP-style part numbers from `walk::PartPath`,
+//! with the body of a message that isn't multipart being part `1`. Blob
+//! identifiers are specific to the server, so they are left for the caller
+//! to add to the output, and are resolved through a callback on input.
+use std::str::FromStr;
+
+use chrono::{DateTime, FixedOffset};
+use serde_json::{json, Map, Value};
+
+use super::address::{Address, Mailbox};
+use super::builder::{encode_header_text, MessageBuilder};
+use super::charset::{DecodeOptions, OnError};
+use super::header::{FromHeader, Header};
+use super::listheaders::ListUris;
+use super::message::{MimeMessage, MimeMultipartType};
+use super::messageid::MessageId;
+use super::mimeheaders::ContentDisposition;
+use super::results::{ParsingError, ParsingResult};
+use super::rfc2045::Rfc2045Parser;
+use super::walk::PartPath;
+
+/// The forms a header value can be requested in, as described in Section
+/// 4.1.2 of RFC 8621.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum HeaderForm {
+    Raw,
+    Text,
+    Addresses,
+    GroupedAddresses,
+    MessageIds,
+    Date,
+    Urls,
+}
+
+impl HeaderForm {
+    fn from_name(name: &str) -> Option<HeaderForm> {
+        match name {
+            "asRaw" => Some(HeaderForm::Raw),
+            "asText" => Some(HeaderForm::Text),
+            "asAddresses" => Some(HeaderForm::Addresses),
+            "asGroupedAddresses" => Some(HeaderForm::GroupedAddresses),
+            "asMessageIds" => Some(HeaderForm::MessageIds),
+            "asDate" => Some(HeaderForm::Date),
+            "asURLs" => Some(HeaderForm::Urls),
+            _ => None,
+        }
+    }
+}
+
+/// A `header:{name}[:as{form}][:all]` property of an Email.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct HeaderProperty {
+    pub name: String,
+    pub form: HeaderForm,
+    /// Whether to give every instance of the header rather than the last
+    pub all: bool,
+}
+
+impl FromStr for HeaderProperty {
+    type Err = ParsingError;
+
+    fn from_str(s: &str) -> ParsingResult<HeaderProperty> {
+        let invalid = || ParsingError::new(format!("Invalid header property: {}", s));
+        let mut parts = s.split(':');
+        if parts.next() != Some("header") {
+            return Err(invalid());
+        }
+        let name = match parts.next() {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => return Err(invalid()),
+        };
+        let mut property = HeaderProperty {
+            name,
+            form: HeaderForm::Raw,
+            all: false,
+        };
+        let mut rest: Vec<&str> = parts.collect();
+        if rest.last() == Some(&"all") {
+            property.all = true;
+            rest.pop();
+        }
+        match rest[..] {
+            [] => {}
+            [form] => property.form = HeaderForm::from_name(form).ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        }
+        Ok(property)
+    }
+}
+
+fn mailbox_value(mbox: &Mailbox) -> Value {
+    json!({ "name": (mbox.name), "email": (mbox.address) })
+}
+
+// Parse a raw header value into the JSON representation of `form`.
+fn parse_form(raw: &str, form: HeaderForm) -> Value {
+    let value = raw.to_string();
+    match form {
+        HeaderForm::Raw => Value::String(value),
+        HeaderForm::Text => String::from_header(value)
+            .map(|text| Value::String(text.trim().to_string()))
+            .unwrap_or(Value::Null),
+        HeaderForm::Addresses => {
+            let addresses = Vec::<Address>::from_header(value).unwrap_or_default();
+            let mut result = Vec::new();
+            for address in addresses.iter() {
+                match *address {
+                    Address::Mailbox(ref mbox) => result.push(mailbox_value(mbox)),
+                    Address::Group(_, ref mboxes) => {
+                        result.extend(mboxes.iter().map(mailbox_value))
+                    }
+                }
+            }
+            Value::Array(result)
+        }
+        HeaderForm::GroupedAddresses => {
+            let addresses = Vec::<Address>::from_header(value).unwrap_or_default();
+            let mut result: Vec<Value> = Vec::new();
+            for address in addresses.iter() {
+                match *address {
+                    Address::Mailbox(ref mbox) => {
+                        // Consecutive mailboxes outside of a group are
+                        // gathered together under a null name
+                        let last_ungrouped = result
+                            .last_mut()
+                            .filter(|group| group["name"].is_null())
+                            .and_then(|group| group["addresses"].as_array().cloned());
+                        match last_ungrouped {
+                            Some(mut addresses) => {
+                                addresses.push(mailbox_value(mbox));
+                                *result.last_mut().unwrap() =
+                                    json!({ "name": null, "addresses": (addresses) });
+                            }
+                            None => result.push(
+                                json!({ "name": null, "addresses": [(mailbox_value(mbox))] }),
+                            ),
+                        }
+                    }
+                    Address::Group(ref name, ref mboxes) => {
+                        let addresses: Vec<Value> = mboxes.iter().map(mailbox_value).collect();
+                        result.push(json!({ "name": name, "addresses": (addresses) }));
+                    }
+                }
+            }
+            Value::Array(result)
+        }
+        HeaderForm::MessageIds => match Vec::<MessageId>::from_header(value) {
+            Ok(ids) if !ids.is_empty() => {
+                Value::Array(ids.iter().map(|id| Value::from(id.id())).collect())
+            }
+            _ => Value::Null,
+        },
+        HeaderForm::Date => match DateTime::<FixedOffset>::from_header(value) {
+            Ok(date) => Value::String(date.to_rfc3339()),
+            Err(_) => Value::Null,
+        },
+        HeaderForm::Urls => match ListUris::from_header(value) {
+            Ok(list) => Value::Array(list.uris.into_iter().map(Value::String).collect()),
+            Err(_) => Value::Null,
+        },
+    }
+}
+
+/// The value of a header property of `message`.
+///
+/// Header names are matched ignoring case. If the header isn't present, this
+/// is null, or an empty list when every instance was requested.
+pub fn header_value(message: &MimeMessage, property: &HeaderProperty) -> Value {
+    let mut values: Vec<Value> = message
+        .headers
+        .iter()
+        .filter(|header| header.name.eq_ignore_ascii_case(&property.name[..]))
+        .map(|header| match property.form {
+            // The raw form keeps any folding, as the header appeared
+            HeaderForm::Raw => Value::from(header.folded_value()),
+            form => parse_form(header.raw_value(), form),
+        })
+        .collect();
+    if property.all {
+        Value::Array(values)
+    } else {
+        values.pop().unwrap_or(Value::Null)
+    }
+}
+
+fn convenience_header(message: &MimeMessage, name: &str, form: HeaderForm) -> Value {
+    let property = HeaderProperty {
+        name: name.to_string(),
+        form,
+        all: false,
+    };
+    match header_value(message, &property) {
+        // An empty address list is given as null
+        Value::Array(ref values) if values.is_empty() => Value::Null,
+        value => value,
+    }
+}
+
+/// Options for `to_email`, matching the arguments to `Email/get`.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct EmailOptions {
+    /// Include the values of the `textBody` parts in `bodyValues`
+    pub fetch_text_body_values: bool,
+    /// Include the values of the `htmlBody` parts in `bodyValues`
+    pub fetch_html_body_values: bool,
+    /// Include the values of every text part in `bodyValues`
+    pub fetch_all_body_values: bool,
+    /// Truncate body values to at most this many bytes, or 0 for no limit
+    pub max_body_value_bytes: usize,
+}
+
+/// A part of the message, along with the information needed to place it.
+struct BodyPart<'a> {
+    id: Option<String>,
+    message: &'a MimeMessage,
+    media_type: String,
+    disposition: Option<String>,
+    name: Option<String>,
+    sub_parts: Vec<BodyPart<'a>>,
+}
+
+fn is_inline_media_type(media_type: &str) -> bool {
+    media_type.starts_with("image/")
+        || media_type.starts_with("audio/")
+        || media_type.starts_with("video/")
+}
+
+impl<'a> BodyPart<'a> {
+    fn new(message: &'a MimeMessage, path: PartPath) -> BodyPart<'a> {
+        let content_type = message.content_type();
+        let disposition = first_header(message, "Content-Disposition")
+            .and_then(|h| ContentDisposition::from_header(h.raw_value().to_string()).ok());
+
+        let name = disposition
+            .as_ref()
+            .and_then(|d| d.filename.clone())
+            .or_else(|| content_type.parameter("name").map(|name| name.to_string()))
+            .map(|name| String::from_header(name.clone()).unwrap_or(name));
+
+        let sub_parts = message
+            .children
+            .iter()
+            .enumerate()
+            .map(|(index, child)| BodyPart::new(child, path.child(index)))
+            .collect();
+
+        let id = if !message.children.is_empty() {
+            None
+        } else if path.is_root() {
+            Some("1".to_string())
+        } else {
+            Some(path.to_string())
+        };
+
+        BodyPart {
+            id,
+            message,
+            media_type: format!("{}/{}", content_type.ttype, content_type.subtype),
+            disposition: disposition.map(|d| d.kind.to_string()),
+            name,
+            sub_parts,
+        }
+    }
+
+    fn is_multipart(&self) -> bool {
+        self.media_type.starts_with("multipart/")
+    }
+
+    fn to_value(&self, with_sub_parts: bool) -> Value {
+        let message = self.message;
+        let headers: Vec<Value> = message
+            .headers
+            .iter()
+            .map(|header| json!({ "name": (header.name.as_str()), "value": (header.raw_value()) }))
+            .collect();
+        let charset = if self.media_type.starts_with("text/") {
+            let params = first_header(message, "Content-Type").map(|h| parse_params(h).1);
+            Some(
+                params
+                    .and_then(|params| params.get("charset").cloned())
+                    .unwrap_or_else(|| "us-ascii".to_string()),
+            )
+        } else {
+            None
+        };
+        let size = if self.is_multipart() {
+            0
+        } else {
+            message.decoded_body_bytes().map_or(0, |bytes| bytes.len())
+        };
+        let language = first_header(message, "Content-Language").map(|header| {
+            header
+                .raw_value()
+                .split(',')
+                .map(|lang| Value::from(lang.trim()))
+                .collect::<Vec<Value>>()
+        });
+        let location = first_header(message, "Content-Location")
+            .map(|header| header.raw_value().trim().to_string());
+
+        let mut value = json!({
+            "partId": (self.id),
+            "blobId": null,
+            "size": size,
+            "headers": (headers),
+            "name": (self.name),
+            "type": (self.media_type),
+            "charset": (charset),
+            "disposition": (self.disposition),
+            "cid": (message.content_id()),
+            "language": (language),
+            "location": (location)
+        });
+        if with_sub_parts && self.is_multipart() {
+            let sub_parts: Vec<Value> = self
+                .sub_parts
+                .iter()
+                .map(|part| part.to_value(true))
+                .collect();
+            value
+                .as_object_mut()
+                .unwrap()
+                .insert("subParts".to_string(), Value::Array(sub_parts));
+        }
+        value
+    }
+}
+
+fn first_header<'a>(message: &'a MimeMessage, name: &str) -> Option<&'a Header> {
+    message
+        .headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case(name))
+}
+
+// Split a header into its lowercased value and parameters.
+fn parse_params(header: &Header) -> (String, std::collections::HashMap<String, String>) {
+    let (value, params) = Rfc2045Parser::new(header.raw_value()).consume_all();
+    let params = params
+        .into_iter()
+        .map(|(key, value)| (key.to_ascii_lowercase(), value))
+        .collect();
+    (value.trim().to_ascii_lowercase(), params)
+}
+
+// Sort the parts into the text body, HTML body and attachments, using the
+// algorithm from Section 4.1.4 of RFC 8621.
+fn parse_structure<'p, 'a>(
+    parts: &'p [BodyPart<'a>],
+    multipart_type: &str,
+    in_alternative: bool,
+    html_body: Option<&mut Vec<&'p BodyPart<'a>>>,
+    text_body: Option<&mut Vec<&'p BodyPart<'a>>>,
+    attachments: &mut Vec<&'p BodyPart<'a>>,
+) {
+    let mut html_body = html_body;
+    let mut text_body = text_body;
+    let text_length = text_body.as_ref().map(|body| body.len());
+    let html_length = html_body.as_ref().map(|body| body.len());
+
+    for (i, part) in parts.iter().enumerate() {
+        let media_type = &part.media_type[..];
+        let is_inline = part.disposition.as_ref().map(|d| &d[..]) != Some("attachment")
+            && (media_type == "text/plain"
+                || media_type == "text/html"
+                || is_inline_media_type(media_type))
+            && (i == 0
+                || (multipart_type != "related"
+                    && (is_inline_media_type(media_type) || part.name.is_none())));
+
+        if part.is_multipart() {
+            let sub_multipart_type = &media_type["multipart/".len()..];
+            parse_structure(
+                &part.sub_parts[..],
+                sub_multipart_type,
+                in_alternative || sub_multipart_type == "alternative",
+                html_body.as_deref_mut(),
+                text_body.as_deref_mut(),
+                attachments,
+            );
+        } else if is_inline {
+            if multipart_type == "alternative" {
+                match (media_type, &mut text_body, &mut html_body) {
+                    ("text/plain", Some(body), _) | ("text/html", _, Some(body)) => body.push(part),
+                    ("text/plain", None, _) | ("text/html", _, None) => {}
+                    _ => attachments.push(part),
+                }
+                continue;
+            } else if in_alternative {
+                if media_type == "text/plain" {
+                    html_body = None;
+                }
+                if media_type == "text/html" {
+                    text_body = None;
+                }
+            }
+            if let Some(ref mut body) = text_body {
+                body.push(part);
+            }
+            if let Some(ref mut body) = html_body {
+                body.push(part);
+            }
+            if (text_body.is_none() || html_body.is_none()) && is_inline_media_type(media_type) {
+                attachments.push(part);
+            }
+        } else {
+            attachments.push(part);
+        }
+    }
+
+    if multipart_type == "alternative" {
+        if let (Some(text_body), Some(html_body)) = (text_body, html_body) {
+            let (text_length, html_length) = (text_length.unwrap(), html_length.unwrap());
+            if text_length == text_body.len() && html_length != html_body.len() {
+                text_body.extend_from_slice(&html_body[html_length..]);
+            }
+            if html_length == html_body.len() && text_length != text_body.len() {
+                html_body.extend_from_slice(&text_body[text_length..]);
+            }
+        }
+    }
+}
+
+// The value of a text part for `bodyValues`.
+fn body_value(part: &BodyPart, max_bytes: usize) -> Value {
+    // Decode strictly first, so a fallback charset being needed is flagged
+    let strict = DecodeOptions {
+        fallback_charset: None,
+        on_error: OnError::Strict,
+    };
+    let decoded = part.message.decoded_body_string_with_options(&strict);
+    let (mut text, is_encoding_problem) = match decoded {
+        Ok(text) => (text, false),
+        Err(_) => {
+            let text = part.message.decoded_body_string().unwrap_or_else(|_| {
+                let bytes = part.message.decoded_body_bytes().unwrap_or_default();
+                String::from_utf8_lossy(&bytes).into_owned()
+            });
+            (text, true)
+        }
+    };
+    let mut is_truncated = false;
+    if max_bytes > 0 && text.len() > max_bytes {
+        let mut end = max_bytes;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        is_truncated = true;
+    }
+    json!({
+        "value": (text),
+        "isEncodingProblem": is_encoding_problem,
+        "isTruncated": is_truncated
+    })
+}
+
+/// The maximum length of the `preview` property
+const PREVIEW_LENGTH: usize = 256;
+
+/// Convert `message` into a JMAP Email object.
+///
+/// The `blobId` of each part is null, and the metadata properties (`id`,
+/// `mailboxIds`, `keywords` and so on) are left for the caller to add.
+pub fn to_email(message: &MimeMessage, options: &EmailOptions) -> Value {
+    let structure = BodyPart::new(message, PartPath::root());
+
+    let mut text_body = Vec::new();
+    let mut html_body = Vec::new();
+    let mut attachments = Vec::new();
+    parse_structure(
+        std::slice::from_ref(&structure),
+        "mixed",
+        false,
+        Some(&mut html_body),
+        Some(&mut text_body),
+        &mut attachments,
+    );
+
+    let mut body_values = Map::new();
+    {
+        let mut add_values = |parts: &[&BodyPart]| {
+            for part in parts.iter() {
+                if part.media_type.starts_with("text/") {
+                    body_values.insert(
+                        part.id.clone().unwrap(),
+                        body_value(part, options.max_body_value_bytes),
+                    );
+                }
+            }
+        };
+        if options.fetch_all_body_values {
+            let mut all = Vec::new();
+            let mut stack = vec![&structure];
+            while let Some(part) = stack.pop() {
+                if part.is_multipart() {
+                    stack.extend(part.sub_parts.iter().rev());
+                } else {
+                    all.push(part);
+                }
+            }
+            add_values(&all[..]);
+        } else {
+            if options.fetch_text_body_values {
+                add_values(&text_body[..]);
+            }
+            if options.fetch_html_body_values {
+                add_values(&html_body[..]);
+            }
+        }
+    }
+
+    let preview: String = text_body
+        .iter()
+        .find(|part| part.media_type == "text/plain")
+        .and_then(|part| part.message.decoded_body_string().ok())
+        .map(|text| text.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .unwrap_or_default()
+        .chars()
+        .take(PREVIEW_LENGTH)
+        .collect();
+
+    let headers: Vec<Value> = message
+        .headers
+        .iter()
+        .map(|header| json!({ "name": (header.name.as_str()), "value": (header.raw_value()) }))
+        .collect();
+    let part_values =
+        |parts: &[&BodyPart]| -> Vec<Value> { parts.iter().map(|p| p.to_value(false)).collect() };
+
+    json!({
+        "blobId": null,
+        "size": (message.encoded_len_estimate()),
+        "headers": (headers),
+        "messageId": (convenience_header(message, "Message-ID", HeaderForm::MessageIds)),
+        "inReplyTo": (convenience_header(message, "In-Reply-To", HeaderForm::MessageIds)),
+        "references": (convenience_header(message, "References", HeaderForm::MessageIds)),
+        "sender": (convenience_header(message, "Sender", HeaderForm::Addresses)),
+        "from": (convenience_header(message, "From", HeaderForm::Addresses)),
+        "to": (convenience_header(message, "To", HeaderForm::Addresses)),
+        "cc": (convenience_header(message, "Cc", HeaderForm::Addresses)),
+        "bcc": (convenience_header(message, "Bcc", HeaderForm::Addresses)),
+        "replyTo": (convenience_header(message, "Reply-To", HeaderForm::Addresses)),
+        "subject": (convenience_header(message, "Subject", HeaderForm::Text)),
+        "sentAt": (convenience_header(message, "Date", HeaderForm::Date)),
+        "bodyStructure": (structure.to_value(true)),
+        "bodyValues": (Value::Object(body_values)),
+        "textBody": (part_values(&text_body[..])),
+        "htmlBody": (part_values(&html_body[..])),
+        "attachments": (part_values(&attachments[..])),
+        "hasAttachment": (!attachments.is_empty()),
+        "preview": (preview)
+    })
+}
+
+fn invalid(message: &str) -> ParsingError {
+    ParsingError::new(format!("Invalid Email object: {}", message))
+}
+
+fn str_property<'a>(object: &'a Value, name: &str) -> ParsingResult<Option<&'a str>> {
+    match object.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(&s[..])),
+        Some(_) => Err(invalid(&format!("{} should be a string", name)[..])),
+    }
+}
+
+fn array_property<'a>(object: &'a Value, name: &str) -> ParsingResult<&'a [Value]> {
+    match object.get(name) {
+        None | Some(Value::Null) => Ok(&[]),
+        Some(Value::Array(values)) => Ok(&values[..]),
+        Some(_) => Err(invalid(&format!("{} should be a list", name)[..])),
+    }
+}
+
+fn mailboxes(object: &Value, name: &str) -> ParsingResult<Vec<Mailbox>> {
+    let mut result = Vec::new();
+    for value in array_property(object, name)?.iter() {
+        let email = str_property(value, "email")?
+            .ok_or_else(|| invalid("an EmailAddress needs an email"))?;
+        result.push(Mailbox {
+            name: str_property(value, "name")?.map(|name| name.to_string()),
+            address: email.to_string(),
+        });
+    }
+    Ok(result)
+}
+
+fn message_ids(object: &Value, name: &str) -> ParsingResult<Vec<MessageId>> {
+    array_property(object, name)?
+        .iter()
+        .map(|id| match id.as_str() {
+            Some(id) => Ok(MessageId::new(id.to_string())),
+            None => Err(invalid(
+                &format!("{} should be a list of strings", name)[..],
+            )),
+        })
+        .collect()
+}
+
+// Format a single JSON value in the given header form as a raw header value.
+fn format_form(value: &Value, form: HeaderForm) -> ParsingResult<String> {
+    let wrong_type = || invalid("header value doesn't match its form");
+    match form {
+        HeaderForm::Raw => value.as_str().map(|s| s.to_string()).ok_or_else(wrong_type),
+        HeaderForm::Text => value
+            .as_str()
+            .map(encode_header_text)
+            .ok_or_else(wrong_type),
+        HeaderForm::Addresses => {
+            let holder = json!({ "addresses": (value.clone()) });
+            let addresses: Vec<Address> = mailboxes(&holder, "addresses")?
+                .into_iter()
+                .map(Address::Mailbox)
+                .collect();
+            Header::new_with_value(String::new(), addresses).map(|h| h.raw_value().to_string())
+        }
+        HeaderForm::GroupedAddresses => {
+            let mut addresses = Vec::new();
+            for group in value.as_array().ok_or_else(wrong_type)?.iter() {
+                let members = mailboxes(group, "addresses")?;
+                match str_property(group, "name")? {
+                    Some(name) => addresses.push(Address::Group(name.to_string(), members)),
+                    None => addresses.extend(members.into_iter().map(Address::Mailbox)),
+                }
+            }
+            Header::new_with_value(String::new(), addresses).map(|h| h.raw_value().to_string())
+        }
+        HeaderForm::MessageIds => {
+            let holder = json!({ "ids": (value.clone()) });
+            let ids = message_ids(&holder, "ids")?;
+            Header::new_with_value(String::new(), &ids[..]).map(|h| h.raw_value().to_string())
+        }
+        HeaderForm::Date => {
+            let date = value.as_str().ok_or_else(wrong_type)?;
+            DateTime::parse_from_rfc3339(date)
+                .map(|date| date.to_rfc2822())
+                .map_err(|_| invalid("invalid date"))
+        }
+        HeaderForm::Urls => {
+            let urls: Vec<String> = value
+                .as_array()
+                .ok_or_else(wrong_type)?
+                .iter()
+                .map(|url| url.as_str().map(|url| format!("<{}>", url)))
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(wrong_type)?;
+            Ok(urls.join(", "))
+        }
+    }
+}
+
+fn multipart(subtype: &str, children: Vec<MimeMessage>) -> MimeMessage {
+    let mut message = MimeMessage::new_blank_message();
+    message.headers.insert(Header::new(
+        "Content-Type".to_string(),
+        format!("multipart/{}", subtype),
+    ));
+    message.children = children;
+    message.update_headers();
+    message.message_type =
+        MimeMultipartType::from_content_type(("multipart".to_string(), subtype.to_string()));
+    message
+}
+
+// Build a MIME part from an EmailBodyPart, finding its content in the body
+// values or through `blobs`.
+fn build_part<F>(part: &Value, body_values: &Value, blobs: &mut F) -> ParsingResult<MimeMessage>
+where
+    F: FnMut(&str) -> Option<Vec<u8>>,
+{
+    let media_type = str_property(part, "type")?
+        .unwrap_or("text/plain")
+        .to_ascii_lowercase();
+    if let Some(subtype) = media_type.strip_prefix("multipart/") {
+        let children = array_property(part, "subParts")?
+            .iter()
+            .map(|sub_part| build_part(sub_part, body_values, blobs))
+            .collect::<ParsingResult<Vec<MimeMessage>>>()?;
+        return Ok(multipart(subtype, children));
+    }
+
+    let mut message = MimeMessage::new_blank_message();
+    let name = str_property(part, "name")?;
+    let (content, charset) = match str_property(part, "partId")? {
+        Some(part_id) => {
+            let text = body_values[part_id]["value"]
+                .as_str()
+                .ok_or_else(|| invalid(&format!("no body value for part {}", part_id)[..]))?;
+            (text.as_bytes().to_vec(), Some("utf-8"))
+        }
+        None => {
+            let blob_id = str_property(part, "blobId")?
+                .ok_or_else(|| invalid("a body part needs a partId or blobId"))?;
+            let content =
+                blobs(blob_id).ok_or_else(|| invalid(&format!("unknown blob {}", blob_id)[..]))?;
+            let charset = str_property(part, "charset")?;
+            (content, charset)
+        }
+    };
+
+    let mut content_type = media_type.clone();
+    if let Some(charset) = charset {
+        content_type.push_str(&format!("; charset={}", charset)[..]);
+    }
+    if let Some(name) = name {
+        content_type.push_str(&format!("; name=\"{}\"", encode_header_text(name))[..]);
+    }
+    message
+        .headers
+        .insert(Header::new("Content-Type".to_string(), content_type));
+    if let Some(disposition) = str_property(part, "disposition")? {
+        let value = match name {
+            Some(name) => format!("{}; filename=\"{}\"", disposition, encode_header_text(name)),
+            None => disposition.to_string(),
+        };
+        message
+            .headers
+            .insert(Header::new("Content-Disposition".to_string(), value));
+    }
+    if let Some(cid) = str_property(part, "cid")? {
+        message
+            .headers
+            .insert(Header::new("Content-ID".to_string(), format!("<{}>", cid)));
+    }
+    if let Some(location) = str_property(part, "location")? {
+        message.headers.insert(Header::new(
+            "Content-Location".to_string(),
+            location.to_string(),
+        ));
+    }
+    let languages: Vec<&str> = array_property(part, "language")?
+        .iter()
+        .filter_map(|lang| lang.as_str())
+        .collect();
+    if !languages.is_empty() {
+        message.headers.insert(Header::new(
+            "Content-Language".to_string(),
+            languages.join(", "),
+        ));
+    }
+    message.set_body_bytes(&content);
+    Ok(message)
+}
+
+/// Build a message from a JMAP Email creation object, as given to
+/// `Email/set`.
+///
+/// The body is taken from `bodyStructure` if present, or otherwise from
+/// `textBody`, `htmlBody` and `attachments`. The content of text parts
+/// comes from `bodyValues`, and of any other parts from `blobs`, which is
+/// called with each `blobId`. Headers given as `header:` properties are
+/// added after the standard headers.
+pub fn from_email<F>(email: &Value, mut blobs: F) -> ParsingResult<MimeMessage>
+where
+    F: FnMut(&str) -> Option<Vec<u8>>,
+{
+    if !email.is_object() {
+        return Err(invalid("expected an object"));
+    }
+    let mut builder = MessageBuilder::new();
+
+    for mbox in mailboxes(email, "from")?.into_iter() {
+        builder = builder.from(mbox);
+    }
+    if let Some(sender) = mailboxes(email, "sender")?.into_iter().next() {
+        builder = builder.sender(sender);
+    }
+    for mbox in mailboxes(email, "replyTo")?.into_iter() {
+        builder = builder.reply_to(mbox);
+    }
+    for mbox in mailboxes(email, "to")?.into_iter() {
+        builder = builder.to(mbox);
+    }
+    for mbox in mailboxes(email, "cc")?.into_iter() {
+        builder = builder.cc(mbox);
+    }
+    for mbox in mailboxes(email, "bcc")?.into_iter() {
+        builder = builder.bcc(mbox);
+    }
+    if let Some(subject) = str_property(email, "subject")? {
+        builder = builder.subject(subject);
+    }
+    if let Some(sent_at) = str_property(email, "sentAt")? {
+        let date = DateTime::parse_from_rfc3339(sent_at).map_err(|_| invalid("invalid sentAt"))?;
+        builder = builder.date(date);
+    }
+    if let Some(id) = message_ids(email, "messageId")?.into_iter().next() {
+        builder = builder.message_id(id);
+    }
+    for id in message_ids(email, "inReplyTo")?.into_iter() {
+        builder = builder.in_reply_to(id);
+    }
+    let references = message_ids(email, "references")?;
+    if !references.is_empty() {
+        builder = builder.references(references);
+    }
+
+    for (key, value) in email.as_object().unwrap().iter() {
+        if !key.starts_with("header:") {
+            continue;
+        }
+        let property: HeaderProperty = key.parse()?;
+        if property.all {
+            for value in value.as_array().ok_or_else(|| invalid(key))?.iter() {
+                builder = builder.header(Header::new(
+                    property.name.clone(),
+                    format_form(value, property.form)?,
+                ));
+            }
+        } else {
+            builder = builder.header(Header::new(
+                property.name.clone(),
+                format_form(value, property.form)?,
+            ));
+        }
+    }
+
+    let body_values = email.get("bodyValues").cloned().unwrap_or(Value::Null);
+    if let Some(structure) = email.get("bodyStructure") {
+        builder = builder.body(build_part(structure, &body_values, &mut blobs)?);
+    } else {
+        let text = array_property(email, "textBody")?;
+        let html = array_property(email, "htmlBody")?;
+        let mut alternatives = Vec::new();
+        for part in text.iter().chain(html.iter()).take(2) {
+            alternatives.push(build_part(part, &body_values, &mut blobs)?);
+        }
+        let body = match alternatives.len() {
+            0 => None,
+            1 => alternatives.pop(),
+            _ => Some(multipart("alternative", alternatives)),
+        };
+        if let Some(body) = body {
+            builder = builder.body(body);
+        }
+        for part in array_property(email, "attachments")?.iter() {
+            builder = builder.attach(build_part(part, &body_values, &mut blobs)?);
+        }
+    }
+
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> MimeMessage {
+        MimeMessage::parse(
+            "From: Joe Blogs <joe@example.org>\r\n\
+             To: Team: jane@example.org;\r\n\
+             Cc: john@example.org, jim@example.org\r\n\
+             Subject: =?utf-8?q?Gr=C3=BC=C3=9Fe?=\r\n\
+             Date: Wed, 17 Dec 2014 09:35:07 +0100\r\n\
+             Message-ID: <1@example.org>\r\n\
+             List-Archive: <https://example.org/archive>\r\n\
+             Content-Type: multipart/mixed; boundary=outer\r\n\r\n\
+             --outer\r\n\
+             Content-Type: multipart/alternative; boundary=alt\r\n\r\n\
+             --alt\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\r\n\
+             Hello   there\r\n\
+             --alt\r\n\
+             Content-Type: multipart/related; boundary=rel\r\n\r\n\
+             --rel\r\n\
+             Content-Type: text/html\r\n\r\n\
+             <img src=\"cid:logo\">\r\n\
+             --rel\r\n\
+             Content-Type: image/png\r\n\
+             Content-ID: <logo>\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n\
+             AAEC\r\n\
+             --rel--\r\n\
+             --alt--\r\n\
+             --outer\r\n\
+             Content-Type: application/pdf; name=report.pdf\r\n\
+             Content-Disposition: attachment\r\n\r\n\
+             PDF\r\n\
+             --outer--\r\n",
+        )
+        .unwrap()
+    }
+
+    fn part_ids(value: &Value) -> Vec<&str> {
+        value
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|part| part["partId"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_header_property() {
+        let property: HeaderProperty = "header:List-Archive:asURLs:all".parse().unwrap();
+        assert_eq!(property.name, "List-Archive");
+        assert_eq!(property.form, HeaderForm::Urls);
+        assert!(property.all);
+        assert_eq!(
+            header_value(&message(), &property),
+            json!([["https://example.org/archive"]])
+        );
+
+        let property: HeaderProperty = "header:to:asGroupedAddresses".parse().unwrap();
+        assert_eq!(
+            header_value(&message(), &property),
+            json!([{ "name": "Team", "addresses": [{ "name": null, "email": "jane@example.org" }] }])
+        );
+        let property: HeaderProperty = "header:Cc:asGroupedAddresses".parse().unwrap();
+        assert_eq!(
+            header_value(&message(), &property)[0]["addresses"][1]["email"],
+            "jim@example.org"
+        );
+
+        assert!("header:X-Foo".parse::<HeaderProperty>().unwrap().form == HeaderForm::Raw);
+        assert!("header:X-Foo:asNothing".parse::<HeaderProperty>().is_err());
+        assert!("subject".parse::<HeaderProperty>().is_err());
+    }
+
+    #[test]
+    fn test_to_email() {
+        let options = EmailOptions {
+            fetch_text_body_values: true,
+            max_body_value_bytes: 5,
+            ..Default::default()
+        };
+        let email = to_email(&message(), &options);
+
+        assert_eq!(email["subject"], "Grüße");
+        assert_eq!(email["messageId"], json!(["1@example.org"]));
+        assert!(email["inReplyTo"].is_null());
+        assert_eq!(
+            email["from"],
+            json!([{ "name": "Joe Blogs", "email": "joe@example.org" }])
+        );
+        assert_eq!(email["to"][0]["email"], "jane@example.org");
+        assert_eq!(email["sentAt"], "2014-12-17T09:35:07+01:00");
+
+        assert!(email["bodyStructure"]["partId"].is_null());
+        assert_eq!(email["bodyStructure"]["subParts"][1]["name"], "report.pdf");
+        assert_eq!(part_ids(&email["textBody"]), vec!["1.1"]);
+        assert_eq!(part_ids(&email["htmlBody"]), vec!["1.2.1"]);
+        assert_eq!(part_ids(&email["attachments"]), vec!["1.2.2", "2"]);
+        assert_eq!(email["attachments"][0]["cid"], "logo");
+        assert_eq!(email["attachments"][0]["size"], 3);
+        assert_eq!(email["hasAttachment"], true);
+        assert_eq!(email["preview"], "Hello there");
+
+        assert_eq!(
+            email["bodyValues"],
+            json!({ "1.1": { "value": "Hello", "isEncodingProblem": false, "isTruncated": true } })
+        );
+    }
+
+    #[test]
+    fn test_from_email() {
+        let email = json!({
+            "from": [{ "name": "Joe Blogs", "email": "joe@example.org" }],
+            "to": [{ "email": "john@example.org" }],
+            "subject": "Report",
+            "sentAt": "2020-01-02T03:04:05Z",
+            "messageId": ["1@example.org"],
+            "header:X-Tags:asText": "Grüße",
+            "textBody": [{ "partId": "text", "type": "text/plain" }],
+            "htmlBody": [{ "partId": "html", "type": "text/html" }],
+            "attachments": [{
+                "blobId": "blob1",
+                "type": "application/pdf",
+                "name": "report.pdf",
+                "disposition": "attachment"
+            }],
+            "bodyValues": {
+                "text": { "value": "Hello" },
+                "html": { "value": "<p>Hello</p>" }
+            }
+        });
+
+        let message = from_email(&email, |blob_id| {
+            if blob_id == "blob1" {
+                Some(vec![0, 1, 2])
+            } else {
+                None
+            }
+        })
+        .unwrap();
+
+        let subject: String = message.headers.get_value("Subject".to_string()).unwrap();
+        assert_eq!(subject, "Report");
+        let tags: String = message.headers.get_value("X-Tags".to_string()).unwrap();
+        assert_eq!(tags, "Grüße");
+        assert_eq!(message.text_body(), Some("Hello".to_string()));
+        assert_eq!(message.html_body(), Some("<p>Hello</p>".to_string()));
+
+        let reparsed = MimeMessage::parse(&message.as_string()[..]).unwrap();
+        let round_trip = to_email(&reparsed, &EmailOptions::default());
+        assert_eq!(round_trip["sentAt"], "2020-01-02T03:04:05+00:00");
+        assert_eq!(round_trip["attachments"][0]["name"], "report.pdf");
+        assert_eq!(part_ids(&round_trip["textBody"]), vec!["1.1"]);
+        assert_eq!(
+            reparsed.children[1].decoded_body_bytes(),
+            Some(vec![0, 1, 2])
+        );
+
+        assert!(from_email(
+            &json!({ "from": [{ "email": "joe@example.org" }],
+                                    "attachments": [{ "blobId": "missing" }] }),
+            |_| None
+        )
+        .is_err());
+    }
+}
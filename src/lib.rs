@@ -1,23 +1,153 @@
 #![cfg_attr(all(feature = "nightly", test), feature(test))]
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
 extern crate base64;
+#[cfg(feature = "std")]
 extern crate chrono;
-extern crate encoding;
+extern crate encoding_rs;
+#[cfg(feature = "rand")]
 extern crate rand;
 
+#[cfg(feature = "std")]
 #[macro_use]
 extern crate lazy_static;
 
 pub use crate::address::{Address, Mailbox};
-pub use crate::header::{FromHeader, Header, HeaderIter, HeaderMap, ToFoldedHeader, ToHeader};
-pub use crate::message::{MimeMessage, MimeMultipartType};
+#[cfg(feature = "std")]
+pub use crate::builder::{ForwardMode, MessageBuilder, ReplyMode, ReplyOptions};
+pub use crate::charset::DecodeOptions;
+pub use crate::header::{
+    check_header_value, fold, scan, FoldingPolicy, FromHeader, Header, HeaderDecodePolicy,
+    HeaderDecoding, HeaderIter, HeaderMap, HeaderName, HeaderValueError, ScannedHeaders,
+    ToFoldedHeader, ToHeader,
+};
+#[cfg(feature = "std")]
+pub use crate::message::{
+    InlineParts, Limits, MimeMessage, MimeMultipartType, ParseOptions, Quirk, Quirks, RawBody,
+};
+#[cfg(feature = "std")]
+pub use crate::messageid::MessageId;
 
-mod address;
+// The items of the std prelude which the core parsers need from alloc
+#[cfg(not(feature = "std"))]
+mod prelude {
+    pub use alloc::borrow::ToOwned;
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+    pub use alloc::{format, vec};
+}
+
+#[cfg(feature = "std")]
+mod builder;
 mod header;
+#[cfg(feature = "std")]
 mod message;
+#[cfg(feature = "std")]
+mod messageid;
+#[cfg(feature = "std")]
+mod repair;
+#[cfg(all(feature = "serde", feature = "std"))]
+mod serde_impl;
+pub mod address;
+#[cfg(feature = "std")]
+pub mod arc;
+#[cfg(feature = "std")]
+pub mod arena;
+#[cfg(feature = "std")]
+pub mod attachment;
+#[cfg(feature = "std")]
+pub mod autoreply;
+#[cfg(feature = "std")]
+pub mod bimi;
+#[cfg(feature = "std")]
+pub mod bodyparse;
+#[cfg(feature = "std")]
+pub mod calendar;
+#[cfg(feature = "std")]
+pub mod canonicalize;
+pub mod charset;
+#[cfg(feature = "std")]
+pub mod convert;
+#[cfg(feature = "detect")]
+pub mod detect;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod digest;
+#[cfg(feature = "std")]
+pub mod dmarc;
+#[cfg(feature = "std")]
+pub mod edit;
+#[cfg(feature = "std")]
+pub mod envelope;
+#[cfg(feature = "std")]
+pub mod environment;
+#[cfg(feature = "std")]
+pub mod external;
+#[cfg(feature = "std")]
+pub mod fingerprint;
+#[cfg(feature = "std")]
+pub mod flowed;
+#[cfg(feature = "std")]
+pub mod imap;
+#[cfg(feature = "jmap")]
+pub mod jmap;
+#[cfg(feature = "std")]
+pub mod listheaders;
+#[cfg(feature = "std")]
+pub mod macfile;
+#[cfg(feature = "std")]
+pub mod maildir;
+#[cfg(feature = "std")]
+pub mod mailto;
+#[cfg(feature = "std")]
+pub mod mbox;
+#[cfg(feature = "std")]
+pub mod mhtml;
+#[cfg(feature = "std")]
 pub mod mimeheaders;
+#[cfg(feature = "std")]
+pub mod originator;
+#[cfg(feature = "std")]
+pub mod partial;
+#[cfg(feature = "std")]
+pub mod priority;
+#[cfg(feature = "std")]
+pub mod receipt;
+#[cfg(feature = "std")]
+pub mod redact;
+#[cfg(feature = "std")]
+pub mod registry;
 pub mod results;
+#[cfg(feature = "std")]
 pub mod rfc2045;
 pub mod rfc2047;
 pub mod rfc5322;
+#[cfg(feature = "std")]
 pub mod rfc822;
+#[cfg(feature = "std")]
+pub mod roundtrip;
+#[cfg(feature = "std")]
+pub mod sanitize;
+#[cfg(feature = "std")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod subject;
+#[cfg(feature = "testsupport")]
+pub mod testsupport;
+#[cfg(feature = "std")]
+pub mod threading;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "std")]
+pub mod uuencode;
+#[cfg(feature = "std")]
+pub mod vcard;
+#[cfg(feature = "std")]
+pub mod walk;
+#[cfg(feature = "std")]
+pub mod xheaders;
+#[cfg(feature = "std")]
+pub mod yenc;
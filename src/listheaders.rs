@@ -0,0 +1,458 @@
+//! Module for the mailing list headers of RFC 2369 and RFC 2919, along with
+//! the one-click unsubscription of RFC 8058.
+use super::header::{FromHeader, HeaderMap, ToHeader};
+use super::results::{ParsingError, ParsingResult};
+use super::rfc5322::Rfc5322Parser;
+
+pub const LIST_ID: &str = "List-Id";
+pub const LIST_HELP: &str = "List-Help";
+pub const LIST_UNSUBSCRIBE: &str = "List-Unsubscribe";
+pub const LIST_SUBSCRIBE: &str = "List-Subscribe";
+pub const LIST_POST: &str = "List-Post";
+pub const LIST_OWNER: &str = "List-Owner";
+pub const LIST_ARCHIVE: &str = "List-Archive";
+pub const LIST_UNSUBSCRIBE_POST: &str = "List-Unsubscribe-Post";
+
+/// The `List-Id` header, RFC 2919 Section 2
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ListId {
+    /// The free-form description of the list, if given
+    pub description: Option<String>,
+    /// The list identifier, such as `list-name.example.org`
+    pub id: String,
+}
+
+impl FromHeader for ListId {
+    fn from_header(value: String) -> ParsingResult<ListId> {
+        let mut parser = Rfc5322Parser::new(&value[..]);
+        let description = parser.consume_phrase(true);
+        parser.consume_linear_whitespace();
+        parser.assert_char('<')?;
+        parser.consume_char();
+        let id = parser.consume_while(|c| c != '>');
+        parser.assert_char('>')?;
+
+        let id = id.trim();
+        if id.is_empty() || !id.contains('.') {
+            return Err(ParsingError::new(format!("Invalid list id: {}", id)));
+        }
+        Ok(ListId {
+            description,
+            id: id.to_string(),
+        })
+    }
+}
+
+impl ToHeader for ListId {
+    fn to_header(value: ListId) -> ParsingResult<String> {
+        Ok(match value.description {
+            Some(description) => format!(
+                "\"{}\" <{}>",
+                description.replace('\\', "\\\\").replace('"', "\\\""),
+                value.id
+            ),
+            None => format!("<{}>", value.id),
+        })
+    }
+}
+
+/// A list of angle-bracketed URIs, as used by the RFC 2369 headers
+/// such as `List-Unsubscribe` and `List-Help`.
+///
+/// URIs are given in order of preference.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ListUris {
+    pub uris: Vec<String>,
+}
+
+impl ListUris {
+    /// Find the first URI with the given scheme, such as `mailto` or `https`.
+    pub fn with_scheme(&self, scheme: &str) -> Option<&str> {
+        self.uris
+            .iter()
+            .find(|uri| {
+                let start = uri.get(..scheme.len());
+                start.is_some_and(|start| start.eq_ignore_ascii_case(scheme))
+                    && uri[scheme.len()..].starts_with(':')
+            })
+            .map(|uri| &uri[..])
+    }
+}
+
+/// Parse a comma separated list of `<uri>`s, skipping any comments.
+fn parse_uri_list(value: &str) -> ParsingResult<Vec<String>> {
+    let mut parser = Rfc5322Parser::new(value);
+    let mut uris = Vec::new();
+
+    loop {
+        parser.consume_while_str(|c| c.is_whitespace());
+        if parser.eof() {
+            break;
+        }
+        match parser.peek() {
+            '<' => {
+                parser.consume_char();
+                let uri = parser.consume_while(|c| c != '>');
+                parser.assert_char('>')?;
+                parser.consume_char();
+                // RFC 2369 Section 2: whitespace in the URI is to be ignored
+                let uri: String = uri.chars().filter(|c| !c.is_whitespace()).collect();
+                if !uri.is_empty() {
+                    uris.push(uri);
+                }
+            }
+            '(' => {
+                // Comments are allowed between elements
+                let mut depth = 0;
+                while let Some(c) = parser.consume_char() {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            ',' => {
+                parser.consume_char();
+            }
+            c => {
+                return Err(ParsingError::new(format!(
+                    "Expected '<' in list header, got {}",
+                    c
+                )))
+            }
+        }
+    }
+
+    if uris.is_empty() {
+        Err(ParsingError::new("No URIs in list header.".to_string()))
+    } else {
+        Ok(uris)
+    }
+}
+
+fn format_uri_list(uris: &[String]) -> ParsingResult<String> {
+    if uris.is_empty() {
+        return Err(ParsingError::new(
+            "Header value cannot be empty".to_string(),
+        ));
+    }
+    let formatted: Vec<String> = uris.iter().map(|uri| format!("<{}>", uri)).collect();
+    Ok(formatted.join(", "))
+}
+
+impl FromHeader for ListUris {
+    fn from_header(value: String) -> ParsingResult<ListUris> {
+        Ok(ListUris {
+            uris: parse_uri_list(&value[..])?,
+        })
+    }
+}
+
+impl ToHeader for ListUris {
+    fn to_header(value: ListUris) -> ParsingResult<String> {
+        format_uri_list(&value.uris[..])
+    }
+}
+
+/// The `List-Post` header, which is either a set of URIs or the special
+/// value `NO` for lists which don't allow posting (RFC 2369 Section 3.4)
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum ListPost {
+    /// Posting to the list is not allowed
+    No,
+    /// Posting is done through one of these URIs
+    Uris(ListUris),
+}
+
+impl FromHeader for ListPost {
+    fn from_header(value: String) -> ParsingResult<ListPost> {
+        // The NO may be followed by a comment
+        let without_comment = value.split('(').next().unwrap_or("").trim();
+        if without_comment.eq_ignore_ascii_case("no") {
+            Ok(ListPost::No)
+        } else {
+            Ok(ListPost::Uris(FromHeader::from_header(value)?))
+        }
+    }
+}
+
+impl ToHeader for ListPost {
+    fn to_header(value: ListPost) -> ParsingResult<String> {
+        match value {
+            ListPost::No => Ok("NO".to_string()),
+            ListPost::Uris(uris) => ToHeader::to_header(uris),
+        }
+    }
+}
+
+/// The `List-Unsubscribe-Post` header of RFC 8058.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ListUnsubscribePost {
+    /// The key to POST, which should be `List-Unsubscribe`
+    pub key: String,
+    /// The value to POST, which should be `One-Click`
+    pub value: String,
+}
+
+impl ListUnsubscribePost {
+    /// The one-click value as required by RFC 8058 Section 3.1
+    pub fn one_click() -> ListUnsubscribePost {
+        ListUnsubscribePost {
+            key: "List-Unsubscribe".to_string(),
+            value: "One-Click".to_string(),
+        }
+    }
+
+    /// Whether this is the one-click token defined by RFC 8058.
+    pub fn is_one_click(&self) -> bool {
+        self.key == "List-Unsubscribe" && self.value == "One-Click"
+    }
+}
+
+impl FromHeader for ListUnsubscribePost {
+    fn from_header(value: String) -> ParsingResult<ListUnsubscribePost> {
+        let mut parts = value.trim().splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if !key.is_empty() => Ok(ListUnsubscribePost {
+                key: key.to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(ParsingError::new(format!(
+                "Invalid List-Unsubscribe-Post: {}",
+                value
+            ))),
+        }
+    }
+}
+
+impl ToHeader for ListUnsubscribePost {
+    fn to_header(value: ListUnsubscribePost) -> ParsingResult<String> {
+        Ok(format!("{}={}", value.key, value.value))
+    }
+}
+
+/// All of the mailing list headers of a message.
+///
+/// Headers which are missing or fail to parse are `None`.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ListHeaders {
+    pub id: Option<ListId>,
+    pub help: Option<ListUris>,
+    pub unsubscribe: Option<ListUris>,
+    pub subscribe: Option<ListUris>,
+    pub post: Option<ListPost>,
+    pub owner: Option<ListUris>,
+    pub archive: Option<ListUris>,
+    pub unsubscribe_post: Option<ListUnsubscribePost>,
+}
+
+impl ListHeaders {
+    /// Pull the mailing list headers out of `headers`.
+    pub fn from_headers(headers: &HeaderMap) -> ListHeaders {
+        ListHeaders {
+            id: headers.get_value(LIST_ID.to_string()).ok(),
+            help: headers.get_value(LIST_HELP.to_string()).ok(),
+            unsubscribe: headers.get_value(LIST_UNSUBSCRIBE.to_string()).ok(),
+            subscribe: headers.get_value(LIST_SUBSCRIBE.to_string()).ok(),
+            post: headers.get_value(LIST_POST.to_string()).ok(),
+            owner: headers.get_value(LIST_OWNER.to_string()).ok(),
+            archive: headers.get_value(LIST_ARCHIVE.to_string()).ok(),
+            unsubscribe_post: headers.get_value(LIST_UNSUBSCRIBE_POST.to_string()).ok(),
+        }
+    }
+
+    /// Returns true if any list header was present.
+    pub fn is_list_message(&self) -> bool {
+        self.id.is_some()
+            || self.help.is_some()
+            || self.unsubscribe.is_some()
+            || self.subscribe.is_some()
+            || self.post.is_some()
+            || self.owner.is_some()
+            || self.archive.is_some()
+    }
+
+    /// The HTTPS URI to POST to for an RFC 8058 one-click unsubscription.
+    ///
+    /// This is only returned when the message carries both the one-click
+    /// `List-Unsubscribe-Post` token and an `https` unsubscribe URI.
+    pub fn one_click_unsubscribe(&self) -> Option<&str> {
+        match (&self.unsubscribe_post, &self.unsubscribe) {
+            (Some(post), Some(uris)) if post.is_one_click() => uris.with_scheme("https"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::header::Header;
+    use super::*;
+
+    #[test]
+    fn test_list_id_parse() {
+        let tests = vec![
+            (
+                "List Header Mailing List <list-header.nisto.com>",
+                Some((Some("List Header Mailing List"), "list-header.nisto.com")),
+            ),
+            (
+                "\"Quoted, list\" <quoted.example.org>",
+                Some((Some("Quoted, list"), "quoted.example.org")),
+            ),
+            (
+                "<commonspace-users.list-id.within.com>",
+                Some((None, "commonspace-users.list-id.within.com")),
+            ),
+            ("no brackets.example.org", None),
+            ("<nodots>", None),
+        ];
+
+        for (input, expected) in tests.into_iter() {
+            let header = Header::new(LIST_ID.to_string(), input.to_string());
+            let parsed: Option<ListId> = header.get_value().ok();
+            let expected = expected.map(|(description, id)| ListId {
+                description: description.map(|d: &str| d.to_string()),
+                id: id.to_string(),
+            });
+            assert_eq!(parsed, expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_list_id_to_header() {
+        let id = ListId {
+            description: Some("A \"quoted\" list".to_string()),
+            id: "list.example.org".to_string(),
+        };
+        let header = Header::new_with_value(LIST_ID.to_string(), id.clone()).unwrap();
+        assert_eq!(
+            header.to_string(),
+            "List-Id: \"A \\\"quoted\\\" list\" <list.example.org>"
+        );
+        assert_eq!(header.get_value::<ListId>().unwrap(), id);
+    }
+
+    #[test]
+    fn test_list_uris_parse() {
+        let tests = vec![
+            ("<mailto:list@host.com?subject=help>", vec!["mailto:list@host.com?subject=help"]),
+            (
+                "<http://www.host.com/list.cgi?cmd=sub&lst=list>,\r\n <mailto:list-manager@host.com?body=subscribe%20list>",
+                vec![
+                    "http://www.host.com/list.cgi?cmd=sub&lst=list",
+                    "mailto:list-manager@host.com?body=subscribe%20list",
+                ],
+            ),
+            (
+                "<mailto:list-off@host.com> (Use this command to get off the list)",
+                vec!["mailto:list-off@host.com"],
+            ),
+            ("<ftp://ftp.host.com/list.txt> (FTP),\r\n\t<mailto:list@host.com?subject=help>", vec!["ftp://ftp.host.com/list.txt", "mailto:list@host.com?subject=help"]),
+            ("<http://www.host.com/list/\r\n\tarchive>", vec!["http://www.host.com/list/archive"]),
+        ];
+
+        for (input, expected) in tests.into_iter() {
+            let header = Header::new(LIST_UNSUBSCRIBE.to_string(), input.to_string());
+            let parsed: ListUris = header.get_value().unwrap();
+            assert_eq!(parsed.uris, expected, "{}", input);
+        }
+
+        for input in vec![
+            "mailto:no-brackets@host.com",
+            "<mailto:unterminated@host.com",
+            "",
+        ]
+        .into_iter()
+        {
+            let header = Header::new(LIST_UNSUBSCRIBE.to_string(), input.to_string());
+            assert!(header.get_value::<ListUris>().is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_list_post_parse() {
+        let header = Header::new(
+            LIST_POST.to_string(),
+            "NO (posting not allowed on this list)".to_string(),
+        );
+        assert_eq!(header.get_value::<ListPost>().unwrap(), ListPost::No);
+
+        let header = Header::new(LIST_POST.to_string(), "<mailto:list@host.com>".to_string());
+        assert_eq!(
+            header.get_value::<ListPost>().unwrap(),
+            ListPost::Uris(ListUris {
+                uris: vec!["mailto:list@host.com".to_string()]
+            })
+        );
+    }
+
+    #[test]
+    fn test_list_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new(
+            LIST_ID.to_string(),
+            "<list.example.org>".to_string(),
+        ));
+        headers.insert(Header::new(
+            LIST_UNSUBSCRIBE.to_string(),
+            "<mailto:unsub@example.org>, <https://example.org/unsub?id=1>".to_string(),
+        ));
+        headers.insert(Header::new(
+            LIST_UNSUBSCRIBE_POST.to_string(),
+            "List-Unsubscribe=One-Click".to_string(),
+        ));
+
+        let list = ListHeaders::from_headers(&headers);
+        assert!(list.is_list_message());
+        assert_eq!(list.id.as_ref().unwrap().id, "list.example.org");
+        assert_eq!(
+            list.unsubscribe.as_ref().unwrap().with_scheme("mailto"),
+            Some("mailto:unsub@example.org")
+        );
+        assert_eq!(
+            list.one_click_unsubscribe(),
+            Some("https://example.org/unsub?id=1")
+        );
+        assert!(list.help.is_none());
+
+        // Schemes are compared without slicing through other characters
+        headers.replace(Header::new(
+            LIST_UNSUBSCRIBE.to_string(),
+            "<\u{e9}\u{e9}\u{e9}:x>, <HTTPS://example.org/unsub>".to_string(),
+        ));
+        let list = ListHeaders::from_headers(&headers);
+        assert_eq!(
+            list.one_click_unsubscribe(),
+            Some("HTTPS://example.org/unsub")
+        );
+
+        let list = ListHeaders::from_headers(&HeaderMap::new());
+        assert!(!list.is_list_message());
+        assert_eq!(list.one_click_unsubscribe(), None);
+    }
+
+    #[test]
+    fn test_list_unsubscribe_post() {
+        let header = Header::new_with_value(
+            LIST_UNSUBSCRIBE_POST.to_string(),
+            ListUnsubscribePost::one_click(),
+        )
+        .unwrap();
+        assert_eq!(
+            header.to_string(),
+            "List-Unsubscribe-Post: List-Unsubscribe=One-Click"
+        );
+        assert!(header
+            .get_value::<ListUnsubscribePost>()
+            .unwrap()
+            .is_one_click());
+
+        let header = Header::new(LIST_UNSUBSCRIBE_POST.to_string(), "garbage".to_string());
+        assert!(header.get_value::<ListUnsubscribePost>().is_err());
+    }
+}
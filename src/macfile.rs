@@ -0,0 +1,262 @@
+//! Module for files sent from classic Mac OS.
+//!
+//! Mac files have two forks: the data fork, which is what other systems
+//! call the file, and a resource fork of metadata such as icons. To send
+//! both, old Mac clients wrap the file in BinHex 4.0 text, sent as
+//! `application/mac-binhex40`, or in AppleSingle, sent as
+//! `application/applefile`. RFC 1740 instead sends the data fork as a part
+//! of its own after an AppleDouble header part, in a `multipart/appledouble`
+//! part. `MimeMessage::attachments` unwraps all of these.
+use super::charset::{self, DecodeOptions};
+use super::results::{ParsingError, ParsingResult};
+
+/// A file unwrapped from BinHex or AppleSingle data, or from an AppleDouble
+/// header.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct MacFile {
+    pub filename: Option<String>,
+    /// The content of the file, which is empty for an AppleDouble header
+    pub data_fork: Vec<u8>,
+    pub resource_fork: Vec<u8>,
+}
+
+const BINHEX_PREAMBLE: &str = "(This file must be converted with BinHex";
+const BINHEX_ALPHABET: &[u8] = b"!\"#$%&'()*+,-012345689@ABCDEFGHIJKLMNPQRSTUVXYZ[`abcdefhijklmpqr";
+const BINHEX_RUN: u8 = 0x90;
+
+const APPLESINGLE_MAGIC: u32 = 0x0005_1600;
+const APPLEDOUBLE_MAGIC: u32 = 0x0005_1607;
+const ENTRY_DATA_FORK: u32 = 1;
+const ENTRY_RESOURCE_FORK: u32 = 2;
+const ENTRY_REAL_NAME: u32 = 3;
+
+/// Decode a file from BinHex 4.0 text, checking its CRCs.
+///
+/// Any text before the `(This file must be converted with BinHex 4.0)`
+/// line is skipped.
+pub fn decode_binhex(text: &str) -> ParsingResult<MacFile> {
+    let invalid = |reason: &str| ParsingError::new(format!("Invalid BinHex data: {}", reason));
+
+    let start = text
+        .find(BINHEX_PREAMBLE)
+        .ok_or_else(|| invalid("missing preamble"))?;
+    let text = &text[start..];
+    let start = text.find(':').ok_or_else(|| invalid("missing start"))? + 1;
+    let end = text[start..]
+        .find(':')
+        .ok_or_else(|| invalid("missing end"))?
+        + start;
+
+    // Each character holds six bits
+    let mut encoded = Vec::new();
+    let (mut bits, mut count) = (0u32, 0);
+    for b in text[start..end].bytes() {
+        if b.is_ascii_whitespace() {
+            continue;
+        }
+        let value = BINHEX_ALPHABET
+            .iter()
+            .position(|&c| c == b)
+            .ok_or_else(|| invalid("unexpected character"))?;
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            encoded.push((bits >> count) as u8);
+        }
+    }
+
+    // Runs are given as the byte, a marker and the length of the run
+    let mut data = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.into_iter();
+    while let Some(b) = bytes.next() {
+        if b != BINHEX_RUN {
+            data.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(0) => data.push(BINHEX_RUN),
+            Some(length) => {
+                let last = *data.last().ok_or_else(|| invalid("run of nothing"))?;
+                data.extend((1..length).map(|_| last));
+            }
+            None => return Err(invalid("truncated run")),
+        }
+    }
+
+    let mut reader = Reader {
+        data: &data[..],
+        pos: 0,
+    };
+    let header_start = reader.pos;
+    let name_length = reader.take(1)?[0] as usize;
+    let name = reader.take(name_length)?;
+    // The version, type, creator and Finder flags
+    reader.take(11)?;
+    let data_length = reader.u32()? as usize;
+    let resource_length = reader.u32()? as usize;
+    reader.checked(header_start)?;
+    let data_start = reader.pos;
+    let data_fork = reader.take(data_length)?.to_vec();
+    reader.checked(data_start)?;
+    let resource_start = reader.pos;
+    let resource_fork = reader.take(resource_length)?.to_vec();
+    reader.checked(resource_start)?;
+
+    Ok(MacFile {
+        filename: mac_filename(name),
+        data_fork,
+        resource_fork,
+    })
+}
+
+/// Decode an AppleSingle file, as sent in `application/applefile` parts.
+pub fn decode_applesingle(data: &[u8]) -> ParsingResult<MacFile> {
+    decode_applefile(data, APPLESINGLE_MAGIC)
+}
+
+/// Decode the AppleDouble header sent as the first part of a
+/// `multipart/appledouble` part, which holds all but the data fork.
+pub fn decode_appledouble_header(data: &[u8]) -> ParsingResult<MacFile> {
+    decode_applefile(data, APPLEDOUBLE_MAGIC)
+}
+
+fn decode_applefile(data: &[u8], magic: u32) -> ParsingResult<MacFile> {
+    let mut reader = Reader { data, pos: 0 };
+    if reader.u32()? != magic {
+        return Err(ParsingError::new(
+            "Invalid AppleSingle data: wrong magic number".to_string(),
+        ));
+    }
+    // The version and filler
+    reader.take(20)?;
+    let count = reader.u16()?;
+
+    let mut file = MacFile {
+        filename: None,
+        data_fork: Vec::new(),
+        resource_fork: Vec::new(),
+    };
+    for _ in 0..count {
+        let id = reader.u32()?;
+        let offset = reader.u32()? as usize;
+        let length = reader.u32()? as usize;
+        let entry = data
+            .get(offset..offset.saturating_add(length))
+            .ok_or_else(|| ParsingError::new("Truncated AppleSingle data".to_string()))?;
+        match id {
+            ENTRY_DATA_FORK => file.data_fork = entry.to_vec(),
+            ENTRY_RESOURCE_FORK => file.resource_fork = entry.to_vec(),
+            ENTRY_REAL_NAME => file.filename = mac_filename(entry),
+            _ => {}
+        }
+    }
+    Ok(file)
+}
+
+// Filenames on classic Mac OS are in Mac Roman.
+fn mac_filename(name: &[u8]) -> Option<String> {
+    charset::decode(name, Some("macintosh"), &DecodeOptions::default())
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+// The CRC-16 of BinHex, with the CCITT polynomial and no initial value.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                crc << 1 ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+// Reads big-endian fields from the front of some data.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, length: usize) -> ParsingResult<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(length))
+            .ok_or_else(|| ParsingError::new("Truncated Mac file".to_string()))?;
+        self.pos += length;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> ParsingResult<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> ParsingResult<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // Read the CRC following the data from `start`, checking it.
+    fn checked(&mut self, start: usize) -> ParsingResult<()> {
+        let crc = crc16(&self.data[start..self.pos]);
+        if self.u16()? != crc {
+            return Err(ParsingError::new(
+                "Invalid BinHex data: bad CRC".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BINHEX: &str = "(This file must be converted with BinHex 4.0)\r\n\r\n\
+                          :#@KPE'a[,R4iG!\"849K8G(4iG!#3\"4F!N!-%S%G)C@aXEb`J6@&M)3eKN!U3!'UU\r\n\
+                          8P053iqh:\r\n";
+
+    #[test]
+    fn test_decode_binhex() {
+        let file = decode_binhex(&format!("Some text first\r\n{}", BINHEX)).unwrap();
+        assert_eq!(file.filename, Some("hello.txt".to_string()));
+        assert_eq!(file.data_fork, &b"Hello, Mac!\raaaaaaaaaa\x90"[..]);
+        assert_eq!(file.resource_fork, b"RSRC");
+
+        assert!(decode_binhex(&BINHEX.replace("8P05", "8P06")).is_err());
+        assert!(decode_binhex(&BINHEX.replace("iqh:", "iqh")).is_err());
+        assert!(decode_binhex(&BINHEX.replace("8P05", "8P0~")).is_err());
+        assert!(decode_binhex(":#@KPE'a[:").is_err());
+    }
+
+    #[test]
+    fn test_decode_applefile() {
+        let single = base64::decode(
+            "AAUWAAACAAAAAAAAAAAAAAAAAAAAAAAAAAMAAAADAAAAPgAAAAkAAAABAAAARwAAAAQAAAACAAAASwAAAA\
+             Rub3Rlcy50eHREYXRhUnNyYw==",
+        )
+        .unwrap();
+        let file = decode_applesingle(&single).unwrap();
+        assert_eq!(file.filename, Some("notes.txt".to_string()));
+        assert_eq!(file.data_fork, b"Data");
+        assert_eq!(file.resource_fork, b"Rsrc");
+        assert!(decode_appledouble_header(&single).is_err());
+        assert!(decode_applesingle(&single[..60]).is_err());
+
+        let double = base64::decode(
+            "AAUWBwACAAAAAAAAAAAAAAAAAAAAAAAAAAIAAAADAAAAMgAAAAcAAAACAAAAOQAAAARwaWMuZ2lmRm9yaw==",
+        )
+        .unwrap();
+        let file = decode_appledouble_header(&double).unwrap();
+        assert_eq!(file.filename, Some("pic.gif".to_string()));
+        assert!(file.data_fork.is_empty());
+        assert_eq!(file.resource_fork, b"Fork");
+    }
+}
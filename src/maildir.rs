@@ -0,0 +1,307 @@
+//! Reading and delivery of messages in Maildir mailboxes.
+//!
+//! A Maildir is a directory containing `tmp`, `new` and `cur`
+//! subdirectories, with each message stored in its own file. Messages are
+//! written into `tmp` and then moved into `new`, so readers never see a
+//! partial message. Once a message has been seen by a client it is moved
+//! into `cur`, with its flags appended to the filename after `:2,`.
+//!
+//! Flags are single characters, kept in ASCII order: `D` (draft), `F`
+//! (flagged), `P` (passed), `R` (replied), `S` (seen) and `T` (trashed).
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use super::environment;
+use super::message::MimeMessage;
+
+/// Separator between the unique name of a message and its info.
+const INFO_SEPARATOR: &str = ":2,";
+
+/// A message stored in a Maildir.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct MaildirEntry {
+    id: String,
+    flags: String,
+    path: PathBuf,
+    is_new: bool,
+}
+
+impl MaildirEntry {
+    fn from_path(path: PathBuf, is_new: bool) -> Option<MaildirEntry> {
+        let name = path.file_name()?.to_str()?.to_string();
+        if name.starts_with('.') {
+            return None;
+        }
+        let (id, flags) = match name.find(INFO_SEPARATOR) {
+            Some(i) => (&name[..i], &name[i + INFO_SEPARATOR.len()..]),
+            None => (&name[..], ""),
+        };
+        Some(MaildirEntry {
+            id: id.to_string(),
+            flags: normalize_flags(flags),
+            path,
+            is_new,
+        })
+    }
+
+    /// The unique name of this message, without any info suffix.
+    pub fn id(&self) -> &str {
+        &self.id[..]
+    }
+
+    /// The flags set on this message, in ASCII order.
+    pub fn flags(&self) -> &str {
+        &self.flags[..]
+    }
+
+    pub fn has_flag(&self, flag: char) -> bool {
+        self.flags.contains(flag)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the message is in `new`, and so hasn't been seen by a client.
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    /// Read and parse the message.
+    pub fn parse(&self) -> io::Result<MimeMessage> {
+        let bytes = fs::read(&self.path)?;
+        MimeMessage::parse_bytes(&bytes)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+// Sort and remove duplicates from a set of flags.
+fn normalize_flags(flags: &str) -> String {
+    let mut flags: Vec<char> = flags.chars().collect();
+    flags.sort_unstable();
+    flags.dedup();
+    flags.into_iter().collect()
+}
+
+/// A Maildir mailbox.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Maildir {
+    path: PathBuf,
+}
+
+impl Maildir {
+    /// Open the Maildir at `path`, which is expected to already exist.
+    pub fn new<P: Into<PathBuf>>(path: P) -> Maildir {
+        Maildir { path: path.into() }
+    }
+
+    /// Open the Maildir at `path`, creating it and its subdirectories if
+    /// they don't exist.
+    pub fn create<P: Into<PathBuf>>(path: P) -> io::Result<Maildir> {
+        let maildir = Maildir::new(path);
+        for dir in ["tmp", "new", "cur"].iter() {
+            fs::create_dir_all(maildir.path.join(dir))?;
+        }
+        Ok(maildir)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn list(&self, dir: &str) -> io::Result<Vec<MaildirEntry>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.path.join(dir))? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                entries.extend(MaildirEntry::from_path(entry.path(), dir == "new"));
+            }
+        }
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(entries)
+    }
+
+    /// The messages in `new`, which haven't been seen by a client yet.
+    pub fn list_new(&self) -> io::Result<Vec<MaildirEntry>> {
+        self.list("new")
+    }
+
+    /// The messages in `cur`.
+    pub fn list_cur(&self) -> io::Result<Vec<MaildirEntry>> {
+        self.list("cur")
+    }
+
+    /// Find a message in either `new` or `cur` by its unique name.
+    pub fn find(&self, id: &str) -> io::Result<Option<MaildirEntry>> {
+        let mut entries = self.list_new()?;
+        entries.extend(self.list_cur()?);
+        Ok(entries.into_iter().find(|entry| entry.id == id))
+    }
+
+    /// Deliver `message` into `new`, returning the entry for it.
+    ///
+    /// The message is written and synced to a file in `tmp` before being
+    /// moved into place.
+    pub fn deliver(&self, message: &MimeMessage) -> io::Result<MaildirEntry> {
+        let id = unique_name();
+        let tmp_path = self.path.join("tmp").join(&id);
+        let new_path = self.path.join("new").join(&id);
+
+        let result = (|| {
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&tmp_path)?;
+            file.write_all(&message.to_bytes())?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, &new_path)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result?;
+
+        Ok(MaildirEntry {
+            id,
+            flags: String::new(),
+            path: new_path,
+            is_new: true,
+        })
+    }
+
+    /// Set the flags on `entry`, moving it into `cur` if it was new.
+    pub fn set_flags(&self, entry: &MaildirEntry, flags: &str) -> io::Result<MaildirEntry> {
+        let flags = normalize_flags(flags);
+        let path = self
+            .path
+            .join("cur")
+            .join(format!("{}{}{}", entry.id, INFO_SEPARATOR, flags));
+        fs::rename(&entry.path, &path)?;
+        Ok(MaildirEntry {
+            id: entry.id.clone(),
+            flags,
+            path,
+            is_new: false,
+        })
+    }
+
+    /// Add `flag` to the flags on `entry`, moving it into `cur` if it was new.
+    pub fn add_flag(&self, entry: &MaildirEntry, flag: char) -> io::Result<MaildirEntry> {
+        let mut flags = entry.flags.clone();
+        flags.push(flag);
+        self.set_flags(entry, &flags[..])
+    }
+
+    /// Remove `flag` from the flags on `entry`, moving it into `cur` if it was new.
+    pub fn remove_flag(&self, entry: &MaildirEntry, flag: char) -> io::Result<MaildirEntry> {
+        let flags: String = entry.flags.chars().filter(|&c| c != flag).collect();
+        self.set_flags(entry, &flags[..])
+    }
+}
+
+// Generate a unique name for a new message, in the form described at
+// https://cr.yp.to/proto/maildir.html: the time, then a part unique to this
+// process and delivery, then the host name.
+fn unique_name() -> String {
+    let now = environment::now();
+    let random = environment::random_u64();
+    let hostname = hostname().replace('/', "\\057").replace(':', "\\072");
+    format!(
+        "{}.M{}P{}R{:016x}.{}",
+        now.timestamp(),
+        now.timestamp_subsec_micros(),
+        process::id(),
+        random,
+        hostname
+    )
+}
+
+fn hostname() -> String {
+    fs::read_to_string("/etc/hostname")
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TempMaildir(Maildir);
+
+    impl TempMaildir {
+        fn new(name: &str) -> TempMaildir {
+            let path = std::env::temp_dir().join(format!("email-{}-{}", name, unique_name()));
+            TempMaildir(Maildir::create(path).unwrap())
+        }
+    }
+
+    impl Drop for TempMaildir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.0.path());
+        }
+    }
+
+    #[test]
+    fn test_parse_entry_name() {
+        let entry =
+            MaildirEntry::from_path(PathBuf::from("cur/123.M1P2.host:2,SRFS"), false).unwrap();
+        assert_eq!(entry.id(), "123.M1P2.host");
+        assert_eq!(entry.flags(), "FRS");
+        assert!(entry.has_flag('S'));
+        assert!(!entry.has_flag('T'));
+
+        let entry = MaildirEntry::from_path(PathBuf::from("new/123.M1P2.host"), true).unwrap();
+        assert_eq!(entry.id(), "123.M1P2.host");
+        assert_eq!(entry.flags(), "");
+
+        assert!(MaildirEntry::from_path(PathBuf::from("cur/.hidden"), false).is_none());
+    }
+
+    #[test]
+    fn test_deliver_and_flag() {
+        let dir = TempMaildir::new("deliver");
+        let maildir = &dir.0;
+
+        let message = MimeMessage::parse("Subject: Hello\r\n\r\nHi!").unwrap();
+        let entry = maildir.deliver(&message).unwrap();
+        assert!(entry.is_new());
+        assert!(!entry.id().contains(':'));
+        assert_eq!(fs::read_dir(maildir.path().join("tmp")).unwrap().count(), 0);
+
+        let new = maildir.list_new().unwrap();
+        assert_eq!(new, vec![entry.clone()]);
+        let parsed = new[0].parse().unwrap();
+        assert_eq!(
+            parsed
+                .headers
+                .get_value::<String>("Subject".to_string())
+                .unwrap(),
+            "Hello"
+        );
+
+        let seen = maildir.add_flag(&entry, 'S').unwrap();
+        let replied = maildir.add_flag(&seen, 'R').unwrap();
+        assert!(maildir.list_new().unwrap().is_empty());
+        let cur = maildir.list_cur().unwrap();
+        assert_eq!(cur, vec![replied.clone()]);
+        assert_eq!(cur[0].flags(), "RS");
+        assert!(cur[0].path().to_str().unwrap().ends_with(":2,RS"));
+
+        let input = b"Content-Transfer-Encoding: 8bit\r\n\r\ncaf\xe9\r\n";
+        let latin1 = maildir
+            .deliver(&MimeMessage::parse_bytes(input).unwrap())
+            .unwrap();
+        assert_eq!(fs::read(latin1.path()).unwrap(), &input[..]);
+        assert_eq!(latin1.parse().unwrap().to_bytes(), &input[..]);
+
+        let unseen = maildir.remove_flag(&replied, 'S').unwrap();
+        assert_eq!(
+            maildir.find(entry.id()).unwrap().unwrap().flags(),
+            unseen.flags()
+        );
+    }
+}
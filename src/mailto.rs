@@ -0,0 +1,297 @@
+//! Module for the `mailto` URIs of RFC 6068.
+//!
+//! A `mailto` URI, as found in links and `List-*` headers, describes a
+//! message to be composed: its recipients, and optionally its subject, body
+//! and other header fields.
+use std::fmt;
+use std::str::FromStr;
+
+use super::address::{Address, Mailbox};
+use super::builder::MessageBuilder;
+use super::messageid::MessageId;
+use super::results::{ParsingError, ParsingResult};
+use super::rfc2045::percent_decode;
+
+/// A parsed `mailto` URI.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Mailto {
+    pub to: Vec<Mailbox>,
+    pub cc: Vec<Mailbox>,
+    pub bcc: Vec<Mailbox>,
+    pub subject: Option<String>,
+    pub body: Option<String>,
+    /// Any other header fields, such as `In-Reply-To`, in the order given
+    pub headers: Vec<(String, String)>,
+}
+
+impl Mailto {
+    /// Parse a `mailto` URI, such as
+    /// `mailto:joe@example.org?subject=Hello&cc=anna@example.org`.
+    ///
+    /// The URI is percent-decoded as UTF-8.
+    pub fn parse(uri: &str) -> ParsingResult<Mailto> {
+        let uri = uri.trim();
+        let rest = match uri.get(..7) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("mailto:") => &uri[7..],
+            _ => return Err(ParsingError::new(format!("Not a mailto URI: {}", uri))),
+        };
+        let (to, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], &rest[i + 1..]),
+            None => (rest, ""),
+        };
+
+        let mut mailto = Mailto {
+            to: parse_addresses(&decode(to))?,
+            ..Mailto::default()
+        };
+        for field in query.split('&').filter(|field| !field.is_empty()) {
+            let (name, value) = match field.find('=') {
+                Some(i) => (decode(&field[..i]), decode(&field[i + 1..])),
+                None => (decode(field), String::new()),
+            };
+            match &name.to_ascii_lowercase()[..] {
+                "to" => mailto.to.extend(parse_addresses(&value)?),
+                "cc" => mailto.cc.extend(parse_addresses(&value)?),
+                "bcc" => mailto.bcc.extend(parse_addresses(&value)?),
+                "subject" => mailto.subject = Some(value),
+                "body" => mailto.body = Some(value),
+                _ => mailto.headers.push((name, value)),
+            }
+        }
+        Ok(mailto)
+    }
+
+    /// The recipients, subject and plain text body of a draft.
+    pub fn from_builder(builder: &MessageBuilder) -> Mailto {
+        Mailto {
+            to: mailboxes(builder.get_to()),
+            cc: mailboxes(builder.get_cc()),
+            bcc: mailboxes(builder.get_bcc()),
+            subject: builder.get_subject().map(|s| s.to_string()),
+            body: builder.get_text_body().map(|s| s.to_string()),
+            headers: Vec::new(),
+        }
+    }
+
+    /// Start a draft with the recipients, subject and body of this URI.
+    ///
+    /// An `In-Reply-To` field is used if it holds a valid Message-ID. Other
+    /// header fields are left out, as RFC 6068 warns that they can't be
+    /// trusted; any which are wanted can be added with
+    /// `MessageBuilder::header`.
+    pub fn to_builder(&self) -> MessageBuilder {
+        let mut builder = MessageBuilder::new();
+        for mailbox in &self.to {
+            builder = builder.to(mailbox.clone());
+        }
+        for mailbox in &self.cc {
+            builder = builder.cc(mailbox.clone());
+        }
+        for mailbox in &self.bcc {
+            builder = builder.bcc(mailbox.clone());
+        }
+        if let Some(subject) = &self.subject {
+            builder = builder.subject(&subject[..]);
+        }
+        if let Some(body) = &self.body {
+            builder = builder.text_body(&body[..]);
+        }
+        let in_reply_to = self
+            .headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("In-Reply-To"))
+            .and_then(|(_, value)| value.parse::<MessageId>().ok());
+        if let Some(id) = in_reply_to {
+            builder = builder.in_reply_to(id);
+        }
+        builder
+    }
+}
+
+impl FromStr for Mailto {
+    type Err = ParsingError;
+
+    fn from_str(s: &str) -> ParsingResult<Mailto> {
+        Mailto::parse(s)
+    }
+}
+
+impl fmt::Display for Mailto {
+    /// Write this as a `mailto` URI, giving only the address of each
+    /// recipient, and line breaks in the body as CRLF.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mailto:{}", encode_addresses(&self.to))?;
+
+        let mut fields = Vec::new();
+        if !self.cc.is_empty() {
+            fields.push(("cc".to_string(), encode_addresses(&self.cc)));
+        }
+        if !self.bcc.is_empty() {
+            fields.push(("bcc".to_string(), encode_addresses(&self.bcc)));
+        }
+        if let Some(subject) = &self.subject {
+            fields.push(("subject".to_string(), encode(subject, "")));
+        }
+        if let Some(body) = &self.body {
+            let body = body.replace("\r\n", "\n").replace('\n', "\r\n");
+            fields.push(("body".to_string(), encode(&body, "")));
+        }
+        for (name, value) in &self.headers {
+            fields.push((encode(name, ""), encode(value, "")));
+        }
+
+        for (i, (name, value)) in fields.iter().enumerate() {
+            let separator = if i == 0 { '?' } else { '&' };
+            write!(f, "{}{}={}", separator, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+fn decode(s: &str) -> String {
+    String::from_utf8_lossy(&percent_decode(s)).into_owned()
+}
+
+// Percent-encode everything in `s` but unreserved characters and `keep`.
+fn encode(s: &str, keep: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for b in s.bytes() {
+        let c = b as char;
+        if c.is_ascii_alphanumeric() || "-._~".contains(c) || keep.contains(c) {
+            result.push(c);
+        } else {
+            result.push_str(&format!("%{:02X}", b));
+        }
+    }
+    result
+}
+
+fn encode_addresses(mailboxes: &[Mailbox]) -> String {
+    let addresses: Vec<String> = mailboxes
+        .iter()
+        .map(|mailbox| encode(&mailbox.address, "@"))
+        .collect();
+    addresses.join(",")
+}
+
+fn parse_addresses(s: &str) -> ParsingResult<Vec<Mailbox>> {
+    if s.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(mailboxes(&Address::parse_list(s)?))
+}
+
+fn mailboxes(addresses: &[Address]) -> Vec<Mailbox> {
+    let mut result = Vec::new();
+    for address in addresses {
+        match address {
+            Address::Mailbox(mailbox) => result.push(mailbox.clone()),
+            Address::Group(_, mailboxes) => result.extend(mailboxes.iter().cloned()),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mailto = Mailto::parse("mailto:chris@example.com").unwrap();
+        assert_eq!(
+            mailto.to,
+            vec![Mailbox::new("chris@example.com".to_string())]
+        );
+        assert_eq!(mailto.subject, None);
+
+        let mailto: Mailto = "MAILTO:joe@example.com,%20anna@example.com\
+                              ?cc=bob@example.com&to=ed@example.com\
+                              &subject=Caf%C3%A9%20time&body=send%20current-issue%0D%0Atoo\
+                              &In-Reply-To=%3C3469A91.D10AF4C@example.com%3E"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            mailto.to,
+            vec![
+                Mailbox::new("joe@example.com".to_string()),
+                Mailbox::new("anna@example.com".to_string()),
+                Mailbox::new("ed@example.com".to_string()),
+            ]
+        );
+        assert_eq!(mailto.cc, vec![Mailbox::new("bob@example.com".to_string())]);
+        assert_eq!(mailto.subject, Some("Café time".to_string()));
+        assert_eq!(mailto.body, Some("send current-issue\r\ntoo".to_string()));
+        assert_eq!(
+            mailto.headers,
+            vec![(
+                "In-Reply-To".to_string(),
+                "<3469A91.D10AF4C@example.com>".to_string()
+            )]
+        );
+
+        let mailto = Mailto::parse("mailto:?to=joe@example.com&body=hi").unwrap();
+        assert_eq!(mailto.to, vec![Mailbox::new("joe@example.com".to_string())]);
+        assert!(Mailto::parse("https://example.com").is_err());
+        assert!(Mailto::parse("mailto:joe@").is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let mailto = Mailto {
+            to: vec![
+                Mailbox::new_with_name("Joe".to_string(), "joe@example.com".to_string()),
+                Mailbox::new("anna+list@example.com".to_string()),
+            ],
+            bcc: vec![Mailbox::new("bob@example.com".to_string())],
+            subject: Some("Café & more".to_string()),
+            body: Some("Line one\nLine two".to_string()),
+            headers: vec![("Keywords".to_string(), "a=b".to_string())],
+            ..Mailto::default()
+        };
+        let uri = mailto.to_string();
+        assert_eq!(
+            uri,
+            "mailto:joe@example.com,anna%2Blist@example.com?bcc=bob@example.com\
+             &subject=Caf%C3%A9%20%26%20more&body=Line%20one%0D%0ALine%20two\
+             &Keywords=a%3Db"
+        );
+        let parsed = Mailto::parse(&uri).unwrap();
+        assert_eq!(parsed.to[0].address, "joe@example.com");
+        assert_eq!(parsed.to[1].address, "anna+list@example.com");
+        assert_eq!(parsed.subject, mailto.subject);
+        assert_eq!(parsed.body, Some("Line one\r\nLine two".to_string()));
+        assert_eq!(parsed.headers, mailto.headers);
+        assert_eq!(Mailto::default().to_string(), "mailto:");
+    }
+
+    #[test]
+    fn test_builder() {
+        let mailto = Mailto::parse(
+            "mailto:joe@example.com?subject=Hi&body=Hello&cc=anna@example.com\
+             &In-Reply-To=%3Cabc@example.com%3E&X-Evil=1",
+        )
+        .unwrap();
+        let builder = mailto.to_builder();
+        assert_eq!(builder.get_subject(), Some("Hi"));
+        assert_eq!(builder.get_text_body(), Some("Hello"));
+        assert_eq!(
+            builder.recipients(),
+            vec![
+                Mailbox::new("joe@example.com".to_string()),
+                Mailbox::new("anna@example.com".to_string()),
+            ]
+        );
+
+        let message = builder.clone().from("me@example.org").build().unwrap();
+        assert!(message.headers.get("In-Reply-To".to_string()).is_some());
+        assert!(message.headers.get("X-Evil".to_string()).is_none());
+
+        let round_trip = Mailto::from_builder(&builder);
+        assert_eq!(round_trip.to, mailto.to);
+        assert_eq!(round_trip.cc, mailto.cc);
+        assert_eq!(round_trip.subject, mailto.subject);
+        assert_eq!(round_trip.body, mailto.body);
+        assert!(round_trip.headers.is_empty());
+    }
+}
@@ -0,0 +1,366 @@
+//! Reading and writing of mbox mailbox files.
+//!
+//! Messages in an mbox file are each preceded by a `From ` line giving the
+//! envelope sender and delivery date. Lines in the body that would be
+//! mistaken for one are escaped with a leading `>`.
+use std::io::{self, BufRead, Read, Write};
+
+use chrono::{DateTime, Utc};
+
+use super::message::MimeMessage;
+use super::results::ParsingResult;
+
+/// The variant of mbox used by a file.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum MboxFormat {
+    /// Any line matching `>*From ` in a message is escaped with one more `>`,
+    /// so the escaping can be reversed exactly.
+    Mboxrd,
+    /// The length of each message body is given by its `Content-Length`
+    /// header, and lines starting `From ` are escaped as `>From `.
+    Mboxcl,
+}
+
+/// A single message read out of an mbox file.
+///
+/// The message is only parsed when `parse` is called.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct MboxMessage {
+    from_line: String,
+    raw: Vec<u8>,
+}
+
+impl MboxMessage {
+    /// The `From ` line preceding this message, without the line ending.
+    pub fn from_line(&self) -> &str {
+        &self.from_line[..]
+    }
+
+    /// The envelope sender given on the `From ` line.
+    pub fn sender(&self) -> &str {
+        let rest = self.from_line.get(5..).unwrap_or("");
+        rest.split_whitespace().next().unwrap_or("")
+    }
+
+    /// The unescaped bytes of this message.
+    pub fn raw(&self) -> &[u8] {
+        &self.raw[..]
+    }
+
+    /// Parse this message, as `MimeMessage::parse_bytes` does.
+    pub fn parse(&self) -> ParsingResult<MimeMessage> {
+        MimeMessage::parse_bytes(&self.raw)
+    }
+}
+
+fn is_from_line(line: &[u8]) -> bool {
+    line.starts_with(b"From ")
+}
+
+fn is_blank(line: &[u8]) -> bool {
+    line == b"\n" || line == b"\r\n"
+}
+
+// Remove the escaping from a line of a message body.
+fn unescape_line(format: MboxFormat, line: &[u8]) -> &[u8] {
+    let quoted = line.iter().take_while(|&&b| b == b'>').count();
+    let escaped = match format {
+        MboxFormat::Mboxrd => quoted > 0,
+        MboxFormat::Mboxcl => quoted == 1,
+    };
+    if escaped && is_from_line(&line[quoted..]) {
+        &line[1..]
+    } else {
+        line
+    }
+}
+
+// Find the value of a Content-Length header in a block of header lines.
+fn content_length(headers: &[u8]) -> Option<usize> {
+    String::from_utf8_lossy(headers)
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+}
+
+/// Iterator over the messages in an mbox file.
+pub struct MboxReader<R> {
+    reader: R,
+    format: MboxFormat,
+    next_from_line: Option<String>,
+}
+
+impl<R: BufRead> MboxReader<R> {
+    pub fn new(reader: R, format: MboxFormat) -> MboxReader<R> {
+        MboxReader {
+            reader,
+            format,
+            next_from_line: None,
+        }
+    }
+
+    fn read_line(&mut self, line: &mut Vec<u8>) -> io::Result<bool> {
+        line.clear();
+        Ok(self.reader.read_until(b'\n', line)? > 0)
+    }
+
+    // Read up to the next `From ` line, which is remembered for the next
+    // message, appending the unescaped lines to `raw`.
+    fn read_until_from_line(&mut self, raw: &mut Vec<u8>) -> io::Result<()> {
+        let mut line = Vec::new();
+        let mut after_blank = raw.is_empty() || raw.ends_with(b"\n\n") || raw.ends_with(b"\n\r\n");
+        while self.read_line(&mut line)? {
+            if after_blank && is_from_line(&line) {
+                self.next_from_line = Some(String::from_utf8_lossy(&line).trim_end().to_string());
+                break;
+            }
+            after_blank = is_blank(&line);
+            raw.extend_from_slice(unescape_line(self.format, &line));
+        }
+        Ok(())
+    }
+
+    fn read_message(&mut self, from_line: String) -> io::Result<MboxMessage> {
+        let mut raw = Vec::new();
+        let mut line = Vec::new();
+
+        if self.format == MboxFormat::Mboxcl {
+            // Read the headers to find the length of the body
+            while self.read_line(&mut line)? {
+                raw.extend_from_slice(&line);
+                if is_blank(&line) {
+                    break;
+                }
+            }
+            if let Some(length) = content_length(&raw) {
+                // The length isn't to be trusted to size the buffer with
+                let mut body = Vec::new();
+                (&mut self.reader)
+                    .take(length as u64)
+                    .read_to_end(&mut body)?;
+                for line in body.split_inclusive(|&b| b == b'\n') {
+                    raw.extend_from_slice(unescape_line(self.format, line));
+                }
+            }
+        }
+
+        self.read_until_from_line(&mut raw)?;
+
+        // The blank line before the next `From ` line isn't part of the message
+        if raw.ends_with(b"\r\n\r\n") {
+            raw.truncate(raw.len() - 2);
+        } else if raw.ends_with(b"\n\n") {
+            raw.truncate(raw.len() - 1);
+        }
+
+        Ok(MboxMessage { from_line, raw })
+    }
+}
+
+impl<R: BufRead> Iterator for MboxReader<R> {
+    type Item = io::Result<MboxMessage>;
+
+    fn next(&mut self) -> Option<io::Result<MboxMessage>> {
+        let from_line = match self.next_from_line.take() {
+            Some(line) => line,
+            None => {
+                // Skip anything before the first `From ` line
+                let mut ignored = Vec::new();
+                if let Err(e) = self.read_until_from_line(&mut ignored) {
+                    return Some(Err(e));
+                }
+                self.next_from_line.take()?
+            }
+        };
+        Some(self.read_message(from_line))
+    }
+}
+
+/// Writer appending messages to an mbox file in mboxrd format.
+///
+/// Messages are written with LF line endings, as is conventional for mbox.
+pub struct MboxWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> MboxWriter<W> {
+    pub fn new(writer: W) -> MboxWriter<W> {
+        MboxWriter { writer }
+    }
+
+    /// Append `message`, received from the envelope sender `sender` at `date`.
+    pub fn append(
+        &mut self,
+        message: &MimeMessage,
+        sender: &str,
+        date: &DateTime<Utc>,
+    ) -> io::Result<()> {
+        let sender = if sender.is_empty() || sender.contains(char::is_whitespace) {
+            "MAILER-DAEMON"
+        } else {
+            sender
+        };
+        writeln!(
+            self.writer,
+            "From {} {}",
+            sender,
+            date.format("%a %b %e %H:%M:%S %Y")
+        )?;
+
+        let content = message.to_bytes();
+        for line in content.split_inclusive(|&b| b == b'\n') {
+            let quoted = line.iter().take_while(|&&b| b == b'>').count();
+            if is_from_line(&line[quoted..]) {
+                self.writer.write_all(b">")?;
+            }
+            match line.strip_suffix(b"\r\n") {
+                Some(line) => {
+                    self.writer.write_all(line)?;
+                    self.writer.write_all(b"\n")?;
+                }
+                None => self.writer.write_all(line)?,
+            }
+        }
+        if !content.ends_with(b"\n") {
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Get back the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::offset::TimeZone;
+
+    fn read_all(input: &str, format: MboxFormat) -> Vec<MboxMessage> {
+        MboxReader::new(input.as_bytes(), format)
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read_mboxrd() {
+        let messages = read_all(
+            "From joe@example.org Thu Jan  2 03:04:05 2020\n\
+             Subject: One\n\
+             \n\
+             >From here\n\
+             >>From there\n\
+             > From nowhere\n\
+             \n\
+             From john@example.org Fri Jan  3 03:04:05 2020\n\
+             Subject: Two\n\
+             \n\
+             Body\n",
+            MboxFormat::Mboxrd,
+        );
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].sender(), "joe@example.org");
+        assert_eq!(
+            messages[0].raw(),
+            &b"Subject: One\n\nFrom here\n>From there\n> From nowhere\n"[..]
+        );
+        assert_eq!(
+            messages[1].from_line(),
+            "From john@example.org Fri Jan  3 03:04:05 2020"
+        );
+        let parsed = messages[1].parse().unwrap();
+        assert_eq!(
+            parsed
+                .headers
+                .get_value::<String>("Subject".to_string())
+                .unwrap(),
+            "Two"
+        );
+        assert_eq!(parsed.body, "Body\n");
+    }
+
+    #[test]
+    fn test_read_mboxcl() {
+        let messages = read_all(
+            "From joe@example.org Thu Jan  2 03:04:05 2020\n\
+             Content-Length: 23\n\
+             \n\
+             Line\n\
+             \n\
+             From inside\n\
+             >From\n\
+             \n\
+             From john@example.org Fri Jan  3 03:04:05 2020\n\
+             \n\
+             >From escaped\n",
+            MboxFormat::Mboxcl,
+        );
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0].raw(),
+            &b"Content-Length: 23\n\nLine\n\nFrom inside\n>From\n"[..]
+        );
+        assert_eq!(messages[1].raw(), &b"\nFrom escaped\n"[..]);
+
+        let messages = read_all(
+            "From \n\
+             Content-Length: 18446744073709551615\n\
+             \n\
+             Body\n",
+            MboxFormat::Mboxcl,
+        );
+        assert_eq!(messages[0].sender(), "");
+        let expected = b"Content-Length: 18446744073709551615\n\nBody\n";
+        assert_eq!(messages[0].raw(), &expected[..]);
+    }
+
+    #[test]
+    fn test_write_round_trip() {
+        let mut first = MimeMessage::new_blank_message();
+        first.body = "From the start\r\n>From quoted".into();
+        let second = MimeMessage::parse("Subject: Two\r\n\r\nBody\r\n").unwrap();
+        let date = Utc.ymd(2020, 1, 2).and_hms(3, 4, 5);
+
+        let mut writer = MboxWriter::new(Vec::new());
+        writer.append(&first, "joe@example.org", &date).unwrap();
+        writer.append(&second, "", &date).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+        assert_eq!(
+            output,
+            "From joe@example.org Thu Jan  2 03:04:05 2020\n\
+             \n\
+             >From the start\n\
+             >>From quoted\n\
+             \n\
+             From MAILER-DAEMON Thu Jan  2 03:04:05 2020\n\
+             Subject: Two\n\
+             \n\
+             Body\n\
+             \n"
+        );
+
+        let messages = read_all(&output[..], MboxFormat::Mboxrd);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].raw(), &b"\nFrom the start\n>From quoted\n"[..]);
+        assert_eq!(messages[1].sender(), "MAILER-DAEMON");
+
+        let input = b"Content-Transfer-Encoding: 8bit\n\ncaf\xe9\n";
+        let message = MimeMessage::parse_bytes(input).unwrap();
+        let mut writer = MboxWriter::new(Vec::new());
+        writer.append(&message, "", &date).unwrap();
+        let output = writer.into_inner();
+        let messages: Vec<_> = MboxReader::new(&output[..], MboxFormat::Mboxrd)
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(messages[0].raw(), &input[..]);
+        assert_eq!(messages[0].parse().unwrap().to_bytes(), &input[..]);
+    }
+}
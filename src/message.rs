@@ -1,18 +1,33 @@
-use super::header::{Header, HeaderMap};
-use super::mimeheaders::{MimeContentTransferEncoding, MimeContentType, MimeContentTypeHeader};
-use super::results::{ParsingError, ParsingResult};
-use super::rfc5322::{Rfc5322Builder, Rfc5322Parser};
-
-use std::collections::HashMap;
+use memchr::{memchr, memchr_iter, memmem};
+
+use super::bodyparse;
+use super::charset::{self, DecodeOptions};
+use super::convert::html_to_text;
+use super::envelope::Envelope;
+use super::environment;
+use super::flowed::FlowedText;
+use super::header::{fold, FoldingPolicy, FromHeader, Header, HeaderDecodePolicy, HeaderMap};
+use super::messageid::MessageId;
+use super::mimeheaders::{
+    ContentDisposition, ContentType, MimeContentTransferEncoding, MimeContentType,
+};
+use super::repair;
+use super::results::{ParseWarning, ParsingError, ParsingResult, WarningCode};
+use super::rfc2047::is_valid_encoded_word;
+use super::rfc5322::Rfc5322Parser;
+use super::walk::PartPath;
+
+use std::borrow::Cow;
 use std::fmt;
+use std::io::{self, BufRead};
+use std::ops::{Deref, Range};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use encoding::label::encoding_from_whatwg_label;
-use encoding::DecoderTrap;
-
-use rand::distributions::Alphanumeric;
-use rand::{thread_rng, Rng};
-
+/// The number of random characters in a generated boundary
 const BOUNDARY_LENGTH: usize = 30;
+/// The longest boundary allowed by RFC 2046
+const MAX_BOUNDARY_LENGTH: usize = 70;
 
 /// Marks the type of a multipart message
 #[derive(Eq, PartialEq, Debug, Clone, Copy)]
@@ -44,6 +59,11 @@ pub enum MimeMultipartType {
     ///
     /// As defined by Section 2.1 of RFC 1847
     Signed,
+    /// A compound object, such as an HTML page and the images it shows,
+    /// whose first entry is the root.
+    ///
+    /// As defined by RFC 2387
+    Related,
 }
 
 impl MimeMultipartType {
@@ -56,6 +76,7 @@ impl MimeMultipartType {
             ("multipart", "parallel") => Some(MimeMultipartType::Parallel),
             ("multipart", "encrypted") => Some(MimeMultipartType::Encrypted),
             ("multipart", "signed") => Some(MimeMultipartType::Signed),
+            ("multipart", "related") => Some(MimeMultipartType::Related),
             ("multipart", "mixed") | ("multipart", _) => Some(MimeMultipartType::Mixed),
             _ => None,
         }
@@ -71,6 +92,7 @@ impl MimeMultipartType {
             MimeMultipartType::Encrypted => (multipart, "encrypted".to_string()),
             MimeMultipartType::Parallel => (multipart, "parallel".to_string()),
             MimeMultipartType::Signed => (multipart, "signed".to_string()),
+            MimeMultipartType::Related => (multipart, "related".to_string()),
         }
     }
 }
@@ -82,9 +104,437 @@ impl fmt::Display for MimeMultipartType {
     }
 }
 
+/// The undecoded body of a MIME part.
+///
+/// The body of a parsed part refers to the input it was parsed from, which
+/// is shared by all the parts of the message, rather than holding a copy of
+/// its own. It is only transfer decoded when asked for, with
+/// `MimeMessage::decoded_body`.
+///
+/// A body which isn't valid UTF-8, such as that of a `binary` or `8bit`
+/// part in a legacy charset, keeps its bytes as they were, which
+/// `as_bytes` gives. Its text then has a placeholder, `U+001A`, in place of
+/// each byte which isn't part of a valid UTF-8 sequence.
+#[derive(Clone, Default)]
+pub struct RawBody {
+    source: Arc<str>,
+    range: Range<usize>,
+    // The bytes of the source, if it wasn't all valid UTF-8
+    bytes: Option<Arc<[u8]>>,
+}
+
+impl RawBody {
+    // The body covering `range` of the same source as `body`.
+    fn shared(body: &RawBody, range: Range<usize>) -> RawBody {
+        RawBody {
+            source: Arc::clone(&body.source),
+            range,
+            bytes: body.bytes.clone(),
+        }
+    }
+
+    // The part of this body covering `range`, sharing its source.
+    fn slice(&self, range: Range<usize>) -> RawBody {
+        RawBody::shared(
+            self,
+            (self.range.start + range.start)..(self.range.start + range.end),
+        )
+    }
+
+    // The whole source this body is part of, as bytes.
+    fn source_bytes(&self) -> &[u8] {
+        match &self.bytes {
+            Some(bytes) => bytes,
+            None => self.source.as_bytes(),
+        }
+    }
+
+    /// The body as a string slice, with placeholders for any bytes which
+    /// aren't valid UTF-8.
+    pub fn as_str(&self) -> &str {
+        &self.source[self.range.clone()]
+    }
+
+    /// The body as the bytes it was parsed or set from.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.source_bytes()[self.range.clone()]
+    }
+
+    /// Whether the body isn't valid UTF-8, and so has placeholders in place
+    /// of some of its bytes when taken as a string.
+    pub fn is_binary(&self) -> bool {
+        self.bytes.is_some()
+    }
+}
+
+// The byte which takes the place of each byte of `bytes` that isn't part
+// of a valid UTF-8 sequence, so that offsets into the text are offsets into
+// the bytes too.
+const PLACEHOLDER: u8 = 0x1A;
+
+// `bytes` as text, with each byte of an invalid UTF-8 sequence replaced by
+// `PLACEHOLDER`.
+fn placeholder_text(bytes: &[u8]) -> String {
+    let mut text = Vec::with_capacity(bytes.len());
+    let mut rest = bytes;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.extend_from_slice(valid.as_bytes());
+                break;
+            }
+            Err(e) => {
+                let valid = e.valid_up_to();
+                let invalid = e.error_len().unwrap_or(rest.len() - valid);
+                text.extend_from_slice(&rest[..valid]);
+                text.resize(text.len() + invalid, PLACEHOLDER);
+                rest = &rest[valid + invalid..];
+            }
+        }
+    }
+    String::from_utf8(text).unwrap()
+}
+
+impl Deref for RawBody {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<String> for RawBody {
+    fn from(body: String) -> RawBody {
+        let range = 0..body.len();
+        RawBody {
+            source: Arc::from(body),
+            range,
+            bytes: None,
+        }
+    }
+}
+
+impl<'a> From<&'a str> for RawBody {
+    fn from(body: &'a str) -> RawBody {
+        RawBody {
+            source: Arc::from(body),
+            range: 0..body.len(),
+            bytes: None,
+        }
+    }
+}
+
+impl From<Vec<u8>> for RawBody {
+    fn from(body: Vec<u8>) -> RawBody {
+        match String::from_utf8(body) {
+            Ok(body) => body.into(),
+            Err(e) => {
+                let bytes = e.into_bytes();
+                RawBody {
+                    source: Arc::from(placeholder_text(&bytes)),
+                    range: 0..bytes.len(),
+                    bytes: Some(Arc::from(bytes)),
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for RawBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl fmt::Debug for RawBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl PartialEq for RawBody {
+    fn eq(&self, other: &RawBody) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for RawBody {}
+
+impl PartialEq<str> for RawBody {
+    fn eq(&self, other: &str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<'a> PartialEq<&'a str> for RawBody {
+    fn eq(&self, other: &&'a str) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl PartialEq<String> for RawBody {
+    fn eq(&self, other: &String) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl PartialEq<RawBody> for str {
+    fn eq(&self, other: &RawBody) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl PartialEq<RawBody> for &str {
+    fn eq(&self, other: &RawBody) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl PartialEq<RawBody> for String {
+    fn eq(&self, other: &RawBody) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+// Whether `content` is made of lines, as the `7bit` and `8bit` transfer
+// encodings require, with no NULs or bare CRs or LFs and no line longer
+// than RFC 5322 allows.
+fn is_line_oriented(content: &[u8]) -> bool {
+    let mut line_len = 0;
+    for (i, &b) in content.iter().enumerate() {
+        match b {
+            b'\n' => line_len = 0,
+            b'\0' => return false,
+            b'\r' if content.get(i + 1) != Some(&b'\n') => return false,
+            b'\r' => {}
+            _ => line_len += 1,
+        }
+        if line_len > 998 {
+            return false;
+        }
+    }
+    true
+}
+
+// `bytes` as a string, with any invalid UTF-8 replaced.
+fn lossy_string(bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+    }
+}
+
+// Count the lines of `s`, including a last line without a line break.
+fn count_lines(s: &str) -> usize {
+    let breaks = memchr_iter(b'\n', s.as_bytes()).count();
+    if s.is_empty() || s.ends_with('\n') {
+        breaks
+    } else {
+        breaks + 1
+    }
+}
+
+/// Options controlling how messages are parsed.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Parse the parts of each multipart in parallel, which only has an
+    /// effect when built with the `rayon` feature.
+    pub parallel: bool,
+    /// The limits on the message, beyond which parsing fails
+    pub limits: Limits,
+    /// Look for files uuencoded in the text parts of the message, which
+    /// `MimeMessage::attachments` then gives along with the other
+    /// attachments
+    pub uuencode: bool,
+    /// Fail on a multipart which ends without its closing boundary, and
+    /// fail to decode bodies which aren't valid for their transfer encoding
+    /// rather than repairing them as they're decoded
+    pub strict: bool,
+    /// Which headers have their encoded words decoded when parsing, rather
+    /// than when `Header::decoded_value` is called, or never
+    pub header_decoding: HeaderDecodePolicy,
+    /// The bugs of particular mail programs to work around, none by default
+    pub quirks: Quirks,
+}
+
+/// A bug of a particular mail program which can be worked around, when
+/// it's set in `ParseOptions::quirks`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Quirk {
+    /// Outlook wraps the files it sends in an `application/ms-tnef` part,
+    /// usually named `winmail.dat`, which `MimeMessage::attachments` then
+    /// unwraps with `SimpleTnefDecoder`. This needs the `tnef` feature.
+    TnefAttachments,
+    /// Gmail may leave the charset out of `text/plain` parts, or give it
+    /// as `us-ascii`, when they hold UTF-8. Text which is valid UTF-8 is
+    /// then decoded as UTF-8 even if it's labelled `us-ascii`.
+    Utf8AsAscii,
+    /// Exchange may fold long `References` and `In-Reply-To` headers
+    /// without indenting the lines it folds onto, which would otherwise end
+    /// the headers there. Lines starting with `<` straight after such a
+    /// header are taken as part of it.
+    UnindentedReferences,
+    /// Apple Mail marks files it attaches as `inline`, so that they're
+    /// shown in the text. A part which has a filename is then taken as an
+    /// attachment even if it's marked `inline`, so it's never mistaken for
+    /// the body of the message.
+    InlineAttachments,
+}
+
+/// A set of `Quirk`s to work around.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub struct Quirks(u8);
+
+impl Quirks {
+    /// No quirks, so messages are parsed as the RFCs say.
+    pub fn none() -> Quirks {
+        Quirks(0)
+    }
+
+    /// Every quirk.
+    pub fn all() -> Quirks {
+        [
+            Quirk::TnefAttachments,
+            Quirk::Utf8AsAscii,
+            Quirk::UnindentedReferences,
+            Quirk::InlineAttachments,
+        ]
+        .iter()
+        .fold(Quirks::none(), |quirks, &quirk| quirks.with(quirk))
+    }
+
+    /// These quirks along with `quirk`.
+    pub fn with(self, quirk: Quirk) -> Quirks {
+        Quirks(self.0 | 1 << quirk as u8)
+    }
+
+    /// These quirks apart from `quirk`.
+    pub fn without(self, quirk: Quirk) -> Quirks {
+        Quirks(self.0 & !(1 << quirk as u8))
+    }
+
+    pub fn contains(&self, quirk: Quirk) -> bool {
+        self.0 & 1 << quirk as u8 != 0
+    }
+}
+
+/// Limits on the size and shape of a message, beyond which parsing fails
+/// with `ParsingError::LimitExceeded` rather than using unbounded time,
+/// memory or stack on adversarial input.
+///
+/// The default limits are well beyond what genuine messages need.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Limits {
+    /// The most headers any one part may have
+    pub max_header_count: usize,
+    /// The longest any header may be in bytes, including its name and any
+    /// folding
+    pub max_header_length: usize,
+    /// How deeply multipart parts may be nested, with the parts of the
+    /// message itself at depth 1
+    pub max_depth: usize,
+    /// The most parts in the whole message, counting the message itself
+    pub max_parts: usize,
+    /// The largest the decoded body of any one part may be, in bytes
+    ///
+    /// A body can't be larger decoded than it is in the input, so there's
+    /// no limit on this by default.
+    pub max_decoded_size: usize,
+}
+
+impl Limits {
+    /// No limits at all, for trusted input.
+    ///
+    /// Parsing doesn't recurse, so multiparts nested to any depth can be
+    /// parsed, but cloning, dropping or writing out the message still takes
+    /// stack for each level of nesting.
+    pub fn unlimited() -> Limits {
+        Limits {
+            max_header_count: usize::MAX,
+            max_header_length: usize::MAX,
+            max_depth: usize::MAX,
+            max_parts: usize::MAX,
+            max_decoded_size: usize::MAX,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_header_count: 10_000,
+            max_header_length: 1 << 20,
+            max_depth: 100,
+            max_parts: 10_000,
+            max_decoded_size: usize::MAX,
+        }
+    }
+}
+
+pub(crate) fn limit_exceeded(limit: &str, max: usize) -> ParsingError {
+    ParsingError::LimitExceeded {
+        limit: limit.to_string(),
+        max,
+    }
+}
+
+// Join the lines `parser` stopped at onto the `References` or
+// `In-Reply-To` header last in `headers` while they look like its
+// continuation without indenting, and carry on with the headers after
+// them, for `Quirk::UnindentedReferences`.
+//
+// The limits apply to the joined header, and to the headers as a whole
+// rather than to each run of them between joined lines.
+fn join_unindented_references(
+    parser: &mut Rfc5322Parser,
+    headers: &mut HeaderMap,
+    limits: &Limits,
+) -> ParsingResult<()> {
+    let (max_count, max_length) = (limits.max_header_count, limits.max_header_length);
+    loop {
+        let (name, mut value) = match headers.iter().last() {
+            Some(last) if last.name == "References" || last.name == "In-Reply-To" => {
+                (last.name.to_string(), last.raw_value().to_string())
+            }
+            _ => return Ok(()),
+        };
+        if parser.eof() || parser.peek() != '<' {
+            return Ok(());
+        }
+        while !parser.eof() && parser.peek() == '<' {
+            let line = parser.consume_while_str(|c| c != '\r' && c != '\n');
+            parser.consume_linebreak();
+            value.push(' ');
+            value.push_str(line.trim());
+            if name.len() + 2 + value.len() > max_length {
+                return Err(limit_exceeded("header length", max_length));
+            }
+        }
+        let n = headers.find(&name).map_or(0, |found| found.len());
+        headers.replace_nth(&name, n - 1, Header::new_raw(&name[..], value));
+
+        let max_count = max_count.saturating_sub(headers.len());
+        let more = parser.consume_headers_with_limits(max_count, max_length)?;
+        for header in more.iter() {
+            headers.insert(header.clone());
+        }
+    }
+}
+
+// At most the size of `body` once decoded with `encoding`, without decoding it
+fn decoded_size_bound(body: &str, encoding: MimeContentTransferEncoding) -> usize {
+    match encoding {
+        MimeContentTransferEncoding::Base64 => {
+            let chars = body.bytes().filter(|b| !b.is_ascii_whitespace()).count();
+            chars / 4 * 3 + chars % 4
+        }
+        _ => body.len(),
+    }
+}
+
 /// Represents a MIME message
 /// [unstable]
-#[derive(Eq, PartialEq, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct MimeMessage {
     /// The headers for this message
     pub headers: HeaderMap,
@@ -93,7 +543,7 @@ pub struct MimeMessage {
     ///
     /// Keep in mind that this is the undecoded form, so may be quoted-printable
     /// or base64 encoded.
-    pub body: String,
+    pub body: RawBody,
 
     /// The MIME multipart message type of this message, or `None` if the message
     /// is not a multipart message.
@@ -106,21 +556,80 @@ pub struct MimeMessage {
     ///
     /// This will always be set, even if the message only has a single part
     pub boundary: String,
+
+    /// The SMTP envelope this message was received with, if known
+    ///
+    /// This isn't part of the content of the message, so isn't serialized or
+    /// compared, but see `MimeMessage::add_trace_headers`.
+    pub envelope: Option<Envelope>,
+
+    span: Option<Range<usize>>,
+    // The size in bytes and number of lines of the body as parsed
+    encoded_size: Option<usize>,
+    line_count: Option<usize>,
+    // What the part held when it was parsed, so that it can be written out
+    // as it appeared in the input while that's unchanged
+    original: Option<Original>,
+    // Whether the part was parsed with `ParseOptions::uuencode`
+    pub(crate) uuencode: bool,
+    // The quirks the part was parsed with
+    pub(crate) quirks: Quirks,
+    // Whether a body which can't be transfer decoded is repaired when it's
+    // decoded, as for parts parsed without `ParseOptions::strict`
+    repair: bool,
+    // Whether the part is a multipart which ended without its closing
+    // boundary
+    truncated: bool,
+    // The text after the closing boundary of a multipart
+    epilogue: RawBody,
+    // Whether the part is within a multipart/digest, where parts default
+    // to message/rfc822
+    pub(crate) in_digest: bool,
 }
 
+// As with headers, the span and sizes recorded by the parser aren't part of
+// a message's identity, and nor is its envelope.
+impl PartialEq for MimeMessage {
+    fn eq(&self, other: &MimeMessage) -> bool {
+        self.headers == other.headers
+            && self.body == other.body
+            && self.message_type == other.message_type
+            && self.children == other.children
+            && self.boundary == other.boundary
+            && self.epilogue == other.epilogue
+    }
+}
+
+impl Eq for MimeMessage {}
+
 impl MimeMessage {
     fn random_boundary() -> String {
-        let mut rng = thread_rng();
-        std::iter::repeat(())
-            .map(|()| rng.sample(Alphanumeric))
-            .take(BOUNDARY_LENGTH)
-            .collect()
+        MimeMessage::generate_boundary("")
+    }
+
+    /// Generate a boundary for a multipart message, made up of `prefix`
+    /// followed by random characters from the entropy source, see the
+    /// `environment` module.
+    pub fn generate_boundary(prefix: &str) -> String {
+        let mut boundary = prefix.to_string();
+        boundary.push_str(&environment::random_alphanumeric(BOUNDARY_LENGTH));
+        boundary
+    }
+
+    // Whether the boundary appears anywhere in the content it delimits, in
+    // which case the message wouldn't parse back as it was built.
+    fn boundary_collides(&self) -> bool {
+        self.body.contains(&self.boundary[..])
+            || self
+                .children
+                .iter()
+                .any(|child| child.as_string().contains(&self.boundary[..]))
     }
 
     /// [unstable]
     pub fn new(body: String) -> MimeMessage {
         let mut message = MimeMessage::new_blank_message();
-        message.body = body;
+        message.body = body.into();
         message.update_headers();
         message
     }
@@ -131,13 +640,17 @@ impl MimeMessage {
         children: Vec<MimeMessage>,
     ) -> MimeMessage {
         let mut message = MimeMessage::new_blank_message();
-        message.body = body;
+        message.body = body.into();
         message.message_type = Some(message_type);
         message.children = children;
         message.update_headers();
         message
     }
 
+    /// Make a multipart message using the given `boundary`.
+    ///
+    /// If the boundary appears in the body or any of the children, random
+    /// characters are added to it until it doesn't.
     pub fn new_with_boundary(
         body: String,
         message_type: MimeMultipartType,
@@ -145,9 +658,12 @@ impl MimeMessage {
         boundary: String,
     ) -> MimeMessage {
         let mut message = MimeMessage::new_blank_message();
-        message.body = body;
+        message.body = body.into();
         message.message_type = Some(message_type);
         message.children = children;
+        for child in message.children.iter_mut() {
+            child.in_digest = message_type == MimeMultipartType::Digest;
+        }
         message.boundary = boundary;
         message.update_headers();
         message
@@ -156,11 +672,22 @@ impl MimeMessage {
     pub fn new_blank_message() -> MimeMessage {
         MimeMessage {
             headers: HeaderMap::new(),
-            body: "".to_string(),
+            body: RawBody::default(),
             message_type: None,
             children: Vec::new(),
 
             boundary: MimeMessage::random_boundary(),
+            span: None,
+            encoded_size: None,
+            line_count: None,
+            original: None,
+            uuencode: false,
+            quirks: Quirks::none(),
+            repair: false,
+            truncated: false,
+            epilogue: RawBody::default(),
+            in_digest: false,
+            envelope: None,
         }
     }
 
@@ -175,45 +702,54 @@ impl MimeMessage {
     /// `message_type`. Note that `message_type` does not allow parameters.
     /// otherwise, if it has a `Content-Type` header, use it as it is,
     /// otherwise, assume the `Content-Type` is 'multipart/mixed'.
-    /// Finally, update the `Content-Type` header with the `boundary`.
+    /// Finally, update the `Content-Type` header with the `boundary`, first
+    /// changing the boundary if it occurs in the content of the message.
     /// It does not update the `message_type`
     pub fn update_headers(&mut self) {
         // Since a single header can not be replace, clone all the headers
         // except th `Content-Type`, which needs will be added later.
         let mut headers = self.headers_without_content_type();
-        // Instead of returning the value of the `Content-Type` as a tuple,
-        // return it as String since it could have params.
-        let mut ct_value: String;
-        if let Some(message_type) = self.message_type {
+        let mixed = || {
+            let (major, minor) = MimeMultipartType::Mixed.to_content_type();
+            ContentType::new(&major, &minor)
+        };
+        let mut content_type = match self.message_type {
             // Use `message_type` as `Content-Type` if present
-            ct_value = message_type.to_string();
-        } else {
-            ct_value = match self.headers.get("Content-Type".to_string()) {
-                // Use `Content-Type` header if present
-                Some(ct_header) => ct_header.get_value().unwrap(),
-                // Or assume it is `multipart/mixed`.
-                None => MimeMultipartType::Mixed.to_string(),
-            };
-        }
+            Some(message_type) => {
+                let (major, minor) = message_type.to_content_type();
+                ContentType::new(&major, &minor)
+            }
+            // Use `Content-Type` header if valid, or assume it is `multipart/mixed`.
+            None => self
+                .headers
+                .get_value("Content-Type".to_string())
+                .unwrap_or_else(|_| mixed()),
+        };
         if let Some(directives) = self
             .headers
             .get("Content-Type-Deltachat-Directives".to_string())
         {
             let directives: String = directives.get_value().unwrap();
-            ct_value = format!("{ct_value}; {directives}");
+            if let Ok(with_directives) =
+                ContentType::from_header(format!("{}; {}", content_type, directives))
+            {
+                content_type = with_directives;
+            }
         }
         if !self.children.is_empty() {
+            // Keep the start of a colliding boundary, so any prefix chosen for
+            // it remains, and add fresh random characters.
+            while self.boundary_collides() {
+                let keep = self
+                    .boundary
+                    .len()
+                    .min(MAX_BOUNDARY_LENGTH - BOUNDARY_LENGTH);
+                self.boundary = MimeMessage::generate_boundary(&self.boundary[..keep]);
+            }
             // Insert the boundary param in the `Content-Type` header.
-            // A MimeContentTypeHeader could be created, but it is
-            // converted back to string when inserted into the headers
-            ct_value = format!(
-                "{}; {}=\"{}\"",
-                ct_value,
-                "boundary".to_string(),
-                self.boundary.clone()
-            );
+            content_type.set_parameter("boundary", &self.boundary);
         }
-        let header = Header::new("Content-Type".to_string(), ct_value);
+        let header = Header::new("Content-Type".to_string(), content_type.to_string());
         headers.insert(header);
         // And replace all the headers.
         self.headers = headers;
@@ -238,248 +774,1176 @@ impl MimeMessage {
 
     /// Parse `s` into a MimeMessage.
     ///
-    /// Recurses down into each message, supporting an unlimited depth of messages.
+    /// Parses each part within the message, to the depth allowed by the
+    /// default `Limits`.
     ///
     /// Be warned that each sub-message that fails to be parsed will be thrown away.
     /// [unstable]
     pub fn parse(s: &str) -> ParsingResult<MimeMessage> {
-        let mut parser = Rfc5322Parser::new(s);
-        match parser.consume_message() {
-            Some((headers, body)) => MimeMessage::from_headers(headers, body),
-            None => Err(ParsingError::new(
-                "Couldn't parse MIME message.".to_string(),
-            )),
+        MimeMessage::parse_with_options(s, &ParseOptions::default())
+    }
+
+    /// Parse `s` into a MimeMessage, as with `parse`, as set out by `options`.
+    pub fn parse_with_options(s: &str, options: &ParseOptions) -> ParsingResult<MimeMessage> {
+        let mut context = ParseContext::new(s, options);
+        MimeMessage::parse_span(&mut context, 0..s.len())
+    }
+
+    /// Parse `input` into a MimeMessage, as with `parse`, where it may not
+    /// be valid UTF-8, as with `binary` and `8bit` parts in legacy charsets.
+    ///
+    /// The bodies of such parts keep their bytes untouched, and the message
+    /// is written out with them by `write_to`. Invalid UTF-8 in headers is
+    /// replaced.
+    pub fn parse_bytes(input: &[u8]) -> ParsingResult<MimeMessage> {
+        MimeMessage::parse_bytes_with_options(input, &ParseOptions::default())
+    }
+
+    /// Parse `input`, which may not be valid UTF-8, as with `parse_bytes`,
+    /// as set out by `options`.
+    pub fn parse_bytes_with_options(
+        input: &[u8],
+        options: &ParseOptions,
+    ) -> ParsingResult<MimeMessage> {
+        let body = RawBody::from(input.to_vec());
+        let mut context = ParseContext::new(body.as_str(), options);
+        context.shared = body.clone();
+        MimeMessage::parse_span(&mut context, 0..input.len())
+    }
+
+    /// Parse `s` into a MimeMessage, as with `parse`, also returning the
+    /// problems in the message which the parser worked around.
+    pub fn parse_with_warnings(s: &str) -> ParsingResult<(MimeMessage, Vec<ParseWarning>)> {
+        let options = ParseOptions::default();
+        MimeMessage::parse_context_with_warnings(ParseContext::new(s, &options), s.len())
+    }
+
+    /// Parse `input`, which may not be valid UTF-8, as with
+    /// `parse_bytes_with_options`, also returning the problems in the
+    /// message which the parser worked around.
+    pub fn parse_bytes_with_warnings(
+        input: &[u8],
+        options: &ParseOptions,
+    ) -> ParsingResult<(MimeMessage, Vec<ParseWarning>)> {
+        let body = RawBody::from(input.to_vec());
+        let mut context = ParseContext::new(body.as_str(), options);
+        context.shared = body.clone();
+        MimeMessage::parse_context_with_warnings(context, input.len())
+    }
+
+    fn parse_context_with_warnings(
+        mut context: ParseContext,
+        len: usize,
+    ) -> ParsingResult<(MimeMessage, Vec<ParseWarning>)> {
+        context.report = true;
+        let message = MimeMessage::parse_span(&mut context, 0..len)?;
+
+        if message.headers.get("Date".to_string()).is_none() {
+            context.warn(WarningCode::MissingDate, 0, "Missing Date header".to_string());
+        }
+        if message.headers.get("From".to_string()).is_none() {
+            context.warn(WarningCode::MissingFrom, 0, "Missing From header".to_string());
         }
+        context.warnings.sort_by_key(|warning| warning.offset);
+
+        Ok((message, context.warnings))
     }
 
-    pub fn as_string(&self) -> String {
-        let mut builder = Rfc5322Builder::new();
+    /// Parse only the headers of `s`, stopping at the blank line ending
+    /// them without looking at the body or any MIME parts.
+    pub fn parse_headers_only(s: &str) -> HeaderMap {
+        Rfc5322Parser::new(s).consume_headers()
+    }
 
-        for header in self.headers.iter() {
-            builder.emit_folded(&header.to_string()[..]);
-            builder.emit_raw("\r\n");
+    /// Read only the headers of a message from `reader`, as with
+    /// `parse_headers_only`.
+    ///
+    /// Input is read up to and including the blank line ending the headers,
+    /// so the body can then be read from `reader`. Invalid UTF-8 in the
+    /// headers is replaced.
+    pub fn read_headers_only<R: BufRead>(reader: &mut R) -> io::Result<HeaderMap> {
+        let mut raw = Vec::new();
+        loop {
+            let start = raw.len();
+            if reader.read_until(b'\n', &mut raw)? == 0 {
+                break;
+            }
+            let line = &raw[start..];
+            if line == b"\n" || line == b"\r\n" {
+                break;
+            }
         }
-        builder.emit_raw("\r\n");
+        Ok(MimeMessage::parse_headers_only(&String::from_utf8_lossy(&raw)))
+    }
 
-        self.as_string_without_headers_internal(builder)
+    // Parse the part of the source covered by `span`, so that the spans
+    // recorded for it and its headers are offsets into the whole source.
+    //
+    // Rather than recursing into each multipart, the parts still being
+    // parsed are kept on a stack, so that deeply nested messages can't
+    // overflow the call stack.
+    fn parse_span(context: &mut ParseContext, span: Range<usize>) -> ParsingResult<MimeMessage> {
+        let base_depth = context.depth;
+        let mut stack = vec![MimeMessage::begin_part(context, span, base_depth)?];
+        loop {
+            let depth = base_depth + stack.len();
+            let top = stack.last_mut().unwrap();
+
+            #[cfg(feature = "rayon")]
+            {
+                if context.parallel && top.child_spans.len() > 1 {
+                    let spans = top.child_spans.by_ref().collect();
+                    for (span, result) in context.parse_parts(spans, depth) {
+                        match result {
+                            Ok(child) => top.message.push_parsed_child(child),
+                            Err(e) => context.drop_part(span, e)?,
+                        }
+                    }
+                    continue;
+                }
+            }
+
+            if let Some(span) = top.child_spans.next() {
+                match MimeMessage::begin_part(context, span.clone(), depth) {
+                    Ok(child) => stack.push(child),
+                    Err(e) => context.drop_part(span, e)?,
+                }
+                continue;
+            }
+
+            // All of the children of the part on top are parsed, so it's
+            // complete
+            let message = stack.pop().unwrap().message;
+            match stack.last_mut() {
+                Some(parent) => parent.message.push_parsed_child(message),
+                None => return Ok(message),
+            }
+        }
+    }
+
+    // Add `child` to the children of this part, as parsed from its body.
+    fn push_parsed_child(&mut self, mut child: MimeMessage) {
+        child.in_digest = self.message_type == Some(MimeMultipartType::Digest);
+        self.children.push(child);
+    }
+
+    // Parse the headers of the part covered by `span`, nested `depth` deep,
+    // and find the spans of its children if it's a multipart.
+    fn begin_part(
+        context: &mut ParseContext,
+        span: Range<usize>,
+        depth: usize,
+    ) -> ParsingResult<PendingPart> {
+        let s = context.source;
+        let limits = &context.options.limits;
+        if depth > limits.max_depth {
+            return Err(limit_exceeded("nesting depth", limits.max_depth));
+        }
+        if context.part_count.fetch_add(1, Ordering::Relaxed) >= limits.max_parts {
+            return Err(limit_exceeded("part count", limits.max_parts));
+        }
+
+        let mut parser = Rfc5322Parser::new_at(&s[..span.end], span.start);
+        let mut headers =
+            parser.consume_headers_with_limits(limits.max_header_count, limits.max_header_length)?;
+        if context.options.quirks.contains(Quirk::UnindentedReferences) {
+            join_unindented_references(&mut parser, &mut headers, limits)?;
+        }
+        headers.apply_decode_policy(&context.options.header_decoding);
+        let body_start = parser.pos();
+        let body = RawBody::shared(&context.shared, body_start..span.end);
+
+        let header_section = &s[span.start..body_start];
+        let separated = header_section.ends_with("\n\r\n") || header_section.ends_with("\n\n");
+        if !headers.is_empty() && !body.is_empty() && !separated {
+            context.warn(
+                WarningCode::MissingBlankLine,
+                body_start,
+                "Headers not followed by a blank line".to_string(),
+            );
+        }
+        for header in headers.iter() {
+            let bad_word = header.raw_value().split_whitespace().find(|word| {
+                word.starts_with("=?") && word.ends_with("?=") && !is_valid_encoded_word(word)
+            });
+            if let Some(word) = bad_word {
+                context.warn(
+                    WarningCode::InvalidEncodedWord,
+                    header.span().map_or(span.start, |span| span.start),
+                    format!("Invalid encoded word in {} header: {}", header.name, word),
+                );
+            }
+        }
+
+        let encoded_size = body.len();
+        let line_count = count_lines(&body);
+        let (mut message, child_spans) =
+            MimeMessage::from_headers(context, headers, body, body_start, depth > 0)?;
+        let max_decoded_size = context.options.limits.max_decoded_size;
+        if message.message_type.is_none() && max_decoded_size < usize::MAX {
+            let encoding = message
+                .headers
+                .get_value("Content-Transfer-Encoding".to_string())
+                .unwrap_or(MimeContentTransferEncoding::Identity);
+            if decoded_size_bound(&message.body, encoding) > max_decoded_size {
+                return Err(limit_exceeded("decoded size", max_decoded_size));
+            }
+        }
+        message.original = Some(Original {
+            headers: message.headers.snapshot(),
+            body: message.body.clone(),
+            boundary: message.boundary.clone(),
+            epilogue: message.epilogue.clone(),
+            child_spans: child_spans.clone(),
+        });
+        message.span = Some(span);
+        message.encoded_size = Some(encoded_size);
+        message.line_count = Some(line_count);
+        message.uuencode = context.options.uuencode;
+        message.quirks = context.options.quirks;
+        message.repair = !context.options.strict;
+        if message.message_type.is_none() && message.repair && context.report {
+            let encoding = message
+                .headers
+                .get_value("Content-Transfer-Encoding".to_string())
+                .unwrap_or(MimeContentTransferEncoding::Identity);
+            // The body is left as it is, and only repaired when decoded, so
+            // it's only decoded here to say what will be repaired
+            let body = message.body.as_bytes();
+            if encoding != MimeContentTransferEncoding::Identity
+                && encoding.decode_bytes(body).is_none()
+            {
+                if let Some((_, fixes)) = repair::repair_body(body, encoding) {
+                    for fix in fixes {
+                        context.warn(WarningCode::RepairedEncoding, body_start, fix);
+                    }
+                }
+            }
+        }
+        Ok(PendingPart {
+            message,
+            child_spans: child_spans.into_iter(),
+        })
+    }
+
+    /// The byte range this part occupied in the input it was parsed from,
+    /// covering its headers and body.
+    ///
+    /// Returns None if the part was created rather than parsed.
+    pub fn part_span(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+
+    /// The bytes of the part at `path`, its headers and body, as they are
+    /// in the input the message was parsed from, as for an IMAP `BODY[1.2]`
+    /// fetch or checking a signature over the part.
+    ///
+    /// The line break before the boundary after a part belongs to the
+    /// boundary, so isn't included. A part which has been changed since it
+    /// was parsed, or was created rather than parsed, is serialized as
+    /// `write_to` would write it within the message.
+    pub fn raw_part_bytes(&self, path: &PartPath) -> Option<Cow<'_, [u8]>> {
+        let part = self.part(path)?;
+        let nested = !path.is_root();
+        if let Some(span) = &part.span {
+            if !part.is_modified() {
+                return Some(Cow::Borrowed(part.source_text(span.clone(), nested)));
+            }
+        }
+        let mut bytes = Vec::with_capacity(part.part_len(nested));
+        part.write_part(&mut bytes, nested).unwrap();
+        Some(Cow::Owned(bytes))
+    }
+
+    /// The size in bytes of the body of this part, including any child
+    /// parts, as it appeared in the input it was parsed from, without
+    /// decoding it.
+    ///
+    /// For a part which was created rather than parsed, or whose body or
+    /// children have since been changed, this is the size the body would
+    /// have when written.
+    pub fn encoded_size(&self) -> usize {
+        match self.encoded_size {
+            Some(size) if self.body_unmodified() => size,
+            _ => self.as_string_without_headers().len(),
+        }
+    }
+
+    /// The number of lines in the body of this part, counted in the same way
+    /// as `encoded_size`, with a last line lacking a line break included.
+    pub fn line_count(&self) -> usize {
+        match self.line_count {
+            Some(count) if self.body_unmodified() => count,
+            _ => count_lines(&self.as_string_without_headers()),
+        }
+    }
+
+    /// The message as a string, with any bytes of binary bodies which
+    /// aren't valid UTF-8 replaced. See `to_bytes` for the message as it
+    /// is.
+    pub fn as_string(&self) -> String {
+        lossy_string(self.to_bytes())
     }
 
     pub fn as_string_without_headers(&self) -> String {
-        let builder = Rfc5322Builder::new();
+        let mut bytes = Vec::new();
+        self.write_body(&mut bytes, false).unwrap();
+        lossy_string(bytes)
+    }
+
+    /// The message as bytes, as `write_to` writes it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.encoded_len_estimate());
+        self.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// Write this message to `writer`, as `as_string` gives it, without
+    /// building the whole message in memory first.
+    ///
+    /// Parts which haven't been changed since they were parsed are written
+    /// out as they appeared in the input, without serializing them again,
+    /// and the body of a part whose headers alone have been changed is too.
+    /// See `is_modified`.
+    pub fn write_to<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.write_part(writer, false)
+    }
+
+    // Write the part, without the line break which belongs to the boundary
+    // after it if it's `nested` in a multipart and written as it was parsed.
+    fn write_part<W: io::Write>(&self, writer: &mut W, nested: bool) -> io::Result<()> {
+        if let Some(span) = &self.span {
+            if !self.is_modified() {
+                return writer.write_all(self.source_text(span.clone(), nested));
+            }
+        }
+
+        for header in self.headers.iter() {
+            let folded = fold(&header.name, header.raw_value(), &FoldingPolicy::default());
+            writer.write_all(folded.as_bytes())?;
+            writer.write_all(b"\r\n")?;
+        }
+        writer.write_all(b"\r\n")?;
 
-        self.as_string_without_headers_internal(builder)
+        self.write_body(writer, nested)
     }
 
-    fn as_string_without_headers_internal(&self, mut builder: Rfc5322Builder) -> String {
-        builder.emit_raw(&format!("{}\r\n", self.body)[..]);
+    fn write_body<W: io::Write>(&self, writer: &mut W, nested: bool) -> io::Result<()> {
+        if let Some(span) = &self.span {
+            if self.body_unmodified() {
+                let text = self.source_text(self.body.range.start..span.end, nested);
+                return writer.write_all(text);
+            }
+        }
+
+        writer.write_all(self.body.as_bytes())?;
+        if self.preamble_needs_line_break() {
+            writer.write_all(b"\r\n")?;
+        }
 
         if !self.children.is_empty() {
             for part in self.children.iter() {
-                builder.emit_raw(&format!("--{}\r\n{}\r\n", self.boundary, part.as_string())[..]);
+                write!(writer, "--{}\r\n", self.boundary)?;
+                part.write_part(writer, true)?;
+                writer.write_all(b"\r\n")?;
+            }
+
+            write!(writer, "--{}--\r\n", self.boundary)?;
+            writer.write_all(self.epilogue.as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// The length in bytes of this message as `write_to` would write it,
+    /// for allocating a buffer or enforcing a size limit before writing it.
+    ///
+    /// Headers are folded to measure them, but the bodies, which are held
+    /// already encoded, are only measured, so this is cheap even for large
+    /// attachments.
+    pub fn encoded_len_estimate(&self) -> usize {
+        self.part_len(false)
+    }
+
+    // The length of the part as written by `write_part`.
+    fn part_len(&self, nested: bool) -> usize {
+        if let Some(span) = &self.span {
+            if !self.is_modified() {
+                return self.source_text(span.clone(), nested).len();
             }
+        }
+        let headers: usize = self
+            .headers
+            .iter()
+            .map(|header| {
+                fold(&header.name, header.raw_value(), &FoldingPolicy::default()).len() + 2
+            })
+            .sum();
+        headers + 2 + self.body_len(nested)
+    }
+
+    // The length of the body as written by `write_body`.
+    fn body_len(&self, nested: bool) -> usize {
+        if let Some(span) = &self.span {
+            if self.body_unmodified() {
+                return self
+                    .source_text(self.body.range.start..span.end, nested)
+                    .len();
+            }
+        }
+        let children: usize = self
+            .children
+            .iter()
+            .map(|part| self.boundary.len() + 6 + part.part_len(true))
+            .sum();
+        let close = if self.children.is_empty() {
+            0
+        } else {
+            self.boundary.len() + 6 + self.epilogue.len()
+        };
+        let line_break = if self.preamble_needs_line_break() {
+            2
+        } else {
+            0
+        };
+        self.body.len() + line_break + children + close
+    }
+
+    // Whether a line break is written after the body. The line break ending
+    // the preamble of a parsed multipart is kept in it, so isn't written
+    // again.
+    fn preamble_needs_line_break(&self) -> bool {
+        self.children.is_empty() || !self.body.ends_with('\n')
+    }
+
+    /// The text before the first boundary of this multipart, which is only
+    /// shown by readers that don't understand MIME, or None if this isn't a
+    /// multipart.
+    ///
+    /// The line break before the first boundary belongs to the boundary, so
+    /// isn't included.
+    pub fn preamble(&self) -> Option<&str> {
+        self.message_type?;
+        let body = self.body.as_str();
+        Some(
+            body.strip_suffix("\r\n")
+                .or_else(|| body.strip_suffix('\n'))
+                .unwrap_or(body),
+        )
+    }
 
-            builder.emit_raw(&format!("--{}--\r\n", self.boundary)[..]);
+    /// Set the text before the first boundary of this multipart, such as a
+    /// notice that the message is in MIME format.
+    pub fn set_preamble(&mut self, preamble: &str) {
+        self.body = preamble.into();
+    }
+
+    /// The text after the closing boundary of this multipart, which is
+    /// ignored by readers, or None if this isn't a multipart.
+    pub fn epilogue(&self) -> Option<&str> {
+        self.message_type?;
+        Some(self.epilogue.as_str())
+    }
+
+    /// Set the text after the closing boundary of this multipart.
+    pub fn set_epilogue(&mut self, epilogue: &str) {
+        self.epilogue = epilogue.into();
+    }
+
+    /// Whether this part, or any multipart within it, ended without its
+    /// closing boundary, as happens when a message is cut off in transit.
+    ///
+    /// The end of the input is then taken as the end of every multipart
+    /// still open, unless parsing with `ParseOptions::strict`.
+    pub fn is_truncated(&self) -> bool {
+        self.parts_depth_first().any(|(_, part)| part.truncated)
+    }
+
+    /// Whether this part has been changed since it was parsed, in its
+    /// headers, its body, or any of its children.
+    ///
+    /// This is always true of a part which was created rather than parsed.
+    pub fn is_modified(&self) -> bool {
+        !self.headers_unmodified() || !self.body_unmodified()
+    }
+
+    // Whether the part has just the headers it was parsed with.
+    fn headers_unmodified(&self) -> bool {
+        self.original
+            .as_ref()
+            .is_some_and(|original| self.headers.is_snapshot(&original.headers))
+    }
+
+    // Whether the part has just the body and children it was parsed with,
+    // none of the children having been changed.
+    fn body_unmodified(&self) -> bool {
+        let original = match &self.original {
+            Some(original) => original,
+            None => return false,
+        };
+        Arc::ptr_eq(&self.body.source, &original.body.source)
+            && self.body.range == original.body.range
+            && self.boundary == original.boundary
+            && Arc::ptr_eq(&self.epilogue.source, &original.epilogue.source)
+            && self.epilogue.range == original.epilogue.range
+            && self.children.len() == original.child_spans.len()
+            && self
+                .children
+                .iter()
+                .zip(original.child_spans.iter())
+                .all(|(part, span)| {
+                    part.span.as_ref() == Some(span)
+                        && Arc::ptr_eq(&part.body.source, &self.body.source)
+                        && !part.is_modified()
+                })
+    }
+
+    // The bytes of the input covering `range`, without the line break which
+    // belongs to the boundary after it if the part is `nested`.
+    fn source_text(&self, range: Range<usize>, nested: bool) -> &[u8] {
+        let text = &self.body.source_bytes()[range];
+        if !nested {
+            return text;
         }
+        text.strip_suffix(b"\r\n")
+            .or_else(|| text.strip_suffix(b"\n"))
+            .unwrap_or(text)
+    }
+
+    /// Set the body to `content`, encoded with the transfer encoding best
+    /// suited to it, and set the `Content-Transfer-Encoding` header to match.
+    pub fn set_body_bytes(&mut self, content: &[u8]) {
+        let encoding = MimeContentTransferEncoding::choose(content);
+        self.set_body_bytes_with_encoding(content, encoding);
+    }
+
+    /// Set the body to `content`, encoded with the given transfer `encoding`
+    /// rather than one chosen for it.
+    ///
+    /// With the Identity encoding, `content` is kept as it is, and the
+    /// header is set to `8bit` if it isn't all ASCII, or `binary` if it
+    /// isn't made of lines which `8bit` allows.
+    pub fn set_body_bytes_with_encoding(
+        &mut self,
+        content: &[u8],
+        encoding: MimeContentTransferEncoding,
+    ) {
+        let name = match encoding {
+            MimeContentTransferEncoding::Identity if !is_line_oriented(content) => "binary",
+            MimeContentTransferEncoding::Identity if content.is_ascii() => "7bit",
+            MimeContentTransferEncoding::Identity => "8bit",
+            MimeContentTransferEncoding::QuotedPrintable => "quoted-printable",
+            MimeContentTransferEncoding::Base64 => "base64",
+        };
+        self.body = match encoding {
+            MimeContentTransferEncoding::Identity => content.to_vec().into(),
+            _ => encoding.encode(content).into(),
+        };
+        self.headers.replace(Header::new(
+            "Content-Transfer-Encoding".to_string(),
+            name.to_string(),
+        ));
+    }
 
-        builder.result().clone()
+    /// Decode the body of this message, borrowing it rather than making a
+    /// copy if it isn't transfer encoded.
+    ///
+    /// Returns None if the body isn't valid for its transfer encoding. A
+    /// part parsed without `ParseOptions::strict` is repaired instead, as
+    /// far as it can be, with the body itself left as it is.
+    pub fn decoded_body(&self) -> Option<Cow<'_, [u8]>> {
+        let transfer_encoding: MimeContentTransferEncoding = self
+            .headers
+            .get_value("Content-Transfer-Encoding".to_string())
+            .unwrap_or(MimeContentTransferEncoding::Identity);
+        let body = self.body.as_bytes();
+        if transfer_encoding == MimeContentTransferEncoding::Identity {
+            return Some(Cow::Borrowed(body));
+        }
+        let decoded = transfer_encoding.decode_bytes(body).or_else(|| {
+            let repaired = repair::repair_body(body, transfer_encoding);
+            let (repaired, _) = repaired.filter(|_| self.repair)?;
+            transfer_encoding.decode_bytes(&repaired)
+        });
+        decoded.map(Cow::Owned)
     }
 
     /// Decode the body of this message, as a series of bytes
     pub fn decoded_body_bytes(&self) -> Option<Vec<u8>> {
-        let transfer_encoding: MimeContentTransferEncoding = self
+        self.decoded_body().map(Cow::into_owned)
+    }
+
+    /// Decode the body of this message, as a string.
+    ///
+    /// This takes into account any charset as set on the `Content-Type` header,
+    /// decoding the bytes with this character set. Bodies in an unknown or
+    /// wrong charset are decoded as UTF-8 or windows-1252, see `charset::decode`.
+    pub fn decoded_body_string(&self) -> ParsingResult<String> {
+        self.decoded_body_string_with_options(&DecodeOptions::default())
+    }
+
+    /// Decode the body of this message, as a string, handling unknown or
+    /// wrong charsets as set out by `options`.
+    ///
+    /// A binary body, one which isn't valid UTF-8 and isn't transfer
+    /// encoded, is only decoded with the charset it declares, and is an
+    /// error without a valid one, rather than being taken to be in the
+    /// fallback charset.
+    pub fn decoded_body_string_with_options(&self, options: &DecodeOptions) -> ParsingResult<String> {
+        let bytes = match self.decoded_body() {
+            // FIXME
+            Some(x) => x,
+            None => {
+                let encoding = self
+                    .headers
+                    .get("Content-Transfer-Encoding".to_string())
+                    .map_or("7bit", |header| header.raw_value());
+                return Err(ParsingError::InvalidEncoding {
+                    encoding: encoding.to_string(),
+                });
+            }
+        };
+
+        let content_type: Option<ContentType> =
+            self.headers.get_value("Content-Type".to_string()).ok();
+        let mut charset = content_type.as_ref().and_then(|ct| ct.charset());
+        if self.quirks.contains(Quirk::Utf8AsAscii)
+            && charset.is_some_and(|charset| charset.eq_ignore_ascii_case("us-ascii"))
+            && std::str::from_utf8(&bytes).is_ok()
+        {
+            charset = None;
+        }
+
+        let transfer_encoding = self
             .headers
             .get_value("Content-Transfer-Encoding".to_string())
             .unwrap_or(MimeContentTransferEncoding::Identity);
-        transfer_encoding.decode(&self.body)
+        if self.body.is_binary() && transfer_encoding == MimeContentTransferEncoding::Identity {
+            let options = DecodeOptions {
+                fallback_charset: None,
+                on_error: options.on_error,
+            };
+            return charset::decode(&bytes, charset, &options);
+        }
+        charset::decode(&bytes, charset, options)
+    }
+
+    /// The content type of this message.
+    ///
+    /// Defaults to text/plain if there is no valid `Content-Type` header, as
+    /// described in Section 5.2 of RFC 2045. See `effective_content_type`
+    /// for the default which depends on where the part is.
+    pub fn content_type(&self) -> ContentType {
+        self.headers
+            .get_value("Content-Type".to_string())
+            .unwrap_or_default()
+    }
+
+    /// The content type of this message, defaulting as RFC 2046 says if
+    /// there is no valid `Content-Type` header.
+    ///
+    /// The default is `message/rfc822` for a part of a `multipart/digest`,
+    /// Section 5.1.5, and `text/plain; charset=us-ascii` anywhere else.
+    pub fn effective_content_type(&self) -> ContentType {
+        match self.headers.get_value("Content-Type".to_string()) {
+            Ok(content_type) => content_type,
+            Err(_) if self.in_digest => ContentType::new("message", "rfc822"),
+            Err(_) => {
+                let mut content_type = ContentType::default();
+                content_type.set_parameter("charset", "us-ascii");
+                content_type
+            }
+        }
+    }
+
+    /// Whether this part has been marked as an attachment by its
+    /// `Content-Disposition` header.
+    fn is_attachment(&self) -> bool {
+        let disposition: Option<ContentDisposition> = self
+            .headers
+            .get_value("Content-Disposition".to_string())
+            .ok();
+        if self.quirks.contains(Quirk::InlineAttachments)
+            && disposition.as_ref().is_some_and(|d| d.filename.is_some())
+        {
+            return true;
+        }
+        disposition.is_some_and(|d| d.is_attachment())
+    }
+
+    // Find the part best representing the body of this message as text/`subtype`.
+    pub(crate) fn find_body_part(&self, subtype: &str) -> Option<&MimeMessage> {
+        if self.children.is_empty() {
+            let content_type = self.content_type();
+            return if content_type.ttype == "text"
+                && content_type.subtype == subtype
+                && !self.is_attachment()
+            {
+                Some(self)
+            } else {
+                None
+            };
+        }
+
+        match &self.content_type().subtype[..] {
+            // The alternatives are in increasing order of preference, so try
+            // the last first.
+            "alternative" => self
+                .children
+                .iter()
+                .rev()
+                .filter_map(|child| child.find_body_part(subtype))
+                .next(),
+            // Only the root part is the body, the rest are referenced from it.
+            "related" => self
+                .children
+                .first()
+                .and_then(|child| child.find_body_part(subtype)),
+            _ => self
+                .children
+                .iter()
+                .filter_map(|child| child.find_body_part(subtype))
+                .next(),
+        }
+    }
+
+    /// The decoded plain text body of this message.
+    ///
+    /// This walks multipart messages, taking the plain text version from
+    /// any multipart/alternative, and skipping attachments. A
+    /// `format=flowed` body has its flowed lines joined.
+    pub fn text_body(&self) -> Option<String> {
+        let part = self.find_body_part("plain")?;
+        let text = part.decoded_body_string().ok()?;
+        Some(match FlowedText::from_content_type(&part.content_type()) {
+            Some(flowed) => flowed.decode(&text[..]),
+            None => text,
+        })
+    }
+
+    /// The decoded HTML body of this message.
+    ///
+    /// This walks multipart messages, taking the HTML version from any
+    /// multipart/alternative, and the root part of any multipart/related.
+    pub fn html_body(&self) -> Option<String> {
+        self.find_body_part("html")
+            .and_then(|part| part.decoded_body_string().ok())
+    }
+
+    /// A short preview of this message's text, such as a mailbox list shows
+    /// beside the subject, of at most `max_chars` characters.
+    ///
+    /// This is made from the plain text body, or failing that the HTML body,
+    /// leaving out quoted replies and any signature as found by
+    /// `bodyparse::split_body`, and collapsing runs of whitespace.
+    pub fn preview(&self, max_chars: usize) -> String {
+        let text = match self.text_body() {
+            Some(text) => text,
+            None => self
+                .html_body()
+                .map(|html| html_to_text(&html[..]))
+                .unwrap_or_default(),
+        };
+
+        let text = bodyparse::visible_text(&text[..]);
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let preview: String = words.join(" ").chars().take(max_chars).collect();
+        preview.trim_end().to_string()
+    }
+
+    /// The filename given to this part by its `Content-Disposition` header,
+    /// or failing that the `name` parameter of its `Content-Type`.
+    pub fn filename(&self) -> Option<String> {
+        let disposition: Option<ContentDisposition> = self
+            .headers
+            .get_value("Content-Disposition".to_string())
+            .ok();
+        disposition
+            .and_then(|d| d.filename)
+            .or_else(|| {
+                let content_type = self.content_type();
+                content_type.parameter("name").map(|name| name.to_string())
+            })
+            .map(|name| String::from_header(name.clone()).unwrap_or(name))
+    }
+
+    /// The identifier from the `Content-ID` header of this part, without the
+    /// angle brackets.
+    pub fn content_id(&self) -> Option<String> {
+        let id: Option<MessageId> = self.headers.get_value("Content-ID".to_string()).ok();
+        id.map(|id| id.id().to_string())
     }
 
-    /// Decode the body of this message, as a string.
+    /// Find the part whose `Content-ID` matches `cid`.
     ///
-    /// This takes into account any charset as set on the `Content-Type` header,
-    /// decoding the bytes with this character set.
-    pub fn decoded_body_string(&self) -> ParsingResult<String> {
-        let bytes = match self.decoded_body_bytes() {
-            // FIXME
-            Some(x) => x,
-            None => {
-                return Err(ParsingError::new(
-                    "Unable to get decoded body bytes.".to_string(),
-                ))
-            }
+    /// `cid` may be given as a bare identifier, in angle brackets, or as a
+    /// `cid:` URL as described in RFC 2392, as found in the HTML body of a
+    /// multipart/related message.
+    pub fn part_by_content_id(&self, cid: &str) -> Option<&MimeMessage> {
+        let cid = cid.trim();
+        let scheme = cid.get(..4);
+        let wanted = if scheme.is_some_and(|scheme| scheme.eq_ignore_ascii_case("cid:")) {
+            percent_decode(&cid[4..])
+        } else {
+            cid.trim_start_matches('<').trim_end_matches('>').to_string()
         };
+        self.inline_parts()
+            .find(|(id, _)| *id == wanted)
+            .map(|(_, part)| part)
+    }
 
-        let content_type: Result<MimeContentTypeHeader, _> =
-            self.headers.get_value("Content-Type".to_string());
-        let charset = match content_type {
-            Ok(ct) => ct.params.get(&"charset".to_string()).cloned(),
-            Err(_) => None,
-        }
-        .unwrap_or_else(|| "us-ascii".to_string());
-
-        match encoding_from_whatwg_label(&charset[..]) {
-            Some(decoder) => match decoder.decode(&bytes, DecoderTrap::Replace) {
-                Ok(x) => Ok(x),
-                Err(e) => Err(ParsingError::new(format!("Unable to decode body: {}", e))),
-            },
-            None => Err(ParsingError::new(format!("Invalid encoding: {}", charset))),
-        }
+    /// Iterate over every part of this message with a `Content-ID`, along
+    /// with that identifier, in the order they appear in the message.
+    pub fn inline_parts(&self) -> InlineParts<'_> {
+        InlineParts { stack: vec![self] }
     }
 
-    // Make a message from a header map and body, parsing out any multi-part
-    // messages that are discovered by looking at the Content-Type header.
-    fn from_headers(headers: HeaderMap, body: String) -> ParsingResult<MimeMessage> {
-        let content_type = {
-            let header = headers.get("Content-Type".to_string());
-            match header {
-                Some(h) => h.get_value(),
-                None => Ok(MimeContentTypeHeader {
-                    content_type: ("text".to_string(), "plain".to_string()),
-                    params: HashMap::new(),
-                }),
-            }
-        }?;
+    // Make a message from a header map and body, along with the spans of
+    // the source covering any child parts, which are discovered by looking
+    // at the Content-Type header and left to the caller to parse.
+    //
+    // The body starts at `body_start` in the source, and is followed by the
+    // boundary of a parent multipart if the part is `nested`.
+    fn from_headers(
+        context: &mut ParseContext,
+        headers: HeaderMap,
+        body: RawBody,
+        body_start: usize,
+        nested: bool,
+    ) -> ParsingResult<(MimeMessage, Vec<Range<usize>>)> {
+        let content_type: ContentType = match headers.get("Content-Type".to_string()) {
+            Some(h) => h.get_value()?,
+            None => ContentType::default(),
+        };
 
         // Pull out the major mime type and the boundary (if it exists)
-        let (mime_type, sub_mime_type) = content_type.content_type;
-        let boundary = content_type.params.get(&"boundary".to_string());
+        let boundary = content_type.boundary().map(|b| b.to_string());
 
-        let mut message = match (&mime_type[..], boundary) {
+        let (mut message, child_spans) = match (&content_type.ttype[..], boundary) {
             // Only consider a multipart message if we have a boundary, otherwise don't
             // bother and just assume it's a single message.
             ("multipart", Some(boundary)) => {
                 // Pull apart the message on the boundary.
-                let mut parts = MimeMessage::split_boundary(&body, boundary);
+                let (mut parts, epilogue_start) = MimeMessage::split_boundary(&body, &boundary);
+                let terminated = epilogue_start.is_some();
+                if !terminated && context.options.strict {
+                    return Err(ParsingError::UnexpectedChar {
+                        pos: body_start + body.len(),
+                        found: None,
+                        expected: format!("closing boundary --{}--", boundary),
+                    });
+                } else if !terminated {
+                    context.warn(
+                        WarningCode::UnterminatedMultipart,
+                        body_start + body.len(),
+                        format!("Missing closing boundary --{}--", boundary),
+                    );
+                    if let Some(last) = parts.len().checked_sub(1) {
+                        let trimmed = match parts.get(last) {
+                            Some(span) if last > 0 => {
+                                repair::trim_partial_boundary(&body, span.clone(), &boundary)
+                            }
+                            _ => None,
+                        };
+                        if let Some(trimmed) = trimmed {
+                            context.warn(
+                                WarningCode::PartialBoundary,
+                                body_start + trimmed.end,
+                                format!(
+                                    "Removed partial boundary {}",
+                                    body[trimmed.end..parts[last].end].trim()
+                                ),
+                            );
+                            if body[trimmed.clone()].trim().is_empty() {
+                                parts.pop();
+                            } else {
+                                parts[last] = trimmed;
+                            }
+                        }
+                    }
+                }
                 // Pop off the first message, as it's part of the parent.
                 let pre_body = if parts.is_empty() {
-                    "".to_string()
+                    body.slice(0..0)
                 } else {
-                    parts.remove(0)
+                    body.slice(parts.remove(0))
                 };
-                // Parse out each of the child parts, recursively downwards.
-                // Filtering out and unwrapping None as we go.
-                let message_parts: Vec<MimeMessage> = parts
-                    .iter()
-                    .filter_map(|part| match MimeMessage::parse(&part[..]) {
-                        Ok(x) => Some(x),
-                        Err(_) => None,
-                    })
+                let spans = parts
+                    .into_iter()
+                    .map(|part| (body_start + part.start)..(body_start + part.end))
                     .collect();
                 // It should be safe to unwrap the multipart type here because we know the main
                 // mimetype is "multipart"
-                let multipart_type =
-                    MimeMultipartType::from_content_type((mime_type, sub_mime_type)).unwrap();
-
-                MimeMessage::new_with_boundary(
-                    pre_body,
-                    multipart_type,
-                    message_parts,
-                    boundary.clone(),
-                )
+                let multipart_type = MimeMultipartType::from_content_type((
+                    content_type.ttype.clone(),
+                    content_type.subtype.clone(),
+                ))
+                .unwrap();
+
+                // The headers are replaced with the parsed ones below, so
+                // leave them (and the boundary) as they are rather than
+                // updating them.
+                let mut message = MimeMessage::new_blank_message();
+                message.body = pre_body;
+                message.message_type = Some(multipart_type);
+                message.boundary = boundary;
+                message.truncated = !terminated;
+                if let Some(start) = epilogue_start {
+                    let mut epilogue = &body[start..];
+                    if nested {
+                        // The line break ending the part belongs to the
+                        // boundary after it
+                        epilogue = epilogue
+                            .strip_suffix("\r\n")
+                            .or_else(|| epilogue.strip_suffix('\n'))
+                            .unwrap_or(epilogue);
+                    }
+                    message.epilogue = body.slice(start..start + epilogue.len());
+                }
+                (message, spans)
+            }
+            (mime_type, _) => {
+                if mime_type == "multipart" {
+                    let header = headers.get("Content-Type".to_string()).unwrap();
+                    context.warn(
+                        WarningCode::MissingBoundary,
+                        header.span().map_or(body_start, |span| span.start),
+                        "Multipart Content-Type has no boundary".to_string(),
+                    );
+                }
+                // The headers are replaced with the parsed ones below
+                let mut message = MimeMessage::new_blank_message();
+                message.body = body;
+                (message, Vec::new())
             }
-            _ => MimeMessage::new(body),
         };
 
         message.headers = headers;
-        Ok(message)
+        Ok((message, child_spans))
     }
 
-    // Split `body` up on the `boundary` string.
-    fn split_boundary(body: &str, boundary: &str) -> Vec<String> {
-        #[derive(Debug)]
-        enum ParseState {
-            Distinguished,
-            DistinguishedEnd,
-            Normal,
-            SeenCr,
-            SeenLf,
-            SeenDash,
-            ReadBoundary,
-            BoundaryEnd,
+    // Split `body` up on the `boundary` string, giving the range of each part
+    // and, if the closing boundary was found, where the epilogue after it
+    // starts.
+    //
+    // The line break before each boundary is left in the part before it.
+    pub(crate) fn split_boundary(body: &str, boundary: &str) -> (Vec<Range<usize>>, Option<usize>) {
+        let mut parts = Vec::new();
+        // Where the part after the last boundary starts
+        let mut part_start = 0;
+        // Rather than looking at every line, search for the delimiter and
+        // only look at the lines it's found at the start of
+        let delimiter = format!("--{}", boundary);
+        let finder = memmem::Finder::new(&delimiter);
+        let bytes = body.as_bytes();
+        let mut search_start = 0;
+        while let Some(found) = finder.find(&bytes[search_start..]) {
+            let line_start = search_start + found;
+            if line_start > 0 && bytes[line_start - 1] != b'\n' {
+                search_start = line_start + 1;
+                continue;
+            }
+            let line_end =
+                memchr(b'\n', &bytes[line_start..]).map_or(body.len(), |i| line_start + i + 1);
+            if let Some(closing) = match_delimiter(&body[line_start..line_end], boundary) {
+                parts.push(part_start..line_start);
+                if closing {
+                    return (parts, Some(line_end));
+                }
+                part_start = line_end;
+            }
+            search_start = line_end;
+        }
+
+        // Push in the final part of the message (what remains)
+        if !body[part_start..].trim().is_empty() {
+            parts.push(part_start..body.len());
         }
+        (parts, None)
+    }
+}
 
-        // Start in a state where we're at the beginning of a line.
-        let mut state = ParseState::SeenLf;
+// Whether `line` is a boundary delimiter of `boundary`, as in RFC 2046
+// Section 5.1.1, and if so whether it's the closing delimiter.
+//
+// The delimiter must start the line, and may only be followed by
+// whitespace, so a longer boundary which this one is a prefix of doesn't
+// match.
+fn match_delimiter(line: &str, boundary: &str) -> Option<bool> {
+    let rest = line.strip_prefix("--")?.strip_prefix(boundary)?;
+    let (closing, padding) = match rest.strip_prefix("--") {
+        Some(padding) => (true, padding),
+        None => (false, rest),
+    };
+    if padding.bytes().all(|b| b.is_ascii_whitespace()) {
+        Some(closing)
+    } else {
+        None
+    }
+}
 
-        // Initialize starting positions
-        let mut boundary_start = 0;
-        let mut boundary_end = 0;
+// What a parsed part held when it was parsed. The headers are shared with
+// the part's map, and the body with its source, so telling whether they've
+// been changed since doesn't compare any content.
+#[derive(Debug, Clone)]
+struct Original {
+    headers: Vec<Arc<Header>>,
+    body: RawBody,
+    boundary: String,
+    epilogue: RawBody,
+    // The spans of the children, which a child dropped for failing to
+    // parse is still among
+    child_spans: Vec<Range<usize>>,
+}
 
-        let mut parts = Vec::new();
+// A part whose headers have been parsed, with its children still to be.
+struct PendingPart {
+    message: MimeMessage,
+    child_spans: std::vec::IntoIter<Range<usize>>,
+}
 
-        let body_slice = &body[..];
-
-        let mut done = false;
-
-        for (pos, c) in body.char_indices() {
-            state = match (state, c) {
-                (ParseState::ReadBoundary, '-') => ParseState::Distinguished,
-                (ParseState::Distinguished, '-') => ParseState::DistinguishedEnd,
-                (ParseState::BoundaryEnd, _) => {
-                    // We're now out of a boundary, so remember where the end is,
-                    // so we can slice from the end of this boundary to the start of the next.
-                    boundary_end = pos;
-                    if c == '\n' {
-                        ParseState::BoundaryEnd
-                    } else {
-                        ParseState::Normal
-                    }
-                }
-                (ParseState::DistinguishedEnd, '\r') | (ParseState::DistinguishedEnd, '\n') => {
-                    let read_boundary = body_slice[(boundary_start + 1)..(pos - 2)].trim();
-                    if read_boundary == boundary {
-                        // Boundary matches, push the part
-                        // The part is from the last boundary's end to this boundary's beginning
-                        let part = &body_slice[boundary_end..(boundary_start - 1)];
-                        parts.push(part.to_string());
-                        done = true;
-                        break;
-                    } else {
-                        // This isn't our boundary, so leave it.
-                        ParseState::Normal
-                    }
-                }
-                (ParseState::ReadBoundary, '\r') | (ParseState::ReadBoundary, '\n') => {
-                    let read_boundary = body_slice[(boundary_start + 1)..pos].trim();
-                    if read_boundary == boundary {
-                        // Boundary matches, push the part
-                        // The part is from the last boundary's end to this boundary's beginning
-                        let part = &body_slice[boundary_end..(boundary_start - 1)];
-                        parts.push(part.to_string());
-                        // This is our boundary, so consume boundary end
-                        ParseState::BoundaryEnd
-                    } else {
-                        // This isn't our boundary, so leave it.
-                        ParseState::Normal
-                    }
-                }
-                (ParseState::ReadBoundary, _) => ParseState::ReadBoundary,
-                (_, '\n') => ParseState::SeenLf,
-                (_, '\r') => ParseState::SeenCr,
-                (ParseState::SeenDash, '-') => {
-                    boundary_start = pos;
-                    ParseState::ReadBoundary
-                }
-                (ParseState::SeenLf, '-') => ParseState::SeenDash,
-                (ParseState::Normal, _) => ParseState::Normal,
-                (_, _) => ParseState::Normal,
-            };
+// State shared while parsing a message and all of its parts.
+struct ParseContext<'s> {
+    source: &'s str,
+    // A copy of the source, which the bodies of the parts refer to
+    shared: RawBody,
+    options: &'s ParseOptions,
+    warnings: Vec<ParseWarning>,
+    // Whether the warnings are wanted, rather than thrown away, so that
+    // checks made only to warn can be skipped
+    report: bool,
+    // Whether to parse the parts of multiparts in parallel, which isn't
+    // done again within the parts being parsed in parallel
+    #[cfg_attr(not(feature = "rayon"), allow(dead_code))]
+    parallel: bool,
+    // How deeply the first part being parsed is nested
+    depth: usize,
+    // The number of parts parsed so far, shared by the contexts of parts
+    // parsed in parallel
+    part_count: Arc<AtomicUsize>,
+}
+
+impl<'s> ParseContext<'s> {
+    fn new(source: &'s str, options: &'s ParseOptions) -> ParseContext<'s> {
+        ParseContext {
+            source,
+            shared: RawBody::from(source),
+            options,
+            warnings: Vec::new(),
+            report: false,
+            parallel: options.parallel,
+            depth: 0,
+            part_count: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // Parse the parts of a multipart covering each of `spans`, nested
+    // `depth` deep, in parallel, with any warnings added in the order of the
+    // parts.
+    #[cfg(feature = "rayon")]
+    fn parse_parts(
+        &mut self,
+        spans: Vec<Range<usize>>,
+        depth: usize,
+    ) -> Vec<(Range<usize>, ParsingResult<MimeMessage>)> {
+        use rayon::prelude::*;
+
+        let this = &*self;
+        let results: Vec<_> = spans
+            .into_par_iter()
+            .map(|span| {
+                let mut context = ParseContext {
+                    source: this.source,
+                    shared: this.shared.clone(),
+                    options: this.options,
+                    warnings: Vec::new(),
+                    report: this.report,
+                    parallel: false,
+                    depth,
+                    part_count: Arc::clone(&this.part_count),
+                };
+                let result = MimeMessage::parse_span(&mut context, span.clone());
+                (span, result, context.warnings)
+            })
+            .collect();
+        results
+            .into_iter()
+            .map(|(span, result, warnings)| {
+                self.warnings.extend(warnings);
+                (span, result)
+            })
+            .collect()
+    }
+
+    // Leave out the part covering `span`, which couldn't be parsed because
+    // of `error`, unless a limit was exceeded, as the limits are on the
+    // whole message and so fail the parse.
+    fn drop_part(&mut self, span: Range<usize>, error: ParsingError) -> ParsingResult<()> {
+        if let ParsingError::LimitExceeded { .. } = error {
+            return Err(error);
         }
+        self.warn(
+            WarningCode::DroppedPart,
+            span.start,
+            format!("Dropped part: {}", error),
+        );
+        Ok(())
+    }
+
+    fn warn(&mut self, code: WarningCode, offset: usize, message: String) {
+        self.warnings.push(ParseWarning {
+            code,
+            message,
+            offset,
+        });
+    }
+}
 
-        if !done {
-            // Push in the final part of the message (what remains)
-            let final_part = &body_slice[boundary_end..];
-            if !final_part.trim().is_empty() {
-                parts.push(final_part.to_string());
+/// Iterator over the parts of a message that have a `Content-ID`.
+///
+/// Returned by `MimeMessage::inline_parts`.
+pub struct InlineParts<'a> {
+    stack: Vec<&'a MimeMessage>,
+}
+
+impl<'a> Iterator for InlineParts<'a> {
+    type Item = (String, &'a MimeMessage);
+
+    fn next(&mut self) -> Option<(String, &'a MimeMessage)> {
+        while let Some(part) = self.stack.pop() {
+            self.stack.extend(part.children.iter().rev());
+            if let Some(id) = part.content_id() {
+                return Some((id, part));
             }
         }
+        None
+    }
+}
 
-        parts
+// Decode %XX escapes in a URL, leaving any invalid escapes as they are.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            result.push(u8::from_str_radix(&s[i + 1..i + 3], 16).unwrap());
+            i += 3;
+            continue;
+        }
+        result.push(bytes[i]);
+        i += 1;
     }
+    String::from_utf8_lossy(&result).into_owned()
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::header::{Header, HeaderMap};
+    use super::super::header::{Header, HeaderDecoding, HeaderMap};
     use super::*;
 
     #[derive(Debug)]
@@ -848,6 +2312,10 @@ mod tests {
                 mime_type: ("multipart", "signed"),
                 result: Some(MimeMultipartType::Signed),
             },
+            MultipartParseTest {
+                mime_type: ("multipart", "related"),
+                result: Some(MimeMultipartType::Related),
+            },
             // Test failure state
             MultipartParseTest {
                 mime_type: ("text", "plain"),
@@ -895,6 +2363,10 @@ mod tests {
             MimeMultipartType::Signed.to_content_type(),
             (multipart.clone(), "signed".to_string())
         );
+        assert_eq!(
+            MimeMultipartType::Related.to_content_type(),
+            (multipart.clone(), "related".to_string())
+        );
     }
 
     #[test]
@@ -902,6 +2374,215 @@ mod tests {
         let message = MimeMessage::new("Body".to_string());
         // This is random, so we can only really check that it's the expected length
         assert_eq!(message.boundary.len(), super::BOUNDARY_LENGTH);
+
+        let boundary = MimeMessage::generate_boundary("=_acme_");
+        assert!(boundary.starts_with("=_acme_"));
+        assert_eq!(boundary.len(), 7 + super::BOUNDARY_LENGTH);
+        assert!(boundary[7..].chars().all(|c| c.is_ascii_alphanumeric()));
+    }
+
+    #[test]
+    fn test_boundary_collision() {
+        let child = MimeMessage::new("--frontier\r\nNot a boundary".to_string());
+        let message = MimeMessage::new_with_boundary(
+            "".to_string(),
+            MimeMultipartType::Mixed,
+            vec![child.clone()],
+            "frontier".to_string(),
+        );
+        assert!(message.boundary.starts_with("frontier"));
+        assert_eq!(message.boundary.len(), 8 + super::BOUNDARY_LENGTH);
+
+        let parsed = MimeMessage::parse(&message.as_string()[..]).unwrap();
+        assert_eq!(parsed.children.len(), 1);
+        assert!(parsed.children[0].body.starts_with("--frontier\r\nNot a boundary"));
+
+        let long = "x".repeat(super::MAX_BOUNDARY_LENGTH);
+        let child = MimeMessage::new(long.clone());
+        let message =
+            MimeMessage::new_with_boundary("".to_string(), MimeMultipartType::Mixed, vec![child], long);
+        assert!(message.boundary.len() <= super::MAX_BOUNDARY_LENGTH);
+    }
+
+    #[test]
+    fn test_split_boundary() {
+        let body = "preamble --foo\r\n\
+                    --foo \t\r\n\
+                    \r\n\
+                    --foobar\r\n\
+                    -- foo\r\n\
+                    --foo\n\
+                    \n\
+                    two\n\
+                    --foo-- \r\n\
+                    epilogue";
+        let (parts, epilogue_start) = MimeMessage::split_boundary(body, "foo");
+        let parts: Vec<&str> = parts.into_iter().map(|part| &body[part]).collect();
+        assert_eq!(
+            parts,
+            vec![
+                "preamble --foo\r\n",
+                "\r\n--foobar\r\n-- foo\r\n",
+                "\ntwo\n"
+            ]
+        );
+        assert_eq!(&body[epilogue_start.unwrap()..], "epilogue");
+
+        let body = "------=_Part_1\r\n\r\none\r\n------=_Part_1--";
+        let (parts, epilogue_start) = MimeMessage::split_boundary(body, "----=_Part_1");
+        assert_eq!(parts, vec![0..0, 16..23]);
+        assert_eq!(epilogue_start, Some(body.len()));
+    }
+
+    #[test]
+    fn test_preamble_and_epilogue() {
+        let input = "Content-Type: multipart/mixed; boundary=outer\r\n\r\n\
+                     This is a MIME message.\r\n\
+                     --outer\r\n\
+                     Content-Type: multipart/alternative; boundary=inner\r\n\r\n\
+                     --inner\r\n\r\n\
+                     Hello\r\n\
+                     --inner--\r\n\
+                     Inner epilogue\r\n\
+                     --outer--\r\n\
+                     Outer epilogue\r\n";
+        let mut message = MimeMessage::parse(input).unwrap();
+        assert_eq!(message.preamble(), Some("This is a MIME message."));
+        assert_eq!(message.epilogue(), Some("Outer epilogue\r\n"));
+        assert_eq!(message.children[0].preamble(), Some(""));
+        assert_eq!(message.children[0].epilogue(), Some("Inner epilogue"));
+        assert_eq!(message.children[0].children[0].epilogue(), None);
+
+        // Both are written out again once the multiparts are serialized
+        message.children[0].children[0].body = "Hello\r\n".to_string().into();
+        let written = message.as_string();
+        assert!(written.contains("This is a MIME message.\r\n--outer\r\n"));
+        assert!(written.contains("--inner--\r\nInner epilogue\r\n--outer--\r\n"));
+        assert!(written.ends_with("--outer--\r\nOuter epilogue\r\n"));
+        assert_eq!(message.encoded_len_estimate(), written.len());
+
+        message.set_preamble("Use a MIME reader.");
+        message.set_epilogue("");
+        assert!(message.is_modified());
+        let written = message.as_string();
+        assert!(written.contains("\r\n\r\nUse a MIME reader.\r\n--outer"));
+        assert!(written.ends_with("--outer--\r\n"));
+        assert_eq!(
+            MimeMessage::parse(&written).unwrap().preamble(),
+            Some("Use a MIME reader.")
+        );
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        let input: &[u8] = b"Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+            --b\r\n\
+            Content-Type: text/plain; charset=iso-8859-1\r\n\
+            Content-Transfer-Encoding: 8bit\r\n\r\n\
+            caf\xe9\r\n\
+            --b\r\n\
+            Content-Type: application/octet-stream\r\n\
+            Content-Transfer-Encoding: binary\r\n\r\n\
+            \x89PNG\x00\xff\xfe\r\n\
+            --b--\r\n";
+        let mut message = MimeMessage::parse_bytes(input).unwrap();
+        assert_eq!(message.to_bytes(), input);
+
+        let text = &message.children[0];
+        assert!(text.body.is_binary());
+        assert_eq!(text.body.as_bytes(), b"caf\xe9\r\n");
+        assert_eq!(text.body.as_str(), "caf\u{1A}\r\n");
+        assert_eq!(text.decoded_body_string().unwrap(), "caf\u{e9}\r\n");
+        let binary = &message.children[1];
+        assert_eq!(
+            binary.decoded_body_bytes().unwrap(),
+            b"\x89PNG\x00\xff\xfe\r\n"
+        );
+        // Without a charset, there's no telling what the text is
+        assert!(binary.decoded_body_string().is_err());
+
+        // Bodies are kept as they are when written out again
+        message.children[0]
+            .headers
+            .insert(Header::new("X-Note".to_string(), "1".to_string()));
+        message.children[1]
+            .set_body_bytes_with_encoding(b"\x00\xff", MimeContentTransferEncoding::Identity);
+        let written = message.to_bytes();
+        let reparsed = MimeMessage::parse_bytes(&written).unwrap();
+        assert_eq!(reparsed.children[0].body.as_bytes(), b"caf\xe9\r\n");
+        let body = reparsed.children[1].body.as_bytes();
+        assert!(body.starts_with(b"\x00\xff\r\n"));
+        assert_eq!(
+            reparsed.children[1]
+                .headers
+                .get("Content-Transfer-Encoding".to_string())
+                .unwrap()
+                .raw_value(),
+            "binary"
+        );
+
+        let message = MimeMessage::parse_bytes(b"Subject: Hello\r\n\r\nHello\r\n").unwrap();
+        assert!(!message.body.is_binary());
+        assert_eq!(message.as_string(), "Subject: Hello\r\n\r\nHello\r\n");
+    }
+
+    #[test]
+    fn test_parse_bytes_encoded() {
+        // Raw 8-bit bytes in a transfer encoded body are decoded as they
+        // are, rather than as the placeholders in its text
+        let input: &[u8] = b"Content-Type: text/plain; charset=iso-8859-1\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+            caf\xe9 cr=E8me\r\n";
+        let message = MimeMessage::parse_bytes(input).unwrap();
+        let decoded = message.decoded_body_bytes().unwrap();
+        assert_eq!(decoded, b"caf\xe9 cr\xe8me\r\n");
+        let decoded = message.decoded_body_string().unwrap();
+        assert_eq!(decoded, "caf\u{e9} cr\u{e8}me\r\n");
+
+        // Nor does repairing the body replace them
+        let input: &[u8] = b"Content-Type: text/plain; charset=iso-8859-1\r\n\
+            Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+            caf\xe9 = cr=E8me\r\n";
+        let message = MimeMessage::parse_bytes(input).unwrap();
+        let decoded = message.decoded_body_bytes().unwrap();
+        assert_eq!(decoded, b"caf\xe9 = cr\xe8me\r\n");
+        assert_eq!(message.to_bytes(), input);
+    }
+
+    #[test]
+    fn test_effective_content_type() {
+        let message = MimeMessage::parse(
+            "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\n\r\n\
+             Hello\r\n\
+             --b\r\n\
+             Content-Type: multipart/digest; boundary=d\r\n\r\n\
+             --d\r\n\r\n\
+             Subject: First\r\n\r\n\
+             One\r\n\
+             --d\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Contents\r\n\
+             --d--\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+        let text = message.children[0].effective_content_type();
+        assert_eq!(text.to_string(), "text/plain; charset=us-ascii");
+        let digest = &message.children[1];
+        assert_eq!(
+            digest.children[0].effective_content_type(),
+            ContentType::new("message", "rfc822")
+        );
+        assert_eq!(digest.children[0].content_type(), ContentType::default());
+        assert_eq!(
+            digest.children[1].effective_content_type(),
+            ContentType::new("text", "plain")
+        );
+        assert_eq!(
+            message.effective_content_type().to_string(),
+            "multipart/mixed; boundary=b"
+        );
     }
 
     #[test]
@@ -921,6 +2602,26 @@ mod tests {
         assert_eq!(headers.len(), 0);
     }
 
+    #[test]
+    fn test_lowercase_header_names() {
+        let text = "from: joe@example.org\r\n\
+                    content-type: multipart/mixed; boundary=b\r\n\
+                    \r\n\
+                    --b\r\n\
+                    content-transfer-encoding: base64\r\n\
+                    \r\n\
+                    SGk=\r\n\
+                    --b--\r\n";
+        let message = MimeMessage::parse(text).unwrap();
+        assert_eq!(message.message_type, Some(MimeMultipartType::Mixed));
+        assert_eq!(message.children.len(), 1);
+        assert_eq!(
+            message.children[0].decoded_body_string().unwrap(),
+            "Hi"
+        );
+        assert!(message.as_string().starts_with("from: joe@example.org\r\ncontent-type: "));
+    }
+
     #[test]
     fn test_multipart_encrypted() {
         let text = "From: Alice Lovelace <alice@openpgp.example>
@@ -955,11 +2656,11 @@ Content-Disposition: inline; filename=\"encrypted.asc\"
                     (RFC 4880 and 3156)\n"
         );
         assert_eq!(mime.children.len(), 2);
-        // XXX: The original boundary should have been removed, lettre does it.
+        assert!(!mime.is_truncated());
         assert_eq!(
             mime.children[1].body,
             "-----BEGIN PGP MESSAGE-----\n\
-                    -----END PGP MESSAGE-----\n\n--boundary_encrypted--"
+                    -----END PGP MESSAGE-----\n\n"
         );
 
         // XXX: parse add extra new lines.
@@ -967,7 +2668,7 @@ Content-Disposition: inline; filename=\"encrypted.asc\"
         assert_eq!(mime.boundary, "boundary_encrypted");
 
         let mime2 = MimeMessage::parse(&mime.as_string()).unwrap();
-        assert_eq!(format!("{}\r\n", mime.body), mime2.body);
+        assert_eq!(mime.body, mime2.body);
         assert_eq!(mime.boundary, mime2.boundary);
         assert_eq!(mime.message_type, mime2.message_type);
 
@@ -980,32 +2681,22 @@ Content-Disposition: inline; filename=\"encrypted.asc\"
         assert_eq!(
             ct,
             "multipart/encrypted; \
-                    protocol=\"application/pgp-encrypted\";\
+                    protocol=\"application/pgp-encrypted\"; \
                     boundary=\"boundary_encrypted\""
         );
 
-        // XXX: the space after `multipart` gets removed.
         let ct2: String = mime2
             .headers
             .get("Content-Type".to_string())
             .unwrap()
             .get_value()
             .unwrap();
-        assert_eq!(
-            ct2,
-            "multipart/encrypted;\
-                    protocol=\"application/pgp-encrypted\";\
-                    boundary=\"boundary_encrypted\""
-        );
+        assert_eq!(ct2, ct);
 
-        assert_eq!(
-            format!("{}\r\n\r\n", mime.children[0].body),
-            mime2.children[0].body
-        );
-        assert_eq!(
-            mime.children[1].body,
-            format!("{}--boundary_encrypted--", mime2.children[1].body)
-        );
+        // The message is written out just as it was parsed
+        assert_eq!(mime.as_string(), text);
+        assert_eq!(mime.children[0].body, mime2.children[0].body);
+        assert_eq!(mime.children[1].body, mime2.children[1].body);
     }
 
     #[test]
@@ -1052,7 +2743,7 @@ Content-Disposition: inline; filename=\"encrypted.asc\"
         message
             .headers
             .insert(Header::new_with_value("To".into(), to).unwrap());
-        message.body = "Hi".to_string();
+        message.body = "Hi".into();
 
         println!("======= HEADERS BEFORE CALL TO AS_STRING: =======");
         for h in message.headers.iter() {
@@ -1068,6 +2759,668 @@ Content-Disposition: inline; filename=\"encrypted.asc\"
         );
         assert!(!headers.lines().any(|l| l.trim().is_empty())); // <--  panics
     }
+    #[test]
+    fn test_text_and_html_body() {
+        let message = MimeMessage::parse(
+            "Content-Type: multipart/mixed; boundary=outer\r\n\r\n\
+             --outer\r\n\
+             Content-Type: multipart/alternative; boundary=alt\r\n\r\n\
+             --alt\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+             Gr=C3=BC=C3=9Fe\r\n\
+             --alt\r\n\
+             Content-Type: multipart/related; boundary=rel\r\n\r\n\
+             --rel\r\n\
+             Content-Type: text/html\r\n\r\n\
+             <p>Hello</p>\r\n\
+             --rel\r\n\
+             Content-Type: text/html\r\n\r\n\
+             <p>Not the root</p>\r\n\
+             --rel--\r\n\
+             --alt--\r\n\
+             --outer\r\n\
+             Content-Type: text/plain\r\n\
+             Content-Disposition: attachment; filename=notes.txt\r\n\r\n\
+             Notes\r\n\
+             --outer--\r\n",
+        )
+        .unwrap();
+
+        assert_eq!(message.text_body(), Some("Grüße\r\n".to_string()));
+        assert_eq!(message.html_body(), Some("<p>Hello</p>\r\n".to_string()));
+
+        let plain = MimeMessage::parse("From: joe@example.org\r\n\r\nHello!").unwrap();
+        assert_eq!(plain.content_type(), ContentType::new("text", "plain"));
+        assert_eq!(plain.text_body(), Some("Hello!".to_string()));
+        assert_eq!(plain.html_body(), None);
+    }
+    #[test]
+    fn test_preview() {
+        let message = MimeMessage::parse(
+            "Content-Type: text/plain\r\n\r\n\
+             Sounds good,   see you\r\n\
+             \tthen.\r\n\
+             \r\n\
+             On Monday, Joe wrote:\r\n\
+             > Lunch at noon?\r\n\
+             \r\n\
+             Anna\r\n\
+             -- \r\n\
+             Anna Example, Example Corp\r\n",
+        )
+        .unwrap();
+        assert_eq!(message.preview(100), "Sounds good, see you then. Anna");
+        assert_eq!(message.preview(12), "Sounds good,");
+        assert_eq!(message.preview(0), "");
+
+        let html = MimeMessage::parse(
+            "Content-Type: text/html\r\n\r\n<h1>News</h1><p>Read &amp; enjoy</p>",
+        )
+        .unwrap();
+        assert_eq!(html.preview(100), "News Read & enjoy");
+
+        let empty = MimeMessage::parse("Content-Type: image/png\r\n\r\nPNG").unwrap();
+        assert_eq!(empty.preview(100), "");
+    }
+    #[test]
+    fn test_part_by_content_id() {
+        let message = MimeMessage::parse(
+            "Content-Type: multipart/related; boundary=rel\r\n\r\n\
+             --rel\r\n\
+             Content-Type: text/html\r\n\r\n\
+             <img src=\"cid:logo%40example.org\">\r\n\
+             --rel\r\n\
+             Content-Type: image/png\r\n\
+             Content-ID: <logo@example.org>\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n\
+             iVBORw0K\r\n\
+             --rel\r\n\
+             Content-Type: image/png\r\n\
+             Content-ID: <photo@example.org>\r\n\r\n\
+             photo\r\n\
+             --rel--\r\n",
+        )
+        .unwrap();
+
+        let ids: Vec<String> = message.inline_parts().map(|(id, _)| id).collect();
+        assert_eq!(ids, vec!["logo@example.org", "photo@example.org"]);
+
+        let logo = message.part_by_content_id("cid:logo%40example.org").unwrap();
+        assert_eq!(logo.decoded_body_bytes().unwrap(), b"\x89PNG\r\n");
+        assert_eq!(
+            message.part_by_content_id("<photo@example.org>").unwrap().body,
+            "photo\r\n"
+        );
+        assert!(message.part_by_content_id("photo@example.org").is_some());
+        assert!(message.part_by_content_id("missing@example.org").is_none());
+        assert!(message.part_by_content_id("abc\u{e9}").is_none());
+    }
+
+    #[test]
+    fn test_decoded_body() {
+        let input = "Content-Type: multipart/mixed; boundary=foo\r\n\r\n\
+                     --foo\r\n\r\n\
+                     Plain\r\n\
+                     --foo\r\n\
+                     Content-Transfer-Encoding: base64\r\n\r\n\
+                     SGk=\r\n\
+                     --foo--\r\n";
+        let message = MimeMessage::parse(input).unwrap();
+        let plain = &message.children[0];
+        assert_eq!(plain.body, "Plain\r\n");
+        assert!(match plain.decoded_body() {
+            Some(Cow::Borrowed(body)) => body == b"Plain\r\n",
+            _ => false,
+        });
+        let encoded = &message.children[1];
+        assert_eq!(encoded.decoded_body().unwrap().into_owned(), b"Hi".to_vec());
+
+        // The parts share the source rather than holding copies
+        assert!(Arc::ptr_eq(&plain.body.source, &encoded.body.source));
+        assert!(Arc::ptr_eq(&message.body.source, &plain.body.source));
+    }
+
+    #[test]
+    fn test_spans() {
+        let input = "From: joe@example.org\r\n\
+                     Content-Type: multipart/mixed;\r\n boundary=foo\r\n\r\n\
+                     --foo\r\n\
+                     Content-Type: text/plain\r\n\r\n\
+                     Hello\r\n\
+                     --foo\r\n\
+                     \r\n\
+                     World\r\n\
+                     --foo--\r\n";
+        let message = MimeMessage::parse(input).unwrap();
+        assert_eq!(message.part_span(), Some(0..input.len()));
+
+        let spans: Vec<&str> = message
+            .headers
+            .iter()
+            .map(|header| &input[header.span().unwrap()])
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                "From: joe@example.org\r\n",
+                "Content-Type: multipart/mixed;\r\n boundary=foo\r\n",
+            ]
+        );
+
+        let first = &message.children[0];
+        assert_eq!(
+            &input[first.part_span().unwrap()],
+            "Content-Type: text/plain\r\n\r\nHello\r\n"
+        );
+        let header = first.headers.get("Content-Type".to_string()).unwrap();
+        assert_eq!(&input[header.span().unwrap()], "Content-Type: text/plain\r\n");
+        assert_eq!(
+            &input[message.children[1].part_span().unwrap()],
+            "\r\nWorld\r\n"
+        );
+
+        assert_eq!((message.encoded_size(), message.line_count()), (67, 8));
+        assert_eq!((first.encoded_size(), first.line_count()), (7, 1));
+        let mut second = message.children[1].clone();
+        assert_eq!((second.encoded_size(), second.line_count()), (7, 1));
+        second.set_body_bytes(b"Hi");
+        assert_eq!((second.encoded_size(), second.line_count()), (4, 1));
+        second.body = "Hi\r\nthere".into();
+        assert_eq!((second.encoded_size(), second.line_count()), (11, 2));
+
+        let mut message = message.clone();
+        message.children[0].body = "Hi\r\n".into();
+        let written = message.as_string_without_headers();
+        assert_eq!(message.encoded_size(), written.len());
+        assert_eq!(message.line_count(), count_lines(&written));
+
+        assert_eq!(MimeMessage::new("Hello".to_string()).part_span(), None);
+        assert_eq!(Header::new("To".to_string(), "joe".to_string()).span(), None);
+    }
+
+    #[test]
+    fn test_raw_part_bytes() {
+        let input = "Content-Type: multipart/mixed; boundary=foo\r\n\r\n\
+                     --foo\r\n\
+                     Content-Type: multipart/alternative; boundary=bar\r\n\r\n\
+                     --bar\r\n\
+                     Content-Type: text/plain\r\n\r\n\
+                     Hello\r\n\
+                     --bar\r\n\
+                     Content-Type: text/html\r\n\r\n\
+                     <p>Hello</p>\r\n\r\n\
+                     --bar--\r\n\
+                     --foo--\r\n";
+        let mut message = MimeMessage::parse(input).unwrap();
+        let raw = |message: &MimeMessage, path: &str| {
+            message
+                .raw_part_bytes(&path.parse().unwrap())
+                .map(|bytes| String::from_utf8(bytes.into_owned()).unwrap())
+        };
+        assert_eq!(raw(&message, "").unwrap(), input);
+        assert_eq!(
+            raw(&message, "1.2").unwrap(),
+            "Content-Type: text/html\r\n\r\n<p>Hello</p>\r\n"
+        );
+        assert!(matches!(
+            message.raw_part_bytes(&"1.1".parse().unwrap()),
+            Some(Cow::Borrowed(_))
+        ));
+        assert_eq!(raw(&message, "1.3"), None);
+
+        message.children[0].children[0].set_body_bytes(b"Hi");
+        assert_eq!(
+            raw(&message, "1.1").unwrap(),
+            "Content-Type: text/plain\r\n\
+             Content-Transfer-Encoding: 7bit\r\n\r\n\
+             Hi\r\n"
+        );
+        assert!(raw(&message, "1").unwrap().contains("Hi\r\n"));
+    }
+
+    #[test]
+    fn test_write_to() {
+        let mut message = MimeMessage::new_with_children(
+            "Preamble".to_string(),
+            MimeMultipartType::Mixed,
+            vec![
+                MimeMessage::new("Hello".to_string()),
+                MimeMessage::new("World".to_string()),
+            ],
+        );
+        message.headers.insert(Header::new(
+            "Subject".to_string(),
+            "A subject long enough that it has to be folded over more than one line, \
+             as it runs on past the limit"
+                .to_string(),
+        ));
+
+        let mut written = Vec::new();
+        message.write_to(&mut written).unwrap();
+        let string = message.as_string();
+        assert_eq!(written, string.as_bytes());
+        assert_eq!(message.encoded_len_estimate(), string.len());
+        assert!(string.ends_with(&format!("--{}--\r\n", message.boundary)));
+    }
+
+    #[test]
+    fn test_write_unmodified() {
+        let input = "Subject: Hi\r\n\
+                     Content-Type: multipart/mixed; boundary=foo\r\n\r\n\
+                     Preamble\r\n\
+                     --foo\r\n\
+                     Content-Type: text/plain\r\n\r\n\
+                     Hello\r\n\
+                     --foo\r\n\
+                     Content-Type: application/octet-stream\r\n\
+                     Content-Transfer-Encoding: base64\r\n\r\n\
+                     AAECAw==\r\n\
+                     --foo--\r\n\
+                     Epilogue\r\n";
+        let mut message = MimeMessage::parse(input).unwrap();
+        assert!(!message.is_modified());
+        assert_eq!(message.as_string(), input);
+        assert_eq!(message.encoded_len_estimate(), input.len());
+
+        // Only the changed headers are written again
+        message.headers.rename("Subject", "X-Original-Subject");
+        assert!(message.is_modified());
+        let body = &input[input.find("Preamble").unwrap()..];
+        let expected = format!(
+            "X-Original-Subject: Hi\r\n\
+             Content-Type: multipart/mixed; boundary=foo\r\n\r\n{}",
+            body
+        );
+        assert_eq!(message.as_string(), expected);
+        assert_eq!(message.encoded_len_estimate(), expected.len());
+
+        // The unchanged attachment is copied when its sibling is changed
+        message.children[0].set_body_bytes(b"Bye");
+        assert!(!message.children[1].is_modified());
+        let written = message.as_string();
+        assert!(written.contains(
+            "--foo\r\n\
+             Content-Type: application/octet-stream\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n\
+             AAECAw==\r\n\
+             --foo--\r\n"
+        ));
+        assert!(!written.contains("Hello"));
+        assert_eq!(message.encoded_len_estimate(), written.len());
+
+        let reparsed = MimeMessage::parse(&written).unwrap();
+        assert_eq!(reparsed.children[1].body, message.children[1].body);
+
+        assert!(MimeMessage::new("Hello".to_string()).is_modified());
+    }
+
+    #[test]
+    fn test_parse_parallel() {
+        let mut input = "Content-Type: multipart/mixed; boundary=foo\r\n\r\n".to_string();
+        for i in 0..20 {
+            input.push_str(&format!(
+                "--foo\r\n\
+                 Content-Type: multipart/alternative; boundary=bar{0}\r\n\r\n\
+                 --bar{0}\r\n\r\nPart {0}\r\n--bar{0}--\r\n",
+                i
+            ));
+        }
+        input.push_str("--foo--\r\n");
+
+        let sequential = MimeMessage::parse(&input).unwrap();
+        let options = ParseOptions {
+            parallel: true,
+            ..Default::default()
+        };
+        let parallel = MimeMessage::parse_with_options(&input, &options).unwrap();
+        assert_eq!(parallel.as_string(), sequential.as_string());
+        assert_eq!(parallel.children.len(), 20);
+        assert_eq!(parallel.children[7].children[0].body, "Part 7\r\n");
+    }
+
+    fn nested(depth: usize) -> String {
+        let mut input = String::new();
+        for i in (0..depth).rev() {
+            input.push_str(&format!(
+                "Content-Type: multipart/mixed; boundary=b{0}\r\n\r\n--b{0}\r\n",
+                i
+            ));
+        }
+        input.push_str("Body\r\n");
+        for i in 0..depth {
+            input.push_str(&format!("\r\n--b{}--\r\n", i));
+        }
+        input
+    }
+
+    fn parse_with_limits(input: &str, limits: Limits) -> ParsingResult<MimeMessage> {
+        let options = ParseOptions {
+            limits,
+            ..Default::default()
+        };
+        MimeMessage::parse_with_options(input, &options)
+    }
+
+    fn exceeded(result: ParsingResult<MimeMessage>) -> Option<(String, usize)> {
+        match result {
+            Err(ParsingError::LimitExceeded { limit, max }) => Some((limit, max)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_parse_deeply_nested() {
+        // Parts are parsed without recursing, so the limit is reached
+        // rather than overflowing the stack
+        let err = MimeMessage::parse(&nested(10_000)).unwrap_err();
+        assert_eq!(err.to_string(), "Exceeded nesting depth limit of 100");
+
+        let limits = Limits {
+            max_depth: 500,
+            max_parts: 1_000,
+            ..Default::default()
+        };
+        let message = parse_with_limits(&nested(500), limits).unwrap();
+        let mut part = &message;
+        let mut depth = 0;
+        while let Some(child) = part.children.first() {
+            part = child;
+            depth += 1;
+        }
+        assert_eq!(depth, 500);
+        assert!(part.body.starts_with("Body\r\n"));
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let limits = Limits {
+            max_depth: 3,
+            ..Default::default()
+        };
+        assert!(parse_with_limits(&nested(3), limits.clone()).is_ok());
+        assert_eq!(
+            exceeded(parse_with_limits(&nested(4), limits)),
+            Some(("nesting depth".to_string(), 3))
+        );
+        let err = MimeMessage::parse(&nested(1000)).unwrap_err();
+        assert_eq!(err.to_string(), "Exceeded nesting depth limit of 100");
+
+        let limits = Limits {
+            max_parts: 3,
+            ..Default::default()
+        };
+        assert!(parse_with_limits(&nested(2), limits.clone()).is_ok());
+        assert_eq!(
+            exceeded(parse_with_limits(&nested(3), limits)),
+            Some(("part count".to_string(), 3))
+        );
+
+        let input = "A: 1\r\nB: 2\r\nC: 3\r\n\r\nBody\r\n";
+        let limits = Limits {
+            max_header_count: 2,
+            ..Default::default()
+        };
+        assert_eq!(
+            exceeded(parse_with_limits(input, limits)),
+            Some(("header count".to_string(), 2))
+        );
+        let limits = Limits {
+            max_header_length: 5,
+            ..Default::default()
+        };
+        assert_eq!(
+            exceeded(parse_with_limits(input, limits)),
+            Some(("header length".to_string(), 5))
+        );
+        assert!(parse_with_limits(input, Limits::unlimited()).is_ok());
+
+        let input = "Content-Transfer-Encoding: base64\r\n\r\naGVsbG8g\r\nd29ybGQ=\r\n";
+        let limits = Limits {
+            max_decoded_size: 12,
+            ..Default::default()
+        };
+        assert!(parse_with_limits(input, limits).is_ok());
+        let limits = Limits {
+            max_decoded_size: 10,
+            ..Default::default()
+        };
+        assert_eq!(
+            exceeded(parse_with_limits(input, limits)),
+            Some(("decoded size".to_string(), 10))
+        );
+    }
+
+    #[test]
+    fn test_parse_headers_only() {
+        let input = "From: joe@example.org\r\n\
+                     Subject: Hello\r\n \
+                     world\r\n\
+                     Content-Type: multipart/mixed; boundary=foo\r\n\r\n\
+                     --foo\r\n\
+                     To: not-a-header@example.org\r\n\r\n\
+                     Body\r\n\
+                     --foo--\r\n";
+        let headers = MimeMessage::parse_headers_only(input);
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers.get_value::<String>("Subject".to_string()).unwrap(), "Hello world");
+        assert!(headers.get("To".to_string()).is_none());
+        let header = headers.get("From".to_string()).unwrap();
+        assert_eq!(&input[header.span().unwrap()], "From: joe@example.org\r\n");
+
+        let mut reader = input.as_bytes();
+        let read = MimeMessage::read_headers_only(&mut reader).unwrap();
+        assert_eq!(read, headers);
+        assert_eq!(reader, &input.as_bytes()[94..]);
+        assert!(reader.starts_with(b"--foo\r\n"));
+
+        let mut reader = &b"Subject: No body\n"[..];
+        assert_eq!(MimeMessage::read_headers_only(&mut reader).unwrap().len(), 1);
+        assert!(MimeMessage::parse_headers_only("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_with_warnings() {
+        let input = "From: joe@example.org\r\n\
+                     Subject: =?utf-8?q?broken=?=\r\n\
+                     Content-Type: multipart/mixed; boundary=foo\r\n\r\n\
+                     --foo\r\n\
+                     Content-Type: text/plain\r\n\
+                     Hello\r\n\
+                     --foo\r\n\
+                     Content-Type: multipart/alternative\r\n\r\n\
+                     World\r\n";
+        let (message, warnings) = MimeMessage::parse_with_warnings(input).unwrap();
+        assert_eq!(message.children.len(), 2);
+
+        let codes: Vec<(WarningCode, usize)> = warnings
+            .iter()
+            .map(|warning| (warning.code, warning.offset))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                (WarningCode::MissingDate, 0),
+                (WarningCode::InvalidEncodedWord, 23),
+                (WarningCode::MissingBlankLine, 133),
+                (WarningCode::MissingBoundary, 147),
+                (WarningCode::UnterminatedMultipart, input.len()),
+            ]
+        );
+        assert_eq!(
+            warnings[1].to_string(),
+            "Invalid encoded word in Subject header: =?utf-8?q?broken=?= at 23"
+        );
+
+        let (_, warnings) = MimeMessage::parse_with_warnings(
+            "From: joe@example.org\r\nDate: Wed, 17 Dec 2014 09:35:07 +0100\r\n\r\nHello",
+        )
+        .unwrap();
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let input = "Content-Type: multipart/mixed; boundary=outer\r\n\r\n\
+                     --outer\r\n\
+                     Content-Type: text/plain\r\n\r\n\
+                     Hello\r\n\
+                     --outer\r\n\
+                     Content-Type: multipart/alternative; boundary=inner\r\n\r\n\
+                     --inner\r\n\
+                     Content-Type: text/plain\r\n\r\n\
+                     World\r\n";
+        let message = MimeMessage::parse(input).unwrap();
+        assert!(message.is_truncated());
+        assert!(!message.children[0].is_truncated());
+        assert!(message.children[1].is_truncated());
+        assert_eq!(message.children[1].children[0].body.as_str(), "World\r\n");
+
+        let options = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let err = MimeMessage::parse_with_options(input, &options).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Expected closing boundary --outer-- at {}, found end of input",
+                input.len()
+            )
+        );
+
+        let input = format!("{}--inner--\r\n--outer--", input);
+        assert!(!MimeMessage::parse(&input).unwrap().is_truncated());
+        assert!(MimeMessage::parse_with_options(&input, &options).is_ok());
+    }
+
+    #[test]
+    fn test_parse_header_decoding() {
+        let mut options = ParseOptions::default();
+        options.header_decoding.set("Subject", HeaderDecoding::Raw);
+        let input = "Subject: =?utf-8?q?caf=C3=A9?=\r\n\r\nHello\r\n";
+        let message = MimeMessage::parse_with_options(input, &options).unwrap();
+        let subject = message.headers.get("Subject".to_string()).unwrap();
+        assert_eq!(subject.decoded_value(), "=?utf-8?q?caf=C3=A9?=");
+        // The policy doesn't change how the message is written
+        assert_eq!(message.as_string(), input);
+    }
+
+    #[test]
+    fn test_parse_repairs() {
+        let input = "From: joe@example.org\r\n\
+                     Date: Wed, 17 Dec 2014 09:35:07 +0100\r\n\
+                     Content-Type: multipart/mixed; boundary=foo\r\n\r\n\
+                     --foo\r\n\
+                     Content-Transfer-Encoding: base64\r\n\r\n\
+                     SGVs*bG8\r\n\
+                     --foo\r\n\
+                     Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+                     1 caf=C3=A9 = 2 =\r\n\
+                     --fo";
+        let (message, warnings) = MimeMessage::parse_with_warnings(input).unwrap();
+        assert_eq!(message.children.len(), 2);
+        assert_eq!(&message.children[0].decoded_body().unwrap()[..], b"Hello");
+        assert_eq!(
+            message.children[1].decoded_body_string().unwrap(),
+            "1 caf\u{e9} = 2 "
+        );
+        let codes: Vec<WarningCode> = warnings.iter().map(|warning| warning.code).collect();
+        // The base64 body decodes as it is, so only the quoted-printable
+        // one needs repair
+        assert_eq!(
+            codes,
+            vec![
+                WarningCode::RepairedEncoding,
+                WarningCode::PartialBoundary,
+                WarningCode::UnterminatedMultipart,
+            ]
+        );
+        assert_eq!(
+            warnings[1].to_string(),
+            format!("Removed partial boundary --fo at {}", input.len() - 4)
+        );
+        // The bodies are left as they were in the input
+        assert!(!message.is_modified());
+        assert_eq!(message.children[1].body.as_str(), "1 caf=C3=A9 = 2 =\r\n");
+        let raw = message.raw_part_bytes(&"2".parse().unwrap()).unwrap();
+        let expected = "Content-Transfer-Encoding: quoted-printable\r\n\r\n1 caf=C3=A9 = 2 =";
+        assert_eq!(&raw[..], expected.as_bytes());
+
+        let options = ParseOptions {
+            strict: true,
+            ..Default::default()
+        };
+        assert!(MimeMessage::parse_with_options(input, &options).is_err());
+        let input = format!("{}o--\r\n", input);
+        let message = MimeMessage::parse_with_options(&input, &options).unwrap();
+        assert_eq!(message.children[0].body.as_str(), "SGVs*bG8\r\n");
+        assert!(message.children[1].decoded_body().is_none());
+
+        // A multipart with no parts at all
+        let input = "Content-Type: multipart/mixed; boundary=x\r\n\r\n";
+        let message = MimeMessage::parse(input).unwrap();
+        assert!(message.children.is_empty());
+    }
+
+    #[test]
+    fn test_quirks() {
+        let quirks = Quirks::all().without(Quirk::TnefAttachments);
+        assert!(quirks.contains(Quirk::Utf8AsAscii));
+        assert!(!quirks.contains(Quirk::TnefAttachments));
+        assert_eq!(Quirks::default(), Quirks::none());
+        let options = ParseOptions {
+            quirks: Quirks::all(),
+            ..Default::default()
+        };
+
+        let input = "Content-Type: text/plain; charset=us-ascii\r\n\r\ncaf\u{e9}";
+        let message = MimeMessage::parse(input).unwrap();
+        assert_ne!(message.decoded_body_string().unwrap(), "caf\u{e9}");
+        let message = MimeMessage::parse_with_options(input, &options).unwrap();
+        assert_eq!(message.decoded_body_string().unwrap(), "caf\u{e9}");
+
+        let input = "References: <a@example.com>\r\n\
+                     <b@example.com>\r\n\
+                     <c@example.com>\r\n\
+                     Subject: Re: Hi\r\n\r\n\
+                     Body";
+        let message = MimeMessage::parse(input).unwrap();
+        assert_eq!(message.headers.len(), 1);
+        let message = MimeMessage::parse_with_options(input, &options).unwrap();
+        let references = message.headers.get("References".to_string()).unwrap();
+        assert_eq!(
+            references.raw_value(),
+            "<a@example.com> <b@example.com> <c@example.com>"
+        );
+        let subject = message.headers.get("Subject".to_string()).unwrap();
+        assert_eq!(subject.raw_value(), "Re: Hi");
+        assert_eq!(message.body.as_str(), "Body");
+
+        // The limits hold across the joined lines
+        let input = format!(
+            "References: <a@example.com>\r\n{}A: 1\r\nB: 2\r\n\r\n",
+            "<c@example.com>\r\n".repeat(10)
+        );
+        let limited = |max_header_count, max_header_length| ParseOptions {
+            quirks: Quirks::all(),
+            limits: Limits {
+                max_header_count,
+                max_header_length,
+                ..Limits::default()
+            },
+            ..Default::default()
+        };
+        assert!(MimeMessage::parse_with_options(&input, &limited(3, 200)).is_ok());
+        assert!(MimeMessage::parse_with_options(&input, &limited(2, 200)).is_err());
+        assert!(MimeMessage::parse_with_options(&input, &limited(3, 100)).is_err());
+
+        let input = "Content-Disposition: inline; filename=photo.jpg\r\n\r\n";
+        let message = MimeMessage::parse(input).unwrap();
+        assert!(!message.is_attachment());
+        let message = MimeMessage::parse_with_options(input, &options).unwrap();
+        assert!(message.is_attachment());
+    }
 }
 
 #[cfg(all(feature = "nightly", test))]
@@ -0,0 +1,227 @@
+use std::fmt;
+use std::str::FromStr;
+
+use super::environment;
+use super::header::{FromHeader, ToHeader};
+use super::results::{ParsingError, ParsingResult};
+use super::rfc5322::Rfc5322Parser;
+
+/// Represents an RFC 5322 `msg-id`, as used by the `Message-ID`,
+/// `In-Reply-To` and `References` headers.
+///
+/// The identifier is stored without its surrounding angle brackets.
+#[derive(PartialEq, Eq, Debug, Clone, Hash)]
+pub struct MessageId {
+    id: String,
+}
+
+impl MessageId {
+    /// Create a new MessageId from `id`, which should not include the
+    /// angle brackets.
+    pub fn new(id: String) -> MessageId {
+        MessageId { id }
+    }
+
+    /// Generate a new, unique MessageId for `domain`.
+    pub fn generate(domain: &str) -> MessageId {
+        let left = environment::random_alphanumeric(24);
+        MessageId::new(format!("{}@{}", left, domain))
+    }
+
+    /// The identifier, without angle brackets.
+    pub fn id(&self) -> &str {
+        &self.id[..]
+    }
+
+    /// The part of the identifier left of the `@`
+    pub fn left(&self) -> &str {
+        self.id.split('@').next().unwrap_or("")
+    }
+
+    /// The part of the identifier right of the `@`, if there is one
+    pub fn right(&self) -> Option<&str> {
+        self.id.split_once('@').map(|(_, right)| right)
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "<{}>", self.id)
+    }
+}
+
+impl FromStr for MessageId {
+    type Err = ParsingError;
+
+    fn from_str(s: &str) -> ParsingResult<MessageId> {
+        FromHeader::from_header(s.to_string())
+    }
+}
+
+/// Parse all message identifiers out of `s`.
+///
+/// This is deliberately lenient, as `In-Reply-To` in particular is often
+/// filled with phrases (`"Your message of ..."`) around the identifier.
+/// Comments and folding whitespace are skipped, as is anything else which
+/// is not in angle brackets. Whitespace inside the brackets, as left by
+/// broken folding, is removed.
+fn parse_message_ids(s: &str) -> ParsingResult<Vec<MessageId>> {
+    let mut parser = Rfc5322Parser::new(s);
+    let mut ids = Vec::new();
+
+    loop {
+        parser.consume_cfws();
+        if parser.eof() {
+            break;
+        }
+        if parser.peek() == '<' {
+            parser.consume_char();
+            let id = parser.consume_while(|c| c != '>' && c != '<');
+            parser.assert_char('>')?;
+            parser.consume_char();
+            let id: String = id.chars().filter(|c| !c.is_whitespace()).collect();
+            if !id.is_empty() {
+                ids.push(MessageId::new(id));
+            }
+        } else if parser.peek() == '"' {
+            // Quoted strings of an obsolete phrase may contain '<'
+            if parser.consume_quoted_str().is_none() {
+                parser.consume_char();
+            }
+        } else {
+            // Anything else is a phrase or garbage, so skip over it
+            parser.consume_while_str(|c| c != '<' && c != '(' && c != '"' && !c.is_whitespace());
+            if !parser.eof() && parser.peek() == '(' && parser.consume_comment().is_none() {
+                parser.consume_char();
+            }
+        }
+    }
+
+    if ids.is_empty() {
+        // Some clients omit the brackets entirely, so accept a single
+        // bare identifier as long as it looks like one.
+        let bare = s.trim();
+        if !bare.is_empty() && bare.contains('@') && !bare.contains(char::is_whitespace) {
+            ids.push(MessageId::new(bare.to_string()));
+        }
+    }
+
+    Ok(ids)
+}
+
+impl FromHeader for MessageId {
+    fn from_header(value: String) -> ParsingResult<MessageId> {
+        match parse_message_ids(&value[..])?.into_iter().next() {
+            Some(id) => Ok(id),
+            None => Err(ParsingError::InvalidToken {
+                pos: 0,
+                kind: "message id".to_string(),
+                token: value,
+            }),
+        }
+    }
+}
+
+impl FromHeader for Vec<MessageId> {
+    fn from_header(value: String) -> ParsingResult<Vec<MessageId>> {
+        parse_message_ids(&value[..])
+    }
+}
+
+impl ToHeader for MessageId {
+    fn to_header(value: MessageId) -> ParsingResult<String> {
+        Ok(value.to_string())
+    }
+}
+
+impl<'a> ToHeader for &'a [MessageId] {
+    fn to_header(value: &'a [MessageId]) -> ParsingResult<String> {
+        if value.is_empty() {
+            return Err(ParsingError::new(
+                "Header value cannot be empty".to_string(),
+            ));
+        }
+        let ids: Vec<String> = value.iter().map(|id| id.to_string()).collect();
+        Ok(ids.join(" "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::header::Header;
+    use super::*;
+
+    #[test]
+    fn test_message_id_parse() {
+        let tests = vec![
+            (
+                "<1234@local.machine.example>",
+                vec!["1234@local.machine.example"],
+            ),
+            (
+                "<1234@local.machine.example> <3456@example.net>",
+                vec!["1234@local.machine.example", "3456@example.net"],
+            ),
+            (
+                "<1234@local.machine.example>\r\n\t<3456@example.net>",
+                vec!["1234@local.machine.example", "3456@example.net"],
+            ),
+            (
+                "(comment <not@an.id>) <1234@example.net> (another)",
+                vec!["1234@example.net"],
+            ),
+            (
+                "Your message of \"Mon, 01 Jan <2001>\" <abc@example.org>",
+                vec!["abc@example.org"],
+            ),
+            (
+                "<broken\r\n\t@folding.example>",
+                vec!["broken@folding.example"],
+            ),
+            ("bare@example.org", vec!["bare@example.org"]),
+            ("not an id", vec![]),
+        ];
+
+        for (input, expected) in tests.into_iter() {
+            let header = Header::new("References".to_string(), input.to_string());
+            let ids: Vec<MessageId> = header.get_value().unwrap();
+            let ids: Vec<&str> = ids.iter().map(|id| id.id()).collect();
+            assert_eq!(ids, expected, "{}", input);
+        }
+
+        let header = Header::new(
+            "References".to_string(),
+            "<unterminated@example".to_string(),
+        );
+        assert!(header.get_value::<Vec<MessageId>>().is_err());
+    }
+
+    #[test]
+    fn test_single_message_id() {
+        let id: MessageId = "<abc@example.org>".parse().unwrap();
+        assert_eq!(id.left(), "abc");
+        assert_eq!(id.right(), Some("example.org"));
+        assert_eq!(id.to_string(), "<abc@example.org>");
+
+        assert!("".parse::<MessageId>().is_err());
+
+        let generated = MessageId::generate("example.org");
+        assert_eq!(generated.right(), Some("example.org"));
+        assert_ne!(generated, MessageId::generate("example.org"));
+    }
+
+    #[test]
+    fn test_message_id_to_header() {
+        let ids = [
+            MessageId::new("a@example.org".to_string()),
+            MessageId::new("b@example.org".to_string()),
+        ];
+        let header = Header::new_with_value("References".to_string(), &ids[..]).unwrap();
+        assert_eq!(
+            header.to_string(),
+            "References: <a@example.org> <b@example.org>"
+        );
+        let empty: Vec<MessageId> = Vec::new();
+        assert!(Header::new_with_value("References".to_string(), &empty[..]).is_err());
+    }
+}
@@ -0,0 +1,525 @@
+//! Module for resolving the URIs in a message to its parts, as described
+//! in RFC 2557 (MHTML).
+//!
+//! A web page saved as MHTML, or an HTML message with inline images, is a
+//! `multipart/related` part whose HTML refers to the other parts by
+//! `cid:` URLs matching their `Content-ID`, or by URIs matching their
+//! `Content-Location`. Relative URIs, in the HTML or in `Content-Location`
+//! itself, are resolved against the location of the part they're found in,
+//! or failing that of the parts enclosing it. `MhtmlBuilder` archives a page
+//! in this form.
+use std::collections::HashMap;
+
+use super::header::Header;
+use super::message::{MimeMessage, MimeMultipartType};
+use super::mimeheaders::{guess_mime_type, ContentType, MimeContentTransferEncoding};
+use super::rfc2045::percent_decode;
+
+/// An index of the parts of a message by `Content-ID` and resolved
+/// `Content-Location`.
+///
+/// Made by `MimeMessage::part_index`.
+pub struct PartIndex<'a> {
+    by_id: HashMap<String, &'a MimeMessage>,
+    by_location: HashMap<String, &'a MimeMessage>,
+    bases: Vec<(&'a MimeMessage, Option<String>)>,
+}
+
+impl<'a> PartIndex<'a> {
+    fn new(message: &'a MimeMessage) -> PartIndex<'a> {
+        let mut index = PartIndex {
+            by_id: HashMap::new(),
+            by_location: HashMap::new(),
+            bases: Vec::new(),
+        };
+        let mut stack: Vec<(&MimeMessage, Option<String>)> = vec![(message, None)];
+        while let Some((part, inherited)) = stack.pop() {
+            let content_base = header_uri(part, "Content-Base")
+                .and_then(|base| resolve_reference(inherited.as_deref(), &base));
+            let base = content_base.or(inherited);
+            let location = part.content_location();
+            let resolved = location
+                .as_ref()
+                .and_then(|location| resolve_reference(base.as_deref(), location));
+
+            if let Some(id) = part.content_id() {
+                index.by_id.entry(id).or_insert(part);
+            }
+            // A relative location with nothing to resolve it against is
+            // matched as it is
+            if let Some(key) = resolved.clone().or(location) {
+                index.by_location.entry(key).or_insert(part);
+            }
+
+            let base = resolved.or(base);
+            stack.extend(
+                part.children
+                    .iter()
+                    .rev()
+                    .map(|child| (child, base.clone())),
+            );
+            index.bases.push((part, base));
+        }
+        index
+    }
+
+    /// The part with the `Content-ID` `id`, without angle brackets.
+    pub fn by_content_id(&self, id: &str) -> Option<&'a MimeMessage> {
+        self.by_id.get(id).copied()
+    }
+
+    /// The base URI against which relative URIs in `part` are resolved, if
+    /// it has one.
+    pub fn base(&self, part: &MimeMessage) -> Option<&str> {
+        self.bases
+            .iter()
+            .find(|(indexed, _)| std::ptr::eq(*indexed, part))
+            .and_then(|(_, base)| base.as_deref())
+    }
+
+    /// Find the part `uri` refers to, resolving it against `base`.
+    ///
+    /// `cid:` URLs are matched against the `Content-ID` of each part, and
+    /// other URIs against the `Content-Location`. Should there be several
+    /// matches, the first part in the message is given.
+    pub fn resolve(&self, uri: &str, base: Option<&str>) -> Option<&'a MimeMessage> {
+        let uri: String = uri.split_whitespace().collect();
+        let scheme = uri.get(..4);
+        if scheme.is_some_and(|scheme| scheme.eq_ignore_ascii_case("cid:")) {
+            let id = String::from_utf8_lossy(&percent_decode(&uri[4..])).into_owned();
+            return self.by_content_id(&id);
+        }
+        let key = resolve_reference(base, &uri)
+            .unwrap_or_else(|| uri.split('#').next().unwrap_or("").to_string());
+        self.by_location.get(&key).copied()
+    }
+}
+
+impl MimeMessage {
+    /// The URI from the `Content-Location` header of this part, with any
+    /// folding whitespace removed.
+    pub fn content_location(&self) -> Option<String> {
+        header_uri(self, "Content-Location")
+    }
+
+    /// Index the parts of this message by `Content-ID` and
+    /// `Content-Location`, to resolve many URIs.
+    pub fn part_index(&self) -> PartIndex<'_> {
+        PartIndex::new(self)
+    }
+
+    /// Find the part a URI in the HTML body of this message refers to,
+    /// following the rules of RFC 2557.
+    pub fn resolve(&self, uri: &str) -> Option<&MimeMessage> {
+        let index = self.part_index();
+        let html = self.find_body_part("html").unwrap_or(self);
+        index.resolve(uri, index.base(html))
+    }
+}
+
+/// Builder for archiving a web page as MHTML.
+///
+/// The page and the resources it uses, such as images and stylesheets, are
+/// put in a `multipart/related` part with the location of each, which can
+/// be sent as it is or used as the body of a message with
+/// `MessageBuilder::body`.
+#[derive(Debug, Clone, Default)]
+pub struct MhtmlBuilder {
+    location: String,
+    html: String,
+    resources: Vec<MimeMessage>,
+    boundary_prefix: String,
+}
+
+impl MhtmlBuilder {
+    /// Start archiving the page at `location` with the given HTML.
+    pub fn new(location: &str, html: &str) -> MhtmlBuilder {
+        MhtmlBuilder {
+            location: location.to_string(),
+            html: html.to_string(),
+            ..MhtmlBuilder::default()
+        }
+    }
+
+    /// Add a resource the page uses, found at `location`, using
+    /// `content_type` or else a type guessed from the location.
+    pub fn resource(
+        mut self,
+        location: &str,
+        content: &[u8],
+        content_type: Option<ContentType>,
+    ) -> MhtmlBuilder {
+        let content_type = content_type.unwrap_or_else(|| {
+            let path = UriRef::parse(location).path;
+            guess_mime_type(path.rsplit('/').next().unwrap_or(""))
+        });
+        let mut part = MimeMessage::new_blank_message();
+        part.headers.insert(Header::new(
+            "Content-Type".to_string(),
+            content_type.to_string(),
+        ));
+        part.headers.insert(Header::new(
+            "Content-Location".to_string(),
+            encode_uri(location),
+        ));
+        part.set_body_bytes_with_encoding(content, MimeContentTransferEncoding::Base64);
+        self.resources.push(part);
+        self
+    }
+
+    /// Start the boundary of the archive with `prefix`, which is followed
+    /// by random characters.
+    pub fn boundary_prefix(mut self, prefix: &str) -> MhtmlBuilder {
+        self.boundary_prefix = prefix.to_string();
+        self
+    }
+
+    /// Build the `multipart/related` part holding the page, then its
+    /// resources in the order they were added.
+    pub fn build(self) -> MimeMessage {
+        let mut page = MimeMessage::new_blank_message();
+        page.headers.insert(Header::new(
+            "Content-Type".to_string(),
+            "text/html; charset=utf-8".to_string(),
+        ));
+        page.headers.insert(Header::new(
+            "Content-Location".to_string(),
+            encode_uri(&self.location),
+        ));
+        page.set_body_bytes(self.html.as_bytes());
+
+        let mut children = vec![page];
+        children.extend(self.resources);
+        let mut archive = MimeMessage::new_with_boundary(
+            "".to_string(),
+            MimeMultipartType::Related,
+            children,
+            MimeMessage::generate_boundary(&self.boundary_prefix[..]),
+        );
+        let mut content_type = archive.content_type();
+        content_type.set_parameter("type", "text/html");
+        archive.headers.replace(Header::new(
+            "Content-Type".to_string(),
+            content_type.to_string(),
+        ));
+        archive
+    }
+}
+
+// Percent-encode the spaces, controls and non-ASCII characters in `uri`,
+// which can't be given in a header as they are.
+fn encode_uri(uri: &str) -> String {
+    let mut encoded = String::with_capacity(uri.len());
+    for b in uri.trim().bytes() {
+        if b.is_ascii_graphic() {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+    encoded
+}
+
+// The URI given in header `name` of `part`. Long URIs may be folded over
+// several lines, RFC 2557 Section 4.4.
+fn header_uri(part: &MimeMessage, name: &str) -> Option<String> {
+    let header = part.headers.get(name.to_string())?;
+    let uri: String = header
+        .raw_value()
+        .trim()
+        .trim_matches('"')
+        .split_whitespace()
+        .collect();
+    Some(uri).filter(|uri| !uri.is_empty())
+}
+
+// The parts of a URI reference, without any fragment.
+struct UriRef<'a> {
+    scheme: Option<&'a str>,
+    authority: Option<&'a str>,
+    path: &'a str,
+    query: Option<&'a str>,
+}
+
+impl<'a> UriRef<'a> {
+    fn parse(uri: &'a str) -> UriRef<'a> {
+        let uri = uri.split('#').next().unwrap_or("");
+        let (scheme, rest) = match uri.find(':') {
+            Some(i)
+                if uri[..i].starts_with(|c: char| c.is_ascii_alphabetic())
+                    && uri[..i]
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) =>
+            {
+                (Some(&uri[..i]), &uri[i + 1..])
+            }
+            _ => (None, uri),
+        };
+        let (authority, rest) = match rest.strip_prefix("//") {
+            Some(rest) => {
+                let end = rest.find(&['/', '?'][..]).unwrap_or(rest.len());
+                (Some(&rest[..end]), &rest[end..])
+            }
+            None => (None, rest),
+        };
+        let (path, query) = match rest.find('?') {
+            Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+            None => (rest, None),
+        };
+        UriRef {
+            scheme,
+            authority,
+            path,
+            query,
+        }
+    }
+}
+
+// Resolve `reference` against `base` as in RFC 3986 Section 5.2, giving
+// `None` if it's relative and there's no absolute base.
+fn resolve_reference(base: Option<&str>, reference: &str) -> Option<String> {
+    let reference = UriRef::parse(reference);
+    if reference.scheme.is_some() {
+        return Some(build_uri(
+            reference.scheme,
+            reference.authority,
+            &remove_dot_segments(reference.path),
+            reference.query,
+        ));
+    }
+
+    let base = UriRef::parse(base?);
+    base.scheme?;
+    let (authority, path, query) = if reference.authority.is_some() {
+        (
+            reference.authority,
+            remove_dot_segments(reference.path),
+            reference.query,
+        )
+    } else if reference.path.is_empty() {
+        (
+            base.authority,
+            base.path.to_string(),
+            reference.query.or(base.query),
+        )
+    } else if reference.path.starts_with('/') {
+        (
+            base.authority,
+            remove_dot_segments(reference.path),
+            reference.query,
+        )
+    } else {
+        let merged = if base.authority.is_some() && base.path.is_empty() {
+            format!("/{}", reference.path)
+        } else {
+            let directory = base.path.rfind('/').map_or("", |i| &base.path[..=i]);
+            format!("{}{}", directory, reference.path)
+        };
+        (
+            base.authority,
+            remove_dot_segments(&merged),
+            reference.query,
+        )
+    };
+    Some(build_uri(base.scheme, authority, &path, query))
+}
+
+fn build_uri(
+    scheme: Option<&str>,
+    authority: Option<&str>,
+    path: &str,
+    query: Option<&str>,
+) -> String {
+    let mut uri = String::new();
+    if let Some(scheme) = scheme {
+        uri.push_str(&scheme.to_ascii_lowercase());
+        uri.push(':');
+    }
+    if let Some(authority) = authority {
+        uri.push_str("//");
+        uri.push_str(authority);
+    }
+    uri.push_str(path);
+    if let Some(query) = query {
+        uri.push('?');
+        uri.push_str(query);
+    }
+    uri
+}
+
+// Remove the `.` and `..` segments of `path`, RFC 3986 Section 5.2.4.
+fn remove_dot_segments(path: &str) -> String {
+    let mut output: Vec<&str> = Vec::new();
+    let mut segments = path.split('/').peekable();
+    while let Some(segment) = segments.next() {
+        let last = segments.peek().is_none();
+        match segment {
+            "." => {}
+            ".." => {
+                if output.len() > 1 || output.first().is_some_and(|first| !first.is_empty()) {
+                    output.pop();
+                }
+            }
+            _ => output.push(segment),
+        }
+        // Dot segments at the end leave the path ending in a slash
+        if last && (segment == "." || segment == "..") {
+            output.push("");
+        }
+    }
+    output.join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_reference() {
+        let base = Some("http://a/b/c/d;p?q");
+        let cases = [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y#s", "http://a/b/c/g?y"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("g/../h", "http://a/b/c/h"),
+            ("HTTP://A/x/../y", "http://A/y"),
+        ];
+        for &(reference, expected) in cases.iter() {
+            assert_eq!(
+                resolve_reference(base, reference),
+                Some(expected.to_string()),
+                "{}",
+                reference
+            );
+        }
+        assert_eq!(resolve_reference(None, "images/a.png"), None);
+        assert_eq!(resolve_reference(Some("images/"), "a.png"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let message = MimeMessage::parse(
+            "Content-Type: multipart/related; boundary=b\r\n\
+             Content-Location: http://example.com/pages/\r\n\r\n\
+             --b\r\n\
+             Content-Type: text/html\r\n\
+             Content-Location: index.html\r\n\r\n\
+             <img src=\"images/logo.png\"><img src=\"cid:photo%40example.com\">\r\n\
+             --b\r\n\
+             Content-Type: image/png\r\n\
+             Content-Location: http://example.com/pages/images/\r\n \
+             logo.png\r\n\r\n\
+             logo\r\n\
+             --b\r\n\
+             Content-Type: image/jpeg\r\n\
+             Content-ID: <photo@example.com>\r\n\r\n\
+             photo\r\n\
+             --b\r\n\
+             Content-Type: text/css\r\n\
+             Content-Location: ../style.css\r\n\r\n\
+             css\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+        let html = &message.children[0];
+        let logo = &message.children[1];
+        let photo = &message.children[2];
+        let css = &message.children[3];
+        assert_eq!(html.content_location(), Some("index.html".to_string()));
+        assert_eq!(
+            logo.content_location(),
+            Some("http://example.com/pages/images/logo.png".to_string())
+        );
+
+        assert_eq!(message.resolve("images/logo.png"), Some(logo));
+        assert_eq!(message.resolve("./images/logo.png#top"), Some(logo));
+        assert_eq!(message.resolve("cid:photo%40example.com"), Some(photo));
+        assert_eq!(message.resolve("/style.css"), Some(css));
+        assert_eq!(message.resolve("images/other.png"), None);
+        assert_eq!(message.resolve("abc\u{e9}"), None);
+
+        let index = message.part_index();
+        assert_eq!(
+            index.base(html),
+            Some("http://example.com/pages/index.html")
+        );
+        assert_eq!(index.by_content_id("photo@example.com"), Some(photo));
+        assert_eq!(
+            index.resolve("http://example.com/style.css", None),
+            Some(css)
+        );
+
+        // Without a base, relative locations are matched as they are
+        let message = MimeMessage::parse(
+            "Content-Type: multipart/related; boundary=b\r\n\r\n\
+             --b\r\n\
+             Content-Type: text/html\r\n\r\n\
+             <img src=\"a.png\">\r\n\
+             --b\r\n\
+             Content-Type: image/png\r\n\
+             Content-Location: a.png\r\n\r\n\
+             png\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+        assert_eq!(message.resolve("a.png"), Some(&message.children[1]));
+        assert_eq!(message.resolve("b.png"), None);
+    }
+
+    #[test]
+    fn test_mhtml_builder() {
+        let archive = MhtmlBuilder::new(
+            "https://example.com/blog/post.html",
+            "<link href=\"../style.css\"><img src=\"my photo.jpg\">",
+        )
+        .resource("https://example.com/style.css", b"p { color: red }", None)
+        .resource(
+            "https://example.com/blog/my photo.jpg?size=large",
+            b"\xff\xd8\xff",
+            None,
+        )
+        .resource(
+            "https://example.com/blog/data",
+            b"{}",
+            Some(ContentType::new("application", "json")),
+        )
+        .boundary_prefix("=_mhtml_")
+        .build();
+        assert_eq!(archive.message_type, Some(MimeMultipartType::Related));
+        let content_type = archive.content_type();
+        assert_eq!(content_type.parameter("type"), Some("text/html"));
+        assert!(content_type.boundary().unwrap().starts_with("=_mhtml_"));
+
+        let message = MimeMessage::parse(&archive.as_string()).unwrap();
+        assert_eq!(message.children.len(), 4);
+        assert_eq!(message.message_type, Some(MimeMultipartType::Related));
+        assert_eq!(message.children[1].content_type().to_string(), "text/css");
+        let photo = &message.children[2];
+        assert_eq!(photo.content_type().to_string(), "image/jpeg");
+        assert_eq!(
+            photo.content_location(),
+            Some("https://example.com/blog/my%20photo.jpg?size=large".to_string())
+        );
+        let encoding = photo.headers.get("Content-Transfer-Encoding".to_string());
+        assert_eq!(encoding.unwrap().raw_value(), "base64");
+        assert_eq!(photo.decoded_body_bytes(), Some(b"\xff\xd8\xff".to_vec()));
+
+        assert_eq!(message.resolve("../style.css"), Some(&message.children[1]));
+        assert_eq!(message.resolve("my%20photo.jpg?size=large"), Some(photo));
+        assert_eq!(
+            message.children[3].content_type().to_string(),
+            "application/json"
+        );
+    }
+}
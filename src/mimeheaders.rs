@@ -1,16 +1,21 @@
 use super::header::{FromHeader, ToHeader};
 use super::results::{ParsingError, ParsingResult};
-use super::rfc2045::Rfc2045Parser;
-use super::rfc2047::decode_q_encoding;
+use super::rfc2045::{decode_quoted_printable, is_token_char, Base64BodyDecoder, Rfc2045Parser};
+use super::rfc822::Rfc822DateParser;
 
 use base64;
+use chrono::{DateTime, FixedOffset};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
 
 /// Content-Type string, major/minor as the first and second elements
 /// respectively.
 pub type MimeContentType = (String, String);
 
 /// Special header type for the Content-Type header.
+///
+/// See `ContentType` for a type which keeps the order of the parameters.
 pub struct MimeContentTypeHeader {
     /// The content type presented by this header
     pub content_type: MimeContentType,
@@ -31,7 +36,11 @@ impl FromHeader for MimeContentTypeHeader {
                 params,
             })
         } else {
-            Err(ParsingError::new(format!("Invalid mimetype: {}", value)))
+            Err(ParsingError::InvalidToken {
+                pos: 0,
+                kind: "mimetype".to_string(),
+                token: value,
+            })
         }
     }
 }
@@ -47,6 +56,358 @@ impl ToHeader for MimeContentTypeHeader {
     }
 }
 
+/// A media type and its parameters, as given by the Content-Type header.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ContentType {
+    /// The top-level type, such as "text", lowercased
+    pub ttype: String,
+    /// The subtype, such as "plain", lowercased
+    pub subtype: String,
+    /// The parameters in the order they appear, with lowercased names.
+    ///
+    /// Values split into RFC 2231 continuations are joined and decoded.
+    pub parameters: Vec<(String, String)>,
+}
+
+impl ContentType {
+    /// Make a content type of `ttype`/`subtype` without any parameters.
+    pub fn new(ttype: &str, subtype: &str) -> ContentType {
+        ContentType {
+            ttype: ttype.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            parameters: Vec::new(),
+        }
+    }
+
+    /// The value of the parameter `name`, if present.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Set the parameter `name` to `value`, replacing any value it had.
+    pub fn set_parameter(&mut self, name: &str, value: &str) {
+        match self
+            .parameters
+            .iter_mut()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        {
+            Some(param) => param.1 = value.to_string(),
+            None => self
+                .parameters
+                .push((name.to_ascii_lowercase(), value.to_string())),
+        }
+    }
+
+    /// The `charset` parameter, if present.
+    pub fn charset(&self) -> Option<&str> {
+        self.parameter("charset")
+    }
+
+    /// The `boundary` parameter, if present.
+    pub fn boundary(&self) -> Option<&str> {
+        self.parameter("boundary")
+    }
+}
+
+impl Default for ContentType {
+    /// The content type of a part without a Content-Type header, as
+    /// described in Section 5.2 of RFC 2045.
+    fn default() -> ContentType {
+        ContentType::new("text", "plain")
+    }
+}
+
+impl fmt::Display for ContentType {
+    /// Values which aren't tokens are quoted, or RFC 2231 encoded as UTF-8
+    /// if they aren't printable ASCII.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.ttype, self.subtype)?;
+        for (name, value) in self.parameters.iter() {
+            write_parameter(f, name, value)?;
+        }
+        Ok(())
+    }
+}
+
+// Write `; name=value`, quoting the value if it isn't a token, or RFC 2231
+// encoding it as UTF-8 if it isn't printable ASCII.
+fn write_parameter(f: &mut fmt::Formatter, name: &str, value: &str) -> fmt::Result {
+    if !value.is_empty() && value.chars().all(is_token_char) {
+        write!(f, "; {}={}", name, value)
+    } else if value.chars().all(|c| c == ' ' || c.is_ascii_graphic()) {
+        let escaped = value.replace('\\', "\\\\").replace('"', "\\\"");
+        write!(f, "; {}=\"{}\"", name, escaped)
+    } else {
+        write!(f, "; {}*=utf-8''", name)?;
+        for b in value.bytes() {
+            match b {
+                b'!' | b'#' | b'$' | b'&' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|'
+                | b'~' => write!(f, "{}", b as char)?,
+                _ if b.is_ascii_alphanumeric() => write!(f, "{}", b as char)?,
+                _ => write!(f, "%{:02X}", b)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromHeader for ContentType {
+    fn from_header(value: String) -> ParsingResult<ContentType> {
+        let mut parser = Rfc2045Parser::new(&value[..]);
+        let mime_type = parser.consume_value();
+        let parameters = parser.consume_parameters();
+
+        let mut parts = mime_type.trim().splitn(2, '/');
+        match (parts.next(), parts.next()) {
+            (Some(ttype), Some(subtype))
+                if !ttype.trim().is_empty() && !subtype.trim().is_empty() =>
+            {
+                let mut content_type = ContentType::new(ttype.trim(), subtype.trim());
+                content_type.parameters = parameters;
+                Ok(content_type)
+            }
+            _ => Err(ParsingError::InvalidToken {
+                pos: 0,
+                kind: "mimetype".to_string(),
+                token: mime_type,
+            }),
+        }
+    }
+}
+
+impl ToHeader for ContentType {
+    fn to_header(value: ContentType) -> ParsingResult<String> {
+        Ok(value.to_string())
+    }
+}
+
+// The media types of common file extensions
+const MIME_TYPES: &[(&str, &str, &str)] = &[
+    ("7z", "application", "x-7z-compressed"),
+    ("avi", "video", "x-msvideo"),
+    ("bmp", "image", "bmp"),
+    ("css", "text", "css"),
+    ("csv", "text", "csv"),
+    ("doc", "application", "msword"),
+    (
+        "docx",
+        "application",
+        "vnd.openxmlformats-officedocument.wordprocessingml.document",
+    ),
+    ("eml", "message", "rfc822"),
+    ("flac", "audio", "flac"),
+    ("gif", "image", "gif"),
+    ("gz", "application", "gzip"),
+    ("heic", "image", "heic"),
+    ("htm", "text", "html"),
+    ("html", "text", "html"),
+    ("ics", "text", "calendar"),
+    ("jpeg", "image", "jpeg"),
+    ("jpg", "image", "jpeg"),
+    ("js", "text", "javascript"),
+    ("json", "application", "json"),
+    ("m4a", "audio", "mp4"),
+    ("md", "text", "markdown"),
+    ("mov", "video", "quicktime"),
+    ("mp3", "audio", "mpeg"),
+    ("mp4", "video", "mp4"),
+    ("odp", "application", "vnd.oasis.opendocument.presentation"),
+    ("ods", "application", "vnd.oasis.opendocument.spreadsheet"),
+    ("odt", "application", "vnd.oasis.opendocument.text"),
+    ("ogg", "audio", "ogg"),
+    ("pdf", "application", "pdf"),
+    ("png", "image", "png"),
+    ("ppt", "application", "vnd.ms-powerpoint"),
+    (
+        "pptx",
+        "application",
+        "vnd.openxmlformats-officedocument.presentationml.presentation",
+    ),
+    ("rtf", "application", "rtf"),
+    ("svg", "image", "svg+xml"),
+    ("tar", "application", "x-tar"),
+    ("tif", "image", "tiff"),
+    ("tiff", "image", "tiff"),
+    ("txt", "text", "plain"),
+    ("vcf", "text", "vcard"),
+    ("wav", "audio", "wav"),
+    ("webm", "video", "webm"),
+    ("webp", "image", "webp"),
+    ("xls", "application", "vnd.ms-excel"),
+    (
+        "xlsx",
+        "application",
+        "vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    ),
+    ("xml", "application", "xml"),
+    ("zip", "application", "zip"),
+];
+
+lazy_static! {
+    static ref CUSTOM_MIME_TYPES: RwLock<HashMap<String, ContentType>> =
+        RwLock::new(HashMap::new());
+}
+
+/// Use `content_type` for files with the given extension when guessing
+/// their media type, in place of any built in type.
+pub fn register_mime_type(extension: &str, content_type: ContentType) {
+    CUSTOM_MIME_TYPES
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(extension.to_ascii_lowercase(), content_type);
+}
+
+/// Guess the media type of a file from the extension of its name, falling
+/// back to application/octet-stream for unknown extensions.
+pub fn guess_mime_type(filename: &str) -> ContentType {
+    let extension = match filename.rfind('.') {
+        Some(i) => filename[i + 1..].to_ascii_lowercase(),
+        None => return ContentType::new("application", "octet-stream"),
+    };
+    let custom = CUSTOM_MIME_TYPES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&extension)
+        .cloned();
+    custom.unwrap_or_else(|| {
+        match MIME_TYPES.binary_search_by_key(&&extension[..], |&(ext, _, _)| ext) {
+            Ok(i) => ContentType::new(MIME_TYPES[i].1, MIME_TYPES[i].2),
+            Err(_) => ContentType::new("application", "octet-stream"),
+        }
+    })
+}
+
+/// The disposition type of a Content-Disposition header.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum DispositionKind {
+    /// The part should be displayed along with the message.
+    Inline,
+    /// The part is separate from the message, and shouldn't be displayed
+    /// unless the user asks for it.
+    Attachment,
+    /// Any other disposition type, lowercased.
+    Extension(String),
+}
+
+impl fmt::Display for DispositionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DispositionKind::Inline => write!(f, "inline"),
+            DispositionKind::Attachment => write!(f, "attachment"),
+            DispositionKind::Extension(ref kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+/// The Content-Disposition header, as defined by RFC 2183.
+///
+/// Parameters other than those below are dropped.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ContentDisposition {
+    /// How the part should be presented
+    pub kind: DispositionKind,
+    /// The suggested name of the file to store the part in
+    pub filename: Option<String>,
+    /// The approximate size of the part in bytes
+    pub size: Option<usize>,
+    /// When the file was created
+    pub creation_date: Option<DateTime<FixedOffset>>,
+    /// When the file was last modified
+    pub modification_date: Option<DateTime<FixedOffset>>,
+}
+
+impl ContentDisposition {
+    /// Make a disposition of `kind` without any parameters.
+    pub fn new(kind: DispositionKind) -> ContentDisposition {
+        ContentDisposition {
+            kind,
+            filename: None,
+            size: None,
+            creation_date: None,
+            modification_date: None,
+        }
+    }
+
+    /// Make an attachment disposition with the given filename.
+    pub fn attachment(filename: &str) -> ContentDisposition {
+        let mut disposition = ContentDisposition::new(DispositionKind::Attachment);
+        disposition.filename = Some(filename.to_string());
+        disposition
+    }
+
+    /// Whether the kind of this disposition is attachment.
+    pub fn is_attachment(&self) -> bool {
+        self.kind == DispositionKind::Attachment
+    }
+}
+
+impl fmt::Display for ContentDisposition {
+    /// The filename is RFC 2231 encoded if it isn't printable ASCII.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(ref filename) = self.filename {
+            write_parameter(f, "filename", filename)?;
+        }
+        if let Some(size) = self.size {
+            write!(f, "; size={}", size)?;
+        }
+        if let Some(date) = self.creation_date {
+            write!(f, "; creation-date=\"{}\"", date.to_rfc2822())?;
+        }
+        if let Some(date) = self.modification_date {
+            write!(f, "; modification-date=\"{}\"", date.to_rfc2822())?;
+        }
+        Ok(())
+    }
+}
+
+impl FromHeader for ContentDisposition {
+    /// Invalid sizes and dates are ignored.
+    fn from_header(value: String) -> ParsingResult<ContentDisposition> {
+        let mut parser = Rfc2045Parser::new(&value[..]);
+        let kind = parser.consume_value().trim().to_ascii_lowercase();
+        let kind = match &kind[..] {
+            "inline" => DispositionKind::Inline,
+            "attachment" => DispositionKind::Attachment,
+            _ if !kind.is_empty() && kind.chars().all(is_token_char) => {
+                DispositionKind::Extension(kind)
+            }
+            _ => {
+                return Err(ParsingError::InvalidToken {
+                    pos: 0,
+                    kind: "disposition type".to_string(),
+                    token: kind,
+                })
+            }
+        };
+
+        let mut disposition = ContentDisposition::new(kind);
+        for (name, value) in parser.consume_parameters() {
+            match &name[..] {
+                "filename" => disposition.filename = Some(value),
+                "size" => disposition.size = value.trim().parse().ok(),
+                "creation-date" => disposition.creation_date = parse_date(&value),
+                "modification-date" => disposition.modification_date = parse_date(&value),
+                _ => {}
+            }
+        }
+        Ok(disposition)
+    }
+}
+
+fn parse_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    Rfc822DateParser::new(value).consume_datetime().ok()
+}
+
+impl ToHeader for ContentDisposition {
+    fn to_header(value: ContentDisposition) -> ParsingResult<String> {
+        Ok(value.to_string())
+    }
+}
+
 /// Special header type for the Content-Transfer-Encoding header.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum MimeContentTransferEncoding {
@@ -62,17 +423,6 @@ pub enum MimeContentTransferEncoding {
     Base64,
 }
 
-// Util function for MimeContentTransferEncoding::decode
-fn byte_in_base64_alphabet(b: char) -> bool {
-    match b {
-        'A'..='Z' => true,
-        'a'..='z' => true,
-        '0'..='9' => true,
-        '+' | '/' | '=' => true,
-        _ => false,
-    }
-}
-
 impl MimeContentTransferEncoding {
     /// Decode the input string with this transfer encoding.
     ///
@@ -80,20 +430,125 @@ impl MimeContentTransferEncoding {
     /// transfer encoding is the Identity encoding.
     /// [unstable]
     pub fn decode(self, input: &str) -> Option<Vec<u8>> {
+        self.decode_bytes(input.as_bytes())
+    }
+
+    /// Decode `input` with this transfer encoding, as `decode` does, for a
+    /// body which may not be valid UTF-8.
+    pub fn decode_bytes(self, input: &[u8]) -> Option<Vec<u8>> {
         match self {
-            MimeContentTransferEncoding::Identity => Some(input.as_bytes().to_vec()),
-            MimeContentTransferEncoding::QuotedPrintable => decode_q_encoding(&input[..]).ok(),
+            MimeContentTransferEncoding::Identity => Some(input.to_vec()),
+            MimeContentTransferEncoding::QuotedPrintable => decode_quoted_printable(input).ok(),
             MimeContentTransferEncoding::Base64 => {
-                // As per RFC 2045 section 6.8, all bytes not part of the Base64 Alphabet Table are
-                // to be ignored.
-                let mut buf = input.to_owned();
-                buf.retain(byte_in_base64_alphabet);
-                base64::decode(&buf).ok()
+                let mut decoder = Base64BodyDecoder::new();
+                let mut output = Vec::with_capacity(input.len() / 4 * 3);
+                decoder.decode(input, &mut output);
+                decoder.finish().ok().map(|()| output)
+            }
+        }
+    }
+
+    /// Choose the transfer encoding best suited to `content`.
+    ///
+    /// Content which is already 7bit, with lines no longer than RFC 5322
+    /// allows, needs no encoding. Binary content, or content where encoding
+    /// each 8-bit byte would make it larger than base64 would, is base64
+    /// encoded, and anything else is quoted-printable.
+    pub fn choose(content: &[u8]) -> MimeContentTransferEncoding {
+        let mut unsafe_bytes = 0;
+        let mut line_len = 0;
+        let mut max_line_len = 0;
+        for (i, &b) in content.iter().enumerate() {
+            match b {
+                b'\n' => line_len = 0,
+                // NUL and bare CRs can't be carried by quoted-printable
+                b'\0' => return MimeContentTransferEncoding::Base64,
+                b'\r' if content.get(i + 1) != Some(&b'\n') => {
+                    return MimeContentTransferEncoding::Base64
+                }
+                b'\r' => {}
+                b'\t' | b' '..=b'~' => line_len += 1,
+                _ => {
+                    unsafe_bytes += 1;
+                    line_len += 1;
+                }
+            }
+            max_line_len = max_line_len.max(line_len);
+        }
+
+        if unsafe_bytes == 0 && max_line_len <= MAX_LINE_LENGTH {
+            MimeContentTransferEncoding::Identity
+        } else if unsafe_bytes * 6 > content.len() {
+            // Quoted-printable takes three bytes for each of these, where
+            // base64 takes four for every three of the content
+            MimeContentTransferEncoding::Base64
+        } else {
+            MimeContentTransferEncoding::QuotedPrintable
+        }
+    }
+
+    /// Encode the input bytes with this transfer encoding.
+    ///
+    /// Any CRLF line breaks in the input are kept as line breaks in the
+    /// quoted-printable output, and base64 output is wrapped into lines of
+    /// 76 characters. Invalid UTF-8 is replaced when using the Identity
+    /// encoding.
+    pub fn encode(self, input: &[u8]) -> String {
+        match self {
+            MimeContentTransferEncoding::Identity => String::from_utf8_lossy(input).into_owned(),
+            MimeContentTransferEncoding::QuotedPrintable => encode_quoted_printable(input),
+            MimeContentTransferEncoding::Base64 => {
+                let encoded = base64::encode(input);
+                let lines: Vec<&str> = encoded
+                    .as_bytes()
+                    .chunks(ENCODED_LINE_LENGTH)
+                    .map(|chunk| std::str::from_utf8(chunk).unwrap())
+                    .collect();
+                lines.join("\r\n")
             }
         }
     }
 }
 
+/// The maximum length of a line of transfer encoded content, from RFC 2045
+const ENCODED_LINE_LENGTH: usize = 76;
+/// The maximum length of a line of any content, from RFC 5322
+const MAX_LINE_LENGTH: usize = 998;
+
+// Util function for MimeContentTransferEncoding::encode
+fn encode_quoted_printable(input: &[u8]) -> String {
+    let mut result = String::new();
+    for (i, line) in input.split(|&b| b == b'\n').enumerate() {
+        if i > 0 {
+            result.push_str("\r\n");
+        }
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let mut line_len = 0;
+        for (j, &b) in line.iter().enumerate() {
+            let is_last = j == line.len() - 1;
+            let encoded = match b {
+                b'!'..=b'<' | b'>'..=b'~' => (b as char).to_string(),
+                // Whitespace at the end of a line would be lost in transport
+                b' ' | b'\t' if !is_last => (b as char).to_string(),
+                _ => format!("={:02X}", b),
+            };
+            // Leave room for the soft line break
+            let limit = if is_last {
+                ENCODED_LINE_LENGTH
+            } else {
+                ENCODED_LINE_LENGTH - 1
+            };
+            if line_len + encoded.len() > limit {
+                result.push_str("=\r\n");
+                line_len = 0;
+            }
+            line_len += encoded.len();
+            result.push_str(&encoded[..]);
+        }
+    }
+    result
+}
+
 impl FromHeader for MimeContentTransferEncoding {
     fn from_header(value: String) -> ParsingResult<MimeContentTransferEncoding> {
         // XXX: Used to be into_ascii_lowercase, which is more memory-efficient. Unfortunately that
@@ -103,7 +558,9 @@ impl FromHeader for MimeContentTransferEncoding {
             "7bit" | "8bit" | "binary" => Ok(MimeContentTransferEncoding::Identity),
             "quoted-printable" => Ok(MimeContentTransferEncoding::QuotedPrintable),
             "base64" => Ok(MimeContentTransferEncoding::Base64),
-            x => Err(ParsingError::new(format!("Invalid encoding: {}", x))),
+            x => Err(ParsingError::InvalidEncoding {
+                encoding: x.to_string(),
+            }),
         }
     }
 }
@@ -185,6 +642,93 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_content_type() {
+        let header = Header::new(
+            "Content-Type".to_string(),
+            "Text/HTML; Charset=\"UTF-8\"; title*0*=utf-8'de'Gr%C3%BC; title*1=\"\\\"sse\\\"\"; \
+             name=\"a b.html\""
+                .to_string(),
+        );
+        let ct: ContentType = header.get_value().unwrap();
+        assert_eq!(ct.ttype, "text");
+        assert_eq!(ct.subtype, "html");
+        assert_eq!(ct.charset(), Some("UTF-8"));
+        assert_eq!(ct.parameter("title"), Some("Grü\"sse\""));
+        assert_eq!(ct.boundary(), None);
+        assert_eq!(
+            ct.to_string(),
+            "text/html; charset=UTF-8; title*=utf-8''Gr%C3%BC%22sse%22; name=\"a b.html\""
+        );
+        let reparsed: ContentType = ContentType::from_header(ct.to_string()).unwrap();
+        assert_eq!(reparsed, ct);
+
+        let mut ct = ContentType::new("multipart", "mixed");
+        ct.set_parameter("Boundary", "a:b");
+        ct.set_parameter("boundary", "=_b");
+        assert_eq!(ct.to_string(), "multipart/mixed; boundary=\"=_b\"");
+
+        assert!(ContentType::from_header("text".to_string()).is_err());
+        assert!(ContentType::from_header("text/; charset=utf-8".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_content_disposition() {
+        let header = Header::new(
+            "Content-Disposition".to_string(),
+            "Attachment; filename*=UTF-8''na%C3%AFve.txt; size=120; \
+             creation-date=\"Wed, 12 Feb 1997 16:29:51 -0500\"; \
+             modification-date=\"yesterday\"; foo=bar"
+                .to_string(),
+        );
+        let disposition: ContentDisposition = header.get_value().unwrap();
+        assert!(disposition.is_attachment());
+        assert_eq!(disposition.filename, Some("naïve.txt".to_string()));
+        assert_eq!(disposition.size, Some(120));
+        assert_eq!(
+            disposition.creation_date,
+            DateTime::parse_from_rfc3339("1997-02-12T16:29:51-05:00").ok()
+        );
+        assert_eq!(disposition.modification_date, None);
+        assert_eq!(
+            disposition.to_string(),
+            "attachment; filename*=utf-8''na%C3%AFve.txt; size=120; \
+             creation-date=\"Wed, 12 Feb 1997 16:29:51 -0500\""
+        );
+        let reparsed = ContentDisposition::from_header(disposition.to_string()).unwrap();
+        assert_eq!(reparsed, disposition);
+
+        assert_eq!(
+            ContentDisposition::attachment("a b.pdf").to_string(),
+            "attachment; filename=\"a b.pdf\""
+        );
+        let inline = ContentDisposition::from_header("inline".to_string()).unwrap();
+        assert_eq!(inline, ContentDisposition::new(DispositionKind::Inline));
+        let other = ContentDisposition::from_header("X-Custom; filename=a".to_string()).unwrap();
+        assert_eq!(other.kind, DispositionKind::Extension("x-custom".to_string()));
+        assert!(ContentDisposition::from_header("".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_guess_mime_type() {
+        let guess = |filename| guess_mime_type(filename).to_string();
+        assert_eq!(guess("report.PDF"), "application/pdf");
+        assert_eq!(guess("photo.final.png"), "image/png");
+        assert_eq!(guess("data.csv"), "text/csv");
+        assert_eq!(guess("archive.tar.gz"), "application/gzip");
+        assert_eq!(guess("README"), "application/octet-stream");
+        assert_eq!(guess("notes."), "application/octet-stream");
+        assert_eq!(guess("model.x-test-stl"), "application/octet-stream");
+
+        let mut stl = ContentType::new("model", "stl");
+        stl.set_parameter("format", "ascii");
+        register_mime_type("X-Test-STL", stl);
+        assert_eq!(guess("model.x-test-stl"), "model/stl; format=ascii");
+
+        // The table must stay sorted to be searched
+        assert!(MIME_TYPES.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
     #[test]
     fn test_content_transfer_parse() {
         let tests = vec![
@@ -254,4 +798,49 @@ mod tests {
             assert_eq!(result, test.output);
         }
     }
+
+    #[test]
+    fn test_content_transfer_encode() {
+        let qp = MimeContentTransferEncoding::QuotedPrintable;
+        assert_eq!(
+            qp.encode("Grüße = hi \r\nbye\t".as_bytes()),
+            "Gr=C3=BC=C3=9Fe =3D hi=20\r\nbye=09"
+        );
+        let long = "a".repeat(100);
+        assert_eq!(
+            qp.encode(long.as_bytes()),
+            format!("{}=\r\n{}", "a".repeat(75), "a".repeat(25))
+        );
+
+        let b64 = MimeContentTransferEncoding::Base64;
+        let data: Vec<u8> = (0..100).collect();
+        let encoded = b64.encode(&data);
+        assert!(encoded.split("\r\n").all(|line| line.len() <= 76));
+        assert_eq!(b64.decode(&encoded), Some(data));
+
+        for input in ["foo=\r\nbar", "Grüße\r\n\r\n", "  \r\n"].iter() {
+            let encoded = qp.encode(input.as_bytes());
+            assert_eq!(qp.decode(&encoded), Some(input.as_bytes().to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_content_transfer_choose() {
+        let choose = |content: &[u8]| MimeContentTransferEncoding::choose(content);
+        assert_eq!(choose(b"Hello\r\nWorld"), MimeContentTransferEncoding::Identity);
+        assert_eq!(
+            choose("a".repeat(1000).as_bytes()),
+            MimeContentTransferEncoding::QuotedPrintable
+        );
+        assert_eq!(
+            choose("Grüße aus Köln, wir sehen uns am Wochenende".as_bytes()),
+            MimeContentTransferEncoding::QuotedPrintable
+        );
+        assert_eq!(
+            choose("こんにちは".as_bytes()),
+            MimeContentTransferEncoding::Base64
+        );
+        assert_eq!(choose(b"GIF89a\0\x01"), MimeContentTransferEncoding::Base64);
+        assert_eq!(choose(b"bare\rCR"), MimeContentTransferEncoding::Base64);
+    }
 }
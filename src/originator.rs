@@ -0,0 +1,105 @@
+//! Module for telling the authors of a message apart from whoever sent it
+//! on their behalf, as RFC 5322 Section 3.6.2 does with the `From` and
+//! `Sender` headers.
+//!
+//! A message may have several authors, in which case it must also give a
+//! single `Sender`, which `MessageBuilder::build` checks. The authors are
+//! what DMARC checks the alignment of, while the sender is the mailbox
+//! which actually transmitted the message.
+use super::address::Mailbox;
+use super::builder::mailboxes;
+use super::message::MimeMessage;
+
+impl MimeMessage {
+    /// The authors of this message, from its `From` header.
+    pub fn authors(&self) -> Vec<Mailbox> {
+        mailboxes(self, "From")
+    }
+
+    /// The mailbox given by the `Sender` header, if there's a valid one.
+    pub fn sender(&self) -> Option<Mailbox> {
+        self.headers.get_value("Sender".to_string()).ok()
+    }
+
+    /// The mailbox responsible for transmitting this message: its `Sender`,
+    /// or when it has none, its only author.
+    ///
+    /// This is None if there's neither, including when there are several
+    /// authors but no `Sender`, which RFC 5322 doesn't allow.
+    pub fn effective_sender(&self) -> Option<Mailbox> {
+        if let Some(sender) = self.sender() {
+            return Some(sender);
+        }
+        let mut authors = self.authors();
+        if authors.len() == 1 {
+            authors.pop()
+        } else {
+            None
+        }
+    }
+
+    /// The domain of the authors of this message, lowercased, which is
+    /// the domain DMARC checks the alignment of SPF and DKIM with.
+    ///
+    /// This is None if there are no authors, if any of them has no
+    /// domain, or if they don't all share the same domain.
+    pub fn author_domain(&self) -> Option<String> {
+        let mut domains = self.authors().into_iter().map(|author| {
+            let (_, domain) = author.address.rsplit_once('@')?;
+            Some(domain.to_ascii_lowercase()).filter(|domain| !domain.is_empty())
+        });
+        let domain = domains.next()??;
+        if domains.any(|other| other.as_ref() != Some(&domain)) {
+            return None;
+        }
+        Some(domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::MessageBuilder;
+
+    #[test]
+    fn test_effective_sender() {
+        let message = MimeMessage::parse("From: joe@example.org\r\n\r\n").unwrap();
+        assert_eq!(message.sender(), None);
+        assert_eq!(
+            message.effective_sender(),
+            Some(Mailbox::new("joe@example.org".to_string()))
+        );
+
+        let message = MimeMessage::parse(
+            "From: joe@example.org, Anna <anna@EXAMPLE.org>\r\n\
+             Sender: secretary@example.net\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(message.authors().len(), 2);
+        assert_eq!(
+            message.effective_sender(),
+            Some(Mailbox::new("secretary@example.net".to_string()))
+        );
+        assert_eq!(message.author_domain(), Some("example.org".to_string()));
+
+        let message =
+            MimeMessage::parse("From: joe@example.org, anna@example.net\r\n\r\n").unwrap();
+        assert_eq!(message.effective_sender(), None);
+        assert_eq!(message.author_domain(), None);
+
+        let input = "From: \"joe@example.net\"@Example.org\r\n\r\n";
+        let message = MimeMessage::parse(input).unwrap();
+        assert_eq!(message.author_domain(), Some("example.org".to_string()));
+    }
+
+    #[test]
+    fn test_build_requires_sender() {
+        let builder = MessageBuilder::new()
+            .from("joe@example.org")
+            .from("anna@example.org");
+        assert!(builder.clone().build().is_err());
+        let message = builder.sender("joe@example.org").build().unwrap();
+        assert_eq!(message.authors().len(), 2);
+        assert!(message.effective_sender().is_some());
+    }
+}
@@ -0,0 +1,636 @@
+//! Module for fitting messages into a size limit, and for splitting them
+//! into `message/partial` fragments, RFC 2046 Section 5.2.2.
+//!
+//! `fit_to_size` first tries to make a message smaller by giving each part
+//! the most compact transfer encoding for its content. If that isn't
+//! enough, the message is split into fragments which each fit, to be put
+//! back together by a `Reassembler` on the receiving side.
+use std::collections::{BTreeMap, HashMap};
+
+use super::environment;
+use super::header::{Header, HeaderMap};
+use super::message::MimeMessage;
+use super::messageid::MessageId;
+use super::mimeheaders::{ContentType, MimeContentTransferEncoding};
+use super::results::{ParsingError, ParsingResult};
+
+// The headers which go in the enclosed message of the first fragment, and
+// are taken from it when reassembling, Section 5.2.2.2. All others go in
+// the header of the fragment itself.
+fn is_enclosed(name: &str) -> bool {
+    let prefix = name.get(..8).unwrap_or("");
+    prefix.eq_ignore_ascii_case("Content-")
+        || ["Subject", "Message-ID", "Encrypted", "MIME-Version"]
+            .iter()
+            .any(|enclosed| enclosed.eq_ignore_ascii_case(name))
+}
+
+/// Fit `message` into `max_size` bytes, as written by `write_to`.
+///
+/// Gives the message as it is if it fits, or with its parts re-encoded by
+/// `optimize_encodings` if that makes it fit. Otherwise it's split into
+/// `message/partial` fragments by `split`.
+pub fn fit_to_size(message: &MimeMessage, max_size: usize) -> ParsingResult<Vec<MimeMessage>> {
+    if message.encoded_len_estimate() <= max_size {
+        return Ok(vec![message.clone()]);
+    }
+    let mut optimized = message.clone();
+    optimize_encodings(&mut optimized);
+    if optimized.encoded_len_estimate() <= max_size {
+        return Ok(vec![optimized]);
+    }
+    split(&optimized, max_size)
+}
+
+/// Re-encode each part of `message` whose content would be smaller in the
+/// transfer encoding `MimeContentTransferEncoding::choose` picks for it,
+/// such as text which was needlessly base64 encoded.
+///
+/// Parts which can't be decoded, and multipart parts, are left as they are.
+pub fn optimize_encodings(message: &mut MimeMessage) {
+    if message.message_type.is_some() {
+        for child in message.children.iter_mut() {
+            optimize_encodings(child);
+        }
+        return;
+    }
+    let content = match message.decoded_body() {
+        Some(content) => content.into_owned(),
+        None => return,
+    };
+    let encoding = MimeContentTransferEncoding::choose(&content[..]);
+    if encoding.encode(&content[..]).len() < message.body.len() {
+        message.set_body_bytes_with_encoding(&content[..], encoding);
+    }
+}
+
+// Give each part of `message` with 8-bit content a transfer encoding which
+// makes it 7-bit, as the fragments of a message must be.
+fn make_7bit(message: &mut MimeMessage) {
+    for child in message.children.iter_mut() {
+        make_7bit(child);
+    }
+    if message.message_type.is_none() && !message.body.is_ascii() {
+        if let Some(content) = message.decoded_body().map(|c| c.into_owned()) {
+            message.set_body_bytes(&content[..]);
+        }
+    }
+}
+
+/// Split `message` into `message/partial` fragments of at most `max_size`
+/// bytes each, as written by `write_to`.
+///
+/// Each fragment has the headers of the message other than its Subject,
+/// its Message-ID and its `Content-` headers, which go in the first
+/// fragment's body, along with a Subject numbering the fragments. Parts
+/// with 8-bit content are re-encoded first. The fragments are split
+/// between lines, so fails if `max_size` leaves no room for a line of the
+/// message along with the headers.
+pub fn split(message: &MimeMessage, max_size: usize) -> ParsingResult<Vec<MimeMessage>> {
+    let mut enclosed = message.clone();
+    make_7bit(&mut enclosed);
+    enclosed.headers.retain(|header| is_enclosed(&header.name));
+    let enclosed = enclosed.as_string();
+
+    let mut outer = message.headers.clone();
+    outer.retain(|header| !is_enclosed(&header.name));
+    let subject = message
+        .headers
+        .get("Subject".to_string())
+        .map(|header| header.raw_value().to_string());
+    let id: Option<MessageId> = message.headers.get_value("Message-ID".to_string()).ok();
+    let id = match id {
+        Some(id) => id.id().to_string(),
+        None => format!("{}@partial", environment::random_alphanumeric(24)),
+    };
+
+    // The size of a fragment without any body, allowing for the numbers
+    let digits = enclosed.len().to_string().len();
+    let number = "9".repeat(digits).parse().unwrap_or(usize::MAX);
+    let overhead = fragment(&outer, subject.as_deref(), &id, number, number, "")
+        .as_string()
+        .len();
+    let room = max_size.checked_sub(overhead).ok_or_else(|| {
+        ParsingError::new(format!("{} bytes is too small to split into", max_size))
+    })?;
+
+    // Each chunk ends with a line break, left for writing the fragment to add
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    for line in enclosed.split_inclusive('\n') {
+        if chunk.len() + line.len() > room && !chunk.is_empty() {
+            chunks.push(without_line_break(&chunk).to_string());
+            chunk.clear();
+        }
+        if line.len() > room {
+            return Err(ParsingError::new(format!(
+                "{} bytes is too small to split into",
+                max_size
+            )));
+        }
+        chunk.push_str(line);
+    }
+    if !chunk.is_empty() {
+        chunks.push(without_line_break(&chunk).to_string());
+    }
+
+    let total = chunks.len();
+    Ok(chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| fragment(&outer, subject.as_deref(), &id, i + 1, total, chunk))
+        .collect())
+}
+
+// `chunk` without the line break it ends with.
+fn without_line_break(chunk: &str) -> &str {
+    chunk
+        .strip_suffix("\r\n")
+        .or_else(|| chunk.strip_suffix('\n'))
+        .unwrap_or(chunk)
+}
+
+// The fragment `number` of `total` with the id `id`.
+fn fragment(
+    outer: &HeaderMap,
+    subject: Option<&str>,
+    id: &str,
+    number: usize,
+    total: usize,
+    chunk: &str,
+) -> MimeMessage {
+    let mut fragment = MimeMessage::new(chunk.to_string());
+    fragment.headers = outer.clone();
+    let subject = match subject {
+        Some(subject) => format!("{} ({}/{})", subject, number, total),
+        None => format!("({}/{})", number, total),
+    };
+    fragment
+        .headers
+        .insert(Header::new("Subject".to_string(), subject));
+    fragment
+        .headers
+        .insert(Header::new("MIME-Version".to_string(), "1.0".to_string()));
+    let mut content_type = ContentType::new("message", "partial");
+    content_type.set_parameter("id", id);
+    content_type.set_parameter("number", &number.to_string());
+    content_type.set_parameter("total", &total.to_string());
+    fragment.headers.insert(Header::new(
+        "Content-Type".to_string(),
+        content_type.to_string(),
+    ));
+    fragment
+}
+
+/// The most fragments a message may be split into for it to be put back
+/// together, so that a fragment giving a huge `total` or `number` can't make
+/// the reassembler hold on to it, or count up to it.
+pub const MAX_FRAGMENTS: usize = 10_000;
+
+/// The most messages a `Reassembler` waits for the rest of the fragments of
+/// by default.
+pub const MAX_PENDING: usize = 1_000;
+
+/// The parameters of a `message/partial` part, telling which fragment of
+/// which message it is.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PartialInfo {
+    /// The id shared by all of the fragments of the message
+    pub id: String,
+    /// The number of this fragment, counting from 1
+    pub number: usize,
+    /// The number of fragments, which must be given by at least the last
+    pub total: Option<usize>,
+}
+
+impl MimeMessage {
+    /// The parameters of this part if it's a `message/partial` fragment, with
+    /// an `id` and a `number` of at least 1.
+    pub fn partial_info(&self) -> Option<PartialInfo> {
+        let content_type = self.content_type();
+        if (&content_type.ttype[..], &content_type.subtype[..]) != ("message", "partial") {
+            return None;
+        }
+        let number = content_type.parameter("number")?.parse().ok()?;
+        if number == 0 {
+            return None;
+        }
+        Some(PartialInfo {
+            id: content_type.parameter("id")?.to_string(),
+            number,
+            total: content_type
+                .parameter("total")
+                .and_then(|total| total.parse().ok()),
+        })
+    }
+}
+
+/// Put the `message/partial` fragments `parts` back together into the
+/// message they were split from, whatever order they're in.
+///
+/// Fails if any of `parts` isn't a fragment of the same message as the
+/// others, if none of them gives the number of fragments, or if any are
+/// missing, naming the ones which are.
+pub fn reassemble(parts: &[MimeMessage]) -> ParsingResult<MimeMessage> {
+    let mut fragments = Fragments::default();
+    let mut id = None;
+    for part in parts.iter() {
+        let info = part
+            .partial_info()
+            .ok_or_else(|| ParsingError::new("Not a message/partial fragment".to_string()))?;
+        if id.get_or_insert_with(|| info.id.clone()) != &info.id {
+            return Err(ParsingError::new(format!(
+                "Fragments of different messages: {} and {}",
+                id.unwrap_or_default(),
+                info.id
+            )));
+        }
+        fragments.add(&info, part)?;
+    }
+    let id = id.ok_or_else(|| ParsingError::new("No fragments".to_string()))?;
+    if fragments.is_complete() {
+        return fragments.assemble();
+    }
+    match fragments.missing() {
+        Some(missing) => {
+            let missing: Vec<String> = missing.iter().map(|n| n.to_string()).collect();
+            Err(ParsingError::new(format!(
+                "Missing fragments {} of {}",
+                missing.join(", "),
+                id
+            )))
+        }
+        None => Err(ParsingError::new(format!(
+            "Number of fragments of {} unknown",
+            id
+        ))),
+    }
+}
+
+/// Puts `message/partial` fragments back together into the messages they
+/// were split from, as they arrive in any order.
+#[derive(Debug, Clone)]
+pub struct Reassembler {
+    // The fragments received of each message, by id
+    pending: HashMap<String, Fragments>,
+    max_pending: usize,
+}
+
+impl Default for Reassembler {
+    fn default() -> Reassembler {
+        Reassembler::with_max_pending(MAX_PENDING)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Fragments {
+    total: Option<usize>,
+    // The header of the first fragment
+    first: Option<HeaderMap>,
+    bodies: BTreeMap<usize, String>,
+}
+
+impl Fragments {
+    // Add the fragment `part`, checking that it agrees with those already
+    // added.
+    fn add(&mut self, info: &PartialInfo, part: &MimeMessage) -> ParsingResult<()> {
+        if info.number > MAX_FRAGMENTS || info.total.is_some_and(|t| t > MAX_FRAGMENTS) {
+            return Err(ParsingError::new(format!(
+                "More than {} fragments of {}",
+                MAX_FRAGMENTS, info.id
+            )));
+        }
+        if let (Some(total), Some(t)) = (info.total, self.total) {
+            if total != t {
+                return Err(ParsingError::new(format!(
+                    "Conflicting numbers of fragments of {}",
+                    info.id
+                )));
+            }
+        }
+        // Fragments added before the total was known may be past it too
+        let total = info.total.or(self.total);
+        let last = self.bodies.keys().next_back().copied().unwrap_or(0);
+        if total.is_some_and(|total| info.number.max(last) > total) {
+            return Err(ParsingError::new(format!(
+                "Fragment {} of {} is past the last",
+                info.number.max(last),
+                info.id
+            )));
+        }
+        match self.bodies.get(&info.number) {
+            Some(body) if body[..] != part.body[..] => {
+                return Err(ParsingError::new(format!(
+                    "Conflicting copies of fragment {} of {}",
+                    info.number, info.id
+                )))
+            }
+            _ => {}
+        }
+        self.total = total;
+        if info.number == 1 {
+            self.first = Some(part.headers.clone());
+        }
+        self.bodies.insert(info.number, part.body.to_string());
+        Ok(())
+    }
+
+    // Whether all of the fragments have been added, which as none are past
+    // the last is when there are as many as there should be.
+    fn is_complete(&self) -> bool {
+        self.total.is_some_and(|total| {
+            self.bodies.len() == total && self.bodies.keys().next_back() == Some(&total)
+        })
+    }
+
+    // The numbers of the fragments still to come, if the number of them is
+    // known yet.
+    fn missing(&self) -> Option<Vec<usize>> {
+        let total = self.total?;
+        Some(
+            (1..=total)
+                .filter(|n| !self.bodies.contains_key(n))
+                .collect(),
+        )
+    }
+
+    // The message the fragments, all of which have been added, make up.
+    fn assemble(self) -> ParsingResult<MimeMessage> {
+        let enclosed: String = self.bodies.values().map(|body| &body[..]).collect();
+        let mut message = MimeMessage::parse(&enclosed)?;
+
+        let mut headers = HeaderMap::new();
+        for header in self.first.iter().flat_map(|first| first.iter()) {
+            if !is_enclosed(&header.name) {
+                headers.insert(header.clone());
+            }
+        }
+        for header in message.headers.iter() {
+            if is_enclosed(&header.name) {
+                headers.insert(header.clone());
+            }
+        }
+        message.headers = headers;
+        Ok(message)
+    }
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler::default()
+    }
+
+    /// A reassembler which waits for the rest of the fragments of at most
+    /// `max_pending` messages at once, rather than `MAX_PENDING`.
+    pub fn with_max_pending(max_pending: usize) -> Reassembler {
+        Reassembler {
+            pending: HashMap::new(),
+            max_pending,
+        }
+    }
+
+    /// Add the fragment `part`, giving the message it's part of if that's
+    /// now complete.
+    ///
+    /// Fails if `part` isn't a `message/partial` fragment, if it conflicts
+    /// with the fragments of its message already added, if it would be the
+    /// first of more messages than are allowed to be pending, or if the
+    /// complete message can't be parsed.
+    pub fn add(&mut self, part: &MimeMessage) -> ParsingResult<Option<MimeMessage>> {
+        let info = part
+            .partial_info()
+            .ok_or_else(|| ParsingError::new("Not a message/partial fragment".to_string()))?;
+        if !self.pending.contains_key(&info.id) && self.pending.len() >= self.max_pending {
+            return Err(ParsingError::new(format!(
+                "More than {} messages pending reassembly",
+                self.max_pending
+            )));
+        }
+        let fragments = self.pending.entry(info.id.clone()).or_default();
+        let added = fragments.add(&info, part);
+        if fragments.bodies.is_empty() {
+            self.pending.remove(&info.id);
+        }
+        added?;
+        if !self.pending[&info.id].is_complete() {
+            return Ok(None);
+        }
+        let fragments = self.pending.remove(&info.id).unwrap_or_default();
+        fragments.assemble().map(Some)
+    }
+
+    /// The numbers of the fragments of the message `id` still to come, if
+    /// some have been added and the number of them is known.
+    pub fn missing(&self, id: &str) -> Option<Vec<usize>> {
+        self.pending.get(id)?.missing()
+    }
+
+    /// The number of messages with some fragments still missing.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> MimeMessage {
+        let mut lines = String::new();
+        for i in 0..40 {
+            lines.push_str(&format!("Line {} of the attachment\r\n", i));
+        }
+        let input = format!(
+            "From: joe@example.org\r\n\
+             To: anna@example.net\r\n\
+             Subject: Report\r\n\
+             Message-ID: <1234@example.org>\r\n\
+             MIME-Version: 1.0\r\n\
+             Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             See attached\r\n\
+             --b\r\n\
+             Content-Type: text/plain; name=report.txt\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n\
+             {}\r\n\
+             --b--\r\n",
+            MimeContentTransferEncoding::Base64.encode(lines.as_bytes())
+        );
+        MimeMessage::parse(&input).unwrap()
+    }
+
+    #[test]
+    fn test_optimize_encodings() {
+        let message = message();
+        let size = message.encoded_len_estimate();
+        let fitted = fit_to_size(&message, size - 100).unwrap();
+        assert_eq!(fitted.len(), 1);
+        let attachment = &fitted[0].children[1];
+        assert_eq!(
+            attachment
+                .headers
+                .get_value::<String>("Content-Transfer-Encoding".to_string())
+                .unwrap(),
+            "7bit"
+        );
+        assert_eq!(
+            attachment.decoded_body(),
+            message.children[1].decoded_body()
+        );
+        assert_eq!(fit_to_size(&message, size).unwrap(), vec![message]);
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let message = message();
+        let fragments = split(&message, 800).unwrap();
+        assert!(fragments.len() > 1);
+        let total = fragments.len();
+        for (i, fragment) in fragments.iter().enumerate() {
+            assert!(fragment.as_string().len() <= 800);
+            let content_type = fragment.content_type();
+            assert_eq!(content_type.parameter("id"), Some("1234@example.org"));
+            assert_eq!(
+                content_type.parameter("number"),
+                Some(&(i + 1).to_string()[..])
+            );
+            assert_eq!(
+                fragment
+                    .headers
+                    .get_value::<String>("Subject".to_string())
+                    .unwrap(),
+                format!("Report ({}/{})", i + 1, total)
+            );
+            assert!(fragment.headers.get("From".to_string()).is_some());
+        }
+
+        // The fragments are read back in, as they would be on delivery
+        let mut reassembler = Reassembler::new();
+        let mut reassembled = None;
+        for fragment in fragments.iter().rev() {
+            assert!(reassembled.is_none());
+            let fragment = MimeMessage::parse(&fragment.as_string()).unwrap();
+            reassembled = reassembler.add(&fragment).unwrap();
+        }
+        assert_eq!(reassembler.pending(), 0);
+        let reassembled = reassembled.unwrap();
+        let names: Vec<&str> = reassembled
+            .headers
+            .iter()
+            .map(|header| &header.name[..])
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "From",
+                "To",
+                "Subject",
+                "Message-ID",
+                "MIME-Version",
+                "Content-Type"
+            ]
+        );
+        assert_eq!(
+            reassembled
+                .headers
+                .get_value::<String>("Subject".to_string())
+                .unwrap(),
+            "Report"
+        );
+        assert_eq!(reassembled.children.len(), 2);
+        assert_eq!(
+            reassembled.children[1].decoded_body(),
+            message.children[1].decoded_body()
+        );
+
+        assert!(split(&message, 100).is_err());
+        assert!(reassembler.add(&message).is_err());
+    }
+
+    #[test]
+    fn test_reassemble() {
+        let fragment = |number: usize, total: &str, body: &str| {
+            MimeMessage::parse(&format!(
+                "From: joe@example.org\r\n\
+                 Content-Type: message/partial; id=\"abc@example.org\";\r\n \
+                 number={}{}\r\n\r\n{}",
+                number, total, body
+            ))
+            .unwrap()
+        };
+        let first = fragment(1, "", "Subject: Hi\r\n\r\nHello ");
+        let second = fragment(2, "; total=2", "there\r\n");
+        assert_eq!(
+            first.partial_info(),
+            Some(PartialInfo {
+                id: "abc@example.org".to_string(),
+                number: 1,
+                total: None,
+            })
+        );
+
+        let message = reassemble(&[second.clone(), first.clone()]).unwrap();
+        assert_eq!(
+            message.as_string(),
+            "From: joe@example.org\r\nSubject: Hi\r\n\r\nHello there\r\n"
+        );
+
+        assert_eq!(
+            reassemble(std::slice::from_ref(&first))
+                .unwrap_err()
+                .to_string(),
+            "Number of fragments of abc@example.org unknown"
+        );
+        let third = fragment(3, "; total=3", "!");
+        assert_eq!(
+            reassemble(&[first.clone(), third.clone()])
+                .unwrap_err()
+                .to_string(),
+            "Missing fragments 2 of abc@example.org"
+        );
+        assert!(reassemble(&[first.clone(), second.clone(), third.clone()]).is_err());
+        let other = fragment(2, "; total=2", "here\r\n");
+        assert!(reassemble(&[first.clone(), second.clone(), other]).is_err());
+
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.add(&third).unwrap(), None);
+        assert_eq!(reassembler.missing("abc@example.org"), Some(vec![1, 2]));
+        assert_eq!(reassembler.missing("other@example.org"), None);
+    }
+
+    #[test]
+    fn test_reassemble_limits() {
+        let fragment = |id: &str, number: &str, total: &str| {
+            MimeMessage::parse(&format!(
+                "Content-Type: message/partial; id=\"{}\"; number={}; total={}\r\n\r\nHi",
+                id, number, total
+            ))
+            .unwrap()
+        };
+        let mut reassembler = Reassembler::new();
+        let huge = fragment("a@example.org", "1", "18446744073709551615");
+        assert!(reassembler.add(&huge).is_err());
+        let huge = fragment("a@example.org", "18446744073709551615", "2");
+        assert!(reassembler.add(&huge).is_err());
+        assert_eq!(reassembler.pending(), 0);
+
+        // A total given after a fragment past it is a conflict
+        let mut reassembler = Reassembler::new();
+        let untotalled = MimeMessage::parse(
+            "Content-Type: message/partial; id=\"a@example.org\"; number=3\r\n\r\nHi",
+        )
+        .unwrap();
+        assert_eq!(reassembler.add(&untotalled).unwrap(), None);
+        let first = fragment("a@example.org", "1", "2");
+        assert!(reassembler.add(&first).is_err());
+        assert_eq!(reassembler.missing("a@example.org"), None);
+
+        let mut reassembler = Reassembler::with_max_pending(1);
+        assert_eq!(reassembler.add(&first).unwrap(), None);
+        let other = fragment("b@example.org", "1", "2");
+        assert!(reassembler.add(&other).is_err());
+        let second = fragment("a@example.org", "2", "2");
+        assert!(reassembler.add(&second).unwrap().is_some());
+    }
+}
@@ -0,0 +1,131 @@
+//! Module for how urgent a message is, which different mail programs give
+//! with different headers: `Importance` and `Priority` from RFC 2156,
+//! `X-Priority`, and Outlook's `X-MSMail-Priority`.
+use super::header::{FromHeader, Header, HeaderName, ToHeader};
+use super::message::MimeMessage;
+use super::results::{ParsingError, ParsingResult};
+use super::xheaders::XPriority;
+
+pub const X_MSMAIL_PRIORITY: &str = "X-MSMail-Priority";
+
+/// How urgent a message is.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum MessagePriority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl MessagePriority {
+    /// The headers giving this priority, one for each of the ways mail
+    /// programs read it, as set by `MessageBuilder::priority`.
+    pub fn headers(&self) -> Vec<Header> {
+        let (x_priority, msmail, importance, priority) = match *self {
+            MessagePriority::High => (XPriority::HIGHEST, "High", "high", "urgent"),
+            MessagePriority::Normal => (XPriority::NORMAL, "Normal", "normal", "normal"),
+            MessagePriority::Low => (XPriority::LOWEST, "Low", "low", "non-urgent"),
+        };
+        let x_priority = XPriority::to_header(x_priority).unwrap();
+        vec![
+            Header::new(HeaderName::X_PRIORITY.to_string(), x_priority),
+            Header::new(X_MSMAIL_PRIORITY.to_string(), msmail.to_string()),
+            Header::new(HeaderName::IMPORTANCE.to_string(), importance.to_string()),
+            Header::new(HeaderName::PRIORITY.to_string(), priority.to_string()),
+        ]
+    }
+}
+
+impl From<XPriority> for MessagePriority {
+    fn from(priority: XPriority) -> MessagePriority {
+        if priority.is_high() {
+            MessagePriority::High
+        } else if priority.is_low() {
+            MessagePriority::Low
+        } else {
+            MessagePriority::Normal
+        }
+    }
+}
+
+impl FromHeader for MessagePriority {
+    /// Any of the values of the headers this module is for are accepted,
+    /// so `high`, `urgent` and `1 (Highest)` are all high.
+    fn from_header(value: String) -> ParsingResult<MessagePriority> {
+        let word = value.trim().to_ascii_lowercase();
+        Ok(match &word[..] {
+            "high" | "urgent" => MessagePriority::High,
+            "normal" => MessagePriority::Normal,
+            "low" | "non-urgent" => MessagePriority::Low,
+            _ => XPriority::from_header(value)
+                .map_err(|_| ParsingError::new(format!("Invalid priority: {}", word)))?
+                .into(),
+        })
+    }
+}
+
+impl ToHeader for MessagePriority {
+    /// The value as `Importance` gives it.
+    fn to_header(value: MessagePriority) -> ParsingResult<String> {
+        Ok(match value {
+            MessagePriority::High => "high",
+            MessagePriority::Normal => "normal",
+            MessagePriority::Low => "low",
+        }
+        .to_string())
+    }
+}
+
+impl MimeMessage {
+    /// How urgent this message is, from the first of its `Importance`,
+    /// `Priority`, `X-Priority` and `X-MSMail-Priority` headers with a
+    /// valid value. A message without any is normal.
+    pub fn priority(&self) -> MessagePriority {
+        let names = [
+            HeaderName::IMPORTANCE,
+            HeaderName::PRIORITY,
+            HeaderName::X_PRIORITY,
+            HeaderName::from(X_MSMAIL_PRIORITY),
+        ];
+        names
+            .iter()
+            .filter_map(|name| self.headers.get_value(name.to_string()).ok())
+            .next()
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_priority() {
+        let tests = [
+            ("High", Some(MessagePriority::High)),
+            ("urgent", Some(MessagePriority::High)),
+            ("non-urgent", Some(MessagePriority::Low)),
+            ("2 (High)", Some(MessagePriority::High)),
+            ("3", Some(MessagePriority::Normal)),
+            ("5 (Lowest)", Some(MessagePriority::Low)),
+            ("soon", None),
+        ];
+        for &(value, expected) in tests.iter() {
+            let priority = MessagePriority::from_header(value.to_string()).ok();
+            assert_eq!(priority, expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_message_priority() {
+        let message = MimeMessage::parse("Subject: Hi\r\n\r\n").unwrap();
+        assert_eq!(message.priority(), MessagePriority::Normal);
+        let message = MimeMessage::parse("X-MSMail-Priority: Low\r\n\r\n").unwrap();
+        assert_eq!(message.priority(), MessagePriority::Low);
+        // Importance is preferred, unless it's invalid
+        let message = MimeMessage::parse("X-Priority: 5\r\nImportance: High\r\n\r\n").unwrap();
+        assert_eq!(message.priority(), MessagePriority::High);
+        let message = MimeMessage::parse("X-Priority: 5\r\nImportance: Very\r\n\r\n").unwrap();
+        assert_eq!(message.priority(), MessagePriority::Low);
+    }
+}
@@ -0,0 +1,81 @@
+//! Module for the headers asking for a read receipt: the
+//! `Disposition-Notification-To` header of RFC 8098, and the older
+//! `Return-Receipt-To`.
+//!
+//! `MessageBuilder::request_receipt` only sets `Disposition-Notification-To`,
+//! as some mail servers answer `Return-Receipt-To` with a receipt for the
+//! delivery of the message, rather than for it being read.
+use super::address::Mailbox;
+use super::builder::mailboxes;
+use super::message::MimeMessage;
+
+pub const DISPOSITION_NOTIFICATION_TO: &str = "Disposition-Notification-To";
+pub const RETURN_RECEIPT_TO: &str = "Return-Receipt-To";
+
+impl MimeMessage {
+    /// The mailboxes asking for a read receipt, from the
+    /// `Disposition-Notification-To` header, or failing that the
+    /// `Return-Receipt-To` header.
+    pub fn receipt_requested_by(&self) -> Vec<Mailbox> {
+        let requested_by = mailboxes(self, DISPOSITION_NOTIFICATION_TO);
+        if requested_by.is_empty() {
+            mailboxes(self, RETURN_RECEIPT_TO)
+        } else {
+            requested_by
+        }
+    }
+
+    /// Whether the author of this message asked for a read receipt.
+    pub fn requests_receipt(&self) -> bool {
+        !self.receipt_requested_by().is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::MessageBuilder;
+
+    #[test]
+    fn test_receipt_requested_by() {
+        let message = MimeMessage::parse(
+            "Disposition-Notification-To: Joe <joe@example.org>\r\n\
+             Return-Receipt-To: other@example.org\r\n\r\n",
+        )
+        .unwrap();
+        let requested_by = message.receipt_requested_by();
+        assert_eq!(
+            requested_by,
+            vec![Mailbox::new_with_name(
+                "Joe".to_string(),
+                "joe@example.org".to_string()
+            )]
+        );
+
+        let message = MimeMessage::parse("Return-Receipt-To: other@example.org\r\n\r\n").unwrap();
+        assert!(message.requests_receipt());
+        let message = MimeMessage::parse("Subject: Hi\r\n\r\n").unwrap();
+        assert!(!message.requests_receipt());
+    }
+
+    #[test]
+    fn test_request_receipt() {
+        let message = MessageBuilder::new()
+            .from("joe@example.org")
+            .request_receipt("receipts@example.org")
+            .build()
+            .unwrap();
+        let header = message.headers.get(DISPOSITION_NOTIFICATION_TO.to_string());
+        assert_eq!(header.unwrap().raw_value(), "<receipts@example.org>");
+        assert_eq!(
+            message.receipt_requested_by()[0].address,
+            "receipts@example.org"
+        );
+
+        let message = MessageBuilder::new()
+            .from("joe@example.org")
+            .request_receipt("joe\r\nBcc: x@example.org")
+            .build();
+        assert!(message.is_err());
+    }
+}
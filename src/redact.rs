@@ -0,0 +1,456 @@
+//! Module for redacting messages, so that they can be shared safely, such
+//! as to reproduce a parsing bug or as a test fixture.
+//!
+//! `redact` replaces addresses, subjects and message IDs with pseudonyms,
+//! and masks the content of bodies and other headers, while keeping the
+//! structure of the message: its tree of parts, their transfer encodings,
+//! and roughly their sizes. Pseudonyms are derived from what they replace,
+//! so the same address gets the same pseudonym wherever it appears, and
+//! replies still refer to the messages they reply to.
+//!
+//! The SMTP envelope of a message is redacted along with its headers: its
+//! paths are replaced as addresses are, and the address of the client it
+//! came from is dropped along with other headers.
+use super::address::{Address, Mailbox};
+use super::envelope::Envelope;
+use super::fingerprint::sha256;
+use super::header::{Header, HeaderMap};
+use super::message::MimeMessage;
+use super::mimeheaders::{ContentDisposition, ContentType, MimeContentTransferEncoding};
+
+// Headers whose values are lists of addresses
+static ADDRESS_HEADERS: [&str; 17] = [
+    "From",
+    "Sender",
+    "Reply-To",
+    "To",
+    "Cc",
+    "Bcc",
+    "Return-Path",
+    "Delivered-To",
+    "X-Original-To",
+    "Resent-From",
+    "Resent-Sender",
+    "Resent-To",
+    "Resent-Cc",
+    "Resent-Bcc",
+    "Mail-Followup-To",
+    "Mail-Reply-To",
+    "Disposition-Notification-To",
+];
+
+// Headers whose values are message IDs, or lists of them
+static MESSAGE_ID_HEADERS: [&str; 5] = [
+    "Message-ID",
+    "In-Reply-To",
+    "References",
+    "Resent-Message-ID",
+    "Content-ID",
+];
+
+/// What `redact` replaces in a message.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct RedactionPolicy {
+    /// A secret mixed into the pseudonyms, so that they can't be matched
+    /// against guessed values, while the same key gives the same pseudonyms
+    pub key: String,
+    /// Replace the display names and addresses of address headers
+    pub addresses: bool,
+    /// Replace the subject
+    pub subjects: bool,
+    /// Replace the message IDs of `Message-ID`, `References` and the like,
+    /// and Content-IDs
+    pub message_ids: bool,
+    /// Mask the bodies of parts, and replace the names of attachments
+    pub bodies: bool,
+    /// Mask the values of all other headers, apart from those in
+    /// `kept_headers`
+    pub other_headers: bool,
+    /// The names of the headers left as they are by `other_headers`
+    pub kept_headers: Vec<String>,
+}
+
+impl Default for RedactionPolicy {
+    /// Redact everything, keeping only the headers which describe the
+    /// structure of the message, and its date.
+    fn default() -> RedactionPolicy {
+        RedactionPolicy {
+            key: String::new(),
+            addresses: true,
+            subjects: true,
+            message_ids: true,
+            bodies: true,
+            other_headers: true,
+            kept_headers: [
+                "Date",
+                "MIME-Version",
+                "Content-Type",
+                "Content-Transfer-Encoding",
+                "Content-Disposition",
+            ]
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+        }
+    }
+}
+
+impl RedactionPolicy {
+    // The pseudonym for `value`, of the given `kind` so that the same value
+    // has unrelated pseudonyms as different kinds of thing.
+    fn pseudonym(&self, kind: &str, value: &str) -> String {
+        let data = format!("{}\0{}\0{}", self.key, kind, value);
+        sha256(data.as_bytes())[..4]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn mailbox(&self, mailbox: &Mailbox) -> Mailbox {
+        let address = mailbox.address.to_lowercase();
+        let (local, domain) = match address.rfind('@') {
+            Some(at) => (&address[..at], &address[at + 1..]),
+            None => (&address[..], ""),
+        };
+        Mailbox {
+            name: mailbox
+                .name
+                .as_ref()
+                .map(|name| format!("Name {}", self.pseudonym("name", name))),
+            address: format!(
+                "u{}@d{}.invalid",
+                self.pseudonym("local", local),
+                self.pseudonym("domain", domain)
+            ),
+        }
+    }
+
+    fn address(&self, address: &Address) -> Address {
+        match *address {
+            Address::Mailbox(ref mailbox) => Address::Mailbox(self.mailbox(mailbox)),
+            Address::Group(ref name, ref mailboxes) => Address::Group(
+                format!("Group {}", self.pseudonym("name", name)),
+                mailboxes
+                    .iter()
+                    .map(|mailbox| self.mailbox(mailbox))
+                    .collect(),
+            ),
+        }
+    }
+
+    // A path of the envelope, replaced as an address is, apart from the
+    // empty path of bounces.
+    fn path(&self, path: &str) -> String {
+        if !self.addresses || path.is_empty() {
+            return path.to_string();
+        }
+        let mailbox = Mailbox {
+            name: None,
+            address: path.to_string(),
+        };
+        self.mailbox(&mailbox).address
+    }
+
+    fn envelope(&self, envelope: &Envelope) -> Envelope {
+        let rcpt_to = envelope.rcpt_to.iter().map(|path| self.path(path));
+        Envelope {
+            mail_from: self.path(&envelope.mail_from),
+            rcpt_to: rcpt_to.collect(),
+            received_at: envelope.received_at,
+            remote_ip: envelope.remote_ip.filter(|_| !self.other_headers),
+        }
+    }
+
+    // Replace each `<id>` in `value`.
+    fn message_ids(&self, value: &str) -> String {
+        let mut redacted = String::new();
+        let mut rest = value;
+        while let Some(start) = rest.find('<') {
+            let end = match rest[start..].find('>') {
+                Some(end) => start + end,
+                None => break,
+            };
+            redacted.push_str(&rest[..start]);
+            let id = &rest[start + 1..end];
+            redacted.push_str(&format!("<{}@redacted.invalid>", self.pseudonym("id", id)));
+            rest = &rest[end + 1..];
+        }
+        redacted.push_str(rest);
+        redacted
+    }
+
+    // The name of an attachment, keeping its extension.
+    fn filename(&self, filename: &str) -> String {
+        let pseudonym = self.pseudonym("file", filename);
+        match filename.rfind('.') {
+            Some(dot) => format!("file-{}{}", pseudonym, &filename[dot..]),
+            None => format!("file-{}", pseudonym),
+        }
+    }
+
+    fn header(&self, header: &Header) -> Header {
+        let name = &header.name;
+        let value = header.raw_value();
+        let is = |names: &[&str]| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+
+        // Headers of the kinds below are left as they are when their kind
+        // isn't replaced, rather than masked as other headers
+        if is(&ADDRESS_HEADERS) {
+            if !self.addresses {
+                return header.clone();
+            }
+            let redacted = header
+                .get_value::<Vec<Address>>()
+                .ok()
+                .and_then(|addresses| {
+                    let addresses: Vec<Address> =
+                        addresses.iter().map(|a| self.address(a)).collect();
+                    Header::new_with_value(name.to_string(), addresses).ok()
+                });
+            return redacted.unwrap_or_else(|| self.masked_header(header));
+        }
+        if is(&["Subject", "Thread-Topic"]) {
+            if !self.subjects {
+                return header.clone();
+            }
+            let subject = format!("Subject {}", self.pseudonym("subject", value));
+            return Header::new(name.to_string(), subject);
+        }
+        if is(&MESSAGE_ID_HEADERS) {
+            if !self.message_ids {
+                return header.clone();
+            }
+            return Header::new(name.to_string(), self.message_ids(value));
+        }
+        if self.bodies && is(&["Content-Type"]) {
+            let mut content_type: ContentType = header.get_value().unwrap_or_default();
+            if let Some(filename) = content_type.parameter("name").map(|n| self.filename(n)) {
+                content_type.set_parameter("name", &filename);
+                return Header::new(name.to_string(), content_type.to_string());
+            }
+        }
+        if self.bodies && is(&["Content-Disposition"]) {
+            if let Ok(mut disposition) = header.get_value::<ContentDisposition>() {
+                if let Some(filename) = disposition.filename.take() {
+                    disposition.filename = Some(self.filename(&filename));
+                    return Header::new(name.to_string(), disposition.to_string());
+                }
+            }
+        }
+        let kept = self
+            .kept_headers
+            .iter()
+            .any(|kept| kept.eq_ignore_ascii_case(name));
+        if self.other_headers && !kept {
+            return self.masked_header(header);
+        }
+        header.clone()
+    }
+
+    fn masked_header(&self, header: &Header) -> Header {
+        let masked = mask(header.raw_value().as_bytes());
+        Header::new(
+            header.name.to_string(),
+            String::from_utf8_lossy(&masked).into_owned(),
+        )
+    }
+
+    fn part(&self, part: &MimeMessage) -> MimeMessage {
+        let mut redacted = part.clone();
+        let mut headers = HeaderMap::new();
+        for header in part.headers.iter() {
+            headers.insert(self.header(header));
+        }
+        redacted.headers = headers;
+        redacted.envelope = part.envelope.as_ref().map(|e| self.envelope(e));
+        redacted.children = part.children.iter().map(|child| self.part(child)).collect();
+        if self.bodies {
+            redacted.body = self.body(part).into();
+            if let Some(epilogue) = part.epilogue() {
+                redacted.set_epilogue(&String::from_utf8_lossy(&mask(epilogue.as_bytes())));
+            }
+        }
+        redacted
+    }
+
+    // The redacted body of `part`, encoded as it was.
+    fn body(&self, part: &MimeMessage) -> String {
+        if part.message_type.is_some() {
+            // The preamble of a multipart
+            return String::from_utf8_lossy(&mask(part.body.as_bytes())).into_owned();
+        }
+        let encoding = part
+            .headers
+            .get_value("Content-Transfer-Encoding".to_string())
+            .unwrap_or(MimeContentTransferEncoding::Identity);
+        let content = match part.decoded_body() {
+            Some(content) => content,
+            None => return String::from_utf8_lossy(&mask(part.body.as_bytes())).into_owned(),
+        };
+
+        let content_type = part.content_type();
+        let redacted = match (&content_type.ttype[..], &content_type.subtype[..]) {
+            ("text", _) => mask(&content),
+            ("message", "rfc822") => match MimeMessage::parse_bytes(&content) {
+                Ok(message) => self.part(&message).to_bytes(),
+                Err(_) => mask(&content),
+            },
+            _ => vec![0; content.len()],
+        };
+        encoding.encode(&redacted[..])
+    }
+}
+
+/// Redact `message` as set out by `policy`, giving a copy of it which can
+/// be shared in its place.
+///
+/// Messages attached as `message/rfc822` parts are redacted in turn. The
+/// content of other text parts, and the values of other headers, are
+/// masked, with each letter and digit replaced, but the punctuation,
+/// spacing and line breaks kept. Other bodies are replaced by zeros.
+pub fn redact(message: &MimeMessage, policy: &RedactionPolicy) -> MimeMessage {
+    policy.part(message)
+}
+
+// Mask the letters and digits of `text`, and any bytes which aren't
+// ASCII, keeping its length.
+fn mask(text: &[u8]) -> Vec<u8> {
+    text.iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' => b'X',
+            b'0'..=b'9' => b'0',
+            b'a'..=b'z' | 0x80..=0xff => b'x',
+            _ => b,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(message: &MimeMessage, name: &str) -> String {
+        message.headers.get_value(name.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_redact() {
+        let input = "From: Joe Blogs <joe@example.org>\r\n\
+                     To: anna@example.net, Team: bob@example.net;\r\n\
+                     Subject: Salary review\r\n\
+                     Message-ID: <1234@example.org>\r\n\
+                     X-Mailer: Mail 4.2\r\n\
+                     Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+                     --b\r\n\
+                     Content-Type: text/plain; charset=utf-8\r\n\
+                     Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+                     Hi Anna, it's =C2=A3100.\r\n\
+                     --b\r\n\
+                     Content-Type: application/pdf; name=review.pdf\r\n\
+                     Content-Transfer-Encoding: base64\r\n\r\n\
+                     JVBERi0=\r\n\
+                     --b--\r\n";
+        let message = MimeMessage::parse(input).unwrap();
+        let policy = RedactionPolicy::default();
+        let redacted = redact(&message, &policy);
+
+        let from = value(&redacted, "From");
+        assert!(from.starts_with("Name "));
+        assert!(from.ends_with(".invalid>"));
+        assert!(!from.contains("joe"));
+        let to = value(&redacted, "To");
+        assert!(to.contains("Group "));
+        assert!(!to.contains("anna") && !to.contains("Team"));
+        assert!(value(&redacted, "Subject").starts_with("Subject "));
+        let id = value(&redacted, "Message-ID");
+        assert!(id.starts_with('<') && id.ends_with("@redacted.invalid>"));
+        assert_eq!(value(&redacted, "X-Mailer"), "Xxxx 0.0");
+
+        assert_eq!(redacted.children.len(), 2);
+        let text = &redacted.children[0];
+        assert_eq!(text.body.trim_end(), "Xx Xxxx, xx'x xx000.");
+        let pdf = &redacted.children[1];
+        assert_eq!(pdf.decoded_body_bytes(), Some(vec![0; 5]));
+        let name = pdf.content_type().parameter("name").unwrap().to_string();
+        assert!(name.starts_with("file-") && name.ends_with(".pdf"));
+
+        // The same values get the same pseudonyms, unless the key differs
+        assert_eq!(redact(&message, &policy), redacted);
+        let keyed = RedactionPolicy {
+            key: "secret".to_string(),
+            ..RedactionPolicy::default()
+        };
+        assert_ne!(value(&redact(&message, &keyed), "From"), from);
+
+        let reparsed = MimeMessage::parse(&redacted.as_string()).unwrap();
+        assert_eq!(reparsed.children.len(), 2);
+    }
+
+    #[test]
+    fn test_redact_preamble_and_epilogue() {
+        let message = MimeMessage::parse(
+            "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             Sent by Joe\r\n\
+             --b\r\n\r\n\
+             Hi\r\n\
+             --b--\r\n\
+             Call 555-1234\r\n",
+        )
+        .unwrap();
+        let redacted = redact(&message, &RedactionPolicy::default());
+        assert_eq!(redacted.preamble(), Some("Xxxx xx Xxx"));
+        assert_eq!(redacted.epilogue(), Some("Xxxx 000-0000\r\n"));
+        assert!(!redacted.as_string().contains("555"));
+    }
+
+    #[test]
+    fn test_redact_policy() {
+        let message = MimeMessage::parse(
+            "From: joe@example.org\r\n\
+             Subject: Hi\r\n\
+             In-Reply-To: <1@example.org>\r\n\r\n\
+             Hello\r\n",
+        )
+        .unwrap();
+        let policy = RedactionPolicy {
+            subjects: false,
+            bodies: false,
+            ..RedactionPolicy::default()
+        };
+        let redacted = redact(&message, &policy);
+        assert_eq!(value(&redacted, "Subject"), "Hi");
+        assert_eq!(redacted.body, message.body);
+        assert_eq!(
+            value(&redacted, "In-Reply-To"),
+            policy.message_ids("<1@example.org>")
+        );
+        assert_ne!(value(&redacted, "From"), "joe@example.org");
+    }
+
+    #[test]
+    fn test_redact_envelope() {
+        let mut message = MimeMessage::parse("From: joe@example.org\r\n\r\nHi\r\n").unwrap();
+        message.envelope = Some(Envelope {
+            mail_from: "Joe@example.org".to_string(),
+            rcpt_to: vec!["anna@example.net".to_string(), "".to_string()],
+            received_at: None,
+            remote_ip: Some("192.0.2.1".parse().unwrap()),
+        });
+        let policy = RedactionPolicy::default();
+        let redacted = redact(&message, &policy);
+        let envelope = redacted.envelope.as_ref().unwrap();
+        // The same address as in the headers gets the same pseudonym
+        let from = format!("<{}>", envelope.mail_from);
+        assert_eq!(from, value(&redacted, "From"));
+        assert!(envelope.rcpt_to[0].ends_with(".invalid"));
+        assert_eq!(envelope.rcpt_to[1], "");
+        assert_eq!(envelope.remote_ip, None);
+
+        let policy = RedactionPolicy {
+            addresses: false,
+            other_headers: false,
+            ..RedactionPolicy::default()
+        };
+        assert_eq!(redact(&message, &policy).envelope, message.envelope);
+    }
+}
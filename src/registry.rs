@@ -0,0 +1,191 @@
+//! Module associating header names with the types their values parse to.
+//!
+//! Registering a type for a header lets `HeaderMap::get_typed` find the
+//! header without being given its name, and `Header::get_any` parse any
+//! header to its registered type. Headers with no type registered are
+//! parsed as strings.
+//!
+//! The headers of this crate whose values have a type of their own, such as
+//! `Content-Type` and `Message-ID`, are registered from the start.
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use chrono::{DateTime, FixedOffset};
+
+use super::arc::{ArcAuthenticationResults, ArcMessageSignature, ArcSeal};
+use super::autoreply::{AutoResponseSuppress, AutoSubmitted, Precedence};
+use super::bimi::{BimiIndicator, BimiLocation, BimiSelector};
+use super::header::{FromHeader, HeaderName};
+use super::listheaders::{ListId, ListPost, ListUnsubscribePost};
+use super::messageid::MessageId;
+use super::mimeheaders::{ContentDisposition, ContentType, MimeContentTransferEncoding};
+use super::results::ParsingResult;
+use super::xheaders::{SpamStatus, XPriority};
+
+/// A header value parsed to whichever type is registered for its header.
+pub type AnyValue = Box<dyn Any + Send + Sync>;
+
+type Parser = fn(String) -> ParsingResult<AnyValue>;
+
+struct Registry {
+    names: HashMap<TypeId, HeaderName>,
+    parsers: HashMap<HeaderName, Parser>,
+}
+
+impl Registry {
+    fn insert<T: FromHeader + Send + Sync + 'static>(&mut self, name: &str) {
+        self.names.insert(TypeId::of::<T>(), HeaderName::from(name));
+        self.parsers.insert(HeaderName::from(name), parse_as::<T>);
+    }
+}
+
+fn parse_as<T: FromHeader + Send + Sync + 'static>(value: String) -> ParsingResult<AnyValue> {
+    T::from_header(value).map(|value| Box::new(value) as AnyValue)
+}
+
+lazy_static! {
+    static ref REGISTRY: RwLock<Registry> = {
+        let mut registry = Registry {
+            names: HashMap::new(),
+            parsers: HashMap::new(),
+        };
+        registry.insert::<DateTime<FixedOffset>>("Date");
+        registry.insert::<MessageId>("Message-ID");
+        registry.insert::<ContentType>("Content-Type");
+        registry.insert::<ContentDisposition>("Content-Disposition");
+        registry.insert::<MimeContentTransferEncoding>("Content-Transfer-Encoding");
+        registry.insert::<ListId>("List-Id");
+        registry.insert::<ListPost>("List-Post");
+        registry.insert::<ListUnsubscribePost>("List-Unsubscribe-Post");
+        registry.insert::<ArcAuthenticationResults>("ARC-Authentication-Results");
+        registry.insert::<ArcMessageSignature>("ARC-Message-Signature");
+        registry.insert::<ArcSeal>("ARC-Seal");
+        registry.insert::<BimiSelector>("BIMI-Selector");
+        registry.insert::<BimiLocation>("BIMI-Location");
+        registry.insert::<BimiIndicator>("BIMI-Indicator");
+        registry.insert::<AutoSubmitted>("Auto-Submitted");
+        registry.insert::<Precedence>("Precedence");
+        registry.insert::<AutoResponseSuppress>("X-Auto-Response-Suppress");
+        registry.insert::<XPriority>("X-Priority");
+        registry.insert::<SpamStatus>("X-Spam-Status");
+        RwLock::new(registry)
+    };
+}
+
+// The registry is only ever changed by single inserts, so remains usable
+// even if a thread panicked while holding the lock.
+fn read() -> RwLockReadGuard<'static, Registry> {
+    REGISTRY.read().unwrap_or_else(|e| e.into_inner())
+}
+
+fn write() -> RwLockWriteGuard<'static, Registry> {
+    REGISTRY.write().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Register `T` as the type of the header `name`.
+///
+/// Any type previously registered for `name` is replaced, and `T` is
+/// associated with `name` alone, replacing any name it was registered for
+/// before.
+pub fn register<T: FromHeader + Send + Sync + 'static>(name: &str) {
+    write().insert::<T>(name);
+}
+
+/// The name of the header `T` is registered for, if any.
+pub fn registered_name<T: 'static>() -> Option<HeaderName> {
+    read().names.get(&TypeId::of::<T>()).cloned()
+}
+
+/// Parse `value` as the type registered for the header `name`, or as a
+/// `String` if there is none.
+pub fn parse(name: &str, value: String) -> ParsingResult<AnyValue> {
+    let parser = read().parsers.get(&HeaderName::from(name)).cloned();
+    match parser {
+        Some(parser) => parser(value),
+        None => parse_as::<String>(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::header::{Header, HeaderMap};
+    use super::super::results::ParsingError;
+
+    #[derive(Debug, PartialEq)]
+    struct Priority(u8);
+
+    // The registry is shared between tests, so each registers its own type
+    #[derive(Debug, PartialEq)]
+    struct Level(String);
+
+    impl FromHeader for Level {
+        fn from_header(value: String) -> ParsingResult<Level> {
+            Ok(Level(value))
+        }
+    }
+
+    impl FromHeader for Priority {
+        fn from_header(value: String) -> ParsingResult<Priority> {
+            match value.trim().parse() {
+                Ok(priority) => Ok(Priority(priority)),
+                Err(_) => Err(ParsingError::new(format!("Invalid priority: {}", value))),
+            }
+        }
+    }
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for &(name, value) in [
+            ("Content-Type", "text/plain; charset=utf-8"),
+            ("message-id", "<1@example.org>"),
+            ("X-Test-Priority", "3"),
+            ("X-Test-Level", "debug"),
+            ("Subject", "Hello"),
+        ]
+        .iter()
+        {
+            headers.insert(Header::new(name.to_string(), value.to_string()));
+        }
+        headers
+    }
+
+    #[test]
+    fn test_get_typed() {
+        let headers = headers();
+        let content_type: ContentType = headers.get_typed().unwrap();
+        assert_eq!(content_type.charset(), Some("utf-8"));
+        let id: MessageId = headers.get_typed().unwrap();
+        assert_eq!(id, MessageId::new("1@example.org".to_string()));
+
+        // Not registered, or registered but missing
+        assert!(headers.get_typed::<Priority>().is_err());
+        assert!(headers.get_typed::<ListId>().is_err());
+
+        register::<Priority>("X-Test-Priority");
+        assert_eq!(registered_name::<Priority>().unwrap(), "x-test-priority");
+        assert_eq!(headers.get_typed::<Priority>().unwrap(), Priority(3));
+    }
+
+    #[test]
+    fn test_get_any() {
+        register::<Level>("X-Test-Level");
+        for header in headers().iter() {
+            let value = header.get_any().unwrap();
+            match header.name.as_str() {
+                "Content-Type" => assert!(value.is::<ContentType>()),
+                "message-id" => assert!(value.is::<MessageId>()),
+                "X-Test-Level" => {
+                    assert_eq!(
+                        value.downcast_ref::<Level>(),
+                        Some(&Level("debug".to_string()))
+                    )
+                }
+                "Subject" => assert_eq!(value.downcast_ref::<String>(), Some(&"Hello".to_string())),
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Repairs for the common ways in which the content of messages is broken,
+//! which are made to salvage what can be of it unless `ParseOptions::strict`
+//! is set.
+//!
+//! A body is only repaired when it can't be decoded as it is, and then only
+//! as it's decoded, so the part keeps the bytes it was parsed from. Each
+//! repair describes what was fixed, which the parser records as a
+//! `ParseWarning`.
+use std::ops::Range;
+
+use super::mimeheaders::MimeContentTransferEncoding;
+use super::rfc2045::Base64BodyDecoder;
+
+// `body` repaired so that it can be decoded with `encoding`, along with a
+// description of each fix, or None if it needs no repair.
+pub(crate) fn repair_body(
+    body: &[u8],
+    encoding: MimeContentTransferEncoding,
+) -> Option<(Vec<u8>, Vec<String>)> {
+    match encoding {
+        MimeContentTransferEncoding::Identity => None,
+        MimeContentTransferEncoding::QuotedPrintable => repair_quoted_printable(body),
+        MimeContentTransferEncoding::Base64 => repair_base64(body),
+    }
+}
+
+// Decode as much of `body` as makes sense as base64, and encode it again.
+fn repair_base64(body: &[u8]) -> Option<(Vec<u8>, Vec<String>)> {
+    let mut decoder = Base64BodyDecoder::repairing();
+    let mut content = Vec::new();
+    decoder.decode(body, &mut content);
+
+    let mut fixes = Vec::new();
+    if decoder.stray > 0 {
+        let stray = decoder.stray;
+        fixes.push(format!("Ignored {} stray characters in base64 body", stray));
+    }
+    if decoder.invalid {
+        fixes.push("Decoded base64 body with padding in the middle".to_string());
+    }
+    if decoder.cut_off || decoder.group == 1 {
+        fixes.push("Dropped incomplete group of base64 body".to_string());
+    } else if decoder.group > 1 {
+        fixes.push("Added missing padding to base64 body".to_string());
+    }
+    if fixes.is_empty() {
+        return None;
+    }
+
+    let mut repaired = MimeContentTransferEncoding::Base64.encode(&content);
+    if body.ends_with(b"\n") {
+        repaired.push_str("\r\n");
+    }
+    Some((repaired.into_bytes(), fixes))
+}
+
+// Escape each `=` in `body` which doesn't start an escape or soft line
+// break, and drop one left dangling at its end.
+fn repair_quoted_printable(body: &[u8]) -> Option<(Vec<u8>, Vec<String>)> {
+    let mut repaired = Vec::with_capacity(body.len());
+    let mut bare = 0;
+    let mut padded_breaks = 0;
+    let mut dangling = false;
+    let mut rest = body;
+    while let Some(i) = rest.iter().position(|&b| b == b'=') {
+        repaired.extend_from_slice(&rest[..i]);
+        let after = &rest[i + 1..];
+        let is_space = |&&b: &&u8| b == b' ' || b == b'\t';
+        let padding = after.iter().take_while(is_space).count();
+        let line_break = &after[padding..];
+        if after.len() >= 2 && after[..2].iter().all(u8::is_ascii_hexdigit) {
+            repaired.extend_from_slice(&rest[i..i + 3]);
+            rest = &after[2..];
+        } else if line_break.starts_with(b"\r\n") || line_break.starts_with(b"\n") {
+            if padding > 0 {
+                padded_breaks += 1;
+            }
+            repaired.push(b'=');
+            rest = line_break;
+        } else if line_break.is_empty() {
+            dangling = true;
+            rest = line_break;
+        } else {
+            bare += 1;
+            repaired.extend_from_slice(b"=3D");
+            rest = after;
+        }
+    }
+    repaired.extend_from_slice(rest);
+
+    let mut fixes = Vec::new();
+    if bare > 0 {
+        fixes.push(format!(
+            "Escaped {} bare '=' in quoted-printable body",
+            bare
+        ));
+    }
+    if padded_breaks > 0 {
+        fixes.push("Removed whitespace after soft line breaks".to_string());
+    }
+    if dangling {
+        fixes.push("Removed '=' at the end of quoted-printable body".to_string());
+    }
+    if fixes.is_empty() {
+        None
+    } else {
+        Some((repaired, fixes))
+    }
+}
+
+// `span` of `body`, the last part of a multipart with `boundary` which
+// lacks a closing boundary, without the start of a boundary cut off at
+// its end, or None if it doesn't end with one.
+pub(crate) fn trim_partial_boundary(
+    body: &str,
+    span: Range<usize>,
+    boundary: &str,
+) -> Option<Range<usize>> {
+    let text = body[span.clone()].trim_end();
+    let line_start = text.rfind('\n').map_or(0, |i| i + 1);
+    let line = &text[line_start..];
+    if line.len() > 2 && format!("--{}--", boundary).starts_with(line) {
+        Some(span.start..span.start + line_start)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repair_base64() {
+        let tests = [
+            ("SGVsbG8=\r\n", None),
+            ("SGVs*bG8=", Some(("SGVsbG8=", 1))),
+            ("SGVsbG8", Some(("SGVsbG8=", 1))),
+            ("SGVsbA==bG8=", Some(("SGVsbGxv", 1))),
+            ("SGVsbG8=A", Some(("SGVsbG8=", 2))),
+            ("SG-Vs*bG8", Some(("SGVsbG8=", 2))),
+        ];
+        for &(body, expected) in tests.iter() {
+            let repaired = repair_body(body.as_bytes(), MimeContentTransferEncoding::Base64);
+            let repaired = repaired
+                .as_ref()
+                .map(|(repaired, fixes)| (std::str::from_utf8(repaired).unwrap(), fixes.len()));
+            assert_eq!(repaired, expected, "{}", body);
+        }
+    }
+
+    #[test]
+    fn test_repair_quoted_printable() {
+        let tests = [
+            ("caf=C3=A9 =\r\nau lait\r\n", None),
+            ("1 + 1 = 2\r\n", Some("1 + 1 =3D 2\r\n")),
+            ("caf=C3=A9=", Some("caf=C3=A9")),
+            ("caf=C3=A9= \r\nau lait", Some("caf=C3=A9=\r\nau lait")),
+            ("=\r=", Some("=3D\r")),
+        ];
+        for &(body, expected) in tests.iter() {
+            let encoding = MimeContentTransferEncoding::QuotedPrintable;
+            let repaired = repair_body(body.as_bytes(), encoding);
+            assert_eq!(
+                repaired.as_ref().map(|(repaired, _)| repaired.as_slice()),
+                expected.map(str::as_bytes),
+                "{}",
+                body
+            );
+            if let Some(repaired) = expected {
+                assert!(MimeContentTransferEncoding::QuotedPrintable
+                    .decode(repaired)
+                    .is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn test_trim_partial_boundary() {
+        let body = "--b\r\n\r\nHello\r\n--b-";
+        assert_eq!(trim_partial_boundary(body, 5..body.len(), "b"), Some(5..14));
+        let body = "--b\r\n\r\nHello\r\n--b\r\n";
+        assert_eq!(trim_partial_boundary(body, 5..body.len(), "b"), Some(5..14));
+        let body = "--b\r\n\r\nHello\r\n-- \r\n";
+        assert_eq!(trim_partial_boundary(body, 5..body.len(), "b"), None);
+        let body = "--b\r\n\r\nHello --b-";
+        assert_eq!(trim_partial_boundary(body, 5..body.len(), "b"), None);
+    }
+}
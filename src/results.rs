@@ -1,31 +1,182 @@
-use std::error::Error;
-use std::fmt;
+use core::error::Error;
+use core::fmt;
 
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+
+/// An error from parsing a message, or any of the values within it.
+///
+/// Positions are byte offsets into the input given to the parser that
+/// failed, which for header values is the value alone.
 #[derive(Debug)]
-pub struct ParsingError {
-    desc: String, // FIXME: Too basic
+pub enum ParsingError {
+    /// The parser found a character it didn't expect, or reached the end
+    /// of its input (in which case `found` is None).
+    UnexpectedChar {
+        pos: usize,
+        found: Option<char>,
+        expected: String,
+    },
+    /// A token was read in full, but isn't a valid value of its kind.
+    InvalidToken {
+        pos: usize,
+        kind: String,
+        token: String,
+    },
+    /// A header which was required is missing.
+    MissingHeader { name: String },
+    /// A charset or transfer encoding is unknown, or the content couldn't
+    /// be decoded with it.
+    InvalidEncoding { encoding: String },
+    /// The input exceeded one of the limits set on parsing, such as the
+    /// number of headers, which is described by `limit`.
+    LimitExceeded { limit: String, max: usize },
+    /// An error from within a larger item, such as the value of a header.
+    Context {
+        context: String,
+        source: Box<ParsingError>,
+    },
+    /// Any other error, described by the string.
+    Other(String),
 }
 
 impl fmt::Display for ParsingError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.description().fmt(f)
+        match *self {
+            ParsingError::UnexpectedChar {
+                pos,
+                found: Some(c),
+                ref expected,
+            } => write!(f, "Expected {} at {}, found {:?}", expected, pos, c),
+            ParsingError::UnexpectedChar {
+                pos,
+                found: None,
+                ref expected,
+            } => write!(f, "Expected {} at {}, found end of input", expected, pos),
+            ParsingError::InvalidToken {
+                pos,
+                ref kind,
+                ref token,
+            } => write!(f, "Invalid {} at {}: {}", kind, pos, token),
+            ParsingError::MissingHeader { ref name } => write!(f, "Missing header: {}", name),
+            ParsingError::InvalidEncoding { ref encoding } => {
+                write!(f, "Invalid encoding: {}", encoding)
+            }
+            ParsingError::LimitExceeded { ref limit, max } => {
+                write!(f, "Exceeded {} limit of {}", limit, max)
+            }
+            ParsingError::Context {
+                ref context,
+                ref source,
+            } => write!(f, "{}: {}", context, source),
+            ParsingError::Other(ref desc) => desc.fmt(f),
+        }
     }
 }
 
 impl Error for ParsingError {
-    fn description(&self) -> &str {
-        &self.desc[..]
-    }
-
-    fn cause(&self) -> Option<&dyn Error> {
-        None
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ParsingError::Context { ref source, .. } => Some(&**source),
+            _ => None,
+        }
     }
 }
 
 impl ParsingError {
     pub fn new(desc: String) -> Self {
-        ParsingError { desc }
+        ParsingError::Other(desc)
+    }
+
+    /// Wrap this error to say what was being parsed when it happened.
+    pub fn context<S: Into<String>>(self, context: S) -> Self {
+        ParsingError::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// The position at which the innermost error happened, if known.
+    pub fn position(&self) -> Option<usize> {
+        match *self {
+            ParsingError::UnexpectedChar { pos, .. } | ParsingError::InvalidToken { pos, .. } => {
+                Some(pos)
+            }
+            ParsingError::Context { ref source, .. } => source.position(),
+            _ => None,
+        }
     }
 }
 
 pub type ParsingResult<T> = Result<T, ParsingError>;
+
+/// The kinds of problem which the parser tolerates, but reports as a
+/// `ParseWarning`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum WarningCode {
+    /// The message has no `Date` header
+    MissingDate,
+    /// The message has no `From` header
+    MissingFrom,
+    /// A header contains an encoded word which can't be decoded, and which
+    /// is left as it is
+    InvalidEncodedWord,
+    /// The headers of a part aren't followed by a blank line
+    MissingBlankLine,
+    /// A multipart part has no boundary, so is treated as a single part
+    MissingBoundary,
+    /// A multipart part doesn't end with a closing boundary
+    UnterminatedMultipart,
+    /// A part couldn't be parsed, so was left out of its parent
+    DroppedPart,
+    /// A body couldn't be decoded with its transfer encoding, so is
+    /// repaired as it's decoded to salvage what it can of its content
+    RepairedEncoding,
+    /// A multipart part ends with a boundary which was cut off, and which
+    /// was removed from its last part
+    PartialBoundary,
+}
+
+/// A problem found while parsing a message, which was worked around rather
+/// than failing the parse.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct ParseWarning {
+    pub code: WarningCode,
+    pub message: String,
+    /// The byte offset into the input where the problem was found
+    pub offset: usize,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {}", self.message, self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_chain() {
+        let err = ParsingError::UnexpectedChar {
+            pos: 4,
+            found: Some('x'),
+            expected: "'@'".to_string(),
+        }
+        .context("Invalid To header");
+        assert_eq!(err.position(), Some(4));
+        assert_eq!(
+            err.to_string(),
+            "Invalid To header: Expected '@' at 4, found 'x'"
+        );
+        assert_eq!(
+            err.source().unwrap().to_string(),
+            "Expected '@' at 4, found 'x'"
+        );
+
+        let err = ParsingError::new("Couldn't parse".to_string());
+        assert_eq!(err.position(), None);
+        assert!(err.source().is_none());
+    }
+}
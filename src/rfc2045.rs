@@ -1,4 +1,6 @@
-//! Module for dealing with RFC2045 style headers.
+//! Module for dealing with RFC2045 style headers, and base64 bodies.
+use super::charset::{self, DecodeOptions};
+use super::results::{ParsingError, ParsingResult};
 use super::rfc5322::Rfc5322Parser;
 
 use std::collections::HashMap;
@@ -19,15 +21,7 @@ impl<'s> Rfc2045Parser<'s> {
     }
 
     fn consume_token(&mut self) -> Option<String> {
-        let token = self.parser.consume_while(|c| {
-            match c {
-                // Not any tspecials
-                '(' | ')' | '<' | '>' | '@' | ',' | ';' | ':' | '\\' | '\"' | '/' | '[' | ']'
-                | '?' | '=' => false,
-                '!'..='~' => true,
-                _ => false,
-            }
-        });
+        let token = self.parser.consume_while(is_token_char);
 
         if !token.is_empty() {
             Some(token)
@@ -36,45 +30,304 @@ impl<'s> Rfc2045Parser<'s> {
         }
     }
 
-    /// Consume up to all of the input into the value and a hashmap
-    /// over parameters to values.
-    pub fn consume_all(&mut self) -> (String, HashMap<String, String>) {
-        let value = self.parser.consume_while(|c| c != ';');
-
-        // Find the parameters
-        let mut params = HashMap::new();
-        while !self.parser.eof() {
-            // Eat the ; and any whitespace
-            assert_eq!(self.parser.consume_char(), Some(';'));
+    /// Consume the value before any parameters.
+    pub fn consume_value(&mut self) -> String {
+        self.parser.consume_while(|c| c != ';')
+    }
 
-            // RFC ignorant mail systems may append a ';' without a parameter after.
-            // This violates the RFC but does happen, so deal with it.
-            if self.parser.eof() {
+    /// Consume the parameters following the value, as a list of their
+    /// lowercased names and values in the order they first appear.
+    ///
+    /// RFC 2231 continuations are joined and their values decoded, and
+    /// malformed parameters are skipped.
+    pub fn consume_parameters(&mut self) -> Vec<(String, String)> {
+        let mut params = Vec::new();
+        loop {
+            // Skip to the next ;, past whatever is left of a malformed parameter
+            self.parser.consume_while_str(|c| c != ';');
+            if self.parser.consume_char().is_none() {
                 break;
             }
-
+            // RFC ignorant mail systems may append a ';' without a parameter after.
+            // This violates the RFC but does happen, so deal with it.
             self.parser.consume_linear_whitespace();
-
             let attribute = self.consume_token();
             self.parser.consume_linear_whitespace();
-            assert_eq!(self.parser.consume_char(), Some('='));
+            if self.parser.eof() || self.parser.peek() != '=' {
+                continue;
+            }
+            self.parser.consume_char();
             self.parser.consume_linear_whitespace();
             // Value can be token or quoted-string
-            let value = if self.parser.peek() == '"' {
+            let value = if !self.parser.eof() && self.parser.peek() == '"' {
                 self.parser.consume_quoted_string()
             } else {
                 self.consume_token()
             };
 
             if let (Some(attrib), Some(val)) = (attribute, value) {
-                params.insert(attrib, val);
+                params.push((attrib, val));
             }
         }
 
+        decode_parameters(params)
+    }
+
+    /// Consume up to all of the input into the value and a hashmap
+    /// over parameters to values.
+    pub fn consume_all(&mut self) -> (String, HashMap<String, String>) {
+        let value = self.consume_value();
+        let params = self.consume_parameters().into_iter().collect();
         (value, params)
     }
 }
 
+/// Whether `c` can be part of an RFC 2045 token.
+pub(crate) fn is_token_char(c: char) -> bool {
+    match c {
+        // Not any tspecials
+        '(' | ')' | '<' | '>' | '@' | ',' | ';' | ':' | '\\' | '\"' | '/' | '[' | ']' | '?'
+        | '=' => false,
+        '!'..='~' => true,
+        _ => false,
+    }
+}
+
+// The parts of a parameter, which RFC 2231 allows to be split into numbered
+// sections, each of which may be percent encoded.
+#[derive(Default)]
+struct ParameterParts {
+    value: Option<String>,
+    sections: Vec<(u32, bool, String)>,
+}
+
+impl ParameterParts {
+    fn join(mut self) -> String {
+        if self.sections.is_empty() {
+            return self.value.unwrap_or_default();
+        }
+        self.sections.sort_by_key(|&(index, _, _)| index);
+
+        // Only the first section declares the charset and language
+        let mut charset = None;
+        let mut bytes = Vec::new();
+        for (i, (_, encoded, value)) in self.sections.iter().enumerate() {
+            let mut value = &value[..];
+            if i == 0 && *encoded {
+                let mut parts = value.splitn(3, '\'');
+                if let (Some(name), Some(_language), Some(rest)) =
+                    (parts.next(), parts.next(), parts.next())
+                {
+                    if !name.is_empty() {
+                        charset = Some(name);
+                    }
+                    value = rest;
+                }
+            }
+            if *encoded {
+                bytes.extend(percent_decode(value));
+            } else {
+                bytes.extend_from_slice(value.as_bytes());
+            }
+        }
+
+        charset::decode(&bytes, charset, &DecodeOptions::default())
+            .unwrap_or_else(|_| String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+// Decode the %XX escapes of an RFC 2231 encoded value, leaving any invalid
+// ones as they are.
+pub(crate) fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+// Join the RFC 2231 continuations of `params` and decode their values,
+// keeping the parameters in the order they first appear.
+fn decode_parameters(params: Vec<(String, String)>) -> Vec<(String, String)> {
+    let mut parts: Vec<(String, ParameterParts)> = Vec::new();
+    for (name, value) in params {
+        let name = name.to_ascii_lowercase();
+        let (name, encoded) = match name.strip_suffix('*') {
+            Some(name) => (name.to_string(), true),
+            None => (name, false),
+        };
+        let (name, index) = match name.rfind('*') {
+            Some(i) => match name[i + 1..].parse::<u32>() {
+                Ok(index) => (name[..i].to_string(), Some(index)),
+                Err(_) => (name, None),
+            },
+            None => (name, None),
+        };
+
+        let i = match parts.iter().position(|(n, _)| *n == name) {
+            Some(i) => i,
+            None => {
+                parts.push((name, ParameterParts::default()));
+                parts.len() - 1
+            }
+        };
+        let entry = &mut parts[i].1;
+        match index {
+            Some(index) => entry.sections.push((index, encoded, value)),
+            // An encoded value without an index is a single section
+            None if encoded => entry.sections.push((0, true, value)),
+            None => entry.value = Some(value),
+        }
+    }
+
+    parts
+        .into_iter()
+        .map(|(name, parts)| (name, parts.join()))
+        .collect()
+}
+
+/// Decode a quoted-printable body, as RFC 2045 Section 6.7 sets out.
+///
+/// Bytes other than escapes are kept as they are, whether or not they're
+/// ASCII, and soft line breaks may end in a bare LF.
+pub(crate) fn decode_quoted_printable(input: &[u8]) -> ParsingResult<Vec<u8>> {
+    let invalid = || ParsingError::InvalidEncoding {
+        encoding: "quoted-printable".to_string(),
+    };
+    let hex = |b: u8| (b as char).to_digit(16).map(|digit| digit as u8);
+    let mut output = Vec::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(i) = rest.iter().position(|&b| b == b'=') {
+        output.extend_from_slice(&rest[..i]);
+        rest = &rest[i + 1..];
+        // A soft line break
+        let line_break = rest.strip_prefix(b"\r\n");
+        if let Some(after) = line_break.or_else(|| rest.strip_prefix(b"\n")) {
+            rest = after;
+            continue;
+        }
+        match *rest {
+            [high, low, ..] => {
+                let byte = hex(high).zip(hex(low)).ok_or_else(invalid)?;
+                output.push(byte.0 << 4 | byte.1);
+                rest = &rest[2..];
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    output.extend_from_slice(rest);
+    Ok(output)
+}
+
+/// Decoder for base64 encoded bodies, which decodes them a piece at a time
+/// rather than needing the whole body at once.
+///
+/// Line breaks of any kind, lines of any length, and anything else outside
+/// the base64 alphabet are ignored, as RFC 2045 Section 6.8 says, and
+/// padding missing from the end is tolerated.
+#[derive(Debug, Clone, Default)]
+pub struct Base64BodyDecoder {
+    // The bits decoded but not yet output, of which `bit_count` are left
+    bits: u32,
+    bit_count: u32,
+    // The number of characters so far of the current group of four
+    pub(crate) group: usize,
+    padded: bool,
+    // Whether anything followed the padding
+    pub(crate) invalid: bool,
+    // Whether padding cut off a group after one character
+    pub(crate) cut_off: bool,
+    // The number of characters ignored which weren't whitespace
+    pub(crate) stray: usize,
+    // Whether to carry on decoding after padding, for `repair`
+    repair: bool,
+}
+
+impl Base64BodyDecoder {
+    pub fn new() -> Base64BodyDecoder {
+        Base64BodyDecoder::default()
+    }
+
+    // A decoder which decodes whatever follows padding too, rather than
+    // ignoring it, as a repair of the body.
+    pub(crate) fn repairing() -> Base64BodyDecoder {
+        Base64BodyDecoder {
+            repair: true,
+            ..Base64BodyDecoder::default()
+        }
+    }
+
+    /// Decode the next piece of the body, adding the bytes it decodes to
+    /// `output`.
+    pub fn decode(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        for &b in input {
+            let value = match b {
+                b'A'..=b'Z' => b - b'A',
+                b'a'..=b'z' => b - b'a' + 26,
+                b'0'..=b'9' => b - b'0' + 52,
+                b'+' => 62,
+                b'/' => 63,
+                b'=' => {
+                    self.cut_off |= self.group == 1;
+                    // The bits left over from an incomplete group are only
+                    // padding
+                    self.group = 0;
+                    self.bit_count = 0;
+                    self.padded = true;
+                    continue;
+                }
+                _ if b.is_ascii_whitespace() => continue,
+                _ => {
+                    self.stray += 1;
+                    continue;
+                }
+            };
+            if self.padded {
+                self.invalid = true;
+                if !self.repair {
+                    continue;
+                }
+                self.padded = false;
+            }
+            self.bits = (self.bits << 6 | u32::from(value)) & 0xFFF;
+            self.bit_count += 6;
+            if self.bit_count >= 8 {
+                self.bit_count -= 8;
+                output.push((self.bits >> self.bit_count) as u8);
+            }
+            self.group = (self.group + 1) % 4;
+        }
+    }
+
+    /// Finish decoding the body, which fails if there was anything after
+    /// the padding, or a group of four was cut off after one character.
+    pub fn finish(self) -> ParsingResult<()> {
+        if self.invalid || self.cut_off || self.group == 1 {
+            Err(ParsingError::InvalidEncoding {
+                encoding: "base64".to_string(),
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -115,6 +368,21 @@ mod tests {
                 output: ("foo/bar", vec![("foo", "bar"), ("baz", "qux")]),
                 name: "Parameter with space",
             },
+            ParserTestCase {
+                input: "foo/bar; Foo=bar; baz; =qux; quux=\"a;b\"",
+                output: ("foo/bar", vec![("foo", "bar"), ("quux", "a;b")]),
+                name: "Malformed parameters",
+            },
+            ParserTestCase {
+                input: "foo/bar; foo*1=\" baz\"; foo*0=bar; qux*=utf-8'en'%E2%82%AC%20",
+                output: ("foo/bar", vec![("foo", "bar baz"), ("qux", "€ ")]),
+                name: "RFC 2231 parameters",
+            },
+            ParserTestCase {
+                input: "foo/bar; foo*0*=iso-8859-1''%E9t; foo*1=%E9; foo=fallback",
+                output: ("foo/bar", vec![("foo", "ét%E9")]),
+                name: "RFC 2231 continuations with charset",
+            },
         ];
 
         for test in tests.into_iter() {
@@ -131,4 +399,32 @@ mod tests {
             assert!(parameters == expected_params, test.name);
         }
     }
+
+    #[test]
+    fn test_base64_body_decoder() {
+        let decode = |pieces: &[&str]| {
+            let mut decoder = Base64BodyDecoder::new();
+            let mut output = Vec::new();
+            for piece in pieces {
+                decoder.decode(piece.as_bytes(), &mut output);
+            }
+            decoder.finish().map(|()| output)
+        };
+        assert_eq!(decode(&["SGVsbG8sIHdvcmxk"]).unwrap(), b"Hello, world");
+        // Split anywhere, with any line breaks
+        assert_eq!(
+            decode(&["SGV", "sbG\r\n8s", "IH\rdv\ncm", "xk\r\n"]).unwrap(),
+            b"Hello, world"
+        );
+        assert_eq!(decode(&["SGVsbG8=\r\n"]).unwrap(), b"Hello");
+        assert_eq!(decode(&["SGVsbG8"]).unwrap(), b"Hello");
+        assert_eq!(decode(&["SGVsbA", "="]).unwrap(), b"Hell");
+        assert_eq!(decode(&[""]).unwrap(), b"");
+
+        assert!(decode(&["SGVsbG8=", "SGVs"]).is_err());
+        assert!(decode(&["SGVsb"]).is_err());
+        assert!(decode(&["SGVsb=", "=="]).is_err());
+        // Stray characters are ignored
+        assert_eq!(decode(&["SG-Vs*bG8="]).unwrap(), b"Hello");
+    }
 }
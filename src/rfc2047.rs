@@ -2,8 +2,9 @@
 // use for to_ascii_lowercase
 use base64::decode;
 
-use encoding::label::encoding_from_whatwg_label;
-use encoding::DecoderTrap;
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
+use super::charset::{self, DecodeOptions};
 
 /// Decode an RFC 2047 string (`s`) into a Rust String.
 ///
@@ -11,28 +12,146 @@ use encoding::DecoderTrap;
 /// "B" encoding (BASE64)
 /// [unstable]
 pub fn decode_rfc2047(s: &str) -> Option<String> {
-    let parts: Vec<&str> = s.split('?').collect();
-    if parts.len() != 5 || parts[0] != "=" || parts[4] != "=" {
-        None
-    } else {
-        let charset = parts[1].to_ascii_lowercase();
-        let encoding = parts[2].to_ascii_lowercase();
-        let content = parts[3];
-
-        let bytes = match &encoding[..] {
-            "q" => decode_q_encoding(content),
-            "b" => decode_base64_encoding(content),
-            _ => panic!("Unknown encoding type"),
-        };
+    decode_rfc2047_with_options(s, &DecodeOptions::default())
+}
+
+/// Decode an RFC 2047 string (`s`) into a Rust String, falling back on
+/// other charsets as `options` allow when the declared one is unknown or
+/// wrong.
+pub fn decode_rfc2047_with_options(s: &str, options: &DecodeOptions) -> Option<String> {
+    let (charset, encoding, content) = split_encoded_word(s)?;
+    let bytes = match &encoding.to_ascii_lowercase()[..] {
+        // Underscores stand for spaces in Q encoded words
+        "q" => decode_q_encoding(&content.replace('_', "=20")),
+        "b" => decode_base64_encoding(content),
+        _ => return None,
+    };
+
+    match bytes {
+        Ok(b) => charset::decode(&b, Some(charset), options).ok(),
+        _ => None,
+    }
+}
+
+/// Decode any encoded words in the unstructured text `s`.
+///
+/// Whitespace between adjacent encoded words is dropped, as per RFC 2047
+/// Section 6.2, so that text split across several words is joined back up.
+/// Anything that fails to decode is left as it is.
+pub fn decode_text(s: &str) -> String {
+    let mut decoded = String::new();
+    // The start of the text not yet pushed on to `decoded`
+    let mut start = 0;
+    let mut after_word = false;
+    let mut pos = 0;
+
+    while let Some(offset) = s[pos..].find("=?") {
+        let word_start = pos + offset;
+        let word = find_encoded_word_end(s, word_start)
+            .and_then(|end| decode_rfc2047(&s[word_start..end]).map(|word| (end, word)));
+        match word {
+            Some((end, word)) => {
+                let between = &s[start..word_start];
+                if !after_word || !between.chars().all(|c| c.is_ascii_whitespace()) {
+                    decoded.push_str(between);
+                }
+                decoded.push_str(&word);
+                after_word = true;
+                start = end;
+                pos = end;
+            }
+            None => pos = word_start + 1,
+        }
+    }
+    decoded.push_str(&s[start..]);
+    decoded
+}
+
+// Find where the encoded word starting at `start` ends, if it looks like one.
+fn find_encoded_word_end(s: &str, start: usize) -> Option<usize> {
+    let rest = &s[start + 2..];
+    let charset_end = rest.find('?')?;
+    let encoding_end = charset_end + 1 + rest[charset_end + 1..].find('?')?;
+    if rest[..encoding_end].contains(char::is_whitespace) {
+        return None;
+    }
+    let content_start = encoding_end + 1;
+    let content = &rest[content_start..];
+    let mut end = content.find("?=")?;
+    if rest[charset_end + 1..encoding_end].eq_ignore_ascii_case("q") {
+        // Some clients leave `?` unencoded in Q encoded text, so a `?=`
+        // followed by hex digits may be a `?` and an escape, unless nothing
+        // else could end the word
+        while is_hex_escape(&content[end + 1..]) {
+            match content[end + 2..].find("?=") {
+                Some(next) => end = end + 2 + next,
+                None => break,
+            }
+        }
+    }
+    Some(start + 2 + content_start + end + 2)
+}
+
+fn is_hex_escape(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    bytes.len() >= 3
+        && bytes[0] == b'='
+        && bytes[1].is_ascii_hexdigit()
+        && bytes[2].is_ascii_hexdigit()
+}
 
-        // XXX: Relies on WHATWG labels, rather than MIME labels for
-        // charset. Consider adding mapping upstream.
-        let decoder = encoding_from_whatwg_label(&charset[..]);
+// Split an encoded word into its charset, encoding and encoded text,
+// dropping any RFC 2231 language tag from the charset.
+fn split_encoded_word(s: &str) -> Option<(&str, &str, &str)> {
+    if s.len() < 4 || !s.starts_with("=?") || !s.ends_with("?=") {
+        return None;
+    }
+    let mut parts = s[2..s.len() - 2].splitn(3, '?');
+    let charset = parts.next()?;
+    let encoding = parts.next()?;
+    let content = parts.next()?;
+    if content.contains('?') && !encoding.eq_ignore_ascii_case("q") {
+        return None;
+    }
+    let charset = match charset.find('*') {
+        Some(pos) => &charset[..pos],
+        None => charset,
+    };
+    Some((charset, encoding, content))
+}
 
-        match (bytes, decoder) {
-            (Ok(b), Some(d)) => d.decode(&b, DecoderTrap::Replace).ok(),
-            _ => None,
+/// Check that `s` is a well-formed encoded word in a known charset, which
+/// `decode_rfc2047` can decode without risking a panic.
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+pub(crate) fn is_valid_encoded_word(s: &str) -> bool {
+    let (charset, encoding, content) = match split_encoded_word(s) {
+        Some(parts) => parts,
+        None => return false,
+    };
+    if charset::lookup(charset).is_none() {
+        return false;
+    }
+    match &encoding.to_ascii_lowercase()[..] {
+        "q" => {
+            let bytes = content.as_bytes();
+            let mut i = 0;
+            while i < bytes.len() {
+                if bytes[i] == b'=' {
+                    if i + 2 >= bytes.len()
+                        || !bytes[i + 1].is_ascii_hexdigit()
+                        || !bytes[i + 2].is_ascii_hexdigit()
+                    {
+                        return false;
+                    }
+                    i += 3;
+                } else {
+                    i += 1;
+                }
+            }
+            true
         }
+        "b" => decode_base64_encoding(content).is_ok(),
+        _ => false,
     }
 }
 
@@ -44,10 +163,11 @@ pub fn decode_q_encoding(s: &str) -> Result<Vec<u8>, String> {
         match char_iter.next() {
             Some('=') => {
                 let mut hex_string = String::new();
-                match char_iter.next().unwrap() {
+                let truncated = || "Unexpected end of encoded text".to_string();
+                match char_iter.next().ok_or_else(truncated)? {
                     '\r' => {
                         // Possible continuation - expect the next character to be a newline
-                        if char_iter.next().unwrap() == '\n' {
+                        if char_iter.next() == Some('\n') {
                             continue;
                         } else {
                             return Err("Invalid line endings in text".to_string());
@@ -56,7 +176,7 @@ pub fn decode_q_encoding(s: &str) -> Result<Vec<u8>, String> {
                     '\n' => continue, // treat unix line endings similar to CRLF
                     c => {
                         hex_string.push(c);
-                        hex_string.push(char_iter.next().unwrap());
+                        hex_string.push(char_iter.next().ok_or_else(truncated)?);
                     }
                 }
                 let hex_string_slice = &hex_string[..];
@@ -82,6 +202,28 @@ fn decode_base64_encoding(s: &str) -> Result<Vec<u8>, String> {
     }
 }
 
+/// Encode `s` as UTF-8 "B" encoded words, split at character boundaries so
+/// that none is longer than the 75 characters RFC 2047 allows.
+pub fn encode_base64_words(s: &str) -> String {
+    // Leaving room for "=?utf-8?b?" and "?=", 45 bytes encode to the 60
+    // characters which remain
+    const MAX_WORD_BYTES: usize = 45;
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        if i + c.len_utf8() - start > MAX_WORD_BYTES {
+            words.push(&s[start..i]);
+            start = i;
+        }
+    }
+    words.push(&s[start..]);
+    words
+        .iter()
+        .map(|word| format!("=?utf-8?b?{}?=", base64::encode(word)))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -96,6 +238,16 @@ mod tests {
         output: &'s [u8],
     }
 
+    #[test]
+    fn test_encode_base64_words() {
+        assert_eq!(encode_base64_words("Grüße"), "=?utf-8?b?R3LDvMOfZQ==?=");
+        let long = "ü".repeat(40);
+        let encoded = encode_base64_words(&long);
+        assert_eq!(encoded.split(' ').count(), 2);
+        assert!(encoded.split(' ').all(|word| word.len() <= 75));
+        assert_eq!(decode_text(&encoded), long);
+    }
+
     #[test]
     fn test_decode() {
         let tests = [
@@ -111,6 +263,10 @@ mod tests {
                 input: "=?utf-8?b?44GT44KT44Gr44Gh44Gv44CC?=",
                 output: "こんにちは。",
             },
+            DecodeTest {
+                input: "=?latin-1?q?caf=E9?=",
+                output: "café",
+            },
         ];
 
         for t in tests.iter() {
@@ -145,8 +301,6 @@ mod tests {
             "=?ISO-8859-1?b?-?=",
             // Not valid RFC 2047
             "=?Doesn't end with equals",
-            // Unknown charset
-            "=?NOCHARSET?q?foo?=",
         ];
 
         for t in tests.iter() {
@@ -154,4 +308,74 @@ mod tests {
             assert!(decode_rfc2047(*t).is_none());
         }
     }
+
+    #[test]
+    fn test_decode_variants() {
+        let tests = [
+            // RFC 2231 language tag
+            DecodeTest {
+                input: "=?utf-8*en?Q?Hello_world?=",
+                output: "Hello world",
+            },
+            DecodeTest {
+                input: "=?ISO-8859-1*fr-CA?B?Q2Fm6Q==?=",
+                output: "Café",
+            },
+            // Unencoded ?s in Q encoded text
+            DecodeTest {
+                input: "=?utf-8?Q?Why?_Because?=",
+                output: "Why? Because",
+            },
+        ];
+
+        for t in tests.iter() {
+            assert_eq!(decode_rfc2047(t.input).unwrap(), t.output.to_string());
+        }
+        assert!(decode_rfc2047("=?utf-8?B?SGk?gd29ybGQ=?=").is_none());
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let tests = [
+            DecodeTest {
+                input: "=?utf-8?q?Gr=C3=BC=C3=9Fe_?= =?utf-8?q?aus?=\r\n\t\
+                        =?utf-8?q?_K=C3=B6ln?=",
+                output: "Grüße aus Köln",
+            },
+            DecodeTest {
+                input: "Plain =?utf-8?q?encoded?= plain",
+                output: "Plain encoded plain",
+            },
+            DecodeTest {
+                input: "=?utf-8?q?one?= =?bad =?utf-8?q?two?=",
+                output: "one =?bad two",
+            },
+            DecodeTest {
+                input: "=?utf-8?Q?What?=3F?= =?utf-8?Q?_Huh??=",
+                output: "What?? Huh?",
+            },
+            DecodeTest {
+                input: "Ends =?utf-8?q?with=",
+                output: "Ends =?utf-8?q?with=",
+            },
+        ];
+
+        for t in tests.iter() {
+            assert_eq!(decode_text(t.input), t.output.to_string());
+        }
+    }
+
+    #[test]
+    fn test_decode_fallback() {
+        // Unknown and mislabelled charsets are sniffed
+        assert_eq!(decode_rfc2047("=?NOCHARSET?q?foo?=").unwrap(), "foo");
+        assert_eq!(decode_rfc2047("=?utf-8?q?caf=E9?=").unwrap(), "café");
+
+        let options = DecodeOptions {
+            fallback_charset: None,
+            on_error: charset::OnError::Strict,
+        };
+        assert!(decode_rfc2047_with_options("=?NOCHARSET?q?foo=E9?=", &options).is_none());
+        assert!(decode_rfc2047_with_options("=?utf-8?q?caf=E9?=", &options).is_none());
+    }
 }
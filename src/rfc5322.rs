@@ -1,5 +1,10 @@
 //! Module with helpers for dealing with RFC 5322
+use alloc::borrow::Cow;
+use core::ops::Range;
+use memchr::memchr2;
 
+#[cfg(not(feature = "std"))]
+use crate::prelude::*;
 use super::header::{Header, HeaderMap};
 use super::results::{ParsingError, ParsingResult};
 use super::rfc2047::decode_rfc2047;
@@ -19,6 +24,22 @@ trait Rfc5322Character {
     }
 }
 
+// Each of these classes is only ASCII characters, so they can be tested a
+// byte at a time
+impl Rfc5322Character for u8 {
+    fn is_ftext(&self) -> bool {
+        (*self as char).is_ftext()
+    }
+
+    fn is_special(&self) -> bool {
+        (*self as char).is_special()
+    }
+
+    fn is_vchar(&self) -> bool {
+        (*self as char).is_vchar()
+    }
+}
+
 impl Rfc5322Character for char {
     fn is_ftext(&self) -> bool {
         match *self {
@@ -71,7 +92,31 @@ impl<'s> Rfc5322Parser<'s> {
         }
     }
 
+    /// Make a new parser for the given string, starting from `pos` rather
+    /// than the beginning.
+    ///
+    /// Positions reported by the parser, such as header spans, are then
+    /// offsets into the whole of `source`.
+    /// [unstable]
+    pub fn new_at(source: &'s str, pos: usize) -> Rfc5322Parser<'s> {
+        Rfc5322Parser {
+            s: source,
+            pos,
+            pos_stack: Vec::new(),
+        }
+    }
+
+    /// Move back to `pos`, as given by `pos`, to backtrack after a test
+    /// fails.
+    /// [unstable]
+    pub fn set_pos(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
     /// Push the current position onto the stack.
+    ///
+    /// Noting `pos` and going back to it with `set_pos` is cheaper, where
+    /// there's only one position to go back to.
     /// [unstable]
     pub fn push_position(&mut self) {
         self.pos_stack.push(self.pos);
@@ -99,10 +144,51 @@ impl<'s> Rfc5322Parser<'s> {
     /// message = fields CRLF body`
     /// [unstable]
     pub fn consume_message(&mut self) -> Option<(HeaderMap, String)> {
+        let headers = self.consume_headers();
+
+        // Whatever remains is the body
+        let body = self.s[self.pos..].to_string();
+        self.pos = self.s.len();
+
+        Some((headers, body))
+    }
+
+    /// Consume the headers of a message, and the blank line ending them,
+    /// leaving the parser at the start of the body.
+    pub fn consume_headers(&mut self) -> HeaderMap {
+        // Nothing can exceed these limits, so there's no error to handle
+        self.consume_headers_with_limits(usize::MAX, usize::MAX)
+            .unwrap_or_default()
+    }
+
+    /// Consume the headers of a message, as with `consume_headers`, failing
+    /// with `ParsingError::LimitExceeded` if there are more than `max_count`
+    /// of them, or any is longer than `max_length` bytes including its name
+    /// and any folding.
+    pub fn consume_headers_with_limits(
+        &mut self,
+        max_count: usize,
+        max_length: usize,
+    ) -> ParsingResult<HeaderMap> {
         let mut headers = HeaderMap::new();
+        let mut count = 0;
         while !self.eof() {
+            let start = self.pos;
             let header = self.consume_header();
             if let Some(header) = header {
+                count += 1;
+                if count > max_count {
+                    return Err(ParsingError::LimitExceeded {
+                        limit: "header count".to_string(),
+                        max: max_count,
+                    });
+                }
+                if self.pos - start > max_length {
+                    return Err(ParsingError::LimitExceeded {
+                        limit: "header length".to_string(),
+                        max: max_length,
+                    });
+                }
                 headers.insert(header);
             } else {
                 // Check end of headers as marked by CRLF
@@ -113,12 +199,7 @@ impl<'s> Rfc5322Parser<'s> {
                 break;
             }
         }
-
-        // Whatever remains is the body
-        let body = self.s[self.pos..].to_string();
-        self.pos = self.s.len();
-
-        Some((headers, body))
+        Ok(headers)
     }
 
     /// Consume a header from the input.
@@ -130,46 +211,76 @@ impl<'s> Rfc5322Parser<'s> {
     /// field = field-name *LWSP ":" unstructured`
     /// [unstable]
     pub fn consume_header(&mut self) -> Option<Header> {
+        let start = self.pos;
+        let (name, value) = self.consume_field()?;
+        let folded_value = &self.s[value.clone()];
+        let mut field_value = String::new();
+        unfold(folded_value, &mut field_value);
+        let header = Header::new_raw(&self.s[name], field_value)
+            .with_folded_value(folded_value)
+            .with_raw_field(&self.s[start..self.pos]);
+        Some(header.with_span(start..self.pos))
+    }
+
+    // Consume a header as `consume_header` does, giving where its name and
+    // its value, still folded, are in the input rather than copying them.
+    pub(crate) fn consume_field(&mut self) -> Option<(Range<usize>, Range<usize>)> {
         let last_pos = self.pos;
         // Parse field-name
-        let field_name = self.consume_while(|c| c.is_ftext());
+        let name_len = self.consume_while_str(|c| c.is_ftext()).len();
+        let name = last_pos..last_pos + name_len;
         self.consume_linear_whitespace();
-        if field_name.is_empty() || self.eof() || self.peek() != ':' {
+        if name.is_empty() || self.eof() || self.peek() != ':' {
             // Fail to parse if we didn't see a field, we're at the end of input
             // or we haven't just seen a ":"
             self.pos = last_pos;
-            None
-        } else {
-            // Consume the ":" and any leading whitespace
-            self.consume_char();
-            self.consume_linear_whitespace();
-            let field_value = self.consume_unstructured();
-
-            // don't just panic!()
-            if !self.consume_linebreak() {
-                return None;
-            };
+            return None;
+        }
+        // Consume the ":" and any leading whitespace
+        self.consume_char();
+        self.consume_linear_whitespace();
+        let value_pos = self.pos;
+        self.skip_unstructured();
+        let value = value_pos..self.pos;
 
-            Some(Header::new(field_name, field_value))
+        // don't just panic!()
+        if self.consume_linebreak() {
+            Some((name, value))
+        } else {
+            None
         }
     }
 
     /// Consume an unstructured from the input.
+    ///
+    /// Folding is undone, with all of the whitespace around each fold
+    /// becoming a single space.
     /// [unstable]
     pub fn consume_unstructured(&mut self) -> String {
+        let start = self.pos;
+        self.skip_unstructured();
         let mut result = String::new();
-        while !self.eof() {
-            if self.peek_linebreak() {
-                // Check for folding whitespace, if it wasn't, then
-                // we're done parsing
-                if !self.consume_folding_whitespace() {
-                    break;
-                }
-            }
+        unfold(&self.s[start..self.pos], &mut result);
+        result
+    }
 
-            result.push_str(&self.consume_while(|c| c.is_vchar() || c == ' ' || c == '\t')[..])
+    // Move past an unstructured, along with any folding in it.
+    fn skip_unstructured(&mut self) {
+        loop {
+            // Take the rest of the line at once, rather than a character at
+            // a time, as this is where most of the time parsing headers goes
+            let rest = &self.s[self.pos..];
+            self.pos += memchr2(b'\r', b'\n', rest.as_bytes()).unwrap_or(rest.len());
+
+            // Check for folding whitespace, if it wasn't, then
+            // we're done parsing
+            if !self.consume_folding_whitespace() {
+                break;
+            }
+            // The obsolete syntax allows lines of only whitespace, which
+            // are part of the same fold
+            while self.peek_linebreak() && self.consume_folding_whitespace() {}
         }
-        result
     }
 
     /// Consume folding whitespace.
@@ -210,13 +321,19 @@ impl<'s> Rfc5322Parser<'s> {
     /// If `allow_dot_atom` is true, then `atom` can be a `dot-atom` in this phrase.
     /// [unstable]
     pub fn consume_word(&mut self, allow_dot_atom: bool) -> Option<String> {
-        let p = self.peek();
-        if p == '"' {
+        self.consume_word_str(allow_dot_atom).map(Cow::into_owned)
+    }
+
+    /// Consume a word from the input as `consume_word` does, borrowing it
+    /// from the input unless it's a quoted string with escapes in it.
+    /// [unstable]
+    pub fn consume_word_str(&mut self, allow_dot_atom: bool) -> Option<Cow<'s, str>> {
+        if self.s.as_bytes().get(self.pos) == Some(&b'"') {
             // Word is a quoted string
-            self.consume_quoted_string()
+            self.consume_quoted_str()
         } else {
             // Word is an atom (or not a word)
-            self.consume_atom(allow_dot_atom)
+            self.consume_atom_str(allow_dot_atom).map(Cow::Borrowed)
         }
     }
 
@@ -230,11 +347,12 @@ impl<'s> Rfc5322Parser<'s> {
     /// [unstable]
     pub fn consume_phrase(&mut self, allow_dot_atom: bool) -> Option<String> {
         let mut phrase = String::new();
+        let mut after_encoded_word = false;
 
         while !self.eof() {
-            self.consume_linear_whitespace();
+            self.consume_cfws();
 
-            let word = match self.consume_word(allow_dot_atom) {
+            let word = match self.consume_word_str(allow_dot_atom) {
                 Some(x) => x,
                 None => break, // If it's not a word, it's no longer
                                // in a phrase, so stop.
@@ -243,19 +361,19 @@ impl<'s> Rfc5322Parser<'s> {
             let w_slice = &word[..];
             // RFC 2047 encoded words start with =?, end with ?=
             let decoded_word = if w_slice.starts_with("=?") && w_slice.ends_with("?=") {
-                match decode_rfc2047(w_slice) {
-                    Some(w) => w,
-                    None => w_slice.to_string(),
-                }
+                decode_rfc2047(w_slice)
             } else {
-                w_slice.to_string()
+                None
             };
 
-            // Make sure we put a leading space on, if this isn't the first insertion
-            if !phrase.is_empty() {
+            // Make sure we put a leading space on, if this isn't the first insertion.
+            // Adjacent encoded words are joined without one.
+            let joined = after_encoded_word && decoded_word.is_some();
+            if !(phrase.is_empty() || joined) {
                 phrase.push_str(" ");
             }
-            phrase.push_str(&decoded_word[..]);
+            after_encoded_word = decoded_word.is_some();
+            phrase.push_str(decoded_word.as_ref().map_or(w_slice, |w| &w[..]));
         }
 
         if !phrase.is_empty() {
@@ -268,51 +386,48 @@ impl<'s> Rfc5322Parser<'s> {
     /// Consume a quoted string from the input
     /// [unstable]
     pub fn consume_quoted_string(&mut self) -> Option<String> {
-        if self.peek() != '"' {
+        self.consume_quoted_str().map(Cow::into_owned)
+    }
+
+    /// Consume a quoted string from the input as `consume_quoted_string`
+    /// does, borrowing it from the input unless it has escapes in it.
+    ///
+    /// If the string isn't terminated, nothing is consumed.
+    /// [unstable]
+    pub fn consume_quoted_str(&mut self) -> Option<Cow<'s, str>> {
+        let bytes = self.s.as_bytes();
+        if bytes.get(self.pos) != Some(&b'"') {
             // Fail if we were called wrong
-            None
-        } else {
-            let mut quoted_string = String::new();
-            let mut inside_escape = false;
-            let mut terminated = false;
-            // Consume the leading "
-            self.consume_char();
-            while !terminated && !self.eof() {
-                match self.peek() {
-                    '\\' if !inside_escape => {
-                        // If we were not already being escaped, consume the
-                        // escape character and mark that we're being escaped.
-                        self.consume_char();
-                        inside_escape = true;
-                    }
-                    '"' if !inside_escape => {
-                        // If this is a DQUOTE and we haven't seen an escape character,
-                        // consume it and mark that we should break from the loop
-                        self.consume_char();
-                        terminated = true;
-                    }
-                    _ => {
-                        // Any old character gets pushed in
-                        if let Some(c) = self.consume_char() {
-                            quoted_string.push(c);
-                            // Clear any escape character state we have
-                            inside_escape = false;
-                        }
-                        // TODO: Should this return a Result<> instead of an Option<>?
-                        else {
-                            return None;
-                        }
-                    }
-                }
+            return None;
+        }
+        let start = self.pos + 1;
+        let mut end = start;
+        let mut escaped = false;
+        // Find the closing DQUOTE, skipping over each escaped character. As
+        // both are ASCII, neither can be part of another character.
+        loop {
+            end += memchr2(b'"', b'\\', bytes.get(end..)?)?;
+            if bytes[end] == b'"' {
+                break;
             }
+            escaped = true;
+            end += 2;
+        }
+        self.pos = end + 1;
 
-            if inside_escape || !terminated {
-                // Return an error state if we're still expecting a character
-                None
-            } else {
-                Some(quoted_string)
+        let content = &self.s[start..end];
+        if !escaped {
+            return Some(Cow::Borrowed(content));
+        }
+        let mut quoted_string = String::with_capacity(content.len());
+        let mut chars = content.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => quoted_string.extend(chars.next()),
+                _ => quoted_string.push(c),
             }
         }
+        Some(Cow::Owned(quoted_string))
     }
 
     /// Consume an atom from the input.
@@ -321,17 +436,104 @@ impl<'s> Rfc5322Parser<'s> {
     /// atext character.
     /// [unstable]
     pub fn consume_atom(&mut self, allow_dot: bool) -> Option<String> {
-        if self.eof() || !self.peek().is_atext() {
-            None
-        } else {
-            Some(self.consume_while(|c| c.is_atext() || (allow_dot && c == '.')))
+        self.consume_atom_str(allow_dot).map(str::to_string)
+    }
+
+    /// Consume an atom from the input as `consume_atom` does, borrowing it
+    /// from the input.
+    /// [unstable]
+    pub fn consume_atom_str(&mut self, allow_dot: bool) -> Option<&'s str> {
+        let rest = &self.s.as_bytes()[self.pos..];
+        if !rest.first().is_some_and(|b| b.is_atext()) {
+            return None;
         }
+        let len = rest
+            .iter()
+            .take_while(|&&b| b.is_atext() || (allow_dot && b == b'.'))
+            .count();
+        let atom = &self.s[self.pos..self.pos + len];
+        self.pos += len;
+        Some(atom)
     }
 
     /// Consume LWSP (Linear whitespace)
     /// [unstable]
     pub fn consume_linear_whitespace(&mut self) {
-        self.consume_while(|c| c == '\t' || c == ' ');
+        let rest = &self.s.as_bytes()[self.pos..];
+        self.pos += rest
+            .iter()
+            .take_while(|&&b| b == b' ' || b == b'\t')
+            .count();
+    }
+
+    /// Consume a comment from the input.
+    ///
+    /// A comment is defined as:
+    ///
+    /// `comment = "(" *([FWS] ccontent) [FWS] ")"`
+    ///
+    /// Comments may be nested, and `\` escapes the next character.
+    /// Returns the text of the comment, without the outer parentheses.
+    /// [unstable]
+    pub fn consume_comment(&mut self) -> Option<String> {
+        let mut comment = String::new();
+        if self.skip_comment(Some(&mut comment)) {
+            Some(comment)
+        } else {
+            None
+        }
+    }
+
+    // Consume a comment as `consume_comment` does, keeping its text in
+    // `text` if given. Returns false, having consumed nothing, if there
+    // isn't a whole comment.
+    fn skip_comment(&mut self, mut text: Option<&mut String>) -> bool {
+        if self.s.as_bytes().get(self.pos) != Some(&b'(') {
+            return false;
+        }
+        let start_pos = self.pos;
+        let mut push = |c: char| {
+            if let Some(ref mut text) = text {
+                text.push(c);
+            }
+        };
+        let mut depth = 0;
+        while let Some(c) = self.consume_char() {
+            match c {
+                '\\' => match self.consume_char() {
+                    Some(escaped) => push(escaped),
+                    None => break,
+                },
+                '(' => {
+                    if depth > 0 {
+                        push(c);
+                    }
+                    depth += 1;
+                }
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return true;
+                    }
+                    push(c);
+                }
+                _ => push(c),
+            }
+        }
+        // Unterminated comment, so put everything back.
+        self.pos = start_pos;
+        false
+    }
+
+    /// Consume CFWS (comments and folding whitespace)
+    /// [unstable]
+    pub fn consume_cfws(&mut self) {
+        loop {
+            self.consume_while_str(|c| c.is_whitespace());
+            if !self.skip_comment(None) {
+                break;
+            }
+        }
     }
 
     /// Consume a single character from the input.
@@ -349,33 +551,29 @@ impl<'s> Rfc5322Parser<'s> {
     // Consume a linebreak: \r\n, \r or \n
     /// [unstable]
     pub fn consume_linebreak(&mut self) -> bool {
-        if self.eof() {
-            return false;
-        }
-
-        let start_pos = self.pos;
-
-        match self.consume_char() {
-            Some('\r') => {
+        let bytes = self.s.as_bytes();
+        match bytes.get(self.pos) {
+            Some(b'\r') => {
+                self.pos += 1;
                 // Try to consume a single \n following the \r
-                if !self.eof() && self.peek() == '\n' {
-                    self.consume_char();
+                if bytes.get(self.pos) == Some(&b'\n') {
+                    self.pos += 1;
                 }
                 true
             }
-            Some('\n') => true,
-            _ => {
-                self.pos = start_pos;
-                false
+            Some(b'\n') => {
+                self.pos += 1;
+                true
             }
+            _ => false,
         }
     }
 
     // Peek at the current character and determine whether it's (part of) a linebreak
     /// [unstable]
     pub fn peek_linebreak(&mut self) -> bool {
-        match self.peek() {
-            '\r' | '\n' => true,
+        match self.s.as_bytes().get(self.pos) {
+            Some(b'\r') | Some(b'\n') => true,
             _ => false,
         }
     }
@@ -390,11 +588,18 @@ impl<'s> Rfc5322Parser<'s> {
     #[inline]
     /// [unstable]
     pub fn consume_while<F: Fn(char) -> bool>(&mut self, test: F) -> String {
-        let start_pos = self.pos;
-        while !self.eof() && test(self.peek()) {
-            self.consume_char();
-        }
-        self.s[start_pos..self.pos].to_string()
+        self.consume_while_str(test).to_string()
+    }
+
+    /// Consume characters as with `consume_while`, borrowing them from the
+    /// input rather than copying them.
+    #[inline]
+    /// [unstable]
+    pub fn consume_while_str<F: Fn(char) -> bool>(&mut self, test: F) -> &'s str {
+        let rest = &self.s[self.pos..];
+        let len = rest.find(|c| !test(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
     }
 
     /// Peek at the current character.
@@ -412,14 +617,10 @@ impl<'s> Rfc5322Parser<'s> {
     pub fn assert_char(&self, c: char) -> ParsingResult<()> {
         self.assert_not_eof()?;
 
-        let actual_c = self.peek();
-        if c == actual_c {
+        if c == self.peek() {
             Ok(())
         } else {
-            Err(ParsingError::new(format!(
-                "Expected {}, got {}",
-                c, actual_c
-            )))
+            Err(self.unexpected(&format!("{:?}", c)[..]))
         }
     }
 
@@ -428,12 +629,30 @@ impl<'s> Rfc5322Parser<'s> {
     /// [unstable]
     pub fn assert_not_eof(&self) -> ParsingResult<()> {
         if self.eof() {
-            Err(ParsingError::new("Reached EOF.".to_string()))
+            Err(self.unexpected("more input"))
         } else {
             Ok(())
         }
     }
 
+    /// Make an error for finding something other than `expected` at the
+    /// current position.
+    /// [unstable]
+    pub fn unexpected(&self, expected: &str) -> ParsingError {
+        ParsingError::UnexpectedChar {
+            pos: self.pos,
+            found: self.s[self.pos..].chars().next(),
+            expected: expected.to_string(),
+        }
+    }
+
+    /// The current position, as a byte offset into the input.
+    #[inline]
+    /// [unstable]
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
     /// Get the unconsumed string. Should only be used for debugging purposes!
     #[inline]
     /// [unstable]
@@ -499,6 +718,29 @@ impl Default for Rfc5322Builder {
     }
 }
 
+// Append the unstructured `folded` to `out` with its folding undone, as
+// `consume_unstructured` gives it.
+pub(crate) fn unfold(folded: &str, out: &mut String) {
+    let start = out.len();
+    let mut rest = folded;
+    loop {
+        let line_len = memchr2(b'\r', b'\n', rest.as_bytes()).unwrap_or(rest.len());
+        out.push_str(&rest[..line_len]);
+        rest = &rest[line_len..];
+        if rest.is_empty() {
+            break;
+        }
+        rest = rest.strip_prefix("\r\n").unwrap_or(&rest[1..]);
+        // Each fold, along with the whitespace around it, becomes a space
+        let trimmed_len = out[start..].trim_end_matches(&[' ', '\t'][..]).len();
+        out.truncate(start + trimmed_len);
+        if trimmed_len > 0 {
+            out.push(' ');
+        }
+        rest = rest.trim_start_matches(&[' ', '\t'][..]);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -559,6 +801,10 @@ mod tests {
                 output: "encoded atoms mixed with unencoded and encoded q-strings",
                 name: "Mix of atoms, q-strings of differing encodings"
             },
+            PhraseTestCase {
+                input: "=?utf-8?q?Adjacent_?= =?utf-8?q?words?= joined", output: "Adjacent words joined",
+                name: "Adjacent encoded atoms"
+            },
             PhraseTestCase {
                 input: "\"John Smith\" <test@example.org>", output: "John Smith",
                 name: "Stop consuming phrase at \"special\" character",
@@ -574,6 +820,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_consume_comment() {
+        let mut p = Rfc5322Parser::new("(a (nested) \\) comment) rest");
+        assert_eq!(p.consume_comment(), Some("a (nested) ) comment".to_string()));
+        assert_eq!(p.peek_to_end(), " rest");
+
+        let mut p = Rfc5322Parser::new("(unterminated");
+        assert_eq!(p.consume_comment(), None);
+        assert_eq!(p.peek_to_end(), "(unterminated");
+
+        let mut p = Rfc5322Parser::new(" (one)\r\n\t(two) word");
+        p.consume_cfws();
+        assert_eq!(p.peek_to_end(), "word");
+    }
+
+    #[test]
+    fn test_consume_word_str() {
+        let mut p = Rfc5322Parser::new("joe.bloggs \"Joe Bloggs\" \"a \\\"b\\\"\" \"open");
+        assert_eq!(p.consume_word_str(true), Some(Cow::Borrowed("joe.bloggs")));
+        p.consume_cfws();
+        assert_eq!(p.consume_word_str(false), Some(Cow::Borrowed("Joe Bloggs")));
+        p.consume_cfws();
+        let escaped: Option<Cow<str>> = Some(Cow::Owned("a \"b\"".to_string()));
+        assert_eq!(p.consume_word_str(false), escaped);
+        p.consume_cfws();
+        let start = p.pos();
+        assert_eq!(p.consume_word_str(false), None);
+        assert_eq!(p.pos(), start);
+
+        let mut p = Rfc5322Parser::new("a.b rest");
+        assert_eq!(p.consume_atom_str(false), Some("a"));
+        assert_eq!(p.consume_atom_str(true), None);
+        p.set_pos(0);
+        assert_eq!(p.consume_atom_str(true), Some("a.b"));
+    }
+
     struct MessageTestCase<'s> {
         input: &'s str,
         headers: Vec<(&'s str, &'s str)>,
@@ -627,6 +909,13 @@ mod tests {
                 ],
                 body: "Folding whitespace test",
             },
+            MessageTestCase {
+                input: "References: <a@example.org>\r\n\t<b@example.org>\r\n \r\n\t <c@example.org>\r\n\r\nBody",
+                headers: vec![
+                    ("References", "<a@example.org> <b@example.org> <c@example.org>"),
+                ],
+                body: "Body",
+            },
         ];
 
         for test in tests.iter() {
@@ -654,6 +943,24 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_folded_value() {
+        let mut p = Rfc5322Parser::new("Subject: Hello\r\n\tWorld\r\nTo: joe@example.org\r\n");
+        let header = p.consume_header().unwrap();
+        assert_eq!(header.raw_value(), "Hello World");
+        assert_eq!(header.folded_value(), "Hello\r\n\tWorld");
+        let header = p.consume_header().unwrap();
+        assert_eq!(header.folded_value(), "joe@example.org");
+    }
+
+    #[test]
+    fn test_non_ascii_value() {
+        let mut p = Rfc5322Parser::new("Subject: caf\u{e9} \u{1}x\r\n\r\nBody");
+        let header = p.consume_header().unwrap();
+        assert_eq!(header.raw_value(), "caf\u{e9} \u{1}x");
+        assert!(p.consume_header().is_none());
+    }
+
     #[test]
     fn test_builder_folding() {
         struct BuildFoldTest<'s> {
@@ -705,3 +1012,72 @@ mod tests {
         assert!(!res.lines().any(|l| l.trim().is_empty()));
     }
 }
+
+#[cfg(all(feature = "nightly", test))]
+mod bench {
+    extern crate test;
+    use self::test::Bencher;
+
+    use super::*;
+    use crate::address::AddressParser;
+    use crate::rfc822::Rfc822DateParser;
+
+    // Header values as they're found in real mail, for the parsers to run
+    // through
+    static ADDRESSES: [&str; 4] = [
+        "joe@example.org",
+        "\"Bloggs, Joe\" <joe@example.org>, Jane Doe <jane.doe@example.org>",
+        "=?UTF-8?Q?Andr=C3=A9?= <andre@example.org> (work), team: a@x.org, b@x.org;",
+        "undisclosed-recipients:;",
+    ];
+    static DATES: [&str; 3] = [
+        "Tue, 1 Jul 2003 10:52:37 +0200",
+        "1 Jul 2003 10:52:37 -0700 (PDT)",
+        "Thu, 13 Feb 1969 23:32:54 -0330",
+    ];
+    static PHRASES: [&str; 3] = [
+        "Joe Bloggs",
+        "\"Bloggs, \\\"Joe\\\"\" (comment)",
+        "=?ISO-8859-1?Q?Keld_J=F8rn?= =?ISO-8859-1?Q?_Simonsen?=",
+    ];
+
+    #[bench]
+    fn bench_address_lists(b: &mut Bencher) {
+        b.iter(|| {
+            for address in ADDRESSES.iter() {
+                let _ = AddressParser::new(address).parse_address_list();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_dates(b: &mut Bencher) {
+        b.iter(|| {
+            for date in DATES.iter() {
+                let _ = Rfc822DateParser::new(date).consume_datetime();
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_phrases(b: &mut Bencher) {
+        b.iter(|| {
+            for phrase in PHRASES.iter() {
+                let _ = Rfc5322Parser::new(phrase).consume_phrase(false);
+            }
+        });
+    }
+
+    #[bench]
+    fn bench_headers(b: &mut Bencher) {
+        let mut headers = String::new();
+        for address in ADDRESSES.iter() {
+            headers.push_str(&format!("To: {}\r\n", address));
+        }
+        for date in DATES.iter() {
+            headers.push_str(&format!("Received: from mx.example.org;\r\n\t{}\r\n", date));
+        }
+        headers.push_str("\r\n");
+        b.iter(|| Rfc5322Parser::new(&headers).consume_headers());
+    }
+}
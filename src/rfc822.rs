@@ -49,44 +49,44 @@ impl<'s> Rfc822DateParser<'s> {
     }
 
     #[inline]
-    fn consume_u32(&mut self) -> Option<u32> {
+    fn consume_u32(&mut self, expected: &str) -> ParsingResult<u32> {
+        if self.parser.eof() || !self.parser.peek().is_ascii_digit() {
+            return Err(self.parser.unexpected(expected));
+        }
+        let pos = self.parser.pos();
+        let word = self.parser.consume_word_str(false).unwrap_or_default();
+        word.parse().map_err(|_| ParsingError::InvalidToken {
+            pos,
+            kind: expected.to_string(),
+            token: word.to_string(),
+        })
+    }
+
+    // Consume a word, which is needed to continue parsing.
+    fn consume_required_word(&mut self, expected: &str) -> ParsingResult<(usize, String)> {
+        let pos = self.parser.pos();
+        if self.parser.eof() {
+            return Err(self.parser.unexpected(expected));
+        }
         match self.parser.consume_word(false) {
-            Some(s) => match s.parse() {
-                // FIXME
-                Ok(x) => Some(x),
-                Err(_) => None,
-            },
-            None => None,
+            Some(word) => Ok((pos, word)),
+            None => Err(self.parser.unexpected(expected)),
         }
     }
 
     fn consume_time(&mut self) -> ParsingResult<(u32, u32, u32)> {
-        let hour = match self.consume_u32() {
-            Some(x) => x,
-            None => {
-                return Err(ParsingError::new(
-                    "Failed to parse time: Expected hour, a number.".to_string(),
-                ))
-            }
-        };
+        let hour = self.consume_u32("hour")?;
 
         self.parser.assert_char(':')?;
         self.parser.consume_char();
 
-        let minute = match self.consume_u32() {
-            Some(x) => x,
-            None => {
-                return Err(ParsingError::new(
-                    "Failed to parse time: Expected minute.".to_string(),
-                ))
-            }
-        };
+        let minute = self.consume_u32("minute")?;
 
         // Seconds are optional, only try to parse if we see the next seperator.
         let second = match self.parser.assert_char(':') {
             Ok(_) => {
                 self.parser.consume_char();
-                self.consume_u32()
+                self.consume_u32("second").ok()
             }
             Err(_) => None,
         }
@@ -96,35 +96,33 @@ impl<'s> Rfc822DateParser<'s> {
     }
 
     fn consume_timezone_offset(&mut self) -> ParsingResult<i32> {
-        match self.parser.consume_word(false) {
-            Some(s) => {
-                // from_str doesn't like leading '+' to indicate positive,
-                // so strip it off if it's there.
-                let mut s_slice = &s[..];
-                s_slice = if s_slice.starts_with('+') {
-                    &s_slice[1..]
-                } else {
-                    s_slice
-                };
-                // Try to parse zone as an int
-                match s_slice.parse::<i32>() {
-                    Ok(i) => {
-                        let offset_hours = i / 100;
-                        let offset_mins = i % 100;
-                        Ok(offset_hours * 3600 + offset_mins * 60)
-                    }
-                    Err(_) => {
-                        // Isn't an int, so try to use the strings->TZ hash.
-                        match TZ_DATA.get(s_slice) {
-                            Some(offset) => Ok(*offset),
-                            None => {
-                                Err(ParsingError::new(format!("Invalid timezone: {}", s_slice)))
-                            }
-                        }
-                    }
+        let (pos, s) = self.consume_required_word("timezone offset")?;
+        // from_str doesn't like leading '+' to indicate positive,
+        // so strip it off if it's there.
+        let mut s_slice = &s[..];
+        s_slice = if s_slice.starts_with('+') {
+            &s_slice[1..]
+        } else {
+            s_slice
+        };
+        // Try to parse zone as an int
+        match s_slice.parse::<i32>() {
+            Ok(i) => {
+                let offset_hours = i / 100;
+                let offset_mins = i % 100;
+                Ok(offset_hours * 3600 + offset_mins * 60)
+            }
+            Err(_) => {
+                // Isn't an int, so try to use the strings->TZ hash.
+                match TZ_DATA.get(s_slice) {
+                    Some(offset) => Ok(*offset),
+                    None => Err(ParsingError::InvalidToken {
+                        pos,
+                        kind: "timezone".to_string(),
+                        token: s_slice.to_string(),
+                    }),
                 }
             }
-            None => Err(ParsingError::new("Expected timezone offset.".to_string())),
         }
     }
 
@@ -147,50 +145,39 @@ impl<'s> Rfc822DateParser<'s> {
     /// [unstable]
     pub fn consume_datetime(&mut self) -> ParsingResult<DateTime<FixedOffset>> {
         // Handle the optional day ","
-        self.parser.push_position();
-        let day_of_week = self.parser.consume_word(false);
+        let start = self.parser.pos();
+        let day_of_week = self.parser.consume_word_str(false);
         if let Some(day_of_week) = day_of_week {
-            // XXX: Used to be into_ascii_lowercase, which is more memory-efficient. Unfortunately that
-            // API was unstable at the time, so we copy the string here
-            let lower_dow = day_of_week.to_ascii_lowercase();
-            if DAYS_OF_WEEK.contains(&&lower_dow[..]) {
+            if DAYS_OF_WEEK
+                .iter()
+                .any(|day| day.eq_ignore_ascii_case(&day_of_week))
+            {
                 // Lose the ","
-                self.parser.consume_while(|c| c == ',' || c.is_whitespace());
+                self.parser
+                    .consume_while_str(|c| c == ',' || c.is_whitespace());
             } else {
                 // What we read doesn't look like a day, so ignore it,
                 // go back to the start and continue on.
-                self.parser.pop_position();
+                self.parser.set_pos(start);
             };
         } else {
             // We don't have a leading day "," so go back to the start.
-            self.parser.pop_position();
+            self.parser.set_pos(start);
         }
 
-        let day_of_month = match self.consume_u32() {
-            Some(x) => x,
-            None => {
-                return Err(ParsingError::new(
-                    "Expected day of month, a number.".to_string(),
-                ))
-            }
-        };
+        let day_of_month = self.consume_u32("day of month")?;
 
         self.parser.consume_linear_whitespace();
         let month = self.consume_month()?;
         self.parser.consume_linear_whitespace();
 
-        let year = match self.consume_u32() {
-            Some(i) => {
-                // See RFC5322 4.3 for justification of obsolete year format handling.
-                match i {
-                    // 2 digit year between 0 and 49 is assumed to be in the 2000s
-                    0..=49 => i + 2000,
-                    // 2 digit year greater than 50 and 3 digit years are added to 1900
-                    50..=999 => i + 1900,
-                    _ => i,
-                }
-            }
-            None => return Err(ParsingError::new("Expected year.".to_string())),
+        let year = match self.consume_u32("year")? {
+            // See RFC5322 4.3 for justification of obsolete year format handling.
+            // 2 digit year between 0 and 49 is assumed to be in the 2000s
+            i @ 0..=49 => i + 2000,
+            // 2 digit year greater than 50 and 3 digit years are added to 1900
+            i @ 50..=999 => i + 1900,
+            i => i,
         };
         self.parser.consume_linear_whitespace();
 
@@ -207,21 +194,21 @@ impl<'s> Rfc822DateParser<'s> {
     }
 
     fn consume_month(&mut self) -> ParsingResult<u32> {
-        match self.parser.consume_word(false) {
-            Some(s) => {
-                // XXX: Used to be into_ascii_lowercase, which is more memory-efficient. Unfortunately that
-                // API was unstable at the time, so we copy the string here
-                let lower_month = s.to_ascii_lowercase();
-                // Add one because months are 1 indexed, array is 0 indexed.
-                for (i, month) in MONTHS.iter().enumerate() {
-                    if month == &&lower_month[..] {
-                        return Ok((i + 1) as u32);
-                    };
-                }
-                Err(ParsingError::new(format!("Invalid month: {}", lower_month)))
-            }
-            None => Err(ParsingError::new("Expected month.".to_string())),
+        let (pos, s) = self.consume_required_word("month")?;
+        // XXX: Used to be into_ascii_lowercase, which is more memory-efficient. Unfortunately that
+        // API was unstable at the time, so we copy the string here
+        let lower_month = s.to_ascii_lowercase();
+        // Add one because months are 1 indexed, array is 0 indexed.
+        for (i, month) in MONTHS.iter().enumerate() {
+            if month == &&lower_month[..] {
+                return Ok((i + 1) as u32);
+            };
         }
+        Err(ParsingError::InvalidToken {
+            pos,
+            kind: "month".to_string(),
+            token: s,
+        })
     }
 }
 
@@ -288,4 +275,23 @@ mod tests {
             assert_eq!(parser.consume_datetime().ok(), test.result);
         }
     }
+
+    #[test]
+    fn test_time_parse_errors() {
+        let error = |input| Rfc822DateParser::new(input).consume_datetime().unwrap_err();
+
+        match error("Mon, 20 Foo 1982 10:01:59 +0545") {
+            ParsingError::InvalidToken { pos, kind, token } => {
+                assert_eq!(pos, 8);
+                assert_eq!(kind, "month");
+                assert_eq!(token, "Foo");
+            }
+            e => panic!("Unexpected error: {}", e),
+        }
+        assert_eq!(error("Mon, 20 Jun 1982 x").position(), Some(17));
+        assert_eq!(
+            error("Mon, 20 Jun 1982 10:01:59").to_string(),
+            "Expected timezone offset at 25, found end of input"
+        );
+    }
 }
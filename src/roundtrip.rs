@@ -0,0 +1,350 @@
+//! Module for checking that a message survives being written out and parsed
+//! again.
+//!
+//! `MimeMessage::roundtrip_check` serializes a message, reparses the result
+//! and compares the two part by part, reporting each difference between
+//! them. Some differences only change how the message is written, such as
+//! the folding of a header or the transfer encoding of a body, and are
+//! reported as normalizations; any others mean that something was lost.
+use std::fmt;
+
+use super::diff::{diff, Change};
+use super::header::Header;
+use super::message::MimeMessage;
+use super::mimeheaders::ContentType;
+use super::results::ParsingResult;
+use super::walk::PartPath;
+
+/// A difference between a part of a message and the same part once the
+/// message has been written out and parsed again.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Difference {
+    /// A part is missing from the reparsed message
+    PartLost { path: PartPath },
+    /// A part appears only in the reparsed message
+    PartAdded { path: PartPath },
+    /// A header is missing from the reparsed part
+    HeaderLost {
+        path: PartPath,
+        name: String,
+        value: String,
+    },
+    /// A header appears only in the reparsed part
+    HeaderAdded {
+        path: PartPath,
+        name: String,
+        value: String,
+    },
+    /// The value of a header changed
+    HeaderChanged {
+        path: PartPath,
+        name: String,
+        before: String,
+        after: String,
+    },
+    /// The value of a header changed only in its whitespace or folding
+    HeaderReformatted { path: PartPath, name: String },
+    /// A parameter of the Content-Type header changed, was lost or was
+    /// added
+    ParameterChanged {
+        path: PartPath,
+        name: String,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    /// The content of the body changed
+    BodyChanged { path: PartPath },
+    /// The body was encoded differently, but has the same content
+    BodyReencoded { path: PartPath },
+}
+
+impl Difference {
+    /// The path of the part the difference is in.
+    pub fn path(&self) -> &PartPath {
+        match *self {
+            Difference::PartLost { ref path }
+            | Difference::PartAdded { ref path }
+            | Difference::HeaderLost { ref path, .. }
+            | Difference::HeaderAdded { ref path, .. }
+            | Difference::HeaderChanged { ref path, .. }
+            | Difference::HeaderReformatted { ref path, .. }
+            | Difference::ParameterChanged { ref path, .. }
+            | Difference::BodyChanged { ref path }
+            | Difference::BodyReencoded { ref path } => path,
+        }
+    }
+
+    /// Whether the difference changes only how the message is written, and
+    /// not what it means.
+    pub fn is_normalization(&self) -> bool {
+        matches!(
+            *self,
+            Difference::HeaderReformatted { .. } | Difference::BodyReencoded { .. }
+        )
+    }
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let path = self.path();
+        if path.is_root() {
+            write!(f, "message: ")?;
+        } else {
+            write!(f, "part {}: ", path)?;
+        }
+        match *self {
+            Difference::PartLost { .. } => write!(f, "part lost"),
+            Difference::PartAdded { .. } => write!(f, "part added"),
+            Difference::HeaderLost { ref name, .. } => write!(f, "{} header lost", name),
+            Difference::HeaderAdded { ref name, .. } => write!(f, "{} header added", name),
+            Difference::HeaderChanged {
+                ref name,
+                ref before,
+                ref after,
+                ..
+            } => write!(
+                f,
+                "{} header changed from {:?} to {:?}",
+                name, before, after
+            ),
+            Difference::HeaderReformatted { ref name, .. } => {
+                write!(f, "{} header reformatted", name)
+            }
+            Difference::ParameterChanged {
+                ref name,
+                ref before,
+                ref after,
+                ..
+            } => write!(
+                f,
+                "Content-Type parameter {} changed from {:?} to {:?}",
+                name, before, after
+            ),
+            Difference::BodyChanged { .. } => write!(f, "body changed"),
+            Difference::BodyReencoded { .. } => write!(f, "body reencoded"),
+        }
+    }
+}
+
+/// The differences found by `MimeMessage::roundtrip_check`, in the order of
+/// the parts they're in.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct RoundtripReport {
+    pub differences: Vec<Difference>,
+}
+
+impl RoundtripReport {
+    /// Whether the message came back exactly as it was.
+    pub fn is_exact(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    /// Whether the message came back with nothing lost, allowing for
+    /// normalizations.
+    pub fn is_lossless(&self) -> bool {
+        self.differences.iter().all(Difference::is_normalization)
+    }
+
+    /// The differences which lost something.
+    pub fn losses(&self) -> impl Iterator<Item = &Difference> {
+        self.differences.iter().filter(|d| !d.is_normalization())
+    }
+}
+
+impl fmt::Display for RoundtripReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for difference in self.differences.iter() {
+            writeln!(f, "{}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+impl MimeMessage {
+    /// Write this message out, parse it again, and report how the result
+    /// differs from it.
+    ///
+    /// Headers are matched by name and position among those of the same
+    /// name, and bodies compared once decoded. Fails if the message can't
+    /// be parsed again at all.
+    pub fn roundtrip_check(&self) -> ParsingResult<RoundtripReport> {
+        let reparsed = MimeMessage::parse(&self.as_string())?;
+        let mut report = RoundtripReport::default();
+        for change in diff(self, &reparsed) {
+            self.classify(&reparsed, change, &mut report.differences);
+        }
+        Ok(report)
+    }
+
+    // Add the differences `change` from this message to `reparsed` amounts
+    // to.
+    fn classify(&self, reparsed: &MimeMessage, change: Change, differences: &mut Vec<Difference>) {
+        let difference = match change {
+            Change::HeaderAdded { path, name, value } => {
+                Difference::HeaderAdded { path, name, value }
+            }
+            Change::HeaderRemoved { path, name, value } => {
+                Difference::HeaderLost { path, name, value }
+            }
+            Change::HeaderChanged {
+                path,
+                name,
+                before,
+                after,
+            } => {
+                if before.split_whitespace().eq(after.split_whitespace()) {
+                    Difference::HeaderReformatted { path, name }
+                } else if name.eq_ignore_ascii_case("Content-Type") {
+                    return compare_content_types(path, name, before, after, differences);
+                } else {
+                    Difference::HeaderChanged {
+                        path,
+                        name,
+                        before,
+                        after,
+                    }
+                }
+            }
+            Change::PartAdded { path } => Difference::PartAdded { path },
+            Change::PartRemoved { path } => Difference::PartLost { path },
+            Change::BodyChanged { path, .. } => {
+                // The preamble of a multipart only matters to its content,
+                // not the space around it
+                if let (Some(before), Some(after)) = (self.part(&path), reparsed.part(&path)) {
+                    let preamble = before.message_type.is_some();
+                    if preamble && before.body.trim() == after.body.trim() {
+                        return;
+                    }
+                }
+                Difference::BodyChanged { path }
+            }
+            Change::BodyReencoded { path } => Difference::BodyReencoded { path },
+        };
+        differences.push(difference);
+    }
+}
+
+// Add the differences between two values of a Content-Type header which
+// aren't just in their whitespace.
+fn compare_content_types(
+    path: PartPath,
+    name: String,
+    before: String,
+    after: String,
+    differences: &mut Vec<Difference>,
+) {
+    let parse = |value: &str| Header::new_raw(name.clone(), value.to_string()).get_value();
+    let types: (ParsingResult<ContentType>, ParsingResult<ContentType>) =
+        (parse(&before), parse(&after));
+    let (ct_before, ct_after) = match types {
+        (Ok(ct_before), Ok(ct_after))
+            if (&ct_before.ttype, &ct_before.subtype) == (&ct_after.ttype, &ct_after.subtype) =>
+        {
+            (ct_before, ct_after)
+        }
+        _ => {
+            differences.push(Difference::HeaderChanged {
+                path,
+                name,
+                before,
+                after,
+            });
+            return;
+        }
+    };
+
+    let names = ct_before
+        .parameters
+        .iter()
+        .chain(ct_after.parameters.iter())
+        .map(|(name, _)| &name[..]);
+    let mut seen: Vec<&str> = Vec::new();
+    let mut changed = false;
+    for parameter in names {
+        if seen.contains(&parameter) {
+            continue;
+        }
+        seen.push(parameter);
+        let (value_before, value_after) = (
+            ct_before.parameter(parameter),
+            ct_after.parameter(parameter),
+        );
+        if value_before != value_after {
+            changed = true;
+            differences.push(Difference::ParameterChanged {
+                path: path.clone(),
+                name: parameter.to_string(),
+                before: value_before.map(str::to_string),
+                after: value_after.map(str::to_string),
+            });
+        }
+    }
+    if !changed {
+        // Only the quoting or order of the parameters changed
+        differences.push(Difference::HeaderReformatted { path, name });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip_exact() {
+        let message = MimeMessage::parse(
+            "Subject: Hi\r\n\
+             Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Hello\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+        let report = message.roundtrip_check().unwrap();
+        assert!(report.is_exact());
+        assert!(report.is_lossless());
+    }
+
+    #[test]
+    fn test_roundtrip_differences() {
+        let mut message = MimeMessage::parse(
+            "Subject: Hi\r\n\
+             Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+             Caf=C3=A9\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+        // Unfolded on the way out, so only its whitespace changes
+        message
+            .headers
+            .insert(Header::new_raw("X-Note".to_string(), "a\r\n b".to_string()));
+        // A boundary in the body of a part ends it early
+        message.children[0].body = "Caf=C3=A9\r\n--b\r\n\r\nmore".to_string().into();
+
+        let report = message.roundtrip_check().unwrap();
+        let differences = vec![
+            Difference::HeaderReformatted {
+                path: PartPath::root(),
+                name: "X-Note".to_string(),
+            },
+            Difference::BodyChanged {
+                path: "1".parse().unwrap(),
+            },
+            Difference::PartAdded {
+                path: "2".parse().unwrap(),
+            },
+        ];
+        assert_eq!(report.differences, differences);
+        assert!(!report.is_lossless());
+        assert_eq!(report.losses().count(), 2);
+        assert_eq!(
+            report.to_string(),
+            "message: X-Note header reformatted\n\
+             part 1: body changed\n\
+             part 2: part added\n"
+        );
+    }
+}
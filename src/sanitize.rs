@@ -0,0 +1,518 @@
+//! Module for defanging untrusted messages.
+//!
+//! `sanitize` rewrites a parsed message as set out by a `SanitizePolicy`:
+//! parts with dangerous media types or filenames, executables, and parts
+//! beyond the limits on their number and size are removed, scripting is
+//! dropped from HTML, and text is converted to UTF-8. Each change is
+//! reported as a `SanitizeAction`.
+//!
+//! HTML is cleaned by an `HtmlCleaner`, which by default is the simple
+//! `StripScripts`; one backed by a full HTML sanitizer can be given to
+//! `sanitize_with_cleaner` instead.
+use std::fmt;
+
+use super::convert::decode_entities;
+use super::header::Header;
+use super::message::MimeMessage;
+
+/// Cleans active content out of HTML.
+pub trait HtmlCleaner {
+    /// The HTML in `html` with anything which could run removed.
+    fn clean(&self, html: &str) -> String;
+}
+
+/// Which rules a message is sanitized by.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SanitizePolicy {
+    /// The media types of parts to remove, as lowercase `type/subtype`
+    pub blocked_types: Vec<String>,
+    /// The filename extensions of parts to remove, lowercase without the dot
+    pub blocked_extensions: Vec<String>,
+    /// Remove parts which aren't text but whose content is an executable,
+    /// whatever type they claim to be
+    pub remove_executables: bool,
+    /// The most parts to keep, with any beyond removed
+    pub max_parts: usize,
+    /// The largest decoded part to keep, in bytes
+    pub max_part_size: usize,
+    /// Clean scripting out of text/html parts
+    pub clean_html: bool,
+    /// Convert text parts in other charsets to UTF-8
+    pub normalize_charsets: bool,
+}
+
+const BLOCKED_TYPES: &[&str] = &[
+    "application/hta",
+    "application/java-archive",
+    "application/javascript",
+    "application/x-javascript",
+    "application/x-ms-shortcut",
+    "application/x-msdos-program",
+    "application/x-msdownload",
+    "application/x-msi",
+    "application/x-sh",
+    "text/javascript",
+    "text/vbscript",
+];
+
+const BLOCKED_EXTENSIONS: &[&str] = &[
+    "bat", "chm", "cmd", "com", "cpl", "dll", "exe", "hta", "jar", "js", "jse", "lnk", "msi",
+    "msp", "pif", "ps1", "reg", "scr", "vbe", "vbs", "wsf", "wsh",
+];
+
+impl Default for SanitizePolicy {
+    fn default() -> SanitizePolicy {
+        SanitizePolicy {
+            blocked_types: BLOCKED_TYPES.iter().map(|t| t.to_string()).collect(),
+            blocked_extensions: BLOCKED_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            remove_executables: true,
+            max_parts: 1000,
+            max_part_size: usize::MAX,
+            clean_html: true,
+            normalize_charsets: true,
+        }
+    }
+}
+
+/// Why a `SanitizeAction` was taken.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SanitizeReason {
+    /// The part has a blocked media type
+    BlockedType,
+    /// The part has a filename with a blocked extension
+    BlockedExtension,
+    /// The content of the part is an executable
+    Executable,
+    /// The message has more parts than allowed
+    TooManyParts,
+    /// The part is larger than allowed
+    TooLarge,
+    /// The HTML of the part contained scripting
+    Scripting,
+    /// The text of the part wasn't in UTF-8
+    Charset,
+}
+
+/// A change made to a message by `sanitize`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SanitizeAction {
+    pub reason: SanitizeReason,
+    /// Whether the part was removed, rather than rewritten
+    pub removed: bool,
+    /// The media type of the part, as it was
+    pub content_type: String,
+    pub filename: Option<String>,
+}
+
+impl fmt::Display for SanitizeAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = if self.removed { "Removed" } else { "Rewrote" };
+        write!(f, "{} {} part", action, self.content_type)?;
+        if let Some(ref filename) = self.filename {
+            write!(f, " {:?}", filename)?;
+        }
+        write!(f, ": {:?}", self.reason)
+    }
+}
+
+/// Sanitize `message` as set out by `policy`, cleaning HTML with
+/// `StripScripts`, and return the changes made.
+///
+/// The message itself is never removed. Should it be a single part which
+/// would be, its content is replaced with an empty text/plain body.
+pub fn sanitize(message: &mut MimeMessage, policy: &SanitizePolicy) -> Vec<SanitizeAction> {
+    sanitize_with_cleaner(message, policy, &StripScripts)
+}
+
+/// Sanitize `message` as with `sanitize`, cleaning HTML with `cleaner`.
+pub fn sanitize_with_cleaner(
+    message: &mut MimeMessage,
+    policy: &SanitizePolicy,
+    cleaner: &dyn HtmlCleaner,
+) -> Vec<SanitizeAction> {
+    let mut sanitizer = Sanitizer {
+        policy,
+        cleaner,
+        parts: 1,
+        actions: Vec::new(),
+    };
+    if let Some(reason) = sanitizer.check(message) {
+        sanitizer.record(message, reason, true);
+        message.headers.remove_all("Content-Disposition");
+        message.headers.replace(Header::new(
+            "Content-Type".to_string(),
+            "text/plain; charset=utf-8".to_string(),
+        ));
+        message.set_body_bytes(b"");
+    } else {
+        sanitizer.sanitize(message);
+    }
+    sanitizer.actions
+}
+
+struct Sanitizer<'a> {
+    policy: &'a SanitizePolicy,
+    cleaner: &'a dyn HtmlCleaner,
+    parts: usize,
+    actions: Vec<SanitizeAction>,
+}
+
+impl Sanitizer<'_> {
+    fn sanitize(&mut self, part: &mut MimeMessage) {
+        for mut child in std::mem::take(&mut part.children) {
+            self.parts += 1;
+            let reason = if self.parts > self.policy.max_parts {
+                Some(SanitizeReason::TooManyParts)
+            } else {
+                self.check(&child)
+            };
+            match reason {
+                Some(reason) => self.record(&child, reason, true),
+                None => {
+                    self.sanitize(&mut child);
+                    part.children.push(child);
+                }
+            }
+        }
+        if part.message_type.is_none() {
+            self.rewrite_text(part);
+        }
+    }
+
+    // The reason to remove `part`, if there is one.
+    fn check(&self, part: &MimeMessage) -> Option<SanitizeReason> {
+        let content_type = part.content_type();
+        let media_type = format!("{}/{}", content_type.ttype, content_type.subtype);
+        if self.policy.blocked_types.contains(&media_type) {
+            return Some(SanitizeReason::BlockedType);
+        }
+        let extension = part
+            .filename()
+            .and_then(|name| name.rfind('.').map(|i| name[i + 1..].to_ascii_lowercase()));
+        if let Some(extension) = extension {
+            if self.policy.blocked_extensions.contains(&extension) {
+                return Some(SanitizeReason::BlockedExtension);
+            }
+        }
+        if part.message_type.is_some() {
+            return None;
+        }
+
+        let content = part.decoded_body();
+        let size = content
+            .as_ref()
+            .map_or(part.body.len(), |content| content.len());
+        if size > self.policy.max_part_size {
+            return Some(SanitizeReason::TooLarge);
+        }
+        if self.policy.remove_executables && content_type.ttype != "text" {
+            if let Some(content) = content {
+                if is_executable(&content) {
+                    return Some(SanitizeReason::Executable);
+                }
+            }
+        }
+        None
+    }
+
+    // Clean the HTML and convert the charset of `part` if it's text.
+    fn rewrite_text(&mut self, part: &mut MimeMessage) {
+        let mut content_type = part.content_type();
+        if content_type.ttype != "text" {
+            return;
+        }
+        let text = match part.decoded_body_string() {
+            Ok(text) => text,
+            Err(_) => return,
+        };
+        let charset = content_type
+            .charset()
+            .map_or("us-ascii".to_string(), |c| c.to_ascii_lowercase());
+        let mut reason = None;
+        if self.policy.normalize_charsets && charset != "utf-8" && charset != "us-ascii" {
+            reason = Some(SanitizeReason::Charset);
+        }
+        let mut cleaned = None;
+        if self.policy.clean_html && content_type.subtype == "html" {
+            let html = self.cleaner.clean(&text);
+            if html != text {
+                reason = Some(SanitizeReason::Scripting);
+                cleaned = Some(html);
+            }
+        }
+
+        if let Some(reason) = reason {
+            self.record(part, reason, false);
+            content_type.set_parameter("charset", "utf-8");
+            part.headers.replace(Header::new(
+                "Content-Type".to_string(),
+                content_type.to_string(),
+            ));
+            part.set_body_bytes(cleaned.as_ref().unwrap_or(&text).as_bytes());
+        }
+    }
+
+    fn record(&mut self, part: &MimeMessage, reason: SanitizeReason, removed: bool) {
+        let content_type = part.content_type();
+        self.actions.push(SanitizeAction {
+            reason,
+            removed,
+            content_type: format!("{}/{}", content_type.ttype, content_type.subtype),
+            filename: part.filename(),
+        });
+    }
+}
+
+// Whether `content` starts like a Windows, ELF or Mach-O executable.
+fn is_executable(content: &[u8]) -> bool {
+    const MAGIC: &[&[u8]] = &[
+        b"MZ",
+        b"\x7fELF",
+        b"\xfe\xed\xfa\xce",
+        b"\xfe\xed\xfa\xcf",
+        b"\xce\xfa\xed\xfe",
+        b"\xcf\xfa\xed\xfe",
+        b"\xca\xfe\xba\xbe",
+    ];
+    MAGIC.iter().any(|magic| content.starts_with(magic))
+}
+
+/// A simple `HtmlCleaner`, which removes script, iframe, object, embed and
+/// applet elements, event handler attributes, and `javascript:` and
+/// `vbscript:` URLs.
+///
+/// This doesn't fully parse the HTML, so may be fooled by HTML which is
+/// badly malformed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StripScripts;
+
+const ACTIVE_ELEMENTS: &[&str] = &["applet", "embed", "iframe", "object", "script"];
+
+impl HtmlCleaner for StripScripts {
+    fn clean(&self, html: &str) -> String {
+        let lower = html.to_ascii_lowercase();
+        let mut result = String::with_capacity(html.len());
+        let mut pos = 0;
+        while let Some(start) = html[pos..].find('<').map(|i| pos + i) {
+            result.push_str(&html[pos..start]);
+            let end = match tag_end(html, start) {
+                Some(end) => end,
+                None => {
+                    pos = start;
+                    break;
+                }
+            };
+            let tag = &html[start..end];
+            let closing = tag.starts_with("</");
+            let name = tag_name(&lower[start..end]);
+            pos = end;
+            if ACTIVE_ELEMENTS.contains(&name) {
+                if !closing {
+                    // Drop everything up to and including the closing tag
+                    let close = format!("</{}", name);
+                    pos = match lower[end..].find(&close[..]) {
+                        Some(i) => tag_end(html, end + i).unwrap_or(html.len()),
+                        None => html.len(),
+                    };
+                }
+            } else if closing || !tag[1..].starts_with(|c: char| c.is_ascii_alphabetic()) {
+                result.push_str(tag);
+            } else {
+                result.push_str(&clean_tag(tag));
+            }
+        }
+        result.push_str(&html[pos..]);
+        result
+    }
+}
+
+// The end of the tag starting with the `<` at `start`, just after its `>`,
+// skipping over any quoted attribute values.
+pub(crate) fn tag_end(html: &str, start: usize) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html[start..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return Some(start + i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+pub(crate) fn tag_name(tag: &str) -> &str {
+    let tag = tag.trim_start_matches('<').trim_start_matches('/');
+    let end = tag
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(tag.len());
+    &tag[..end]
+}
+
+// `tag` without any event handlers or script URLs among its attributes.
+fn clean_tag(tag: &str) -> String {
+    let inner = &tag[1..tag.len() - 1];
+    let (inner, self_closing) = match inner.strip_suffix('/') {
+        Some(inner) => (inner, true),
+        None => (inner, false),
+    };
+    let name_end = inner
+        .find(|c: char| c.is_whitespace() || c == '/')
+        .unwrap_or(inner.len());
+    let mut result = format!("<{}", &inner[..name_end]);
+
+    let mut rest = &inner[name_end..];
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        if rest.is_empty() {
+            break;
+        }
+        let name_len = rest
+            .find(|c: char| c.is_whitespace() || c == '=')
+            .unwrap_or(rest.len());
+        let name = &rest[..name_len];
+        let mut len = name_len;
+        let mut value = "";
+        let after_name = rest[len..].trim_start();
+        if let Some(after_equals) = after_name.strip_prefix('=') {
+            let value_start = after_equals.trim_start();
+            let value_len = match value_start.chars().next() {
+                Some(q) if q == '"' || q == '\'' => value_start[1..]
+                    .find(q)
+                    .map_or(value_start.len(), |i| i + 2),
+                _ => value_start
+                    .find(char::is_whitespace)
+                    .unwrap_or(value_start.len()),
+            };
+            value = &value_start[..value_len];
+            len = rest.len() - value_start.len() + value_len;
+        }
+        if !is_script_attribute(name, value) {
+            result.push(' ');
+            result.push_str(&rest[..len]);
+        }
+        rest = &rest[len..];
+    }
+
+    if self_closing {
+        result.push_str(" /");
+    }
+    result.push('>');
+    result
+}
+
+fn is_script_attribute(name: &str, value: &str) -> bool {
+    let prefix = name.get(..2).filter(|_| name.len() > 2);
+    if prefix.is_some_and(|on| on.eq_ignore_ascii_case("on")) {
+        return true;
+    }
+    // Browsers decode character references in attributes, and ignore
+    // whitespace and control characters in URL schemes
+    let value = decode_entities(value.trim_matches(|c| c == '"' || c == '\''));
+    let value: String = value
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .take(11)
+        .collect::<String>()
+        .to_ascii_lowercase();
+    value.starts_with("javascript:") || value.starts_with("vbscript:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_scripts() {
+        let html = "<p onclick=\"steal()\" class=x>Hi <b>there</b></p>\
+                    <SCRIPT type=\"text/javascript\">alert('<p>')</script>\
+                    <a href=\" JavaScript:alert(1)\" title='a > b'>link</a>\
+                    <img src=\"cat.png\"/><br>\
+                    <iframe src=\"https://example.org/\"></iframe>1 < 2";
+        assert_eq!(
+            StripScripts.clean(html),
+            "<p class=x>Hi <b>there</b></p>\
+             <a title='a > b'>link</a>\
+             <img src=\"cat.png\" /><br>1 < 2"
+        );
+    }
+
+    #[test]
+    fn test_strip_scripts_encoded() {
+        let html = "<a href=\"&#106;avascript:alert(1)\">a</a>\
+                    <a href='javascript&colon;alert(1)'>b</a>\
+                    <a href=&#x56;bscript:x>c</a>\
+                    <p a\u{e9}=1 title=\"&amp;\">d</p>";
+        assert_eq!(
+            StripScripts.clean(html),
+            "<a>a</a><a>b</a><a>c</a><p a\u{e9}=1 title=\"&amp;\">d</p>"
+        );
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let mut message = MimeMessage::parse(
+            "Content-Type: multipart/mixed; boundary=foo\r\n\r\n\
+             --foo\r\n\
+             Content-Type: text/html; charset=iso-8859-1\r\n\
+             Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+             <p onload=3D\"x()\">Caf=E9</p>\r\n\
+             --foo\r\n\
+             Content-Type: application/octet-stream\r\n\
+             Content-Disposition: attachment; filename=\"invoice.PDF.exe\"\r\n\r\n\
+             AAAA\r\n\
+             --foo\r\n\
+             Content-Type: application/pdf\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n\
+             TVqQAAMAAAAEAAAA\r\n\
+             --foo\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Fine\r\n\
+             --foo--\r\n",
+        )
+        .unwrap();
+        let actions = sanitize(&mut message, &SanitizePolicy::default());
+        let reasons: Vec<_> = actions.iter().map(|a| (a.reason, a.removed)).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (SanitizeReason::Scripting, false),
+                (SanitizeReason::BlockedExtension, true),
+                (SanitizeReason::Executable, true),
+            ]
+        );
+        assert_eq!(
+            actions[1].to_string(),
+            "Removed application/octet-stream part \"invoice.PDF.exe\": BlockedExtension"
+        );
+        assert_eq!(message.children.len(), 2);
+        let html = &message.children[0];
+        assert_eq!(html.content_type().charset(), Some("utf-8"));
+        assert_eq!(html.decoded_body_string().unwrap(), "<p>Caf\u{e9}</p>\r\n");
+
+        let policy = SanitizePolicy {
+            max_parts: 1,
+            ..Default::default()
+        };
+        let actions = sanitize(&mut message, &policy);
+        assert_eq!(actions.len(), 2);
+        assert!(actions
+            .iter()
+            .all(|a| a.reason == SanitizeReason::TooManyParts));
+        assert!(message.children.is_empty());
+
+        let mut message = MimeMessage::parse(
+            "Content-Type: application/x-msdownload\r\n\r\n\
+             MZ\r\n",
+        )
+        .unwrap();
+        let actions = sanitize(&mut message, &SanitizePolicy::default());
+        assert_eq!(actions[0].reason, SanitizeReason::BlockedType);
+        assert_eq!(
+            message.content_type().to_string(),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(message.decoded_body_bytes().unwrap(), b"");
+    }
+}
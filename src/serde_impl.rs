@@ -0,0 +1,357 @@
+//! Serde support for the public types, enabled by the `serde` feature.
+//!
+//! Addresses are objects with a `name` and `address`, or for groups a
+//! `group` name and list of `mailboxes`. Headers are kept in order, as a list
+//! of objects with a `name` and undecoded `value`. Message bodies are decoded
+//! from their transfer encoding, and given as text in `body` where possible,
+//! or as base64 in `body_base64` otherwise, with any parts in `children`.
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeSeq, SerializeStruct, Serializer};
+
+use super::address::{Address, Mailbox};
+use super::charset;
+use super::header::{Header, HeaderMap};
+use super::message::{MimeMessage, MimeMultipartType};
+use super::mimeheaders::{ContentType, MimeContentTransferEncoding};
+
+impl Serialize for Mailbox {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Mailbox", 2)?;
+        state.serialize_field("name", &self.name)?;
+        state.serialize_field("address", &self.address)?;
+        state.end()
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Address::Mailbox(ref mbox) => mbox.serialize(serializer),
+            Address::Group(ref name, ref mboxes) => {
+                let mut state = serializer.serialize_struct("Group", 2)?;
+                state.serialize_field("group", name)?;
+                state.serialize_field("mailboxes", mboxes)?;
+                state.end()
+            }
+        }
+    }
+}
+
+// Visits the fields common to Mailbox and Address, so both can share it.
+struct AddressVisitor;
+
+impl<'de> Visitor<'de> for AddressVisitor {
+    type Value = Address;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an address or group")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Address, A::Error> {
+        let mut name: Option<String> = None;
+        let mut address: Option<String> = None;
+        let mut group: Option<String> = None;
+        let mut mailboxes: Option<Vec<Mailbox>> = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match &key[..] {
+                "name" => name = map.next_value()?,
+                "address" => address = Some(map.next_value()?),
+                "group" => group = Some(map.next_value()?),
+                "mailboxes" => mailboxes = Some(map.next_value()?),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        match (address, group) {
+            (Some(address), None) => Ok(Address::Mailbox(Mailbox { name, address })),
+            (None, Some(group)) => Ok(Address::Group(group, mailboxes.unwrap_or_default())),
+            (Some(_), Some(_)) => Err(de::Error::custom(
+                "an address can't have both `address` and `group`",
+            )),
+            (None, None) => Err(de::Error::missing_field("address")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Mailbox {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Mailbox, D::Error> {
+        match deserializer.deserialize_map(AddressVisitor)? {
+            Address::Mailbox(mbox) => Ok(mbox),
+            Address::Group(..) => Err(de::Error::custom("expected a mailbox, not a group")),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+        deserializer.deserialize_map(AddressVisitor)
+    }
+}
+
+impl Serialize for Header {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Header", 2)?;
+        state.serialize_field("name", self.name.as_str())?;
+        state.serialize_field("value", self.raw_value())?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Header {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Header, D::Error> {
+        struct HeaderVisitor;
+
+        impl<'de> Visitor<'de> for HeaderVisitor {
+            type Value = Header;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a header")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Header, A::Error> {
+                let mut name: Option<String> = None;
+                let mut value: Option<String> = None;
+                while let Some(key) = map.next_key::<String>()? {
+                    match &key[..] {
+                        "name" => name = Some(map.next_value()?),
+                        "value" => value = Some(map.next_value()?),
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+                let name = name.ok_or_else(|| de::Error::missing_field("name"))?;
+                let value = value.ok_or_else(|| de::Error::missing_field("value"))?;
+                Ok(Header::new(name, value))
+            }
+        }
+
+        deserializer.deserialize_struct("Header", &["name", "value"], HeaderVisitor)
+    }
+}
+
+impl Serialize for HeaderMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.len()))?;
+        for header in self.iter() {
+            seq.serialize_element(header)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for HeaderMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<HeaderMap, D::Error> {
+        struct HeaderMapVisitor;
+
+        impl<'de> Visitor<'de> for HeaderMapVisitor {
+            type Value = HeaderMap;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a list of headers")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<HeaderMap, A::Error> {
+                let mut headers = HeaderMap::new();
+                while let Some(header) = seq.next_element()? {
+                    headers.insert(header);
+                }
+                Ok(headers)
+            }
+        }
+
+        deserializer.deserialize_seq(HeaderMapVisitor)
+    }
+}
+
+// The body of a message as it is represented when serialized.
+enum Body {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+impl Body {
+    fn from_message(message: &MimeMessage) -> Body {
+        if !message.children.is_empty() {
+            // The preamble of a multipart is never transfer encoded
+            return Body::Text(message.body.to_string());
+        }
+        if message.content_type().ttype == "text" {
+            if let Ok(text) = message.decoded_body_string() {
+                return Body::Text(text);
+            }
+        }
+        match message.decoded_body_bytes() {
+            Some(bytes) => Body::Binary(bytes),
+            None => Body::Text(message.body.to_string()),
+        }
+    }
+
+    fn into_message_body(self, message: &MimeMessage) -> Result<String, String> {
+        if !message.children.is_empty() {
+            return Ok(match self {
+                Body::Text(text) => text,
+                Body::Binary(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            });
+        }
+
+        let content_type: Option<ContentType> =
+            message.headers.get_value("Content-Type".to_string()).ok();
+        let bytes = match self {
+            Body::Binary(bytes) => bytes,
+            Body::Text(text) => {
+                let charset = content_type
+                    .and_then(|ct| ct.charset().map(|charset| charset.to_string()))
+                    .unwrap_or_else(|| "us-ascii".to_string());
+                charset::encode(&text, &charset).map_err(|e| e.to_string())?
+            }
+        };
+        let transfer_encoding: MimeContentTransferEncoding = message
+            .headers
+            .get_value("Content-Transfer-Encoding".to_string())
+            .unwrap_or(MimeContentTransferEncoding::Identity);
+        Ok(transfer_encoding.encode(&bytes))
+    }
+}
+
+impl Serialize for MimeMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let fields = if self.children.is_empty() { 2 } else { 3 };
+        let mut state = serializer.serialize_struct("MimeMessage", fields)?;
+        state.serialize_field("headers", &self.headers)?;
+        match Body::from_message(self) {
+            Body::Text(text) => state.serialize_field("body", &text)?,
+            Body::Binary(bytes) => state.serialize_field("body_base64", &base64::encode(&bytes))?,
+        }
+        if !self.children.is_empty() {
+            state.serialize_field("children", &self.children)?;
+        }
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for MimeMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<MimeMessage, D::Error> {
+        struct MessageVisitor;
+
+        impl<'de> Visitor<'de> for MessageVisitor {
+            type Value = MimeMessage;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a MIME message")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<MimeMessage, A::Error> {
+                let mut headers: Option<HeaderMap> = None;
+                let mut body: Option<Body> = None;
+                let mut children: Vec<MimeMessage> = Vec::new();
+                while let Some(key) = map.next_key::<String>()? {
+                    match &key[..] {
+                        "headers" => headers = Some(map.next_value()?),
+                        "body" => body = Some(Body::Text(map.next_value()?)),
+                        "body_base64" => {
+                            let encoded: String = map.next_value()?;
+                            let bytes = base64::decode(&encoded).map_err(de::Error::custom)?;
+                            body = Some(Body::Binary(bytes));
+                        }
+                        "children" => children = map.next_value()?,
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                let mut message = MimeMessage::new_blank_message();
+                message.headers = headers.ok_or_else(|| de::Error::missing_field("headers"))?;
+                message.children = children;
+                if !message.children.is_empty() {
+                    let content_type: Option<ContentType> =
+                        message.headers.get_value("Content-Type".to_string()).ok();
+                    if let Some(ct) = content_type {
+                        if let Some(boundary) = ct.boundary() {
+                            message.boundary = boundary.to_string();
+                        }
+                        message.message_type =
+                            MimeMultipartType::from_content_type((ct.ttype, ct.subtype));
+                    }
+                }
+                if let Some(body) = body {
+                    message.body = body
+                        .into_message_body(&message)
+                        .map_err(de::Error::custom)?
+                        .into();
+                }
+                Ok(message)
+            }
+        }
+
+        deserializer.deserialize_struct(
+            "MimeMessage",
+            &["headers", "body", "body_base64", "children"],
+            MessageVisitor,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_address_json() {
+        let addresses = vec![
+            Address::new_mailbox_with_name("Joe Blogs".to_string(), "joe@example.org".to_string()),
+            Address::new_group(
+                "Team".to_string(),
+                vec![Mailbox::new("jane@example.org".to_string())],
+            ),
+        ];
+        let json = serde_json::to_string(&addresses).unwrap();
+        assert_eq!(
+            json,
+            "[{\"name\":\"Joe Blogs\",\"address\":\"joe@example.org\"},\
+             {\"group\":\"Team\",\"mailboxes\":[{\"name\":null,\"address\":\"jane@example.org\"}]}]"
+        );
+        let parsed: Vec<Address> = serde_json::from_str(&json[..]).unwrap();
+        assert_eq!(parsed, addresses);
+
+        assert!(serde_json::from_str::<Mailbox>("{\"group\":\"Team\"}").is_err());
+        assert!(serde_json::from_str::<Address>("{\"name\":\"Joe\"}").is_err());
+    }
+
+    #[test]
+    fn test_message_json() {
+        let message = MimeMessage::parse(
+            "Subject: Hello\r\n\
+             Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+             --b\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+             Gr=C3=BC=C3=9Fe\r\n\
+             --b\r\n\
+             Content-Type: application/octet-stream\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n\
+             AAEC\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(value["headers"][0]["name"], "Subject");
+        assert_eq!(value["headers"][0]["value"], "Hello");
+        assert_eq!(value["children"][0]["body"], "Grüße\r\n");
+        assert_eq!(value["children"][1]["body_base64"], "AAEC");
+
+        let json = serde_json::to_string(&message).unwrap();
+        let parsed: MimeMessage = serde_json::from_str(&json[..]).unwrap();
+        assert_eq!(parsed.headers, message.headers);
+        assert_eq!(parsed.boundary, "b");
+        assert_eq!(parsed.message_type, Some(MimeMultipartType::Mixed));
+        assert_eq!(parsed.children.len(), 2);
+        assert_eq!(parsed.children[0].body, "Gr=C3=BC=C3=9Fe\r\n");
+        assert_eq!(parsed.children[1].decoded_body_bytes(), Some(vec![0, 1, 2]));
+    }
+}
@@ -0,0 +1,607 @@
+//! Incremental parsing of messages as they arrive.
+//!
+//! A `MimeStreamParser` is fed the message a chunk at a time, such as the
+//! `DATA` of an SMTP transaction, and gives back `StreamEvent`s as soon as
+//! they can be known, without buffering the whole message: first the
+//! headers, then the body, with the start and end of each part of any
+//! multipart in between.
+//!
+//! The `Limits` of a `ParseOptions` apply here too, along with a limit on
+//! how much of a line of the body is held back while it could still turn
+//! out to be a boundary, so a parser fed adversarial input only holds a
+//! bounded amount of it at once.
+//!
+//! With the `async` feature, `AsyncMimeReader` does the same for a tokio
+//! `AsyncRead`.
+#[cfg(feature = "async")]
+use std::collections::VecDeque;
+#[cfg(feature = "async")]
+use std::io;
+
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::header::HeaderMap;
+use super::message::{limit_exceeded, Limits};
+use super::mimeheaders::ContentType;
+use super::results::ParsingResult;
+use super::rfc5322::Rfc5322Parser;
+
+// How much of a line of body is held back before it's given as a `Body`
+// event, unless a boundary of the multipart it's in is longer.
+const MAX_HELD_LINE: usize = 1000;
+
+/// Something found in a message by a `MimeStreamParser`.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum StreamEvent {
+    /// The headers of the message, or of the part which just started
+    Headers(HeaderMap),
+    /// Some of the body of the message or the current part, as it appears
+    /// in the input without any transfer decoding
+    ///
+    /// The body of a multipart is its preamble, before the first part.
+    Body(Vec<u8>),
+    /// A part of a multipart starts, and is followed by its headers
+    PartStart,
+    /// The current part ends
+    PartEnd,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum State {
+    Headers,
+    Body,
+    // After the closing boundary of a multipart, where lines are ignored
+    Epilogue,
+}
+
+// A multipart which the parser is within.
+#[derive(Debug, Clone)]
+struct Multipart {
+    boundary: String,
+    in_part: bool,
+}
+
+/// A parser which is given a message a chunk at a time.
+#[derive(Debug, Clone)]
+pub struct MimeStreamParser {
+    limits: Limits,
+    state: State,
+    // The start of a line which hasn't been ended yet
+    buffer: Vec<u8>,
+    // Whether the start of the current line has already been given as
+    // body, or skipped, so that it can't be a boundary
+    mid_line: bool,
+    // The header lines of the message or part read so far
+    header_lines: Vec<u8>,
+    // The number of headers in `header_lines`, and the length of the last
+    header_count: usize,
+    header_length: usize,
+    // The number of parts started, counting the message itself
+    part_count: usize,
+    // The line break ending the last line of body, which belongs to the
+    // boundary if the next line is one
+    held: Vec<u8>,
+    // The multiparts which the parser is within, innermost last
+    multiparts: Vec<Multipart>,
+}
+
+impl Default for MimeStreamParser {
+    fn default() -> MimeStreamParser {
+        MimeStreamParser::new()
+    }
+}
+
+impl MimeStreamParser {
+    pub fn new() -> MimeStreamParser {
+        MimeStreamParser::with_limits(Limits::default())
+    }
+
+    /// A parser which fails with `ParsingError::LimitExceeded` on messages
+    /// beyond `limits`.
+    pub fn with_limits(limits: Limits) -> MimeStreamParser {
+        MimeStreamParser {
+            limits,
+            state: State::Headers,
+            buffer: Vec::new(),
+            mid_line: false,
+            header_lines: Vec::new(),
+            header_count: 0,
+            header_length: 0,
+            part_count: 1,
+            held: Vec::new(),
+            multiparts: Vec::new(),
+        }
+    }
+
+    /// Parse the next chunk of the message, returning whatever could be
+    /// found in it.
+    ///
+    /// The end of a line is only known once its line break has been fed,
+    /// so some of `bytes` may only be returned by a later call.
+    ///
+    /// Fails if the message is beyond the parser's limits, after which
+    /// the parser starts over with a new message.
+    pub fn feed(&mut self, bytes: &[u8]) -> ParsingResult<Vec<StreamEvent>> {
+        let mut events = Vec::new();
+        let result = self.feed_lines(bytes, &mut events);
+        self.reset_on_error(result.map(|()| events))
+    }
+
+    /// Finish parsing at the end of the message, returning whatever
+    /// remained, and ending any parts which haven't been closed.
+    ///
+    /// The parser is then ready for a new message.
+    pub fn finish(&mut self) -> ParsingResult<Vec<StreamEvent>> {
+        let mut events = Vec::new();
+        let rest = std::mem::take(&mut self.buffer);
+        let result = if rest.is_empty() {
+            Ok(())
+        } else {
+            self.line(&rest, &mut events)
+        };
+        if result.is_ok() && self.state == State::Headers {
+            self.end_headers(&mut events);
+        }
+        if !self.held.is_empty() {
+            events.push(StreamEvent::Body(std::mem::take(&mut self.held)));
+        }
+        for multipart in self.multiparts.drain(..).rev() {
+            if multipart.in_part {
+                events.push(StreamEvent::PartEnd);
+            }
+        }
+        *self = MimeStreamParser::with_limits(self.limits.clone());
+        result.map(|()| events)
+    }
+
+    fn reset_on_error<T>(&mut self, result: ParsingResult<T>) -> ParsingResult<T> {
+        if result.is_err() {
+            *self = MimeStreamParser::with_limits(self.limits.clone());
+        }
+        result
+    }
+
+    // Parse each line ended in `bytes`, only looking at each byte once,
+    // and keep the start of the line which isn't.
+    fn feed_lines(&mut self, bytes: &[u8], events: &mut Vec<StreamEvent>) -> ParsingResult<()> {
+        let mut rest = bytes;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            let (line, after) = rest.split_at(end + 1);
+            if self.buffer.is_empty() {
+                self.line(line, events)?;
+            } else {
+                let mut buffer = std::mem::take(&mut self.buffer);
+                buffer.extend_from_slice(line);
+                self.line(&buffer, events)?;
+                // Keep the allocation for the next line
+                buffer.clear();
+                self.buffer = buffer;
+            }
+            rest = after;
+        }
+        self.buffer.extend_from_slice(rest);
+        self.flush_partial_line(events)
+    }
+
+    // Give up holding back the start of a line too long to be a boundary.
+    fn flush_partial_line(&mut self, events: &mut Vec<StreamEvent>) -> ParsingResult<()> {
+        let longest_boundary = self.multiparts.iter().map(|m| m.boundary.len() + 4).max();
+        if self.buffer.len() <= longest_boundary.unwrap_or(0).max(MAX_HELD_LINE) {
+            return Ok(());
+        }
+        match self.state {
+            State::Headers => {
+                let max = self.limits.max_header_length;
+                if self.buffer.len() > max {
+                    return Err(limit_exceeded("header length", max));
+                }
+            }
+            State::Body => {
+                let mut body = std::mem::take(&mut self.held);
+                body.append(&mut self.buffer);
+                events.push(StreamEvent::Body(body));
+                self.mid_line = true;
+            }
+            State::Epilogue => {
+                self.buffer.clear();
+                self.mid_line = true;
+            }
+        }
+        Ok(())
+    }
+
+    fn line(&mut self, line: &[u8], events: &mut Vec<StreamEvent>) -> ParsingResult<()> {
+        if self.mid_line {
+            self.mid_line = false;
+        } else if self.find_boundary(line).is_some() {
+            if self.state == State::Headers {
+                self.end_headers(events);
+            }
+            // The headers may have started a multipart, so look again
+            if let Some((index, closing)) = self.find_boundary(line) {
+                return self.boundary(index, closing, events);
+            }
+        }
+
+        match self.state {
+            State::Headers => {
+                if line == b"\r\n" || line == b"\n" {
+                    self.end_headers(events);
+                } else {
+                    self.header_line(line)?;
+                }
+            }
+            State::Body => {
+                let content_end = line.len() - line_break_length(line);
+                let mut body = std::mem::take(&mut self.held);
+                body.extend_from_slice(&line[..content_end]);
+                self.held = line[content_end..].to_vec();
+                if !body.is_empty() {
+                    events.push(StreamEvent::Body(body));
+                }
+            }
+            State::Epilogue => {}
+        }
+        Ok(())
+    }
+
+    fn header_line(&mut self, line: &[u8]) -> ParsingResult<()> {
+        if line.starts_with(b" ") || line.starts_with(b"\t") {
+            self.header_length += line.len();
+        } else {
+            self.header_count += 1;
+            self.header_length = line.len();
+        }
+        let limits = &self.limits;
+        if self.header_count > limits.max_header_count {
+            return Err(limit_exceeded("header count", limits.max_header_count));
+        } else if self.header_length > limits.max_header_length {
+            return Err(limit_exceeded("header length", limits.max_header_length));
+        }
+        self.header_lines.extend_from_slice(line);
+        Ok(())
+    }
+
+    // The index of the multipart which `line` is a boundary of, if any, and
+    // whether it's the closing boundary.
+    fn find_boundary(&self, line: &[u8]) -> Option<(usize, bool)> {
+        if !line.starts_with(b"--") || self.multiparts.is_empty() {
+            return None;
+        }
+        let line = String::from_utf8_lossy(&line[2..]);
+        let line = line.trim_end();
+        self.multiparts
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, multipart)| {
+                let boundary = &multipart.boundary[..];
+                match line.strip_prefix(boundary) {
+                    Some("") => Some((index, false)),
+                    Some("--") => Some((index, true)),
+                    _ => None,
+                }
+            })
+    }
+
+    fn boundary(
+        &mut self,
+        index: usize,
+        closing: bool,
+        events: &mut Vec<StreamEvent>,
+    ) -> ParsingResult<()> {
+        // The line break before a boundary is part of it
+        self.held.clear();
+        // Any multiparts within this one are left unterminated
+        for multipart in self.multiparts.drain(index + 1..).rev() {
+            if multipart.in_part {
+                events.push(StreamEvent::PartEnd);
+            }
+        }
+        let multipart = &mut self.multiparts[index];
+        if multipart.in_part {
+            events.push(StreamEvent::PartEnd);
+        }
+        if closing {
+            self.multiparts.pop();
+            self.state = State::Epilogue;
+            return Ok(());
+        }
+        multipart.in_part = true;
+        // The parts of the outermost multipart are at depth 2
+        let depth = self.multiparts.len() + 1;
+        if depth > self.limits.max_depth {
+            return Err(limit_exceeded("nesting depth", self.limits.max_depth));
+        } else if self.part_count >= self.limits.max_parts {
+            return Err(limit_exceeded("part count", self.limits.max_parts));
+        }
+        self.part_count += 1;
+        events.push(StreamEvent::PartStart);
+        self.state = State::Headers;
+        Ok(())
+    }
+
+    fn end_headers(&mut self, events: &mut Vec<StreamEvent>) {
+        self.header_count = 0;
+        self.header_length = 0;
+        let lines = std::mem::take(&mut self.header_lines);
+        let headers = Rfc5322Parser::new(&String::from_utf8_lossy(&lines)).consume_headers();
+        let content_type: ContentType = headers
+            .get_value("Content-Type".to_string())
+            .unwrap_or_default();
+        if content_type.ttype == "multipart" {
+            if let Some(boundary) = content_type.boundary() {
+                self.multiparts.push(Multipart {
+                    boundary: boundary.to_string(),
+                    in_part: false,
+                });
+            }
+        }
+        events.push(StreamEvent::Headers(headers));
+        self.state = State::Body;
+    }
+}
+
+fn line_break_length(line: &[u8]) -> usize {
+    if line.ends_with(b"\r\n") {
+        2
+    } else if line.ends_with(b"\n") {
+        1
+    } else {
+        0
+    }
+}
+
+/// Parses a message from a tokio `AsyncRead` as it arrives, giving the
+/// same events as a `MimeStreamParser`.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct AsyncMimeReader<R> {
+    reader: R,
+    parser: MimeStreamParser,
+    events: VecDeque<StreamEvent>,
+    finished: bool,
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin> From<R> for AsyncMimeReader<R> {
+    fn from(reader: R) -> AsyncMimeReader<R> {
+        AsyncMimeReader::with_limits(reader, Limits::default())
+    }
+}
+
+#[cfg(feature = "async")]
+impl<R: AsyncRead + Unpin> AsyncMimeReader<R> {
+    /// A reader of `reader` which fails on messages beyond `limits`, as a
+    /// `MimeStreamParser::with_limits` does.
+    pub fn with_limits(reader: R, limits: Limits) -> AsyncMimeReader<R> {
+        AsyncMimeReader {
+            reader,
+            parser: MimeStreamParser::with_limits(limits),
+            events: VecDeque::new(),
+            finished: false,
+        }
+    }
+
+    /// The next event in the message, reading more of it as needed, or
+    /// None once the end of the input has been reached.
+    ///
+    /// A message beyond the reader's limits is an error of kind
+    /// `InvalidData`.
+    pub async fn next_event(&mut self) -> io::Result<Option<StreamEvent>> {
+        let mut chunk = [0u8; 8192];
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Some(event));
+            }
+            if self.finished {
+                return Ok(None);
+            }
+            let read = self.reader.read(&mut chunk).await?;
+            let events = if read == 0 {
+                self.finished = true;
+                self.parser.finish()
+            } else {
+                self.parser.feed(&chunk[..read])
+            };
+            let events = events.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.events.extend(events);
+        }
+    }
+
+    /// The underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = "From: joe@example.org\r\n\
+                           Content-Type: multipart/mixed; boundary=foo\r\n\
+                           \r\n\
+                           Preamble\r\n\
+                           --foo\r\n\
+                           Content-Type: text/plain\r\n\
+                           \r\n\
+                           Hello\r\n\
+                           world\r\n\
+                           --foo\r\n\
+                           Content-Type: multipart/alternative; boundary=bar\r\n\
+                           \r\n\
+                           --bar\r\n\
+                           \r\n\
+                           Inner\r\n\
+                           --bar--\r\n\
+                           --foo--\r\n\
+                           Epilogue\r\n";
+
+    // Join up the body events, so the output doesn't depend on how the
+    // input was split into chunks
+    fn merge(events: Vec<StreamEvent>) -> Vec<StreamEvent> {
+        let mut merged: Vec<StreamEvent> = Vec::new();
+        for event in events {
+            match (merged.last_mut(), event) {
+                (Some(StreamEvent::Body(body)), StreamEvent::Body(more)) => body.extend(more),
+                (_, event) => merged.push(event),
+            }
+        }
+        merged
+    }
+
+    fn header_count(event: &StreamEvent) -> Option<usize> {
+        match *event {
+            StreamEvent::Headers(ref headers) => Some(headers.len()),
+            _ => None,
+        }
+    }
+
+    fn check_events(events: Vec<StreamEvent>) {
+        let events = merge(events);
+        assert_eq!(events.len(), 13);
+        assert_eq!(header_count(&events[0]), Some(2));
+        assert_eq!(events[1], StreamEvent::Body(b"Preamble".to_vec()));
+        assert_eq!(events[2], StreamEvent::PartStart);
+        assert_eq!(header_count(&events[3]), Some(1));
+        assert_eq!(events[4], StreamEvent::Body(b"Hello\r\nworld".to_vec()));
+        assert_eq!(events[5], StreamEvent::PartEnd);
+        assert_eq!(events[6], StreamEvent::PartStart);
+        assert_eq!(header_count(&events[7]), Some(1));
+        assert_eq!(events[8], StreamEvent::PartStart);
+        assert_eq!(header_count(&events[9]), Some(0));
+        assert_eq!(events[10], StreamEvent::Body(b"Inner".to_vec()));
+        assert_eq!(events[11], StreamEvent::PartEnd);
+        assert_eq!(events[12], StreamEvent::PartEnd);
+    }
+
+    #[test]
+    fn test_stream_parser() {
+        let mut parser = MimeStreamParser::new();
+        let mut events = parser.feed(MESSAGE.as_bytes()).unwrap();
+        events.extend(parser.finish().unwrap());
+        check_events(events);
+
+        // Fed a byte at a time, the same is found
+        let mut events = Vec::new();
+        for byte in MESSAGE.as_bytes() {
+            events.extend(parser.feed(&[*byte]).unwrap());
+        }
+        events.extend(parser.finish().unwrap());
+        check_events(events);
+    }
+
+    #[test]
+    fn test_stream_parser_unterminated() {
+        let mut parser = MimeStreamParser::new();
+        let mut events = parser
+            .feed(b"Subject: Hi\r\n\r\nBody\r\nwithout end")
+            .unwrap();
+        assert_eq!(header_count(&events[0]), Some(1));
+        events.extend(parser.finish().unwrap());
+        assert_eq!(
+            merge(events)[1..],
+            [StreamEvent::Body(b"Body\r\nwithout end".to_vec())]
+        );
+
+        let mut events = parser
+            .feed(b"Content-Type: multipart/mixed; boundary=foo\n\n--foo\n\nPart\n")
+            .unwrap();
+        events.extend(parser.finish().unwrap());
+        assert_eq!(
+            merge(events)[1..],
+            [
+                StreamEvent::PartStart,
+                StreamEvent::Headers(HeaderMap::new()),
+                StreamEvent::Body(b"Part\n".to_vec()),
+                StreamEvent::PartEnd,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stream_parser_limits() {
+        // A long line is given as it arrives, rather than held back
+        let mut parser = MimeStreamParser::new();
+        parser.feed(b"Subject: Hi\r\n\r\nStart\r\n").unwrap();
+        let long = vec![b'a'; MAX_HELD_LINE + 1];
+        let events = parser.feed(&long).unwrap();
+        let mut body = b"\r\n".to_vec();
+        body.extend_from_slice(&long);
+        assert_eq!(events, [StreamEvent::Body(body)]);
+        assert!(parser.buffer.is_empty());
+        // Even if the rest of it looks like a boundary
+        let mut parser = MimeStreamParser::new();
+        parser
+            .feed(b"Content-Type: multipart/mixed; boundary=foo\r\n\r\n--foo\r\n\r\n")
+            .unwrap();
+        parser.feed(&long).unwrap();
+        assert_eq!(merge(parser.feed(b"--foo--\r\n").unwrap()).len(), 1);
+
+        let limits = Limits {
+            max_header_count: 2,
+            max_header_length: 20,
+            ..Limits::default()
+        };
+        let mut parser = MimeStreamParser::with_limits(limits);
+        assert!(parser.feed(b"A: 1\r\nB: 2\r\n").is_ok());
+        assert!(parser.feed(b"C: 3\r\n").is_err());
+        // The parser starts over after an error
+        assert!(parser.feed(b"A: 1\r\n \r\n").is_ok());
+        assert!(parser.feed(b" 123456789012345\r\n").is_err());
+        assert!(parser.feed(&vec![b'a'; MAX_HELD_LINE + 1]).is_err());
+
+        let limits = Limits {
+            max_depth: 2,
+            max_parts: 3,
+            ..Limits::default()
+        };
+        let mut parser = MimeStreamParser::with_limits(limits);
+
+        let nested = "Content-Type: multipart/mixed; boundary=a\r\n\r\n\
+                      --a\r\n\
+                      Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+                      --b\r\n\r\n";
+        assert!(parser.feed(nested.as_bytes()).is_err());
+        let parts = "Content-Type: multipart/mixed; boundary=a\r\n\r\n\
+                     --a\r\n\r\n--a\r\n\r\n";
+        assert!(parser.feed(parts.as_bytes()).is_ok());
+        assert!(parser.feed(b"--a\r\n").is_err());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_reader() {
+        use std::future::Future;
+        use std::sync::Arc;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct NoopWaker;
+
+        impl Wake for NoopWaker {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        // Reading from a slice never has to wait, so polling once is enough
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let waker = Waker::from(Arc::new(NoopWaker));
+            let mut context = Context::from_waker(&waker);
+            match Box::pin(future).as_mut().poll(&mut context) {
+                Poll::Ready(output) => output,
+                Poll::Pending => panic!("Reading from a slice shouldn't wait"),
+            }
+        }
+
+        let mut reader = AsyncMimeReader::from(MESSAGE.as_bytes());
+        let events = block_on(async {
+            let mut events = Vec::new();
+            while let Some(event) = reader.next_event().await.unwrap() {
+                events.push(event);
+            }
+            events
+        });
+        check_events(events);
+    }
+}
@@ -0,0 +1,148 @@
+//! Module for normalizing subjects, to tell which messages are about the
+//! same thing when their references don't say.
+//!
+//! Mail clients mark replies and forwards by adding a prefix to the
+//! subject, which is localized by many of them and stacks up as a
+//! conversation goes on, and mailing lists add a tag in brackets. The
+//! normalized subject has all of these removed.
+
+// Prefixes marking replies and forwards, lowercase, from the clients of
+// various languages
+static PREFIXES: [&str; 23] = [
+    "re", "fw", "fwd", // English
+    "aw", "wg", // German
+    "sv", "vs", // Scandinavian
+    "antw", "doorst", // Dutch
+    "tr", "réf", // French
+    "r", "rif", // Italian
+    "res", "enc", // Portuguese
+    "odp", "pd", // Polish
+    "ynt", "ilt", // Turkish
+    "回复", "回覆", "答复", "转发", // Chinese
+];
+
+/// Normalize `subject` for comparing it with others.
+///
+/// Reply and forward prefixes, such as `Re:`, `AW:` or `回复:`, are
+/// removed however many there are, along with mailing list tags in
+/// brackets among them, and each run of whitespace left is reduced to a
+/// single space. Prefixes with a count, as in `Re[2]:`, are removed too.
+pub fn normalize(subject: &str) -> String {
+    strip(subject).0
+}
+
+/// Whether `subject` starts with a reply or forward prefix, possibly
+/// after a mailing list tag.
+pub fn is_reply(subject: &str) -> bool {
+    strip(subject).1
+}
+
+// The normalized subject, and whether any prefixes were removed.
+fn strip(subject: &str) -> (String, bool) {
+    let mut s = subject.trim_start();
+    let mut is_reply = false;
+    loop {
+        if let Some(rest) = strip_tag(s) {
+            s = rest;
+        } else if let Some(rest) = strip_prefix(s) {
+            s = rest;
+            is_reply = true;
+        } else {
+            break;
+        }
+        s = s.trim_start();
+    }
+    (
+        s.split_whitespace().collect::<Vec<&str>>().join(" "),
+        is_reply,
+    )
+}
+
+// `s` without the mailing list tag it starts with.
+fn strip_tag(s: &str) -> Option<&str> {
+    let rest = s.strip_prefix('[')?;
+    let end = rest.find(']')?;
+    Some(&rest[end + 1..])
+}
+
+/// `subject` without the prefixes it starts with which are among
+/// `prefixes`, given lowercase and without their colons, recognized as
+/// `normalize` does.
+pub(crate) fn strip_prefixes<'s>(subject: &'s str, prefixes: &[&str]) -> &'s str {
+    let mut s = subject.trim();
+    while let Some(rest) = strip_prefix_of(s, prefixes) {
+        s = rest.trim_start();
+    }
+    s
+}
+
+// `s` without the reply or forward prefix it starts with.
+fn strip_prefix(s: &str) -> Option<&str> {
+    strip_prefix_of(s, &PREFIXES)
+}
+
+// `s` without the prefix it starts with, if it's one of `prefixes`.
+fn strip_prefix_of<'s>(s: &'s str, prefixes: &[&str]) -> Option<&'s str> {
+    let word_len = s
+        .char_indices()
+        .find(|&(_, c)| !c.is_alphabetic())
+        .map_or(s.len(), |(i, _)| i);
+    let word = s[..word_len].to_lowercase();
+    if !prefixes.contains(&&word[..]) {
+        return None;
+    }
+    let mut rest = &s[word_len..];
+    // A count of the replies, as in `Re[2]:`, `Re(2):` or `Re^2:`
+    if let Some(count) = rest.strip_prefix(&['[', '('][..]) {
+        let end = count.find(&[']', ')'][..])?;
+        if !count[..end].chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        rest = &count[end + 1..];
+    } else if let Some(count) = rest.strip_prefix('^') {
+        rest = count.trim_start_matches(|c: char| c.is_ascii_digit());
+    }
+    rest.trim_start()
+        .strip_prefix(':')
+        .or_else(|| rest.trim_start().strip_prefix('：'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let subjects = [
+            ("Lunch", "Lunch"),
+            ("Re: Lunch", "Lunch"),
+            ("RE: re: Fwd: Lunch", "Lunch"),
+            ("AW: WG: Mittagessen", "Mittagessen"),
+            ("SV: Lunsj", "Lunsj"),
+            ("回复：午饭", "午饭"),
+            ("Re[2]: Lunch", "Lunch"),
+            ("Re^3: Lunch", "Lunch"),
+            (
+                "[rust-users] Re: [rust-users]  Lunch\t today ",
+                "Lunch today",
+            ),
+            ("Re : Lunch", "Lunch"),
+            // Words which only look like prefixes are kept
+            ("Regarding: Lunch", "Regarding: Lunch"),
+            ("Re Lunch", "Re Lunch"),
+            ("Re[x]: Lunch", "Re[x]: Lunch"),
+            ("", ""),
+        ];
+        for &(subject, normalized) in subjects.iter() {
+            assert_eq!(normalize(subject), normalized, "{}", subject);
+        }
+    }
+
+    #[test]
+    fn test_is_reply() {
+        assert!(is_reply("Re: Lunch"));
+        assert!(is_reply("[list] Fwd: Lunch"));
+        assert!(!is_reply("[list] Lunch"));
+        assert!(!is_reply("Lunch: Re: today"));
+    }
+}
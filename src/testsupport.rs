@@ -0,0 +1,426 @@
+//! A harness for checking the parser against a corpus of messages, such as
+//! before upgrading this crate, built with the `testsupport` feature.
+//!
+//! Each `.eml` file in the corpus is parsed, and a JSON snapshot of the
+//! result, with the headers, content type, body and warnings of each part,
+//! is compared against the snapshot in the `.json` file beside it. Missing
+//! snapshots are written, so the first run over a corpus records how the
+//! current version parses it.
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Map, Value};
+
+use super::message::{MimeMessage, ParseOptions};
+use super::results::{ParseWarning, ParsingError};
+
+/// The JSON snapshot of `message`, as kept for each message of a corpus.
+///
+/// Each part has its headers, with their values unfolded, and its content
+/// type. Text parts have their decoded text, and other parts only the size
+/// of their decoded body. Multipart parts have their children instead.
+pub fn snapshot(message: &MimeMessage, warnings: &[ParseWarning]) -> Value {
+    let mut snapshot = part_snapshot(message);
+    let warnings: Vec<Value> = warnings
+        .iter()
+        .map(|warning| json!(format!("{:?}", warning.code)))
+        .collect();
+    snapshot.insert("warnings".to_string(), Value::Array(warnings));
+    Value::Object(snapshot)
+}
+
+fn part_snapshot(part: &MimeMessage) -> Map<String, Value> {
+    let mut snapshot = Map::new();
+    let headers: Vec<Value> = part
+        .headers
+        .iter()
+        .map(|header| json!([&header.name[..], header.raw_value()]))
+        .collect();
+    snapshot.insert("headers".to_string(), Value::Array(headers));
+    let content_type = part.effective_content_type();
+    snapshot.insert("contentType".to_string(), json!(content_type.to_string()));
+    if !part.children.is_empty() {
+        let children = part
+            .children
+            .iter()
+            .map(|child| Value::Object(part_snapshot(child)))
+            .collect();
+        snapshot.insert("children".to_string(), Value::Array(children));
+    } else if content_type.ttype == "text" {
+        let body = part.decoded_body_string().ok();
+        snapshot.insert("body".to_string(), json!(body));
+    } else {
+        let size = part.decoded_body_bytes().map(|body| body.len());
+        snapshot.insert("size".to_string(), json!(size));
+    }
+    snapshot
+}
+
+/// Where the snapshot of a message differs from the one expected of it.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SnapshotDiff {
+    /// Where the difference is, as a JSON pointer such as
+    /// `/children/0/body`
+    pub pointer: String,
+    /// The value expected there, if there was one
+    pub expected: Option<Value>,
+    /// The value found there, if there was one
+    pub actual: Option<Value>,
+}
+
+impl fmt::Display for SnapshotDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let show = |value: &Option<Value>| match value {
+            Some(value) => value.to_string(),
+            None => "nothing".to_string(),
+        };
+        write!(
+            f,
+            "{}: expected {}, found {}",
+            self.pointer,
+            show(&self.expected),
+            show(&self.actual)
+        )
+    }
+}
+
+/// The differences between the snapshots `expected` and `actual`.
+pub fn diff_snapshots(expected: &Value, actual: &Value) -> Vec<SnapshotDiff> {
+    let mut diffs = Vec::new();
+    diff_values(String::new(), Some(expected), Some(actual), &mut diffs);
+    diffs
+}
+
+fn diff_values(
+    pointer: String,
+    expected: Option<&Value>,
+    actual: Option<&Value>,
+    diffs: &mut Vec<SnapshotDiff>,
+) {
+    match (expected, actual) {
+        (Some(Value::Object(expected)), Some(Value::Object(actual))) => {
+            let mut keys: Vec<&String> = expected.keys().chain(actual.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let escaped = key.replace('~', "~0").replace('/', "~1");
+                let pointer = format!("{}/{}", pointer, escaped);
+                diff_values(pointer, expected.get(key), actual.get(key), diffs);
+            }
+        }
+        (Some(Value::Array(expected)), Some(Value::Array(actual))) => {
+            for i in 0..expected.len().max(actual.len()) {
+                let pointer = format!("{}/{}", pointer, i);
+                diff_values(pointer, expected.get(i), actual.get(i), diffs);
+            }
+        }
+        (expected, actual) if expected != actual => diffs.push(SnapshotDiff {
+            pointer,
+            expected: expected.cloned(),
+            actual: actual.cloned(),
+        }),
+        _ => {}
+    }
+}
+
+/// How a message of a corpus fared.
+#[derive(Debug)]
+pub enum Outcome {
+    /// It parsed just as its snapshot says
+    Passed,
+    /// It had no snapshot, so one was written
+    Recorded,
+    /// Its snapshot was replaced, as `Corpus::update_snapshots` asks
+    Updated,
+    /// It parsed differently to its snapshot
+    Mismatched(Vec<SnapshotDiff>),
+    /// It failed to parse at all
+    Failed(ParsingError),
+}
+
+/// How a single message of a corpus fared.
+#[derive(Debug)]
+pub struct FileReport {
+    /// The path of the message
+    pub path: PathBuf,
+    pub outcome: Outcome,
+    /// The problems the parser worked around in the message
+    pub warnings: Vec<ParseWarning>,
+}
+
+/// How each message of a corpus fared, in the order of their paths.
+#[derive(Debug, Default)]
+pub struct CorpusReport {
+    pub files: Vec<FileReport>,
+}
+
+impl CorpusReport {
+    /// The number of messages which passed, including those whose snapshots
+    /// were just written.
+    pub fn passed(&self) -> usize {
+        self.count(|outcome| {
+            matches!(
+                outcome,
+                Outcome::Passed | Outcome::Recorded | Outcome::Updated
+            )
+        })
+    }
+
+    /// The number of messages which parsed differently to their snapshots.
+    pub fn mismatched(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Mismatched(_)))
+    }
+
+    /// The number of messages which failed to parse.
+    pub fn failed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Failed(_)))
+    }
+
+    /// The number of warnings across all of the messages.
+    pub fn warnings(&self) -> usize {
+        self.files.iter().map(|file| file.warnings.len()).sum()
+    }
+
+    /// Whether every message passed.
+    pub fn is_ok(&self) -> bool {
+        self.passed() == self.files.len()
+    }
+
+    fn count<F: Fn(&Outcome) -> bool>(&self, test: F) -> usize {
+        self.files.iter().filter(|file| test(&file.outcome)).count()
+    }
+}
+
+impl fmt::Display for CorpusReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for file in self.files.iter() {
+            match file.outcome {
+                Outcome::Mismatched(ref diffs) => {
+                    writeln!(f, "MISMATCH {}", file.path.display())?;
+                    for diff in diffs.iter() {
+                        writeln!(f, "    {}", diff)?;
+                    }
+                }
+                Outcome::Failed(ref error) => {
+                    writeln!(f, "FAILED {}: {}", file.path.display(), error)?
+                }
+                _ => {}
+            }
+        }
+        write!(
+            f,
+            "{} passed, {} mismatched, {} failed; {} warnings",
+            self.passed(),
+            self.mismatched(),
+            self.failed(),
+            self.warnings()
+        )
+    }
+}
+
+/// A directory of `.eml` files, searched recursively, with the snapshot of
+/// each in a `.json` file of the same name.
+#[derive(Debug, Clone)]
+pub struct Corpus {
+    path: PathBuf,
+    options: ParseOptions,
+    update: bool,
+}
+
+impl Corpus {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Corpus {
+        Corpus {
+            path: path.into(),
+            options: ParseOptions::default(),
+            update: false,
+        }
+    }
+
+    /// Parse the messages as set out by `options`.
+    pub fn options(mut self, options: ParseOptions) -> Corpus {
+        self.options = options;
+        self
+    }
+
+    /// Replace the snapshots of messages which parse differently, rather
+    /// than reporting them, to accept the changes.
+    pub fn update_snapshots(mut self, update: bool) -> Corpus {
+        self.update = update;
+        self
+    }
+
+    /// Check each message against its snapshot.
+    ///
+    /// Fails only if the corpus can't be read, or a snapshot can't be read
+    /// or written.
+    pub fn run(&self) -> io::Result<CorpusReport> {
+        let mut paths = Vec::new();
+        find_messages(&self.path, &mut paths)?;
+        paths.sort();
+        let mut report = CorpusReport::default();
+        for path in paths {
+            report.files.push(self.check(path)?);
+        }
+        Ok(report)
+    }
+
+    fn check(&self, path: PathBuf) -> io::Result<FileReport> {
+        let input = fs::read(&path)?;
+        let (message, warnings) =
+            match MimeMessage::parse_bytes_with_warnings(&input, &self.options) {
+                Ok(parsed) => parsed,
+                Err(error) => {
+                    return Ok(FileReport {
+                        path,
+                        outcome: Outcome::Failed(error),
+                        warnings: Vec::new(),
+                    })
+                }
+            };
+        let actual = snapshot(&message, &warnings);
+        let snapshot_path = path.with_extension("json");
+        let outcome = match fs::read(&snapshot_path) {
+            Ok(expected) => {
+                let expected: Value = serde_json::from_slice(&expected)?;
+                let diffs = diff_snapshots(&expected, &actual);
+                if diffs.is_empty() {
+                    Outcome::Passed
+                } else if self.update {
+                    write_snapshot(&snapshot_path, &actual)?;
+                    Outcome::Updated
+                } else {
+                    Outcome::Mismatched(diffs)
+                }
+            }
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => {
+                write_snapshot(&snapshot_path, &actual)?;
+                Outcome::Recorded
+            }
+            Err(error) => return Err(error),
+        };
+        Ok(FileReport {
+            path,
+            outcome,
+            warnings,
+        })
+    }
+}
+
+fn find_messages(dir: &Path, paths: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_messages(&path, paths)?;
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("eml"))
+        {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn write_snapshot(path: &Path, snapshot: &Value) -> io::Result<()> {
+    let mut json = serde_json::to_string_pretty(snapshot)?;
+    json.push('\n');
+    fs::write(path, json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::environment;
+
+    struct TempCorpus(PathBuf);
+
+    impl TempCorpus {
+        fn new() -> TempCorpus {
+            let name = format!("email-corpus-{:016x}", environment::random_u64());
+            let path = std::env::temp_dir().join(name);
+            fs::create_dir_all(path.join("nested")).unwrap();
+            TempCorpus(path)
+        }
+    }
+
+    impl Drop for TempCorpus {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_corpus() {
+        let corpus = TempCorpus::new();
+        let plain = corpus.0.join("plain.eml");
+        fs::write(
+            &plain,
+            "From: joe@example.org\r\nSubject: Hi\r\n\r\nHello\r\n",
+        )
+        .unwrap();
+        fs::write(
+            corpus.0.join("nested/multipart.EML"),
+            "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\n\r\nOne\r\n\
+             --b\r\nContent-Type: image/png\r\n\r\nPNG\r\n",
+        )
+        .unwrap();
+        fs::write(corpus.0.join("notes.txt"), "Not a message").unwrap();
+
+        // The first run records the snapshots
+        let report = Corpus::new(&corpus.0).run().unwrap();
+        assert_eq!(report.files.len(), 2);
+        assert!(matches!(report.files[0].outcome, Outcome::Recorded));
+        assert_eq!(report.files[0].warnings.len(), 3);
+        assert!(report.is_ok());
+        let report = Corpus::new(&corpus.0).run().unwrap();
+        assert!(matches!(report.files[1].outcome, Outcome::Passed));
+        assert_eq!(
+            report.to_string(),
+            "2 passed, 0 mismatched, 0 failed; 4 warnings"
+        );
+
+        fs::write(
+            &plain,
+            "From: joe@example.org\r\nSubject: Bye\r\n\r\nHello\r\n",
+        )
+        .unwrap();
+        let report = Corpus::new(&corpus.0).run().unwrap();
+        match report.files[1].outcome {
+            Outcome::Mismatched(ref diffs) => {
+                assert_eq!(diffs.len(), 1);
+                assert_eq!(diffs[0].pointer, "/headers/1/1");
+                assert_eq!(
+                    diffs[0].to_string(),
+                    "/headers/1/1: expected \"Hi\", found \"Bye\""
+                );
+            }
+            ref outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+        assert!(!report.is_ok());
+
+        let report = Corpus::new(&corpus.0).update_snapshots(true).run().unwrap();
+        assert!(matches!(report.files[1].outcome, Outcome::Updated));
+        assert!(Corpus::new(&corpus.0).run().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_diff_snapshots() {
+        let expected = json!({"a/b": [1, 2], "c": {"d": true}});
+        let actual = json!({"a/b": [1], "c": {"d": false}, "e": null});
+        let diffs: Vec<String> = diff_snapshots(&expected, &actual)
+            .iter()
+            .map(|diff| diff.to_string())
+            .collect();
+        assert_eq!(
+            diffs,
+            vec![
+                "/a~1b/1: expected 2, found nothing",
+                "/c/d: expected true, found false",
+                "/e: expected nothing, found null",
+            ]
+        );
+    }
+}
@@ -0,0 +1,515 @@
+//! Module implementing conversation threading.
+//!
+//! This follows Jamie Zawinski's threading algorithm, as also used by
+//! RFC 5256 `THREAD=REFERENCES`, over the `Message-ID`, `References`,
+//! `In-Reply-To` and `Subject` headers of a collection of messages.
+use std::collections::HashMap;
+
+use super::header::HeaderMap;
+use super::messageid::MessageId;
+use super::subject;
+
+/// The headers of a message relevant to threading it.
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct MessageSummary {
+    /// The `Message-ID` of the message
+    pub message_id: Option<MessageId>,
+    /// The ancestors of the message, oldest first
+    pub references: Vec<MessageId>,
+    /// The `Subject` of the message
+    pub subject: Option<String>,
+}
+
+impl MessageSummary {
+    /// Build a summary from a message's headers.
+    ///
+    /// The references are taken from `References`, with the first identifier
+    /// of `In-Reply-To` appended if it isn't already the last reference.
+    pub fn from_headers(headers: &HeaderMap) -> MessageSummary {
+        let mut references: Vec<MessageId> = headers
+            .get_value("References".to_string())
+            .unwrap_or_default();
+        let in_reply_to: Vec<MessageId> = headers
+            .get_value("In-Reply-To".to_string())
+            .unwrap_or_default();
+        if let Some(parent) = in_reply_to.into_iter().next() {
+            if references.last() != Some(&parent) {
+                references.push(parent);
+            }
+        }
+
+        MessageSummary {
+            message_id: headers.get_value("Message-ID".to_string()).ok(),
+            references,
+            subject: headers.get_value("Subject".to_string()).ok(),
+        }
+    }
+}
+
+/// A node in a conversation tree.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Thread {
+    /// The index of this node's message in the input to `thread`, or `None`
+    /// if this node stands in for a message that was referenced but not given.
+    pub message: Option<usize>,
+    /// The replies to this message
+    pub children: Vec<Thread>,
+}
+
+impl Thread {
+    /// The indexes of all messages in this thread, depth first.
+    pub fn messages(&self) -> Vec<usize> {
+        let mut result = Vec::new();
+        self.collect_messages(&mut result);
+        result
+    }
+
+    fn collect_messages(&self, result: &mut Vec<usize>) {
+        let mut stack = vec![self];
+        while let Some(thread) = stack.pop() {
+            if let Some(message) = thread.message {
+                result.push(message);
+            }
+            stack.extend(thread.children.iter().rev());
+        }
+    }
+}
+
+impl Drop for Thread {
+    // Take the tree apart a node at a time, as dropping it recursively
+    // could overflow the stack for deep threads
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.children);
+        while let Some(mut thread) = stack.pop() {
+            stack.append(&mut thread.children);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Container {
+    message: Option<usize>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+struct Threader {
+    containers: Vec<Container>,
+}
+
+impl Threader {
+    fn new_container(&mut self) -> usize {
+        self.containers.push(Container::default());
+        self.containers.len() - 1
+    }
+
+    /// Returns true if `ancestor` is `node` or one of its parents.
+    fn is_ancestor(&self, ancestor: usize, node: usize) -> bool {
+        let mut current = Some(node);
+        while let Some(c) = current {
+            if c == ancestor {
+                return true;
+            }
+            current = self.containers[c].parent;
+        }
+        false
+    }
+
+    fn unlink(&mut self, child: usize) {
+        if let Some(parent) = self.containers[child].parent.take() {
+            self.containers[parent].children.retain(|&c| c != child);
+        }
+    }
+
+    fn link(&mut self, parent: usize, child: usize) {
+        self.unlink(child);
+        self.containers[child].parent = Some(parent);
+        self.containers[parent].children.push(child);
+    }
+
+    /// The containers of the trees below `roots`, each after all of its
+    /// descendants, found without recursing so that deep trees can't
+    /// overflow the stack.
+    fn descendants_first(&self, roots: &[usize]) -> Vec<usize> {
+        let mut order = Vec::new();
+        let mut stack = roots.to_vec();
+        while let Some(c) = stack.pop() {
+            order.push(c);
+            stack.extend(self.containers[c].children.iter());
+        }
+        order.reverse();
+        order
+    }
+
+    /// Remove empty containers below `roots`, returning the new list of roots.
+    ///
+    /// Empty containers with no children are dropped, and those with children
+    /// are replaced by their children. At the root level this only happens
+    /// if there is a single child, so that siblings are not split into
+    /// separate threads.
+    fn prune(&mut self, roots: Vec<usize>) -> Vec<usize> {
+        // Each container's children are pruned before it is
+        for c in self.descendants_first(&roots) {
+            let children = std::mem::take(&mut self.containers[c].children);
+            let mut pruned = Vec::new();
+            for child in children.into_iter() {
+                pruned.extend(self.prune_one(child, false));
+            }
+            for &p in pruned.iter() {
+                self.containers[p].parent = Some(c);
+            }
+            self.containers[c].children = pruned;
+        }
+
+        let mut result = Vec::new();
+        for root in roots.into_iter() {
+            result.extend(self.prune_one(root, true));
+        }
+        result
+    }
+
+    /// What `c`, whose children have already been pruned, is replaced by.
+    fn prune_one(&mut self, c: usize, is_root: bool) -> Vec<usize> {
+        let container = &mut self.containers[c];
+        if container.message.is_some() {
+            vec![c]
+        } else if container.children.is_empty() {
+            // Nothing to see here, drop it.
+            Vec::new()
+        } else if !is_root || container.children.len() == 1 {
+            let promoted = std::mem::take(&mut container.children);
+            let parent = container.parent;
+            for &p in promoted.iter() {
+                self.containers[p].parent = parent;
+            }
+            promoted
+        } else {
+            vec![c]
+        }
+    }
+
+    /// The conversation trees below `roots`.
+    fn build(&self, roots: &[usize]) -> Vec<Thread> {
+        let mut built: HashMap<usize, Thread> = HashMap::new();
+        for c in self.descendants_first(roots) {
+            let children = self.containers[c]
+                .children
+                .iter()
+                .filter_map(|child| built.remove(child))
+                .collect();
+            let thread = Thread {
+                message: self.containers[c].message,
+                children,
+            };
+            built.insert(c, thread);
+        }
+        roots.iter().filter_map(|root| built.remove(root)).collect()
+    }
+}
+
+/// Thread a collection of messages into conversation trees.
+///
+/// The returned roots and their children refer to messages by their index in
+/// `messages`, and are ordered by the position of the (first) message in
+/// `messages`, so pass them in date order to get date ordered threads.
+pub fn thread(messages: &[MessageSummary]) -> Vec<Thread> {
+    let mut threader = Threader {
+        containers: Vec::new(),
+    };
+    let mut id_table: HashMap<String, usize> = HashMap::new();
+
+    for (index, message) in messages.iter().enumerate() {
+        // Find (or make) the container for this message
+        let container = match message.message_id {
+            Some(ref id) => match id_table.get(id.id()) {
+                Some(&c) if threader.containers[c].message.is_none() => c,
+                Some(_) => {
+                    // A duplicate Message-ID, so treat it as a message without one
+                    threader.new_container()
+                }
+                None => {
+                    let c = threader.new_container();
+                    id_table.insert(id.id().to_string(), c);
+                    c
+                }
+            },
+            None => threader.new_container(),
+        };
+        threader.containers[container].message = Some(index);
+
+        // Link together the references, without overriding existing links
+        // and without making loops.
+        let mut previous: Option<usize> = None;
+        for reference in message.references.iter() {
+            let c = match id_table.get(reference.id()) {
+                Some(&c) => c,
+                None => {
+                    let c = threader.new_container();
+                    id_table.insert(reference.id().to_string(), c);
+                    c
+                }
+            };
+            if let Some(p) = previous {
+                if threader.containers[c].parent.is_none() && !threader.is_ancestor(c, p) {
+                    threader.link(p, c);
+                }
+            }
+            previous = Some(c);
+        }
+
+        // The message's own references are authoritative for its parent
+        threader.unlink(container);
+        if let Some(p) = previous {
+            if !threader.is_ancestor(container, p) {
+                threader.link(p, container);
+            }
+        }
+    }
+
+    let roots: Vec<usize> = (0..threader.containers.len())
+        .filter(|&c| threader.containers[c].parent.is_none())
+        .collect();
+    let mut roots = threader.prune(roots);
+
+    // Gather together threads whose roots share a subject
+    let subject_of = |threader: &Threader, c: usize| -> Option<(String, bool)> {
+        let container = &threader.containers[c];
+        let message = match container.message {
+            Some(m) => Some(m),
+            None => container
+                .children
+                .first()
+                .and_then(|&child| threader.containers[child].message),
+        };
+        message
+            .and_then(|m| messages[m].subject.as_ref())
+            .map(|s| (subject::normalize(s), subject::is_reply(s)))
+            .filter(|(s, _)| !s.is_empty())
+    };
+
+    let mut subject_table: HashMap<String, usize> = HashMap::new();
+    for &root in roots.iter() {
+        if let Some((subject, is_reply)) = subject_of(&threader, root) {
+            let replace = match subject_table.get(&subject) {
+                None => true,
+                Some(&existing) => {
+                    let existing_empty = threader.containers[existing].message.is_none();
+                    let existing_reply = subject_of(&threader, existing).is_some_and(|s| s.1);
+                    (threader.containers[root].message.is_none() && !existing_empty)
+                        || (existing_reply && !is_reply)
+                }
+            };
+            if replace {
+                subject_table.insert(subject, root);
+            }
+        }
+    }
+
+    let mut merged_roots = Vec::new();
+    for root in roots.drain(..) {
+        let (subject, is_reply) = match subject_of(&threader, root) {
+            Some(s) => s,
+            None => {
+                merged_roots.push(root);
+                continue;
+            }
+        };
+        let target = subject_table[&subject];
+        if target == root {
+            merged_roots.push(root);
+            continue;
+        }
+
+        let root_empty = threader.containers[root].message.is_none();
+        let target_empty = threader.containers[target].message.is_none();
+        let target_reply = subject_of(&threader, target).is_some_and(|s| s.1);
+        if root_empty && target_empty {
+            let children = std::mem::take(&mut threader.containers[root].children);
+            for child in children.into_iter() {
+                threader.link(target, child);
+            }
+        } else if target_empty || (!target_reply && is_reply) {
+            threader.link(target, root);
+        } else if root_empty {
+            // The target is merged into this root, which takes its place.
+            threader.link(root, target);
+            subject_table.insert(subject, root);
+            merged_roots.retain(|&r| r != target);
+            merged_roots.push(root);
+        } else {
+            // Neither is a reply of the other, so make them siblings
+            let parent = threader.new_container();
+            threader.link(parent, target);
+            threader.link(parent, root);
+            subject_table.insert(subject, parent);
+            match merged_roots.iter().position(|&r| r == target) {
+                Some(i) => merged_roots[i] = parent,
+                None => merged_roots.push(parent),
+            }
+        }
+    }
+
+    threader.build(&merged_roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::header::Header;
+    use super::*;
+
+    fn summary(id: &str, references: &[&str], subject: &str) -> MessageSummary {
+        MessageSummary {
+            message_id: Some(MessageId::new(id.to_string())),
+            references: references
+                .iter()
+                .map(|r| MessageId::new(r.to_string()))
+                .collect(),
+            subject: Some(subject.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_summary_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new(
+            "Message-ID".to_string(),
+            "<c@example.org>".to_string(),
+        ));
+        headers.insert(Header::new(
+            "References".to_string(),
+            "<a@example.org>\r\n\t<b@example.org>".to_string(),
+        ));
+        headers.insert(Header::new(
+            "In-Reply-To".to_string(),
+            "<b@example.org>".to_string(),
+        ));
+        let s = MessageSummary::from_headers(&headers);
+        assert_eq!(
+            s.message_id,
+            Some(MessageId::new("c@example.org".to_string()))
+        );
+        assert_eq!(s.references.len(), 2);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new(
+            "In-Reply-To".to_string(),
+            "<b@example.org>".to_string(),
+        ));
+        let s = MessageSummary::from_headers(&headers);
+        assert_eq!(
+            s.references,
+            vec![MessageId::new("b@example.org".to_string())]
+        );
+        assert_eq!(s.message_id, None);
+    }
+
+    #[test]
+    fn test_simple_thread() {
+        let messages = vec![
+            summary("a", &[], "Hello"),
+            summary("b", &["a"], "Re: Hello"),
+            summary("c", &["a", "b"], "Re: Hello"),
+            summary("d", &["a"], "Re: Hello"),
+            summary("e", &[], "Unrelated"),
+        ];
+        let threads = thread(&messages);
+        assert_eq!(threads.len(), 2);
+        assert_eq!(
+            threads[0],
+            Thread {
+                message: Some(0),
+                children: vec![
+                    Thread {
+                        message: Some(1),
+                        children: vec![Thread {
+                            message: Some(2),
+                            children: vec![],
+                        }],
+                    },
+                    Thread {
+                        message: Some(3),
+                        children: vec![],
+                    },
+                ],
+            }
+        );
+        assert_eq!(threads[0].messages(), vec![0, 1, 2, 3]);
+        assert_eq!(threads[1].messages(), vec![4]);
+    }
+
+    #[test]
+    fn test_missing_parent() {
+        // Two replies to a message we don't have are kept together under an
+        // empty container.
+        let messages = vec![
+            summary("b", &["a"], "Re: Hello"),
+            summary("c", &["a"], "Re: Hello"),
+        ];
+        let threads = thread(&messages);
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].message, None);
+        assert_eq!(threads[0].messages(), vec![0, 1]);
+
+        // A single reply to a missing message is promoted to the root.
+        let threads = thread(&messages[..1]);
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].message, Some(0));
+    }
+
+    #[test]
+    fn test_subject_grouping() {
+        let messages = vec![
+            summary("a", &[], "Hello"),
+            // A reply from a client which doesn't set References
+            summary("b", &[], "Re: Hello"),
+        ];
+        let threads = thread(&messages);
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].message, Some(0));
+        assert_eq!(threads[0].children[0].message, Some(1));
+    }
+
+    #[test]
+    fn test_reference_loops() {
+        let messages = vec![summary("a", &["b"], "One"), summary("b", &["a"], "Two")];
+        let threads = thread(&messages);
+        let mut all: Vec<usize> = threads.iter().flat_map(|t| t.messages()).collect();
+        all.sort();
+        assert_eq!(all, vec![0, 1]);
+
+        // A message referencing itself
+        let threads = thread(&[summary("a", &["a"], "Self")]);
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].messages(), vec![0]);
+    }
+
+    #[test]
+    fn test_deep_threads() {
+        let ids: Vec<String> = (0..20_000).map(|i| i.to_string()).collect();
+
+        // A long chain of references to messages which aren't given
+        let references: Vec<&str> = ids.iter().map(|id| &id[..]).collect();
+        let threads = thread(&[summary("a", &references, "Deep")]);
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].messages(), vec![0]);
+
+        // And a long chain of replies
+        let messages: Vec<MessageSummary> = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| summary(id, &references[i.saturating_sub(1)..i], "Deep"))
+            .collect();
+        let threads = thread(&messages);
+        assert_eq!(threads.len(), 1);
+        assert_eq!(threads[0].messages().len(), 20_000);
+    }
+
+    #[test]
+    fn test_duplicate_message_ids() {
+        let messages = vec![summary("a", &[], "One"), summary("a", &[], "Two")];
+        let threads = thread(&messages);
+        let mut all: Vec<usize> = threads.iter().flat_map(|t| t.messages()).collect();
+        all.sort();
+        assert_eq!(all, vec![0, 1]);
+    }
+}
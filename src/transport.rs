@@ -0,0 +1,285 @@
+//! Module for sending messages over SMTP, RFC 5321.
+//!
+//! A message sent with the `DATA` command has every line ended by CRLF and
+//! is ended by a line holding only `.`, so any line of the message starting
+//! with `.` has another added in front of it, Section 4.5.2. `to_smtp_data`
+//! gives a message in this form, and a `DataReader` reverses it on the
+//! receiving side.
+//!
+//! With `CHUNKING`, RFC 3030, the message is instead sent as it is in
+//! `BDAT` chunks of a given size; see `bdat_chunks`.
+use std::cmp;
+
+use super::message::MimeMessage;
+
+/// The message as it's sent over SMTP, with every line ended by CRLF, but
+/// without any dot-stuffing.
+///
+/// This is what's sent in `BDAT` chunks.
+pub fn to_smtp_bytes(message: &MimeMessage) -> Vec<u8> {
+    let message = message.to_bytes();
+    let mut bytes = Vec::with_capacity(message.len() + 2);
+    let mut input = message.iter().copied().peekable();
+    while let Some(b) = input.next() {
+        match b {
+            b'\r' => {
+                if input.peek() == Some(&b'\n') {
+                    input.next();
+                }
+                bytes.extend_from_slice(b"\r\n");
+            }
+            b'\n' => bytes.extend_from_slice(b"\r\n"),
+            _ => bytes.push(b),
+        }
+    }
+    if !bytes.is_empty() && !bytes.ends_with(b"\r\n") {
+        bytes.extend_from_slice(b"\r\n");
+    }
+    bytes
+}
+
+/// The message as it's sent after the `DATA` command, dot-stuffed and
+/// ended by the `.` line.
+pub fn to_smtp_data(message: &MimeMessage) -> Vec<u8> {
+    let mut data = dot_stuff(&to_smtp_bytes(message)[..]);
+    data.extend_from_slice(b".\r\n");
+    data
+}
+
+/// Add a `.` to the start of each line of `data` which starts with one.
+pub fn dot_stuff(data: &[u8]) -> Vec<u8> {
+    let mut stuffed = Vec::with_capacity(data.len() + data.len() / 64);
+    let mut line_start = true;
+    for &b in data {
+        if line_start && b == b'.' {
+            stuffed.push(b'.');
+        }
+        stuffed.push(b);
+        line_start = b == b'\n';
+    }
+    stuffed
+}
+
+/// Remove the dot-stuffing from the whole of some `DATA`, up to the `.`
+/// line if it's there.
+///
+/// Use a `DataReader` when the data arrives a chunk at a time.
+pub fn unstuff(data: &[u8]) -> Vec<u8> {
+    let mut reader = DataReader::new();
+    reader.feed(data);
+    reader.finish()
+}
+
+/// A chunk of a message to send with `BDAT`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct BdatChunk<'a> {
+    pub data: &'a [u8],
+    /// Whether this is the last chunk of the message
+    pub last: bool,
+}
+
+impl<'a> BdatChunk<'a> {
+    /// The `BDAT` command which the chunk follows.
+    pub fn command(&self) -> String {
+        if self.last {
+            format!("BDAT {} LAST\r\n", self.data.len())
+        } else {
+            format!("BDAT {}\r\n", self.data.len())
+        }
+    }
+}
+
+/// Split `data`, as given by `to_smtp_bytes`, into `BDAT` chunks of at most
+/// `chunk_size` bytes.
+///
+/// The last chunk is marked as such. Empty `data` gives one empty last
+/// chunk, as `BDAT 0 LAST` is how it's sent.
+pub fn bdat_chunks(data: &[u8], chunk_size: usize) -> BdatChunks<'_> {
+    BdatChunks {
+        data,
+        chunk_size: cmp::max(chunk_size, 1),
+        done: false,
+    }
+}
+
+/// Iterator over the `BDAT` chunks of a message.
+///
+/// Returned by `bdat_chunks`.
+#[derive(Debug, Clone)]
+pub struct BdatChunks<'a> {
+    data: &'a [u8],
+    chunk_size: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for BdatChunks<'a> {
+    type Item = BdatChunk<'a>;
+
+    fn next(&mut self) -> Option<BdatChunk<'a>> {
+        if self.done {
+            return None;
+        }
+        let len = cmp::min(self.chunk_size, self.data.len());
+        let (data, rest) = self.data.split_at(len);
+        self.data = rest;
+        self.done = rest.is_empty();
+        Some(BdatChunk {
+            data,
+            last: self.done,
+        })
+    }
+}
+
+/// A reader of the `DATA` of an SMTP transaction, which is given it a chunk
+/// at a time as it's received, and removes the dot-stuffing.
+///
+/// Only CRLF ends a line, so a bare CR or LF followed by `.` neither ends
+/// the data nor is unstuffed. Reading the data to end at `.` after a bare
+/// line break, as the server it's relayed to may not, would let a sender
+/// smuggle in a second message.
+#[derive(Debug, Clone, Default)]
+pub struct DataReader {
+    message: Vec<u8>,
+    // The start of the current line, while it may still be the `.` line
+    line: Vec<u8>,
+    // Whether the start of the current line has been added to the message
+    in_line: bool,
+    // Whether the last byte added was a CR
+    cr: bool,
+    done: bool,
+}
+
+impl DataReader {
+    pub fn new() -> DataReader {
+        DataReader::default()
+    }
+
+    /// Feed the reader the next chunk of input.
+    ///
+    /// Once the `.` line ending the data is read, this gives the number of
+    /// bytes of `bytes` up to the end of it, as any after it are the next
+    /// command. Input fed after that is ignored.
+    pub fn feed(&mut self, bytes: &[u8]) -> Option<usize> {
+        if self.done {
+            return None;
+        }
+        for (i, &b) in bytes.iter().enumerate() {
+            if self.in_line {
+                self.message.push(b);
+                self.in_line = !(self.cr && b == b'\n');
+                self.cr = b == b'\r';
+                continue;
+            }
+            self.line.push(b);
+            if self.line == b".\r\n" {
+                self.line.clear();
+                self.done = true;
+                return Some(i + 1);
+            } else if b".\r\n".starts_with(&self.line) {
+                continue;
+            }
+            // The line isn't the `.` line, so only its first byte matters
+            let start = if self.line.starts_with(b".") { 1 } else { 0 };
+            self.message.extend_from_slice(&self.line[start..]);
+            self.line.clear();
+            self.in_line = true;
+            self.cr = b == b'\r';
+        }
+        None
+    }
+
+    /// Whether the `.` line ending the data has been read.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// The message read, including any unended last line if the `.` line
+    /// wasn't read.
+    pub fn finish(mut self) -> Vec<u8> {
+        let start = if self.line.starts_with(b".") { 1 } else { 0 };
+        self.message.extend_from_slice(&self.line[start..]);
+        self.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_smtp_data() {
+        let message = MimeMessage::parse("Subject: Hi\r\n\r\n.Hidden\nline\r\n..\r\n.").unwrap();
+        let data = to_smtp_data(&message);
+        assert_eq!(
+            &data[..],
+            &b"Subject: Hi\r\n\r\n..Hidden\r\nline\r\n...\r\n..\r\n.\r\n"[..]
+        );
+        assert_eq!(unstuff(&data[..]), to_smtp_bytes(&message));
+
+        // 8bit bodies in legacy charsets are sent untouched
+        let input = b"Content-Transfer-Encoding: 8bit\r\n\r\ncaf\xe9\r\n";
+        let message = MimeMessage::parse_bytes(input).unwrap();
+        assert_eq!(to_smtp_bytes(&message), &input[..]);
+    }
+
+    #[test]
+    fn test_dot_stuff() {
+        assert_eq!(dot_stuff(b".a\r\nb.\r\n.\r\n"), b"..a\r\nb.\r\n..\r\n");
+        assert_eq!(dot_stuff(b""), b"");
+    }
+
+    #[test]
+    fn test_bdat_chunks() {
+        let chunks: Vec<BdatChunk> = bdat_chunks(b"abcdefg", 3).collect();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].data, b"abc");
+        assert_eq!(chunks[0].command(), "BDAT 3\r\n");
+        assert_eq!(chunks[2].data, b"g");
+        assert_eq!(chunks[2].command(), "BDAT 1 LAST\r\n");
+
+        let chunks: Vec<BdatChunk> = bdat_chunks(b"abcdef", 3).collect();
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[1].last);
+
+        let chunks: Vec<BdatChunk> = bdat_chunks(b"", 3).collect();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].command(), "BDAT 0 LAST\r\n");
+    }
+
+    #[test]
+    fn test_data_reader() {
+        let mut reader = DataReader::new();
+        assert_eq!(reader.feed(b"Subject: Hi\r\n\r\n.."), None);
+        assert_eq!(reader.feed(b"dots\r\n."), None);
+        assert!(!reader.is_done());
+        assert_eq!(reader.feed(b"\r\nQUIT\r\n"), Some(2));
+        assert!(reader.is_done());
+        assert_eq!(reader.feed(b"more\r\n"), None);
+        assert_eq!(reader.finish(), b"Subject: Hi\r\n\r\n.dots\r\n");
+
+        assert_eq!(unstuff(b"a\r\n..b"), b"a\r\n.b");
+    }
+
+    #[test]
+    fn test_data_reader_bare_line_breaks() {
+        // Only `\r\n.\r\n` ends the data
+        let data = b"hi\n.\r\nMAIL FROM:<x@example.org>\r\n";
+        let mut reader = DataReader::new();
+        assert_eq!(reader.feed(data), None);
+        assert_eq!(reader.finish(), &data[..]);
+        let data = b"hi\r\n.\nMAIL FROM:<x@example.org>\r\n";
+        let mut reader = DataReader::new();
+        assert_eq!(reader.feed(data), None);
+        assert_eq!(reader.finish(), b"hi\r\n\nMAIL FROM:<x@example.org>\r\n");
+        assert_eq!(unstuff(b"a\n..b\r\n.\r\n"), b"a\n..b\r\n");
+
+        // The end of the data may be split between chunks
+        let mut reader = DataReader::new();
+        assert_eq!(reader.feed(b"a\r"), None);
+        assert_eq!(reader.feed(b"\n."), None);
+        assert_eq!(reader.feed(b"\r"), None);
+        assert_eq!(reader.feed(b"\nQUIT"), Some(1));
+        assert_eq!(reader.finish(), b"a\r\n");
+        assert_eq!(unstuff(b".\r\n"), b"");
+    }
+}
@@ -0,0 +1,167 @@
+//! Module for files uuencoded into the text of a message.
+//!
+//! Before MIME, files were sent by uuencoding them into the body of a
+//! message between a `begin 644 name` line and an `end` line, and some
+//! clients and scanners still do. `find_uuencoded` finds and decodes these
+//! blocks; when parsing with `ParseOptions::uuencode`, they're also given
+//! by `MimeMessage::attachments`.
+use std::ops::Range;
+
+/// A file found uuencoded in some text.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct UuencodedFile {
+    /// The Unix permissions given on the `begin` line, such as `0o644`
+    pub mode: u32,
+    pub filename: String,
+    pub content: Vec<u8>,
+    /// The span of the text covered, from the `begin` line to the end of
+    /// the `end` line
+    pub span: Range<usize>,
+}
+
+/// Find and decode the uuencoded files in `text`, in the order they
+/// appear.
+///
+/// Blocks which aren't closed by an `end` line, or which hold characters
+/// uuencoding doesn't use, are skipped.
+pub fn find_uuencoded(text: &str) -> Vec<UuencodedFile> {
+    let mut files = Vec::new();
+    let mut lines = LineOffsets { text, pos: 0 };
+
+    while let Some((start, line)) = lines.next() {
+        let (mode, filename) = match parse_begin(line) {
+            Some(begin) => begin,
+            None => continue,
+        };
+
+        let mut content = Vec::new();
+        let mut valid = true;
+        let mut end = None;
+        for (line_start, line) in lines.by_ref() {
+            if line == "end" {
+                end = Some(line_start + line.len());
+                break;
+            }
+            if valid && !decode_line(line, &mut content) {
+                valid = false;
+            }
+        }
+
+        if let (true, Some(end)) = (valid, end) {
+            files.push(UuencodedFile {
+                mode,
+                filename: filename.to_string(),
+                content,
+                span: start..end,
+            });
+        }
+    }
+    files
+}
+
+// The mode and filename of a `begin` line.
+fn parse_begin(line: &str) -> Option<(u32, &str)> {
+    let rest = line.strip_prefix("begin ")?;
+    let space = rest.find(' ')?;
+    let mode = &rest[..space];
+    if mode.len() < 3 || mode.len() > 4 {
+        return None;
+    }
+    let mode = u32::from_str_radix(mode, 8).ok()?;
+    let filename = rest[space + 1..].trim();
+    if filename.is_empty() {
+        return None;
+    }
+    Some((mode, filename))
+}
+
+// Decode one line of uuencoded data onto `content`, returning false if it
+// isn't valid.
+fn decode_line(line: &str, content: &mut Vec<u8>) -> bool {
+    let bytes = line.as_bytes();
+    if bytes.iter().any(|&b| !(b' '..=b'`').contains(&b)) {
+        return false;
+    }
+    let length = match bytes.first() {
+        Some(&b) => ((b - b' ') & 0x3f) as usize,
+        None => return true,
+    };
+    // Trailing spaces may have been stripped in transit, so missing
+    // characters are taken as zeros
+    let sextet = |i: usize| bytes.get(i).map_or(0, |&b| (b - b' ') & 0x3f);
+
+    let mut decoded = Vec::with_capacity(length + 2);
+    let mut i = 1;
+    while decoded.len() < length {
+        let (a, b, c, d) = (sextet(i), sextet(i + 1), sextet(i + 2), sextet(i + 3));
+        decoded.push(a << 2 | b >> 4);
+        decoded.push(b << 4 | c >> 2);
+        decoded.push(c << 6 | d);
+        i += 4;
+    }
+    decoded.truncate(length);
+    content.extend(decoded);
+    true
+}
+
+// The lines of `text` with their offsets, without line breaks.
+struct LineOffsets<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Iterator for LineOffsets<'a> {
+    type Item = (usize, &'a str);
+
+    fn next(&mut self) -> Option<(usize, &'a str)> {
+        if self.pos >= self.text.len() {
+            return None;
+        }
+        let start = self.pos;
+        let rest = &self.text[start..];
+        let end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+        self.pos += end;
+        let line = rest[..end].trim_end_matches(['\r', '\n']);
+        Some((start, line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_uuencoded() {
+        let text = "Here it is:\r\n\
+                    \r\n\
+                    begin 644 hello.txt\r\n\
+                    M2&5L;&\\L('=O<FQD(2!4:&ES(&ES(&$@=75E;F-O9&5D(&9I;&4@=VET:\"!M\r\n\
+                    :;W)E('1H86X@-#4@8GET97,@;V8@9&%T82X\r\n\
+                    `\r\n\
+                    end\r\n\
+                    begin 600 bad.bin\r\n\
+                    #0V%T\x7f\r\n\
+                    end\r\n\
+                    begin 0755 cat\n\
+                    #0V%T\n\
+                    end\n\
+                    begin 644 unterminated.txt\r\n\
+                    #0V%T\r\n";
+        let files = find_uuencoded(text);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].mode, 0o644);
+        assert_eq!(files[0].filename, "hello.txt");
+        assert_eq!(
+            files[0].content,
+            &b"Hello, world! This is a uuencoded file with more than 45 bytes of data."[..]
+        );
+        assert!(text[files[0].span.clone()].starts_with("begin 644"));
+        assert!(text[files[0].span.clone()].ends_with("`\r\nend"));
+
+        assert_eq!(files[1].mode, 0o755);
+        assert_eq!(files[1].filename, "cat");
+        assert_eq!(files[1].content, b"Cat");
+
+        assert!(find_uuencoded("begin the day\r\nend\r\n").is_empty());
+    }
+}
@@ -0,0 +1,61 @@
+//! Module for contact cards sent by mail, as `text/vcard` parts.
+//!
+//! vCards, described in RFC 6350, are sent as `text/vcard`, or as
+//! `text/x-vcard` by older clients. `MimeMessage::vcards` finds them, and
+//! `MessageBuilder::attach_vcard` sends one.
+use super::message::MimeMessage;
+
+// Whether `part` holds a vCard.
+fn is_vcard(part: &MimeMessage) -> bool {
+    let content_type = part.content_type();
+    content_type.ttype == "text"
+        && (content_type.subtype == "vcard" || content_type.subtype == "x-vcard")
+}
+
+impl MimeMessage {
+    /// The decoded vCards in this message, in the order they appear.
+    ///
+    /// Each part may hold several cards. Parts which can't be decoded are
+    /// left out.
+    pub fn vcards(&self) -> Vec<String> {
+        self.parts_depth_first()
+            .filter(|(_, part)| part.children.is_empty() && is_vcard(part))
+            .filter_map(|(_, part)| part.decoded_body_string().ok())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vcards() {
+        let message = MimeMessage::parse(
+            "Content-Type: multipart/mixed; boundary=b\r\n\r\n\
+             --b\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             My card\r\n\
+             --b\r\n\
+             Content-Type: text/vcard; charset=utf-8\r\n\
+             Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+             BEGIN:VCARD\r\n\
+             VERSION:4.0\r\n\
+             FN:J=C3=B6rg\r\n\
+             END:VCARD\r\n\
+             --b\r\n\
+             Content-Type: text/x-vcard; charset=iso-8859-1\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n\
+             QkVHSU46VkNBUkQNClZFUlNJT046Mi4xDQpGTjpSZW7pDQpFTkQ6VkNBUkQNCg==\r\n\
+             --b--\r\n",
+        )
+        .unwrap();
+        let cards = message.vcards();
+        assert_eq!(cards.len(), 2);
+        assert!(cards[0].contains("FN:Jörg\r\n"));
+        assert!(cards[1].contains("FN:René\r\n"));
+
+        let plain = MimeMessage::parse("Content-Type: text/plain\r\n\r\nHi").unwrap();
+        assert!(plain.vcards().is_empty());
+    }
+}
@@ -0,0 +1,224 @@
+//! Traversal of the tree of parts making up a MIME message.
+use std::fmt;
+use std::str::FromStr;
+
+use super::message::MimeMessage;
+use super::results::{ParsingError, ParsingResult};
+
+/// The position of a part within a message, as an IMAP-style part number.
+///
+/// Each element is the 1-based index of a child within its parent, so the
+/// second child of the first part is `1.2`. The message itself has an
+/// empty path; note that IMAP addresses the body of a message that isn't
+/// multipart as part `1`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Default)]
+pub struct PartPath(Vec<usize>);
+
+impl PartPath {
+    /// The path of the top level message.
+    pub fn root() -> PartPath {
+        PartPath(Vec::new())
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// How many levels below the top level message this part is.
+    pub fn depth(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The 1-based indices making up this path.
+    pub fn indices(&self) -> &[usize] {
+        &self.0[..]
+    }
+
+    /// The path of the `index`th (0-based) child of this part.
+    pub fn child(&self, index: usize) -> PartPath {
+        let mut indices = self.0.clone();
+        indices.push(index + 1);
+        PartPath(indices)
+    }
+}
+
+impl fmt::Display for PartPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, index) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ".")?;
+            }
+            write!(f, "{}", index)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for PartPath {
+    type Err = ParsingError;
+
+    fn from_str(s: &str) -> ParsingResult<PartPath> {
+        if s.is_empty() {
+            return Ok(PartPath::root());
+        }
+        let mut indices = Vec::new();
+        for index in s.split('.') {
+            match index.parse::<usize>() {
+                Ok(i) if i > 0 => indices.push(i),
+                _ => return Err(ParsingError::new(format!("Invalid part number: {}", s))),
+            }
+        }
+        Ok(PartPath(indices))
+    }
+}
+
+/// Callbacks for `MimeMessage::walk`.
+pub trait MimeVisitor {
+    /// Called for each part, before any of its children.
+    ///
+    /// Return `false` to skip the children of this part.
+    fn visit_part(&mut self, path: &PartPath, part: &MimeMessage) -> bool;
+
+    /// Called for each part once all of its children have been visited.
+    fn leave_part(&mut self, _path: &PartPath, _part: &MimeMessage) {}
+}
+
+/// Depth first iterator over the parts of a message.
+///
+/// Returned by `MimeMessage::parts_depth_first`.
+pub struct PartsDepthFirst<'a> {
+    stack: Vec<(PartPath, &'a MimeMessage)>,
+}
+
+impl<'a> Iterator for PartsDepthFirst<'a> {
+    type Item = (PartPath, &'a MimeMessage);
+
+    fn next(&mut self) -> Option<(PartPath, &'a MimeMessage)> {
+        let (path, part) = self.stack.pop()?;
+        for (index, child) in part.children.iter().enumerate().rev() {
+            self.stack.push((path.child(index), child));
+        }
+        Some((path, part))
+    }
+}
+
+impl MimeMessage {
+    /// Walk every part of this message depth first, starting with the
+    /// message itself.
+    pub fn walk<V: MimeVisitor>(&self, visitor: &mut V) {
+        self.walk_path(&PartPath::root(), visitor);
+    }
+
+    fn walk_path<V: MimeVisitor>(&self, path: &PartPath, visitor: &mut V) {
+        if visitor.visit_part(path, self) {
+            for (index, child) in self.children.iter().enumerate() {
+                child.walk_path(&path.child(index), visitor);
+            }
+        }
+        visitor.leave_part(path, self);
+    }
+
+    /// Iterate over every part of this message depth first, starting with the
+    /// message itself, along with the path of each.
+    pub fn parts_depth_first(&self) -> PartsDepthFirst<'_> {
+        PartsDepthFirst {
+            stack: vec![(PartPath::root(), self)],
+        }
+    }
+
+    /// The part of this message at `path`, if there is one.
+    pub fn part(&self, path: &PartPath) -> Option<&MimeMessage> {
+        let mut part = self;
+        for &index in path.indices() {
+            part = part.children.get(index - 1)?;
+        }
+        Some(part)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message() -> MimeMessage {
+        MimeMessage::parse(
+            "Content-Type: multipart/mixed; boundary=outer\r\n\r\n\
+             --outer\r\n\
+             Content-Type: multipart/alternative; boundary=alt\r\n\r\n\
+             --alt\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             Text\r\n\
+             --alt\r\n\
+             Content-Type: text/html\r\n\r\n\
+             <p>HTML</p>\r\n\
+             --alt--\r\n\
+             --outer\r\n\
+             Content-Type: image/png\r\n\r\n\
+             PNG\r\n\
+             --outer--\r\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_part_path() {
+        let path: PartPath = "1.2.3".parse().unwrap();
+        assert_eq!(path.indices(), &[1, 2, 3]);
+        assert_eq!(path.to_string(), "1.2.3");
+        assert_eq!(path.child(0).to_string(), "1.2.3.1");
+        assert!("".parse::<PartPath>().unwrap().is_root());
+        assert!("1.0".parse::<PartPath>().is_err());
+        assert!("1..2".parse::<PartPath>().is_err());
+    }
+
+    #[test]
+    fn test_parts_depth_first() {
+        let message = message();
+        let parts: Vec<(String, String)> = message
+            .parts_depth_first()
+            .map(|(path, part)| {
+                let content_type = part.content_type();
+                (
+                    path.to_string(),
+                    format!("{}/{}", content_type.ttype, content_type.subtype),
+                )
+            })
+            .collect();
+        assert_eq!(
+            parts,
+            vec![
+                ("".to_string(), "multipart/mixed".to_string()),
+                ("1".to_string(), "multipart/alternative".to_string()),
+                ("1.1".to_string(), "text/plain".to_string()),
+                ("1.2".to_string(), "text/html".to_string()),
+                ("2".to_string(), "image/png".to_string()),
+            ]
+        );
+
+        let html = message.part(&"1.2".parse().unwrap()).unwrap();
+        assert_eq!(html.body, "<p>HTML</p>\r\n");
+        assert!(message.part(&"3".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_walk() {
+        // Produce a nested summary, skipping the insides of alternatives
+        struct Summary(String);
+
+        impl MimeVisitor for Summary {
+            fn visit_part(&mut self, path: &PartPath, part: &MimeMessage) -> bool {
+                self.0
+                    .push_str(&format!("({} {}", path, part.content_type().subtype)[..]);
+                part.content_type().subtype != "alternative"
+            }
+
+            fn leave_part(&mut self, _path: &PartPath, _part: &MimeMessage) {
+                self.0.push(')');
+            }
+        }
+
+        let mut summary = Summary(String::new());
+        message().walk(&mut summary);
+        assert_eq!(summary.0, "( mixed(1 alternative)(2 png))");
+    }
+}
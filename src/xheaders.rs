@@ -0,0 +1,247 @@
+//! Module for the `X-` headers which aren't in any standard, but which
+//! mail clients and spam filters have set for long enough that filtering
+//! code looks for them, such as `X-Priority` and `X-Spam-Status`.
+use super::header::{FromHeader, Header, HeaderMap, HeaderName, ToHeader};
+use super::results::{ParsingError, ParsingResult};
+
+pub const X_SPAM_STATUS: &str = "X-Spam-Status";
+
+/// The `X-Priority` header, a level from 1, the highest, to 5, the lowest,
+/// with 3 as normal.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy)]
+pub struct XPriority(u8);
+
+impl XPriority {
+    pub const HIGHEST: XPriority = XPriority(1);
+    pub const HIGH: XPriority = XPriority(2);
+    pub const NORMAL: XPriority = XPriority(3);
+    pub const LOW: XPriority = XPriority(4);
+    pub const LOWEST: XPriority = XPriority(5);
+
+    /// The priority at `level`, or None if it isn't from 1 to 5.
+    pub fn new(level: u8) -> Option<XPriority> {
+        if (1..=5).contains(&level) {
+            Some(XPriority(level))
+        } else {
+            None
+        }
+    }
+
+    pub fn level(&self) -> u8 {
+        self.0
+    }
+
+    /// Whether the priority is above normal.
+    pub fn is_high(&self) -> bool {
+        self.0 < 3
+    }
+
+    /// Whether the priority is below normal.
+    pub fn is_low(&self) -> bool {
+        self.0 > 3
+    }
+}
+
+impl FromHeader for XPriority {
+    /// Only the level at the start of the value is read, so `1 (Highest)`
+    /// and `1` are the same.
+    fn from_header(value: String) -> ParsingResult<XPriority> {
+        let level = value.trim_start().bytes().next().filter(u8::is_ascii_digit);
+        level
+            .and_then(|level| XPriority::new(level - b'0'))
+            .ok_or_else(|| ParsingError::new(format!("Invalid X-Priority: {}", value)))
+    }
+}
+
+impl ToHeader for XPriority {
+    fn to_header(value: XPriority) -> ParsingResult<String> {
+        let name = ["Highest", "High", "Normal", "Low", "Lowest"][value.0 as usize - 1];
+        Ok(format!("{} ({})", value.0, name))
+    }
+}
+
+/// The `X-Spam-Status` header set by SpamAssassin, such as
+/// `Yes, score=7.3 required=5.0 tests=BAYES_99,URIBL_BLACK`.
+#[derive(PartialEq, Debug, Clone)]
+pub struct SpamStatus {
+    pub is_spam: bool,
+    pub score: Option<f64>,
+    /// The score at which mail is taken as spam
+    pub required: Option<f64>,
+    /// The names of the rules the message matched
+    pub tests: Vec<String>,
+}
+
+impl FromHeader for SpamStatus {
+    /// Fields other than the score, the required score and the tests are
+    /// ignored, as are scores which aren't numbers.
+    fn from_header(value: String) -> ParsingResult<SpamStatus> {
+        let (verdict, fields) = value.split_once(',').unwrap_or((&value[..], ""));
+        let verdict = verdict.trim();
+        let is_spam = verdict.eq_ignore_ascii_case("yes");
+        if !is_spam && !verdict.eq_ignore_ascii_case("no") {
+            let message = format!("Invalid X-Spam-Status: {}", value);
+            return Err(ParsingError::new(message));
+        }
+        let mut status = SpamStatus {
+            is_spam,
+            score: None,
+            required: None,
+            tests: Vec::new(),
+        };
+        // The list of tests may be folded onto several lines, so a word
+        // after one ending in a comma is part of the same field
+        let mut words: Vec<String> = Vec::new();
+        for word in fields.split_whitespace() {
+            match words.last_mut() {
+                Some(last) if last.ends_with(',') => last.push_str(word),
+                _ => words.push(word.to_string()),
+            }
+        }
+        for field in words.iter() {
+            let (name, value) = match field.split_once('=') {
+                Some(field) => field,
+                None => continue,
+            };
+            match &name.to_ascii_lowercase()[..] {
+                "score" | "hits" => status.score = value.parse().ok(),
+                "required" => status.required = value.parse().ok(),
+                "tests" => {
+                    // SpamAssassin gives `none` when no rules matched
+                    let is_test = |test: &&str| !test.is_empty() && *test != "none";
+                    let tests = value.split(',').filter(is_test);
+                    status.tests = tests.map(str::to_string).collect();
+                }
+                _ => {}
+            }
+        }
+        Ok(status)
+    }
+}
+
+impl ToHeader for SpamStatus {
+    fn to_header(value: SpamStatus) -> ParsingResult<String> {
+        let mut header = if value.is_spam { "Yes," } else { "No," }.to_string();
+        if let Some(score) = value.score {
+            header.push_str(&format!(" score={:.1}", score));
+        }
+        if let Some(required) = value.required {
+            header.push_str(&format!(" required={:.1}", required));
+        }
+        if !value.tests.is_empty() {
+            header.push_str(&format!(" tests={}", value.tests.join(",")));
+        }
+        Ok(header)
+    }
+}
+
+impl HeaderMap {
+    /// The headers which aren't part of any standard: those whose names
+    /// start with `X-`, and any others which aren't among the names with
+    /// a constant such as `HeaderName::CONTENT_TYPE`. They're given in
+    /// order.
+    pub fn x_headers(&self) -> impl Iterator<Item = &Header> {
+        self.iter().filter(|header| {
+            let name = header.name.as_str();
+            let prefix = name.get(..2).filter(|_| name.len() > 2);
+            let is_x = prefix.is_some_and(|x| x.eq_ignore_ascii_case("x-"));
+            is_x || !header.name.is_standard()
+        })
+    }
+
+    /// The value of the `X-Priority` header, if there's a valid one.
+    pub fn x_priority(&self) -> Option<XPriority> {
+        self.get_value(HeaderName::X_PRIORITY.to_string()).ok()
+    }
+
+    /// The mail program named by the `X-Mailer` header, decoded.
+    pub fn x_mailer(&self) -> Option<String> {
+        let mailer: String = self.get_value(HeaderName::X_MAILER.to_string()).ok()?;
+        Some(mailer.trim().to_string()).filter(|mailer| !mailer.is_empty())
+    }
+
+    /// The value of the `X-Spam-Status` header, if there's a valid one.
+    pub fn spam_status(&self) -> Option<SpamStatus> {
+        self.get_value(X_SPAM_STATUS.to_string()).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::MimeMessage;
+
+    #[test]
+    fn test_x_priority() {
+        let tests = [
+            ("1 (Highest)", Some(XPriority::HIGHEST)),
+            ("3", Some(XPriority::NORMAL)),
+            (" 5 (Lowest)", Some(XPriority::LOWEST)),
+            ("0", None),
+            ("High", None),
+        ];
+        for &(value, expected) in tests.iter() {
+            let priority = XPriority::from_header(value.to_string()).ok();
+            assert_eq!(priority, expected, "{}", value);
+        }
+        assert!(XPriority::HIGH.is_high());
+        assert!(!XPriority::NORMAL.is_high() && !XPriority::NORMAL.is_low());
+        assert_eq!(
+            XPriority::to_header(XPriority::LOW).unwrap(),
+            "4 (Low)".to_string()
+        );
+    }
+
+    #[test]
+    fn test_spam_status() {
+        let value = "Yes, score=7.3 required=5.0 tests=BAYES_99,\r\n\
+                     \tURIBL_BLACK autolearn=no version=3.4.2";
+        let status = SpamStatus::from_header(value.to_string()).unwrap();
+        assert!(status.is_spam);
+        assert_eq!(status.score, Some(7.3));
+        assert_eq!(status.required, Some(5.0));
+        assert_eq!(status.tests, vec!["BAYES_99", "URIBL_BLACK"]);
+        assert_eq!(
+            SpamStatus::to_header(status).unwrap(),
+            "Yes, score=7.3 required=5.0 tests=BAYES_99,URIBL_BLACK"
+        );
+
+        let status = SpamStatus::from_header("No, hits=-1.2 tests=none".to_string()).unwrap();
+        assert!(!status.is_spam);
+        assert_eq!(status.score, Some(-1.2));
+        assert!(status.tests.is_empty());
+        assert!(SpamStatus::from_header("Maybe".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_x_headers() {
+        let message = MimeMessage::parse(
+            "Subject: Hi\r\n\
+             X-Mailer: =?utf-8?q?Caf=C3=A9_Mail?=\r\n\
+             X-Priority: 2 (High)\r\n\
+             Archived-At: <https://example.org/1>\r\n\
+             x-spam-status: No, score=0.1 required=5.0\r\n\
+             Content-Type: text/plain\r\n\r\n",
+        )
+        .unwrap();
+        let names: Vec<&str> = message
+            .headers
+            .x_headers()
+            .map(|header| header.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["X-Mailer", "X-Priority", "Archived-At", "x-spam-status"]
+        );
+        let headers = &message.headers;
+        assert_eq!(headers.x_mailer(), Some("Caf\u{e9} Mail".to_string()));
+        assert_eq!(headers.x_priority(), Some(XPriority::HIGH));
+        assert_eq!(headers.spam_status().unwrap().score, Some(0.1));
+        assert_eq!(HeaderMap::new().spam_status(), None);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(Header::new("\u{e9}t\u{e9}".to_string(), "1".to_string()));
+        headers.insert(Header::new("X".to_string(), "2".to_string()));
+        assert_eq!(headers.x_headers().count(), 2);
+    }
+}
@@ -0,0 +1,251 @@
+//! Module for files yEncoded into the text of a message.
+//!
+//! yEnc, used to post binaries to Usenet, encodes a file between a
+//! `=ybegin` line and a `=yend` line with very little overhead. Mail
+//! gatewayed from news still carries it. `find_yenc` finds and decodes
+//! these blocks, and `MimeMessage::attachments` gives the whole files found
+//! in text parts.
+use std::ops::Range;
+
+/// Which part of a file split across several yEnc blocks a block holds.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct YencPart {
+    /// The 1-based number of the part
+    pub number: u32,
+    /// How many parts there are, if given
+    pub total: Option<u32>,
+    /// The 1-based offsets of the first and last bytes of the part in the
+    /// file
+    pub begin: u64,
+    pub end: u64,
+}
+
+/// A file, or part of one, found yEncoded in some text.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct YencFile {
+    pub filename: String,
+    /// The size of the whole file
+    pub size: u64,
+    pub part: Option<YencPart>,
+    pub content: Vec<u8>,
+    /// The span of the text covered, from the `=ybegin` line to the end of
+    /// the `=yend` line
+    pub span: Range<usize>,
+}
+
+impl YencFile {
+    /// Whether this holds the whole file, rather than one part of it.
+    pub fn is_complete(&self) -> bool {
+        match &self.part {
+            Some(part) => part.begin == 1 && part.end == self.size,
+            None => true,
+        }
+    }
+}
+
+/// Find and decode the yEnc blocks in `data`, in the order they appear.
+///
+/// Blocks which aren't closed by a `=yend` line, or whose size or CRC
+/// doesn't match that given, are skipped.
+pub fn find_yenc(data: &[u8]) -> Vec<YencFile> {
+    let mut files = Vec::new();
+    let mut lines = data.split(|&b| b == b'\n').scan(0, |pos, line| {
+        let start = *pos;
+        *pos += line.len() + 1;
+        Some((start, line.strip_suffix(b"\r").unwrap_or(line)))
+    });
+
+    while let Some((start, line)) = lines.next() {
+        let header = match line.strip_prefix(b"=ybegin ") {
+            Some(header) => String::from_utf8_lossy(header),
+            None => continue,
+        };
+
+        let mut part_line = None;
+        let mut trailer = None;
+        let mut encoded = Vec::new();
+        for (line_start, line) in lines.by_ref() {
+            if let Some(rest) = line.strip_prefix(b"=ypart ") {
+                part_line = Some(String::from_utf8_lossy(rest));
+            } else if let Some(rest) = line.strip_prefix(b"=yend") {
+                trailer = Some((line_start + line.len(), String::from_utf8_lossy(rest)));
+                break;
+            } else {
+                encoded.extend_from_slice(line);
+            }
+        }
+
+        let (end, trailer) = match trailer {
+            Some(trailer) => trailer,
+            None => break,
+        };
+        let file = decode_block(&header, part_line.as_deref(), &trailer, &encoded[..]);
+        if let Some(file) = file {
+            files.push(YencFile {
+                span: start..end,
+                ..file
+            });
+        }
+    }
+    files
+}
+
+/// Decode yEnc data, ignoring line breaks.
+pub fn decode(encoded: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'\r' | b'\n' => {}
+            b'=' => {
+                if let Some(&escaped) = bytes.next() {
+                    decoded.push(escaped.wrapping_sub(64).wrapping_sub(42));
+                }
+            }
+            _ => decoded.push(b.wrapping_sub(42)),
+        }
+    }
+    decoded
+}
+
+// The file of a block with the given control lines, if it's valid.
+fn decode_block(
+    header: &str,
+    part: Option<&str>,
+    trailer: &str,
+    encoded: &[u8],
+) -> Option<YencFile> {
+    let filename = keyword(header, "name")?.to_string();
+    let size = keyword(header, "size")?.parse().ok()?;
+    let part = match (keyword(header, "part"), part) {
+        (Some(number), Some(part)) => Some(YencPart {
+            number: number.parse().ok()?,
+            total: keyword(header, "total").and_then(|total| total.parse().ok()),
+            begin: keyword(part, "begin")?.parse().ok()?,
+            end: keyword(part, "end")?.parse().ok()?,
+        }),
+        (None, None) => None,
+        _ => return None,
+    };
+
+    let content = decode(encoded);
+    let expected = match &part {
+        // The offsets are 1-based, so 0 is never valid
+        Some(part) if part.begin == 0 => return None,
+        Some(part) => part.end.checked_sub(part.begin)?.checked_add(1)?,
+        None => size,
+    };
+    if content.len() as u64 != expected
+        || keyword(trailer, "size").is_some_and(|size| size.parse() != Ok(expected))
+    {
+        return None;
+    }
+    let crc = keyword(trailer, if part.is_some() { "pcrc32" } else { "crc32" });
+    if crc.is_some_and(|crc| u32::from_str_radix(crc, 16) != Ok(crc32(&content[..]))) {
+        return None;
+    }
+
+    Some(YencFile {
+        filename,
+        size,
+        part,
+        content,
+        span: 0..0,
+    })
+}
+
+// The value of `key` on a yEnc control line of `key=value` pairs, where
+// the name runs to the end of the line.
+fn keyword<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let (pairs, name) = match line.find("name=") {
+        Some(i) => (&line[..i], Some(line[i + 5..].trim())),
+        None => (line, None),
+    };
+    if key == "name" {
+        return name.filter(|name| !name.is_empty());
+    }
+    pairs
+        .split_whitespace()
+        .find_map(|pair| pair.strip_prefix(key)?.strip_prefix('='))
+}
+
+// The CRC-32 of zlib and yEnc.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_yenc() {
+        let data = b"Posted from the news gateway\r\n\
+                     =ybegin line=8 size=16 name=my file.bin\r\n\
+                     *+=}=J=M\r\n\
+                     =@)\xa3o\x98\x8dJ\r\n\
+                     \x8e\x8b\x9e\x8b\r\n\
+                     =yend size=16 crc32=fd4ea287\r\n\
+                     =ybegin part=1 total=2 line=8 size=8 name=abc.txt\n\
+                     =ypart begin=1 end=4\n\
+                     klmn\n\
+                     =yend size=4 part=1 pcrc32=db1720a5 crc32=68dcb61c\n\
+                     =ybegin line=8 size=4 name=bad-crc.txt\r\n\
+                     klmn\r\n\
+                     =yend size=4 crc32=00000000\r\n\
+                     =ybegin line=8 size=5 name=bad-size.txt\r\n\
+                     klmn\r\n\
+                     =yend size=5\r\n\
+                     =ybegin line=8 size=4 name=unterminated.txt\r\n\
+                     klmn\r\n";
+        let files = find_yenc(&data[..]);
+        assert_eq!(files.len(), 2);
+
+        assert_eq!(files[0].filename, "my file.bin");
+        assert_eq!(files[0].size, 16);
+        assert_eq!(
+            files[0].content,
+            &b"\x00\x01\x13\xe0\xe3\xd6\xffyEnc data"[..]
+        );
+        assert!(files[0].is_complete());
+        assert!(data[files[0].span.clone()].starts_with(b"=ybegin line=8"));
+        assert!(data[files[0].span.clone()].ends_with(b"crc32=fd4ea287"));
+
+        assert_eq!(files[1].filename, "abc.txt");
+        assert_eq!(files[1].content, b"ABCD");
+        assert_eq!(
+            files[1].part,
+            Some(YencPart {
+                number: 1,
+                total: Some(2),
+                begin: 1,
+                end: 4,
+            })
+        );
+        assert!(!files[1].is_complete());
+
+        // Offsets which would overflow are skipped over
+        let data = b"=ybegin part=1 line=8 size=8 name=a.txt\n\
+                     =ypart begin=0 end=18446744073709551615\n\
+                     klmn\n\
+                     =yend size=4 part=1\n";
+        assert!(find_yenc(&data[..]).is_empty());
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"rovvy*=}\r\nr"), b"HELLO\x00\x13H");
+        assert_eq!(crc32(b"HELLO\x00\x13"), 0x06a4_a651);
+    }
+}